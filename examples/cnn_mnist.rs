@@ -45,7 +45,7 @@ fn compute_logits<'g>(c: &'g Context<f32>, train: bool) -> Tensor<'g> {
     let x = x.reshape(&[-1, 1, 28, 28]); // 2D -> 4D
     let z1 = conv_pool(x, c.variable("w1"), c.variable("b1"), train); // map to 32 channel
     let z2 = conv_pool(z1, c.variable("w2"), c.variable("b2"), train); // map to 64 channel
-    let z3 = T::reshape(z2, &[-1, 64 * 7 * 7]); // flatten
+    let z3 = T::flatten(z2, 1); // (batch, 64 * 7 * 7)
     let z4 = T::matmul(z3, c.variable("w3")) + c.variable("b3");
     T::dropout(z4, 0.25, train)
 }
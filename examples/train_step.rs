@@ -0,0 +1,78 @@
+//! Steps per second of a training loop rebuilding its graph in each iteration,
+//! versus a `TrainStep` built once.
+extern crate autograd as ag;
+
+use ag::optimizers::{self, Optimizer, TrainStep, SGD};
+use ag::prelude::*;
+use ag::tensor_ops::*;
+use std::time::Instant;
+
+const BATCH_SIZE: usize = 32;
+const NUM_STEPS: usize = 2000;
+
+fn loss<'g>(
+    x: ag::Tensor<'g, f32>,
+    y: ag::Tensor<'g, f32>,
+    g: &'g ag::Context<f32>,
+) -> ag::Tensor<'g, f32> {
+    let h = tanh(matmul(x, g.variable("w1")) + g.variable("b1"));
+    let h = tanh(matmul(h, g.variable("w2")) + g.variable("b2"));
+    let z = matmul(h, g.variable("w3"));
+    mean_all(square(z - y))
+}
+
+fn make_env(rng: &ag::ndarray_ext::ArrayRng<f32>) -> ag::VariableEnvironment<f32> {
+    let mut env = ag::VariableEnvironment::new();
+    env.name("w1").set(rng.glorot_uniform(&[8, 64]));
+    env.name("b1").set(ag::ndarray_ext::zeros(&[1, 64]));
+    env.name("w2").set(rng.glorot_uniform(&[64, 64]));
+    env.name("b2").set(ag::ndarray_ext::zeros(&[1, 64]));
+    env.name("w3").set(rng.glorot_uniform(&[64, 1]));
+    env
+}
+
+fn main() {
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    let x_batch = rng.standard_normal(&[BATCH_SIZE, 8]);
+    let y_batch = rng.standard_normal(&[BATCH_SIZE, 1]);
+    let sgd = SGD::new(0.01);
+
+    let env = make_env(&rng);
+    let start = Instant::now();
+    for _ in 0..NUM_STEPS {
+        env.run(|g| {
+            let x = g.placeholder("x", &[-1, 8]);
+            let y = g.placeholder("y", &[-1, 1]);
+            let loss = loss(x, y, g);
+            let ns = g.default_namespace();
+            let (vars, grads) = optimizers::grad_helper(&[loss], &ns);
+            let mut feeder = ag::Feeder::new();
+            feeder.push(x, x_batch.view()).push(y, y_batch.view());
+            sgd.update(&vars, &grads, g, feeder);
+        });
+    }
+    let rebuilt = NUM_STEPS as f64 / start.elapsed().as_secs_f64();
+    println!("rebuilding each step: {:.0} steps/sec", rebuilt);
+
+    let env = make_env(&rng);
+    let start = Instant::now();
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 8]);
+        let y = g.placeholder("y", &[-1, 1]);
+        let loss = loss(x, y, g);
+        let ns = g.default_namespace();
+        let vars: Vec<_> = g.var_tensors_by_name(&ns).map(|(_, t)| t).collect();
+        let mut step = TrainStep::new(&sgd, loss, &vars, g);
+        for _ in 0..NUM_STEPS {
+            let mut feeder = ag::Feeder::new();
+            feeder.push(x, x_batch.view()).push(y, y_batch.view());
+            step.step(feeder).unwrap();
+        }
+    });
+    let reused = NUM_STEPS as f64 / start.elapsed().as_secs_f64();
+    println!(
+        "TrainStep: {:.0} steps/sec ({:.2}x)",
+        reused,
+        reused / rebuilt
+    );
+}
@@ -0,0 +1,109 @@
+//! Where the arrays of an evaluation are computed.
+//!
+//! A [Backend] implements the primitive array operations the ops are built on: allocation,
+//! matrix products and broadcasting element-wise arithmetic. Each [Context](crate::Context)
+//! holds one, [CpuBackend] by default, and ops reach it through
+//! [ComputeContext::backend](crate::op::ComputeContext::backend) in `Op::compute`.
+//!
+//! This is groundwork for accelerators: the arrays are still ndarray arrays in host memory,
+//! so a backend for a device would copy its inputs in and its results out for now. The ops
+//! routed through the backend are the zero constants, `matmul` (outside of
+//! [reference mode](crate::Context::set_reference_mode)) and `+`, `-`, `*`, `/`;
+//! everything else runs on ndarray directly.
+//!
+//! ```
+//! use autograd as ag;
+//! use ag::backend::{Backend, BinaryOp, CpuBackend};
+//! use ag::ndarray::{Array2, ArrayView2};
+//! use ag::tensor_ops as T;
+//! use ag::{NdArray, NdArrayView};
+//!
+//! // Logs the matrix products and leaves the work to the CPU
+//! struct Logging;
+//!
+//! impl Backend<f32> for Logging {
+//!     fn name(&self) -> &'static str {
+//!         "logging"
+//!     }
+//!
+//!     fn zeros(&self, shape: &[usize]) -> NdArray<f32> {
+//!         CpuBackend.zeros(shape)
+//!     }
+//!
+//!     fn matmul(&self, a: &ArrayView2<f32>, b: &ArrayView2<f32>) -> Array2<f32> {
+//!         println!("matmul {:?} x {:?}", a.dim(), b.dim());
+//!         CpuBackend.matmul(a, b)
+//!     }
+//!
+//!     fn binary(&self, op: BinaryOp, a: &NdArrayView<f32>, b: &NdArrayView<f32>) -> NdArray<f32> {
+//!         CpuBackend.binary(op, a, b)
+//!     }
+//! }
+//!
+//! ag::run(|ctx| {
+//!     ctx.set_backend(Logging);
+//!     assert_eq!(ctx.backend_name(), "logging");
+//!     let y = T::matmul(T::ones(&[2, 3], ctx), T::ones(&[3, 4], ctx));
+//!     assert_eq!(y.eval(ctx).unwrap(), ag::ndarray::Array2::from_elem((2, 4), 3.).into_dyn());
+//! });
+//! ```
+use crate::ndarray_ext::{self, NdArray, NdArrayView};
+use crate::tensor_ops::{binary_ops, dot_ops};
+use crate::Float;
+use ndarray::{Array2, ArrayView2};
+
+/// The element-wise binary operations of a [Backend].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// The array operations an evaluation runs on.
+///
+/// The arguments are always valid: the ops check the shapes before calling the backend,
+/// so an implementation may panic on anything else.
+pub trait Backend<F: Float> {
+    /// A name for messages, e.g. `"cpu"`.
+    fn name(&self) -> &'static str;
+
+    /// Allocates an array of zeros of `shape`.
+    fn zeros(&self, shape: &[usize]) -> NdArray<F>;
+
+    /// Returns the matrix product `a b` of `a` of shape `(m, k)` and `b` of shape `(k, n)`.
+    ///
+    /// Either operand may be a transposed view.
+    fn matmul(&self, a: &ArrayView2<F>, b: &ArrayView2<F>) -> Array2<F>;
+
+    /// Returns `a op b`, broadcasting the shapes of `a` and `b` against each other.
+    fn binary(&self, op: BinaryOp, a: &NdArrayView<F>, b: &NdArrayView<F>) -> NdArray<F>;
+}
+
+/// The default [Backend], computing with ndarray (and BLAS if one of its features is enabled).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuBackend;
+
+impl<F: Float> Backend<F> for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn zeros(&self, shape: &[usize]) -> NdArray<F> {
+        ndarray_ext::zeros(shape)
+    }
+
+    fn matmul(&self, a: &ArrayView2<F>, b: &ArrayView2<F>) -> Array2<F> {
+        dot_ops::mat_mul(a, b, true)
+    }
+
+    fn binary(&self, op: BinaryOp, a: &NdArrayView<F>, b: &NdArrayView<F>) -> NdArray<F> {
+        match op {
+            BinaryOp::Add => binary_ops::add_forward(a, b),
+            BinaryOp::Sub => binary_ops::sub_forward(a, b),
+            BinaryOp::Mul => binary_ops::mul_forward(a, b),
+            BinaryOp::Div => binary_ops::div_forward(a, b),
+        }
+    }
+}
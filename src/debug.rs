@@ -0,0 +1,200 @@
+//! Reproducing failing training steps
+//!
+//! [dump_repro] saves what a step of a training loop evaluates its loss with: the variables
+//! of the context with its [global step](crate::Context::global_step), the arrays fed to the
+//! placeholders, the states of the generators of the random ops and a
+//! [fingerprint](crate::Graph::fingerprint) of the graph of the loss. [load_repro] restores
+//! them, e.g. in a test attached to a bug report, for the same code to build the graph again
+//! and evaluate the step as it was.
+//!
+//! The graph itself isn't saved, as ops can hold arbitrary code: [Repro::matches] checks that
+//! the graph rebuilt is the one dumped, and [Repro::restore] sets the generators of its random
+//! ops back to where they were at the dump, which is needed when the graph evaluated
+//! several steps before it.
+//!
+//! ```
+//! use autograd as ag;
+//! use ag::prelude::*;
+//! use ag::tensor_ops as T;
+//!
+//! fn loss<'g>(g: &'g ag::Context<f64>) -> ag::Tensor<'g, f64> {
+//!     let x = g.placeholder("x", &[-1, 2]);
+//!     T::sum_all(T::square(T::matmul(x, g.variable("w"))))
+//! }
+//!
+//! let path = std::env::temp_dir().join(format!("autograd_doc_repro_{}.json", std::process::id()));
+//! let mut env = ag::VariableEnvironment::new();
+//! env.slot().name("w").set(ag::ndarray::arr2(&[[1.], [2.]]));
+//! let x = ag::ndarray::arr2(&[[3., 4.]]);
+//!
+//! env.run(|g| {
+//!     let loss = loss(g);
+//!     let mut feeder = ag::Feeder::new();
+//!     feeder.push("x", x.view());
+//!     ag::debug::dump_repro(&loss, g, &feeder, &path).unwrap();
+//! });
+//!
+//! let repro = ag::debug::load_repro::<f64, _>(&path).unwrap();
+//! repro.env.run(|g| {
+//!     let loss = loss(g);
+//!     assert!(repro.matches(&loss, g));
+//!     repro.restore(g);
+//!     let ret = g.evaluator().push(loss).set_feeder(repro.feeder()).run();
+//!     // (3 * 1 + 4 * 2)^2
+//!     assert_eq!(ret[0], Ok(ag::ndarray::arr0(121.).into_dyn()));
+//! });
+//! # std::fs::remove_file(path).unwrap();
+//! ```
+use crate::evaluation::{Feeder, PlaceholderKey};
+use crate::graph::TensorID;
+use crate::ndarray_ext::NdArray;
+use crate::tensor::Tensor;
+use crate::variable::{DeserializedVariableEnvironment, SerializableVariableEnvironment};
+use crate::{Context, Float, VariableEnvironment};
+use rand_xorshift::XorShiftRng;
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+/// A training step restored by [load_repro].
+pub struct Repro<F: Float> {
+    /// The variables at the time of the dump
+    pub env: VariableEnvironment<F>,
+    /// The arrays fed to the placeholders, by placeholder name
+    pub feeds: Vec<(String, NdArray<F>)>,
+    fingerprint: u64,
+    // Generator of each random op, by tensor id
+    rngs: Vec<(TensorID, XorShiftRng)>,
+}
+
+impl<F: Float> Repro<F> {
+    /// Returns a feeder with the arrays fed to the placeholders of the dumped step.
+    pub fn feeder(&self) -> Feeder<F> {
+        let mut feeder = Feeder::new();
+        for (name, value) in &self.feeds {
+            feeder.push(crate::spec::intern(name), value.view());
+        }
+        feeder
+    }
+
+    /// Returns true if the graph of `loss` has the structure of the one dumped.
+    pub fn matches<'g>(&self, loss: &Tensor<'g, F>, ctx: &'g Context<F>) -> bool {
+        ctx.fingerprint(&[loss]) == self.fingerprint
+    }
+
+    /// Sets the generators of the random ops of `ctx` back to their states at the dump.
+    ///
+    /// Call it once the graph is built again, before evaluating the step: the generators are
+    /// given to the tensors with the same ids (see [Tensor::id]).
+    pub fn restore(&self, ctx: &Context<F>) {
+        let nodes = ctx.graph.node_set.borrow();
+        for (id, rng) in &self.rngs {
+            if let Some(op) = nodes.get(*id).and_then(|node| node.op.as_ref()) {
+                op.restore_state(rng);
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SerializableRepro<'a, F> {
+    fingerprint: u64,
+    feeds: Vec<(&'a str, NdArray<F>)>,
+    rngs: Vec<(TensorID, XorShiftRng)>,
+    env: SerializableVariableEnvironment<'a, F>,
+}
+
+#[derive(Deserialize)]
+struct DeserializedRepro<F> {
+    fingerprint: u64,
+    feeds: Vec<(String, NdArray<F>)>,
+    rngs: Vec<(TensorID, XorShiftRng)>,
+    env: DeserializedVariableEnvironment<F>,
+}
+
+/// Saves the variables of `ctx`, the arrays of `feeder`, the generators of the random ops of
+/// `ctx` and the fingerprint of the graph of `loss` to `path`, as JSON.
+/// See [the module docs](self).
+///
+/// Returns an error if the file can't be written, or if a random op draws from another
+/// generator than the default `XorShiftRng`, which can't be saved.
+pub fn dump_repro<'g, F: Float, P: AsRef<Path>>(
+    loss: &Tensor<'g, F>,
+    ctx: &'g Context<F>,
+    feeder: &Feeder<F>,
+    path: P,
+) -> Result<(), Box<dyn Error>> {
+    // the placeholders fed by tensor are saved by name
+    let feeds = feeder
+        .feeds
+        .iter()
+        .map(|feed| {
+            let name = match feed.placeholder_key {
+                PlaceholderKey::Name(name) => name,
+                PlaceholderKey::ID(id) => ctx.access_inner(id).placeholder_name.unwrap(),
+            };
+            (name, feed.value.to_owned())
+        })
+        .collect();
+    let mut rngs = Vec::new();
+    for node in ctx.graph.node_set.borrow().iter() {
+        let op = match node.op {
+            Some(ref op) => op,
+            None => continue,
+        };
+        if let Some(state) = op.state() {
+            match state.downcast::<XorShiftRng>() {
+                Ok(rng) => rngs.push((node.id, *rng)),
+                Err(_) => {
+                    return Err(format!(
+                        "dump_repro: the generator of {} (tensor {}) can't be saved",
+                        op.name(),
+                        node.id
+                    )
+                    .into())
+                }
+            }
+        }
+    }
+    let repro = SerializableRepro {
+        fingerprint: ctx.fingerprint(&[loss]),
+        feeds,
+        rngs,
+        env: ctx.env().prepare_for_serde(),
+    };
+    let f = File::create(path.as_ref())?;
+    serde_json::to_writer(f, &repro)?;
+    Ok(())
+}
+
+/// Restores a training step saved by [dump_repro].
+///
+/// Returns an error if the file can't be read or isn't a dump.
+pub fn load_repro<F: ReproFloat, P: AsRef<Path>>(path: P) -> Result<Repro<F>, Box<dyn Error>> {
+    F::load_repro(path.as_ref())
+}
+
+/// The float types [load_repro] can read, f32 and f64.
+pub trait ReproFloat: Float {
+    #[doc(hidden)]
+    fn load_repro(path: &Path) -> Result<Repro<Self>, Box<dyn Error>>;
+}
+
+macro_rules! impl_repro_float {
+    ($t:ty) => {
+        impl ReproFloat for $t {
+            fn load_repro(path: &Path) -> Result<Repro<Self>, Box<dyn Error>> {
+                let raw: DeserializedRepro<$t> = serde_json::from_reader(File::open(path)?)?;
+                Ok(Repro {
+                    env: VariableEnvironment::<$t>::load_internal(raw.env)?,
+                    feeds: raw.feeds,
+                    fingerprint: raw.fingerprint,
+                    rngs: raw.rngs,
+                })
+            }
+        }
+    };
+}
+
+impl_repro_float!(f32);
+impl_repro_float!(f64);
@@ -1,16 +1,21 @@
-use crate::ndarray::ArrayView;
-use crate::ndarray_ext::{NdArray, NdArrayView, RawNdArrayView};
+use crate::ndarray::{ArrayView, IxDyn, RawArrayViewMut};
+use crate::ndarray_ext::{self, NdArray, NdArrayView, RawNdArrayView};
 use crate::op::{self, OpInput};
 
 use crate::tensor::{Tensor, TensorInternal};
-use crate::{Context, FxHashMap, VariableEnvironment};
+use crate::{Context, FxHashMap, FxHashSet, VariableEnvironment};
 use crate::{Float, Graph};
 
-use std::cell::Ref;
 use crate::graph::TensorID;
+use crate::variable::VariableID;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 /// Unique id for a placeholder tensor
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum PlaceholderKey {
     Name(&'static str),
     ID(usize),
@@ -89,7 +94,7 @@ pub struct Evaluator<'graph, 'env, 'feed, F: Float> {
 /// ```
 #[derive(Clone)]
 pub struct Feeder<'view, F: Float> {
-    feeds: Vec<Feed<'view, F>>,
+    pub(crate) feeds: Vec<Feed<'view, F>>,
 }
 
 impl<'view, F: Float> Feeder<'view, F> {
@@ -111,6 +116,15 @@ impl<'view, F: Float> Feeder<'view, F> {
         });
         self
     }
+
+    // The same feeds, borrowed from `self`: more of shorter lifetimes can be pushed to it.
+    pub(crate) fn reborrow(&self) -> Feeder<'_, F> {
+        let feeds = self.feeds.iter().map(|feed| Feed {
+            placeholder_key: feed.placeholder_key,
+            value: feed.value.view(),
+        });
+        Feeder { feeds: feeds.collect() }
+    }
 }
 
 impl<'graph, 'env, 'view, F: Float> Context<'env, F> {
@@ -124,6 +138,332 @@ impl<'graph, 'env, 'view, F: Float> Context<'env, F> {
             eval_targets: Vec::new(),
         }
     }
+
+    /// Evaluates the tensors of `requests` in a single pass, and returns their values
+    /// keyed by the labels.
+    ///
+    /// The same tensor can be requested under several labels; if a label appears twice,
+    /// the last tensor wins. Fails with the first error in the order of `requests`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let logits = T::convert_to_tensor(ag::ndarray::arr1(&[1., 2.]), g);
+    ///     let loss = T::reduce_sum(logits, &[0], false);
+    ///     let ret = g.eval_named(&[("loss", &loss), ("logits", &logits)]).unwrap();
+    ///     assert_eq!(ret["loss"][[]], 3.);
+    ///     assert_eq!(ret["logits"].shape(), &[2]);
+    /// });
+    /// ```
+    pub fn eval_named(
+        &self,
+        requests: &[(&str, &Tensor<'graph, F>)],
+    ) -> Result<HashMap<String, NdArray<F>>, crate::EvalError> {
+        let targets: Vec<_> = requests.iter().map(|&(_, x)| *x).collect();
+        for x in &targets {
+            crate::graph::assert_same_graph(self, x.graph);
+        }
+        let results = self.eval(&targets, &[], self.var_env_ref, false);
+        let mut ret = HashMap::with_capacity(requests.len());
+        for (&(name, _), result) in requests.iter().zip(results) {
+            ret.insert(name.to_string(), result?);
+        }
+        Ok(ret)
+    }
+
+    /// Evaluates `tensors` in a single pass, and returns read-only views of their values.
+    ///
+    /// A tensor requested more than once is stored once: all its requests view the same
+    /// array, where [Evaluator::run] would return a copy for each. The arrays are owned by
+    /// the returned [EvalViews], which keeps the context borrowed while the views are used.
+    /// Fails with the first error in the order of `tensors`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let x: ag::Tensor<f32> = T::ones(&[3], g);
+    ///     let y = T::exp(x);
+    ///     let views = g.eval_views(&[&y, &x, &y]).unwrap();
+    ///     assert_eq!(views.len(), 3);
+    ///     assert_eq!(views.get(1), ag::ndarray::arr1(&[1., 1., 1.]).into_dyn());
+    ///     assert_eq!(views.get(0).as_ptr(), views.get(2).as_ptr());
+    /// });
+    /// ```
+    pub fn eval_views(
+        &self,
+        tensors: &[&Tensor<'graph, F>],
+    ) -> Result<EvalViews<'_, F>, crate::EvalError> {
+        let mut unique: Vec<Tensor<'graph, F>> = Vec::new();
+        let mut index = Vec::with_capacity(tensors.len());
+        for &x in tensors {
+            crate::graph::assert_same_graph(self, x.graph);
+            let i = unique.iter().position(|t| t.id == x.id).unwrap_or_else(|| {
+                unique.push(*x);
+                unique.len() - 1
+            });
+            index.push(i);
+        }
+        let results = self.eval(&unique, &[], self.var_env_ref, false);
+        if let Some(&i) = index.iter().find(|&&i| results[i].is_err()) {
+            return Err(results.into_iter().nth(i).unwrap().unwrap_err());
+        }
+        let arrays = results.into_iter().map(Result::unwrap).collect();
+        Ok(EvalViews {
+            arrays,
+            index,
+            _ctx: PhantomData,
+        })
+    }
+
+    /// Evaluates `xs` like [Evaluator::run], giving up once `limit` of wall-clock time
+    /// has elapsed.
+    ///
+    /// The clock is checked before each op's computation: when it's past the deadline, no
+    /// further op is run and the tensors not computed yet fail with
+    /// [EvalError::Timeout](crate::EvalError::Timeout). An op is never interrupted, so a
+    /// single long op can overrun `limit` by its whole running time.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    /// use std::time::Duration;
+    ///
+    /// ag::run(|g| {
+    ///     let x: ag::Tensor<f32> = T::ones(&[2], g);
+    ///     let y = T::exp(x);
+    ///     let ret = g.eval_with_timeout(&[&y], Duration::from_secs(10));
+    ///     assert!(ret[0].is_ok());
+    ///     let ret = g.eval_with_timeout(&[&y], Duration::from_secs(0));
+    ///     assert!(matches!(ret[0], Err(ag::EvalError::Timeout { .. })));
+    /// });
+    /// ```
+    pub fn eval_with_timeout(
+        &self,
+        xs: &[&Tensor<'graph, F>],
+        limit: Duration,
+    ) -> Vec<Result<NdArray<F>, crate::EvalError>> {
+        for x in xs {
+            crate::graph::assert_same_graph(self, x.graph);
+        }
+        let targets: Vec<_> = xs.iter().map(|&&x| x).collect();
+        self.deadline.set(Some((Instant::now() + limit, limit)));
+        let ret = self.eval(&targets, &[], self.var_env_ref, false);
+        self.deadline.set(None);
+        ret
+    }
+
+    /// Evaluates `xs` with `feeder` like [Evaluator::run], and also returns the shape of every
+    /// op output computed by this call, in the order the ops ran.
+    ///
+    /// Where [Context::infer_shape] can't tell a shape before the evaluation, this gives the
+    /// concrete ones, e.g. to find the op a shape error comes from. Only the ops actually run
+    /// are listed: not the placeholders, variables and cached tensors, nor the failed ops.
+    /// For an op with several outputs, the shape is the one of the first.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let x: ag::Tensor<f32> = T::ones(&[2, 3], g);
+    ///     let y = T::reduce_sum(x, &[1], false);
+    ///     let (ret, trace) = g.eval_with_trace(&[&y], ag::Feeder::new());
+    ///     assert_eq!(ret[0].as_ref().unwrap().shape(), &[2]);
+    ///     let (last, shape) = trace.last().unwrap();
+    ///     assert_eq!((last.id(), shape.as_slice()), (y.id(), &[2][..]));
+    /// });
+    /// ```
+    pub fn eval_with_trace(
+        &'graph self,
+        xs: &[&Tensor<'graph, F>],
+        feeder: Feeder<F>,
+    ) -> (
+        Vec<Result<NdArray<F>, crate::EvalError>>,
+        Vec<(Tensor<'graph, F>, Vec<usize>)>,
+    ) {
+        for x in xs {
+            crate::graph::assert_same_graph(self, x.graph);
+        }
+        let targets: Vec<_> = xs.iter().map(|&&x| x).collect();
+        *self.shape_trace.borrow_mut() = Some(Vec::new());
+        let ret = self.eval(&targets, &feeder.feeds, self.var_env_ref, false);
+        let trace = self.shape_trace.borrow_mut().take().unwrap();
+        let trace = trace
+            .into_iter()
+            .map(|(id, shape)| (self.tensor(id), shape))
+            .collect();
+        (ret, trace)
+    }
+
+    /// Evaluates `xs` with `feeder` like [Evaluator::run], through a compilation of their graph
+    /// specialized for the shapes fed to the placeholders.
+    ///
+    /// The first call for some `xs` and feed shapes [compiles](crate::Graph::compile) the
+    /// graph of `xs`, and replaces the [shape](crate::tensor_ops::shape) of each fed
+    /// placeholder with a constant; the compiled tensors are kept in this context, and later
+    /// calls with the same `xs` and shapes evaluate them again. A feed of another shape
+    /// compiles again, e.g. for the last smaller batch of an epoch. For graphs built once and
+    /// evaluated at every step; see [Context::compilation_count] for the number of compilations.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = g.placeholder("x", &[-1, 2]);
+    ///     let y = T::exp(x) + x;
+    ///     for batch_size in [3, 3, 1] {
+    ///         let value = ag::ndarray::Array2::zeros((batch_size, 2));
+    ///         let mut feeder = ag::Feeder::new();
+    ///         feeder.push(x, value.view());
+    ///         let ret = g.eval_compiled(&[y], feeder);
+    ///         assert_eq!(ret[0].as_ref().unwrap().shape(), &[batch_size, 2]);
+    ///     }
+    ///     assert_eq!(g.compilation_count(), 2);
+    /// });
+    /// ```
+    pub fn eval_compiled<A: AsRef<Tensor<'graph, F>> + Copy>(
+        &'graph self,
+        xs: &[A],
+        feeder: Feeder<F>,
+    ) -> Vec<Result<NdArray<F>, crate::EvalError>> {
+        for x in xs {
+            crate::graph::assert_same_graph(self, x.as_ref().graph);
+        }
+        let mut feed_shapes: Vec<_> = feeder
+            .feeds
+            .iter()
+            .map(|feed| (feed.placeholder_key, feed.value.shape().to_vec()))
+            .collect();
+        // feeds pushed in another order share the compilation
+        feed_shapes.sort_by_key(|&(key, _)| key);
+        let key = (xs.iter().map(|x| x.as_ref().id).collect(), feed_shapes);
+        let cached = self.compile_cache.borrow().get(&key).cloned();
+        let compiled = match cached {
+            Some(ids) => ids.into_iter().map(|id| self.tensor(id)).collect(),
+            None => {
+                let compiled = self.compile_for_shapes(xs, &key.1);
+                let ids = compiled.iter().map(|y| y.id).collect();
+                self.compile_cache.borrow_mut().insert(key, ids);
+                self.compilations.set(self.compilations.get() + 1);
+                compiled
+            }
+        };
+        self.eval(&compiled, &feeder.feeds, self.var_env_ref, false)
+    }
+
+    // `Graph::compile`, with the shapes of the placeholders fed with `shapes` folded
+    fn compile_for_shapes<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        xs: &[A],
+        shapes: &[(PlaceholderKey, Vec<usize>)],
+    ) -> Vec<Tensor<'graph, F>> {
+        let compiled = self.compile(xs);
+        self.rewrite(&compiled, |t| {
+            if t.op_name() != "Shape" {
+                return None;
+            }
+            let x = t.get_input(0);
+            let name = self.access_inner(x.id).placeholder_name?;
+            let (_, shape) = shapes.iter().find(|(key, _)| match *key {
+                PlaceholderKey::ID(id) => id == x.id,
+                PlaceholderKey::Name(n) => n == name,
+            })?;
+            let shape = shape.iter().map(|&d| F::from(d).unwrap()).collect::<Vec<_>>();
+            Some(crate::tensor_ops::convert_to_tensor(ndarray::Array1::from(shape), self))
+        })
+    }
+
+    /// Returns the number of compilations done by [Context::eval_compiled] in this context.
+    pub fn compilation_count(&self) -> usize {
+        self.graph.compilations.get()
+    }
+
+    /// Evaluates `xs` like [Evaluator::run], with the ops parallelized over at most
+    /// `num_threads` threads.
+    ///
+    /// The ops using rayon (convolutions, batched matmuls...) otherwise share its global
+    /// thread pool, sized to the number of CPUs. Processes running several models at once can
+    /// give each evaluation a few threads instead, so that they don't oversubscribe the CPUs;
+    /// `num_threads = 1` makes the evaluation serial. The pool is built for this call only.
+    ///
+    /// The evaluation itself stays on the calling thread: the ops run their parallel sections
+    /// in the pool through [ComputeContext::install](crate::op::ComputeContext::install).
+    ///
+    /// Panics if `num_threads` is 0, or if the thread pool can't be built.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let x: ag::Tensor<f32> = T::ones(&[4, 2, 2], g);
+    ///     let y = T::batch_matmul(x, x);
+    ///     let ret = g.eval_with_threads(&[&y], 1);
+    ///     assert_eq!(ret[0].as_ref().unwrap()[[3, 1, 0]], 2.);
+    /// });
+    /// ```
+    pub fn eval_with_threads(
+        &self,
+        xs: &[&Tensor<'graph, F>],
+        num_threads: usize,
+    ) -> Vec<Result<NdArray<F>, crate::EvalError>> {
+        assert!(
+            num_threads > 0,
+            "eval_with_threads: num_threads must be positive"
+        );
+        for x in xs {
+            crate::graph::assert_same_graph(self, x.graph);
+        }
+        let targets: Vec<_> = xs.iter().map(|&&x| x).collect();
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .build()
+            .expect("eval_with_threads: failed to build the thread pool");
+        let prev = self.graph.thread_pool.replace(Some(Rc::new(pool)));
+        let ret = self.eval(&targets, &[], self.var_env_ref, false);
+        *self.graph.thread_pool.borrow_mut() = prev;
+        ret
+    }
+}
+
+/// The values of the tensors requested from [Context::eval_views], in the order of the request.
+pub struct EvalViews<'c, F: Float> {
+    arrays: Vec<NdArray<F>>,
+    // Index in `arrays` of each request
+    index: Vec<usize>,
+    _ctx: PhantomData<&'c ()>,
+}
+
+impl<'c, F: Float> EvalViews<'c, F> {
+    /// Returns the number of requested tensors.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns true if no tensor was requested.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Returns the value of the `i` th requested tensor.
+    ///
+    /// Panics if `i` is out of bounds.
+    #[inline]
+    pub fn get(&self, i: usize) -> NdArrayView<'_, F> {
+        self.arrays[self.index[i]].view()
+    }
+
+    /// Iterates over the values in the order of the request.
+    pub fn iter(&self) -> impl Iterator<Item = NdArrayView<'_, F>> {
+        self.index.iter().map(move |&i| self.arrays[i].view())
+    }
 }
 
 impl<'tensor, 'view, 'graph, 'env, 'ctx, F: Float> Evaluator<'graph, 'env, 'view, F> {
@@ -166,59 +506,838 @@ impl<'tensor, 'view, 'graph, 'env, 'ctx, F: Float> Evaluator<'graph, 'env, 'view
     #[inline]
     /// Evaluates the buffered tensors.
     pub fn run(&'tensor self) -> Vec<Result<NdArray<F>, crate::EvalError>> {
-        self.graph
-            .eval(self.eval_targets.as_slice(), &self.feeder.feeds, self.var_env)
+        self.graph.eval(
+            self.eval_targets.as_slice(),
+            &self.feeder.feeds,
+            self.var_env,
+            false,
+        )
+    }
+
+    /// Same as [Evaluator::run], but the outputs of all the nodes computed along the way
+    /// stay cached in the context so that they can be read with [Context::peek].
+    ///
+    /// The cache holds a copy of every intermediate array until [Context::clear_kept]
+    /// (or [Context::clear]) is called, so the memory used by an evaluation is not released
+    /// when it returns. See [Context::memory_report] to check its size.
+    pub fn run_keep(&'tensor self) -> Vec<Result<NdArray<F>, crate::EvalError>> {
+        self.graph.eval(
+            self.eval_targets.as_slice(),
+            &self.feeder.feeds,
+            self.var_env,
+            true,
+        )
+    }
+
+    /// Evaluates the buffered tensors once for each feeder yielded by `feeders`.
+    ///
+    /// The execution order is computed only once, and the arrays of the intermediate outputs
+    /// are recycled from a batch to the next: they go to the
+    /// [buffer pool](Context::set_buffer_pool) of the context, or to a pool of the stream if
+    /// that's off. A dataset can then be processed batch by batch without rebuilding the
+    /// graph, the batches of the same shape allocating most of their arrays once.
+    /// The feeds of each batch take precedence over the feeds of this evaluator,
+    /// which are shared by all the batches.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let x = g.placeholder("x", &[-1]);
+    ///     let y = T::reduce_sum(x, &[0], false);
+    ///
+    ///     let batches = vec![ag::ndarray::arr1(&[1., 2.]), ag::ndarray::arr1(&[3., 4.])];
+    ///     let feeders = batches.iter().map(|batch| {
+    ///         let mut feeder = ag::Feeder::new();
+    ///         feeder.push(x, batch.view());
+    ///         feeder
+    ///     });
+    ///     let sums: Vec<f64> = g
+    ///         .evaluator()
+    ///         .push(y)
+    ///         .run_stream(feeders)
+    ///         .map(|ret| ret[0].as_ref().unwrap()[[]])
+    ///         .collect();
+    ///     assert_eq!(sums, vec![3., 7.]);
+    /// });
+    /// ```
+    pub fn run_stream<I>(&self, feeders: I) -> EvalStream<'_, 'graph, 'env, 'view, F, I::IntoIter>
+    where
+        I: IntoIterator<Item = Feeder<'view, F>>,
+    {
+        EvalStream {
+            order: self.graph.schedule(&self.eval_targets),
+            cache_version: self.graph.cache_version.get(),
+            storage: OpOutputStorage::new(),
+            pool: Rc::new(RefCell::new(BufferPool::new())),
+            evaluator: self,
+            feeders: feeders.into_iter(),
+        }
+    }
+}
+
+/// Iterator returned by [Evaluator::run_stream].
+///
+/// Yields the results of the evaluation targets for each batch.
+pub struct EvalStream<'e, 'graph, 'env, 'view, F: Float, I> {
+    evaluator: &'e Evaluator<'graph, 'env, 'view, F>,
+    order: Vec<TensorID>,
+    // `Graph::cache_version` when `order` was computed
+    cache_version: usize,
+    storage: OpOutputStorage<F>,
+    // The pool of the batches when the context has none
+    pool: Rc<RefCell<BufferPool<F>>>,
+    feeders: I,
+}
+
+impl<'e, 'graph, 'env, 'view, F, I> Iterator for EvalStream<'e, 'graph, 'env, 'view, F, I>
+where
+    F: Float,
+    I: Iterator<Item = Feeder<'view, F>>,
+{
+    type Item = Vec<Result<NdArray<F>, crate::EvalError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut feeds = self.feeders.next()?.feeds;
+        feeds.extend(self.evaluator.feeder.feeds.iter().cloned());
+        let ev = self.evaluator;
+        let cache_version = ev.graph.cache_version.get();
+        if cache_version != self.cache_version {
+            // cached values cut off parts of the graph
+            self.order = ev.graph.schedule(&ev.eval_targets);
+            self.cache_version = cache_version;
+        }
+        let pool = ev.graph.pool.borrow().clone().unwrap_or_else(|| self.pool.clone());
+        // the outputs left by the previous batch
+        self.storage.recycle(&mut pool.borrow_mut());
+        let prev = ev.graph.pool.replace(Some(pool));
+        let ret = ev.graph.eval_in_order(
+            &ev.eval_targets,
+            &self.order,
+            &mut self.storage,
+            &feeds,
+            ev.var_env,
+            false,
+        );
+        *ev.graph.pool.borrow_mut() = prev;
+        Some(ret)
+    }
+}
+
+/// Memory held by a single array, as reported by [Context::memory_report].
+#[derive(Clone, Debug, PartialEq)]
+pub struct TensorMemory {
+    /// Variable id for variable arrays, tensor id for op outputs.
+    pub id: usize,
+    /// `"namespace/name"` for variables (just the name in the default namespace),
+    /// the op name for op outputs.
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub bytes: usize,
+}
+
+impl TensorMemory {
+    fn new<A>(id: usize, name: String, arr: &ndarray::ArrayViewD<A>) -> Self {
+        TensorMemory {
+            id,
+            name,
+            shape: arr.shape().to_vec(),
+            bytes: arr.len() * std::mem::size_of::<A>(),
+        }
+    }
+}
+
+/// Summary of the arrays a [Context] retains.
+///
+/// `outputs` are the op outputs alive at the peak of the most recent evaluation.
+/// An output is dropped once its last consumer has run, and the targets when it returns.
+/// Outputs that merely view their inputs occupy no memory and are not listed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MemoryReport {
+    pub variables: Vec<TensorMemory>,
+    pub outputs: Vec<TensorMemory>,
+    /// Outputs cached by [Evaluator::run_keep] or [Tensor::eval_keep].
+    pub kept: Vec<TensorMemory>,
+}
+
+impl MemoryReport {
+    /// Total bytes of the variable arrays.
+    pub fn variable_bytes(&self) -> usize {
+        self.variables.iter().map(|m| m.bytes).sum()
+    }
+
+    /// Total bytes of the op outputs.
+    pub fn output_bytes(&self) -> usize {
+        self.outputs.iter().map(|m| m.bytes).sum()
+    }
+
+    /// Total bytes of the cached outputs.
+    pub fn kept_bytes(&self) -> usize {
+        self.kept.iter().map(|m| m.bytes).sum()
+    }
+
+    /// `variable_bytes() + output_bytes() + kept_bytes()`
+    pub fn total_bytes(&self) -> usize {
+        self.variable_bytes() + self.output_bytes() + self.kept_bytes()
+    }
+}
+
+fn variable_memory<F: Float>(env: &VariableEnvironment<F>) -> Vec<TensorMemory> {
+    let mut names = vec![None; env.array_list.len()];
+    for (name, vid) in &env.name_to_id {
+        names[vid.0] = Some(if name.namespace_id.is_empty() {
+            name.variable_name.clone()
+        } else {
+            format!("{}/{}", name.namespace_id, name.variable_name)
+        });
+    }
+    env.array_list
+        .iter()
+        .zip(names)
+        .enumerate()
+        .map(|(i, (arr, name))| {
+            #[cfg(feature = "f16")]
+            if let Some(half) = env.half.get(&VariableID(i)) {
+                let mut ret = TensorMemory::new(i, name.unwrap_or_default(), &half.borrow().view());
+                // plus the master weights, if any
+                ret.bytes += arr.borrow().len() * std::mem::size_of::<F>();
+                return ret;
+            }
+            TensorMemory::new(i, name.unwrap_or_default(), &arr.borrow().view())
+        })
+        .collect()
+}
+
+fn kept_memory<F: Float>(graph: &Graph<F>) -> Vec<TensorMemory> {
+    let mut ret: Vec<_> = graph
+        .kept_outputs
+        .borrow()
+        .iter()
+        .map(|(&id, y)| {
+            let name = graph.access_inner(id).get_op().name().to_string();
+            TensorMemory::new(id, name, &y.view())
+        })
+        .collect();
+    ret.sort_by_key(|m| m.id);
+    ret
+}
+
+impl<'env, F: Float> Context<'env, F> {
+    /// Returns the value of `t` cached by the last [Evaluator::run_keep] or
+    /// [Tensor::eval_keep] that computed it.
+    ///
+    /// Placeholders and variables are never cached; this returns `None` for them,
+    /// as well as for tensors that failed to compute.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let x: ag::Tensor<f64> = T::ones(&[2], g);
+    ///     let hidden = T::exp(x);
+    ///     let y = T::reduce_sum(hidden, &[0], false);
+    ///
+    ///     y.eval_keep(g).unwrap();
+    ///     assert_eq!(*g.peek(&hidden).unwrap(), ag::ndarray::arr1(&[1f64.exp(); 2]).into_dyn());
+    ///     g.clear_kept();
+    ///     assert!(g.peek(&hidden).is_none());
+    /// });
+    /// ```
+    pub fn peek(&self, t: &Tensor<F>) -> Option<Ref<'_, NdArray<F>>> {
+        crate::graph::assert_same_graph(self, t.graph);
+        Ref::filter_map(self.graph.kept_outputs.borrow(), |kept| kept.get(&t.id)).ok()
+    }
+
+    /// Returns a copy of the value [Context::peek] would borrow, for saving activations.
+    ///
+    /// The array is owned, so holding it doesn't keep the cache borrowed: the context can
+    /// evaluate or [clear_kept](Context::clear_kept) meanwhile.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let hidden: ag::Tensor<f64> = T::exp(T::ones(&[2], g));
+    ///     hidden.eval_keep(g).unwrap();
+    ///     let saved = g.snapshot(&hidden).unwrap();
+    ///     g.clear_kept();
+    ///     assert_eq!(saved, hidden.eval(g).unwrap());
+    /// });
+    /// ```
+    pub fn snapshot(&self, t: &Tensor<F>) -> Option<NdArray<F>> {
+        self.peek(t).map(|y| y.clone())
+    }
+
+    /// Drops all the values cached by [Evaluator::run_keep] or [Tensor::eval_keep].
+    pub fn clear_kept(&self) {
+        self.graph.kept_outputs.borrow_mut().clear();
+    }
+
+    /// Reports the bytes held by the variables and by the op outputs of the last evaluation.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// let mut env = ag::VariableEnvironment::<f32>::new();
+    /// env.name("w").set(ag::ndarray_ext::zeros(&[2, 3]));
+    ///
+    /// env.run(|g| {
+    ///     let report = g.memory_report();
+    ///     assert_eq!(report.variable_bytes(), 2 * 3 * 4);
+    ///     assert_eq!(report.variables[0].name, "w");
+    /// });
+    /// ```
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport {
+            variables: variable_memory(self.var_env_ref),
+            outputs: self.graph.peak_outputs.borrow().clone(),
+            kept: kept_memory(&self.graph),
+        }
+    }
+
+    /// Returns the number of ops computed by the evaluations of this context.
+    ///
+    /// Outputs reused from a cache, e.g. of a [pinned](crate::Tensor::pin) tensor, don't count.
+    /// See [Context::reset_counters] to start counting again.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let x = T::ones(&[2], g);
+    ///     let y = T::exp(x) + T::square(x);
+    ///     y.eval(g).unwrap();
+    ///     // the shape of `ones`, ones, exp, square and add
+    ///     assert_eq!(g.op_call_count(), 5);
+    ///     assert_eq!(g.op_call_counts()[0], ("AddOp", 1));
+    /// });
+    /// ```
+    pub fn op_call_count(&self) -> usize {
+        self.graph.op_calls.borrow().values().sum()
+    }
+
+    /// Same as [Context::op_call_count], by op type: `(op name, count)` sorted by name.
+    pub fn op_call_counts(&self) -> Vec<(&'static str, usize)> {
+        let mut ret: Vec<_> = self
+            .graph
+            .op_calls
+            .borrow()
+            .iter()
+            .map(|(&name, &n)| (name, n))
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// Returns the number of arrays allocated for the op outputs by the evaluations of this
+    /// context.
+    ///
+    /// Outputs viewing the arrays of their inputs, e.g. of [reshape](crate::tensor_ops::reshape),
+    /// and outputs written into arrays of the [buffer pool](Context::set_buffer_pool) don't
+    /// count. See [Context::reset_counters] to start counting again.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let x = T::ones(&[2, 3], g);
+    ///     T::reshape(T::exp(x), &[3, 2]).eval(g).unwrap();
+    ///     // the shapes of `ones` and `reshape`, ones and exp
+    ///     assert_eq!(g.allocation_count(), 4);
+    /// });
+    /// ```
+    pub fn allocation_count(&self) -> usize {
+        self.graph.allocations.get()
+    }
+
+    /// Resets the counts of [Context::op_call_count] and [Context::allocation_count] to zero.
+    pub fn reset_counters(&self) {
+        self.graph.op_calls.borrow_mut().clear();
+        self.graph.allocations.set(0);
+    }
+
+    /// Registers a hook called at the end of every evaluation with the outputs alive
+    /// at its peak memory usage.
+    pub fn set_memory_hook<H: Fn(&MemoryReport) + 'static>(&self, hook: H) {
+        *self.graph.memory_hook.borrow_mut() = Some(Box::new(hook));
+    }
+
+    /// Removes the hook registered by [Context::set_memory_hook].
+    pub fn clear_memory_hook(&self) {
+        *self.graph.memory_hook.borrow_mut() = None;
+    }
+
+    /// Registers a hook called with each variable and its gradient as soon as the gradient
+    /// is computed by an evaluation, e.g. to monitor vanishing or exploding gradients.
+    ///
+    /// The gradients are the ones given by [grad](crate::tensor_ops::grad),
+    /// [grad_with_default](crate::tensor_ops::grad_with_default) and
+    /// [grad_helper](crate::optimizers::grad_helper). See also [GradientNorms].
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::new();
+    /// let w = env.name("w").set(ag::ndarray_ext::ones(&[2, 3]));
+    ///
+    /// env.run(|g| {
+    ///     let norms = ag::GradientNorms::new();
+    ///     g.on_gradient(norms.hook());
+    ///
+    ///     let w = g.variable(w);
+    ///     let gw = T::grad(&[T::reduce_sum(w, &[0, 1], false)], &[w])[0];
+    ///     gw.eval(g).unwrap();
+    ///     assert_eq!(norms.get(w.get_variable_id().unwrap()), Some(6f32.sqrt()));
+    /// });
+    /// ```
+    pub fn on_gradient<H: FnMut(&Tensor<F>, &NdArray<F>) + 'static>(&self, hook: H) {
+        *self.graph.gradient_hook.borrow_mut() = Some(Box::new(hook));
+    }
+
+    /// Removes the hook registered by [Context::on_gradient].
+    pub fn clear_gradient_hook(&self) {
+        *self.graph.gradient_hook.borrow_mut() = None;
+    }
+
+    /// Makes the evaluations of this context time each `Op::compute` call into `profiler`.
+    ///
+    /// Timing costs a clock read per op, so there is no profiler by default.
+    pub fn set_profiler(&self, profiler: &Profiler) {
+        *self.graph.profiler.borrow_mut() = Some(profiler.clone());
+    }
+
+    /// Removes the profiler set by [Context::set_profiler].
+    pub fn clear_profiler(&self) {
+        *self.graph.profiler.borrow_mut() = None;
+    }
+
+    /// Makes the evaluations of this context recycle the arrays of the op outputs.
+    ///
+    /// With the pool on, an output is given back to the pool as soon as its last consumer
+    /// has run, instead of being freed, and the ops drawing their output from the pool (the
+    /// element-wise arithmetic and activations, the broadcasts of the reduction gradients)
+    /// reuse an array of the same size if it holds one. A training loop computing the same
+    /// shapes at each step then allocates most of its arrays once. The pool keeps the arrays
+    /// across the evaluations, and is dropped by turning it off; it's off by default. Custom
+    /// ops can use it with [ComputeContext::alloc](crate::op::ComputeContext::alloc).
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     g.set_buffer_pool(true);
+    ///     let x = T::ones(&[2, 3], g);
+    ///     let y = T::tanh(T::exp(x) * 2.);
+    ///     y.eval(g).unwrap();
+    ///     y.eval(g).unwrap();
+    ///     // the second evaluation reused the outputs freed by the first one
+    ///     assert!(g.buffer_pool_stats().hits > 0);
+    /// });
+    /// ```
+    pub fn set_buffer_pool(&self, enabled: bool) {
+        let mut pool = self.graph.pool.borrow_mut();
+        if !enabled {
+            *pool = None;
+        } else if pool.is_none() {
+            *pool = Some(Rc::new(RefCell::new(BufferPool::new())));
+        }
+    }
+
+    /// Returns the counters of the pool turned on by [Context::set_buffer_pool], or zeros if
+    /// it's off.
+    pub fn buffer_pool_stats(&self) -> PoolStats {
+        match &*self.graph.pool.borrow() {
+            Some(pool) => pool.borrow().stats(),
+            None => PoolStats::default(),
+        }
+    }
+}
+
+/// Counters of the buffer pool of a context, see [Context::set_buffer_pool].
+///
+/// The counts are since the pool was turned on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Arrays requested by the ops and taken from the pool
+    pub hits: usize,
+    /// Arrays requested by the ops and allocated, the pool having none of their size
+    pub misses: usize,
+    /// Op outputs given back to the pool
+    pub recycled: usize,
+    /// Bytes of the arrays held by the pool now
+    pub pooled_bytes: usize,
+}
+
+// Buffers kept by a `BufferPool` for each length
+const MAX_POOLED_BUFFERS: usize = 64;
+
+// Buffers of the op outputs no longer read, by length.
+// Only the lengths requested by an op are kept, since the outputs of the ops allocating
+// their own arrays would otherwise pile up.
+pub(crate) struct BufferPool<F: Float> {
+    buffers: FxHashMap<usize, Vec<Vec<F>>>,
+    stats: PoolStats,
+}
+
+impl<F: Float> BufferPool<F> {
+    pub(crate) fn new() -> Self {
+        BufferPool {
+            buffers: FxHashMap::default(),
+            stats: PoolStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> PoolStats {
+        self.stats
+    }
+
+    // A buffer of `len` elements with unspecified values
+    pub(crate) fn take(&mut self, len: usize) -> Vec<F> {
+        match self.buffers.get_mut(&len).and_then(|b| b.pop()) {
+            Some(buffer) => {
+                self.stats.hits += 1;
+                self.stats.pooled_bytes -= len * std::mem::size_of::<F>();
+                buffer
+            }
+            None => {
+                self.stats.misses += 1;
+                self.buffers.entry(len).or_default();
+                vec![F::zero(); len]
+            }
+        }
+    }
+
+    // Keeps the buffer of `x` if its length was requested, unless it's not contiguous
+    pub(crate) fn recycle(&mut self, x: NdArray<F>) {
+        let buffers = match self.buffers.get_mut(&x.len()) {
+            Some(b) if b.len() < MAX_POOLED_BUFFERS && x.is_standard_layout() => b,
+            _ => return,
+        };
+        let buffer = x.into_raw_vec();
+        self.stats.recycled += 1;
+        self.stats.pooled_bytes += buffer.len() * std::mem::size_of::<F>();
+        buffers.push(buffer);
+    }
+}
+
+/// Times of the `Op::compute` calls, by op type.
+///
+/// Register it with [Context::set_profiler]. The clones of a profiler share the records,
+/// which accumulate over the evaluations until [Profiler::clear].
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g: &mut ag::Context<f32>| {
+///     let profiler = ag::Profiler::new();
+///     g.set_profiler(&profiler);
+///     let x = T::ones(&[64, 64], g);
+///     let y = T::matmul(x, x);
+///     y.eval(g).unwrap();
+///     y.eval(g).unwrap();
+///
+///     let matmul = profiler.stats().into_iter().find(|t| t.name == "MatMul").unwrap();
+///     assert_eq!(matmul.count, 2);
+///     println!("{}", profiler.report());
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Profiler {
+    times: Rc<RefCell<FxHashMap<&'static str, Vec<Duration>>>>,
+}
+
+/// Timing statistics of an op type, given by [Profiler::stats].
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpTiming {
+    /// Short name of the op, as in [Tensor::op_name]
+    pub name: &'static str,
+    /// Number of calls
+    pub count: usize,
+    pub total: Duration,
+    pub mean: Duration,
+    /// Median of the calls
+    pub p50: Duration,
+    /// 95th percentile of the calls
+    pub p95: Duration,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler::default()
+    }
+
+    fn record(&self, name: &'static str, time: Duration) {
+        self.times.borrow_mut().entry(name).or_default().push(time);
+    }
+
+    /// Statistics of each op type, the longest total time first.
+    pub fn stats(&self) -> Vec<OpTiming> {
+        let mut ret: Vec<_> = self
+            .times
+            .borrow()
+            .iter()
+            .map(|(&name, times)| {
+                let mut sorted = times.clone();
+                sorted.sort();
+                // nearest-rank percentile
+                let percentile =
+                    |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).max(1) - 1];
+                let total: Duration = sorted.iter().sum();
+                OpTiming {
+                    name,
+                    count: sorted.len(),
+                    total,
+                    mean: total / sorted.len() as u32,
+                    p50: percentile(0.5),
+                    p95: percentile(0.95),
+                }
+            })
+            .collect();
+        ret.sort_by(|a, b| b.total.cmp(&a.total).then(a.name.cmp(b.name)));
+        ret
+    }
+
+    /// Formats [Profiler::stats] as a table, with the times in milliseconds.
+    pub fn report(&self) -> String {
+        let stats = self.stats();
+        let width = stats.iter().map(|t| t.name.len()).max().unwrap_or(0).max(2);
+        let ms = |d: Duration| d.as_secs_f64() * 1e3;
+        let mut ret = format!(
+            "{:<w$} {:>8} {:>12} {:>10} {:>10} {:>10}\n",
+            "op",
+            "count",
+            "total (ms)",
+            "mean",
+            "p50",
+            "p95",
+            w = width
+        );
+        for t in stats {
+            ret += &format!(
+                "{:<w$} {:>8} {:>12.3} {:>10.3} {:>10.3} {:>10.3}\n",
+                t.name,
+                t.count,
+                ms(t.total),
+                ms(t.mean),
+                ms(t.p50),
+                ms(t.p95),
+                w = width
+            );
+        }
+        ret
+    }
+
+    pub fn clear(&self) {
+        self.times.borrow_mut().clear();
+    }
+}
+
+/// Records the L2 norm of the latest gradient of each variable.
+///
+/// Register [GradientNorms::hook] with [Context::on_gradient].
+#[derive(Clone, Default)]
+pub struct GradientNorms<F: Float> {
+    norms: Rc<RefCell<FxHashMap<VariableID, F>>>,
+}
+
+impl<F: Float> GradientNorms<F> {
+    pub fn new() -> Self {
+        GradientNorms {
+            norms: Rc::new(RefCell::new(FxHashMap::default())),
+        }
+    }
+
+    /// Hook recording into this; the clones of `self` share the records.
+    pub fn hook(&self) -> impl FnMut(&Tensor<F>, &NdArray<F>) + 'static {
+        let norms = self.norms.clone();
+        move |var, gx| {
+            if let Some(vid) = var.get_variable_id() {
+                let norm = gx.fold(F::zero(), |acc, &g| acc + g * g).sqrt();
+                norms.borrow_mut().insert(vid, norm);
+            }
+        }
+    }
+
+    /// The norm of the latest gradient of the variable `vid`.
+    pub fn get(&self, vid: VariableID) -> Option<F> {
+        self.norms.borrow().get(&vid).cloned()
+    }
+
+    /// The norms of all the variables whose gradients have been computed.
+    pub fn norms(&self) -> FxHashMap<VariableID, F> {
+        self.norms.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.norms.borrow_mut().clear();
     }
 }
 
 #[derive(Clone)]
 pub(crate) struct Feed<'view, T: Float> {
     /// The id of the placeholder tensor
-    placeholder_key: PlaceholderKey,
+    pub(crate) placeholder_key: PlaceholderKey,
     /// A run-time value of the placeholder
-    value: NdArrayView<'view, T>,
+    pub(crate) value: NdArrayView<'view, T>,
 }
 
 // Storage in which compute results are stored.
-struct OpOutputStorage<F: Float> {
-    inner: FxHashMap<TensorID, Result<op::SmallVec<OpOutput<F>>, op::OpError>>
+pub(crate) struct OpOutputStorage<F: Float> {
+    inner: FxHashMap<TensorID, Result<op::SmallVec<StoredOutput<F>>, op::OpError>>
 }
 
 use crate::op::OpOutput;
 
+// An output of `Op::compute` in the storage
+enum StoredOutput<F: Float> {
+    Full(OpOutput<F>),
+    // Output of a node built inside `Context::autocast`
+    #[cfg(feature = "f16")]
+    Half(ndarray::ArrayD<half::f16>),
+}
+
+impl<F: Float> StoredOutput<F> {
+    // Stores `y`, rounded to f16 if `half`
+    fn new(y: OpOutput<F>, half: bool) -> Self {
+        #[cfg(feature = "f16")]
+        if half {
+            let y = match y {
+                OpOutput::Owned(y) => y.mapv(|x| half::f16::from_f32(x.to_f32().unwrap())),
+                OpOutput::View(y) => unsafe { y.deref_into_view() }
+                    .mapv(|x| half::f16::from_f32(x.to_f32().unwrap())),
+            };
+            return StoredOutput::Half(y);
+        }
+        debug_assert!(!half, "f16 outputs require the `f16` feature");
+        StoredOutput::Full(y)
+    }
+
+    fn shape(&self) -> &[usize] {
+        match self {
+            StoredOutput::Full(OpOutput::Owned(y)) => y.shape(),
+            StoredOutput::Full(OpOutput::View(y)) => y.shape(),
+            #[cfg(feature = "f16")]
+            StoredOutput::Half(y) => y.shape(),
+        }
+    }
+
+    // A copy of the array, upcast to `F` if it's stored as f16
+    fn to_owned(&self) -> NdArray<F> {
+        match self {
+            StoredOutput::Full(OpOutput::Owned(y)) => y.clone(),
+            StoredOutput::Full(OpOutput::View(y)) => {
+                unsafe { y.clone().deref_into_view() }.to_owned()
+            }
+            #[cfg(feature = "f16")]
+            StoredOutput::Half(y) => y.mapv(|x| F::from(x.to_f32()).unwrap()),
+        }
+    }
+
+    fn into_owned(self) -> NdArray<F> {
+        match self {
+            StoredOutput::Full(OpOutput::Owned(y)) => y,
+            y => y.to_owned(),
+        }
+    }
+}
+
 impl<F: Float> OpOutputStorage<F> {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         OpOutputStorage {
             inner: FxHashMap::default()
         }
     }
 
     #[inline]
-    fn insert(&mut self, key: TensorID, output: Result<op::SmallVec<OpOutput<F>>, op::OpError>) {
+    fn insert(
+        &mut self,
+        key: TensorID,
+        output: Result<op::SmallVec<StoredOutput<F>>, op::OpError>,
+    ) {
         self.inner.insert(key, output);
     }
 
+    // Views the output stored as `F`. Those stored as f16 are read with `upcast`.
     #[inline]
     fn get(&self, key: TensorID, selector: usize) -> Result<RawNdArrayView<F>, op::OpError> {
         match self.inner.get(&key).unwrap() {
-            Ok(ys) => {
-                match &ys[selector] {
-                    OpOutput::Owned(arr) => Ok(arr.raw_view()),
-                    OpOutput::View(arr) => Ok(arr.clone())
-                }
+            Ok(ys) => match ys.get(selector) {
+                Some(StoredOutput::Full(OpOutput::Owned(arr))) => Ok(arr.raw_view()),
+                Some(StoredOutput::Full(OpOutput::View(arr))) => Ok(arr.clone()),
+                #[cfg(feature = "f16")]
+                Some(StoredOutput::Half(_)) => unreachable!("f16 outputs are read by `upcast`"),
+                None => Err(op::OpError::OutOfBounds(format!(
+                    "nth_tensor: output {} requested from an op with {} outputs",
+                    selector,
+                    ys.len()
+                ))),
             },
             Err(e) => Err(e.clone())
         }
     }
 
+    // The owned array of a successful output, which views can't point into
+    #[inline]
+    fn get_mut(&mut self, key: TensorID, selector: usize) -> Option<RawArrayViewMut<F, IxDyn>> {
+        match self.inner.get_mut(&key) {
+            Some(Ok(ys)) => match ys.get_mut(selector) {
+                Some(StoredOutput::Full(OpOutput::Owned(arr))) => Some(arr.raw_view_mut()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // The output upcast to `F`, if it's stored as f16
+    #[cfg(feature = "f16")]
+    fn upcast(&self, key: TensorID, selector: usize) -> Option<NdArray<F>> {
+        match self.inner.get(&key) {
+            Some(Ok(ys)) => match ys.get(selector) {
+                Some(y @ StoredOutput::Half(_)) => Some(y.to_owned()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    fn upcast(&self, _: TensorID, _: usize) -> Option<NdArray<F>> {
+        None
+    }
+
+    fn get_owned(&self, key: TensorID, selector: usize) -> Result<NdArray<F>, op::OpError> {
+        match self.inner.get(&key).unwrap() {
+            Ok(ys) => Ok(ys[selector].to_owned()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
     fn take(&mut self, key: TensorID, selector: usize) -> Result<NdArray<F>, op::OpError> {
-        self.inner.remove(&key).unwrap().and_then(|mut ys| {
-            // Use the first NdArray
-            match ys.swap_remove(selector) {
-                OpOutput::Owned(y) => Ok(y),
-                OpOutput::View(y) => Ok(unsafe { y.deref_into_view() }.to_owned())
+        self.inner
+            .remove(&key)
+            .unwrap()
+            .map(|mut ys| ys.swap_remove(selector).into_owned())
+    }
+
+    // Gives the arrays of the outputs to `pool`, emptying the storage
+    fn recycle(&mut self, pool: &mut BufferPool<F>) {
+        for (_, ys) in self.inner.drain() {
+            for y in ys.into_iter().flatten() {
+                if let StoredOutput::Full(OpOutput::Owned(y)) = y {
+                    pool.recycle(y);
+                }
             }
-        })
+        }
     }
 }
 
@@ -226,6 +1345,7 @@ fn find_placeholder_value_by_key<'feeds, F: Float>(
     feeds: &'feeds [Feed<F>],
     node: &Tensor<F>,
     placeholder_name: &str,
+    symbols: &mut FxHashMap<&'static str, usize>,
 ) -> NdArrayView<'feeds, F> {
     for feed in feeds {
         match feed.placeholder_key {
@@ -233,6 +1353,7 @@ fn find_placeholder_value_by_key<'feeds, F: Float>(
                 if node.id == id {
                     let ret = feed.value.view();
                     node.validate_using_known_shape(ret.shape());
+                    node.bind_symbols(ret.shape(), symbols);
                     return ret;
                 }
             }
@@ -240,6 +1361,7 @@ fn find_placeholder_value_by_key<'feeds, F: Float>(
                 if placeholder_name == name {
                     let ret = feed.value.view();
                     node.validate_using_known_shape(ret.shape());
+                    node.bind_symbols(ret.shape(), symbols);
                     return ret;
                 }
             }
@@ -254,120 +1376,577 @@ impl<F: Float> Graph<F> {
         tensors: &'tensor [A],
         feeds: &[Feed<'feed, F>],
         env: &VariableEnvironment<F>,
+        keep: bool,
     ) -> Vec<Result<NdArray<F>, crate::EvalError>>
     where
         A: AsRef<Tensor<'graph, F>> + Copy,
     {
+        let order = self.schedule(tensors);
         let mut storage = OpOutputStorage::new();
+        self.eval_in_order(tensors, &order, &mut storage, feeds, env, keep)
+    }
+
+    // Lists the nodes to compute to evaluate `tensors`, in execution order.
+    pub(crate) fn schedule<'graph, A>(&'graph self, tensors: &[A]) -> Vec<TensorID>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        let mut order = Vec::new();
+        let mut scheduled = FxHashSet::default();
+        let cache = self.cache.borrow();
 
         // Graph traversal using depth-first-search
         // Vec<(tensor_id, should_visit)>
         let mut dfs_stack = Vec::<(TensorID, bool)>::with_capacity(1 << 10);
 
+        let max_depth = self.max_depth.get();
         for t in tensors {
             crate::graph::assert_same_graph(self, t.as_ref().graph);
-            dfs_stack.push((t.as_ref().id(), false));
+            if self.topo_rank(t.as_ref().id()) <= max_depth {
+                dfs_stack.push((t.as_ref().id(), false));
+            }
         }
 
         while let Some((node_id, should_visit)) = dfs_stack.pop() {
+            if cache.contains_key(&node_id) {
+                continue;
+            }
             let target_node = self.access_inner(node_id);
 
             if should_visit {
-                if would_not_visit(&target_node, &storage) {
+                if would_not_visit(&target_node, &scheduled, &cache) {
                     continue;
                 }
+                scheduled.insert(node_id);
+                order.push(node_id);
+            } else {
+                // Update dfs stack
+                dfs_stack.push((node_id, true));
+                // Push children if needed
+                for child in &target_node.incoming_nodes {
+                    let child = self.access_inner(child.id);
+                    if !would_not_visit(&child, &scheduled, &cache) {
+                        dfs_stack.push((child.id, false));
+                    }
+                }
+            }
+        }
+        self.hoist_shapes(order)
+    }
 
-                // ===========================================
-                // Aggregate input values for the `target_node`
-                // ===========================================
+    // Moves the `Shape` ops reading an input whose values aren't needed by the backward of
+    // its consumer (see `Op::needs_input_for_grad`) right after that consumer,
+    // so the input is freed once the forward pass is done with it.
+    fn hoist_shapes(&self, order: Vec<TensorID>) -> Vec<TensorID> {
+        let shape_op = std::any::type_name::<crate::tensor_ops::array_ops::Shape>();
+        // input -> the `Shape` ops reading it
+        let mut shapes_of = FxHashMap::<TensorID, Vec<TensorID>>::default();
+        for &id in &order {
+            let node = self.access_inner(id);
+            if node.get_op().name() == shape_op {
+                let input = node.incoming_nodes[0].id;
+                shapes_of.entry(input).or_default().push(id);
+            }
+        }
+        if shapes_of.is_empty() {
+            return order;
+        }
 
-                // input arrays for `Op::compute`
-                let mut op_inputs = op::SmallVec::new();
+        let mut done = FxHashSet::default();
+        let mut ret = Vec::with_capacity(order.len());
+        for id in order {
+            if !done.insert(id) {
+                continue;
+            }
+            ret.push(id);
+            let node = self.access_inner(id);
+            let needs = node.get_op().needs_input_for_grad();
+            for (i, incoming) in node.incoming_nodes.iter().enumerate() {
+                if needs.get(i) != Some(&false) {
+                    continue;
+                }
+                // `incoming` precedes `id`, so its shape can be computed now
+                for shape in shapes_of.remove(&incoming.id).unwrap_or_default() {
+                    if done.insert(shape) {
+                        ret.push(shape);
+                    }
+                }
+            }
+        }
+        ret
+    }
 
-                // Would be Err if fail to collect input arrays
-                let mut incoming_nodes_status = Ok(());
+    // Panics if a variable updated in place by the nodes of `order` shares memory with another
+    // variable they read or update, i.e. a slice and its parent (see
+    // `VariableEnvironment::slice`): the view it writes to would overlap the other one.
+    fn check_aliasing(
+        &self,
+        order: &[TensorID],
+        cache: &FxHashMap<TensorID, Vec<NdArray<F>>>,
+        env: &VariableEnvironment<F>,
+    ) {
+        let mut read = FxHashSet::default();
+        let mut written = Vec::new();
+        for node_id in order.iter().filter(|id| !cache.contains_key(id)) {
+            for incoming in &self.access_inner(*node_id).incoming_nodes {
+                if let Some(vid) = incoming.get_variable_id(self) {
+                    read.insert(vid);
+                    if incoming.allow_mut {
+                        written.push(vid);
+                    }
+                }
+            }
+        }
+        for vid in written {
+            if let Some(alias) = env.aliases(vid).into_iter().find(|a| read.contains(a)) {
+                panic!(
+                    "eval: variable {} is updated in place while its alias {} is used by the \
+                     same evaluation",
+                    vid, alias
+                );
+            }
+        }
+    }
 
-                // Initialize `op_inputs`
+    // Computes the nodes in `order` (given by `schedule`) and collects the values of `tensors`.
+    pub(crate) fn eval_in_order<'feed, 'graph, A>(
+        &'graph self,
+        tensors: &[A],
+        order: &[TensorID],
+        storage: &mut OpOutputStorage<F>,
+        feeds: &[Feed<'feed, F>],
+        env: &VariableEnvironment<F>,
+        keep: bool,
+    ) -> Vec<Result<NdArray<F>, crate::EvalError>>
+    where
+        A: AsRef<Tensor<'graph, F>> + Copy,
+    {
+        storage.inner.clear();
+        // sizes of the symbolic dims, bound by the placeholders fed
+        let mut symbols = FxHashMap::default();
+        let max_depth = self.max_depth.get();
+        let pinned = self.pinned.borrow();
+        let frozen = self.frozen.borrow();
+        let incremental = self.incremental.get();
+        let cache = self.cache.borrow();
+        let mut new_cache = Vec::new();
+        // nodes that must be recomputed every time: they depend on feeds or update variables
+        let mut volatile = FxHashSet::default();
+        // variables updated in place
+        let mut mutated = Vec::new();
+        if !env.slices.is_empty() {
+            self.check_aliasing(order, &cache, env);
+        }
+
+        // Remaining consumers of each output: it's freed after the last one has run,
+        // unless it's a target or `keep` is set.
+        let mut uses = FxHashMap::<TensorID, usize>::default();
+        if !keep {
+            for &node_id in order {
+                if !cache.contains_key(&node_id) {
+                    for incoming in &self.access_inner(node_id).incoming_nodes {
+                        *uses.entry(incoming.id).or_insert(0) += 1;
+                    }
+                }
+            }
+            for t in tensors {
+                uses.remove(&t.as_ref().id);
+            }
+        }
+        // (step at which it was freed, owned arrays) for the output computed at each step
+        let mut memory = Vec::<(usize, Vec<TensorMemory>)>::new();
+        let mut step_of = FxHashMap::<TensorID, usize>::default();
+        let (mut live_bytes, mut peak_bytes, mut peak_step) = (0, 0, 0);
+        let deadline = self.deadline.get();
+        let mut timed_out = None;
+        // the variables stored as f16 read by this evaluation, upcast to `F`
+        let mut upcasts = FxHashMap::<VariableID, NdArray<F>>::default();
+        let mut stale_upcasts = Vec::new();
+
+        for &node_id in order {
+            if cache.contains_key(&node_id) {
+                continue;
+            }
+            // the nodes left are never computed, and their consumers never see them
+            if let Some((at, limit)) = deadline {
+                if Instant::now() > at {
+                    timed_out = Some(limit);
+                    break;
+                }
+            }
+            let target_node = self.access_inner(node_id);
+
+            // ===========================================
+            // Aggregate input values for the `target_node`
+            // ===========================================
+
+            // input arrays for `Op::compute`
+            let mut op_inputs = op::SmallVec::new();
+            // the inputs stored as f16, upcast to `F` for `Op::compute`
+            let mut input_upcasts = Vec::new();
+            // the variables stored as f16 updated in place by `Op::compute`
+            let mut half_updates = Vec::new();
+
+            // Would be Err if fail to collect input arrays
+            let mut incoming_nodes_status = Ok(());
+            let allows_failed_inputs = target_node.get_op().allows_failed_inputs();
+            let mut input_errors = op::SmallVec::new();
+
+            // Initialize `op_inputs`
+            for incoming in &target_node.incoming_nodes {
+                let in_tensor = incoming.as_tensor(self);
+                let overwritable = incoming.allow_mut && uses.get(&incoming.id) == Some(&1);
+                let in_ndarray = {
+                    if let Some(ph_name) = in_tensor.placeholder_name() {
+                        // use placeholder
+                        Ok(OpInput::new_non_variable(find_placeholder_value_by_key(
+                            feeds,
+                            &in_tensor,
+                            ph_name,
+                            &mut symbols,
+                        )))
+                    } else if let Some(vid) = incoming.get_variable_id(self) {
+                        // use variable
+                        if env.is_half(vid) && incoming.allow_mut {
+                            // updated in its f32 master weights, rounded to f16 after the op
+                            env.init_master_weights(vid);
+                            half_updates.push(vid);
+                            Ok(OpInput::new_rdwr_variable(env.as_view_mut(vid)))
+                        } else if env.is_half(vid) {
+                            // upcast once per evaluation
+                            let upcast = upcasts
+                                .entry(vid)
+                                .or_insert_with(|| env.upcast_half(vid).unwrap());
+                            Ok(OpInput::new_rdonly_variable(unsafe {
+                                upcast.raw_view().deref_into_view()
+                            }))
+                        } else if incoming.allow_mut {
+                            Ok(OpInput::new_rdwr_variable(env.as_view_mut(vid)))
+                        } else {
+                            Ok(OpInput::new_rdonly_variable(env.as_view(vid)))
+                        }
+                    } else if let Some(ys) = cache.get(&incoming.id) {
+                        // use cached value
+                        Ok(OpInput::new_non_variable(
+                            ys[incoming.array_selector].view(),
+                        ))
+                    } else if let Some(got) = overwritable
+                        .then(|| storage.get_mut(incoming.id, incoming.array_selector))
+                        .flatten()
+                    {
+                        // nothing else reads it, so the consumer may overwrite it
+                        Ok(OpInput::new_rdwr_non_variable(unsafe { got.deref_into_view_mut() }))
+                    } else if let Some(upcast) =
+                        storage.upcast(incoming.id, incoming.array_selector)
+                    {
+                        // stored as f16 by `Context::autocast`
+                        let view = unsafe { upcast.raw_view().deref_into_view() };
+                        input_upcasts.push(upcast);
+                        Ok(OpInput::new_non_variable(view))
+                    } else {
+                        storage
+                            .get(incoming.id, incoming.array_selector)
+                            .map(|got| OpInput::new_non_variable(unsafe { got.deref_into_view() }))
+                    }
+                };
+                match in_ndarray {
+                    Ok(x) => {
+                        op_inputs.push(x);
+                        input_errors.push(None);
+                    }
+                    Err(e) if allows_failed_inputs => {
+                        let empty = ArrayView::from_shape(ndarray::IxDyn(&[0]), &[]).unwrap();
+                        op_inputs.push(OpInput::new_non_variable(empty));
+                        input_errors.push(Some(e));
+                    }
+                    Err(e) => {
+                        incoming_nodes_status = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            // =================
+            // Run Op::compute()
+            // =================
+            let pool_hits = || self.pool.borrow().as_ref().map_or(0, |pool| pool.borrow().stats().hits);
+            let hits_before = pool_hits();
+            let compute_result = incoming_nodes_status.and_then(|()| {
+                let mut op_ctx = op::ComputeContext::new(op_inputs);
+                op_ctx.deterministic = self.deterministic.get() || self.reference.get();
+                op_ctx.reference = self.reference.get();
+                op_ctx.training = self.training.get();
+                op_ctx.backend = self.backend.borrow().clone();
+                op_ctx.pool = self.pool.borrow().clone();
+                op_ctx.thread_pool = self.thread_pool.borrow().clone();
+                op_ctx.input_errors = input_errors;
+                let op = target_node.get_op();
+                let name = short_op_name(op.name());
+                *self.op_calls.borrow_mut().entry(name).or_insert(0) += 1;
+                let start = self.profiler.borrow().is_some().then(Instant::now);
+                let compute_status = op.compute(&mut op_ctx);
+                if let (Some(profiler), Some(start)) = (&*self.profiler.borrow(), start) {
+                    profiler.record(name, start.elapsed());
+                }
+                debug_assert!(
+                    compute_status.is_err() || !op_ctx.ys.is_empty(),
+                    "Bad op implementation: empty return value"
+                );
+                compute_status.map(|()| {
+                    let mut ys = op_ctx.ys;
+                    ys.extend(op_ctx.stashed);
+                    ys
+                })
+            });
+            #[cfg(feature = "f16")]
+            let half = self.half_outputs.borrow().contains(&node_id);
+            #[cfg(not(feature = "f16"))]
+            let half = false;
+            let compute_result = compute_result.map(|ys| {
+                ys.into_iter()
+                    .map(|y| match y {
+                        // views may point into `input_upcasts`, dropped below
+                        OpOutput::View(y) if !input_upcasts.is_empty() => {
+                            OpOutput::Owned(unsafe { y.deref_into_view() }.to_owned())
+                        }
+                        y => y,
+                    })
+                    .map(|y| StoredOutput::new(y, half))
+                    .collect::<op::SmallVec<_>>()
+            });
+            drop(input_upcasts);
+            for vid in half_updates {
+                env.round_master_weights(vid);
+                // the later reads upcast the updated array; outputs may still view the old one
+                stale_upcasts.extend(upcasts.remove(&vid));
+            }
+            if incremental {
                 for incoming in &target_node.incoming_nodes {
-                    let in_tensor = incoming.as_tensor(self);
-                    let in_ndarray = {
-                        if let Some(ph_name) = in_tensor.placeholder_name() {
-                            // use placeholder
-                            Ok(OpInput::new_non_variable(find_placeholder_value_by_key(feeds, &in_tensor, ph_name)))
-                        } else if let Some(vid) = incoming.get_variable_id(self) {
-                            // use variable
-                            if incoming.allow_mut {
-                                Ok(OpInput::new_rdwr_variable(env.as_view_mut(vid)))
+                    // op outputs overwritten by their only consumer are never read again
+                    let mutates = incoming.allow_mut && incoming.get_variable_id(self).is_some();
+                    if mutates {
+                        mutated.push(incoming.id);
+                    }
+                    if mutates
+                        || volatile.contains(&incoming.id)
+                        || self.access_inner(incoming.id).placeholder_name.is_some()
+                    {
+                        volatile.insert(node_id);
+                    }
+                }
+            }
+            let should_cache = pinned.contains(&node_id)
+                || frozen.contains(&node_id)
+                || (incremental && !volatile.contains(&node_id));
+            if let (true, Ok(ys)) = (should_cache, &compute_result) {
+                let ys: Vec<_> = ys.iter().map(StoredOutput::to_owned).collect();
+                new_cache.push((node_id, ys));
+            }
+            // no hook inside `Context::no_grad`
+            let hook = &mut *self.gradient_hook.borrow_mut();
+            if let (Some(hook), Ok(ys), false) = (hook, &compute_result, self.no_grad.get()) {
+                if let Some(&var) = self.gradient_of.borrow().get(&node_id) {
+                    let gx = ys[0].to_owned();
+                    hook(&self.tensor(var), &gx);
+                }
+            }
+            if let (Some(trace), Ok(ys)) = (&mut *self.shape_trace.borrow_mut(), &compute_result) {
+                trace.push((node_id, ys[0].shape().to_vec()));
+            }
+            let mut outputs = Vec::new();
+            // outputs written into arrays of the pool weren't allocated
+            let mut reused = pool_hits() - hits_before;
+            if let Ok(ys) = &compute_result {
+                for y in ys {
+                    let name = || target_node.get_op().name().to_string();
+                    match y {
+                        StoredOutput::Full(OpOutput::Owned(y)) => {
+                            if reused > 0 {
+                                reused -= 1;
                             } else {
-                                Ok(OpInput::new_rdonly_variable(env.as_view(vid)))
+                                self.allocations.set(self.allocations.get() + 1);
                             }
-                        } else {
-                            storage.get(incoming.id, incoming.array_selector).and_then(|got| {
-                                Ok(OpInput::new_non_variable(unsafe { got.deref_into_view()}))
-                            })
+                            outputs.push(TensorMemory::new(node_id, name(), &y.view()));
                         }
-                    };
-                    match in_ndarray {
-                        Ok(x) => op_inputs.push(x),
-                        Err(e) => {
-                            incoming_nodes_status = Err(e);
-                            break;
+                        #[cfg(feature = "f16")]
+                        StoredOutput::Half(y) => {
+                            self.allocations.set(self.allocations.get() + 1);
+                            outputs.push(TensorMemory::new(node_id, name(), &y.view()));
+                        }
+                        StoredOutput::Full(OpOutput::View(_)) => {
+                            // may point into the inputs, which have to outlive it
+                            for incoming in &target_node.incoming_nodes {
+                                uses.remove(&incoming.id);
+                            }
                         }
                     }
                 }
+            }
+            storage.insert(node_id, compute_result);
 
-                // =================
-                // Run Op::compute()
-                // =================
-                let compute_result = incoming_nodes_status.and_then(|()| {
-                    let mut op_ctx = op::ComputeContext::new(op_inputs);
-                    let compute_status = target_node.get_op().compute(&mut op_ctx);
-                    debug_assert!(!op_ctx.ys.is_empty(), "Bad op implementation: empty return value");
-                    let output = compute_status.map(|()| op_ctx.ys);
-                    output
-                });
-                storage.insert(node_id, compute_result);
-            } else {
-                // Update dfs stack
-                dfs_stack.push((node_id, true));
-                // Push children if needed
-                for child in &target_node.incoming_nodes {
-                    let child = self.access_inner(child.id);
-                    if !would_not_visit(&child, &storage) {
-                        dfs_stack.push((child.id, false));
+            let step = memory.len();
+            live_bytes += outputs.iter().map(|m| m.bytes).sum::<usize>();
+            if live_bytes >= peak_bytes {
+                peak_bytes = live_bytes;
+                peak_step = step;
+            }
+            memory.push((usize::MAX, outputs));
+            step_of.insert(node_id, step);
+
+            // Free the inputs used for the last time. Errors are kept for `error_trace`.
+            for incoming in &target_node.incoming_nodes {
+                if let Some(n) = uses.get_mut(&incoming.id) {
+                    *n -= 1;
+                    if *n == 0 && matches!(storage.inner.get(&incoming.id), Some(Ok(_))) {
+                        let freed = storage.inner.remove(&incoming.id);
+                        if let (Some(pool), Some(Ok(ys))) = (&*self.pool.borrow(), freed) {
+                            let mut pool = pool.borrow_mut();
+                            for y in ys {
+                                if let StoredOutput::Full(OpOutput::Owned(y)) = y {
+                                    pool.recycle(y);
+                                }
+                            }
+                        }
+                        let freed = &mut memory[step_of[&incoming.id]];
+                        freed.0 = step;
+                        live_bytes -= freed.1.iter().map(|m| m.bytes).sum::<usize>();
                     }
                 }
             }
         }
+        drop(cache);
+        if !new_cache.is_empty() {
+            self.cache.borrow_mut().extend(new_cache);
+            self.cache_version.set(self.cache_version.get() + 1);
+        }
+        for var in mutated {
+            self.invalidate_consumers(var);
+            // the variables sharing memory with a mutated slice (or parent) changed too
+            if let Some(vid) = self.access_inner(var).variable_id {
+                for alias in env.aliases(vid) {
+                    let id = self.variable2node.borrow().get(&alias).cloned();
+                    if let Some(id) = id {
+                        self.invalidate_consumers(id);
+                    }
+                }
+            }
+        }
+
+        // The outputs alive after the computation at `peak_step`
+        let mut outputs = Vec::new();
+        for (step, (freed, mems)) in memory.into_iter().enumerate() {
+            if step <= peak_step && peak_step <= freed {
+                outputs.extend(mems);
+            }
+        }
+        outputs.sort_by_key(|m| m.id);
+        if let Some(hook) = &*self.memory_hook.borrow() {
+            hook(&MemoryReport {
+                variables: variable_memory(env),
+                outputs: outputs.clone(),
+                kept: kept_memory(self),
+            });
+        }
+        *self.peak_outputs.borrow_mut() = outputs;
 
         // Aggregate return values
         let mut ret = Vec::with_capacity(tensors.len());
+        // a tensor requested more than once is moved out of the storage on its last request
+        let mut requests_left = FxHashMap::<TensorID, usize>::default();
+        for t in tensors {
+            *requests_left.entry(t.as_ref().id).or_insert(0) += 1;
+        }
         for t in tensors {
             let t = t.as_ref();
+            let left = requests_left.get_mut(&t.id).unwrap();
+            *left -= 1;
             let arr = if let Some(vid) = t.get_variable_id() {
                 // case 1: variable tensor
-                Ok(env.array_list[vid.0].clone().into_inner())
+                Ok(env.upcast_half(vid).unwrap_or_else(|| env.as_view(vid).to_owned()))
             } else if let Some(name) = t.placeholder_name() {
                 // case 2: placeholder tensor
-                Ok(find_placeholder_value_by_key(feeds, t, name).to_owned())
-            } else {
-                // case 3: normal tensor
-                storage.take(t.id, 0).map_err(|e| {
-                    crate::EvalError::OpError(e)
+                Ok(find_placeholder_value_by_key(feeds, t, name, &mut symbols).to_owned())
+            } else if self.topo_rank(t.id) > max_depth {
+                Err(crate::EvalError::TooDeep {
+                    depth: self.topo_rank(t.id),
+                    max_depth,
                 })
+            } else if let (Some(limit), false) = (timed_out, self.was_computed(t.id, storage)) {
+                // case 3: the evaluation stopped before computing it
+                Err(crate::EvalError::Timeout { limit })
+            } else if !storage.inner.contains_key(&t.id) {
+                // case 4: pinned or incremental tensor computed by a previous evaluation
+                Ok(self.cache.borrow()[&t.id][0].clone())
+            } else if let Some(Err(error)) = storage.inner.get(&t.id) {
+                // case 5: the tensor or one of its inputs failed
+                Err(crate::EvalError::OpError {
+                    error: error.clone(),
+                    trace: self.error_trace(t.id, storage),
+                })
+            } else if keep || *left > 0 {
+                // case 6: normal tensor, left in the storage
+                Ok(storage.get_owned(t.id, 0).unwrap())
+            } else {
+                // case 7: normal tensor
+                Ok(storage.take(t.id, 0).unwrap())
+            };
+            let arr = if self.column_major.get() {
+                arr.map(ndarray_ext::into_column_major)
+            } else {
+                arr
             };
             ret.push(arr);
         }
+        if keep {
+            let mut kept = self.kept_outputs.borrow_mut();
+            for (id, ys) in storage.inner.drain() {
+                if let Ok(mut ys) = ys {
+                    kept.insert(id, ys.swap_remove(0).into_owned());
+                }
+            }
+        }
         ret
     }
+
+    fn was_computed(&self, id: TensorID, storage: &OpOutputStorage<F>) -> bool {
+        storage.inner.contains_key(&id) || self.cache.borrow().contains_key(&id)
+    }
+
+    // Names of the ops from the node that raised the error up to `id`, which failed.
+    fn error_trace(&self, id: TensorID, storage: &OpOutputStorage<F>) -> Vec<&'static str> {
+        let mut trace = Vec::new();
+        let mut cur = Some(id);
+        while let Some(id) = cur {
+            let node = self.access_inner(id);
+            trace.push(short_op_name(node.get_op().name()));
+            // an error is propagated from the first failed input
+            cur = node
+                .incoming_nodes
+                .iter()
+                .map(|incoming| incoming.id)
+                .find(|id| matches!(storage.inner.get(id), Some(Err(_))));
+        }
+        trace.reverse();
+        trace
+    }
+}
+
+// "autograd::tensor_ops::array_ops::Slice" -> "Slice"
+pub(crate) fn short_op_name(name: &'static str) -> &'static str {
+    let path = name.split('<').next().unwrap_or(name);
+    path.rsplit("::").next().unwrap_or(path)
 }
 
 #[inline]
 fn would_not_visit<F: Float>(
     node: &Ref<TensorInternal<F>>,
-    storage: &OpOutputStorage<F>,
+    scheduled: &FxHashSet<TensorID>,
+    cache: &FxHashMap<TensorID, Vec<NdArray<F>>>,
 ) -> bool {
-    node.placeholder_name.is_some() || node.is_variable() || storage.inner.contains_key(&node.id())
+    node.placeholder_name.is_some()
+        || node.is_variable()
+        || scheduled.contains(&node.id())
+        || cache.contains_key(&node.id())
 }
 
 #[test]
@@ -452,3 +2031,44 @@ fn test_eval3() {
             .run();
     })
 }
+
+#[test]
+fn test_memory_report() {
+    use crate::tensor_ops as T;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let mut env = VariableEnvironment::<f32>::new();
+    env.name("a").set(ndarray::Array::zeros((2, 3)));
+    env.namespace_mut("ns")
+        .slot()
+        .name("b")
+        .set(ndarray::Array::zeros(4));
+
+    env.run(|g| {
+        let report = g.memory_report();
+        assert_eq!(report.variables.len(), 2);
+        assert_eq!(report.variables[0].name, "a");
+        assert_eq!(report.variables[0].bytes, 2 * 3 * 4);
+        assert_eq!(report.variables[1].name, "ns/b");
+        assert_eq!(report.variables[1].shape, vec![4]);
+        assert_eq!(report.variable_bytes(), 10 * 4);
+        assert!(report.outputs.is_empty());
+
+        let peak = Rc::new(Cell::new(0));
+        let peak_ = peak.clone();
+        g.set_memory_hook(move |report| peak_.set(report.total_bytes()));
+
+        let x = T::zeros(&[4, 5], g);
+        let y = T::sigmoid(x);
+        y.eval(g).unwrap();
+
+        // `x` and `y`: the shape of `x` is freed once `x` is computed
+        let report = g.memory_report();
+        let bytes: Vec<usize> = report.outputs.iter().map(|m| m.bytes).collect();
+        assert_eq!(bytes, vec![20 * 4, 20 * 4]);
+        assert_eq!(report.outputs.last().unwrap().id, y.id);
+        assert_eq!(report.total_bytes(), (10 + 40) * 4);
+        assert_eq!(peak.get(), report.total_bytes());
+    });
+}
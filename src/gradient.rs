@@ -33,12 +33,12 @@ where
     if let Some(gys) = gys {
         assert_eq!(ys.len(), gys.len(), "`ys.len()` must match `gys.len()`");
         for (y, &gy) in ys.into_iter().zip(gys) {
-            grad_map.push_grad(y.as_ref().id, gy);
+            grad_map.push_grad(y.as_ref().id, 0, gy);
         }
     } else {
         let start_gy = T::scalar(F::one(), g);
         for y in ys.into_iter() {
-            grad_map.push_grad(y.as_ref().id, start_gy);
+            grad_map.push_grad(y.as_ref().id, 0, start_gy);
         }
     }
 
@@ -51,12 +51,26 @@ where
     // Start backprop from `ys`.
     while let Some(y) = heap.pop() {
         let gxs = {
+            let needs_input_grad = g
+                .tensor(y.id)
+                .inner()
+                .get_backprop_inputs()
+                .iter()
+                .map(|x| grad_map.get_mut(x.id).on_backprop_path)
+                .collect();
             let y_grad_info = grad_map.get_mut(y.id);
-            let gy = y_grad_info.gradient();
+            let gys = y_grad_info.output_gradients();
 
             // Call Op::grad
             let y_tensor = g.tensor(y.id);
-            let ctx = GradientContext::new(gy, y_tensor, g);
+            if y_tensor.inner().incoming_nodes.iter().any(|x| x.allow_mut) {
+                panic!(
+                    "grad: {} updates its input in place and has no output to differentiate; \
+                     order it with `Tensor::depends_on` instead",
+                    crate::evaluation::short_op_name(y_tensor.inner().get_op().name())
+                );
+            }
+            let ctx = GradientContext::new(gys, y_tensor, g, needs_input_grad);
             let gxs = ctx.compute_input_grads();
             debug_assert_eq!(y_tensor.num_backprop_inputs(), gxs.len());
             gxs
@@ -65,14 +79,17 @@ where
         // Register computed gradients
         let y = g.tensor(y.id);
         for (x, gx) in y.inner().get_backprop_inputs().iter().zip(gxs) {
+            let output = x.array_selector;
             let x = x.as_tensor(g);
             let x_grad_info = grad_map.get_mut(x.id);
             if x_grad_info.on_backprop_path {
                 if let Some(gx) = gx {
-                    let x_not_visited =  x_grad_info.gradients.len() == 0;
-                    grad_map.push_grad(x.id, gx);
-                    // update heap
-                    if !x.is_source() && x_not_visited {
+                    let x_not_visited = x_grad_info.is_empty();
+                    // `x` may be any output of a multi-output op
+                    grad_map.push_grad(x.id, output, gx);
+                    // update heap, unless nothing below `x` wants a gradient (e.g. `x` is an
+                    // intermediate tensor in `xs`): the backprop stops there.
+                    if !x.is_source() && x_not_visited && has_child_on_path(x, &grad_map.inner) {
                         heap.push(x.to_node());
                     }
                 }
@@ -129,9 +146,17 @@ pub(crate) struct GradientMap<'graph, F: Float> {
 
 impl<'graph, F: Float> GradientMap<'graph, F> {
     pub(crate) fn extract_grad(&mut self, x: impl AsRef<Tensor<'graph, F>>) -> Option<Tensor<'graph, F>> {
-        if let Some(info) = self.inner.get_mut(&x.as_ref().id) {
+        let x = x.as_ref();
+        if let Some(info) = self.inner.get_mut(&x.id) {
             if info.on_backprop_path {
-                return Some(info.gradient());
+                // `None` if every op on the path gave `x` no gradient
+                if let Some(gx) = info.gradient_at(0) {
+                    if x.is_variable() && !x.graph.no_grad.get() {
+                        // for `Context::on_gradient`
+                        x.graph.gradient_of.borrow_mut().insert(gx.id, x.id);
+                    }
+                    return Some(gx);
+                }
             }
         }
         // can't differentiate!
@@ -144,14 +169,19 @@ impl<'graph, F: Float> GradientMap<'graph, F> {
     }
 
     #[inline]
-    fn push_grad(&mut self, key: TensorID, grad: Tensor<'graph, F>) {
-        self.inner.get_mut(&key).unwrap().gradients.push(grad);
+    fn push_grad(&mut self, key: TensorID, output: usize, grad: Tensor<'graph, F>) {
+        let info = self.inner.get_mut(&key).unwrap();
+        if info.gradients.len() <= output {
+            info.gradients.resize_with(output + 1, SmallVec::new);
+        }
+        info.gradients[output].push(grad);
     }
 }
 
 // GradientInfo is keyed by a TensorID and holds its gradient info for back-prop
 struct GradientInfo<'graph, F: Float> {
-    gradients: SmallVec<Tensor<'graph, F>>,
+    // the gradients accumulated for each output of the op
+    gradients: SmallVec<SmallVec<Tensor<'graph, F>>>,
     on_backprop_path: bool,
 }
 
@@ -165,11 +195,26 @@ impl<'graph, F: Float> GradientInfo<'graph, F> {
     }
 
     #[inline]
-    fn gradient(&mut self) -> Tensor<'graph, F> {
-        if self.gradients.len() > 1 { // the accumulated gradients are added together at this time.
-            self.gradients[0] = T::add_n(self.gradients.as_slice());
+    fn is_empty(&self) -> bool {
+        self.gradients.iter().all(|grads| grads.is_empty())
+    }
+
+    // The gradient of the `output`th output.
+    #[inline]
+    fn gradient_at(&mut self, output: usize) -> Option<Tensor<'graph, F>> {
+        let grads = self.gradients.get_mut(output)?;
+        if grads.len() > 1 {
+            // the accumulated gradients are added together at this time.
+            let sum = T::add_n(grads.as_slice());
+            grads.clear();
+            grads.push(sum);
         }
-        self.gradients[0]
+        grads.first().copied()
+    }
+
+    #[inline]
+    fn output_gradients(&mut self) -> SmallVec<Option<Tensor<'graph, F>>> {
+        (0..self.gradients.len()).map(|i| self.gradient_at(i)).collect()
     }
 }
 
@@ -1,15 +1,27 @@
-use crate::tensor::{Tensor, TensorInternal};
+use crate::op::{self, OpError};
+use crate::op::SmallVec;
+use crate::tensor::{Dim, IncomingTensor, Tensor, TensorInternal};
 
+use crate::backend::{Backend, CpuBackend};
+use crate::evaluation::{BufferPool, MemoryReport, PlaceholderKey, Profiler, TensorMemory};
 use crate::{Evaluator, Feeder, tensor_ops as T, tensor_ops};
-use crate::variable::{VariableID, VariableNamespace};
-use crate::{Float, FxHashMap, NdArray, VariableEnvironment};
+use crate::variable::{GetVariableTensor, VariableID, VariableNamespace};
+use crate::variable::{NamespaceTrait, VariableTie, RUNNING_STATS_NAMESPACE_ID};
+use crate::{Float, FxHashMap, FxHashSet, NdArray, VariableEnvironment};
 
-use std::cell::{Ref, RefMut, RefCell};
+use std::any::Any;
+use std::cell::{Cell, Ref, RefMut, RefCell};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::ops::Deref;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 pub type TensorID = usize;
 
+type MemoryHook = Box<dyn Fn(&MemoryReport)>;
+type GradientHook<F> = Box<dyn FnMut(&Tensor<'_, F>, &NdArray<F>)>;
+
 /// Graph represents a computation graph holding tensors inside.
 ///
 /// NOTE:
@@ -17,12 +29,108 @@ pub type TensorID = usize;
 pub struct Graph<F: Float> {
     pub(crate) node_set: RefCell<Vec<TensorInternal<F>>>,
     pub(crate) variable2node: RefCell<FxHashMap<VariableID, TensorID>>,
+    // The ties of the environment, see `nn::tie_weights`
+    pub(crate) ties: FxHashMap<VariableID, VariableTie>,
+    // Op outputs held by the storage of the last `eval`, at its peak
+    pub(crate) peak_outputs: RefCell<Vec<TensorMemory>>,
+    pub(crate) memory_hook: RefCell<Option<MemoryHook>>,
+    pub(crate) gradient_hook: RefCell<Option<GradientHook<F>>>,
+    pub(crate) profiler: RefCell<Option<Profiler>>,
+    // See `Context::set_buffer_pool`
+    pub(crate) pool: RefCell<Option<Rc<RefCell<BufferPool<F>>>>>,
+    // Set during `Context::eval_with_threads`
+    pub(crate) thread_pool: RefCell<Option<Rc<rayon::ThreadPool>>>,
+    // Gradient tensors given by `grad` and friends, and the variables they're the gradients of
+    pub(crate) gradient_of: RefCell<FxHashMap<TensorID, TensorID>>,
+    pub(crate) max_depth: Cell<usize>,
+    pub(crate) deterministic: Cell<bool>,
+    // See `Context::set_column_major`
+    pub(crate) column_major: Cell<bool>,
+    pub(crate) reference: Cell<bool>,
+    // See `Context::training`
+    pub(crate) training: Cell<bool>,
+    // Set by `Context::set_backend`
+    pub(crate) backend: RefCell<Option<Rc<dyn Backend<F>>>>,
+    // Set inside `Context::no_grad`
+    pub(crate) no_grad: Cell<bool>,
+    // Set inside `Context::autocast`, to the ops kept in full precision
+    #[cfg(feature = "f16")]
+    pub(crate) autocast: RefCell<Option<Vec<&'static str>>>,
+    // Nodes built inside `Context::autocast` whose outputs are stored as f16
+    #[cfg(feature = "f16")]
+    pub(crate) half_outputs: RefCell<FxHashSet<TensorID>>,
+    // Outputs cached by `eval_keep`
+    pub(crate) kept_outputs: RefCell<FxHashMap<TensorID, NdArray<F>>>,
+    // Tensors marked with `Tensor::pin`
+    pub(crate) pinned: RefCell<FxHashSet<TensorID>>,
+    // Tensors made by `freeze_after_first`: cached like pinned ones, but never invalidated
+    pub(crate) frozen: RefCell<FxHashSet<TensorID>>,
+    pub(crate) incremental: Cell<bool>,
+    // Outputs reused across evaluations: pinned tensors, and everything in incremental mode
+    pub(crate) cache: RefCell<FxHashMap<TensorID, Vec<NdArray<F>>>>,
+    // Incremented each time `cache` changes
+    pub(crate) cache_version: Cell<usize>,
+    // Number of `Op::compute` calls by short op name, since the last `Context::reset_counters`
+    pub(crate) op_calls: RefCell<FxHashMap<&'static str, usize>>,
+    // Number of arrays allocated for op outputs, since the last `Context::reset_counters`
+    pub(crate) allocations: Cell<usize>,
+    // Set during `Context::eval_with_timeout`: the instant to give up at, and the budget
+    pub(crate) deadline: Cell<Option<(Instant, Duration)>>,
+    // Set during `Context::eval_with_trace`: the shape of the first output of each op run
+    pub(crate) shape_trace: RefCell<Option<Vec<(TensorID, Vec<usize>)>>>,
+    // See `Context::eval_compiled`: the compiled outputs by outputs and feed shapes
+    pub(crate) compile_cache: RefCell<FxHashMap<CompileKey, Vec<TensorID>>>,
+    pub(crate) compilations: Cell<usize>,
+    // See `tensor_ops::jacobian`: the seed placeholder and the VJP by (y, x)
+    pub(crate) jacobian_vjps: RefCell<FxHashMap<(TensorID, TensorID), (TensorID, TensorID)>>,
 }
 
+// The outputs given to `Context::eval_compiled`, and the shape fed to each placeholder
+pub(crate) type CompileKey = (Vec<TensorID>, Vec<(PlaceholderKey, Vec<usize>)>);
+
 pub const NUM_NODES_WARN: usize = 50_000;
 pub const NUM_NODES_CRITICAL: usize = 500_000;
 
 impl<'graph, F: Float> Graph<F> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Graph {
+            node_set: RefCell::new(Vec::with_capacity(capacity)),
+            variable2node: RefCell::new(FxHashMap::default()),
+            ties: FxHashMap::default(),
+            peak_outputs: RefCell::new(Vec::new()),
+            memory_hook: RefCell::new(None),
+            gradient_hook: RefCell::new(None),
+            profiler: RefCell::new(None),
+            pool: RefCell::new(None),
+            thread_pool: RefCell::new(None),
+            gradient_of: RefCell::new(FxHashMap::default()),
+            max_depth: Cell::new(usize::MAX),
+            deterministic: Cell::new(false),
+            column_major: Cell::new(false),
+            reference: Cell::new(false),
+            training: Cell::new(true),
+            backend: RefCell::new(None),
+            no_grad: Cell::new(false),
+            #[cfg(feature = "f16")]
+            autocast: RefCell::new(None),
+            #[cfg(feature = "f16")]
+            half_outputs: RefCell::new(FxHashSet::default()),
+            kept_outputs: RefCell::new(FxHashMap::default()),
+            pinned: RefCell::new(FxHashSet::default()),
+            frozen: RefCell::new(FxHashSet::default()),
+            incremental: Cell::new(false),
+            cache: RefCell::new(FxHashMap::default()),
+            cache_version: Cell::new(0),
+            op_calls: RefCell::new(FxHashMap::default()),
+            allocations: Cell::new(0),
+            deadline: Cell::new(None),
+            shape_trace: RefCell::new(None),
+            compile_cache: RefCell::new(FxHashMap::default()),
+            compilations: Cell::new(0),
+            jacobian_vjps: RefCell::new(FxHashMap::default()),
+        }
+    }
+
     #[inline]
     pub(crate) fn install(&'graph self, mut node: TensorInternal<F>) -> TensorID {
         let mut inner = self.node_set.borrow_mut();
@@ -63,10 +171,349 @@ impl<'graph, F: Float> Graph<F> {
         Tensor { id, graph: self }
     }
 
+    /// Returns the placeholders and variables that `outputs` depend on, ordered by id.
+    ///
+    /// These are all the values the evaluation of `outputs` reads, so it tells which
+    /// placeholders must be fed before running it.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = g.placeholder("x", &[2]);
+    ///     let y = g.placeholder("y", &[2]);
+    ///     let z = T::exp(x) + T::ones(&[2], g);
+    ///     let inputs = g.inputs_of(&[z]);
+    ///     assert_eq!(inputs.len(), 1);
+    ///     assert_eq!(inputs[0].placeholder_name(), Some("x"));
+    ///     assert_eq!(g.inputs_of(&[z, y * x]).len(), 2);
+    /// });
+    /// ```
+    pub fn inputs_of<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        outputs: &[A],
+    ) -> Vec<Tensor<'graph, F>> {
+        let mut ret = Vec::new();
+        self.visit(outputs, &mut |t| {
+            if t.is_placeholder() || t.get_variable_id().is_some() {
+                ret.push(*t);
+            }
+        });
+        ret.sort_unstable_by_key(|t| t.id);
+        ret
+    }
+
+    /// Walks the nodes `outputs` depend on, calling `visitor` once for each of them.
+    ///
+    /// The walk is a post-order: every node is visited after all its inputs, which are
+    /// taken in the order of `outputs` and in the order of each op's inputs, so the same graph
+    /// is always walked the same way. `outputs` themselves are visited too.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = g.placeholder("x", &[2]);
+    ///     let y = T::exp(x);
+    ///     let z = y + x;
+    ///
+    ///     let mut ids = Vec::new();
+    ///     g.visit(&[z], &mut |t| ids.push(t.id()));
+    ///     assert_eq!(ids, vec![x.id(), y.id(), z.id()]);
+    /// });
+    /// ```
+    pub fn visit<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        outputs: &[A],
+        visitor: &mut dyn FnMut(&Tensor<'graph, F>),
+    ) {
+        let mut visited = FxHashSet::default();
+        // Vec<(tensor_id, inputs_done)>
+        let mut stack: Vec<(TensorID, bool)> = outputs
+            .iter()
+            .rev()
+            .map(|y| (y.as_ref().id, false))
+            .collect();
+        while let Some((id, inputs_done)) = stack.pop() {
+            if inputs_done {
+                visitor(&self.tensor(id));
+            } else if visited.insert(id) {
+                stack.push((id, true));
+                let node = self.access_inner(id);
+                stack.extend(node.incoming_nodes.iter().rev().map(|x| (x.id, false)));
+            }
+        }
+    }
+
+    /// Returns a hash of the structure of the graph of `outputs`.
+    ///
+    /// The hash covers the op type of each node, the parameters its op feeds to
+    /// [Op::hash_params](crate::op::Op::hash_params), and how the nodes are connected:
+    /// variables count by their ids and placeholders by their names and shapes, but not by
+    /// their values, while the values of constants are part of the hash. So the same code
+    /// building the same graph gives the same fingerprint, in another `run` or process, and a
+    /// key for caching the artifacts compiled from a graph doesn't depend on the tensor ids.
+    /// The hash is stable for a given build of this crate.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = g.placeholder("x", &[-1, 3]);
+    ///     let a = T::softmax(x, 1);
+    ///     let _unrelated = T::exp(x);
+    ///     let b = T::softmax(x, 1);
+    ///     assert_eq!(g.fingerprint(&[a]), g.fingerprint(&[b]));
+    ///     assert_ne!(g.fingerprint(&[a]), g.fingerprint(&[T::softmax(x, 0)]));
+    /// });
+    /// ```
+    pub fn fingerprint<A: AsRef<Tensor<'graph, F>>>(&'graph self, outputs: &[A]) -> u64 {
+        let mut hashes: FxHashMap<TensorID, u64> = FxHashMap::default();
+        self.visit(outputs, &mut |t| {
+            let node = self.access_inner(t.id);
+            let mut state = rustc_hash::FxHasher::default();
+            let op = node.get_op();
+            op.name().hash(&mut state);
+            op.hash_params(&mut state);
+            node.placeholder_name.hash(&mut state);
+            node.known_shape.as_ref().map(|s| s.get()).hash(&mut state);
+            node.variable_id.hash(&mut state);
+            node.is_differentiable.hash(&mut state);
+            for x in &node.incoming_nodes {
+                (hashes[&x.id], x.array_selector, x.allow_mut).hash(&mut state);
+            }
+            hashes.insert(t.id, state.finish());
+        });
+        let mut state = rustc_hash::FxHasher::default();
+        for y in outputs {
+            hashes[&y.as_ref().id].hash(&mut state);
+        }
+        state.finish()
+    }
+
+    /// Rewrites the graph of `outputs` by `rule`, and returns the rewritten outputs.
+    ///
+    /// The nodes `outputs` depend on are walked in the order of [Graph::visit]. For each of
+    /// them, `rule` is called with the node, whose inputs have already been rewritten, and
+    /// the node is replaced with the tensor `rule` returns, if any. A node whose inputs were
+    /// replaced is rebuilt on the new inputs with the same op, so the original graph is left
+    /// as it is; nodes not depending on any replaced node are reused.
+    ///
+    /// This is the building block for optimization passes, e.g. dropping `x * 1`:
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = T::ones(&[2], g);
+    ///     let y = T::exp(x * 1.);
+    ///
+    ///     let rewritten = g.rewrite(&[y], |t| {
+    ///         let one = t.num_inputs() == 2 && t.get_input(1).op_name() == "Scalar";
+    ///         (t.op_name() == "MulOp" && one).then(|| t.get_input(0))
+    ///     });
+    ///     assert_eq!(rewritten[0].get_input(0).id(), x.id());
+    ///     assert_eq!(rewritten[0].eval(g), y.eval(g));
+    /// });
+    /// ```
+    pub fn rewrite<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        outputs: &[A],
+        mut rule: impl FnMut(&Tensor<'graph, F>) -> Option<Tensor<'graph, F>>,
+    ) -> Vec<Tensor<'graph, F>> {
+        self.rewrite_nodes(outputs, |_, t| rule(t))
+    }
+
+    // `rewrite` whose `rule` is also given the id of the node before its inputs were rewritten
+    fn rewrite_nodes<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        outputs: &[A],
+        mut rule: impl FnMut(TensorID, &Tensor<'graph, F>) -> Option<Tensor<'graph, F>>,
+    ) -> Vec<Tensor<'graph, F>> {
+        let mut order = Vec::new();
+        self.visit(outputs, &mut |t| order.push(t.id));
+        // Original id -> id of its rewritten node
+        let mut replaced: FxHashMap<TensorID, TensorID> = FxHashMap::default();
+        for id in order {
+            let rebuilt = self.rebuild_with_inputs(id, &replaced);
+            let new = rule(id, &self.tensor(rebuilt)).map_or(rebuilt, |t| t.id);
+            if new != id {
+                replaced.insert(id, new);
+            }
+        }
+        outputs
+            .iter()
+            .map(|y| {
+                let id = y.as_ref().id;
+                self.tensor(*replaced.get(&id).unwrap_or(&id))
+            })
+            .collect()
+    }
+
+    /// Optimizes the graph of `outputs` for evaluation, and returns the tensors to evaluate
+    /// instead of `outputs`. They compute the same values; the graph of `outputs` is left as
+    /// it is, see [Graph::rewrite].
+    ///
+    /// An addition whose first operand is an op output read by nothing else is rewritten to
+    /// add into the array of that operand, instead of allocating one for the sum. Whether the
+    /// operand can be overwritten is checked again by each evaluation: if it's evaluated too,
+    /// read by another tensor evaluated alongside, kept or cached, the addition allocates
+    /// as usual. The tensors returned are to be evaluated, not differentiated: compile the
+    /// gradients instead.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let x = T::ones(&[2, 3], g);
+    ///     let y = T::exp(x) + x;
+    ///
+    ///     let compiled = g.compile(&[y]);
+    ///     assert_eq!(compiled[0].op_name(), "AddInplaceOp");
+    ///     assert_eq!(compiled[0].eval(g), y.eval(g));
+    /// });
+    /// ```
+    pub fn compile<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        outputs: &[A],
+    ) -> Vec<Tensor<'graph, F>> {
+        // Number of the nodes reading each node; the outputs are read by the caller
+        let mut consumers = FxHashMap::<TensorID, usize>::default();
+        self.visit(outputs, &mut |t| {
+            for x in &self.access_inner(t.id).incoming_nodes {
+                *consumers.entry(x.id).or_insert(0) += 1;
+            }
+        });
+        for y in outputs {
+            consumers.insert(y.as_ref().id, usize::MAX);
+        }
+        let add = std::any::type_name::<tensor_ops::binary_ops::AddOp>();
+        self.rewrite_nodes(outputs, |id, t| {
+            let node = self.access_inner(id);
+            if node.get_op().name() != add {
+                return None;
+            }
+            let x0 = self.access_inner(node.incoming_nodes[0].id);
+            let overwritable =
+                consumers[&x0.id] == 1 && x0.placeholder_name.is_none() && x0.variable_id.is_none();
+            drop((node, x0));
+            overwritable.then(|| self.rebuild_with_op(t.id, tensor_ops::binary_ops::AddInplaceOp))
+        })
+    }
+
+    // Installs a copy of the node `id` computed by `op`, which may overwrite its first input.
+    fn rebuild_with_op<O: op::Op<F> + 'static>(
+        &'graph self,
+        id: TensorID,
+        op: O,
+    ) -> Tensor<'graph, F> {
+        let node = self.access_inner(id);
+        let mut incoming_nodes = node.incoming_nodes.clone();
+        incoming_nodes[0].allow_mut = true;
+        let new = TensorInternal {
+            id: usize::default(),
+            op: Some(Rc::new(op)),
+            incoming_nodes,
+            topo_rank: node.topo_rank,
+            shape: node.shape,
+            placeholder_name: node.placeholder_name,
+            is_differentiable: node.is_differentiable,
+            backprop_inputs: Some(
+                node.backprop_inputs
+                    .clone()
+                    .unwrap_or_else(|| node.incoming_nodes.clone()),
+            ),
+            known_shape: node.known_shape.clone(),
+            variable_id: node.variable_id,
+        };
+        drop(node);
+        self.tensor(self.install(new))
+    }
+
+    // Installs a copy of the node `id` reading the inputs in `replaced` instead,
+    // or returns `id` if it reads none of them.
+    fn rebuild_with_inputs(
+        &'graph self,
+        id: TensorID,
+        replaced: &FxHashMap<TensorID, TensorID>,
+    ) -> TensorID {
+        let node = self.access_inner(id);
+        let remap = |x: &IncomingTensor| IncomingTensor {
+            id: *replaced.get(&x.id).unwrap_or(&x.id),
+            allow_mut: x.allow_mut,
+            array_selector: x.array_selector,
+        };
+        let touches = |x: &IncomingTensor| replaced.contains_key(&x.id);
+        let changed = node.incoming_nodes.iter().any(touches)
+            || node.backprop_inputs.iter().flatten().any(touches)
+            || node.shape.is_some_and(|s| replaced.contains_key(&s));
+        if !changed {
+            return id;
+        }
+        let incoming_nodes: SmallVec<IncomingTensor> =
+            node.incoming_nodes.iter().map(remap).collect();
+        let topo_rank = incoming_nodes
+            .iter()
+            .map(|x| self.topo_rank(x.id) + 1)
+            .max()
+            .unwrap_or(0);
+        let new = TensorInternal {
+            id: usize::default(),
+            op: node.op.clone(),
+            incoming_nodes,
+            topo_rank,
+            shape: node.shape.map(|s| *replaced.get(&s).unwrap_or(&s)),
+            placeholder_name: node.placeholder_name,
+            is_differentiable: node.is_differentiable,
+            backprop_inputs: node
+                .backprop_inputs
+                .as_ref()
+                .map(|xs| xs.iter().map(remap).collect()),
+            known_shape: node.known_shape.clone(),
+            variable_id: node.variable_id,
+        };
+        drop(node);
+        self.install(new)
+    }
+
     #[inline]
     pub(crate) fn topo_rank(&self, id: TensorID) -> usize {
         self.node_set.borrow()[id].topo_rank
     }
+
+    // Drops the cached outputs of the nodes depending on `id`.
+    pub(crate) fn invalidate_consumers(&self, id: TensorID) {
+        let mut cache = self.cache.borrow_mut();
+        if cache.is_empty() {
+            return;
+        }
+        // Inputs are always installed before their consumers.
+        let nodes = self.node_set.borrow();
+        let frozen = self.frozen.borrow();
+        let mut dirty = FxHashSet::default();
+        dirty.insert(id);
+        for node in &nodes[id + 1..] {
+            // a frozen tensor keeps its first value, and so do its consumers
+            if frozen.contains(&node.id) {
+                continue;
+            }
+            if node.incoming_nodes.iter().any(|x| dirty.contains(&x.id)) {
+                dirty.insert(node.id);
+                cache.remove(&node.id);
+            }
+        }
+        self.cache_version.set(self.cache_version.get() + 1);
+    }
+
+    pub(crate) fn clear_cache_if(&self, mut predicate: impl FnMut(TensorID) -> bool) {
+        self.cache.borrow_mut().retain(|&id, _| !predicate(id));
+        self.cache_version.set(self.cache_version.get() + 1);
+    }
 }
 
 impl<T: Float> fmt::Debug for Graph<T> {
@@ -88,13 +535,9 @@ where
     F: Float,
     FN: FnOnce(&mut Context<F>) -> R,
 {
-    let graph_internal = Graph {
-        node_set: RefCell::new(Vec::with_capacity(512)),
-        variable2node: RefCell::new(FxHashMap::default()),
-    };
     let mut ctx = Context {
         var_env_ref: &mut VariableEnvironment::new(),
-        graph: graph_internal,
+        graph: Graph::new(512),
     };
     f(&mut ctx)
 }
@@ -111,6 +554,18 @@ pub struct Context<'env, F: Float> {
     pub(crate) var_env_ref: &'env VariableEnvironment<F>,
 }
 
+/// State of a training run saved by [Context::checkpoint].
+pub struct ContextSnapshot<F: Float> {
+    // Array of each variable, by id; `None` for the slices and the tied variables
+    variables: Vec<Option<NdArray<F>>>,
+    // Arrays of the variables stored as f16 (see `half_storage`)
+    #[cfg(feature = "f16")]
+    half: Vec<(VariableID, ndarray::ArrayD<half::f16>)>,
+    global_step: usize,
+    // See `Op::state`
+    op_states: Vec<(TensorID, Box<dyn Any>)>,
+}
+
 impl<'graph, 'env, F: Float> Context<'env, F> {
     /// Get or create a variable namespace with the specified name.
     ///
@@ -141,6 +596,737 @@ impl<'graph, 'env, F: Float> Context<'env, F> {
     pub fn clear(&mut self) {
         self.graph.node_set.borrow_mut().clear();
         self.graph.variable2node.borrow_mut().clear();
+        self.graph.peak_outputs.borrow_mut().clear();
+        self.graph.kept_outputs.borrow_mut().clear();
+        self.graph.pinned.borrow_mut().clear();
+        self.graph.frozen.borrow_mut().clear();
+        self.graph.gradient_of.borrow_mut().clear();
+        self.graph.clear_cache_if(|_| true);
+    }
+
+    /// Limits the depth of the graphs this context evaluates.
+    ///
+    /// Evaluating a tensor whose longest path to a source node is longer than `max_depth`
+    /// fails with [EvalError::TooDeep](crate::EvalError::TooDeep) without computing anything.
+    /// Unlimited by default.
+    ///
+    /// ```
+    /// use autograd as ag;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     g.set_max_depth(1);
+    ///     let x = ag::tensor_ops::ones(&[2], g);
+    ///     assert!(x.eval(g).is_ok());
+    ///     assert!((x + x).eval(g).is_err());
+    /// });
+    /// ```
+    #[inline]
+    pub fn set_max_depth(&self, max_depth: usize) {
+        self.graph.max_depth.set(max_depth);
+    }
+
+    /// Returns the depth limit set by [Context::set_max_depth].
+    #[inline]
+    pub fn max_depth(&self) -> usize {
+        self.graph.max_depth.get()
+    }
+
+    /// Returns the length of the longest path from a source node to one of `outputs`.
+    ///
+    /// A source node (constant, placeholder or variable) has depth 0, and an op is one deeper
+    /// than its deepest input. This is the depth compared against [Context::set_max_depth].
+    /// The depth of each node is recorded when it's created, so this doesn't walk the graph.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let x = g.placeholder("x", &[2]);
+    ///     let y = T::exp(T::exp(x)) + x;
+    ///     assert_eq!(g.graph_depth(&[x]), 0);
+    ///     assert_eq!(g.graph_depth(&[y, x]), 3);
+    /// });
+    /// ```
+    pub fn graph_depth<A: AsRef<Tensor<'graph, F>>>(&self, outputs: &[A]) -> usize {
+        outputs
+            .iter()
+            .map(|t| {
+                assert_same_graph(self, t.as_ref().graph);
+                self.graph.topo_rank(t.as_ref().id)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Drops the outputs cached for the tensors marked with [Tensor::pin].
+    ///
+    /// They stay pinned, and are computed again by the next evaluation that needs them.
+    #[inline]
+    pub fn clear_pinned(&self) {
+        let pinned = self.graph.pinned.borrow();
+        self.graph.clear_cache_if(|id| pinned.contains(&id));
+    }
+
+    /// Returns the number of tensors whose outputs are cached in this context: the
+    /// [pinned](Tensor::pin) ones, the ones made by
+    /// [freeze_after_first](crate::tensor_ops::freeze_after_first), and every output kept
+    /// by [Context::set_incremental].
+    #[inline]
+    pub fn cache_len(&self) -> usize {
+        self.graph.cache.borrow().len()
+    }
+
+    /// Drops all the cached outputs counted by [Context::cache_len].
+    ///
+    /// Unlike [Context::clear], the tensors stay valid, pinned and frozen: the next
+    /// evaluation that needs them computes and caches them again.
+    #[inline]
+    pub fn clear_cache(&self) {
+        self.graph.clear_cache_if(|_| true);
+    }
+
+    /// Drops the cached output of `t`, if any, and returns whether there was one.
+    ///
+    /// Only `t` is evicted: the tensors computed from it keep their cached outputs, which
+    /// the following evaluations still use without reading `t`. Evict them as well to have
+    /// them computed again from the new value of `t`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let a = T::exp(T::ones(&[2], g)).pin();
+    ///     let b = T::square(a).pin();
+    ///     b.eval(g).unwrap();
+    ///     assert_eq!(g.cache_len(), 2);
+    ///
+    ///     assert!(g.evict(&a));
+    ///     assert!(!g.evict(&a));
+    ///     assert_eq!(g.cache_len(), 1);
+    ///     // `b` is still cached, so `a` isn't computed again
+    ///     g.reset_counters();
+    ///     b.eval(g).unwrap();
+    ///     assert_eq!(g.op_call_count(), 0);
+    /// });
+    /// ```
+    pub fn evict(&self, t: &Tensor<'graph, F>) -> bool {
+        assert_same_graph(self, t.graph);
+        let cached = self.graph.cache.borrow().contains_key(&t.id);
+        if cached {
+            self.graph.clear_cache_if(|id| id == t.id);
+        }
+        cached
+    }
+
+    /// Makes the following evaluations reuse the values computed by the previous ones.
+    ///
+    /// When on, the output of every node that depends neither on a placeholder nor on an op
+    /// updating a variable in place is kept in this context, and computed again only after
+    /// one of the variables behind it is overwritten with [Context::set_variable]
+    /// (or updated in place by an op such as an optimizer update).
+    /// This suits interactive use, where one of the variables is tweaked between evaluations.
+    ///
+    /// Copies of all those outputs stay alive in the context, and all ops are assumed to be pure:
+    /// random ops would keep returning their first sample. Turning it off drops the copies.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::new();
+    /// env.name("a").set(ag::ndarray::arr1(&[1., 2.]));
+    /// env.name("b").set(ag::ndarray::arr1(&[3., 4.]));
+    ///
+    /// env.run(|g| {
+    ///     g.set_incremental(true);
+    ///     let a = T::exp(g.variable("a"));
+    ///     let b = T::exp(g.variable("b"));
+    ///     let y = T::sum_all(a + b);
+    ///     y.eval(g).unwrap();
+    ///
+    ///     // recomputes `a` and `y`, but not `b`
+    ///     g.set_variable("a", ag::ndarray::arr1(&[0., 0.]).into_dyn());
+    ///     let expected = 2. + 3f64.exp() + 4f64.exp();
+    ///     assert!((y.eval(g).unwrap()[[]] - expected).abs() < 1e-9);
+    /// });
+    /// ```
+    #[inline]
+    pub fn set_incremental(&self, incremental: bool) {
+        self.graph.incremental.set(incremental);
+        if !incremental {
+            let pinned = self.graph.pinned.borrow();
+            let frozen = self.graph.frozen.borrow();
+            self.graph.clear_cache_if(|id| !pinned.contains(&id) && !frozen.contains(&id));
+        }
+    }
+
+    /// Returns the flag set by [Context::set_incremental].
+    #[inline]
+    pub fn is_incremental(&self) -> bool {
+        self.graph.incremental.get()
+    }
+
+    /// Releases the arrays of the variables that none of `live_outputs` depends on, e.g. after
+    /// discarding part of a model, and returns their ids.
+    ///
+    /// The states of the optimizers (the moments of [Adam](crate::optimizers::Adam) and the
+    /// accumulators of the others) and the variables of
+    /// [RUNNING_STATS_NAMESPACE_ID](crate::variable::RUNNING_STATS_NAMESPACE_ID) are kept,
+    /// although no output of the model depends on them. See [Context::gc_keeping] to keep the
+    /// variables of other namespaces.
+    ///
+    /// The live set is the variables [Graph::inputs_of] finds for `live_outputs` and the kept
+    /// ones above, plus the parents of the live
+    /// [slices](VariableEnvironment::slice). A released variable keeps its
+    /// id and name so that the ids of the others stay valid, but its array is replaced with an
+    /// empty one of shape `[0]`, and so are the ones its slices view and the `f16` ones of the
+    /// variables stored as `f16` (see `half_storage`). The variables whose
+    /// array is [shared](VariableEnvironment::share) with another environment are left alone,
+    /// as are the ones already released.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    ///
+    /// let mut env = ag::VariableEnvironment::<f32>::new();
+    /// let w = env.set(ag::ndarray_ext::ones(&[2, 2]));
+    /// let old_head = env.set(ag::ndarray_ext::ones(&[2, 10]));
+    /// let adam = ag::optimizers::Adam::default("my_adam", [w], &mut env);
+    ///
+    /// env.run(|g| {
+    ///     let y = g.variable(w) * 2.;
+    ///     // the moments of `w` are kept for the next update
+    ///     assert_eq!(g.gc(&[&y]), vec![old_head]);
+    ///     assert_eq!(g.env().get_array_by_id(old_head).unwrap().borrow().len(), 0);
+    /// });
+    /// ```
+    pub fn gc(&'graph self, live_outputs: &[&Tensor<'graph, F>]) -> Vec<VariableID> {
+        self.gc_keeping(live_outputs, &[])
+    }
+
+    /// Same as [Context::gc], keeping the variables of `keep_namespaces` as well.
+    pub fn gc_keeping(
+        &'graph self,
+        live_outputs: &[&Tensor<'graph, F>],
+        keep_namespaces: &[&str],
+    ) -> Vec<VariableID> {
+        let env = self.var_env_ref;
+        let used = self.inputs_of(live_outputs).into_iter();
+        let kept = env
+            .name_to_id
+            .iter()
+            .filter(|(name, _)| {
+                let ns = name.namespace_id.as_str();
+                ns == RUNNING_STATS_NAMESPACE_ID
+                    || env.state_namespaces.contains(ns)
+                    || keep_namespaces.contains(&ns)
+            })
+            .map(|(_, &vid)| vid);
+        let mut live = FxHashSet::default();
+        for vid in used.filter_map(|t| t.get_variable_id()).chain(kept) {
+            live.insert(vid);
+            if let Some(slice) = env.slices.get(&vid) {
+                live.insert(slice.parent);
+            }
+        }
+        let mut ret = Vec::new();
+        for (i, array) in env.array_list.iter().enumerate() {
+            let vid = VariableID::from(i);
+            if live.contains(&vid) || env.slices.contains_key(&vid) || Rc::strong_count(array) > 1 {
+                continue;
+            }
+            #[cfg(feature = "f16")]
+            if let Some(half) = env.half.get(&vid) {
+                let mut half = half.borrow_mut();
+                if half.shape() != [0] {
+                    *half = ndarray::ArrayD::from_elem(ndarray::IxDyn(&[0]), half::f16::ZERO);
+                    // and the master weights
+                    *array.borrow_mut() = NdArray::zeros(ndarray::IxDyn(&[0]));
+                    ret.push(vid);
+                }
+                continue;
+            }
+            let mut array = array.borrow_mut();
+            if array.shape() == [0] {
+                continue;
+            }
+            *array = NdArray::zeros(ndarray::IxDyn(&[0]));
+            ret.push(vid);
+        }
+        for &vid in &ret {
+            let id = self.graph.variable2node.borrow().get(&vid).cloned();
+            if let Some(id) = id {
+                self.graph.invalidate_consumers(id);
+            }
+        }
+        ret
+    }
+
+    /// Overwrites a variable array, and drops the cached outputs that depend on it.
+    ///
+    /// `key` is anything accepted by `Context::variable`. See [Context::set_incremental].
+    pub fn set_variable<K>(&'graph self, key: K, value: NdArray<F>)
+    where
+        Self: GetVariableTensor<'graph, F, K>,
+    {
+        let var = self.variable(key);
+        let vid = var.get_variable_id().unwrap();
+        if self.var_env_ref.slices.contains_key(&vid) {
+            // written through to the parent
+            self.var_env_ref.as_view_mut(vid).assign(&value);
+        } else if self.var_env_ref.assign_half(vid, &value.view()) {
+            // rounded to f16
+        } else {
+            *self.var_env_ref.array_list[vid.0].borrow_mut() = value;
+        }
+        self.graph.invalidate_consumers(var.id);
+        for alias in self.var_env_ref.aliases(vid) {
+            if let Some(&id) = self.graph.variable2node.borrow().get(&alias) {
+                self.graph.invalidate_consumers(id);
+            }
+        }
+    }
+
+    /// Returns the running mean and variance registered under `name`.
+    ///
+    /// Returns `None` if `name` wasn't registered with
+    /// [VariableEnvironment::add_running_stats].
+    pub fn running_stats(&self, name: &str) -> Option<(NdArray<F>, NdArray<F>)> {
+        let ns = self.var_env_ref.namespace(RUNNING_STATS_NAMESPACE_ID);
+        let mean = ns.get_array_by_name(format!("{}/mean", name))?;
+        let var = ns.get_array_by_name(format!("{}/var", name))?;
+        let ret = (mean.borrow().clone(), var.borrow().clone());
+        Some(ret)
+    }
+
+    /// Overwrites the running mean and variance registered under `name`, e.g. with the ones
+    /// of a checkpoint, and drops the cached outputs that depend on them.
+    ///
+    /// Panics if `name` wasn't registered with [VariableEnvironment::add_running_stats].
+    pub fn set_running_stats(&'graph self, name: &str, mean: NdArray<F>, var: NdArray<F>) {
+        let ns = self.var_env_ref.namespace(RUNNING_STATS_NAMESPACE_ID);
+        for (suffix, value) in [("mean", mean), ("var", var)] {
+            let t = self
+                .graph
+                .variable_by_name(format!("{}/{}", name, suffix), &ns);
+            let vid = t.get_variable_id().unwrap();
+            *self.var_env_ref.array_list[vid.0].borrow_mut() = value;
+            self.graph.invalidate_consumers(t.id);
+        }
+    }
+
+    /// Returns the number of elements of the variables that `outputs` depend on,
+    /// e.g. to report the size of a model.
+    ///
+    /// The running statistics registered with [VariableEnvironment::add_running_stats]
+    /// are not trained by backprop, so they aren't counted.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::<f32>::new();
+    /// env.name("w").set(ag::ndarray_ext::ones(&[3, 2]));
+    /// env.name("b").set(ag::ndarray_ext::ones(&[1, 2]));
+    ///
+    /// env.run(|g| {
+    ///     let x = g.placeholder("x", &[-1, 3]);
+    ///     let y = T::matmul(x, g.variable("w")) + g.variable("b");
+    ///     assert_eq!(g.num_parameters(&[y]), 3 * 2 + 2);
+    /// });
+    /// ```
+    pub fn num_parameters<A: AsRef<Tensor<'graph, F>>>(&'graph self, outputs: &[A]) -> usize {
+        let mut running_stats = FxHashSet::default();
+        for (name, &vid) in &self.var_env_ref.name_to_id {
+            if name.namespace_id == RUNNING_STATS_NAMESPACE_ID {
+                running_stats.insert(vid);
+            }
+        }
+        let env = self.var_env_ref;
+        let vids: FxHashSet<_> = self
+            .graph
+            .inputs_of(outputs)
+            .iter()
+            .filter_map(|t| t.get_variable_id())
+            .filter(|vid| !running_stats.contains(vid))
+            .collect();
+        let mut ret = 0;
+        for &vid in &vids {
+            // slices are already counted in their parent
+            match env.slices.get(&vid) {
+                Some(slice) if vids.contains(&slice.parent) => {}
+                _ => ret += env.variable_shape(vid).iter().product::<usize>(),
+            }
+        }
+        ret
+    }
+
+    /// Makes the reductions of this context sum in a fixed, pairwise order.
+    ///
+    /// With this flag on, `reduce_sum`, `reduce_mean` and `sum_all` give bitwise identical results
+    /// regardless of the memory layout of their inputs (e.g. after a `transpose`).
+    /// The price is speed: each reduced lane is summed separately instead of accumulating
+    /// whole sub-arrays at once, which is typically several times slower for reductions
+    /// over any axis but the last.
+    ///
+    /// It also breaks the ties of `reduce_max` and `reduce_min` in their gradients: each
+    /// reduced group passes its gradient to its first extremum, the one with the lowest flat
+    /// (row-major) index, instead of to every element equal to the extremum. `argmax`,
+    /// `argmin` and `max_pool2d` always pick the lowest index.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f64>| {
+    ///     let x = T::convert_to_tensor(ag::ndarray::arr1(&[1., 3., 3.]), g);
+    ///     let gx = T::grad(&[T::reduce_max(x, &[0], false)], &[x])[0];
+    ///     assert_eq!(gx.eval(g), Ok(ag::ndarray::arr1(&[0., 1., 1.]).into_dyn()));
+    ///     g.set_deterministic(true);
+    ///     assert_eq!(gx.eval(g), Ok(ag::ndarray::arr1(&[0., 1., 0.]).into_dyn()));
+    /// });
+    /// ```
+    #[inline]
+    pub fn set_deterministic(&self, deterministic: bool) {
+        self.graph.deterministic.set(deterministic);
+    }
+
+    /// Returns the flag set by [Context::set_deterministic].
+    #[inline]
+    pub fn is_deterministic(&self) -> bool {
+        self.graph.deterministic.get()
+    }
+
+    /// Makes the evaluations return their arrays in column-major (Fortran) order.
+    ///
+    /// For handing the results without a copy to column-major consumers, e.g. Fortran BLAS,
+    /// Julia or R. The ops still compute in the layouts they use, mostly row-major: each
+    /// returned array that isn't column-major already is copied into a column-major one. The
+    /// values are the same either way; only the strides change. Row-major is the default.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f64>| {
+    ///     let x = T::convert_to_tensor(ag::ndarray::arr2(&[[1., 2., 3.], [4., 5., 6.]]), g);
+    ///     g.set_column_major(true);
+    ///     let y = x.eval(g).unwrap();
+    ///     assert_eq!(y.strides(), &[1, 2]);
+    ///     assert_eq!(y, ag::ndarray::arr2(&[[1., 2., 3.], [4., 5., 6.]]).into_dyn());
+    /// });
+    /// ```
+    #[inline]
+    pub fn set_column_major(&self, column_major: bool) {
+        self.graph.column_major.set(column_major);
+    }
+
+    /// Returns the flag set by [Context::set_column_major].
+    #[inline]
+    pub fn is_column_major(&self) -> bool {
+        self.graph.column_major.get()
+    }
+
+    /// Makes the ops take their single-threaded, pure-Rust path.
+    ///
+    /// Matrix products skip BLAS even when one of the BLAS features is enabled, batched ones
+    /// run on a single thread, and reductions sum in the fixed order of
+    /// [Context::set_deterministic]. This is the ground truth to validate the fast paths
+    /// against, and what gradient checks should be run with.
+    /// Convolutions are not affected.
+    #[inline]
+    pub fn set_reference_mode(&self, reference: bool) {
+        self.graph.reference.set(reference);
+    }
+
+    /// Returns the flag set by [Context::set_reference_mode].
+    #[inline]
+    pub fn is_reference_mode(&self) -> bool {
+        self.graph.reference.get()
+    }
+
+    /// Switches the ops that behave differently in training and in inference to either mode.
+    ///
+    /// The flag is read when the graph is evaluated, so the same tensors can be evaluated
+    /// in both modes without rebuilding them. It's on by default. It's followed by
+    /// [dropout_by_mode](crate::tensor_ops::dropout_by_mode),
+    /// [batch_norm_by_mode](crate::tensor_ops::batch_norm_by_mode) and
+    /// [training_switch](crate::tensor_ops::training_switch); the ops taking an explicit
+    /// `train` flag ignore it.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let y = T::dropout_by_mode(T::ones(&[4], g), 0.5);
+    ///     g.training(false);
+    ///     assert_eq!(y.eval(g).unwrap(), ag::ndarray::arr1(&[0.5; 4]).into_dyn());
+    ///     assert!(!g.is_training());
+    /// });
+    /// ```
+    #[inline]
+    pub fn training(&self, training: bool) {
+        self.graph.training.set(training);
+    }
+
+    /// Returns the flag set by [Context::training].
+    #[inline]
+    pub fn is_training(&self) -> bool {
+        self.graph.training.get()
+    }
+
+    /// Returns the number of training steps taken with the variable environment of this context.
+    ///
+    /// The counter lives in the [VariableEnvironment], so it carries over from one `run` to the
+    /// next, and is saved and loaded with the variables. [Optimizer::update] and
+    /// [TrainStep::step] increment it once per update; an update op evaluated by hand (e.g.
+    /// from [Optimizer::get_update_op]) is counted with [Context::increment_global_step].
+    /// Schedules such as [warmup](crate::training::warmup) take it as their step.
+    ///
+    /// [Optimizer::update]: crate::optimizers::Optimizer::update
+    /// [Optimizer::get_update_op]: crate::optimizers::Optimizer::get_update_op
+    /// [TrainStep::step]: crate::optimizers::TrainStep::step
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::optimizers::{Optimizer, SGD};
+    ///
+    /// let env = ag::VariableEnvironment::<f32>::new();
+    /// let sgd = SGD::new(0.1);
+    /// for _ in 0..3 {
+    ///     env.run(|g| {
+    ///         let (vars, grads): (&[ag::Tensor<f32>], &[ag::Tensor<f32>]) = (&[], &[]);
+    ///         sgd.update(vars, grads, g, ag::Feeder::new());
+    ///     });
+    /// }
+    /// env.run(|g| assert_eq!(g.global_step(), 3));
+    /// ```
+    #[inline]
+    pub fn global_step(&self) -> usize {
+        self.var_env_ref.global_step.get()
+    }
+
+    /// Counts one training step, and returns the new [Context::global_step].
+    #[inline]
+    pub fn increment_global_step(&self) -> usize {
+        let step = self.var_env_ref.global_step.get() + 1;
+        self.var_env_ref.global_step.set(step);
+        step
+    }
+
+    /// Sets [Context::global_step], e.g. to 0 to restart the schedules.
+    #[inline]
+    pub fn set_global_step(&self, step: usize) {
+        self.var_env_ref.global_step.set(step);
+    }
+
+    /// Saves the state of the training run in this context, to roll back to with
+    /// [Context::restore], e.g. after a bad update.
+    ///
+    /// The snapshot holds copies of the arrays of all the variables, including the running
+    /// statistics, the states of the optimizers and the variables
+    /// [stored as `f16`](crate::half_storage), the [global step](Context::global_step),
+    /// and the generators of the random ops of the graph (see [Op::state](crate::op::Op::state)).
+    /// The generators that aren't `XorShiftRng` or `StdRng` are only captured if they were
+    /// given with [ArrayRng::cloneable](crate::ndarray_ext::ArrayRng::cloneable); the others
+    /// go on from where they are after a restore.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::new();
+    /// env.name("w").set(ag::ndarray::arr1(&[1., 2.]));
+    ///
+    /// env.run(|g| {
+    ///     let noise = T::standard_normal(&[2], g);
+    ///     let snapshot = g.checkpoint();
+    ///     let first = noise.eval(g).unwrap();
+    ///     g.set_variable("w", ag::ndarray::arr1(&[f64::NAN, 0.]).into_dyn());
+    ///
+    ///     g.restore(&snapshot);
+    ///     assert_eq!(g.variable("w").eval(g), Ok(ag::ndarray::arr1(&[1., 2.]).into_dyn()));
+    ///     assert_eq!(noise.eval(g).unwrap(), first);
+    /// });
+    /// ```
+    pub fn checkpoint(&self) -> ContextSnapshot<F> {
+        let env = self.var_env_ref;
+        let variables = env
+            .array_list
+            .iter()
+            .enumerate()
+            .map(|(i, arr)| {
+                // the arrays of the slices and the tied variables are in other variables
+                let vid = VariableID(i);
+                (!env.slices.contains_key(&vid) && !env.ties.contains_key(&vid))
+                    .then(|| arr.borrow().clone())
+            })
+            .collect();
+        let op_states = self
+            .graph
+            .node_set
+            .borrow()
+            .iter()
+            .filter_map(|node| node.op.as_ref()?.state().map(|state| (node.id, state)))
+            .collect();
+        ContextSnapshot {
+            variables,
+            #[cfg(feature = "f16")]
+            half: env.half.iter().map(|(&vid, arr)| (vid, arr.borrow().clone())).collect(),
+            global_step: env.global_step.get(),
+            op_states,
+        }
+    }
+
+    /// Sets the state of the training run back to `snapshot`, taken by [Context::checkpoint].
+    ///
+    /// The variables created after the checkpoint keep their values. The states of the ops
+    /// are given back to the tensors with the same ids, which are the same tensors in the
+    /// context the snapshot was taken from, or in one building the same graph
+    /// (see [Tensor::id]).
+    pub fn restore(&self, snapshot: &ContextSnapshot<F>) {
+        let env = self.var_env_ref;
+        for (i, arr) in snapshot.variables.iter().enumerate() {
+            if let Some(arr) = arr {
+                *env.array_list[i].borrow_mut() = arr.clone();
+            }
+        }
+        #[cfg(feature = "f16")]
+        for (vid, arr) in &snapshot.half {
+            if let Some(half) = env.half.get(vid) {
+                *half.borrow_mut() = arr.clone();
+            }
+        }
+        let restored: Vec<_> = self
+            .graph
+            .variable2node
+            .borrow()
+            .iter()
+            .filter(|(vid, _)| vid.0 < snapshot.variables.len())
+            .map(|(_, &id)| id)
+            .collect();
+        for id in restored {
+            self.graph.invalidate_consumers(id);
+        }
+        env.global_step.set(snapshot.global_step);
+
+        let nodes = self.graph.node_set.borrow();
+        for (id, state) in &snapshot.op_states {
+            if let Some(op) = nodes.get(*id).and_then(|node| node.op.as_ref()) {
+                op.restore_state(&**state);
+            }
+        }
+    }
+
+    /// Makes the evaluations of this context compute with `backend` instead of [CpuBackend].
+    ///
+    /// See [backend](crate::backend) for the ops it covers. The results of `eval` are the same
+    /// ndarray arrays either way.
+    pub fn set_backend<B: Backend<F> + 'static>(&self, backend: B) {
+        *self.graph.backend.borrow_mut() = Some(Rc::new(backend));
+    }
+
+    /// Returns the [name](Backend::name) of the backend set by [Context::set_backend].
+    pub fn backend_name(&self) -> &'static str {
+        match *self.graph.backend.borrow() {
+            Some(ref backend) => backend.name(),
+            None => Backend::<F>::name(&CpuBackend),
+        }
+    }
+
+    /// Runs `f` without the bookkeeping kept for gradients, for lighter inference.
+    ///
+    /// Gradient tensors are built lazily by [grad](crate::tensor_ops::grad), so forward values
+    /// are the same inside and outside the scope. What's skipped inside it is the tracking of
+    /// gradient tensors: the hook of [Context::on_gradient] isn't called, and the gradients
+    /// created in the scope are never reported to it afterwards.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let y = g.no_grad(|g| T::sigmoid(T::zeros(&[2], g)).eval(g).unwrap());
+    ///     assert_eq!(y, ag::ndarray::arr1(&[0.5, 0.5]).into_dyn());
+    ///     assert!(!g.is_no_grad());
+    /// });
+    /// ```
+    pub fn no_grad<R>(&'graph self, f: impl FnOnce(&'graph Self) -> R) -> R {
+        let prev = self.graph.no_grad.replace(true);
+        let ret = f(self);
+        self.graph.no_grad.set(prev);
+        ret
+    }
+
+    /// Returns true inside [Context::no_grad].
+    #[inline]
+    pub fn is_no_grad(&self) -> bool {
+        self.graph.no_grad.get()
+    }
+
+    /// Runs `f` with the matrix products and convolutions built in it computed in half precision
+    /// (requires the `f16` feature).
+    ///
+    /// The ops of [AUTOCAST_OPS](crate::tensor_ops::AUTOCAST_OPS) created inside the scope round
+    /// their inputs to `f16` values, as with [half_precision](crate::tensor_ops::half_precision),
+    /// and the evaluations store their outputs and the rounded inputs as `f16`: these arrays
+    /// take 2 bytes per element in [Context::memory_report], and their consumers read them
+    /// upcast to `F`. The kernels compute in `F` on the rounded values. Every other op, e.g.
+    /// reductions and losses, stays in the precision of `F`. Gradients go through the roundings
+    /// unchanged, so the variables are the full-precision master weights.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g: &mut ag::Context<f32>| {
+    ///     let a = T::convert_to_tensor(ag::ndarray::arr2(&[[1.0001f32]]), g);
+    ///     let y = g.autocast(|_| T::matmul(a, a));
+    ///     assert_eq!(y.eval(g).unwrap()[[0, 0]], 1.);
+    ///     assert!(!g.is_autocast());
+    /// });
+    /// ```
+    #[cfg(feature = "f16")]
+    pub fn autocast<R>(&'graph self, f: impl FnOnce(&'graph Self) -> R) -> R {
+        self.autocast_with_full_precision(&[], f)
+    }
+
+    /// Same as [Context::autocast], but the ops named in `full_precision` (function names
+    /// from [AUTOCAST_OPS](crate::tensor_ops::AUTOCAST_OPS), e.g. `"conv2d"`) stay in the
+    /// precision of `F`.
+    ///
+    /// Panics if a name isn't in `AUTOCAST_OPS`.
+    #[cfg(feature = "f16")]
+    pub fn autocast_with_full_precision<R>(
+        &'graph self,
+        full_precision: &[&'static str],
+        f: impl FnOnce(&'graph Self) -> R,
+    ) -> R {
+        for name in full_precision {
+            assert!(
+                crate::tensor_ops::AUTOCAST_OPS.contains(name),
+                "autocast: {:?} is not one of {:?}",
+                name,
+                crate::tensor_ops::AUTOCAST_OPS
+            );
+        }
+        let prev = self.graph.autocast.replace(Some(full_precision.to_vec()));
+        let ret = f(self);
+        *self.graph.autocast.borrow_mut() = prev;
+        ret
+    }
+
+    /// Returns true inside [Context::autocast].
+    #[cfg(feature = "f16")]
+    #[inline]
+    pub fn is_autocast(&self) -> bool {
+        self.graph.autocast.borrow().is_some()
     }
 
     /// Creates a placeholder tensor in a [Graph].
@@ -197,6 +1383,110 @@ impl<'graph, 'env, F: Float> Context<'env, F> {
         let b = b.set_known_shape(shape);
         b.build(T::basic_source_ops::Placeholder)
     }
+
+    /// Creates a placeholder whose dims may be named unknowns.
+    ///
+    /// Same as [Context::placeholder], but a dim can be a [Dim::Symbol] such as the batch size,
+    /// which [Context::infer_shape] carries through the ops. The placeholders sharing a symbol
+    /// must be fed arrays of the same size along it.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    /// use ag::Dim;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let x = g.symbolic_placeholder("x", &[Dim::Symbol("batch"), Dim::Known(3)]);
+    ///     let y = g.symbolic_placeholder("y", &[Dim::Symbol("batch"), Dim::Known(1)]);
+    ///     let z = x * y;
+    ///     assert_eq!(
+    ///         g.infer_shape(z),
+    ///         Ok(Some(vec![Dim::Symbol("batch"), Dim::Known(3)]))
+    ///     );
+    /// });
+    /// ```
+    pub fn symbolic_placeholder(
+        &'graph self,
+        name: &'static str,
+        shape: &[Dim],
+    ) -> Tensor<'graph, F> {
+        let b = Tensor::builder(self).set_placeholder_name(name);
+        let dims: Option<Vec<F>> = shape
+            .iter()
+            .map(|d| d.value().map(|n| F::from(n).unwrap()))
+            .collect();
+        let b = if let Some(dims) = dims {
+            let rank = dims.len();
+            let shape = T::convert_to_tensor(
+                NdArray::from_shape_vec(ndarray::IxDyn(&[rank]), dims).unwrap(),
+                self,
+            );
+            b.set_shape(&shape)
+        } else {
+            b
+        };
+        let b = b.set_known_dims(shape);
+        b.build(T::basic_source_ops::Placeholder)
+    }
+
+    /// Infers the static shape of `x` before evaluating it.
+    ///
+    /// The shapes come from the placeholders, variables and constants `x` depends on, and
+    /// go through the ops implementing [op::Op::infer_shape](crate::op::Op::infer_shape).
+    /// Dims that depend on the values fed are [Dim::Unknown] or [Dim::Symbol]; the shape is
+    /// `None` as soon as an op on the way doesn't know its output shape.
+    ///
+    /// Incompatible shapes are reported without evaluating anything, as long as the dims
+    /// that don't match are known.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    /// use ag::Dim;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let x = g.placeholder("x", &[-1, 64]);
+    ///     let w = T::convert_to_tensor(ag::ndarray_ext::zeros(&[64, 10]), g);
+    ///     let y = T::matmul(x, w);
+    ///     assert_eq!(g.infer_shape(y), Ok(Some(vec![Dim::Unknown, Dim::Known(10)])));
+    ///
+    ///     let bad = T::matmul(y, w);
+    ///     assert!(g.infer_shape(bad).is_err());
+    /// });
+    /// ```
+    pub fn infer_shape<A: AsRef<Tensor<'graph, F>>>(
+        &'graph self,
+        x: A,
+    ) -> Result<Option<Vec<Dim>>, OpError> {
+        let mut shapes = FxHashMap::<TensorID, Option<Vec<Dim>>>::default();
+        // Vec<(tensor_id, inputs_done)>
+        let mut stack = vec![(x.as_ref().id, false)];
+        while let Some((id, inputs_done)) = stack.pop() {
+            if shapes.contains_key(&id) {
+                continue;
+            }
+            let node = self.access_inner(id);
+            let shape = if let Some(ref known_shape) = node.known_shape {
+                Some(known_shape.get().to_vec())
+            } else if let Some(vid) = node.variable_id {
+                let shape = self.var_env_ref.variable_shape(vid);
+                Some(shape.into_iter().map(Dim::Known).collect())
+            } else if !inputs_done {
+                stack.push((id, true));
+                stack.extend(node.incoming_nodes.iter().map(|x| (x.id, false)));
+                continue;
+            } else {
+                let inputs: Vec<_> = node
+                    .incoming_nodes
+                    .iter()
+                    .map(|x| shapes[&x.id].clone())
+                    .collect();
+                node.get_op().infer_shape(&inputs)?
+            };
+            shapes.insert(id, shape);
+        }
+        Ok(shapes.remove(&x.as_ref().id).unwrap())
+    }
 }
 
 impl<'env, F: Float> Deref for Context<'env, F> {
@@ -0,0 +1,159 @@
+//! Half-precision storage of `f32` variables (requires the `f16` feature).
+//!
+//! [VariableEnvironment::store_as_half] keeps a variable as `f16` and frees its `f32` array,
+//! halving its memory, e.g. for a large embedding table. Computation still happens in `f32`:
+//! each evaluation reading the variable upcasts it to a temporary `f32` array, dropped when
+//! the evaluation returns. `save` and `load` keep it as `f16`.
+//!
+//! A variable stored as `f16` can be trained: the first evaluation updating it in place, e.g.
+//! an optimizer's update, copies it to `f32` master weights kept next to its `f16` array.
+//! The updates are applied to the master weights, then rounded to `f16`, so the small ones
+//! aren't lost to rounding. A trained variable then takes 6 bytes per element, 2 more than in
+//! `f32`, so the memory is saved on the variables that aren't trained.
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::variable::VariableID;
+use crate::VariableEnvironment;
+use half::f16;
+use std::cell::RefCell;
+
+/// An array stored as `f16`.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::half_storage::HalfArray;
+///
+/// let x = ag::ndarray::arr1(&[1f32, 0.5, -3.]).into_dyn();
+/// let half = HalfArray::from_f32(&x.view());
+/// assert_eq!(half.bytes(), 3 * 2);
+/// assert_eq!(half.to_f32(), x);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct HalfArray {
+    inner: ndarray::ArrayD<f16>,
+}
+
+impl HalfArray {
+    /// Rounds `x` to the nearest `f16` values.
+    pub fn from_f32(x: &NdArrayView<f32>) -> Self {
+        HalfArray {
+            inner: x.mapv(f16::from_f32),
+        }
+    }
+
+    /// Upcasts this array to `f32`.
+    pub fn to_f32(&self) -> NdArray<f32> {
+        self.inner.mapv(f16::to_f32)
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        self.inner.shape()
+    }
+
+    /// Bytes occupied by the elements.
+    pub fn bytes(&self) -> usize {
+        self.inner.len() * std::mem::size_of::<f16>()
+    }
+
+    /// Returns the underlying `f16` array.
+    pub fn as_array(&self) -> &ndarray::ArrayD<f16> {
+        &self.inner
+    }
+}
+
+impl From<ndarray::ArrayD<f16>> for HalfArray {
+    fn from(inner: ndarray::ArrayD<f16>) -> Self {
+        HalfArray { inner }
+    }
+}
+
+impl VariableEnvironment<f32> {
+    /// Stores the variable `vid` as `f16`, rounding its elements, and frees its `f32` array.
+    ///
+    /// Evaluations upcast it to `f32` to compute with it. Updating it in place gives it `f32`
+    /// master weights (see the [module docs](self)).
+    /// [get_array_by_id](VariableEnvironment::get_array_by_id) returns those, or an empty array
+    /// before the first update; read it with [get_half](VariableEnvironment::get_half).
+    ///
+    /// Does nothing if it's already stored as `f16`. Panics if `vid` is a
+    /// [slice](VariableEnvironment::slice) or has slices, is [tied](crate::nn::tie_weights),
+    /// or if its array is [shared](VariableEnvironment::share) with another env.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::<f32>::new();
+    /// let table = env.set(ag::ndarray::arr2(&[[1f32, 0.1], [2., 3.]]));
+    /// env.store_as_half(table);
+    /// assert_eq!(env.get_array_by_id(table).unwrap().borrow().len(), 0);
+    /// assert_eq!(env.get_half(table).bytes(), 4 * 2);
+    ///
+    /// env.run(|g| {
+    ///     let ids = T::convert_to_tensor(ag::ndarray::arr1(&[1f32]), g);
+    ///     let rows = T::gather(g.variable(table), ids, 0);
+    ///     assert_eq!(rows.eval(g).unwrap(), ag::ndarray::arr2(&[[2f32, 3.]]).into_dyn());
+    /// });
+    /// ```
+    pub fn store_as_half(&mut self, vid: VariableID) {
+        if self.is_half(vid) {
+            return;
+        }
+        assert!(
+            !self.slices.contains_key(&vid) && self.aliases(vid).is_empty(),
+            "store_as_half: variable {} is a slice or has slices",
+            vid
+        );
+        assert!(
+            !self.ties.contains_key(&vid) && self.ties.values().all(|tie| tie.to != vid),
+            "store_as_half: variable {} is tied",
+            vid
+        );
+        assert!(
+            std::rc::Rc::strong_count(&self.array_list[vid.0]) == 1,
+            "store_as_half: the array of variable {} is shared with another env",
+            vid
+        );
+        let array = self.array_list[vid.0].replace(NdArray::zeros(ndarray::IxDyn(&[0])));
+        let half = HalfArray::from_f32(&array.view());
+        self.half.insert(vid, RefCell::new(half.inner));
+    }
+
+    /// Returns a half-precision copy of the variable `vid`.
+    pub fn get_half(&self, vid: VariableID) -> HalfArray {
+        match self.half.get(&vid) {
+            Some(half) => HalfArray::from(half.borrow().clone()),
+            None => HalfArray::from_f32(&self.as_view(vid)),
+        }
+    }
+
+    /// Overwrites the variable `vid` with `value`, upcast to `f32` unless the variable is
+    /// stored as `f16` (its master weights, if any, get the upcast values).
+    ///
+    /// Panics if the shapes don't match.
+    pub fn set_half(&self, vid: VariableID, value: &HalfArray) {
+        let has_master_weights = self.has_master_weights(vid);
+        if let Some(half) = self.half.get(&vid) {
+            let mut half = half.borrow_mut();
+            assert_eq!(
+                half.shape(),
+                value.shape(),
+                "set_half: shape mismatch for variable {}",
+                vid
+            );
+            half.assign(&value.inner);
+            if has_master_weights {
+                self.as_view_mut(vid).assign(&value.to_f32());
+            }
+            return;
+        }
+        let mut var = self.as_view_mut(vid);
+        assert_eq!(
+            var.shape(),
+            value.shape(),
+            "set_half: shape mismatch for variable {}",
+            vid
+        );
+        var.zip_mut_with(&value.inner, |a, &b| *a = b.to_f32());
+    }
+}
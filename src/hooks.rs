@@ -49,6 +49,12 @@ pub struct ShowShape;
 
 pub struct ShowPrefixedShape(pub &'static str);
 
+/// Prints the shape, min/max/mean and first elements to stderr, with the given message.
+pub struct Summary(pub &'static str);
+
+// Number of the leading elements `Summary` prints
+const NUM_SUMMARY_ELEMENTS: usize = 5;
+
 // Calls the given function.
 pub struct Raw<T: Float, FUN: Fn(&NdArrayView<T>) -> () + Send + Sync> {
     pub(crate) raw: FUN,
@@ -90,3 +96,37 @@ impl<T: Float> Hook<T> for ShowPrefixedShape {
         println!("{}\n{:?}", self.0, arr.shape());
     }
 }
+
+impl<T: Float> Hook<T> for Summary {
+    fn call(&self, arr: &crate::ndarray_ext::NdArrayView<T>) {
+        if arr.is_empty() {
+            eprintln!("{} shape={:?} (empty)", self.0, arr.shape());
+            return;
+        }
+        let mut min = T::infinity();
+        let mut max = T::neg_infinity();
+        let mut sum = T::zero();
+        for &a in arr.iter() {
+            min = min.min(a);
+            max = max.max(a);
+            sum += a;
+        }
+        let mean = sum / T::from(arr.len()).unwrap();
+        let first: Vec<T> = arr.iter().take(NUM_SUMMARY_ELEMENTS).cloned().collect();
+        let ellipsis = if arr.len() > NUM_SUMMARY_ELEMENTS {
+            ", ..."
+        } else {
+            ""
+        };
+        eprintln!(
+            "{} shape={:?} min={} max={} mean={} values={:?}{}",
+            self.0,
+            arr.shape(),
+            min,
+            max,
+            mean,
+            first,
+            ellipsis
+        );
+    }
+}
@@ -136,17 +136,25 @@ extern crate serde_derive;
 extern crate approx;
 extern crate special;
 
+pub mod backend;
+pub mod debug;
 pub mod evaluation;
 mod gradient;
 pub(crate) mod graph;
+#[cfg(feature = "f16")]
+pub mod half_storage;
 pub mod hooks;
+pub mod metrics;
 pub mod ndarray_ext;
+pub mod nn;
 pub mod op;
 pub mod optimizers;
 pub mod prelude;
+pub mod spec;
 pub mod tensor;
 pub mod tensor_ops;
 pub mod test_helper;
+pub mod training;
 pub mod variable;
 
 use rustc_hash::{FxHashMap, FxHashSet};
@@ -225,14 +233,17 @@ pub use crate::ndarray_ext::array_gen;
 
 pub use crate::ndarray_ext::{NdArray, NdArrayView, NdArrayViewMut};
 
-pub use crate::evaluation::{Evaluator, Feeder};
+pub use crate::evaluation::{
+    EvalStream, EvalViews, Evaluator, Feeder, GradientNorms, MemoryReport, OpTiming, PoolStats,
+    Profiler,
+};
 
-pub use crate::tensor::Tensor;
+pub use crate::tensor::{Dim, Tensor};
 
 pub(crate) use graph::Graph;
 pub(crate) use op::OpOutput;
 
-pub use crate::graph::{run, Context};
+pub use crate::graph::{run, Context, ContextSnapshot};
 pub use crate::variable::VariableEnvironment;
 use serde::{Deserialize, Serialize};
 
@@ -240,7 +251,16 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, PartialEq)]
 pub enum EvalError {
     /// Error during `Op`'s computation.
-    OpError(op::OpError),
+    ///
+    /// `trace` lists the names of the ops from the failed one up to the evaluated tensor.
+    OpError {
+        error: op::OpError,
+        trace: Vec<&'static str>,
+    },
+    /// The tensor is deeper than [Context::set_max_depth] allows.
+    TooDeep { depth: usize, max_depth: usize },
+    /// The evaluation ran out of the time given to [Context::eval_with_timeout].
+    Timeout { limit: std::time::Duration },
 }
 
 impl std::error::Error for EvalError {}
@@ -248,7 +268,21 @@ impl std::error::Error for EvalError {}
 impl fmt::Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            EvalError::OpError(e) => e.fmt(f),
+            EvalError::OpError { error, trace } => {
+                write!(f, "{}", error.to_string().trim_end_matches(": "))?;
+                for name in trace {
+                    write!(f, "\n    at {}", name)?;
+                }
+                Ok(())
+            }
+            EvalError::TooDeep { depth, max_depth } => write!(
+                f,
+                "graph deeper than {} (depth {}); increase it with Context::set_max_depth",
+                max_depth, depth
+            ),
+            EvalError::Timeout { limit } => {
+                write!(f, "evaluation timed out after {:?}", limit)
+            }
         }
     }
 }
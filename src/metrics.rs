@@ -0,0 +1,65 @@
+//! Non-differentiable statistics of evaluated arrays, for training diagnostics.
+//!
+//! These take the arrays returned by evaluation, e.g. the gradients of a step, rather than
+//! tensors, so they never add nodes to a graph.
+use crate::ndarray_ext::NdArrayView;
+use crate::Float;
+
+/// Counts the elements of `x` falling in each of `bins` equal-width bins spanning `range`.
+///
+/// Bin `i` covers `[lo + i * w, lo + (i + 1) * w)` with `w = (hi - lo) / bins`, except for the
+/// last one which also includes `hi`. Elements outside `range`, and NaNs, are dropped rather
+/// than clamped into the edge bins, so the counts may sum to less than `x.len()`.
+///
+/// Panics if `bins` is zero or `range` is empty.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::metrics::histogram;
+///
+/// let x = ag::ndarray::arr1(&[0., 0.1, 0.5, 0.9, 1.0, 1.5]).into_dyn();
+/// assert_eq!(histogram(&x.view(), 2, (0., 1.)), vec![2, 3]);
+/// ```
+pub fn histogram<F: Float>(x: &NdArrayView<F>, bins: usize, range: (F, F)) -> Vec<usize> {
+    let (lo, hi) = range;
+    assert!(bins > 0, "histogram: bins must be positive");
+    assert!(lo < hi, "histogram: range must satisfy lo < hi");
+    let scale = F::from(bins).unwrap() / (hi - lo);
+    let mut counts = vec![0; bins];
+    for &v in x.iter() {
+        if v.is_nan() || v < lo || v > hi {
+            continue;
+        }
+        let i = ((v - lo) * scale).to_usize().unwrap_or(bins - 1);
+        counts[i.min(bins - 1)] += 1;
+    }
+    counts
+}
+
+/// Counts the occurrences of each non-negative integer in `indices`.
+///
+/// The result has length `max(minlength, max(indices) + 1)`. Indices are truncated toward
+/// zero like a cast; negative ones and NaNs are dropped.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::metrics::bincount;
+///
+/// let labels = ag::ndarray::arr1(&[0., 2., 2., 1., 2.]).into_dyn();
+/// assert_eq!(bincount(&labels.view(), 0), vec![1, 1, 3]);
+/// assert_eq!(bincount(&labels.view(), 5), vec![1, 1, 3, 0, 0]);
+/// ```
+pub fn bincount<F: Float>(indices: &NdArrayView<F>, minlength: usize) -> Vec<usize> {
+    let mut counts = vec![0; minlength];
+    for &v in indices.iter() {
+        if v.is_nan() || v < F::zero() {
+            continue;
+        }
+        let i = v.to_usize().unwrap();
+        if i >= counts.len() {
+            counts.resize(i + 1, 0);
+        }
+        counts[i] += 1;
+    }
+    counts
+}
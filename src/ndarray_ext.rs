@@ -6,6 +6,10 @@ use ndarray;
 // expose array_gen
 pub use crate::array_gen::*;
 use crate::Float;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
 
 /// alias for `ndarray::Array<T, IxDyn>`
 pub type NdArray<T> = ndarray::Array<T, ndarray::IxDyn>;
@@ -110,6 +114,16 @@ pub(crate) fn deep_copy<T: Float>(x: &NdArrayView<T>) -> NdArray<T> {
     unsafe { NdArray::from_shape_vec_unchecked(x.shape(), vec) }
 }
 
+// `x` laid out in column-major order, copied unless it is already
+pub(crate) fn into_column_major<T: Float>(x: NdArray<T>) -> NdArray<T> {
+    if x.t().is_standard_layout() {
+        return x;
+    }
+    let mut ret = NdArray::zeros(ndarray::ShapeBuilder::f(x.raw_dim()));
+    ret.assign(&x);
+    ret
+}
+
 #[inline]
 pub(crate) fn scalar_shape<T: Float>() -> NdArray<T> {
     // tested
@@ -121,6 +135,37 @@ pub(crate) fn is_scalar_shape(shape: &[usize]) -> bool {
     shape.len() == 0 || shape == [0]
 }
 
+/// Shape of the result of broadcasting arrays of shapes `a` and `b` together (numpy's rules).
+///
+/// Dimensions are matched from the last one, and each pair must be equal or contain 1.
+///
+/// ```
+/// use autograd::ndarray_ext::broadcast_shapes;
+///
+/// assert_eq!(broadcast_shapes(&[3, 1], &[2, 1, 4]), Ok(vec![2, 3, 4]));
+/// assert!(broadcast_shapes(&[3, 1], &[2, 4]).is_err());
+/// ```
+pub fn broadcast_shapes(a: &[usize], b: &[usize]) -> Result<Vec<usize>, crate::op::OpError> {
+    let ndim = a.len().max(b.len());
+    let mut ret = vec![0; ndim];
+    for i in 0..ndim {
+        // counted from the last dim
+        let da = if i < a.len() { a[a.len() - 1 - i] } else { 1 };
+        let db = if i < b.len() { b[b.len() - 1 - i] } else { 1 };
+        ret[ndim - 1 - i] = if da == db || db == 1 {
+            da
+        } else if da == 1 {
+            db
+        } else {
+            return Err(crate::op::OpError::IncompatibleShape(format!(
+                "cannot broadcast {:?} and {:?}",
+                a, b
+            )));
+        };
+    }
+    Ok(ret)
+}
+
 #[inline]
 pub(crate) fn shape_of_view<T: Float>(x: &NdArrayView<T>) -> NdArray<T> {
     let shape = x
@@ -157,11 +202,16 @@ pub(crate) fn zip<I, J>(i: I, j: J) -> iter::Zip<I::IntoIter, J::IntoIter>
 
 pub fn select<F: Clone>(param: &NdArrayView<F>, axis: ndarray::Axis, indices: &[ndarray::Ix]) -> NdArray<F>
 {
+    if indices.is_empty() {
+        // nothing to concatenate: the result is empty along `axis`
+        let mut shape = param.shape().to_vec();
+        shape[axis.index()] = 0;
+        return NdArray::from_shape_vec(shape, Vec::new()).unwrap();
+    }
     let mut subs = vec![param.view(); indices.len()];
     for (&i, sub) in zip(indices, &mut subs[..]) {
         sub.collapse_axis(axis, i);
     }
-    assert!(!subs.is_empty(), "Got empty indices for select");
     concatenate(axis, subs.as_slice()).unwrap()
 }
 
@@ -212,6 +262,124 @@ pub fn concatenate<F: Clone>(axis: ndarray::Axis, arrays: &[NdArrayView<F>]) ->
 }
 
 
+// Header of the files written by `save_array`
+const ARRAY_MAGIC: &[u8; 4] = b"AGND";
+const ARRAY_FORMAT_VERSION: u8 = 1;
+
+// Tag of the element type in the header of `save_array`
+fn dtype_tag<T: Float>() -> Option<u8> {
+    if crate::same_type::<T, f32>() {
+        Some(1)
+    } else if crate::same_type::<T, f64>() {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn dtype_name(tag: u8) -> &'static str {
+    match tag {
+        1 => "f32",
+        2 => "f64",
+        _ => "unknown",
+    }
+}
+
+fn invalid_data(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+/// Writes `x` to `path` in a small binary format recording its shape and element type.
+///
+/// The file holds the magic bytes `AGND`, a format version byte, an element type byte
+/// (1 for f32, 2 for f64), the number of dims as a little-endian `u32`, each dim as a
+/// little-endian `u64`, and then the elements in row-major order, little-endian.
+/// Read it back with [load_array].
+///
+/// ```
+/// use autograd as ag;
+/// use ag::ndarray_ext::{load_array, save_array};
+///
+/// let dir = std::env::temp_dir().join("rust-autograd-doc-save-array");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let x = ag::ndarray::arr2(&[[1f32, 2.], [3., 4.]]).into_dyn();
+/// save_array(dir.join("x.bin"), &x).unwrap();
+/// assert_eq!(load_array::<f32, _>(dir.join("x.bin")).unwrap(), x);
+/// ```
+pub fn save_array<T: Float, P: AsRef<Path>>(path: P, x: &NdArray<T>) -> Result<(), Box<dyn Error>> {
+    let tag = dtype_tag::<T>()
+        .ok_or_else(|| invalid_data("save_array: unsupported element type".to_string()))?;
+    let mut w = io::BufWriter::new(File::create(path.as_ref())?);
+    w.write_all(ARRAY_MAGIC)?;
+    w.write_all(&[ARRAY_FORMAT_VERSION, tag])?;
+    w.write_all(&(x.ndim() as u32).to_le_bytes())?;
+    for &n in x.shape() {
+        w.write_all(&(n as u64).to_le_bytes())?;
+    }
+    // `iter` is in logical row-major order whatever the memory layout
+    for &v in x.iter() {
+        if tag == 1 {
+            w.write_all(&v.to_f32().unwrap().to_le_bytes())?;
+        } else {
+            w.write_all(&v.to_f64().unwrap().to_le_bytes())?;
+        }
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads an array written by [save_array].
+///
+/// Fails if the file isn't in that format, or if it holds elements of another type than `T`.
+pub fn load_array<T: Float, P: AsRef<Path>>(path: P) -> Result<NdArray<T>, Box<dyn Error>> {
+    let mut r = io::BufReader::new(File::open(path.as_ref())?);
+    let mut header = [0u8; 10];
+    r.read_exact(&mut header)?;
+    if &header[..4] != ARRAY_MAGIC {
+        return Err(invalid_data("load_array: not an array file".to_string()));
+    }
+    if header[4] != ARRAY_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "load_array: unknown format version {}",
+            header[4]
+        )));
+    }
+    let tag = header[5];
+    if Some(tag) != dtype_tag::<T>() {
+        return Err(invalid_data(format!(
+            "load_array: the file holds {} elements, not {}",
+            dtype_name(tag),
+            std::any::type_name::<T>()
+        )));
+    }
+    let ndim = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    let mut shape = Vec::with_capacity(ndim);
+    let mut buf = [0u8; 8];
+    for _ in 0..ndim {
+        r.read_exact(&mut buf)?;
+        shape.push(u64::from_le_bytes(buf) as usize);
+    }
+    let len: usize = shape.iter().product();
+    let mut data = Vec::with_capacity(len);
+    for _ in 0..len {
+        let v = if tag == 1 {
+            let mut buf = [0u8; 4];
+            r.read_exact(&mut buf)?;
+            T::from(f32::from_le_bytes(buf)).unwrap()
+        } else {
+            r.read_exact(&mut buf)?;
+            T::from(f64::from_le_bytes(buf)).unwrap()
+        };
+        data.push(v);
+    }
+    if r.read(&mut buf)? != 0 {
+        return Err(invalid_data(
+            "load_array: trailing bytes after the elements".to_string(),
+        ));
+    }
+    Ok(NdArray::from_shape_vec(shape, data)?)
+}
+
 /// A collection of array generator functions.
 pub mod array_gen {
     use rand::distributions::Distribution;
@@ -220,13 +388,19 @@ pub mod array_gen {
     use std::marker::PhantomData;
 
     use super::*;
+    use rand::rngs::StdRng;
     use rand_xorshift::XorShiftRng;
+    use std::any::Any;
     use std::cell::RefCell;
 
     /// Helper structure to create ndarrays whose elements are pseudorandom numbers.
     ///
     /// This is actually a wrapper of an arbitrary `rand::Rng`, the default is `XorShiftRng`.
     ///
+    /// [Context::checkpoint](crate::Context::checkpoint) saves the generators of the random ops
+    /// that are `XorShiftRng` or `StdRng`, or that were given with [ArrayRng::cloneable].
+    /// The others aren't captured: restoring a checkpoint leaves them as they are.
+    ///
     /// ```
     /// use autograd as ag;
     /// use rand::{self, prelude::StdRng, SeedableRng};
@@ -243,6 +417,20 @@ pub mod array_gen {
     pub struct ArrayRng<T: Float, R: Rng = XorShiftRng> {
         phantom: PhantomData<T>,
         rng: RefCell<R>,
+        // Set by `ArrayRng::cloneable`
+        capture: Option<RngCapture<R>>,
+    }
+
+    // Copies a generator for `Op::state`, and a copy back for `Op::restore_state`
+    type RngCapture<R> = (fn(&R) -> Box<dyn Any>, fn(&mut R, &dyn Any) -> bool);
+
+    fn clone_rng<R: Clone + 'static>(rng: &R) -> Box<dyn Any> {
+        Box::new(rng.clone())
+    }
+
+    // Returns false if `state` isn't an `R`
+    fn assign_rng<R: Clone + 'static>(rng: &mut R, state: &dyn Any) -> bool {
+        state.downcast_ref::<R>().map(|state| *rng = state.clone()).is_some()
     }
 
     const XORSHIFT_DEFAULT_SEED: [u8; 16] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
@@ -259,17 +447,94 @@ pub mod array_gen {
             ArrayRng {
                 phantom: PhantomData,
                 rng: RefCell::new(rng),
+                capture: None,
+            }
+        }
+    }
+
+    impl<T: Float, R: Rng + Clone + 'static> ArrayRng<T, R> {
+        /// Same as [ArrayRng::new], and lets [Context::checkpoint](crate::Context::checkpoint)
+        /// save the generator whatever its type.
+        ///
+        /// ```
+        /// use autograd as ag;
+        /// use ag::tensor_ops as T;
+        /// use ag::rand::rngs::mock::StepRng;
+        ///
+        /// ag::run(|g: &mut ag::Context<f64>| {
+        ///     let rng = ag::ndarray_ext::ArrayRng::cloneable(StepRng::new(1, 1 << 60));
+        ///     let noise = T::standard_uniform_rng(rng, &[3], g);
+        ///     let snapshot = g.checkpoint();
+        ///     let first = noise.eval(g).unwrap();
+        ///     g.restore(&snapshot);
+        ///     assert_eq!(noise.eval(g).unwrap(), first);
+        /// });
+        /// ```
+        pub fn cloneable(rng: R) -> Self {
+            ArrayRng {
+                phantom: PhantomData,
+                rng: RefCell::new(rng),
+                capture: Some((clone_rng::<R>, assign_rng::<R>)),
             }
         }
     }
 
     impl<T: Float, R: Rng> ArrayRng<T, R> {
         /// Creates `ArrRng` with pre-instantiated `Rng`.
+        ///
+        /// Unless it's a `XorShiftRng` or a `StdRng`, the generator isn't saved by
+        /// [Context::checkpoint](crate::Context::checkpoint); see [ArrayRng::cloneable].
         pub fn new(rng: R) -> Self {
             ArrayRng {
                 phantom: PhantomData,
                 rng: RefCell::new(rng),
+                capture: None,
+            }
+        }
+
+        // Copy of the generator, if it was given to `cloneable` or is a `XorShiftRng` or a
+        // `StdRng`: `R` needn't be `Clone`
+        pub(crate) fn state(&self) -> Option<Box<dyn Any>>
+        where
+            R: 'static,
+        {
+            let rng = self.rng.borrow();
+            if let Some((clone, _)) = self.capture {
+                return Some(clone(&rng));
             }
+            let rng: &dyn Any = &*rng;
+            if let Some(rng) = rng.downcast_ref::<XorShiftRng>() {
+                return Some(clone_rng(rng));
+            }
+            rng.downcast_ref::<StdRng>().map(clone_rng)
+        }
+
+        // Sets the generator to a copy returned by `state`.
+        //
+        // Panics if `state` is the copy of another type of generator, e.g. one taken from
+        // another op: leaving the generator as it is would silently replay other samples.
+        pub(crate) fn restore_state(&self, state: &dyn Any)
+        where
+            R: 'static,
+        {
+            let mut rng = self.rng.borrow_mut();
+            let restored = if let Some((_, assign)) = self.capture {
+                assign(&mut rng, state)
+            } else {
+                let rng: &mut dyn Any = &mut *rng;
+                if let Some(rng) = rng.downcast_mut::<XorShiftRng>() {
+                    assign_rng(rng, state)
+                } else if let Some(rng) = rng.downcast_mut::<StdRng>() {
+                    assign_rng(rng, state)
+                } else {
+                    false
+                }
+            };
+            assert!(
+                restored,
+                "restore_state: the state isn't a {}",
+                std::any::type_name::<R>()
+            );
         }
 
         /// Generates `ndarray::Array<T, ndarray::IxDyn>` whose elements are random numbers.
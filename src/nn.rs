@@ -0,0 +1,368 @@
+//! Building blocks for neural networks, written over the ops in [tensor_ops](crate::tensor_ops).
+//!
+//! [Sequential] chains layers over an input tensor:
+//! ```
+//! use autograd as ag;
+//! use ag::nn::Sequential;
+//! use ag::optimizers::{grad_helper, Optimizer, SGD};
+//! use ag::tensor_ops as T;
+//!
+//! let mlp = Sequential::new("mlp", 784).dense(128).relu().dropout(0.1).dense(10);
+//!
+//! let mut env = ag::VariableEnvironment::<f32>::new();
+//! let params = mlp.register(&mut env);
+//! let sgd = SGD::new(0.01);
+//!
+//! env.run(|ctx| {
+//!     let x = ctx.placeholder("x", &[-1, 784]);
+//!     let y = ctx.placeholder("y", &[-1]);
+//!     let logits = mlp.build(x, ctx, true);
+//!     let loss = T::sparse_softmax_cross_entropy(logits, y);
+//!
+//!     let ns = ctx.namespace("mlp");
+//!     let (vars, grads) = grad_helper(&[loss], &ns);
+//!     assert_eq!(vars.len(), params.len());
+//!
+//!     let x_batch = ag::ndarray_ext::zeros(&[2, 784]);
+//!     let y_batch = ag::ndarray::arr1(&[1., 3.]).into_dyn();
+//!     let mut feeder = ag::Feeder::new();
+//!     feeder.push(x, x_batch.view()).push(y, y_batch.view());
+//!     sgd.update(&vars, &grads, ctx, feeder);
+//! });
+//! ```
+//!
+//! A single fully connected layer is a [Dense] registered under a name, applied with [dense]:
+//! ```
+//! use autograd as ag;
+//! use ag::nn::{self, Dense, Initializer};
+//! use ag::tensor_ops as T;
+//!
+//! let mut env = ag::VariableEnvironment::<f64>::new();
+//! Dense::new(3, 2).initializer(Initializer::GlorotNormal).register(&mut env, "net", "fc1");
+//! Dense::new(2, 1).bias(false).register(&mut env, "net", "fc2");
+//!
+//! env.run(|ctx| {
+//!     let x = T::ones(&[4, 3], ctx);
+//!     let y = nn::dense(nn::dense(x, ctx, "net", "fc1"), ctx, "net", "fc2");
+//!     assert_eq!(y.eval(ctx).unwrap().shape(), &[4, 1]);
+//! });
+//! ```
+use crate::ndarray_ext::{self, ArrayRng, NdArray};
+use crate::tensor::Tensor;
+use crate::tensor_ops as T;
+use crate::variable::{NamespaceTrait, VariableEnvironment, VariableID, VariableTie};
+use crate::{Context, Float};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How [Dense] fills its weight matrix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Initializer {
+    /// [ArrayRng::glorot_uniform], the default
+    GlorotUniform,
+    /// [ArrayRng::glorot_normal]
+    GlorotNormal,
+    /// All zeros
+    Zeros,
+}
+
+impl Initializer {
+    fn init<F: Float>(self, shape: &[usize], rng: &ArrayRng<F>) -> NdArray<F> {
+        match self {
+            Initializer::GlorotUniform => rng.glorot_uniform(shape),
+            Initializer::GlorotNormal => rng.glorot_normal(shape),
+            Initializer::Zeros => ndarray_ext::zeros(shape),
+        }
+    }
+}
+
+/// A fully connected layer `matmul(x, w) + b`, for `x` of shape `[batch, input_dim]`.
+///
+/// [Dense::register] creates `w` of shape `[input_dim, units]` and `b` of shape `[1, units]`
+/// with the names `"{name}/w"` and `"{name}/b"`; [dense] applies them in a graph.
+#[derive(Clone, Debug)]
+pub struct Dense {
+    input_dim: usize,
+    units: usize,
+    bias: bool,
+    initializer: Initializer,
+}
+
+impl Dense {
+    /// Creates a layer with a bias and `glorot_uniform` weights.
+    pub fn new(input_dim: usize, units: usize) -> Self {
+        Dense {
+            input_dim,
+            units,
+            bias: true,
+            initializer: Initializer::GlorotUniform,
+        }
+    }
+
+    /// Switches the bias on or off. A bias is always initialized with zeros.
+    pub fn bias(mut self, bias: bool) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    /// Sets how the weights are initialized.
+    pub fn initializer(mut self, initializer: Initializer) -> Self {
+        self.initializer = initializer;
+        self
+    }
+
+    /// Registers the variables of this layer with `namespace` of `env`, and returns their ids.
+    pub fn register<F: Float>(
+        &self,
+        env: &mut VariableEnvironment<F>,
+        namespace: &'static str,
+        name: &str,
+    ) -> Vec<VariableID> {
+        self.register_with_rng(env, namespace, name, &ArrayRng::default())
+    }
+
+    /// Same as [Dense::register], drawing the weights from `rng`.
+    pub fn register_with_rng<F: Float>(
+        &self,
+        env: &mut VariableEnvironment<F>,
+        namespace: &'static str,
+        name: &str,
+        rng: &ArrayRng<F>,
+    ) -> Vec<VariableID> {
+        let mut ns = env.namespace_mut(namespace);
+        let w = self.initializer.init(&[self.input_dim, self.units], rng);
+        let mut ret = vec![ns.slot().name(format!("{}/w", name)).set(w)];
+        if self.bias {
+            let b = ndarray_ext::zeros(&[1, self.units]);
+            ret.push(ns.slot().name(format!("{}/b", name)).set(b));
+        }
+        ret
+    }
+}
+
+/// Applies the [Dense] layer registered as `name` in `namespace` to `x`.
+///
+/// Returns `matmul(x, w) + b`, or `matmul(x, w)` if the layer has no bias.
+/// Panics if no such layer has been registered with the environment of `ctx`.
+pub fn dense<'g, F: Float>(
+    x: Tensor<'g, F>,
+    ctx: &'g Context<F>,
+    namespace: &'static str,
+    name: &str,
+) -> Tensor<'g, F> {
+    let ns = ctx.var_env_ref.namespace(namespace);
+    let w = ctx.variable_by_name(format!("{}/w", name), &ns);
+    let y = T::matmul(x, w);
+    let b_name = format!("{}/b", name);
+    if ns.get_array_by_name(&b_name).is_some() {
+        y + ctx.variable_by_name(b_name, &ns)
+    } else {
+        y
+    }
+}
+
+/// Ties the variable `b` to the variable `a` of `env`, e.g. to share the input embedding of a
+/// language model with its output projection.
+///
+/// `b` must have the shape of `a` or, for matrices, of its transpose. If the shapes are the
+/// same, `b` holds the array of `a` from then on, like [VariableEnvironment::share] does
+/// across environments, and `ctx.variable` of `b` is the tensor of `a`. Otherwise `b` gives up
+/// its own array, and `ctx.variable` of `b` is `transpose(a)`. Either way both usages read
+/// the one array of `a`, so the gradients of both accumulate into `a` and the updates of `a`
+/// are seen through `b`; [grad_helper](crate::optimizers::grad_helper) leaves `b` out.
+///
+/// The tie is kept by the environment: it holds in every `run`, and is saved and loaded
+/// with the variables.
+///
+/// Panics if the shapes don't match, if `a` and `b` are the same variable, or if either is
+/// a slice or already tied.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::nn;
+/// use ag::prelude::*;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// let embedding = env.set(ag::ndarray_ext::ones(&[10, 4]));
+/// let projection = env.set(ag::ndarray_ext::zeros(&[4, 10]));
+/// nn::tie_weights(&mut env, embedding, projection);
+///
+/// env.run(|ctx| {
+///     let p = ctx.variable(projection);
+///     assert_eq!(p.eval(ctx).unwrap(), ag::ndarray_ext::ones::<f64>(&[4, 10]));
+/// });
+/// ```
+pub fn tie_weights<F: Float>(env: &mut VariableEnvironment<F>, a: VariableID, b: VariableID) {
+    for vid in [a, b] {
+        assert!(
+            !env.slices.contains_key(&vid) && !env.ties.contains_key(&vid),
+            "tie_weights: variable {} is a slice or already tied",
+            vid
+        );
+        assert!(!env.is_half(vid), "tie_weights: variable {} is stored as f16", vid);
+    }
+    assert!(a != b, "tie_weights: can't tie variable {} to itself", a);
+    let (a_shape, b_shape) = (env.variable_shape(a), env.variable_shape(b));
+    let transposed = if a_shape == b_shape {
+        false
+    } else if a_shape.len() == 2 && a_shape.iter().rev().eq(&b_shape) {
+        true
+    } else {
+        panic!(
+            "tie_weights: can't tie a variable of shape {:?} to one of shape {:?}",
+            b_shape, a_shape
+        );
+    };
+    env.array_list[b.0] = if transposed {
+        Rc::new(RefCell::new(NdArray::zeros(ndarray::IxDyn(&[0]))))
+    } else {
+        env.array_list[a.0].clone()
+    };
+    env.ties.insert(b, VariableTie { to: a, transposed });
+}
+
+/// The sinusoidal positional encoding of the original transformer, of shape `[seq_len, dim]`.
+///
+/// Position `pos` is encoded as `sin(pos / 10000^(2i / dim))` at column `2i` and
+/// `cos(pos / 10000^(2i / dim))` at column `2i + 1`. It's a constant: add it to the embeddings.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::nn;
+///
+/// ag::run(|ctx: &mut ag::Context<f64>| {
+///     let pe = nn::sinusoidal_positional_encoding(10, 4, ctx).eval(ctx).unwrap();
+///     assert_eq!(pe.shape(), &[10, 4]);
+///     // position 0 is `[sin 0, cos 0, ...]`
+///     assert_eq!(pe.slice(ag::ndarray::s![0, ..]).to_vec(), vec![0., 1., 0., 1.]);
+/// });
+/// ```
+pub fn sinusoidal_positional_encoding<'g, F: Float>(
+    seq_len: usize,
+    dim: usize,
+    ctx: &'g Context<F>,
+) -> Tensor<'g, F> {
+    let pos = T::reshape(
+        T::arange(F::zero(), F::from(seq_len).unwrap(), F::one(), ctx),
+        &[seq_len, 1],
+    );
+    let col = T::arange(F::zero(), F::from(dim).unwrap(), F::one(), ctx);
+    let two = T::scalar(F::from(2.).unwrap(), ctx);
+    // `2i` for both of the columns `2i` and `2i + 1`
+    let even_col = T::floor(col / two) * two;
+    let is_odd = col - even_col;
+    let log_base = F::from(10000.).unwrap().ln() / F::from(dim).unwrap();
+    let angles = pos * T::exp(even_col * T::scalar(-log_base, ctx));
+    T::sin(angles) * (T::scalar(F::one(), ctx) - is_odd) + T::cos(angles) * is_odd
+}
+
+enum Layer<F: Float> {
+    // `Dense(units)`, followed by its index among the dense layers
+    Dense(usize, usize),
+    ReLU,
+    Sigmoid,
+    Tanh,
+    Dropout(F),
+}
+
+/// A stack of layers applied one after another.
+///
+/// The weights of the dense layers live in the namespace given to [Sequential::new]:
+/// call [Sequential::register] on the `VariableEnvironment` once, then [Sequential::build]
+/// in each `run` to get the output for an input tensor. The `i` th dense layer
+/// computes `matmul(x, w) + b` with the variables named `"dense{i}/w"` and `"dense{i}/b"`,
+/// so optimizers can pick them up by the namespace or by the ids `register` returns.
+pub struct Sequential<F: Float> {
+    namespace: &'static str,
+    input_dim: usize,
+    layers: Vec<Layer<F>>,
+    num_dense: usize,
+}
+
+impl<F: Float> Sequential<F> {
+    /// Creates an empty stack for inputs of shape `[batch, input_dim]`.
+    pub fn new(namespace: &'static str, input_dim: usize) -> Self {
+        Sequential {
+            namespace,
+            input_dim,
+            layers: Vec::new(),
+            num_dense: 0,
+        }
+    }
+
+    /// Appends a fully connected layer with `units` outputs.
+    pub fn dense(mut self, units: usize) -> Self {
+        self.layers.push(Layer::Dense(units, self.num_dense));
+        self.num_dense += 1;
+        self
+    }
+
+    /// Appends [relu](crate::tensor_ops::relu).
+    pub fn relu(mut self) -> Self {
+        self.layers.push(Layer::ReLU);
+        self
+    }
+
+    /// Appends [sigmoid](crate::tensor_ops::sigmoid).
+    pub fn sigmoid(mut self) -> Self {
+        self.layers.push(Layer::Sigmoid);
+        self
+    }
+
+    /// Appends [tanh](crate::tensor_ops::tanh).
+    pub fn tanh(mut self) -> Self {
+        self.layers.push(Layer::Tanh);
+        self
+    }
+
+    /// Appends [dropout](crate::tensor_ops::dropout), active only when building with `train`.
+    pub fn dropout(mut self, dropout_ratio: F) -> Self {
+        self.layers.push(Layer::Dropout(dropout_ratio));
+        self
+    }
+
+    /// Registers the weights of the dense layers with `env`, and returns their ids.
+    ///
+    /// The weights are initialized by `glorot_uniform` and the biases with zeros.
+    /// Use [Sequential::register_with_rng] to choose the random generator.
+    pub fn register(&self, env: &mut VariableEnvironment<F>) -> Vec<VariableID> {
+        self.register_with_rng(env, &ArrayRng::default())
+    }
+
+    /// Same as [Sequential::register], drawing the weights from `rng`.
+    pub fn register_with_rng(
+        &self,
+        env: &mut VariableEnvironment<F>,
+        rng: &ArrayRng<F>,
+    ) -> Vec<VariableID> {
+        let mut ret = Vec::with_capacity(2 * self.num_dense);
+        let mut dim = self.input_dim;
+        for layer in &self.layers {
+            if let Layer::Dense(units, i) = *layer {
+                let name = format!("dense{}", i);
+                let ids = Dense::new(dim, units).register_with_rng(env, self.namespace, &name, rng);
+                ret.extend(ids);
+                dim = units;
+            }
+        }
+        ret
+    }
+
+    /// Applies the layers to `x` of shape `[batch, input_dim]`.
+    ///
+    /// `train` switches dropout on. Panics if the weights haven't been registered
+    /// with the environment of `ctx`.
+    pub fn build<'g>(&self, x: Tensor<'g, F>, ctx: &'g Context<F>, train: bool) -> Tensor<'g, F> {
+        let mut h = x;
+        for layer in &self.layers {
+            h = match *layer {
+                Layer::Dense(_, i) => dense(h, ctx, self.namespace, &format!("dense{}", i)),
+                Layer::ReLU => T::relu(h),
+                Layer::Sigmoid => T::sigmoid(h),
+                Layer::Tanh => T::tanh(h),
+                Layer::Dropout(ratio) => T::dropout(h, ratio, train),
+            };
+        }
+        h
+    }
+}
@@ -47,14 +47,20 @@
 //! }
 //! ```
 //!
-use std::any::type_name;
+use std::any::{type_name, Any};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::hash::Hasher;
 use std::marker::PhantomData;
 use std::mem;
+use std::rc::Rc;
 
+use crate::backend::{Backend, BinaryOp, CpuBackend};
+use crate::evaluation::BufferPool;
 use crate::ndarray_ext::{NdArrayView, NdArrayViewMut, RawNdArrayView};
 use crate::smallvec::SmallVec as RawSmallVec;
-use crate::tensor::Tensor;
+use crate::tensor::{Dim, Tensor};
 use crate::{Float, NdArray};
 use crate::op::OpInput::NonVariable;
 
@@ -70,6 +76,7 @@ pub enum OpError {
     TypeUnsupported(String),
     InvalidDims(String),
     OutOfBounds(String),
+    NotPositiveDefinite(String),
 }
 
 impl std::error::Error for OpError {}
@@ -82,10 +89,65 @@ impl fmt::Display for OpError {
             OpError::TypeUnsupported(s) => write!(f, "{}: ", s),
             OpError::InvalidDims(s) => write!(f, "{}: ", s),
             OpError::OutOfBounds(s) => write!(f, "{}: ", s),
+            OpError::NotPositiveDefinite(s) => write!(f, "{}: ", s),
         }
     }
 }
 
+/// Value of a parameter of an op, see [Op::params].
+#[derive(Clone, Debug, PartialEq)]
+pub enum OpParam {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Ints(Vec<i64>),
+    Str(String),
+}
+
+impl From<bool> for OpParam {
+    fn from(a: bool) -> Self {
+        OpParam::Bool(a)
+    }
+}
+
+impl From<usize> for OpParam {
+    fn from(a: usize) -> Self {
+        OpParam::Int(a as i64)
+    }
+}
+
+impl From<isize> for OpParam {
+    fn from(a: isize) -> Self {
+        OpParam::Int(a as i64)
+    }
+}
+
+impl From<f64> for OpParam {
+    fn from(a: f64) -> Self {
+        OpParam::Float(a)
+    }
+}
+
+impl From<&[isize]> for OpParam {
+    fn from(a: &[isize]) -> Self {
+        OpParam::Ints(a.iter().map(|&a| a as i64).collect())
+    }
+}
+
+impl From<String> for OpParam {
+    fn from(a: String) -> Self {
+        OpParam::Str(a)
+    }
+}
+
+/// Parameters of an op by name, see [Op::params].
+pub type OpParams = BTreeMap<&'static str, OpParam>;
+
+// Collects the parameters returned by `Op::params`
+pub(crate) fn params<const N: usize>(params: [(&'static str, OpParam); N]) -> OpParams {
+    params.into_iter().collect()
+}
+
 /// Trait for tensor operations. `Tensor` structs wrap this.
 pub trait Op<F: Float> {
     /// Name of this op
@@ -98,6 +160,71 @@ pub trait Op<F: Float> {
 
     /// Returns gradients for input nodes by use of output's gradients etc.
     fn grad(&self, ctx: &mut GradientContext<F>);
+
+    /// Whether `grad` reads the values of each input, or at most its shape.
+    ///
+    /// Inputs beyond the returned slice (all of them by default) are assumed to be needed.
+    /// When this returns `false` for an input, the evaluation computes the shape of that input
+    /// as soon as this op has run, so that the input array can be freed before the backward pass.
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[]
+    }
+
+    /// Static shape of the output given the static shapes of the inputs (`None` where unknown).
+    ///
+    /// Used by [Context::infer_shape](crate::Context::infer_shape). Returns `Ok(None)` when
+    /// the shape can't be told before the evaluation, which is the default.
+    fn infer_shape(&self, _inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, OpError> {
+        Ok(None)
+    }
+
+    /// Feeds the parameters of this op, e.g. its axis, to `state`.
+    ///
+    /// Used by [Graph::fingerprint](crate::Graph::fingerprint): two ops of the same type
+    /// feeding the same values count as the same op. The default feeds nothing, which is right
+    /// for the ops without parameters. The ops of [crate::tensor_ops] feed all their parameters
+    /// but the states of the random generators and the closures.
+    fn hash_params(&self, _state: &mut dyn Hasher) {}
+
+    /// Parameters of this op by name, e.g. `"stride"` for a convolution.
+    ///
+    /// Read by [Tensor::op_params](crate::Tensor::op_params), for tools which describe the
+    /// nodes of a graph. The default is empty.
+    fn params(&self) -> OpParams {
+        OpParams::new()
+    }
+
+    /// Number of outputs appended by `compute`, not counting the arrays kept with
+    /// [ComputeContext::stash]. Only read by [GradientContext::stashed], so only the ops
+    /// stashing arrays need to tell it; the default is 1.
+    fn num_outputs(&self) -> usize {
+        1
+    }
+
+    /// Whether `compute` runs even if some inputs failed to evaluate; the default is `false`,
+    /// which fails this op with the first error of its inputs.
+    ///
+    /// If `true`, each failed input comes as an empty array and its error is given by
+    /// [ComputeContext::input_error].
+    fn allows_failed_inputs(&self) -> bool {
+        false
+    }
+
+    /// Copy of the state this op changes as it computes, e.g. the generator of a random op,
+    /// for [Context::checkpoint](crate::Context::checkpoint). The default is `None`, for the
+    /// ops without such a state.
+    fn state(&self) -> Option<Box<dyn Any>> {
+        None
+    }
+
+    /// Sets the state of this op back to a copy returned by [Op::state].
+    fn restore_state(&self, _state: &dyn Any) {}
+}
+
+// Bits of `x` as an `f64`, to hash a float parameter.
+#[inline]
+pub(crate) fn float_bits<T: Float>(x: T) -> u64 {
+    x.to_f64().unwrap().to_bits()
 }
 
 pub(crate) struct DummyOp<F: Float> {
@@ -127,6 +254,8 @@ pub(crate) enum OpInput<'v, T: Float> {
     NonVariable(Option<NdArrayView<'v, T>>),
     RdOnlyVariable(Option<NdArrayView<'v, T>>),
     RdWrVariable(Option<NdArrayViewMut<'v, T>>),
+    // An op output no other op reads, which the consumer may overwrite
+    RdWrNonVariable(Option<NdArrayViewMut<'v, T>>),
 }
 
 /// `Op::compute`'s output
@@ -154,6 +283,12 @@ impl<'view, T: Float> OpInput<'view, T> {
     pub fn new_rdwr_variable(x: NdArrayViewMut<'view, T>) -> Self {
         OpInput::RdWrVariable(Some(x))
     }
+
+    #[inline]
+    /// Make a read/write input array from an op output used by nothing else
+    pub fn new_rdwr_non_variable(x: NdArrayViewMut<'view, T>) -> Self {
+        OpInput::RdWrNonVariable(Some(x))
+    }
 }
 
 /// Context of an `Op`'s computation phase.
@@ -188,6 +323,22 @@ pub struct ComputeContext<'v, T: Float> {
     xs: SmallVec<OpInput<'v, T>>,
     // Output arrays
     pub(crate) ys: SmallVec<OpOutput<T>>,
+    // Arrays kept for `Op::grad`, output after `ys`
+    pub(crate) stashed: SmallVec<OpOutput<T>>,
+    // See `Context::set_deterministic`
+    pub(crate) deterministic: bool,
+    // See `Context::set_reference_mode`
+    pub(crate) reference: bool,
+    // See `Context::training`
+    pub(crate) training: bool,
+    // See `Context::set_backend`; `CpuBackend` if not set
+    pub(crate) backend: Option<Rc<dyn Backend<T>>>,
+    // See `Context::set_buffer_pool`
+    pub(crate) pool: Option<Rc<RefCell<BufferPool<T>>>>,
+    // See `Context::eval_with_threads`; rayon's global pool if not set
+    pub(crate) thread_pool: Option<Rc<rayon::ThreadPool>>,
+    // Errors of the failed inputs, see `Op::allows_failed_inputs`
+    pub(crate) input_errors: SmallVec<Option<OpError>>,
 }
 
 impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
@@ -196,9 +347,125 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
         ComputeContext {
             xs,
             ys: SmallVec::new(),
+            stashed: SmallVec::new(),
+            deterministic: false,
+            reference: false,
+            training: true,
+            backend: None,
+            pool: None,
+            thread_pool: None,
+            input_errors: SmallVec::new(),
         }
     }
 
+    /// The error of the `i` th input if it failed to evaluate.
+    ///
+    /// Always `None` unless [Op::allows_failed_inputs] returns true.
+    #[inline]
+    pub fn input_error(&self, i: usize) -> Option<&OpError> {
+        self.input_errors.get(i).and_then(|e| e.as_ref())
+    }
+
+    /// Returns true if the results of this op must not depend on the summation order.
+    ///
+    /// See [Context::set_deterministic](crate::Context::set_deterministic).
+    #[inline]
+    pub fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Returns true if this op must avoid BLAS and multithreading.
+    ///
+    /// See [Context::set_reference_mode](crate::Context::set_reference_mode).
+    #[inline]
+    pub fn reference_mode(&self) -> bool {
+        self.reference
+    }
+
+    /// Returns true if this op must behave as in training rather than in inference.
+    ///
+    /// See [Context::training](crate::Context::training).
+    #[inline]
+    pub fn training(&self) -> bool {
+        self.training
+    }
+
+    /// Runs `f` in the rayon thread pool this op must parallelize its work in.
+    ///
+    /// That's the pool of [Context::eval_with_threads](crate::Context::eval_with_threads)
+    /// during such a call, rayon's global pool otherwise. Ops using rayon call their parallel
+    /// iterators inside `f`, which may run on a thread of the pool: it takes the input arrays
+    /// it needs, not the context.
+    #[inline]
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self.thread_pool {
+            Some(ref pool) => pool.install(f),
+            None => f(),
+        }
+    }
+
+    /// Returns the backend to compute with.
+    ///
+    /// See [Context::set_backend](crate::Context::set_backend).
+    #[inline]
+    pub fn backend(&self) -> &dyn Backend<T> {
+        match self.backend {
+            Some(ref backend) => &**backend,
+            None => &CpuBackend,
+        }
+    }
+
+    /// Returns an array of `shape` to write the output into, with unspecified values.
+    ///
+    /// The array comes from the buffer pool of the context if it's on and holds one of
+    /// this size, and is allocated (filled with zeros) otherwise.
+    /// See [Context::set_buffer_pool](crate::Context::set_buffer_pool).
+    pub fn alloc(&self, shape: &[usize]) -> NdArray<T> {
+        match self.pool {
+            Some(ref pool) => {
+                let len = shape.iter().product();
+                let buffer = pool.borrow_mut().take(len);
+                NdArray::from_shape_vec(shape, buffer).unwrap()
+            }
+            None => crate::ndarray_ext::zeros(shape),
+        }
+    }
+
+    /// Same as `x.mapv(f)`, the result being taken from [ComputeContext::alloc].
+    pub fn alloc_map<F: FnMut(T) -> T>(&self, x: &NdArrayView<T>, mut f: F) -> NdArray<T> {
+        let mut y = self.alloc(x.shape());
+        ndarray::Zip::from(&mut y).and(x).for_each(|y, &x| *y = f(x));
+        y
+    }
+
+    /// Same as `x.to_owned()`, the result being taken from [ComputeContext::alloc].
+    pub fn alloc_copy(&self, x: &NdArrayView<T>) -> NdArray<T> {
+        self.alloc_map(x, |x| x)
+    }
+
+    // `a op b` with the backend, or in a pooled array for the default backend
+    pub(crate) fn binary(&self, op: BinaryOp, a: &NdArrayView<T>, b: &NdArrayView<T>) -> NdArray<T> {
+        // scalars (and empty arrays) are special-cased by the backend
+        let special = |x: &NdArrayView<T>| x.is_empty() || x.ndim() == 0;
+        if self.pool.is_none() || self.backend.is_some() || special(a) || special(b) {
+            return self.backend().binary(op, a, b);
+        }
+        let shape = match crate::ndarray_ext::broadcast_shapes(a.shape(), b.shape()) {
+            Ok(shape) => shape,
+            Err(_) => return self.backend().binary(op, a, b),
+        };
+        let (a, b) = (a.broadcast(shape.as_slice()).unwrap(), b.broadcast(shape.as_slice()).unwrap());
+        let f: fn(T, T) -> T = match op {
+            BinaryOp::Add => |a, b| a + b,
+            BinaryOp::Sub => |a, b| a - b,
+            BinaryOp::Mul => |a, b| a * b,
+            BinaryOp::Div => |a, b| a / b,
+        };
+        let mut y = self.alloc(&shape);
+        ndarray::Zip::from(&mut y).and(&a).and(&b).for_each(|y, &a, &b| *y = f(a, b));
+        y
+    }
+
     /// Grabs the `i` th input array as a *read-only* array view.
     ///
     /// Calling `input(i)` more than once causes panic.
@@ -223,7 +490,7 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
                     i, i
                 ),
             },
-            OpInput::RdWrVariable(_) => {
+            OpInput::RdWrVariable(_) | OpInput::RdWrNonVariable(_) => {
                 panic!(
                     "Bad op impl: cannot perform mutable borrowing for input({}). Use input_mut() instead.",
                     i
@@ -232,6 +499,15 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
         }
     }
 
+    /// Returns whether the `i` th input can be grabbed by `input_mut`.
+    #[inline]
+    pub(crate) fn input_is_mut(&self, i: usize) -> bool {
+        matches!(
+            self.xs.get(i),
+            Some(OpInput::RdWrVariable(_)) | Some(OpInput::RdWrNonVariable(_))
+        )
+    }
+
     /// Grabs the `i` th input array as a *read-write* array view.
     ///
     /// Calling `input_mut(i)` more than once causes panic.
@@ -242,13 +518,15 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
             None => panic!("Bad op impl: {}'s input doesn't exist.", i),
         };
         match x {
-            OpInput::RdWrVariable(ref mut a) => match a.take() {
-                Some(ret) => ret,
-                None => panic!(
-                    "Bad op impl: input({})/input_mut({}) cannot be called twice",
-                    i, i
-                ),
-            },
+            OpInput::RdWrVariable(ref mut a) | OpInput::RdWrNonVariable(ref mut a) => {
+                match a.take() {
+                    Some(ret) => ret,
+                    None => panic!(
+                        "Bad op impl: input({})/input_mut({}) cannot be called twice",
+                        i, i
+                    ),
+                }
+            }
             _ => {
                 panic!(
                     "Bad op impl: cannot perform mutable borrowing for input({})",
@@ -274,7 +552,7 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
         let mut contains_variable_input= false;
         for x in &self.xs {
             match x {
-                NonVariable(_) => {},
+                NonVariable(_) | OpInput::RdWrNonVariable(_) => {},
                 _ => contains_variable_input = true
             }
         }
@@ -301,6 +579,19 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
         self.ys.push(OpOutput::Owned(y));
     }
 
+    /// Keeps `y` for the gradient of this op, e.g. a statistic of the forward pass that the
+    /// backward pass would otherwise compute again.
+    ///
+    /// The stashed arrays are extra outputs after the ones of `append_output`: `Op::grad` gets
+    /// the `i` th one as a tensor with [GradientContext::stashed], for the backward ops to take
+    /// as input. Like the other outputs, it's computed once per evaluation and freed after its
+    /// last consumer. A multi-output op stashing arrays must tell its number of outputs with
+    /// [Op::num_outputs].
+    #[inline]
+    pub fn stash(&mut self, y: NdArray<T>) {
+        self.stashed.push(OpOutput::Owned(y));
+    }
+
     /// Returns a number of input arrays.
     #[inline]
     pub fn num_inputs(&self) -> usize {
@@ -341,23 +632,38 @@ impl<'graph, 'view, T: Float> ComputeContext<'view, T> {
 /// ```
 pub struct GradientContext<'graph, T: Float> {
     gy: Tensor<'graph, T>,
+    // Gradients of the other outputs of a multi-output op, if any flowed into them
+    gys: SmallVec<Option<Tensor<'graph, T>>>,
     y: Tensor<'graph, T>,
     graph: &'graph crate::graph::Graph<T>,
     gxs: SmallVec<Option<Tensor<'graph, T>>>,
+    // Whether the gradient of each input leads to the differentiated tensors
+    needs_input_grad: SmallVec<bool>,
+    // `Op::num_outputs` of the op
+    num_outputs: usize,
 }
 
 impl<'graph, T: Float> GradientContext<'graph, T> {
     #[inline]
     pub(crate) fn new(
-        gy: Tensor<'graph, T>,
+        gys: SmallVec<Option<Tensor<'graph, T>>>,
         y: Tensor<'graph, T>,
         graph: &'graph crate::graph::Graph<T>,
+        needs_input_grad: SmallVec<bool>,
     ) -> Self {
+        let gy = match gys.first() {
+            Some(&Some(gy)) => gy,
+            // only the other outputs were used
+            _ => crate::tensor_ops::zeros(&crate::tensor_ops::shape(y), graph),
+        };
         GradientContext {
             gy,
+            gys,
             y,
             graph,
             gxs: SmallVec::new(),
+            needs_input_grad,
+            num_outputs: 1,
         }
     }
 
@@ -366,6 +672,7 @@ impl<'graph, T: Float> GradientContext<'graph, T> {
         let id = self.y.id;
         // steal op
         let stolen = self.graph().access_inner_mut(id).op.take().unwrap();
+        self.num_outputs = stolen.num_outputs();
 
         // call Op::grad
         stolen.grad(&mut self);
@@ -379,18 +686,59 @@ impl<'graph, T: Float> GradientContext<'graph, T> {
         self.gxs
     }
 
+    /// Returns false if the gradient of the `i` th input is dropped, e.g. for a constant.
+    ///
+    /// Such inputs can get `None` from [GradientContext::append_input_grad] instead of a
+    /// gradient that is built but never used.
+    #[inline]
+    pub fn needs_input_grad(&self, i: usize) -> bool {
+        self.needs_input_grad.get(i).cloned().unwrap_or(true)
+    }
+
     /// Returns the gradient of the op's output.
+    ///
+    /// For a multi-output op, this is the gradient of the first output.
     #[inline]
     pub fn output_grad(&self) -> Tensor<'graph, T> {
         self.gy
     }
 
+    /// Returns the gradient of the `i` th output of a multi-output op.
+    ///
+    /// It's zeros if the `i` th output doesn't lead to the differentiated tensors.
+    /// See [nth_tensor](crate::tensor_ops::nth_tensor).
+    pub fn output_grad_at(&self, i: usize) -> Tensor<'graph, T> {
+        if i == 0 {
+            return self.gy;
+        }
+        match self.gys.get(i) {
+            Some(&Some(gy)) => gy,
+            _ => crate::tensor_ops::zeros(&crate::tensor_ops::shape(self.output_at(i)), self.graph),
+        }
+    }
+
     /// Grabs the output of the op.
     #[inline]
     pub fn output(&self) -> Tensor<'graph, T> {
         self.y
     }
 
+    /// Grabs the `i` th output of a multi-output op.
+    #[inline]
+    pub fn output_at(&self, i: usize) -> Tensor<'graph, T> {
+        if i == 0 {
+            self.y
+        } else {
+            crate::tensor_ops::nth_tensor(self.y, i)
+        }
+    }
+
+    /// Grabs the `i` th array kept by [ComputeContext::stash] in the forward pass.
+    #[inline]
+    pub fn stashed(&self, i: usize) -> Tensor<'graph, T> {
+        self.output_at(self.num_outputs + i)
+    }
+
     /// Returns input tensors.
     #[inline]
     pub fn inputs(&self) -> SmallVec<Tensor<'graph, T>> {
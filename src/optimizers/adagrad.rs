@@ -1,6 +1,6 @@
 //! Adagrad optimizer
 
-use crate::optimizers::Optimizer;
+use crate::optimizers::{LrMultipliers, Optimizer};
 use crate::tensor::Tensor;
 use crate::tensor_ops::gradient_descent_ops::adagrad;
 use crate::variable::VariableID;
@@ -13,6 +13,8 @@ pub struct AdaGrad<F: Float> {
     /// default: 0.01
     pub lr: F,
     pub adagrad_namespace_id: &'static str,
+    /// Factors of `lr` for some variables, empty by default
+    pub lr_multipliers: LrMultipliers<F>,
 }
 
 impl<'t, 'g, F: Float> AdaGrad<F> {
@@ -37,19 +39,17 @@ impl<'t, 'g, F: Float> AdaGrad<F> {
     ) -> AdaGrad<F> {
         for vid in var_id_list.into_iter() {
             let h = {
-                let target_var = env
-                    .get_array_by_id(vid)
-                    .expect("variable array not found")
-                    .borrow();
-                let var_shape = target_var.shape();
+                let var_shape = &env.variable_shape(vid);
                 crate::ndarray_ext::zeros(var_shape)
             };
             let mut ns = env.namespace_mut(adagrad_namespace_id);
             ns.slot().name(format!("{}", vid)).set(h);
         }
+        env.add_state_namespace(adagrad_namespace_id);
         AdaGrad {
             lr,
             adagrad_namespace_id,
+            lr_multipliers: LrMultipliers::new(),
         }
     }
 }
@@ -79,9 +79,15 @@ impl<F: Float> Optimizer<F> for AdaGrad<F> {
                     .append_input(param, true)
                     .append_input(grads[i].as_ref(), false)
                     .append_input(&h, true)
-                    .build(adagrad::AdaGradOp { lr: self.lr }),
+                    .build(adagrad::AdaGradOp {
+                        lr: self.lr * self.lr_multiplier(param, g),
+                    }),
             );
         }
         ret
     }
+
+    fn lr_multipliers(&self) -> Option<&LrMultipliers<F>> {
+        Some(&self.lr_multipliers)
+    }
 }
@@ -1,6 +1,6 @@
 //! Adam optimizer
 
-use crate::optimizers::Optimizer;
+use crate::optimizers::{LrMultipliers, Optimizer};
 use crate::tensor::Tensor;
 use crate::tensor_ops::gradient_descent_ops::adam;
 use crate::variable::VariableID;
@@ -51,6 +51,8 @@ pub struct Adam<F: Float> {
     pub b1: F,
     pub b2: F,
     pub adam_namespace_id: &'static str,
+    /// Factors of `alpha` for some variables, empty by default
+    pub lr_multipliers: LrMultipliers<F>,
 }
 
 impl<'t, 'g, F: Float> Adam<F> {
@@ -86,11 +88,7 @@ impl<'t, 'g, F: Float> Adam<F> {
             let v_name = format!("{}v", vid);
             let t_name = format!("{}t", vid);
             let (m, v, t) = {
-                let target_var = env
-                    .get_array_by_id(vid)
-                    .expect("variable array not found")
-                    .borrow();
-                let var_shape = target_var.shape();
+                let var_shape = &env.variable_shape(vid);
                 (
                     crate::ndarray_ext::zeros(var_shape),
                     crate::ndarray_ext::zeros(var_shape),
@@ -102,12 +100,14 @@ impl<'t, 'g, F: Float> Adam<F> {
             adam_ns.slot().name(v_name).set(v);
             adam_ns.slot().name(t_name).set(t);
         }
+        env.add_state_namespace(adam_namespace_id);
         Adam {
             alpha,
             eps,
             b1,
             b2,
             adam_namespace_id,
+            lr_multipliers: LrMultipliers::new(),
         }
     }
 }
@@ -142,7 +142,7 @@ impl<F: Float> Optimizer<F> for Adam<F> {
                     .append_input(&v, true)
                     .append_input(&t, true)
                     .build(adam::AdamOp {
-                        alpha: self.alpha,
+                        alpha: self.alpha * self.lr_multiplier(param, g),
                         eps: self.eps,
                         b1: self.b1,
                         b2: self.b2,
@@ -151,4 +151,8 @@ impl<F: Float> Optimizer<F> for Adam<F> {
         }
         ret
     }
+
+    fn lr_multipliers(&self) -> Option<&LrMultipliers<F>> {
+        Some(&self.lr_multipliers)
+    }
 }
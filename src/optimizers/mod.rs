@@ -2,17 +2,22 @@
 pub mod adagrad;
 pub mod adam;
 pub mod momentum_sgd;
+pub mod multi_task;
 pub mod sgd;
+pub mod train_step;
 
 use crate::evaluation::Feeder;
 
 use crate::tensor::Tensor;
 use crate::variable::VariableNamespace;
-use crate::{Context, Float};
+use crate::{Context, EvalError, Float, NdArray};
+use std::collections::HashMap;
 pub use adagrad::AdaGrad;
 pub use adam::Adam;
 pub use momentum_sgd::MomentumSGD;
+pub use multi_task::MultiTask;
 pub use sgd::SGD;
+pub use train_step::TrainStep;
 
 /// Differentiates `losses` with all the relevant variables in the `namespace`
 ///
@@ -45,6 +50,81 @@ where
     (vars, grads)
 }
 
+/// Evaluates the gradients of `loss` with `xs`, without the value of `loss`.
+///
+/// Same as evaluating [grad](crate::tensor_ops::grad)`(&[loss], xs)` with `feeder`. Since
+/// `loss` isn't a target of the evaluation, it isn't copied out, and it's freed along with
+/// the other activations of the forward pass as soon as the backward pass is done with them.
+/// For training loops that don't log the loss at every step.
+///
+/// Returns the gradients in the order of `xs`, or the first error raised.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::grad_only;
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::new();
+/// let w = env.set(ag::ndarray::arr1(&[1., -2.]));
+///
+/// env.run(|g| {
+///     let x = g.placeholder("x", &[2]);
+///     let loss = T::sum_all(T::square(x * g.variable(w)));
+///
+///     let x_value = ag::ndarray::arr1(&[3., 1.]);
+///     let mut feeder = ag::Feeder::new();
+///     feeder.push(x, x_value.view());
+///     let gw = grad_only(loss, &[g.variable(w)], feeder, g).unwrap();
+///     // 2 * x^2 * w
+///     assert_eq!(gw[0], ag::ndarray::arr1(&[18., -4.]).into_dyn());
+/// });
+/// ```
+pub fn grad_only<'g, A, B, F: Float>(
+    loss: A,
+    xs: &[B],
+    feeder: Feeder<F>,
+    ctx: &'g Context<F>,
+) -> Result<Vec<NdArray<F>>, EvalError>
+where
+    A: AsRef<Tensor<'g, F>> + Copy,
+    B: AsRef<Tensor<'g, F>> + Copy,
+{
+    let grads = crate::tensor_ops::grad(&[loss], xs);
+    let mut evaluator = ctx.evaluator();
+    evaluator.extend(&grads).set_feeder(feeder);
+    evaluator.run().into_iter().collect()
+}
+
+/// Factors of the learning rate of some variables, by variable name.
+///
+/// A variable is updated with `base_lr * multipliers[name]`, where `name` is the name it was
+/// registered with in its namespace; the variables not listed, or without a name, use
+/// `base_lr`. E.g. a pretrained backbone can be fine-tuned slower than a new head:
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::{Optimizer, SGD};
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// env.slot().name("backbone").set(ag::ndarray::arr1(&[1.]));
+/// env.slot().name("head").set(ag::ndarray::arr1(&[1.]));
+/// let mut sgd = SGD::new(0.1);
+/// sgd.lr_multipliers.insert("backbone".to_string(), 0.1);
+///
+/// env.run(|g| {
+///     let (backbone, head) = (g.variable("backbone"), g.variable("head"));
+///     let loss = T::sum_all(backbone + head);
+///     let grads = T::grad(&[loss], &[backbone, head]);
+///     sgd.update(&[backbone, head], &grads, g, ag::Feeder::new());
+///     assert!((backbone.eval(g).unwrap()[0] - 0.99).abs() < 1e-12);
+///     assert!((head.eval(g).unwrap()[0] - 0.9).abs() < 1e-12);
+/// });
+/// ```
+pub type LrMultipliers<F> = HashMap<String, F>;
+
 /// Trait for gradient descent optimizers
 pub trait Optimizer<F: Float> {
     /// Creates dummy tensors to update `variables`
@@ -60,9 +140,31 @@ pub trait Optimizer<F: Float> {
         A: AsRef<Tensor<'g, F>> + Copy,
         B: AsRef<Tensor<'g, F>> + Copy;
 
+    /// Returns the learning rate multipliers of this optimizer, if it supports them.
+    ///
+    /// The optimizers of this crate do; see [LrMultipliers].
+    fn lr_multipliers(&self) -> Option<&LrMultipliers<F>> {
+        None
+    }
+
+    /// Returns the factor of the learning rate for `variable`: its entry in
+    /// [Optimizer::lr_multipliers], or 1.
+    fn lr_multiplier<'g>(&self, variable: &Tensor<'g, F>, g: &'g Context<F>) -> F {
+        let multipliers = match self.lr_multipliers() {
+            Some(m) if !m.is_empty() => m,
+            _ => return F::one(),
+        };
+        variable
+            .get_variable_id()
+            .and_then(|vid| g.env().variable_name(vid))
+            .and_then(|name| multipliers.get(name))
+            .cloned()
+            .unwrap_or_else(F::one)
+    }
+
     /// Runs the graph and updates the variable arrays.
     ///
-    /// Updates `variables` destructively.
+    /// Updates `variables` destructively, and increments [Context::global_step].
     fn update<'g, A, B>(&self, variables: &[A], grads: &[B], g: &'g Context<F>, feeder: Feeder<F>)
     where
         A: AsRef<Tensor<'g, F>> + Copy,
@@ -79,6 +181,7 @@ pub trait Optimizer<F: Float> {
             .for_each(|r| {
                 r.unwrap();
             });
+        g.increment_global_step();
     }
 
     /// Returns a tensor to update the given parameters
@@ -1,6 +1,6 @@
 //! Momentum SGD optimizer
 
-use crate::optimizers::Optimizer;
+use crate::optimizers::{LrMultipliers, Optimizer};
 use crate::tensor::Tensor;
 use crate::tensor_ops::gradient_descent_ops::sgd;
 use crate::variable::VariableID;
@@ -35,6 +35,8 @@ pub struct MomentumSGD<F> {
     pub alpha: F,
     pub momentum: F,
     pub momentum_sgd_namespace_id: &'static str,
+    /// Factors of `alpha` for some variables, empty by default
+    pub lr_multipliers: LrMultipliers<F>,
 }
 
 impl<'t, 'g, F: Float> MomentumSGD<F> {
@@ -64,20 +66,18 @@ impl<'t, 'g, F: Float> MomentumSGD<F> {
         for vid in var_id_list.into_iter() {
             let v_name = format!("{}", vid);
             let v = {
-                let target_var = env
-                    .get_array_by_id(vid)
-                    .expect("variable array not found")
-                    .borrow();
-                let var_shape = target_var.shape();
+                let var_shape = &env.variable_shape(vid);
                 crate::ndarray_ext::zeros(var_shape)
             };
             let mut ns = env.namespace_mut(momentum_sgd_namespace_id);
             ns.slot().name(v_name).set(v);
         }
+        env.add_state_namespace(momentum_sgd_namespace_id);
         MomentumSGD {
             alpha,
             momentum,
             momentum_sgd_namespace_id,
+            lr_multipliers: LrMultipliers::new(),
         }
     }
 }
@@ -108,11 +108,15 @@ impl<F: Float> Optimizer<F> for MomentumSGD<F> {
                     .append_input(grads[i].as_ref(), false)
                     .append_input(&v, true)
                     .build(sgd::MomentumSGDOp {
-                        lr: self.alpha,
+                        lr: self.alpha * self.lr_multiplier(param, g),
                         momentum: self.momentum,
                     }),
             );
         }
         ret
     }
+
+    fn lr_multipliers(&self) -> Option<&LrMultipliers<F>> {
+        Some(&self.lr_multipliers)
+    }
 }
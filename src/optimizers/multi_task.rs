@@ -0,0 +1,222 @@
+//! Training step of several losses sharing a trunk
+
+use crate::evaluation::{Feeder, OpOutputStorage};
+use crate::graph::TensorID;
+use crate::optimizers::Optimizer;
+use crate::tensor::Tensor;
+use crate::tensor_ops as T;
+use crate::tensor_ops::graph_ops::ControlDependency;
+use crate::{Context, EvalError, Float, NdArray};
+
+// `Optimizer::compute_updates` of one optimizer
+type ComputeUpdates<'g, F> =
+    Box<dyn Fn(&[Tensor<'g, F>], &[Tensor<'g, F>]) -> Vec<Tensor<'g, F>> + 'g>;
+
+// Variables updated together by one optimizer
+struct Group<'g, F: Float> {
+    variables: Vec<Tensor<'g, F>>,
+    compute_updates: ComputeUpdates<'g, F>,
+}
+
+impl<'g, F: Float> Group<'g, F> {
+    fn new<O, A>(optimizer: &'g O, variables: &[A], ctx: &'g Context<F>) -> Self
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        Group {
+            variables: variables.iter().map(|v| *v.as_ref()).collect(),
+            compute_updates: Box::new(move |vs, gs| optimizer.compute_updates(vs, gs, ctx)),
+        }
+    }
+}
+
+// The ops run by each step, built by the first one
+struct Built<'g, F: Float> {
+    // The updates, followed by the losses
+    targets: Vec<Tensor<'g, F>>,
+    order: Vec<TensorID>,
+    // `Graph::cache_version` when `order` was computed
+    cache_version: usize,
+    storage: OpOutputStorage<F>,
+}
+
+/// Training step of several losses ("heads") computed from shared variables (the "trunk"),
+/// each head with its own optimizer and variables.
+///
+/// The variables of a head are updated with the gradient of its loss only, and the trunk
+/// with the gradient of the sum of all the losses. A [MultiTask::step] runs the forward pass
+/// of all the heads, their backward passes and all the updates in a single evaluation, so the
+/// trunk's activations are computed once. Every gradient is computed before any variable is
+/// updated.
+///
+/// The ops are built by the first step, and reused by the following ones as in
+/// [TrainStep](crate::optimizers::TrainStep).
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::{MultiTask, Adam, SGD};
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// let trunk = env.set(ag::ndarray::arr2(&[[1., 0.], [0., 1.]]));
+/// let head_a = env.set(ag::ndarray::arr2(&[[1.], [1.]]));
+/// let head_b = env.set(ag::ndarray::arr2(&[[1.], [-1.]]));
+/// let adam = Adam::default("multi_task_adam", vec![head_b], &mut env);
+/// let sgd = SGD::new(0.05);
+///
+/// env.run(|g| {
+///     let x = g.placeholder("x", &[-1, 2]);
+///     let h = T::relu(T::matmul(x, g.variable(trunk)));
+///     let loss_a = T::mean_all(T::square(T::matmul(h, g.variable(head_a)) - 1.));
+///     let loss_b = T::mean_all(T::square(T::matmul(h, g.variable(head_b)) + 1.));
+///
+///     let mut task = MultiTask::new(&sgd, &[g.variable(trunk)], g);
+///     task.add_head(&sgd, loss_a, &[g.variable(head_a)])
+///         .add_head(&adam, loss_b, &[g.variable(head_b)]);
+///
+///     let batch = ag::ndarray::arr2(&[[1., 2.], [0.5, -1.]]);
+///     let feeder = || {
+///         let mut feeder = ag::Feeder::new();
+///         feeder.push(x, batch.view());
+///         feeder
+///     };
+///     let first = task.step(feeder()).unwrap();
+///     for _ in 0..50 {
+///         task.step(feeder()).unwrap();
+///     }
+///     let last = task.step(feeder()).unwrap();
+///     assert!(last[0][[]] < first[0][[]] && last[1][[]] < first[1][[]]);
+/// });
+/// ```
+pub struct MultiTask<'g, F: Float> {
+    ctx: &'g Context<'g, F>,
+    trunk: Group<'g, F>,
+    // The loss of each head, and its variables
+    heads: Vec<(Tensor<'g, F>, Group<'g, F>)>,
+    built: Option<Built<'g, F>>,
+}
+
+impl<'g, F: Float> MultiTask<'g, F> {
+    /// Creates a training step whose shared `trunk` variables are updated by `optimizer`.
+    ///
+    /// Add the heads with [MultiTask::add_head].
+    pub fn new<O, A>(optimizer: &'g O, trunk: &[A], ctx: &'g Context<'g, F>) -> Self
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        MultiTask {
+            ctx,
+            trunk: Group::new(optimizer, trunk, ctx),
+            heads: Vec::new(),
+            built: None,
+        }
+    }
+
+    /// Adds a head: `loss`, whose gradient updates `variables` with `optimizer`, and the trunk.
+    ///
+    /// A non-scalar `loss` is summed up. `variables` should not be used by the other heads,
+    /// nor be part of the trunk. Adding a head after a step builds the ops again.
+    pub fn add_head<O, A>(
+        &mut self,
+        optimizer: &'g O,
+        loss: Tensor<'g, F>,
+        variables: &[A],
+    ) -> &mut Self
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        self.heads
+            .push((loss, Group::new(optimizer, variables, self.ctx)));
+        self.built = None;
+        self
+    }
+
+    /// Returns the losses of the heads, in the order they were added.
+    pub fn losses(&self) -> Vec<Tensor<'g, F>> {
+        self.heads.iter().map(|(loss, _)| *loss).collect()
+    }
+
+    fn build(&self) -> Built<'g, F> {
+        let losses: Vec<_> = self
+            .heads
+            .iter()
+            .map(|(loss, _)| T::sum_all(loss))
+            .collect();
+        let mut grads: Vec<Vec<Tensor<'g, F>>> = self
+            .heads
+            .iter()
+            .zip(&losses)
+            .map(|((_, head), &loss)| T::grad(&[loss], &head.variables))
+            .collect();
+        grads.push(T::grad(&[T::add_n(&losses)], &self.trunk.variables));
+
+        // No update may run before all the gradients and losses are computed, since they
+        // read the variables.
+        let mut deps: Vec<_> = grads.iter().flatten().cloned().collect();
+        deps.extend(&losses);
+        let mut targets = Vec::new();
+        let groups = self.heads.iter().map(|(_, group)| group);
+        for (group, grads) in groups.chain(Some(&self.trunk)).zip(grads) {
+            let gated: Vec<_> = grads
+                .iter()
+                .map(|g| {
+                    let mut b = Tensor::builder(self.ctx).append_input(g, false);
+                    for dep in &deps {
+                        b = b.append_input(dep, false);
+                    }
+                    b.build(ControlDependency)
+                })
+                .collect();
+            targets.extend((group.compute_updates)(&group.variables, &gated));
+        }
+        // The last targets are scheduled first
+        targets.extend(losses);
+        Built {
+            order: self.ctx.schedule(&targets),
+            cache_version: self.ctx.cache_version.get(),
+            targets,
+            storage: OpOutputStorage::new(),
+        }
+    }
+
+    /// Runs one step with `feeder`: evaluates the losses and updates the variables.
+    ///
+    /// Returns the values of the losses before the update, in the order the heads were added,
+    /// or the first error raised. [Context::global_step] is incremented if the update
+    /// succeeded.
+    ///
+    /// Panics if no head was added.
+    pub fn step(&mut self, feeder: Feeder<F>) -> Result<Vec<NdArray<F>>, EvalError> {
+        assert!(!self.heads.is_empty(), "MultiTask::step: no head was added");
+        if self.built.is_none() {
+            self.built = Some(self.build());
+        }
+        let graph = &self.ctx.graph;
+        let built = self.built.as_mut().unwrap();
+        let cache_version = graph.cache_version.get();
+        if cache_version != built.cache_version {
+            // cached values cut off parts of the graph
+            built.order = graph.schedule(&built.targets);
+            built.cache_version = cache_version;
+        }
+        let mut results = graph.eval_in_order(
+            &built.targets,
+            &built.order,
+            &mut built.storage,
+            &feeder.feeds,
+            self.ctx.var_env_ref,
+            false,
+        );
+        let losses = results.split_off(results.len() - self.heads.len());
+        for update in results {
+            update?;
+        }
+        let losses = losses.into_iter().collect::<Result<Vec<_>, _>>()?;
+        self.ctx.increment_global_step();
+        Ok(losses)
+    }
+}
@@ -1,6 +1,6 @@
 //! SGD optimizer
 
-use crate::optimizers::Optimizer;
+use crate::optimizers::{LrMultipliers, Optimizer};
 use crate::tensor::Tensor;
 use crate::tensor_ops::gradient_descent_ops::sgd;
 
@@ -30,11 +30,16 @@ use crate::{Context, Float};
 /// ```
 pub struct SGD<F> {
     pub alpha: F,
+    /// Factors of `alpha` for some variables, empty by default
+    pub lr_multipliers: LrMultipliers<F>,
 }
 
 impl<'t, 'g, F: Float> SGD<F> {
     pub fn new(alpha: F) -> SGD<F> {
-        SGD { alpha }
+        SGD {
+            alpha,
+            lr_multipliers: LrMultipliers::new(),
+        }
     }
 }
 
@@ -53,13 +58,18 @@ impl<F: Float> Optimizer<F> for SGD<F> {
         assert_eq!(num_params, grads.len());
         let mut ret = Vec::with_capacity(num_params);
         for i in 0..num_params {
+            let alpha = self.alpha * self.lr_multiplier(params[i].as_ref(), ctx);
             ret.push(
                 Tensor::builder(ctx)
                     .append_input(params[i].as_ref(), true)
                     .append_input(grads[i].as_ref(), false)
-                    .build(sgd::SGDOp { alpha: self.alpha }),
+                    .build(sgd::SGDOp { alpha }),
             );
         }
         ret
     }
+
+    fn lr_multipliers(&self) -> Option<&LrMultipliers<F>> {
+        Some(&self.lr_multipliers)
+    }
 }
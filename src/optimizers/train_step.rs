@@ -0,0 +1,115 @@
+//! Training step reusing its graph across iterations
+
+use crate::evaluation::{Feeder, OpOutputStorage};
+use crate::graph::TensorID;
+use crate::optimizers::Optimizer;
+use crate::tensor::Tensor;
+use crate::tensor_ops as T;
+use crate::{Context, EvalError, Float, Graph, NdArray, VariableEnvironment};
+
+/// Forward pass, backward pass and parameter update of a loss, built once and run every step.
+///
+/// Rebuilding the graph in each iteration of a training loop costs the creation of all its
+/// nodes, and the scheduling of their execution. A `TrainStep` creates the gradients and the
+/// update ops once, computes their execution order once, and then each [TrainStep::step]
+/// only swaps the feeds and runs that order, reusing the storage of the intermediate outputs.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::{TrainStep, SGD};
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// let w = env.set(ag::ndarray::arr1(&[0.]).into_dyn());
+/// let sgd = SGD::new(0.1);
+///
+/// env.run(|g| {
+///    let x = g.placeholder("x", &[-1]);
+///    let w = g.variable(w);
+///    let loss = T::reduce_mean(T::square(x - w), &[0], false);
+///    let mut train = TrainStep::new(&sgd, loss, &[w], g);
+///
+///    let batches = [ag::ndarray::arr1(&[1., 3.]), ag::ndarray::arr1(&[2., 2.])];
+///    for batch in batches.iter().cycle().take(100) {
+///        let mut feeder = ag::Feeder::new();
+///        feeder.push(x, batch.view());
+///        train.step(feeder).unwrap();
+///    }
+///    assert!((w.eval(g).unwrap()[0] - 2.).abs() < 1e-6);
+/// });
+/// ```
+pub struct TrainStep<'g, F: Float> {
+    graph: &'g Graph<F>,
+    env: &'g VariableEnvironment<F>,
+    // The loss, followed by the update ops
+    targets: Vec<Tensor<'g, F>>,
+    order: Vec<TensorID>,
+    // `Graph::cache_version` when `order` was computed
+    cache_version: usize,
+    storage: OpOutputStorage<F>,
+}
+
+impl<'g, F: Float> TrainStep<'g, F> {
+    /// Builds the gradients of `loss` with respect to `variables`, and their updates by `optimizer`.
+    ///
+    /// A non-scalar `loss` is summed up. The state of `optimizer` lives in the variable
+    /// environment, so it needn't outlive this step.
+    pub fn new<O, A>(
+        optimizer: &O,
+        loss: Tensor<'g, F>,
+        variables: &[A],
+        ctx: &'g Context<F>,
+    ) -> Self
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let grads = T::grad(&[T::sum_all(loss)], variables);
+        let mut targets = optimizer.compute_updates(variables, &grads, ctx);
+        // The last target is scheduled first, so the loss is computed before any update.
+        targets.push(loss);
+        let graph: &'g Graph<F> = ctx;
+        TrainStep {
+            graph,
+            env: ctx.var_env_ref,
+            order: graph.schedule(&targets),
+            cache_version: graph.cache_version.get(),
+            targets,
+            storage: OpOutputStorage::new(),
+        }
+    }
+
+    /// Returns the loss tensor.
+    pub fn loss(&self) -> Tensor<'g, F> {
+        self.targets[self.targets.len() - 1]
+    }
+
+    /// Runs one step with `feeder`: evaluates the loss and updates the variables.
+    ///
+    /// Returns the value of the loss before the update, or the first error raised.
+    /// [Context::global_step] is incremented if the update succeeded.
+    pub fn step(&mut self, feeder: Feeder<F>) -> Result<NdArray<F>, EvalError> {
+        let cache_version = self.graph.cache_version.get();
+        if cache_version != self.cache_version {
+            // cached values cut off parts of the graph
+            self.order = self.graph.schedule(&self.targets);
+            self.cache_version = cache_version;
+        }
+        let mut results = self.graph.eval_in_order(
+            &self.targets,
+            &self.order,
+            &mut self.storage,
+            &feeder.feeds,
+            self.env,
+            false,
+        );
+        let loss = results.pop().unwrap();
+        for update in results {
+            update?;
+        }
+        let step = self.env.global_step.get();
+        self.env.global_step.set(step + 1);
+        loss
+    }
+}
@@ -0,0 +1,476 @@
+//! Graphs built from a textual spec
+//!
+//! A spec describes a graph one node per line, each one named and built by an op from the
+//! nodes above it:
+//!
+//! ```text
+//! # a two-layer perceptron
+//! x = placeholder(shape=[-1, 4])
+//! w1 = variable()
+//! w2 = variable()
+//! a = matmul(x, w1)
+//! h = relu(a)
+//! logits = matmul(h, w2)
+//! ```
+//!
+//! A line reads `name = op(inputs..., key=value...)`, where the inputs are the names of
+//! earlier nodes and the parameters are integers, floats, `true`/`false`, `"strings"` or
+//! lists of integers such as `[-1, 4]`. Calls don't nest, and a name can't be defined twice.
+//! `#` starts a comment. The ops are:
+//!
+//! * sources: `placeholder(shape, name=<node name>)`, `variable(name=<node name>,
+//!   namespace="")` looking up a registered variable, `scalar(value)`, `zeros(shape)`,
+//!   `ones(shape)`
+//! * element-wise: `neg`, `abs`, `square`, `sqrt`, `exp`, `ln`, `relu`, `sigmoid`, `tanh`,
+//!   `softplus`, `elu(alpha=1.0)`, `leaky_relu(alpha=0.01)`, `add`, `sub`, `mul`, `div`,
+//!   `maximum`, `minimum`
+//! * `matmul`, `reshape(shape)`, `transpose(axes)`, `softmax(axis=-1)`,
+//!   `log_softmax(axis=-1)`
+//! * reductions: `sum_all`, `mean_all`, `reduce_sum(axes, keep_dims=false)`, `reduce_mean`,
+//!   `reduce_max`
+//! * `conv2d(x, w, pad=0, stride=1)`, `max_pool2d(x, size, pad=0, stride=1)`,
+//!   `conv1d(x, w, pad=0, stride=1)`, `max_pool1d(x, size, pad=0, stride=1)`,
+//!   `avg_pool1d(x, size, pad=0, stride=1)`
+//! * losses: `sigmoid_cross_entropy`, `softmax_cross_entropy`,
+//!   `sparse_softmax_cross_entropy`
+//!
+//! ```
+//! use autograd as ag;
+//! use ag::prelude::*;
+//!
+//! let mut env = ag::VariableEnvironment::new();
+//! env.slot().name("w").set(ag::ndarray::arr2(&[[1., 2.], [3., 4.]]));
+//!
+//! env.run(|g| {
+//!     let nodes = ag::spec::from_spec(
+//!         "x = placeholder(shape=[-1, 2])
+//!          w = variable()
+//!          y = matmul(x, w)   # x w
+//!          loss = sum_all(y)",
+//!         g,
+//!     )
+//!     .unwrap();
+//!     let x = ag::ndarray::arr2(&[[1., 1.]]).into_dyn();
+//!     let loss = g.evaluator().push(nodes["loss"]).feed("x", x.view()).run();
+//!     assert_eq!(loss[0], Ok(ag::ndarray::arr0(10.).into_dyn()));
+//! });
+//! ```
+use crate::op::OpParam;
+use crate::tensor::Tensor;
+use crate::tensor_ops as T;
+use crate::variable::FullName;
+use crate::{Context, Float, FxHashSet};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Error in a spec given to [from_spec].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecError {
+    /// Line of the error, from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::error::Error for SpecError {}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Builds the graph described by `spec` in `ctx`; see [the module docs](self) for the syntax.
+///
+/// Returns the nodes by name, or the first error of the spec: a syntax error, an unknown op,
+/// input or parameter, or a variable not registered in the environment of `ctx`. The ops
+/// check their inputs as usual, so a wrong shape panics or fails the evaluation as if the
+/// graph was built by hand.
+pub fn from_spec<'g, F: Float>(
+    spec: &str,
+    ctx: &'g Context<F>,
+) -> Result<HashMap<String, Tensor<'g, F>>, SpecError> {
+    let mut nodes = HashMap::new();
+    for (i, line) in spec.lines().enumerate() {
+        let error = |message| SpecError {
+            line: i + 1,
+            message,
+        };
+        let tokens = tokenize(line).map_err(error)?;
+        if tokens.is_empty() {
+            continue;
+        }
+        let mut call = parse(&tokens).map_err(error)?;
+        if nodes.contains_key(call.name) {
+            return Err(error(format!("`{}` is already defined", call.name)));
+        }
+        let node = build(&mut call, ctx, &nodes).map_err(error)?;
+        if let Some(key) = call.params.keys().next() {
+            return Err(error(format!("unknown parameter `{}` of {}", key, call.op)));
+        }
+        nodes.insert(call.name.to_string(), node);
+    }
+    Ok(nodes)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token<'s> {
+    Ident(&'s str),
+    Number(&'s str),
+    Str(&'s str),
+    Punct(char),
+}
+
+fn tokenize(line: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c == '#' {
+            break;
+        } else if c.is_whitespace() {
+            chars.next();
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(&line[start..end]));
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            chars.next();
+            let mut end = start + 1;
+            let mut prev = c;
+            while let Some(&(i, c)) = chars.peek() {
+                // the sign of an exponent
+                let exp_sign = (c == '-' || c == '+') && (prev == 'e' || prev == 'E');
+                if !(c.is_ascii_alphanumeric() || c == '.' || exp_sign) {
+                    break;
+                }
+                end = i + 1;
+                prev = c;
+                chars.next();
+            }
+            tokens.push(Token::Number(&line[start..end]));
+        } else if c == '"' {
+            chars.next();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i,
+                    Some(_) => {}
+                    None => return Err("unterminated string".to_string()),
+                }
+            };
+            tokens.push(Token::Str(&line[start + 1..end]));
+        } else if "=(),[]".contains(c) {
+            chars.next();
+            tokens.push(Token::Punct(c));
+        } else {
+            return Err(format!("unexpected character `{}`", c));
+        }
+    }
+    Ok(tokens)
+}
+
+// A line of a spec: `name = op(inputs..., params...)`
+struct Call<'s> {
+    name: &'s str,
+    op: &'s str,
+    inputs: Vec<&'s str>,
+    // Removed as they're read by `build`, which leaves the unknown ones
+    params: HashMap<&'s str, OpParam>,
+}
+
+fn parse<'s>(tokens: &[Token<'s>]) -> Result<Call<'s>, String> {
+    let mut rest = tokens;
+    let mut next = || -> Result<&Token<'s>, String> {
+        let (first, tail) = rest.split_first().ok_or("unexpected end of line")?;
+        rest = tail;
+        Ok(first)
+    };
+    let ident = |t: &Token<'s>, what: &str| match *t {
+        Token::Ident(s) => Ok(s),
+        ref t => Err(format!("expected {}, found {:?}", what, t)),
+    };
+    let punct = |t: &Token<'s>, c: char| match *t {
+        Token::Punct(d) if d == c => Ok(()),
+        ref t => Err(format!("expected `{}`, found {:?}", c, t)),
+    };
+
+    let name = ident(next()?, "a node name")?;
+    punct(next()?, '=')?;
+    let op = ident(next()?, "an op")?;
+    punct(next()?, '(')?;
+    let mut call = Call {
+        name,
+        op,
+        inputs: Vec::new(),
+        params: HashMap::new(),
+    };
+    if tokens.len() > 4 && tokens[4] == Token::Punct(')') {
+        next()?;
+    } else {
+        loop {
+            let arg = ident(next()?, "an input or a parameter")?;
+            let t = next()?;
+            let t = if *t == Token::Punct('=') {
+                let value = match next()? {
+                    Token::Number(s) => parse_number(s)?,
+                    Token::Str(s) => OpParam::Str(s.to_string()),
+                    Token::Ident("true") => OpParam::Bool(true),
+                    Token::Ident("false") => OpParam::Bool(false),
+                    Token::Punct('[') => {
+                        let mut list = Vec::new();
+                        let mut t = next()?;
+                        while *t != Token::Punct(']') {
+                            match parse_number(number(t)?)? {
+                                OpParam::Int(a) => list.push(a),
+                                _ => return Err("expected an integer in a list".to_string()),
+                            }
+                            t = next()?;
+                            if *t == Token::Punct(',') {
+                                t = next()?;
+                            }
+                        }
+                        OpParam::Ints(list)
+                    }
+                    t => return Err(format!("expected a value, found {:?}", t)),
+                };
+                if call.params.insert(arg, value).is_some() {
+                    return Err(format!("parameter `{}` is given twice", arg));
+                }
+                next()?
+            } else {
+                call.inputs.push(arg);
+                t
+            };
+            match *t {
+                Token::Punct(',') => {}
+                Token::Punct(')') => break,
+                ref t => return Err(format!("expected `,` or `)`, found {:?}", t)),
+            }
+        }
+    }
+    if let Some(t) = rest.first() {
+        return Err(format!("unexpected {:?} after `)`", t));
+    }
+    Ok(call)
+}
+
+fn number<'s>(t: &Token<'s>) -> Result<&'s str, String> {
+    match *t {
+        Token::Number(s) => Ok(s),
+        ref t => Err(format!("expected a number, found {:?}", t)),
+    }
+}
+
+fn parse_number(s: &str) -> Result<OpParam, String> {
+    if let Ok(a) = s.parse::<i64>() {
+        Ok(OpParam::Int(a))
+    } else if let Ok(a) = s.parse::<f64>() {
+        Ok(OpParam::Float(a))
+    } else {
+        Err(format!("invalid number `{}`", s))
+    }
+}
+
+// Placeholder names have to be `'static`: each distinct name read from a spec is leaked once.
+pub(crate) fn intern(name: &str) -> &'static str {
+    thread_local! {
+        static NAMES: RefCell<FxHashSet<&'static str>> = RefCell::new(FxHashSet::default());
+    }
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        match names.get(name) {
+            Some(&interned) => interned,
+            None => {
+                let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+                names.insert(interned);
+                interned
+            }
+        }
+    })
+}
+
+impl<'s> Call<'s> {
+    fn param(&mut self, key: &str) -> Option<OpParam> {
+        self.params.remove(key)
+    }
+
+    fn missing(&self, key: &str) -> String {
+        format!("missing parameter `{}` of {}", key, self.op)
+    }
+
+    fn wrong_type(&self, key: &str, expected: &str) -> String {
+        format!("parameter `{}` of {} should be {}", key, self.op, expected)
+    }
+
+    fn int(&mut self, key: &str, default: Option<i64>) -> Result<i64, String> {
+        match self.param(key) {
+            Some(OpParam::Int(a)) => Ok(a),
+            Some(_) => Err(self.wrong_type(key, "an integer")),
+            None => default.ok_or_else(|| self.missing(key)),
+        }
+    }
+
+    fn usize(&mut self, key: &str, default: Option<usize>) -> Result<usize, String> {
+        let a = self.int(key, default.map(|a| a as i64))?;
+        if a < 0 {
+            return Err(self.wrong_type(key, "non-negative"));
+        }
+        Ok(a as usize)
+    }
+
+    fn float<F: Float>(&mut self, key: &str, default: Option<f64>) -> Result<F, String> {
+        let a = match self.param(key) {
+            Some(OpParam::Float(a)) => a,
+            Some(OpParam::Int(a)) => a as f64,
+            Some(_) => return Err(self.wrong_type(key, "a number")),
+            None => default.ok_or_else(|| self.missing(key))?,
+        };
+        Ok(F::from(a).unwrap())
+    }
+
+    fn bool(&mut self, key: &str, default: bool) -> Result<bool, String> {
+        match self.param(key) {
+            Some(OpParam::Bool(a)) => Ok(a),
+            Some(_) => Err(self.wrong_type(key, "`true` or `false`")),
+            None => Ok(default),
+        }
+    }
+
+    fn ints(&mut self, key: &str) -> Result<Vec<isize>, String> {
+        match self.param(key) {
+            Some(OpParam::Ints(a)) => Ok(a.into_iter().map(|a| a as isize).collect()),
+            Some(_) => Err(self.wrong_type(key, "a list of integers")),
+            None => Err(self.missing(key)),
+        }
+    }
+
+    fn string(&mut self, key: &str, default: &str) -> Result<String, String> {
+        match self.param(key) {
+            Some(OpParam::Str(a)) => Ok(a),
+            Some(_) => Err(self.wrong_type(key, "a string")),
+            None => Ok(default.to_string()),
+        }
+    }
+}
+
+fn build<'g, F: Float>(
+    call: &mut Call,
+    ctx: &'g Context<F>,
+    nodes: &HashMap<String, Tensor<'g, F>>,
+) -> Result<Tensor<'g, F>, String> {
+    let mut x = Vec::with_capacity(call.inputs.len());
+    for input in &call.inputs {
+        match nodes.get(*input) {
+            Some(&t) => x.push(t),
+            None => return Err(format!("unknown node `{}`", input)),
+        }
+    }
+    let arity = match call.op {
+        "placeholder" | "variable" | "scalar" | "zeros" | "ones" => 0,
+        "add" | "sub" | "mul" | "div" | "maximum" | "minimum" | "matmul" | "conv2d" | "conv1d"
+        | "sigmoid_cross_entropy" | "softmax_cross_entropy" | "sparse_softmax_cross_entropy" => 2,
+        _ => 1,
+    };
+    if x.len() != arity {
+        return Err(format!(
+            "{} takes {} inputs, {} given",
+            call.op,
+            arity,
+            x.len()
+        ));
+    }
+
+    let y = match call.op {
+        "placeholder" => {
+            let shape = call.ints("shape")?;
+            let name = call.string("name", call.name)?;
+            ctx.placeholder(intern(&name), &shape)
+        }
+        "variable" => {
+            let name = FullName {
+                namespace_id: call.string("namespace", "")?,
+                variable_name: call.string("name", call.name)?,
+            };
+            match ctx.env().name_to_id.get(&name) {
+                Some(&vid) => ctx.variable_by_id(vid),
+                None => return Err(format!("variable `{}` not found", name.variable_name)),
+            }
+        }
+        "scalar" => T::scalar(call.float("value", None)?, ctx),
+        "zeros" => T::zeros(&call.ints("shape")?, ctx),
+        "ones" => T::ones(&call.ints("shape")?, ctx),
+
+        "neg" => T::neg(x[0]),
+        "abs" => T::abs(x[0]),
+        "square" => T::square(x[0]),
+        "sqrt" => T::sqrt(x[0]),
+        "exp" => T::exp(x[0]),
+        "ln" => T::ln(x[0]),
+        "relu" => T::relu(x[0]),
+        "sigmoid" => T::sigmoid(x[0]),
+        "tanh" => T::tanh(x[0]),
+        "softplus" => T::softplus(x[0]),
+        "elu" => T::elu(x[0], call.float("alpha", Some(1.))?),
+        "leaky_relu" => T::leaky_relu(x[0], call.float("alpha", Some(0.01))?),
+        "add" => x[0] + x[1],
+        "sub" => x[0] - x[1],
+        "mul" => x[0] * x[1],
+        "div" => x[0] / x[1],
+        "maximum" => T::maximum(x[0], x[1]),
+        "minimum" => T::minimum(x[0], x[1]),
+
+        "matmul" => T::matmul(x[0], x[1]),
+        "reshape" => T::reshape(x[0], &call.ints("shape")?),
+        "transpose" => T::transpose(x[0], &call.ints("axes")?),
+        "softmax" => T::softmax(x[0], call.int("axis", Some(-1))? as isize),
+        "log_softmax" => T::log_softmax(x[0], call.int("axis", Some(-1))? as isize),
+
+        "sum_all" => T::sum_all(x[0]),
+        "mean_all" => T::mean_all(x[0]),
+        "reduce_sum" => T::reduce_sum(x[0], &call.ints("axes")?, call.bool("keep_dims", false)?),
+        "reduce_mean" => T::reduce_mean(x[0], &call.ints("axes")?, call.bool("keep_dims", false)?),
+        "reduce_max" => T::reduce_max(x[0], &call.ints("axes")?, call.bool("keep_dims", false)?),
+
+        "conv2d" => T::conv2d(
+            x[0],
+            x[1],
+            call.usize("pad", Some(0))?,
+            call.usize("stride", Some(1))?,
+        ),
+        "max_pool2d" => T::max_pool2d(
+            x[0],
+            call.usize("size", None)?,
+            call.usize("pad", Some(0))?,
+            call.usize("stride", Some(1))?,
+        ),
+        "conv1d" => T::conv1d(
+            x[0],
+            x[1],
+            call.usize("pad", Some(0))?,
+            call.usize("stride", Some(1))?,
+        ),
+        "max_pool1d" => T::max_pool1d(
+            x[0],
+            call.usize("size", None)?,
+            call.usize("pad", Some(0))?,
+            call.usize("stride", Some(1))?,
+        ),
+        "avg_pool1d" => T::avg_pool1d(
+            x[0],
+            call.usize("size", None)?,
+            call.usize("pad", Some(0))?,
+            call.usize("stride", Some(1))?,
+        ),
+
+        "sigmoid_cross_entropy" => T::sigmoid_cross_entropy(x[0], x[1]),
+        "softmax_cross_entropy" => T::softmax_cross_entropy(x[0], x[1]),
+        "sparse_softmax_cross_entropy" => T::sparse_softmax_cross_entropy(x[0], x[1]),
+        op => return Err(format!("unknown op `{}`", op)),
+    };
+    Ok(y)
+}
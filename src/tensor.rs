@@ -5,11 +5,13 @@ use crate::{NdArray, NdArrayView};
 use crate::graph::{AsGraph, Graph, TensorID};
 use crate::op::{GradientContext, SmallVec, OpError};
 use crate::variable::VariableID;
+use crate::FxHashMap;
 use std::cell::Ref;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::marker::PhantomData;
 use std::ops::{Add, Div, Mul, Sub};
+use std::rc::Rc;
 
 /// Lazy-evaluated multi-dimensional array
 ///
@@ -93,11 +95,62 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
     /// See also [Evaluator](../evaluation/struct.Evaluator.html).
     pub fn eval(&self, ctx: &Context<F>) -> Result<NdArray<F>, crate::EvalError> {
         crate::graph::assert_same_graph(ctx, self.graph);
-        let mut ret = ctx.eval(&[self], &[], ctx.var_env_ref);
+        let mut ret = ctx.eval(&[self], &[], ctx.var_env_ref, false);
         debug_assert_eq!(ret.len(), 1);
         ret.remove(0)
     }
 
+    /// Evaluates this tensor, keeping the values of all the intermediate tensors in `ctx`.
+    ///
+    /// Use [Context::peek] to read them. See [Evaluator::run_keep](crate::Evaluator::run_keep).
+    pub fn eval_keep(&self, ctx: &Context<F>) -> Result<NdArray<F>, crate::EvalError> {
+        crate::graph::assert_same_graph(ctx, self.graph);
+        let mut ret = ctx.eval(&[self], &[], ctx.var_env_ref, true);
+        debug_assert_eq!(ret.len(), 1);
+        ret.remove(0)
+    }
+
+    /// Evaluates this tensor, returning a reference instead of a new array.
+    ///
+    /// If this is a variable tensor, the reference points to the variable's array itself,
+    /// so nothing is computed or copied; otherwise the result is moved into `ctx`
+    /// as if by [Tensor::eval_keep] (but without the intermediate values).
+    /// The returned `Ref` borrows `ctx`, and an array in `ctx` can't be updated while it's alive:
+    /// drop it before optimizing the variable, or evaluating again with `eval_ref`/`eval_keep`.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    ///
+    /// let mut env = ag::VariableEnvironment::new();
+    /// let w = env.name("w").set(ag::ndarray_ext::ones(&[2, 2]));
+    ///
+    /// env.run(|g| {
+    ///     let w = g.variable(w);
+    ///     let w_ref = w.eval_ref(g).unwrap();
+    ///     assert_eq!(w_ref.shape(), &[2, 2]);
+    ///
+    ///     let y = (w * 2.).eval_ref(g).unwrap();
+    ///     assert_eq!(y[[0, 0]], 2.);
+    /// });
+    /// ```
+    pub fn eval_ref<'c>(
+        &self,
+        ctx: &'c Context<F>,
+    ) -> Result<Ref<'c, NdArray<F>>, crate::EvalError> {
+        crate::graph::assert_same_graph(ctx, self.graph);
+        match self.get_variable_id() {
+            // slices have no array of their own to borrow
+            Some(vid) if !ctx.var_env_ref.slices.contains_key(&vid) => {
+                return Ok(ctx.var_env_ref.array_list[vid.0].borrow());
+            }
+            _ => {}
+        }
+        let y = self.eval(ctx)?;
+        ctx.graph.kept_outputs.borrow_mut().insert(self.id, y);
+        Ok(ctx.peek(self).unwrap())
+    }
+
     /// Ensures that this tensor is evaluated after the arguments.
     ///
     /// You can use the return value instead of `self`.
@@ -137,6 +190,50 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
         crate::tensor_ops::control_dependencies(self, on)
     }
 
+    /// Caches the value of this tensor in the context after its first evaluation.
+    ///
+    /// Later evaluations reuse the cached value instead of computing this tensor and
+    /// the tensors behind it again, until [Context::clear_pinned] or [Context::clear] is called.
+    /// Panics if this tensor depends on a placeholder or a variable, whose values can change
+    /// between evaluations. Note that random ops are not detected: pinning one freezes its sample.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run(|g| {
+    ///     let table: ag::Tensor<f32> = T::exp(T::ones(&[1000], g)).pin();
+    ///     let x = g.placeholder("x", &[1000]);
+    ///     let y = table * x;
+    ///
+    ///     for _ in 0..3 {
+    ///         // `table` is computed only once
+    ///         let x_value = ag::ndarray_ext::zeros(&[1000]);
+    ///         g.evaluator().push(y).feed(x, x_value.view()).run();
+    ///     }
+    /// });
+    /// ```
+    pub fn pin(self) -> Tensor<'graph, F> {
+        let mut stack = vec![self.id];
+        let mut visited = crate::FxHashSet::default();
+        while let Some(id) = stack.pop() {
+            let node = self.graph.access_inner(id);
+            if node.placeholder_name.is_some() || node.is_variable() {
+                panic!(
+                    "pin: tensor {} depends on a placeholder or a variable",
+                    self.id
+                );
+            }
+            for incoming in &node.incoming_nodes {
+                if visited.insert(incoming.id) {
+                    stack.push(incoming.id);
+                }
+            }
+        }
+        self.graph.pinned.borrow_mut().insert(self.id);
+        self
+    }
+
     /// Creates a new [TensorBuilder](struct.TensorBuilder.html).
     #[inline]
     pub fn builder(graph: &'graph impl AsGraph<F>) -> TensorBuilder<'graph, F> {
@@ -320,6 +417,11 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
     }
 
     /// Returns the id of this tensor in this graph.
+    ///
+    /// Ids are allocated by the context in creation order, from 0, and never reused until
+    /// [Context::clear](crate::Context::clear). They don't depend on addresses or hashing, so
+    /// building the same graph (gradients included) in the same order gives the same ids on
+    /// every run, e.g. in dumps of the graph.
     #[inline(always)]
     pub fn id(&self) -> usize {
         self.id
@@ -349,10 +451,39 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
     }
 
     #[inline]
-    pub(crate) fn get_variable_id(&self) -> Option<VariableID> {
+    /// Id of the variable this tensor refers to, if it was created by `Context::variable`.
+    pub fn get_variable_id(&self) -> Option<VariableID> {
         self.inner().variable_id
     }
 
+    #[inline]
+    /// Input node used when evaluating this tensor.
+    pub fn get_input(&self, idx: usize) -> Tensor<'graph, F> {
+        self.graph.tensor(self.inner().incoming_nodes[idx].id)
+    }
+
+    /// Returns the name of the op of this tensor without its path, e.g. `"MulOp"`.
+    pub fn op_name(&self) -> &'static str {
+        crate::evaluation::short_op_name(self.inner().get_op().name())
+    }
+
+    /// Returns the parameters of the op of this tensor, e.g. the stride of a convolution.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::op::OpParam;
+    /// use ag::tensor_ops as T;
+    ///
+    /// ag::run::<f32, _, _>(|g| {
+    ///     let y = T::softmax(T::zeros(&[2, 3], g), 1);
+    ///     assert_eq!(y.op_name(), "Softmax");
+    ///     assert_eq!(y.op_params()["axis"], OpParam::Int(1));
+    /// });
+    /// ```
+    pub fn op_params(&self) -> crate::op::OpParams {
+        self.inner().get_op().params()
+    }
+
     #[inline]
     /// Input node used when backprop.
     pub fn get_backprop_input(&self, idx: usize) -> Tensor<'graph, F> {
@@ -370,6 +501,19 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
         self.inner().placeholder_name
     }
 
+    // The dims of this tensor known when the graph is built, without looking at its inputs:
+    // those of the placeholders and the constants
+    pub(crate) fn known_dims(&self) -> Option<Vec<Dim>> {
+        let inner = self.inner();
+        if let Some(ref known_shape) = inner.known_shape {
+            return Some(known_shape.get().to_vec());
+        }
+        if !inner.incoming_nodes.is_empty() {
+            return None;
+        }
+        inner.op.as_ref()?.infer_shape(&[]).ok().flatten()
+    }
+
     #[inline]
     pub fn validate_using_known_shape(&self, shape: &[usize]) {
         if let Some(ref known_shape) = self.inner().known_shape {
@@ -385,6 +529,31 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
         }
     }
 
+    // Binds the symbolic dims of this placeholder to the sizes of the array fed to it,
+    // checking them against the sizes already bound by other placeholders.
+    pub(crate) fn bind_symbols(
+        &self,
+        shape: &[usize],
+        symbols: &mut FxHashMap<&'static str, usize>,
+    ) {
+        if let Some(ref known_shape) = self.inner().known_shape {
+            for (&d, &n) in known_shape.get().iter().zip(shape) {
+                if let Dim::Symbol(name) = d {
+                    let bound = *symbols.entry(name).or_insert(n);
+                    if bound != n {
+                        panic!(
+                            "Shape error: dim `{}` of placeholder {:?} is {}, but another placeholder bound it to {}",
+                            name,
+                            self.placeholder_name().unwrap_or_default(),
+                            n,
+                            bound
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     #[inline]
     pub fn is_differentiable(&self) -> bool {
         self.inner().is_differentiable
@@ -398,6 +567,35 @@ impl<'tensor, 'graph: 'tensor, F: Float> Tensor<'graph, F> {
     }
 }
 
+/// Shows the id and the op of this tensor, e.g. `tensor 3 (Slice)`.
+///
+/// Copies of a tensor share the same id, which is unique in its graph.
+impl<'b, T: Float> fmt::Display for Tensor<'b, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner();
+        if let Some(name) = inner.placeholder_name {
+            write!(f, "tensor {} (placeholder \"{}\")", self.id, name)
+        } else if inner.is_variable() {
+            write!(f, "tensor {} (variable)", self.id)
+        } else {
+            let name = crate::evaluation::short_op_name(inner.get_op().name());
+            write!(f, "tensor {} ({})", self.id, name)
+        }
+    }
+}
+
+impl<'b, T: Float> fmt::Debug for Tensor<'b, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner();
+        let inputs: Vec<_> = inner.incoming_nodes.iter().map(|x| x.id).collect();
+        f.debug_struct("Tensor")
+            .field("id", &self.id)
+            .field("op", &inner.get_op().name())
+            .field("inputs", &inputs)
+            .finish()
+    }
+}
+
 impl<'b, T: Float> AsRef<Tensor<'b, T>> for Tensor<'b, T> {
     #[inline(always)]
     fn as_ref(&self) -> &Tensor<'b, T> {
@@ -410,7 +608,7 @@ pub(crate) struct TensorInternal<F: Float> {
     pub(crate) id: usize,
 
     /// Operation to evaluate this tensor.
-    pub(crate) op: Option<Box<dyn op::Op<F>>>,
+    pub(crate) op: Option<Rc<dyn op::Op<F>>>,
 
     /// References to immediate predecessors.
     pub(crate) incoming_nodes: SmallVec<IncomingTensor>,
@@ -442,7 +640,7 @@ pub(crate) struct TensorInternal<F: Float> {
 
 impl<F: Float> TensorInternal<F> {
     /// Returns the Op of this tensor
-    pub fn get_op(&self) -> &Box<dyn op::Op<F>> {
+    pub fn get_op(&self) -> &Rc<dyn op::Op<F>> {
         self.op
             .as_ref()
             .expect("bad impl: Op is now stolen in gradient.rs")
@@ -538,7 +736,7 @@ impl<T: Float> fmt::Display for TensorInternal<T> {
 /// Denotes a tensor that enters a certain tensor in a computation graph
 ///
 /// See also [TensorBuilder](struct.TensorBuilder.html).
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub(crate) struct IncomingTensor {
     // Tensor id
     pub(crate) id: usize,
@@ -618,8 +816,75 @@ pub struct TensorBuilder<'g, F: Float> {
 }
 
 const NUM_MAX_KNOWN_SHAPE_SIZE: usize = 4;
-type ShapeVec = smallvec::SmallVec<[isize; NUM_MAX_KNOWN_SHAPE_SIZE]>;
+type ShapeVec = smallvec::SmallVec<[Dim; NUM_MAX_KNOWN_SHAPE_SIZE]>;
+
+/// A dim size of a static shape, known when the graph is built.
+///
+/// `Symbol`s are named unknowns: dims sharing a name (e.g. `"batch"`) are the same size,
+/// which lets [Context::infer_shape](crate::Context::infer_shape) check the ops mixing them.
+/// The size bound to a symbol is only known at evaluation, from the placeholders fed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Dim {
+    Known(usize),
+    Symbol(&'static str),
+    Unknown,
+}
+
+impl Dim {
+    /// Returns the size of this dim if it is known.
+    #[inline]
+    pub fn value(&self) -> Option<usize> {
+        match *self {
+            Dim::Known(n) => Some(n),
+            _ => None,
+        }
+    }
 
+    /// Merges two dims that must be the same size, e.g. the inner dims of a matmul.
+    /// `None` if they can't be.
+    pub(crate) fn unify(self, other: Dim) -> Option<Dim> {
+        match (self, other) {
+            (Dim::Known(a), Dim::Known(b)) => (a == b).then_some(self),
+            (Dim::Known(_), _) => Some(self),
+            (_, Dim::Known(_)) => Some(other),
+            (Dim::Symbol(a), Dim::Symbol(b)) if a == b => Some(self),
+            (Dim::Unknown, _) => Some(other),
+            (_, Dim::Unknown) => Some(self),
+            // different symbols can still be bound to the same size
+            _ => Some(Dim::Unknown),
+        }
+    }
+
+    /// The dim that broadcasting `self` and `other` results in. `None` if they can't be
+    /// broadcast.
+    pub(crate) fn broadcast(self, other: Dim) -> Option<Dim> {
+        match (self, other) {
+            (Dim::Known(1), _) => Some(other),
+            (_, Dim::Known(1)) => Some(self),
+            // the unknown side is either 1 or the same size
+            _ => self.unify(other),
+        }
+    }
+}
+
+/// Shape of the result of broadcasting the static shapes `a` and `b` together.
+///
+/// Same rules as [broadcast_shapes](crate::ndarray_ext::broadcast_shapes).
+pub(crate) fn broadcast_dims(a: &[Dim], b: &[Dim]) -> Result<Vec<Dim>, op::OpError> {
+    let ndim = a.len().max(b.len());
+    let mut ret = vec![Dim::Unknown; ndim];
+    for i in 0..ndim {
+        // counted from the last dim
+        let da = a.len().checked_sub(i + 1).map_or(Dim::Known(1), |j| a[j]);
+        let db = b.len().checked_sub(i + 1).map_or(Dim::Known(1), |j| b[j]);
+        ret[ndim - 1 - i] = da.broadcast(db).ok_or_else(|| {
+            op::OpError::IncompatibleShape(format!("cannot broadcast {:?} and {:?}", a, b))
+        })?;
+    }
+    Ok(ret)
+}
+
+#[derive(Clone)]
 pub(crate) struct KnownShape {
     shape: ShapeVec,
     #[allow(dead_code)]
@@ -628,23 +893,35 @@ pub(crate) struct KnownShape {
 
 impl KnownShape {
     pub(crate) fn new(shape: &[isize]) -> Self {
-        let mut is_fully_defined = true;
         for &a in shape {
-            if a == -1 {
-                is_fully_defined = false;
-            } else if a <= -1 || a == 0 {
+            if a != -1 && a <= 0 {
                 panic!("Given shape ({:?}) contains invalid dim size(s)", &shape);
             }
         }
+        let dims: ShapeVec = shape
+            .iter()
+            .map(|&a| match a {
+                -1 => Dim::Unknown,
+                _ => Dim::Known(a as usize),
+            })
+            .collect();
+        Self::from_dims(&dims)
+    }
 
+    pub(crate) fn from_dims(shape: &[Dim]) -> Self {
+        for &d in shape {
+            if d == Dim::Known(0) {
+                panic!("Given shape ({:?}) contains invalid dim size(s)", &shape);
+            }
+        }
         Self {
             shape: ShapeVec::from(shape),
-            is_fully_defined,
+            is_fully_defined: shape.iter().all(|d| d.value().is_some()),
         }
     }
 
     #[inline]
-    pub fn get(&self) -> &[isize] {
+    pub fn get(&self) -> &[Dim] {
         self.shape.as_slice()
     }
 
@@ -652,9 +929,11 @@ impl KnownShape {
         if self.shape.len() != target.len() {
             return false;
         }
-        for (&i, &u) in self.shape.iter().zip(target) {
-            if i > 0 && i as usize != u {
-                return false;
+        for (&d, &u) in self.shape.iter().zip(target) {
+            if let Dim::Known(n) = d {
+                if n != u {
+                    return false;
+                }
             }
         }
         true
@@ -698,6 +977,12 @@ impl<'graph, F: Float> TensorBuilder<'graph, F> {
         self
     }
 
+    #[inline]
+    pub(crate) fn set_known_dims(mut self, s: &[Dim]) -> TensorBuilder<'graph, F> {
+        self.known_shape = Some(KnownShape::from_dims(s));
+        self
+    }
+
     #[inline]
     pub(crate) fn set_shape(mut self, s: &Tensor<'graph, F>) -> TensorBuilder<'graph, F> {
         self.shape = Some(s.id());
@@ -785,7 +1070,7 @@ impl<'graph, F: Float> TensorBuilder<'graph, F> {
         let new = TensorInternal {
             // `id` is set in `Graph::install`
             id: usize::default(),
-            op: Some(Box::new(op)),
+            op: Some(Rc::new(op)),
             incoming_nodes: self.in_nodes,
             topo_rank: rank,
             shape: self.shape,
@@ -914,6 +1199,12 @@ impl_bin_op_between_tensors!(Div, div, DivOp);
 /// Implementors can be converted to `Tensor`.
 pub trait AsTensor<'graph, F: Float> {
     fn as_tensor(&self, graph: &'graph impl AsGraph<F>) -> Tensor<'graph, F>;
+
+    /// The values of the converted tensor if they are known when the graph is built,
+    /// so that ops can validate them on construction. `None` for tensors.
+    fn known_values(&self) -> Option<Vec<isize>> {
+        None
+    }
 }
 
 impl<'graph, F: Float> AsTensor<'graph, F> for Tensor<'graph, F> {
@@ -935,6 +1226,10 @@ macro_rules! impl_as_tensor_for_array {
                 let arr = NdArray::from_shape_vec(ndarray::IxDyn(&[self.len()]), vec).unwrap();
                 T::convert_to_tensor(arr, graph.as_graph())
             }
+
+            fn known_values(&self) -> Option<Vec<isize>> {
+                self.iter().map(|a| a.to_isize()).collect()
+            }
         }
     };
 }
@@ -948,3 +1243,16 @@ impl_as_tensor_for_array!(5);
 impl_as_tensor_for_array!(6);
 impl_as_tensor_for_array!(7);
 impl_as_tensor_for_array!(8);
+
+impl<'graph, F: Float, I: crate::Int> AsTensor<'graph, F> for Vec<I> {
+    fn as_tensor(&self, graph: &'graph impl AsGraph<F>) -> Tensor<'graph, F> {
+        let vec = self.iter().map(|&a| F::from(a).unwrap()).collect::<Vec<F>>();
+        // unwrap is safe
+        let arr = NdArray::from_shape_vec(ndarray::IxDyn(&[self.len()]), vec).unwrap();
+        T::convert_to_tensor(arr, graph.as_graph())
+    }
+
+    fn known_values(&self) -> Option<Vec<isize>> {
+        self.iter().map(|a| a.to_isize()).collect()
+    }
+}
@@ -2,12 +2,13 @@ use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
 #[cfg(feature = "mkl")]
 use crate::same_type;
-use crate::tensor::Tensor;
+use crate::tensor::{Dim, Tensor};
 #[cfg(feature = "mkl")]
 use crate::tensor_ops::blas_ffi::*;
 use crate::tensor_ops::*;
 use crate::Float;
 use ndarray;
+use std::hash::{Hash, Hasher};
 
 pub struct ELU<T> {
     pub alpha: T,
@@ -108,22 +109,38 @@ impl<T: Float> op::Op<T> for Softmax {
         let sum = reduce_sum(y * gy, &[self.axis], true);
         ctx.append_input_grad(Some((gy - sum) * y))
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([("axis", self.axis.into())])
+    }
 }
 
 impl<T: Float> op::Op<T> for Softplus {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
-        let ret = ctx.input(0).map(move |a| (a.exp() + T::one()).ln());
+        // log(1 + exp(a)) = max(a, 0) + log(1 + exp(-|a|)), which doesn't overflow
+        let ret = ctx
+            .input(0)
+            .map(move |&a| a.max(T::zero()) + (-a.abs()).exp().ln_1p());
         ctx.append_output(ret);
         Ok(())
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        let gy = ctx.output_grad();
-        let a = exp(ctx.input(0));
-        let b = a + scalar(T::one(), ctx.graph());
-        let gx = gy * (a / b);
+        let gx = ctx.output_grad() * sigmoid(ctx.input(0));
         ctx.append_input_grad(Some(gx))
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
 }
 
 impl<T: Float> op::Op<T> for Sigmoid {
@@ -136,9 +153,8 @@ impl<T: Float> op::Op<T> for Sigmoid {
         #[cfg(not(feature = "mkl"))]
         {
             let half = T::from(0.5).unwrap();
-            ret = ctx
-                .input(0)
-                .mapv(move |a| ((a * half).tanh() * half) + half);
+            let x = ctx.input(0);
+            ret = ctx.alloc_map(&x, move |a| ((a * half).tanh() * half) + half);
         }
         ctx.append_output(ret);
         Ok(())
@@ -149,11 +165,16 @@ impl<T: Float> op::Op<T> for Sigmoid {
         let y = ctx.output();
         ctx.append_input_grad(Some(gy * (y - square(y))));
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
 }
 
 impl<T: Float> op::Op<T> for ReLU {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
-        let ret = ctx.input(0).map(|a| a.max(T::zero()));
+        let x = ctx.input(0);
+        let ret = ctx.alloc_map(&x, |a| a.max(T::zero()));
         ctx.append_output(ret);
         Ok(())
     }
@@ -164,6 +185,10 @@ impl<T: Float> op::Op<T> for ReLU {
         let bin = greater(ctx.input(0), scalar(T::zero(), s));
         ctx.append_input_grad(Some(mul(bin, gy)))
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
 }
 
 impl<T: Float> op::Op<T> for Identity {
@@ -178,6 +203,10 @@ impl<T: Float> op::Op<T> for Identity {
         let gy = ctx.output_grad();
         ctx.append_input_grad(Some(gy))
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
 }
 
 impl<T: Float> op::Op<T> for ELU<T> {
@@ -202,6 +231,14 @@ impl<T: Float> op::Op<T> for ELU<T> {
             .build(ELUGrad { alpha: self.alpha });
         ctx.append_input_grad(Some(gx))
     }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        op::float_bits(self.alpha).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ELUGrad<T> {
@@ -4,11 +4,12 @@ use crate::ndarray_ext;
 use crate::ndarray_ext::NdArrayViewMut;
 use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
-use crate::tensor::Tensor;
+use crate::tensor::{Dim, Tensor};
 use crate::tensor_ops::*;
 use crate::Float;
 use std::iter::FromIterator;
 use ndarray::SliceInfoElem;
+use std::hash::{Hash, Hasher};
 
 pub struct ExpandDims;
 
@@ -22,6 +23,18 @@ pub struct SliceGrad {
     pub indices: Vec<SliceInfoElem>,
 }
 
+pub struct SlidingWindow {
+    pub size: usize,
+    pub stride: usize,
+    pub axis: isize,
+}
+
+pub struct SlidingWindowGrad {
+    pub size: usize,
+    pub stride: usize,
+    pub axis: isize,
+}
+
 pub struct Split {
     pub axis: isize,
     pub start_index: isize,
@@ -39,10 +52,26 @@ pub struct Tile {
     pub num: usize,
 }
 
+pub struct BroadcastTo;
+
+/// Gradient of `BroadcastTo`: inputs are `gy` and `x`.
+pub struct BroadcastToGrad;
+
 pub struct Concat {
     pub axis: isize,
 }
 
+/// Stacks inputs of the same shape along a new `axis` of the output.
+pub struct Stack {
+    pub axis: isize,
+}
+
+/// Splits the input into its `num` slices along `axis`, one output each.
+pub struct Unstack {
+    pub axis: isize,
+    pub num: usize,
+}
+
 pub struct ConcatGrad {
     pub axis: isize,
     pub index: usize,
@@ -58,6 +87,71 @@ pub struct ClipGrad<T: Float> {
     pub max: T,
 }
 
+// Percentiles in [0, 100]
+pub struct ClipByPercentile<T: Float> {
+    pub low: T,
+    pub high: T,
+}
+
+/// How `pad` fills the padded region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PadMode<T: Float> {
+    /// Fills with the given value.
+    Constant(T),
+    /// Mirrors the values without repeating the edge, e.g. `[1, 2, 3] -> [3, 2, 1, 2, 3, 2, 1]`.
+    Reflect,
+    /// Repeats the edge values, e.g. `[1, 2, 3] -> [1, 1, 1, 2, 3, 3, 3]`.
+    Edge,
+}
+
+/// Axis order of the grids made by `meshgrid`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshgridIndexing {
+    /// Matrix indexing: the `i`-th input runs along axis `i`.
+    Ij,
+    /// Cartesian indexing: like `Ij` with the first two axes swapped.
+    Xy,
+}
+
+pub struct Roll {
+    pub shifts: Vec<isize>,
+    pub axes: Vec<isize>,
+}
+
+pub struct Flip {
+    pub axes: Vec<isize>,
+}
+
+/// Moves `block x block` spatial blocks of an NCHW tensor into channels,
+/// or back with `inverse` (depth to space).
+pub struct SpaceToDepth {
+    pub block: usize,
+    pub inverse: bool,
+}
+
+/// Indices that sort the lanes along `axis`; equal elements keep their order, NaNs go last.
+pub struct ArgSort {
+    pub axis: isize,
+    pub descending: bool,
+}
+
+/// `y[.., k, ..] = x[.., indices[.., k, ..], ..]` along `axis`, or with `inverse` the scatter
+/// `y[.., indices[.., k, ..], ..] = x[.., k, ..]`, which undoes it for permutations.
+pub struct TakeAlongAxis {
+    pub axis: isize,
+    pub inverse: bool,
+}
+
+pub struct Pad<T: Float> {
+    pub paddings: Vec<(usize, usize)>,
+    pub mode: PadMode<T>,
+}
+
+pub struct PadGrad<T: Float> {
+    pub paddings: Vec<(usize, usize)>,
+    pub mode: PadMode<T>,
+}
+
 pub struct AddN;
 
 pub struct Gather {
@@ -69,6 +163,22 @@ pub struct GatherGrad {
     pub axis: isize,
 }
 
+/// Adds the subviews of `updates` into zeros at `indices` along `axis`; the inverse of `Gather`.
+pub struct ScatterAdd {
+    pub axis: isize,
+}
+
+/// Elements of `x` where `mask` is nonzero, in row-major order.
+pub struct BooleanMask;
+
+/// Zeros of the shape of `mask`, with the elements of the 1-D `gy` where `mask` is nonzero.
+pub struct BooleanMaskGrad;
+
+/// `x` with `value` where the (broadcast) `mask` is nonzero.
+pub struct MaskedFill<T: Float> {
+    pub value: T,
+}
+
 pub struct IndexOp {
     pub index: isize,
 }
@@ -87,6 +197,10 @@ pub struct Size;
 
 pub struct Reshape;
 
+pub struct Flatten {
+    pub start_axis: isize,
+}
+
 pub struct InferBinOpShape;
 
 pub struct Assign;
@@ -238,6 +352,41 @@ impl<T: Float> op::Op<T> for Reshape {
     }
 }
 
+impl<T: Float> op::Op<T> for Flatten {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let start = ndarray_ext::normalize_negative_axis(self.start_axis, x.ndim());
+        if start > x.ndim() {
+            return Err(op::OpError::OutOfBounds(format!(
+                "flatten: start_axis {} is out of bounds for shape {:?}",
+                self.start_axis,
+                x.shape()
+            )));
+        }
+        let mut target = x.shape()[..start].to_vec();
+        target.push(x.shape()[start..].iter().product());
+        if x.is_standard_layout() {
+            // safe unwrap: the size is unchanged
+            ctx.append_output_view(x.into_shape(target).unwrap());
+        } else {
+            ctx.append_output(ndarray_ext::deep_copy(&x).into_shape(target).unwrap());
+        }
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(shape(ctx.input(0)), false)
+            .build(Reshape);
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.start_axis.hash(&mut state);
+    }
+}
+
 impl<T: Float> op::Op<T> for SetDiff1D {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let x0 = ctx.input(0);
@@ -312,6 +461,10 @@ impl<T: Float> op::Op<T> for IndexOp {
             .build(op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.index.hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for IndexOpGrad {
@@ -396,6 +549,10 @@ impl<T: Float> op::Op<T> for Gather {
         ctx.append_input_grad(None);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.should_normalize_negative_indices).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for GatherGrad {
@@ -473,6 +630,181 @@ impl<T: Float> op::Op<T> for GatherGrad {
     }
 }
 
+impl<T: Float> op::Op<T> for ScatterAdd {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let indices = ctx.input(0);
+        let updates = ctx.input(1);
+        let target_shape = ndarray_ext::as_shape(&ctx.input(2));
+        let ndim = target_shape.len() as isize;
+        if self.axis < -ndim || self.axis >= ndim {
+            return Err(op::OpError::OutOfBounds(format!(
+                "scatter_add: axis {} is out of bounds for rank {}",
+                self.axis, ndim
+            )));
+        }
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, target_shape.len());
+        let (former, latter) = (&target_shape[..axis], &target_shape[axis + 1..]);
+        let expected: Vec<usize> = former
+            .iter()
+            .chain(indices.shape())
+            .chain(latter)
+            .cloned()
+            .collect();
+        if updates.shape() != expected.as_slice() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "scatter_add: updates must have shape {:?} for indices {:?} along axis {}, got {:?}",
+                expected,
+                indices.shape(),
+                axis,
+                updates.shape()
+            )));
+        }
+
+        let len = target_shape[axis] as isize;
+        // one subview per index along `axis`
+        let flat_shape: Vec<usize> = former
+            .iter()
+            .chain(&[indices.len()])
+            .chain(latter)
+            .cloned()
+            .collect();
+        let updates = updates.into_shape(flat_shape).unwrap();
+        let mut ret = NdArray::zeros(target_shape.as_slice());
+        for (sub, &i) in updates.axis_iter(ndarray::Axis(axis)).zip(&indices) {
+            let i = i.to_isize().unwrap();
+            let j = if i < 0 { i + len } else { i };
+            if j < 0 || j >= len {
+                return Err(op::OpError::OutOfBounds(format!(
+                    "scatter_add: index {} is out of bounds for length {}",
+                    i, len
+                )));
+            }
+            let mut dst = ret.index_axis_mut(ndarray::Axis(axis), j as usize);
+            dst += &sub;
+        }
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        let indices = ctx.input(0);
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(Some(gather_common(gy, indices, self.axis)));
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for BooleanMask {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let mask = ctx.input(1);
+        if x.shape() != mask.shape() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "boolean_mask: mask {:?} must have the shape of x {:?}",
+                mask.shape(),
+                x.shape()
+            )));
+        }
+        let selected: Vec<T> = x
+            .iter()
+            .zip(mask.iter())
+            .filter(|(_, &m)| m != T::zero())
+            .map(|(&a, _)| a)
+            .collect();
+        ctx.append_output(NdArray::from_shape_vec(vec![selected.len()], selected).unwrap());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let mask = ctx.input(1);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(mask, false)
+            .set_shape(&shape(mask))
+            .build(BooleanMaskGrad);
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for MaskedFill<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let mask = ctx.input(1);
+        let mask = mask.broadcast(x.shape()).ok_or_else(|| {
+            op::OpError::IncompatibleShape(format!(
+                "masked_fill: mask {:?} doesn't broadcast to the shape of x {:?}",
+                mask.shape(),
+                x.shape()
+            ))
+        })?;
+        let value = self.value;
+        let mut y = x.to_owned();
+        ndarray::Zip::from(&mut y).and(&mask).for_each(|y, &m| {
+            if m != T::zero() {
+                *y = value;
+            }
+        });
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // the filled elements don't depend on x
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.input(1), false)
+            .build(MaskedFill { value: T::zero() });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[false, true]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        op::float_bits(self.value).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for BooleanMaskGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let mask = ctx.input(1);
+        let mut gx = NdArray::zeros(mask.shape());
+        let mut gy = gy.iter();
+        for (gx, &m) in gx.iter_mut().zip(mask.iter()) {
+            if m == T::zero() {
+                continue;
+            }
+            *gx = *gy.next().ok_or_else(|| {
+                op::OpError::IncompatibleShape(
+                    "boolean_mask: the gradient has fewer elements than the mask selects"
+                        .to_string(),
+                )
+            })?;
+        }
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let mask = ctx.input(1);
+        ctx.append_input_grad(Some(boolean_mask(ctx.output_grad(), mask)));
+        ctx.append_input_grad(None);
+    }
+}
+
 #[cfg(feature = "mkl")]
 pub(crate) fn inplace_add_impl<F: Float>(mut a: NdArrayViewMut<F>, b: &NdArrayView<F>) {
     use crate::same_type;
@@ -534,6 +866,318 @@ impl<T: Float> op::Op<T> for AddN {
     }
 }
 
+impl<T: Float> op::Op<T> for Roll {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        if self.shifts.len() != self.axes.len() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "roll: lengths of shifts ({}) and axes ({}) must match",
+                self.shifts.len(),
+                self.axes.len()
+            )));
+        }
+        let mut ret = x.to_owned();
+        for (&shift, &axis) in self.shifts.iter().zip(&self.axes) {
+            let axis = ndarray_ext::normalize_negative_axis(axis, x.ndim());
+            if axis >= x.ndim() {
+                return Err(op::OpError::OutOfBounds(format!(
+                    "roll: axis {} is out of bounds for shape {:?}",
+                    axis,
+                    x.shape()
+                )));
+            }
+            let len = x.shape()[axis];
+            if len == 0 {
+                continue;
+            }
+            let s = shift.rem_euclid(len as isize) as usize;
+            if s == 0 {
+                continue;
+            }
+            let mut rolled = ret.clone();
+            let axis = ndarray::Axis(axis);
+            rolled
+                .slice_axis_mut(axis, (s..).into())
+                .assign(&ret.slice_axis(axis, (..len - s).into()));
+            rolled
+                .slice_axis_mut(axis, (..s).into())
+                .assign(&ret.slice_axis(axis, (len - s..).into()));
+            ret = rolled;
+        }
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(Roll {
+                shifts: self.shifts.iter().map(|s| -s).collect(),
+                axes: self.axes.clone(),
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (&self.shifts, &self.axes).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Flip {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let mut x = ctx.input(0);
+        let ndim = x.ndim();
+        let mut flipped = vec![false; ndim];
+        for &axis in &self.axes {
+            let axis = ndarray_ext::normalize_negative_axis(axis, ndim);
+            if axis >= ndim {
+                return Err(op::OpError::OutOfBounds(format!(
+                    "flip: axis {:?} is out of bounds for shape {:?}",
+                    self.axes,
+                    x.shape()
+                )));
+            }
+            if flipped[axis] {
+                return Err(op::OpError::InvalidDims(format!(
+                    "flip: axis {} is given more than once",
+                    axis
+                )));
+            }
+            flipped[axis] = true;
+            x.invert_axis(ndarray::Axis(axis));
+        }
+        ctx.append_output_view(x);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(Flip {
+                axes: self.axes.clone(),
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axes.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for SpaceToDepth {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let b = self.block;
+        let name = if self.inverse {
+            "depth_to_space"
+        } else {
+            "space_to_depth"
+        };
+        if x.ndim() != 4 || b == 0 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "{}: input must be an NCHW tensor and block positive, got shape {:?} and block {}",
+                name,
+                x.shape(),
+                b
+            )));
+        }
+        let (n, c, h, w) = (x.shape()[0], x.shape()[1], x.shape()[2], x.shape()[3]);
+        // (n, c, h, bh, w, bw) <-> (n, bh, bw, c, h, w)
+        let (split, perm, out) = if self.inverse {
+            if c % (b * b) != 0 {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "{}: {} channels are not divisible by block * block = {}",
+                    name,
+                    c,
+                    b * b
+                )));
+            }
+            let split = [n, b, b, c / (b * b), h, w];
+            (split, [0, 3, 4, 1, 5, 2], [n, c / (b * b), h * b, w * b])
+        } else {
+            if h % b != 0 || w % b != 0 {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "{}: spatial dims {:?} are not divisible by block {}",
+                    name,
+                    [h, w],
+                    b
+                )));
+            }
+            let split = [n, c, h / b, b, w / b, b];
+            (split, [0, 3, 5, 1, 2, 4], [n, c * b * b, h / b, w / b])
+        };
+        let x = x.as_standard_layout().into_shape(&split[..]).unwrap();
+        let y = x.permuted_axes(&perm[..]).as_standard_layout().into_owned();
+        let y = y.into_shape(&out[..]).unwrap();
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // a permutation of the elements, undone by the inverse one
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .set_shape(&shape(ctx.input(0)))
+            .build(SpaceToDepth {
+                block: self.block,
+                inverse: !self.inverse,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.block, self.inverse).hash(&mut state);
+    }
+}
+
+// Index in the source array which fills the `i`-th element of a padded axis.
+#[inline]
+fn pad_source_index<T: Float>(
+    i: usize,
+    before: usize,
+    len: usize,
+    mode: PadMode<T>,
+) -> Option<usize> {
+    let j = i as isize - before as isize;
+    if 0 <= j && (j as usize) < len {
+        return Some(j as usize);
+    }
+    match mode {
+        PadMode::Constant(_) => None,
+        PadMode::Edge => Some(if j < 0 { 0 } else { len - 1 }),
+        PadMode::Reflect => {
+            if len == 1 {
+                return Some(0);
+            }
+            let period = 2 * (len as isize - 1);
+            let j = j.rem_euclid(period);
+            Some(if j < len as isize { j } else { period - j } as usize)
+        }
+    }
+}
+
+fn validate_paddings<T: Float>(
+    paddings: &[(usize, usize)],
+    mode: PadMode<T>,
+    shape: &[usize],
+) -> Result<(), op::OpError> {
+    if paddings.len() != shape.len() {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "pad: length of paddings ({}) must match the input's ndim ({})",
+            paddings.len(),
+            shape.len()
+        )));
+    }
+    if let PadMode::Constant(_) = mode {
+        return Ok(());
+    }
+    for (axis, (&(before, after), &len)) in paddings.iter().zip(shape).enumerate() {
+        if len == 0 && before + after > 0 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "pad: can't pad the empty axis {} with {:?} mode",
+                axis, mode
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl<T: Float> op::Op<T> for Pad<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        validate_paddings(&self.paddings, self.mode, x.shape())?;
+        let out_shape: Vec<usize> = x
+            .shape()
+            .iter()
+            .zip(&self.paddings)
+            .map(|(&len, &(before, after))| before + len + after)
+            .collect();
+        let mut src = vec![0; x.ndim()];
+        let ret = NdArray::from_shape_fn(out_shape, |idx| {
+            for (axis, s) in src.iter_mut().enumerate() {
+                let before = self.paddings[axis].0;
+                match pad_source_index(idx[axis], before, x.shape()[axis], self.mode) {
+                    Some(i) => *s = i,
+                    None => {
+                        if let PadMode::Constant(fill) = self.mode {
+                            return fill;
+                        }
+                    }
+                }
+            }
+            x[src.as_slice()]
+        });
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(PadGrad {
+                paddings: self.paddings.clone(),
+                mode: self.mode,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.paddings.hash(&mut state);
+        match self.mode {
+            PadMode::Constant(value) => (0, op::float_bits(value)).hash(&mut state),
+            PadMode::Reflect => 1.hash(&mut state),
+            PadMode::Edge => 2.hash(&mut state),
+        }
+    }
+}
+
+impl<T: Float> op::Op<T> for PadGrad<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        if self.paddings.len() != gy.ndim() {
+            return Err(op::OpError::IncompatibleShape(
+                "PadGrad: length of paddings must match the gradient's ndim".to_string(),
+            ));
+        }
+        let x_shape: Vec<usize> = gy
+            .shape()
+            .iter()
+            .zip(&self.paddings)
+            .map(|(&len, &(before, after))| len - before - after)
+            .collect();
+        let mut gx = NdArray::<T>::zeros(x_shape.as_slice());
+        let mut src = vec![0; gy.ndim()];
+        // Folds the gradients of the padded region back onto their source elements
+        'outer: for (idx, &g) in gy.indexed_iter() {
+            for (axis, s) in src.iter_mut().enumerate() {
+                let before = self.paddings[axis].0;
+                match pad_source_index(idx[axis], before, x_shape[axis], self.mode) {
+                    Some(i) => *s = i,
+                    None => continue 'outer,
+                }
+            }
+            gx[src.as_slice()] += g;
+        }
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // Transpose of the fold above; the fill value is a constant.
+        let mode = match self.mode {
+            PadMode::Constant(_) => PadMode::Constant(T::zero()),
+            mode => mode,
+        };
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(Pad {
+                paddings: self.paddings.clone(),
+                mode,
+            });
+        ctx.append_input_grad(Some(ggy));
+    }
+}
+
 impl<T: Float> op::Op<T> for Clip<T> {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let ret = ctx.input(0).map(move |a| a.min(self.max).max(self.min));
@@ -554,6 +1198,10 @@ impl<T: Float> op::Op<T> for Clip<T> {
             });
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (op::float_bits(self.min), op::float_bits(self.max)).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ClipGrad<T> {
@@ -573,6 +1221,48 @@ impl<T: Float> op::Op<T> for ClipGrad<T> {
     }
 }
 
+// The `q`th percentile of `values` with linear interpolation between the closest ranks, as in
+// numpy. `values` is reordered; it must be non-empty and free of NaNs.
+fn percentile<T: Float>(values: &mut [T], q: T) -> T {
+    let rank = q / T::from(100.).unwrap() * T::from(values.len() - 1).unwrap();
+    let i = rank.floor().to_usize().unwrap().min(values.len() - 1);
+    let frac = rank - T::from(i).unwrap();
+    // partial sort: only `values[i]` is in place, the larger values are after it
+    let (_, &mut lower, upper) = values.select_nth_unstable_by(i, |a, b| a.partial_cmp(b).unwrap());
+    match upper.iter().cloned().reduce(T::min) {
+        Some(next) if frac > T::zero() => lower + frac * (next - lower),
+        _ => lower,
+    }
+}
+
+impl<T: Float> op::Op<T> for ClipByPercentile<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let mut values: Vec<T> = x.iter().cloned().filter(|a| !a.is_nan()).collect();
+        if values.is_empty() {
+            let y = x.to_owned();
+            ctx.append_output(y);
+            return Ok(());
+        }
+        let low = percentile(&mut values, self.low);
+        let high = percentile(&mut values, self.high);
+        // NaNs stay NaNs
+        let y = x.mapv(|a| if a.is_nan() { a } else { a.max(low).min(high) });
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // passes through where `x` wasn't clamped; the bounds are constants
+        let gx = ctx.output_grad() * equal(ctx.input(0), ctx.output());
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (op::float_bits(self.low), op::float_bits(self.high)).hash(&mut state);
+    }
+}
+
 impl<T: Float> op::Op<T> for Concat {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let mut views = Vec::with_capacity(ctx.num_inputs());
@@ -602,8 +1292,12 @@ impl<T: Float> op::Op<T> for Concat {
         let inputs = ctx.inputs();
 
         for i in 0..num_inputs {
+            if !ctx.needs_input_grad(i) {
+                ctx.append_input_grad(None);
+                continue;
+            }
             let mut builder = Tensor::builder(ctx.graph())
-                .set_shape(&shape(ctx.input(0)))
+                .set_shape(&shape(ctx.input(i)))
                 .append_input(&ctx.output_grad(), false);
 
             for input in inputs.iter() {
@@ -617,6 +1311,99 @@ impl<T: Float> op::Op<T> for Concat {
             ctx.append_input_grad(Some(gx));
         }
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Stack {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let mut views = Vec::with_capacity(ctx.num_inputs());
+        for i in 0..ctx.num_inputs() {
+            views.push(ctx.input(i));
+        }
+        if let Some(x) = views.iter().find(|x| x.shape() != views[0].shape()) {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "stack: all the inputs must have the same shape, got {:?} and {:?}",
+                views[0].shape(),
+                x.shape()
+            )));
+        }
+        let ndim = views[0].ndim() as isize + 1;
+        if self.axis < -ndim || self.axis >= ndim {
+            return Err(op::OpError::OutOfBounds(format!(
+                "stack: axis {} is out of bounds for an output of rank {}",
+                self.axis, ndim
+            )));
+        }
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, ndim as usize);
+        match ndarray::stack(ndarray::Axis(axis), views.as_slice()) {
+            Ok(y) => {
+                ctx.append_output(y);
+                Ok(())
+            }
+            Err(e) => Err(op::OpError::NdArrayError("stack".to_string(), e)),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // each input gets its slice of `gy` along `axis`
+        let gy = ctx.output_grad();
+        for i in 0..ctx.num_inputs() {
+            if !ctx.needs_input_grad(i) {
+                ctx.append_input_grad(None);
+                continue;
+            }
+            let slice = Tensor::builder(ctx.graph())
+                .append_input(&gy, false)
+                .build(Split {
+                    axis: self.axis,
+                    start_index: i as isize,
+                    end_index: i as isize + 1,
+                });
+            ctx.append_input_grad(Some(squeeze(slice, &[self.axis])));
+        }
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Unstack {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let ndim = x.ndim() as isize;
+        if self.axis < -ndim || self.axis >= ndim {
+            return Err(op::OpError::OutOfBounds(format!(
+                "unstack: axis {} is out of bounds for an input of rank {}",
+                self.axis, ndim
+            )));
+        }
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, x.ndim());
+        if x.shape()[axis] != self.num {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "unstack: expected {} slices along axis {}, got an input of shape {:?}",
+                self.num,
+                self.axis,
+                x.shape()
+            )));
+        }
+        for i in 0..self.num {
+            ctx.append_output_view(x.clone().index_axis_move(ndarray::Axis(axis), i));
+        }
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gys: Vec<_> = (0..self.num).map(|i| ctx.output_grad_at(i)).collect();
+        ctx.append_input_grad(Some(stack(&gys, self.axis)));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.num).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ConcatGrad {
@@ -629,9 +1416,9 @@ impl<T: Float> op::Op<T> for ConcatGrad {
             self.axis as usize
         };
 
-        // make slice indices
+        // make slice indices: the inputs of the concat follow `gy`
         let mut start_idx = 0;
-        for i in 1..self.index {
+        for i in 1..=self.index {
             start_idx += ctx.input(i).shape()[axis];
         }
         let region_len = ctx.input(self.index + 1).shape()[axis] as isize;
@@ -641,7 +1428,7 @@ impl<T: Float> op::Op<T> for ConcatGrad {
                     // partial region
                     SliceInfoElem::Slice {
                         start: start_idx as isize,
-                        end: Some(region_len),
+                        end: Some(start_idx as isize + region_len),
                         step: 1,
                     }
                 } else {
@@ -693,6 +1480,65 @@ impl<T: Float> op::Op<T> for Tile {
     fn grad(&self, ctx: &mut op::GradientContext<T>) {
         ctx.append_input_grad(Some(reduce_sum(ctx.output_grad(), &[self.axis], true)));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.num).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for BroadcastTo {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let target_shape = ndarray_ext::as_shape(&ctx.input(1));
+        match x.broadcast(target_shape.as_slice()) {
+            Some(ret) => {
+                ctx.append_output(ret.to_owned());
+                Ok(())
+            }
+            None => Err(op::OpError::IncompatibleShape(format!(
+                "broadcast_to: cannot broadcast {:?} to {:?}",
+                x.shape(),
+                target_shape
+            ))),
+        }
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(&ctx.output_grad(), false)
+            .append_input(&ctx.input(0), false)
+            .set_shape(&shape(ctx.input(0)))
+            .build(BroadcastToGrad);
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for BroadcastToGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let x_shape = ctx.input(1).shape().to_vec();
+        // the leading axes added by the broadcast are summed away
+        let num_leading = gy.ndim() - x_shape.len();
+        let mut gx = gy.to_owned();
+        for _ in 0..num_leading {
+            gx = gx.sum_axis(ndarray::Axis(0));
+        }
+        // and the ones stretched from size 1 are summed into it
+        for (i, &d) in x_shape.iter().enumerate() {
+            if d == 1 && gx.shape()[i] != 1 {
+                gx = gx.sum_axis(ndarray::Axis(i)).insert_axis(ndarray::Axis(i));
+            }
+        }
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggy = broadcast_to(ctx.output_grad(), &shape(ctx.input(0)));
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+    }
 }
 
 impl<T: Float> op::Op<T> for Split {
@@ -721,6 +1567,10 @@ impl<T: Float> op::Op<T> for Split {
             .build(op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.start_index, self.end_index).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for SplitGrad {
@@ -777,9 +1627,45 @@ fn make_indices_for_split<T: Float>(
         .collect::<Vec<_>>()
 }
 
+// Whether `elem` can slice an axis of length `len`
+pub(crate) fn slice_in_bounds(elem: SliceInfoElem, len: usize) -> bool {
+    let in_bounds = |i: isize, inclusive: bool| {
+        let i = if i < 0 { i + len as isize } else { i };
+        0 <= i && (i as usize) < len + inclusive as usize
+    };
+    match elem {
+        SliceInfoElem::NewAxis => true,
+        SliceInfoElem::Slice { start, end, step } => {
+            step != 0 && in_bounds(start, true) && end.is_none_or(|end| in_bounds(end, true))
+        }
+        SliceInfoElem::Index(i) => in_bounds(i, false),
+    }
+}
+
+impl Slice {
+    // `slice_collapse` panics on these, so they are reported as errors instead.
+    fn check_bounds(&self, shape: &[usize]) -> Result<(), op::OpError> {
+        let mut axis = 0;
+        for elem in &self.indices {
+            if let SliceInfoElem::NewAxis = elem {
+                continue;
+            }
+            if !matches!(shape.get(axis), Some(&len) if slice_in_bounds(*elem, len)) {
+                return Err(op::OpError::OutOfBounds(format!(
+                    "slice: {:?} is out of bounds for shape {:?}",
+                    elem, shape
+                )));
+            }
+            axis += 1;
+        }
+        Ok(())
+    }
+}
+
 impl<T: Float> op::Op<T> for Slice {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let mut y = ctx.input(0);
+        self.check_bounds(y.shape())?;
         y.slice_collapse(self.indices.as_slice());
         ctx.append_output_view(y);
         Ok(())
@@ -798,6 +1684,10 @@ impl<T: Float> op::Op<T> for Slice {
             .build(op);
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.indices.hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for SliceGrad {
@@ -823,6 +1713,94 @@ impl<T: Float> op::Op<T> for SliceGrad {
         ctx.append_input_grad(None);
     }
 }
+// Resolves a negative `axis` and returns the axis order moving it to the end.
+fn window_axes(axis: isize, ndim: usize) -> Result<(usize, Vec<usize>), op::OpError> {
+    let resolved = if axis < 0 { axis + ndim as isize } else { axis };
+    if resolved < 0 || resolved >= ndim as isize {
+        return Err(op::OpError::OutOfBounds(format!(
+            "sliding_window: axis {} is out of bounds for rank {}",
+            axis, ndim
+        )));
+    }
+    let axis = resolved as usize;
+    let mut perm: Vec<usize> = (0..ndim).filter(|&i| i != axis).collect();
+    perm.push(axis);
+    Ok((axis, perm))
+}
+
+impl<T: Float> op::Op<T> for SlidingWindow {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let (axis, perm) = window_axes(self.axis, x.ndim())?;
+        let len = x.shape()[axis];
+        if len < self.size {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "sliding_window: window size {} is larger than the axis length {}",
+                self.size, len
+            )));
+        }
+        let windows: Vec<_> = (0..=len - self.size)
+            .step_by(self.stride)
+            .map(|start| {
+                x.slice_axis(ndarray::Axis(axis), (start..start + self.size).into())
+                    .permuted_axes(perm.as_slice())
+            })
+            .collect();
+        let y = ndarray::stack(ndarray::Axis(axis), &windows).unwrap();
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(SlidingWindowGrad {
+                size: self.size,
+                stride: self.stride,
+                axis: self.axis,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.size, self.stride, self.axis).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for SlidingWindowGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        let (axis, perm) = window_axes(self.axis, x.ndim())?;
+        let mut gx = NdArray::zeros(x.shape());
+        // overlapping windows accumulate into the same elements
+        for (i, gy) in gy.axis_iter(ndarray::Axis(axis)).enumerate() {
+            let start = i * self.stride;
+            let mut dst = gx
+                .slice_axis_mut(ndarray::Axis(axis), (start..start + self.size).into())
+                .permuted_axes(perm.as_slice());
+            dst += &gy;
+        }
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(SlidingWindow {
+                size: self.size,
+                stride: self.stride,
+                axis: self.axis,
+            });
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+    }
+}
+
 impl<T: Float> op::Op<T> for Squeeze {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let mut x = ctx.input(0).clone();
@@ -880,3 +1858,107 @@ impl<T: Float> op::Op<T> for ExpandDims {
         ctx.append_input_grad(None);
     }
 }
+
+fn lane_axis(name: &str, axis: isize, shape: &[usize]) -> Result<usize, op::OpError> {
+    let ret = ndarray_ext::normalize_negative_axis(axis, shape.len());
+    if ret >= shape.len() {
+        return Err(op::OpError::OutOfBounds(format!(
+            "{}: axis {} is out of bounds for shape {:?}",
+            name, axis, shape
+        )));
+    }
+    Ok(ret)
+}
+
+fn sort_order<T: Float>(a: T, b: T, descending: bool) -> std::cmp::Ordering {
+    use std::cmp::Ordering;
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        // safe unwraps: no NaN
+        _ if descending => b.partial_cmp(&a).unwrap(),
+        _ => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+impl<T: Float> op::Op<T> for ArgSort {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let axis = ndarray::Axis(lane_axis("argsort", self.axis, x.shape())?);
+        let mut y = NdArray::<T>::zeros(x.raw_dim());
+        let mut order = Vec::with_capacity(x.len_of(axis));
+        for (xl, mut yl) in x.lanes(axis).into_iter().zip(y.lanes_mut(axis)) {
+            order.clear();
+            order.extend(0..xl.len());
+            // `sort_by` is stable
+            order.sort_by(|&i, &j| sort_order(xl[i], xl[j], self.descending));
+            for (dst, &i) in yl.iter_mut().zip(&order) {
+                *dst = T::from(i).unwrap();
+            }
+        }
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.descending).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for TakeAlongAxis {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let indices = ctx.input(1);
+        if x.shape() != indices.shape() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "take_along_axis: indices of shape {:?} for input of shape {:?}",
+                indices.shape(),
+                x.shape()
+            )));
+        }
+        let axis = ndarray::Axis(lane_axis("take_along_axis", self.axis, x.shape())?);
+        let len = x.len_of(axis);
+        let mut y = NdArray::<T>::zeros(x.raw_dim());
+        let lanes = x.lanes(axis).into_iter().zip(indices.lanes(axis));
+        for ((xl, il), mut yl) in lanes.zip(y.lanes_mut(axis)) {
+            for (k, &i) in il.iter().enumerate() {
+                let i = i.to_usize().filter(|&i| i < len).ok_or_else(|| {
+                    op::OpError::OutOfBounds(format!(
+                        "take_along_axis: index {} is out of bounds for axis of length {}",
+                        i, len
+                    ))
+                })?;
+                if self.inverse {
+                    yl[i] = xl[k];
+                } else {
+                    yl[k] = xl[i];
+                }
+            }
+        }
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let indices = ctx.input(1);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(indices, false)
+            .set_shape(&shape(indices))
+            .build(TakeAlongAxis {
+                axis: self.axis,
+                inverse: !self.inverse,
+            });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.inverse).hash(&mut state);
+    }
+}
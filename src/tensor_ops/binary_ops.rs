@@ -1,6 +1,7 @@
-use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::backend::BinaryOp;
+use crate::ndarray_ext::{broadcast_shapes, is_scalar_shape, NdArray, NdArrayView};
 use crate::op;
-use crate::tensor::Tensor;
+use crate::tensor::{broadcast_dims, Dim, Tensor};
 use crate::tensor_ops::*;
 use crate::Float;
 use crate::Graph;
@@ -9,6 +10,8 @@ use ndarray::Axis;
 use std::mem;
 
 pub struct AddOp;
+// `AddOp` writing into its first input when the evaluation allows it, see `Graph::compile`
+pub struct AddInplaceOp;
 pub struct SubOp;
 pub struct MulOp;
 pub struct DivOp;
@@ -130,9 +133,11 @@ impl<T: Float> op::Op<T> for MaybeBroadcast {
             ctx.append_output(ret.to_owned());
             Ok(())
         } else {
-            Err(op::OpError::IncompatibleShape(
-                "PreprocessBinOpGradGrad: Can't broadcast.".to_string(),
-            ))
+            Err(op::OpError::IncompatibleShape(format!(
+                "cannot broadcast {:?} to {:?}",
+                input.shape(),
+                target_shape
+            )))
         }
     }
 
@@ -144,9 +149,30 @@ impl<T: Float> op::Op<T> for MaybeBroadcast {
     }
 }
 
+// Fails with a readable error instead of ndarray's panic when the shapes don't broadcast.
+#[inline]
+fn check_broadcast<T: Float>(x0: &NdArrayView<T>, x1: &NdArrayView<T>) -> Result<(), op::OpError> {
+    let (shape0, shape1) = (x0.shape(), x1.shape());
+    if shape0 == shape1 || is_scalar_shape(shape0) || is_scalar_shape(shape1) {
+        return Ok(());
+    }
+    broadcast_shapes(shape0, shape1).map(|_| ())
+}
+
+// Static shape of the elementwise binary ops, which broadcast their inputs
+fn infer_broadcast_shape(inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+    match (&inputs[0], &inputs[1]) {
+        (Some(a), Some(b)) => broadcast_dims(a, b).map(Some),
+        _ => Ok(None),
+    }
+}
+
 impl<T: Float> op::Op<T> for AddOp {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
-        let ret = add_forward(&ctx.input(0), &ctx.input(1));
+        let x0 = ctx.input(0);
+        let x1 = ctx.input(1);
+        check_broadcast(&x0, &x1)?;
+        let ret = ctx.binary(BinaryOp::Add, &x0, &x1);
         ctx.append_output(ret);
         Ok(())
     }
@@ -158,10 +184,55 @@ impl<T: Float> op::Op<T> for AddOp {
         let gy = ctx.output_grad();
         let shape0 = &shape(x0);
         let shape1 = &shape(x1);
-        let gy0 = maybe_reduce(shape0, &gy, g);
-        let gy1 = maybe_reduce(shape1, &gy, g);
-        ctx.append_input_grad(Some(gy0));
-        ctx.append_input_grad(Some(gy1));
+        let gy0 = ctx
+            .needs_input_grad(0)
+            .then(|| maybe_reduce(shape0, &gy, g));
+        let gy1 = ctx
+            .needs_input_grad(1)
+            .then(|| maybe_reduce(shape1, &gy, g));
+        ctx.append_input_grad(gy0);
+        ctx.append_input_grad(gy1);
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        // only the shapes, to reduce the gradient
+        &[false, false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        infer_broadcast_shape(inputs)
+    }
+}
+
+impl<T: Float> op::Op<T> for AddInplaceOp {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        if !ctx.input_is_mut(0) {
+            return AddOp.compute(ctx);
+        }
+        let mut x0 = ctx.input_mut(0);
+        let x1 = ctx.input(1);
+        if x1.broadcast(x0.shape()).is_none() {
+            // the sum is larger than `x0`
+            check_broadcast(&x0.view(), &x1)?;
+            let ret = ctx.binary(BinaryOp::Add, &x0.view(), &x1);
+            ctx.append_output(ret);
+            return Ok(());
+        }
+        x0.zip_mut_with(&x1, |a, &b| *a = *a + b);
+        ctx.append_output_view_raw(x0.raw_view());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        AddOp.grad(ctx)
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[false, false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        infer_broadcast_shape(inputs)
     }
 }
 
@@ -169,25 +240,8 @@ impl<T: Float> op::Op<T> for SubOp {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let x0 = &ctx.input(0);
         let x1 = &ctx.input(1);
-        let shape0: &[usize] = x0.shape();
-        let shape1: &[usize] = x1.shape();
-        let ret = if shape0.len() == 0 {
-            // is scalar
-            let x0_elem = x0[ndarray::IxDyn(&[])];
-            x1.map(move |&a| x0_elem - a)
-        } else if shape0 == shape1 {
-            #[cfg(feature = "mkl")]
-            {
-                use crate::{same_type, tensor_ops::blas_ffi::*};
-                bin_op_same_shape!(vsSub, vdSub, -, x0, x1)
-            }
-            #[cfg(not(feature = "mkl"))]
-            {
-                x0 - x1
-            }
-        } else {
-            x0 - x1
-        };
+        check_broadcast(x0, x1)?;
+        let ret = ctx.binary(BinaryOp::Sub, x0, x1);
         ctx.append_output(ret);
         Ok(())
     }
@@ -199,10 +253,21 @@ impl<T: Float> op::Op<T> for SubOp {
         let shape0 = &shape(x0);
         let shape1 = &shape(x1);
         let gy = &ctx.output_grad();
-        let gy0 = maybe_reduce(shape0, gy, g);
-        let gy1 = maybe_reduce(shape1, gy, g);
-        ctx.append_input_grad(Some(gy0));
-        ctx.append_input_grad(Some(neg(&gy1)));
+        let gy0 = ctx.needs_input_grad(0).then(|| maybe_reduce(shape0, gy, g));
+        let gy1 = ctx
+            .needs_input_grad(1)
+            .then(|| neg(maybe_reduce(shape1, gy, g)));
+        ctx.append_input_grad(gy0);
+        ctx.append_input_grad(gy1);
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        // only the shapes, to reduce the gradient
+        &[false, false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        infer_broadcast_shape(inputs)
     }
 }
 
@@ -210,7 +275,8 @@ impl<T: Float> op::Op<T> for MulOp {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let a = ctx.input(0);
         let b = ctx.input(1);
-        let ret = mul_forward(&a, &b);
+        check_broadcast(&a, &b)?;
+        let ret = ctx.binary(BinaryOp::Mul, &a, &b);
         ctx.append_output(ret);
         Ok(())
     }
@@ -225,14 +291,19 @@ impl<T: Float> op::Op<T> for MulOp {
 
         let gy = ctx.output_grad();
 
-        let gx0 = gy * x1;
-        let gx1 = gy * x0;
+        let gx0 = ctx
+            .needs_input_grad(0)
+            .then(|| maybe_reduce(shape0, &(gy * x1), graph));
+        let gx1 = ctx
+            .needs_input_grad(1)
+            .then(|| maybe_reduce(shape1, &(gy * x0), graph));
 
-        let gx0 = maybe_reduce(shape0, &gx0, graph);
-        let gx1 = maybe_reduce(shape1, &gx1, graph);
+        ctx.append_input_grad(gx0);
+        ctx.append_input_grad(gx1);
+    }
 
-        ctx.append_input_grad(Some(gx0));
-        ctx.append_input_grad(Some(gx1));
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        infer_broadcast_shape(inputs)
     }
 }
 
@@ -240,32 +311,8 @@ impl<T: Float> op::Op<T> for DivOp {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         let x0 = &ctx.input(0);
         let x1 = &ctx.input(1);
-        let shape0: &[usize] = x0.shape();
-        let shape1: &[usize] = x1.shape();
-        let is_scalar0 = shape0.len() == 0 || shape0 == [0];
-        let is_scalar1 = shape1.len() == 0 || shape1 == [1];
-        let ret = if is_scalar0 {
-            // a is a scalar
-            let x0_elem = x0[ndarray::IxDyn(&[])];
-            x1.map(move |&a| x0_elem / a)
-        } else if is_scalar1 {
-            // b is a scalar
-            let x1_elem = x1[ndarray::IxDyn(&[])];
-            let rhs = T::one() / x1_elem;
-            x0.mapv(|x0_elem| x0_elem * rhs)
-        } else if shape0 == shape1 {
-            #[cfg(feature = "mkl")]
-            {
-                use crate::{same_type, tensor_ops::blas_ffi::*};
-                bin_op_same_shape!(vsDiv, vdDiv, /, x0, x1)
-            }
-            #[cfg(not(feature = "mkl"))]
-            {
-                x0 / x1
-            }
-        } else {
-            x0 / x1
-        };
+        check_broadcast(x0, x1)?;
+        let ret = ctx.binary(BinaryOp::Div, x0, x1);
         ctx.append_output(ret);
         Ok(())
     }
@@ -278,14 +325,20 @@ impl<T: Float> op::Op<T> for DivOp {
         let shape1 = &shape(x1);
         let gy = ctx.output_grad();
 
-        let gx0 = gy / x1;
-        let gx1 = neg(x0) * pow(x1, T::from(-2.).unwrap()) * gy;
+        let gx0 = ctx
+            .needs_input_grad(0)
+            .then(|| maybe_reduce(shape0, &(gy / x1), g));
+        let gx1 = ctx.needs_input_grad(1).then(|| {
+            let gx1 = neg(x0) * pow(x1, T::from(-2.).unwrap()) * gy;
+            maybe_reduce(shape1, &gx1, g)
+        });
 
-        let gx0 = maybe_reduce(shape0, &gx0, g);
-        let gx1 = maybe_reduce(shape1, &gx1, g);
+        ctx.append_input_grad(gx0);
+        ctx.append_input_grad(gx1);
+    }
 
-        ctx.append_input_grad(Some(gx0));
-        ctx.append_input_grad(Some(gx1));
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        infer_broadcast_shape(inputs)
     }
 }
 
@@ -303,7 +356,7 @@ fn maybe_reduce<'g, T: Float>(
 
 macro_rules! impl_bin_op_forward {
     ($forward_name:ident, $bin_op:tt, $vms_op:ident, $vmd_op:ident) => {
-        fn $forward_name<'v, T: Float>(x0: &NdArrayView<'v, T>, x1: &NdArrayView<'v, T>) -> NdArray<T>
+        pub(crate) fn $forward_name<T: Float>(x0: &NdArrayView<T>, x1: &NdArrayView<T>) -> NdArray<T>
         {
             let shape0: &[usize] = x0.shape();
             let shape1: &[usize] = x1.shape();
@@ -345,3 +398,54 @@ macro_rules! impl_bin_op_forward {
 
 impl_bin_op_forward!(add_forward, +, vsAdd, vdAdd);
 impl_bin_op_forward!(mul_forward, *, vsMul, vdMul);
+
+pub(crate) fn sub_forward<T: Float>(x0: &NdArrayView<T>, x1: &NdArrayView<T>) -> NdArray<T> {
+    let shape0: &[usize] = x0.shape();
+    let shape1: &[usize] = x1.shape();
+    if shape0.len() == 0 {
+        // is scalar
+        let x0_elem = x0[ndarray::IxDyn(&[])];
+        x1.map(move |&a| x0_elem - a)
+    } else if shape0 == shape1 {
+        #[cfg(feature = "mkl")]
+        {
+            use crate::{same_type, tensor_ops::blas_ffi::*};
+            bin_op_same_shape!(vsSub, vdSub, -, x0, x1)
+        }
+        #[cfg(not(feature = "mkl"))]
+        {
+            x0 - x1
+        }
+    } else {
+        x0 - x1
+    }
+}
+
+pub(crate) fn div_forward<T: Float>(x0: &NdArrayView<T>, x1: &NdArrayView<T>) -> NdArray<T> {
+    let shape0: &[usize] = x0.shape();
+    let shape1: &[usize] = x1.shape();
+    let is_scalar0 = shape0.len() == 0 || shape0 == [0];
+    let is_scalar1 = shape1.len() == 0 || shape1 == [1];
+    if is_scalar0 {
+        // a is a scalar
+        let x0_elem = x0[ndarray::IxDyn(&[])];
+        x1.map(move |&a| x0_elem / a)
+    } else if is_scalar1 {
+        // b is a scalar
+        let x1_elem = x1[ndarray::IxDyn(&[])];
+        let rhs = T::one() / x1_elem;
+        x0.mapv(|x0_elem| x0_elem * rhs)
+    } else if shape0 == shape1 {
+        #[cfg(feature = "mkl")]
+        {
+            use crate::{same_type, tensor_ops::blas_ffi::*};
+            bin_op_same_shape!(vsDiv, vdDiv, /, x0, x1)
+        }
+        #[cfg(not(feature = "mkl"))]
+        {
+            x0 / x1
+        }
+    } else {
+        x0 / x1
+    }
+}
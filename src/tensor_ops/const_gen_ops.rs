@@ -1,8 +1,10 @@
 use crate::ndarray_ext;
 use crate::ndarray_ext::NdArray;
 use crate::op;
+use crate::tensor::Dim;
 use crate::Float;
 use ndarray;
+use std::hash::{Hash, Hasher};
 
 pub struct Zeros;
 pub struct Ones;
@@ -22,27 +24,24 @@ impl<T: Float> op::Op<T> for Scalar<T> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn infer_shape(&self, _: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(Some(Vec::new()))
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        op::float_bits(self.val).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for Zeros {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = &ctx.input(0);
-        let ret = if let Some(a) = shape.as_slice() {
-            ndarray_ext::zeros(
-                a.iter()
-                    .map(|&b| b.to_usize().unwrap())
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            )
-        } else {
-            ndarray_ext::zeros(
-                shape
-                    .iter()
-                    .map(|&b| b.to_usize().unwrap())
-                    .collect::<Vec<_>>()
-                    .as_slice(),
-            )
-        };
+        let shape = shape
+            .iter()
+            .map(|&b| b.to_usize().unwrap())
+            .collect::<Vec<_>>();
+        let ret = ctx.backend().zeros(&shape);
         ctx.append_output(ret);
         Ok(())
     }
@@ -87,4 +86,16 @@ impl<T: Float> op::Op<T> for ConvertToTensor<T> {
     }
 
     fn grad(&self, _: &mut crate::op::GradientContext<T>) {}
+
+    fn infer_shape(&self, _: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        let shape = self.arr.shape().iter().map(|&n| Dim::Known(n));
+        Ok(Some(shape.collect()))
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.arr.shape().hash(&mut state);
+        for &a in self.arr.iter() {
+            op::float_bits(a).hash(&mut state);
+        }
+    }
 }
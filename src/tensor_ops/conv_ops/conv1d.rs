@@ -0,0 +1,243 @@
+use super::*;
+use ndarray::Ix3;
+
+/// 1D convolution of an NCL tensor `x` with an `(out_channel, channel, filter)` tensor `w`.
+pub struct Conv1D {
+    pub pad: usize,
+    pub stride: usize,
+}
+
+/// Gradient of `Conv1D` w.r.t. `x`: inputs are `gy`, `w` and `x` (for its length).
+pub struct Conv1DInputGrad {
+    pub pad: usize,
+    pub stride: usize,
+}
+
+/// Gradient of `Conv1D` w.r.t. `w`: inputs are `x`, `gy` and `w` (for its filter size).
+pub struct Conv1DFilterGrad {
+    pub pad: usize,
+    pub stride: usize,
+}
+
+pub(super) fn check_ncl<T: Float>(x: &NdArrayView<T>, name: &str) -> Result<(), op::OpError> {
+    if x.ndim() != 3 {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: input must be an NCL tensor, got shape {:?}",
+            name,
+            x.shape()
+        )));
+    }
+    Ok(())
+}
+
+// The `(t, k, i)` triples where the output position `t` reads the input position `i`
+// through the tap `k` of a window of `size`, skipping the padding.
+pub(super) fn taps(
+    len: usize,
+    size: usize,
+    pad: usize,
+    stride: usize,
+    name: &str,
+) -> Result<(usize, Vec<(usize, usize, usize)>), op::OpError> {
+    if len + 2 * pad < size {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: window of {} longer than the padded length {}",
+            name,
+            size,
+            len + 2 * pad
+        )));
+    }
+    let out_len = (len + 2 * pad - size) / stride + 1;
+    let mut ret = Vec::with_capacity(out_len * size);
+    for t in 0..out_len {
+        for k in 0..size {
+            let i = t * stride + k;
+            if i >= pad && i - pad < len {
+                ret.push((t, k, i - pad));
+            }
+        }
+    }
+    Ok((out_len, ret))
+}
+
+fn check_filter<T: Float>(
+    x: &NdArrayView<T>,
+    w: &NdArrayView<T>,
+    name: &str,
+) -> Result<(), op::OpError> {
+    check_ncl(x, name)?;
+    if w.ndim() != 3 || w.shape()[1] != x.shape()[1] {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: filter of shape {:?} doesn't match the input of shape {:?}",
+            name,
+            w.shape(),
+            x.shape()
+        )));
+    }
+    Ok(())
+}
+
+impl<T: Float> op::Op<T> for Conv1D {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let w = ctx.input(1);
+        check_filter(&x, &w, "conv1d")?;
+        let x = x.into_dimensionality::<Ix3>().unwrap();
+        let w = w.into_dimensionality::<Ix3>().unwrap();
+        let (batch, ch, len) = x.dim();
+        let (out_ch, _, size) = w.dim();
+        let (out_len, taps) = taps(len, size, self.pad, self.stride, "conv1d")?;
+
+        let mut y = ndarray::Array3::<T>::zeros((batch, out_ch, out_len));
+        for b in 0..batch {
+            for o in 0..out_ch {
+                for c in 0..ch {
+                    for &(t, k, i) in &taps {
+                        y[[b, o, t]] += w[[o, c, k]] * x[[b, c, i]];
+                    }
+                }
+            }
+        }
+        ctx.append_output(y.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        let x = ctx.input(0);
+        let w = ctx.input(1);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(gy, false)
+            .append_input(w, false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(Conv1DInputGrad {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        let gw = Tensor::builder(ctx.graph())
+            .append_input(x, false)
+            .append_input(gy, false)
+            .append_input(w, false)
+            .set_shape(&shape(w))
+            .build(Conv1DFilterGrad {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(Some(gw));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.pad, self.stride).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([("pad", self.pad.into()), ("stride", self.stride.into())])
+    }
+}
+
+impl<T: Float> op::Op<T> for Conv1DInputGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let w = ctx.input(1);
+        let x = ctx.input(2);
+        check_filter(&x, &w, "conv1d")?;
+        let gy = gy.into_dimensionality::<Ix3>().unwrap();
+        let w = w.into_dimensionality::<Ix3>().unwrap();
+        let (batch, ch, len) = (x.shape()[0], x.shape()[1], x.shape()[2]);
+        let (out_ch, _, size) = w.dim();
+        let (_, taps) = taps(len, size, self.pad, self.stride, "conv1d")?;
+
+        // every output position sends its gradient back through the taps it read
+        let mut gx = ndarray::Array3::<T>::zeros((batch, ch, len));
+        for b in 0..batch {
+            for o in 0..out_ch {
+                for c in 0..ch {
+                    for &(t, k, i) in &taps {
+                        gx[[b, c, i]] += w[[o, c, k]] * gy[[b, o, t]];
+                    }
+                }
+            }
+        }
+        ctx.append_output(gx.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggx = ctx.output_grad();
+        let gy = ctx.input(0);
+        let w = ctx.input(1);
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ggx, false)
+            .append_input(w, false)
+            .build(Conv1D {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        let gw = Tensor::builder(ctx.graph())
+            .append_input(ggx, false)
+            .append_input(gy, false)
+            .append_input(w, false)
+            .set_shape(&shape(w))
+            .build(Conv1DFilterGrad {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(Some(gw));
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for Conv1DFilterGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let gy = ctx.input(1);
+        let w = ctx.input(2);
+        check_filter(&x, &w, "conv1d")?;
+        let x = x.into_dimensionality::<Ix3>().unwrap();
+        let gy = gy.into_dimensionality::<Ix3>().unwrap();
+        let (batch, ch, len) = x.dim();
+        let (out_ch, size) = (w.shape()[0], w.shape()[2]);
+        let (_, taps) = taps(len, size, self.pad, self.stride, "conv1d")?;
+
+        let mut gw = ndarray::Array3::<T>::zeros((out_ch, ch, size));
+        for b in 0..batch {
+            for o in 0..out_ch {
+                for c in 0..ch {
+                    for &(t, k, i) in &taps {
+                        gw[[o, c, k]] += gy[[b, o, t]] * x[[b, c, i]];
+                    }
+                }
+            }
+        }
+        ctx.append_output(gw.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggw = ctx.output_grad();
+        let x = ctx.input(0);
+        let gy = ctx.input(1);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(gy, false)
+            .append_input(ggw, false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(Conv1DInputGrad {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(x, false)
+            .append_input(ggw, false)
+            .build(Conv1D {
+                pad: self.pad,
+                stride: self.stride,
+            });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+    }
+}
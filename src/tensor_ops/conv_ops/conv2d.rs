@@ -533,16 +533,18 @@ impl<T: Float> crate::op::Op<T> for Conv2D {
         // Grab inputs
         let x = &ctx.input(0);
         let w = &ctx.input(1);
-        let result = conv2d_impl(
-            x,
-            w,
-            self.pad,
-            self.pad,
-            self.stride,
-            self.stride,
-            self.dilation,
-            self.dilation,
-        );
+        let result = ctx.install(|| {
+            conv2d_impl(
+                x,
+                w,
+                self.pad,
+                self.pad,
+                self.stride,
+                self.stride,
+                self.dilation,
+                self.dilation,
+            )
+        });
         match result {
             Ok((y, cols)) => {
                 ctx.append_output(y);
@@ -584,6 +586,18 @@ impl<T: Float> crate::op::Op<T> for Conv2D {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(Some(gw));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.pad, self.stride, self.dilation).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("pad", self.pad.into()),
+            ("stride", self.stride.into()),
+            ("dilation", self.dilation.into()),
+        ])
+    }
 }
 
 impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
@@ -591,7 +605,7 @@ impl<T: Float> crate::op::Op<T> for Conv2DWithCols {
         // Grab inputs
         let cols = &ctx.input(0);
         let w = &ctx.input(1);
-        let y = conv2d_with_cols_impl(cols, w);
+        let y = ctx.install(|| conv2d_with_cols_impl(cols, w));
         ctx.append_output(y);
         Ok(())
     }
@@ -250,16 +250,18 @@ impl<T: Float> crate::op::Op<T> for Conv2DTranspose {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let gy = &ctx.input(0); // (batch, ych, yh, yw)
         let w = &ctx.input(1); // (ych, xch, kh, kw)
-        let gx = conv2d_transpose_impl(
-            gy,
-            w,
-            self.pad,
-            self.pad,
-            self.stride,
-            self.stride,
-            self.dilation,
-            self.dilation,
-        );
+        let gx = ctx.install(|| {
+            conv2d_transpose_impl(
+                gy,
+                w,
+                self.pad,
+                self.pad,
+                self.stride,
+                self.stride,
+                self.dilation,
+                self.dilation,
+            )
+        });
         match gx {
             Ok(gx) => {
                 ctx.append_output(gx);
@@ -298,6 +300,18 @@ impl<T: Float> crate::op::Op<T> for Conv2DTranspose {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(Some(gw));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.pad, self.stride, self.dilation).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("pad", self.pad.into()),
+            ("stride", self.stride.into()),
+            ("dilation", self.dilation.into()),
+        ])
+    }
 }
 
 fn conv2d_transpose_filter_grad_impl<F: Float>(
@@ -435,17 +449,19 @@ impl<T: Float> crate::op::Op<T> for Conv2DTransposeFilterGrad {
         let gy = &ctx.input(0);
         let x = &ctx.input(1);
         let w = &ctx.input(2);
-        let gw = conv2d_transpose_filter_grad_impl(
-            x,
-            w,
-            gy,
-            self.pad,
-            self.pad,
-            self.dilation,
-            self.dilation,
-            self.stride,
-            self.stride,
-        );
+        let gw = ctx.install(|| {
+            conv2d_transpose_filter_grad_impl(
+                x,
+                w,
+                gy,
+                self.pad,
+                self.pad,
+                self.dilation,
+                self.dilation,
+                self.stride,
+                self.stride,
+            )
+        });
         ctx.append_output(gw);
         Ok(())
     }
@@ -0,0 +1,183 @@
+use super::*;
+use ndarray::Ix4;
+
+/// How `grid_sample` treats the sampling points outside of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GridSamplePadding {
+    /// The pixels outside of the input are zeros.
+    Zeros,
+    /// The sampling points are clamped to the edges of the input.
+    Border,
+}
+
+/// Samples an NCHW tensor at the normalized `(x, y)` coordinates of an `(N, H_out, W_out, 2)` grid
+/// with bilinear interpolation (`align_corners=False` in PyTorch).
+pub struct GridSample {
+    pub padding: GridSamplePadding,
+}
+
+/// Gradients of `GridSample`: inputs are `gy`, `x` and `grid`, outputs are the gradients
+/// of `x` and `grid`.
+pub struct GridSampleGrad {
+    pub padding: GridSamplePadding,
+}
+
+// One of the (up to) four pixels interpolated at a sampling point:
+// `(row, col, weight, d weight / d grid_x, d weight / d grid_y)`.
+type Tap = (usize, usize, f64, f64, f64);
+
+// Pixel coordinate of the normalized coordinate `u` along an axis of length `len`,
+// and its derivative.
+fn unnormalize(u: f64, len: usize, padding: GridSamplePadding) -> (f64, f64) {
+    let len_f = len as f64;
+    let src = ((u + 1.) * len_f - 1.) / 2.;
+    match padding {
+        GridSamplePadding::Zeros => (src, len_f / 2.),
+        GridSamplePadding::Border => {
+            let clamped = src.max(0.).min(len_f - 1.);
+            (clamped, if clamped == src { len_f / 2. } else { 0. })
+        }
+    }
+}
+
+fn taps(u: f64, v: f64, h: usize, w: usize, padding: GridSamplePadding) -> Vec<Tap> {
+    let (ix, dix) = unnormalize(u, w, padding);
+    let (iy, diy) = unnormalize(v, h, padding);
+    let (x0, y0) = (ix.floor(), iy.floor());
+    let (fx, fy) = (ix - x0, iy - y0);
+    let mut ret = Vec::with_capacity(4);
+    for &(dy, wy, dwy) in &[(0., 1. - fy, -1.), (1., fy, 1.)] {
+        for &(dx, wx, dwx) in &[(0., 1. - fx, -1.), (1., fx, 1.)] {
+            let (row, col) = (y0 + dy, x0 + dx);
+            // with `Border`, an out of range corner only appears with weight 0
+            if row < 0. || col < 0. || row >= h as f64 || col >= w as f64 {
+                continue;
+            }
+            ret.push((
+                row as usize,
+                col as usize,
+                wy * wx,
+                wy * dwx * dix,
+                dwy * wx * diy,
+            ));
+        }
+    }
+    ret
+}
+
+fn check_inputs<T: Float>(x: &NdArrayView<T>, grid: &NdArrayView<T>) -> Result<(), op::OpError> {
+    if x.ndim() != 4 || x.shape()[2] == 0 || x.shape()[3] == 0 {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "grid_sample: input must be a non-empty NCHW tensor, got shape {:?}",
+            x.shape()
+        )));
+    }
+    if grid.ndim() != 4 || grid.shape()[3] != 2 || grid.shape()[0] != x.shape()[0] {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "grid_sample: grid must have shape ({}, h_out, w_out, 2), got {:?}",
+            x.shape()[0],
+            grid.shape()
+        )));
+    }
+    Ok(())
+}
+
+impl<T: Float> op::Op<T> for GridSample {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let grid = ctx.input(1);
+        check_inputs(&x, &grid)?;
+        let x = x.into_dimensionality::<Ix4>().unwrap();
+        let grid = grid.into_dimensionality::<Ix4>().unwrap();
+        let (batch, ch, h, w) = x.dim();
+        let (_, h_out, w_out, _) = grid.dim();
+
+        let mut y = ndarray::Array4::<T>::zeros((batch, ch, h_out, w_out));
+        for b in 0..batch {
+            for i in 0..h_out {
+                for j in 0..w_out {
+                    let u = grid[[b, i, j, 0]].to_f64().unwrap();
+                    let v = grid[[b, i, j, 1]].to_f64().unwrap();
+                    for (row, col, wt, _, _) in taps(u, v, h, w, self.padding) {
+                        let wt = T::from(wt).unwrap();
+                        for c in 0..ch {
+                            y[[b, c, i, j]] += wt * x[[b, c, row, col]];
+                        }
+                    }
+                }
+            }
+        }
+        ctx.append_output(y.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let grid = ctx.input(1);
+        let grads = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(x, false)
+            .append_input(grid, false)
+            .build(GridSampleGrad {
+                padding: self.padding,
+            });
+        ctx.append_input_grad(Some(nth_tensor(grads, 0)));
+        ctx.append_input_grad(Some(nth_tensor(grads, 1)));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.padding.hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([("padding", format!("{:?}", self.padding).into())])
+    }
+}
+
+impl<T: Float> op::Op<T> for GridSampleGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        let grid = ctx.input(2);
+        check_inputs(&x, &grid)?;
+        let gy = gy.into_dimensionality::<Ix4>().unwrap();
+        let x = x.into_dimensionality::<Ix4>().unwrap();
+        let grid = grid.into_dimensionality::<Ix4>().unwrap();
+        let (batch, ch, h, w) = x.dim();
+        let (_, h_out, w_out, _) = grid.dim();
+
+        let mut gx = ndarray::Array4::<T>::zeros((batch, ch, h, w));
+        let mut ggrid = ndarray::Array4::<T>::zeros((batch, h_out, w_out, 2));
+        for b in 0..batch {
+            for i in 0..h_out {
+                for j in 0..w_out {
+                    let u = grid[[b, i, j, 0]].to_f64().unwrap();
+                    let v = grid[[b, i, j, 1]].to_f64().unwrap();
+                    for (row, col, wt, dwu, dwv) in taps(u, v, h, w, self.padding) {
+                        let (wt, dwu, dwv) = (
+                            T::from(wt).unwrap(),
+                            T::from(dwu).unwrap(),
+                            T::from(dwv).unwrap(),
+                        );
+                        for c in 0..ch {
+                            let g = gy[[b, c, i, j]];
+                            let val = x[[b, c, row, col]];
+                            gx[[b, c, row, col]] += wt * g;
+                            ggrid[[b, i, j, 0]] += dwu * val * g;
+                            ggrid[[b, i, j, 1]] += dwv * val * g;
+                        }
+                    }
+                }
+            }
+        }
+        ctx.append_output(gx.into_dyn());
+        ctx.append_output(ggrid.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+}
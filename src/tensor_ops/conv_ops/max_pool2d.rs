@@ -240,6 +240,18 @@ impl<T: Float> crate::op::Op<T> for MaxPool2D {
             });
         ctx.append_input_grad(Some(gx));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.pad, self.stride, self.size).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("pad", self.pad.into()),
+            ("stride", self.stride.into()),
+            ("size", self.size.into()),
+        ])
+    }
 }
 
 impl<T: Float> crate::op::Op<T> for MaxPool2DGrad {
@@ -8,13 +8,18 @@ use crate::Tensor;
 use ndarray;
 use rayon::prelude::*;
 use std::f32;
+use std::hash::{Hash, Hasher};
 use std::slice;
 
+pub mod conv1d;
 #[macro_use]
 pub mod conv2d;
 #[macro_use]
 pub mod conv2d_transpose;
+pub mod grid_sample;
 pub mod max_pool2d;
+pub mod pool1d;
+pub mod upsample;
 #[cfg(feature = "blas")]
 use crate::tensor_ops::blas_ffi::*;
 #[cfg(feature = "blas")]
@@ -0,0 +1,218 @@
+use super::conv1d::{check_ncl, taps};
+use super::*;
+use ndarray::Ix3;
+
+/// 1D max pooling of an NCL tensor; the padding is never the max.
+///
+/// Stashes the position of the max of each window for the gradient.
+pub struct MaxPool1D {
+    pub size: usize,
+    pub pad: usize,
+    pub stride: usize,
+}
+
+/// Gradient of `MaxPool1D`: inputs are `gy`, the stashed positions and `x` (for its length).
+pub struct MaxPool1DGrad;
+
+/// Gradient of `MaxPool1DGrad`: inputs are `ggx` and the stashed positions.
+pub struct MaxPool1DGradGrad;
+
+/// 1D average pooling of an NCL tensor, the padding counting as zeros.
+pub struct AvgPool1D {
+    pub size: usize,
+    pub pad: usize,
+    pub stride: usize,
+}
+
+/// Gradient of `AvgPool1D`: inputs are `gy` and `x` (for its length).
+pub struct AvgPool1DGrad {
+    pub size: usize,
+    pub pad: usize,
+    pub stride: usize,
+}
+
+impl<T: Float> op::Op<T> for MaxPool1D {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        check_ncl(&x, "max_pool1d")?;
+        let x = x.into_dimensionality::<Ix3>().unwrap();
+        let (batch, ch, len) = x.dim();
+        let (out_len, taps) = taps(len, self.size, self.pad, self.stride, "max_pool1d")?;
+
+        let mut y = ndarray::Array3::<T>::from_elem((batch, ch, out_len), T::neg_infinity());
+        let mut argmax = ndarray::Array3::<T>::zeros((batch, ch, out_len));
+        for b in 0..batch {
+            for c in 0..ch {
+                for &(t, _, i) in &taps {
+                    if x[[b, c, i]] > y[[b, c, t]] {
+                        y[[b, c, t]] = x[[b, c, i]];
+                        argmax[[b, c, t]] = T::from(i).unwrap();
+                    }
+                }
+            }
+        }
+        ctx.append_output(y.into_dyn());
+        ctx.stash(argmax.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.stashed(0), false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(MaxPool1DGrad);
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.size, self.pad, self.stride).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("size", self.size.into()),
+            ("pad", self.pad.into()),
+            ("stride", self.stride.into()),
+        ])
+    }
+}
+
+impl<T: Float> op::Op<T> for MaxPool1DGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let argmax = ctx.input(1);
+        let x = ctx.input(2);
+        check_ncl(&x, "max_pool1d")?;
+        let mut gx = ndarray::Array3::<T>::zeros((x.shape()[0], x.shape()[1], x.shape()[2]));
+        // each window sends its gradient back to its max
+        let gy = gy.into_dimensionality::<Ix3>().unwrap();
+        for ((b, c, t), &g) in gy.indexed_iter() {
+            let i = argmax[[b, c, t]].to_usize().unwrap();
+            gx[[b, c, i]] += g;
+        }
+        ctx.append_output(gx.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.input(1), false)
+            .build(MaxPool1DGradGrad);
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for MaxPool1DGradGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let ggx = ctx.input(0);
+        let argmax = ctx.input(1);
+        let argmax = argmax.into_dimensionality::<Ix3>().unwrap();
+        let ggy = argmax
+            .indexed_iter()
+            .map(|((b, c, _), &i)| ggx[[b, c, i.to_usize().unwrap()]])
+            .collect();
+        let ggy = NdArray::from_shape_vec(argmax.shape(), ggy).unwrap();
+        ctx.append_output(ggy);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gggx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.input(1), false)
+            .append_input(ctx.input(0), false)
+            .set_shape(&shape(ctx.input(0)))
+            .build(MaxPool1DGrad);
+        ctx.append_input_grad(Some(gggx));
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for AvgPool1D {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        check_ncl(&x, "avg_pool1d")?;
+        let x = x.into_dimensionality::<Ix3>().unwrap();
+        let (batch, ch, len) = x.dim();
+        let (out_len, taps) = taps(len, self.size, self.pad, self.stride, "avg_pool1d")?;
+        let scale = T::one() / T::from(self.size).unwrap();
+
+        let mut y = ndarray::Array3::<T>::zeros((batch, ch, out_len));
+        for b in 0..batch {
+            for c in 0..ch {
+                for &(t, _, i) in &taps {
+                    y[[b, c, t]] += x[[b, c, i]] * scale;
+                }
+            }
+        }
+        ctx.append_output(y.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(AvgPool1DGrad {
+                size: self.size,
+                pad: self.pad,
+                stride: self.stride,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.size, self.pad, self.stride).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("size", self.size.into()),
+            ("pad", self.pad.into()),
+            ("stride", self.stride.into()),
+        ])
+    }
+}
+
+impl<T: Float> op::Op<T> for AvgPool1DGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        check_ncl(&x, "avg_pool1d")?;
+        let (batch, ch, len) = (x.shape()[0], x.shape()[1], x.shape()[2]);
+        let (_, taps) = taps(len, self.size, self.pad, self.stride, "avg_pool1d")?;
+        let scale = T::one() / T::from(self.size).unwrap();
+        let gy = gy.into_dimensionality::<Ix3>().unwrap();
+
+        let mut gx = ndarray::Array3::<T>::zeros((batch, ch, len));
+        for b in 0..batch {
+            for c in 0..ch {
+                for &(t, _, i) in &taps {
+                    gx[[b, c, i]] += gy[[b, c, t]] * scale;
+                }
+            }
+        }
+        ctx.append_output(gx.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(AvgPool1D {
+                size: self.size,
+                pad: self.pad,
+                stride: self.stride,
+            });
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+    }
+}
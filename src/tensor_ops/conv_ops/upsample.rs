@@ -0,0 +1,132 @@
+use super::*;
+use ndarray::Ix4;
+
+/// Upsamples the spatial dims of an NCHW tensor by `scale`.
+///
+/// With `bilinear`, each pixel interpolates its 4 nearest source pixels
+/// (half-pixel centers, as with `align_corners=False` in PyTorch); otherwise it copies
+/// the source pixel `floor(i / scale)`.
+pub struct Upsample2D {
+    pub scale: f64,
+    pub bilinear: bool,
+}
+
+/// Adjoint of `Upsample2D`: inputs are the gradient `gy` and the source `x`.
+pub struct Upsample2DGrad {
+    pub scale: f64,
+    pub bilinear: bool,
+}
+
+// For each output index along an axis of length `len`, the (up to) two source indices
+// and their weights.
+fn sampling_plan(len: usize, scale: f64, bilinear: bool) -> Vec<[(usize, f64); 2]> {
+    let out_len = (len as f64 * scale).floor() as usize;
+    let mut plan = Vec::with_capacity(out_len);
+    for i in 0..out_len {
+        if bilinear {
+            let src = ((i as f64 + 0.5) / scale - 0.5).max(0.);
+            let i0 = (src.floor() as usize).min(len - 1);
+            let i1 = (i0 + 1).min(len - 1);
+            let frac = src - i0 as f64;
+            plan.push([(i0, 1. - frac), (i1, frac)]);
+        } else {
+            let src = ((i as f64 / scale).floor() as usize).min(len - 1);
+            plan.push([(src, 1.), (src, 0.)]);
+        }
+    }
+    plan
+}
+
+fn check_nchw<T: Float>(x: &NdArrayView<T>, name: &str) -> Result<(), op::OpError> {
+    if x.ndim() != 4 || x.shape()[2] == 0 || x.shape()[3] == 0 {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: input must be a non-empty NCHW tensor, got shape {:?}",
+            name,
+            x.shape()
+        )));
+    }
+    Ok(())
+}
+
+impl<T: Float> op::Op<T> for Upsample2D {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        check_nchw(&x, "upsample2d")?;
+        let x = x.into_dimensionality::<Ix4>().unwrap();
+        let (batch, ch, h, w) = x.dim();
+        let plan_h = sampling_plan(h, self.scale, self.bilinear);
+        let plan_w = sampling_plan(w, self.scale, self.bilinear);
+
+        let mut y = ndarray::Array4::<T>::zeros((batch, ch, plan_h.len(), plan_w.len()));
+        for ((b, c, i, j), dst) in y.indexed_iter_mut() {
+            let mut acc = T::zero();
+            for &(si, wi) in &plan_h[i] {
+                for &(sj, wj) in &plan_w[j] {
+                    acc += T::from(wi * wj).unwrap() * x[[b, c, si, sj]];
+                }
+            }
+            *dst = acc;
+        }
+        ctx.append_output(y.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(Upsample2DGrad {
+                scale: self.scale,
+                bilinear: self.bilinear,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.scale.to_bits(), self.bilinear).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("scale", self.scale.into()),
+            ("bilinear", self.bilinear.into()),
+        ])
+    }
+}
+
+impl<T: Float> op::Op<T> for Upsample2DGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        check_nchw(&x, "upsample2d")?;
+        let (h, w) = (x.shape()[2], x.shape()[3]);
+        let plan_h = sampling_plan(h, self.scale, self.bilinear);
+        let plan_w = sampling_plan(w, self.scale, self.bilinear);
+        let gy = gy.into_dimensionality::<Ix4>().unwrap();
+
+        // every output pixel sends its gradient back to the pixels it was sampled from
+        let mut gx = ndarray::Array4::<T>::zeros((x.shape()[0], x.shape()[1], h, w));
+        for ((b, c, i, j), &g) in gy.indexed_iter() {
+            for &(si, wi) in &plan_h[i] {
+                for &(sj, wj) in &plan_w[j] {
+                    gx[[b, c, si, sj]] += T::from(wi * wj).unwrap() * g;
+                }
+            }
+        }
+        ctx.append_output(gx.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let ggy = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .build(Upsample2D {
+                scale: self.scale,
+                bilinear: self.bilinear,
+            });
+        ctx.append_input_grad(Some(ggy));
+        ctx.append_input_grad(None);
+    }
+}
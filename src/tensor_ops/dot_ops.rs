@@ -1,7 +1,7 @@
 /// Some gemm kernel usages are ported from ndarray
 use crate::ndarray_ext::NdArray;
 use crate::same_type;
-use crate::tensor::Tensor;
+use crate::tensor::{Dim, Tensor};
 #[cfg(feature = "blas")]
 use crate::tensor_ops::blas_ffi::*;
 use crate::Float;
@@ -15,6 +15,7 @@ use ndarray::{ArrayView2, ArrayViewMut2};
 use std::cmp;
 #[cfg(feature = "blas")]
 use std::mem;
+use std::hash::{Hash, Hasher};
 
 #[cfg(feature = "blas")]
 #[inline]
@@ -376,7 +377,7 @@ fn batch_mat_mul_impl_fast<F: Float>(
             call_kernel_def!(f64, cblas_dgemm);
         }
     }
-    batch_mat_mul_impl_slow(alpha, lhs, rhs, beta, c)
+    batch_mat_mul_impl_slow(alpha, lhs, rhs, beta, c, true)
 }
 
 /// C ← α A B + β C
@@ -430,6 +431,7 @@ fn batch_mat_mul_impl_slow<F: Float>(
     rhs: &NdArrayView<'_, F>,
     beta: F,
     c: &mut NdArrayViewMut<'_, F>,
+    parallel: bool,
 ) {
     let mut lhs_ = lhs.view();
     let mut rhs_ = rhs.view();
@@ -491,9 +493,12 @@ fn batch_mat_mul_impl_slow<F: Float>(
                     let rhs_iter = rhs_slice.par_iter().step_by(rhs_batch_size);
                     let c_iter = c_slice.par_iter_mut().step_by(c_batch_size);
 
+                    // a single job runs all the batches in order on one thread
+                    let min_len = if parallel { 1 } else { num_batches };
                     lhs_iter
                         .zip_eq(rhs_iter)
                         .zip_eq(c_iter)
+                        .with_min_len(min_len)
                         .for_each(|((lhs, rhs), c)| {
                             ::matrixmultiply::$f(
                                 m,
@@ -561,6 +566,41 @@ pub struct BatchMatMul {
     pub transpose_b: bool,
 }
 
+/// `MatMul` computed by `block` × `block` tiles, so the working set of the kernel stays small.
+pub struct TiledMatMul {
+    pub transpose_a: bool,
+    pub transpose_b: bool,
+    pub block: usize,
+}
+
+/// `a b` in a new matrix, column-major if both operands are. Skips BLAS unless `blas`.
+#[cfg_attr(not(feature = "blas"), allow(unused_variables))]
+pub(crate) fn mat_mul<T: Float>(a: &ArrayView2<T>, b: &ArrayView2<T>, blas: bool) -> ndarray::Array2<T> {
+    let ((m, _), (_, n)) = (a.dim(), b.dim());
+    let column_major = a.strides()[0] == 1 && b.strides()[0] == 1;
+    // A is Copy so this is safe
+    let mut v = Vec::with_capacity(m * n);
+    let mut c;
+    unsafe {
+        v.set_len(m * n);
+        c = ndarray::Array::from_shape_vec_unchecked((m, n).set_f(column_major), v);
+    }
+
+    #[cfg(feature = "blas")]
+    {
+        if blas {
+            mat_mul_impl_blas(T::one(), a, b, T::zero(), &mut c.view_mut());
+        } else {
+            mat_mul_impl_slow(T::one(), a, b, T::zero(), &mut c.view_mut());
+        }
+    }
+    #[cfg(not(feature = "blas"))]
+    {
+        mat_mul_impl_slow(T::one(), a, b, T::zero(), &mut c.view_mut());
+    }
+    c
+}
+
 impl<T: Float> op::Op<T> for MatMul {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let mut a = ctx
@@ -582,49 +622,82 @@ impl<T: Float> op::Op<T> for MatMul {
             return Err(op::OpError::IncompatibleShape(dot_shape_error(m, k, k2, n)));
         }
 
-        let lhs_s0 = a.strides()[0];
-        let rhs_s0 = b.strides()[0];
-        let column_major = lhs_s0 == 1 && rhs_s0 == 1;
-        // A is Copy so this is safe
-        let mut v = Vec::with_capacity(m * n);
-        let mut c;
-        unsafe {
-            v.set_len(m * n);
-            c = ndarray::Array::from_shape_vec_unchecked((m, n).set_f(column_major), v);
-        }
-
-        #[cfg(feature = "blas")]
-        {
-            mat_mul_impl_blas(T::one(), &a, &b, T::zero(), &mut c.view_mut());
-        }
-        #[cfg(not(feature = "blas"))]
-        {
-            mat_mul_impl_slow(T::one(), &a, &b, T::zero(), &mut c.view_mut());
-        }
+        let c = if ctx.reference_mode() {
+            mat_mul(&a, &b, false)
+        } else {
+            ctx.backend().matmul(&a, &b)
+        };
         ctx.append_output(c.into_dyn());
         Ok(())
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // With C = op(A) op(B): op(A)' = gy op(B)^T and op(B)' = op(A)^T gy.
         let gy = &ctx.output_grad();
-        let opa = Tensor::builder(ctx.graph())
-            .append_input(gy, false)
-            .append_input(&ctx.input(1), false)
-            .build(MatMul {
-                transpose_a: false,
-                transpose_b: true,
-            });
-
-        let opb = Tensor::builder(ctx.graph())
-            .append_input(&ctx.input(0), false)
-            .append_input(gy, false)
-            .build(MatMul {
-                transpose_a: true,
-                transpose_b: false,
-            });
-
-        ctx.append_input_grad(Some(opa));
-        ctx.append_input_grad(Some(opb));
+        let (a, b) = (&ctx.input(0), &ctx.input(1));
+        let (ta, tb) = (self.transpose_a, self.transpose_b);
+        let mm = |x, y, transpose_a, transpose_b| {
+            Tensor::builder(ctx.graph())
+                .append_input(x, false)
+                .append_input(y, false)
+                .build(MatMul {
+                    transpose_a,
+                    transpose_b,
+                })
+        };
+        let ga = if ta {
+            mm(b, gy, tb, true)
+        } else {
+            mm(gy, b, false, !tb)
+        };
+        let gb = if tb {
+            mm(gy, a, true, ta)
+        } else {
+            mm(a, gy, !ta, false)
+        };
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        let (a, b) = match (&inputs[0], &inputs[1]) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Ok(None),
+        };
+        if a.len() != 2 || b.len() != 2 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "matmul: inputs must be 2D, got {:?} and {:?}",
+                a, b
+            )));
+        }
+        let (m, k) = if self.transpose_a {
+            (a[1], a[0])
+        } else {
+            (a[0], a[1])
+        };
+        let (k2, n) = if self.transpose_b {
+            (b[1], b[0])
+        } else {
+            (b[0], b[1])
+        };
+        if k.unify(k2).is_none() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "matmul: inner dims of {:?} and {:?} differ",
+                a, b
+            )));
+        }
+        Ok(Some(vec![m, n]))
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.transpose_a, self.transpose_b).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("transpose_a", self.transpose_a.into()),
+            ("transpose_b", self.transpose_b.into()),
+        ])
     }
 }
 
@@ -671,6 +744,11 @@ impl<T: Float> op::Op<T> for BatchMatMul {
             ret[rank0 - 1] = shape1[rank0 - 1];
             ret
         };
+        if x0.is_empty() || x1.is_empty() {
+            // an empty batch, or sums over an empty inner dimension, which are 0
+            ctx.append_output(NdArray::zeros(ret_shape));
+            return Ok(());
+        }
         // A is Copy so this is safe
         let size: usize = ret_shape.iter().product();
         let mut v = Vec::with_capacity(size);
@@ -680,14 +758,22 @@ impl<T: Float> op::Op<T> for BatchMatMul {
             // BatchMatMul's ret val is a c-order array.
             c = ndarray::Array::from_shape_vec_unchecked(ret_shape, v);
         }
-        #[cfg(feature = "blas")]
-        {
-            batch_mat_mul_impl_fast(T::one(), &x0, &x1, T::zero(), &mut c.view_mut());
-        }
-        #[cfg(not(feature = "blas"))]
-        {
-            batch_mat_mul_impl_slow(T::one(), &x0, &x1, T::zero(), &mut c.view_mut())
-        }
+        let parallel = !ctx.reference_mode();
+        let mut c_view = c.view_mut();
+        ctx.install(|| {
+            #[cfg(feature = "blas")]
+            {
+                if parallel {
+                    batch_mat_mul_impl_fast(T::one(), &x0, &x1, T::zero(), &mut c_view);
+                } else {
+                    batch_mat_mul_impl_slow(T::one(), &x0, &x1, T::zero(), &mut c_view, false);
+                }
+            }
+            #[cfg(not(feature = "blas"))]
+            {
+                batch_mat_mul_impl_slow(T::one(), &x0, &x1, T::zero(), &mut c_view, parallel)
+            }
+        });
 
         // reshape to dst shape with safe unwrapping
         ctx.append_output(c);
@@ -695,25 +781,193 @@ impl<T: Float> op::Op<T> for BatchMatMul {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // With C = op(A) op(B): op(A)' = gy op(B)^T and op(B)' = op(A)^T gy.
         let gy = &ctx.output_grad();
-        let opa = Tensor::builder(ctx.graph())
-            .append_input(gy, false)
-            .append_input(&ctx.input(1), false)
-            .build(BatchMatMul {
-                transpose_a: false,
-                transpose_b: true,
-            });
-
-        let opb = Tensor::builder(ctx.graph())
-            .append_input(&ctx.input(0), false)
-            .append_input(gy, false)
-            .build(BatchMatMul {
-                transpose_a: true,
-                transpose_b: false,
-            });
-
-        ctx.append_input_grad(Some(opa));
-        ctx.append_input_grad(Some(opb));
+        let (a, b) = (&ctx.input(0), &ctx.input(1));
+        let (ta, tb) = (self.transpose_a, self.transpose_b);
+        let mm = |x, y, transpose_a, transpose_b| {
+            Tensor::builder(ctx.graph())
+                .append_input(x, false)
+                .append_input(y, false)
+                .build(BatchMatMul {
+                    transpose_a,
+                    transpose_b,
+                })
+        };
+        let ga = if ta {
+            mm(b, gy, tb, true)
+        } else {
+            mm(gy, b, false, !tb)
+        };
+        let gb = if tb {
+            mm(gy, a, true, ta)
+        } else {
+            mm(a, gy, !ta, false)
+        };
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.transpose_a, self.transpose_b).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("transpose_a", self.transpose_a.into()),
+            ("transpose_b", self.transpose_b.into()),
+        ])
+    }
+}
+
+impl<T: Float> op::Op<T> for TiledMatMul {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let mut a = ctx
+            .input(0)
+            .into_dimensionality::<ndarray::Ix2>()
+            .expect("lhs input for TiledMatMul must be 2D");
+        let mut b = ctx
+            .input(1)
+            .into_dimensionality::<ndarray::Ix2>()
+            .expect("rhs input for TiledMatMul must be 2D");
+        if self.transpose_a {
+            a.swap_axes(0, 1);
+        }
+        if self.transpose_b {
+            b.swap_axes(0, 1);
+        }
+        let ((m, k), (k2, n)) = (a.dim(), b.dim());
+        if k != k2 || m.checked_mul(n).is_none() {
+            return Err(op::OpError::IncompatibleShape(dot_shape_error(m, k, k2, n)));
+        }
+
+        let bs = self.block;
+        let mut c = ndarray::Array2::<T>::zeros((m, n));
+        for i in (0..m).step_by(bs) {
+            for j in (0..n).step_by(bs) {
+                let mut c = c.slice_mut(ndarray::s![i..(i + bs).min(m), j..(j + bs).min(n)]);
+                // accumulates the products of the tiles along k
+                for p in (0..k).step_by(bs) {
+                    let a = a.slice(ndarray::s![i..(i + bs).min(m), p..(p + bs).min(k)]);
+                    let b = b.slice(ndarray::s![p..(p + bs).min(k), j..(j + bs).min(n)]);
+                    #[cfg(feature = "blas")]
+                    {
+                        if ctx.reference_mode() {
+                            mat_mul_impl_slow(T::one(), &a, &b, T::one(), &mut c);
+                        } else {
+                            mat_mul_impl_blas(T::one(), &a, &b, T::one(), &mut c);
+                        }
+                    }
+                    #[cfg(not(feature = "blas"))]
+                    {
+                        mat_mul_impl_slow(T::one(), &a, &b, T::one(), &mut c);
+                    }
+                }
+            }
+        }
+        ctx.append_output(c.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // With C = op(A) op(B): op(A)' = gy op(B)^T and op(B)' = op(A)^T gy.
+        let gy = &ctx.output_grad();
+        let (a, b) = (&ctx.input(0), &ctx.input(1));
+        let (ta, tb) = (self.transpose_a, self.transpose_b);
+        let tiled = |x, y, transpose_a, transpose_b| {
+            Tensor::builder(ctx.graph())
+                .append_input(x, false)
+                .append_input(y, false)
+                .build(TiledMatMul {
+                    transpose_a,
+                    transpose_b,
+                    block: self.block,
+                })
+        };
+        let ga = if ta {
+            tiled(b, gy, tb, true)
+        } else {
+            tiled(gy, b, false, !tb)
+        };
+        let gb = if tb {
+            tiled(gy, a, true, ta)
+        } else {
+            tiled(a, gy, !ta, false)
+        };
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.transpose_a, self.transpose_b, self.block).hash(&mut state);
+    }
+}
+
+pub struct Outer;
+
+pub struct Dot;
+
+fn check_1d<T: Float>(x: &NdArrayView<T>, name: &str, side: &str) -> Result<(), op::OpError> {
+    if x.ndim() != 1 {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: {} input must be 1-D, actual shape: {:?}",
+            name,
+            side,
+            x.shape()
+        )));
+    }
+    Ok(())
+}
+
+impl<T: Float> op::Op<T> for Outer {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let a = ctx.input(0);
+        let b = ctx.input(1);
+        check_1d(&a, "outer", "Left-hand-side")?;
+        check_1d(&b, "outer", "Right-hand-side")?;
+        let (m, n) = (a.len(), b.len());
+        let ret = ndarray::Array::from_shape_fn((m, n), |(i, j)| a[i] * b[j]);
+        ctx.append_output(ret.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        use crate::tensor_ops::{expand_dims, reduce_sum};
+        let gy = ctx.output_grad();
+        let a = ctx.input(0);
+        let b = ctx.input(1);
+        let ga = reduce_sum(gy * expand_dims(b, &[0]), &[1], false);
+        let gb = reduce_sum(gy * expand_dims(a, &[1]), &[0], false);
+        ctx.append_input_grad(Some(ga));
+        ctx.append_input_grad(Some(gb));
+    }
+}
+
+impl<T: Float> op::Op<T> for Dot {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let a = ctx.input(0);
+        let b = ctx.input(1);
+        check_1d(&a, "dot", "Left-hand-side")?;
+        check_1d(&b, "dot", "Right-hand-side")?;
+        if a.len() != b.len() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "dot: input lengths mismatch: {} vs {}",
+                a.len(),
+                b.len()
+            )));
+        }
+        let ret = a
+            .iter()
+            .zip(b.iter())
+            .fold(T::zero(), |acc, (&x, &y)| acc + x * y);
+        ctx.append_output(ndarray::arr0(ret).into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(gy * ctx.input(1)));
+        ctx.append_input_grad(Some(gy * ctx.input(0)));
     }
 }
 
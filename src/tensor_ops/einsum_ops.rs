@@ -0,0 +1,231 @@
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::op;
+use crate::tensor::Tensor;
+use crate::tensor_ops::*;
+use crate::Float;
+use std::hash::{Hash, Hasher};
+
+/// Einstein summation over an arbitrary number of operands.
+///
+/// When `with_template` is true, the last input is not multiplied but only used to
+/// determine the sizes of the output labels (needed when an output label does not
+/// appear in any multiplied operand, e.g. in the backward of `"ij->i"`).
+pub struct Einsum {
+    pub inputs: Vec<Vec<u8>>,
+    pub output: Vec<u8>,
+    pub with_template: bool,
+}
+
+/// Parses `"ij,jk->ik"` style equations into per-operand labels and output labels.
+///
+/// When `->` is omitted, the output consists of the labels appearing exactly once,
+/// in alphabetical order (same as numpy).
+pub(crate) fn parse_equation(
+    equation: &str,
+    num_operands: usize,
+) -> Result<(Vec<Vec<u8>>, Vec<u8>), String> {
+    let equation: String = equation.chars().filter(|c| !c.is_whitespace()).collect();
+    let (lhs, rhs) = match equation.find("->") {
+        Some(pos) => (&equation[..pos], Some(&equation[pos + 2..])),
+        None => (&equation[..], None),
+    };
+    let validate = |labels: &str| -> Result<Vec<u8>, String> {
+        if let Some(c) = labels.chars().find(|c| !c.is_ascii_alphabetic()) {
+            return Err(format!("invalid label '{}' in \"{}\"", c, equation));
+        }
+        Ok(labels.as_bytes().to_vec())
+    };
+
+    let inputs = lhs
+        .split(',')
+        .map(validate)
+        .collect::<Result<Vec<_>, _>>()?;
+    if inputs.len() != num_operands {
+        return Err(format!(
+            "\"{}\" describes {} operand(s), but {} given",
+            equation,
+            inputs.len(),
+            num_operands
+        ));
+    }
+
+    let output = match rhs {
+        Some(rhs) => {
+            let output = validate(rhs)?;
+            for (i, l) in output.iter().enumerate() {
+                if output[..i].contains(l) {
+                    return Err(format!(
+                        "output label '{}' appears more than once in \"{}\"",
+                        *l as char, equation
+                    ));
+                }
+                if !inputs.iter().any(|labels| labels.contains(l)) {
+                    return Err(format!(
+                        "output label '{}' does not appear in the inputs of \"{}\"",
+                        *l as char, equation
+                    ));
+                }
+            }
+            output
+        }
+        None => {
+            let mut output: Vec<u8> = inputs
+                .iter()
+                .flatten()
+                .filter(|l| inputs.iter().flatten().filter(|m| m == l).count() == 1)
+                .cloned()
+                .collect();
+            output.sort_unstable();
+            output
+        }
+    };
+    Ok((inputs, output))
+}
+
+impl Einsum {
+    // Each label in order of first appearance, and its size.
+    fn label_sizes<T: Float>(
+        &self,
+        xs: &[NdArrayView<T>],
+    ) -> Result<(Vec<u8>, Vec<usize>), op::OpError> {
+        let mut labels: Vec<u8> = Vec::new();
+        let mut sizes: Vec<usize> = Vec::new();
+        let mut specs: Vec<&[u8]> = self.inputs.iter().map(|l| l.as_slice()).collect();
+        if self.with_template {
+            specs.push(self.output.as_slice());
+        }
+        for (i, (spec, x)) in specs.into_iter().zip(xs).enumerate() {
+            if x.ndim() != spec.len() {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "einsum: operand {} has {} dims, but its labels are \"{}\"",
+                    i,
+                    x.ndim(),
+                    String::from_utf8_lossy(spec)
+                )));
+            }
+            for (&l, &dim) in spec.iter().zip(x.shape()) {
+                match labels.iter().position(|&m| m == l) {
+                    Some(pos) if sizes[pos] != dim => {
+                        return Err(op::OpError::IncompatibleShape(format!(
+                            "einsum: label '{}' has inconsistent sizes {} and {}",
+                            l as char, sizes[pos], dim
+                        )));
+                    }
+                    Some(_) => {}
+                    None => {
+                        labels.push(l);
+                        sizes.push(dim);
+                    }
+                }
+            }
+        }
+        Ok((labels, sizes))
+    }
+}
+
+// Stride of each label in an array described by `spec` (repeated labels share the
+// sum of their strides, which walks the diagonal).
+fn label_strides(labels: &[u8], spec: &[u8], strides: &[isize]) -> Vec<isize> {
+    let mut ret = vec![0; labels.len()];
+    for (l, &s) in spec.iter().zip(strides) {
+        let pos = labels.iter().position(|m| m == l).unwrap();
+        ret[pos] += s;
+    }
+    ret
+}
+
+impl<T: Float> op::Op<T> for Einsum {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let xs: Vec<_> = (0..ctx.num_inputs()).map(|i| ctx.input(i)).collect();
+        let (labels, sizes) = self.label_sizes(&xs)?;
+        let out_shape: Vec<usize> = self
+            .output
+            .iter()
+            .map(|l| sizes[labels.iter().position(|m| m == l).unwrap()])
+            .collect();
+        let mut ret = NdArray::<T>::zeros(out_shape);
+
+        let operands = &xs[..self.inputs.len()];
+        let operand_strides: Vec<Vec<isize>> = operands
+            .iter()
+            .zip(&self.inputs)
+            .map(|(x, spec)| label_strides(&labels, spec, x.strides()))
+            .collect();
+        let out_strides = label_strides(&labels, &self.output, ret.strides());
+
+        if sizes.iter().all(|&s| s > 0) {
+            // Walks over every combination of the label indices.
+            let mut index = vec![0usize; labels.len()];
+            let mut offsets = vec![0isize; operands.len()];
+            let mut out_offset = 0isize;
+            let out_ptr = ret.as_mut_ptr();
+            loop {
+                let mut prod = T::one();
+                for (x, &offset) in operands.iter().zip(&offsets) {
+                    // safe: offsets always stay within each operand
+                    prod *= unsafe { *x.as_ptr().offset(offset) };
+                }
+                unsafe {
+                    *out_ptr.offset(out_offset) += prod;
+                }
+
+                // advance the innermost label
+                let mut axis = labels.len();
+                loop {
+                    if axis == 0 {
+                        ctx.append_output(ret);
+                        return Ok(());
+                    }
+                    axis -= 1;
+                    index[axis] += 1;
+                    for (offset, strides) in offsets.iter_mut().zip(&operand_strides) {
+                        *offset += strides[axis];
+                    }
+                    out_offset += out_strides[axis];
+                    if index[axis] < sizes[axis] {
+                        break;
+                    }
+                    let rewind = sizes[axis] as isize;
+                    for (offset, strides) in offsets.iter_mut().zip(&operand_strides) {
+                        *offset -= strides[axis] * rewind;
+                    }
+                    out_offset -= out_strides[axis] * rewind;
+                    index[axis] = 0;
+                }
+            }
+        }
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        let num_operands = self.inputs.len();
+        for k in 0..num_operands {
+            // d(out)/d(operand k): contract gy with the other operands back into k's labels.
+            let mut inputs = vec![self.output.clone()];
+            let mut builder = Tensor::builder(ctx.graph()).append_input(gy, false);
+            for j in (0..num_operands).filter(|&j| j != k) {
+                inputs.push(self.inputs[j].clone());
+                builder = builder.append_input(ctx.input(j), false);
+            }
+            let x = ctx.input(k);
+            let gx = builder
+                .append_input(x, false)
+                .set_shape(&shape(x))
+                .build(Einsum {
+                    inputs,
+                    output: self.inputs[k].clone(),
+                    with_template: true,
+                });
+            ctx.append_input_grad(Some(gx));
+        }
+        if self.with_template {
+            ctx.append_input_grad(None);
+        }
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (&self.inputs, &self.output, self.with_template).hash(&mut state);
+    }
+}
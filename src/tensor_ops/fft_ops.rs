@@ -0,0 +1,333 @@
+use crate::ndarray;
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::op;
+use crate::tensor::Tensor;
+use crate::tensor_ops::*;
+use crate::Float;
+use ndarray::{Axis, Zip};
+use num::complex::Complex;
+use std::hash::{Hash, Hasher};
+
+/// Stacks the real and imaginary parts into a complex tensor (last axis of size 2).
+pub struct MakeComplex;
+
+/// Extracts the real (`index == 0`) or imaginary (`index == 1`) part of a complex tensor.
+pub struct ComplexPart {
+    pub index: usize,
+}
+
+/// Discrete Fourier transform along an axis of a complex tensor.
+///
+/// `inverse` flips the sign of the exponent and `normalize` divides the result by the
+/// length of the axis, so `ifft` is `Fft { inverse: true, normalize: true, .. }`.
+pub struct Fft {
+    pub axis: isize,
+    pub inverse: bool,
+    pub normalize: bool,
+}
+
+/// DFT of a real tensor along `axis`, keeping the `n / 2 + 1` non-redundant bins.
+///
+/// With `scaled`, bin `k` is multiplied by its weight in `Irfft` (`1 / n` or `2 / n`),
+/// which makes this the adjoint of `Irfft { scaled: true, .. }`.
+pub struct Rfft {
+    pub axis: isize,
+    pub scaled: bool,
+}
+
+/// Inverse of `Rfft`: real signal of length `n` along `axis` from its `n / 2 + 1` bins.
+///
+/// When `n` is `None`, it's the length along `axis` of the second input.
+pub struct Irfft {
+    pub axis: isize,
+    pub n: Option<usize>,
+    pub scaled: bool,
+}
+
+fn check_complex<T: Float>(z: &NdArrayView<T>, name: &str) -> Result<(), op::OpError> {
+    if z.shape().last() != Some(&2) {
+        return Err(op::OpError::IncompatibleShape(format!(
+            "{}: complex tensors must have a last axis of size 2, got shape {:?}",
+            name,
+            z.shape()
+        )));
+    }
+    Ok(())
+}
+
+fn normalize_axis(axis: isize, ndim: usize, name: &str) -> Result<Axis, op::OpError> {
+    let ret = if axis < 0 { axis + ndim as isize } else { axis };
+    if ret < 0 || ret >= ndim as isize {
+        return Err(op::OpError::OutOfBounds(format!(
+            "{}: axis {} is out of bounds for rank {}",
+            name, axis, ndim
+        )));
+    }
+    Ok(Axis(ret as usize))
+}
+
+// Complex tensor from separate real and imaginary parts.
+fn stack_complex<T: Float>(z: &ndarray::ArrayD<Complex<T>>) -> NdArray<T> {
+    let re = z.mapv(|c| c.re);
+    let im = z.mapv(|c| c.im);
+    ndarray::stack(Axis(z.ndim()), &[re.view(), im.view()]).unwrap()
+}
+
+// Weight of bin `k` in the inverse real transform of length `n`.
+fn rfft_scale<T: Float>(scaled: bool, k: usize, n: usize) -> T {
+    if !scaled {
+        T::one()
+    } else if k == 0 || 2 * k == n {
+        T::one() / T::from(n).unwrap()
+    } else {
+        T::from(2.).unwrap() / T::from(n).unwrap()
+    }
+}
+
+impl<T: Float> op::Op<T> for MakeComplex {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let re = ctx.input(0);
+        let im = ctx.input(1);
+        if re.shape() != im.shape() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "complex: real part {:?} and imaginary part {:?} must have the same shape",
+                re.shape(),
+                im.shape()
+            )));
+        }
+        let z = ndarray::stack(Axis(re.ndim()), &[re, im]).unwrap();
+        ctx.append_output(z);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(real(gy)));
+        ctx.append_input_grad(Some(imag(gy)));
+    }
+}
+
+impl<T: Float> op::Op<T> for ComplexPart {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let z = ctx.input(0);
+        check_complex(&z, "complex part")?;
+        let y = z.index_axis(Axis(z.ndim() - 1), self.index).to_owned();
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        let zeros = zeros(&shape(gy), ctx.graph());
+        let gx = if self.index == 0 {
+            complex(gy, zeros)
+        } else {
+            complex(zeros, gy)
+        };
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.index.hash(&mut state);
+    }
+}
+
+// In-place transform of `z`; `z.len()` needn't be a power of two.
+fn transform<T: Float>(z: &mut [Complex<T>], inverse: bool) {
+    let n = z.len();
+    if n <= 1 {
+        return;
+    }
+    let sign = if inverse { T::one() } else { -T::one() };
+    let two_pi = T::from(2. * std::f64::consts::PI).unwrap();
+    if !n.is_power_of_two() {
+        // plain DFT
+        let x = z.to_vec();
+        for (k, zk) in z.iter_mut().enumerate() {
+            *zk = Complex::new(T::zero(), T::zero());
+            for (j, &xj) in x.iter().enumerate() {
+                let theta = sign * two_pi * T::from((j * k) % n).unwrap() / T::from(n).unwrap();
+                *zk += xj * Complex::new(theta.cos(), theta.sin());
+            }
+        }
+        return;
+    }
+
+    // iterative radix-2 Cooley-Tukey
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            z.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let theta = sign * two_pi / T::from(len).unwrap();
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let angle = theta * T::from(k).unwrap();
+                let w = Complex::new(angle.cos(), angle.sin());
+                let a = z[start + k];
+                let b = z[start + k + len / 2] * w;
+                z[start + k] = a + b;
+                z[start + k + len / 2] = a - b;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+impl<T: Float> op::Op<T> for Fft {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        check_complex(&x, "fft")?;
+        let axis = normalize_axis(self.axis, x.ndim() - 1, "fft")?;
+        let last = Axis(x.ndim() - 1);
+
+        let mut z = Zip::from(x.index_axis(last, 0))
+            .and(x.index_axis(last, 1))
+            .map_collect(|&re, &im| Complex::new(re, im));
+        let n = z.len_of(axis);
+        let mut buf = Vec::with_capacity(n);
+        for mut lane in z.lanes_mut(axis) {
+            buf.clear();
+            buf.extend(lane.iter().cloned());
+            transform(&mut buf, self.inverse);
+            for (dst, &src) in lane.iter_mut().zip(&buf) {
+                *dst = src;
+            }
+        }
+        if self.normalize && n > 0 {
+            let n = T::from(n).unwrap();
+            z.mapv_inplace(|c| c / n);
+        }
+        ctx.append_output(stack_complex(&z));
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // The adjoint of the unnormalized transform is the one with the opposite sign,
+        // and the real scaling passes through unchanged.
+        let gy = ctx.output_grad();
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(gy, false)
+            .set_shape(&shape(gy))
+            .build(Fft {
+                axis: self.axis,
+                inverse: !self.inverse,
+                normalize: self.normalize,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.inverse, self.normalize).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Rfft {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let axis = normalize_axis(self.axis, x.ndim(), "rfft")?;
+        let n = x.len_of(axis);
+        if n == 0 {
+            return Err(op::OpError::IncompatibleShape(
+                "rfft: the transformed axis is empty".to_string(),
+            ));
+        }
+        let mut shape = x.shape().to_vec();
+        shape[axis.index()] = n / 2 + 1;
+        let mut z = ndarray::ArrayD::<Complex<T>>::zeros(shape);
+        let mut buf = Vec::with_capacity(n);
+        for (src, mut dst) in x.lanes(axis).into_iter().zip(z.lanes_mut(axis)) {
+            buf.clear();
+            buf.extend(src.iter().map(|&a| Complex::new(a, T::zero())));
+            transform(&mut buf, false);
+            for (k, (dst, &src)) in dst.iter_mut().zip(&buf).enumerate() {
+                *dst = src * rfft_scale::<T>(self.scaled, k, n);
+            }
+        }
+        ctx.append_output(stack_complex(&z));
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(x, false)
+            .set_shape(&shape(x))
+            .build(Irfft {
+                axis: self.axis,
+                n: None,
+                scaled: self.scaled,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.scaled).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Irfft {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let z = ctx.input(0);
+        check_complex(&z, "irfft")?;
+        let axis = normalize_axis(self.axis, z.ndim() - 1, "irfft")?;
+        let n = match self.n {
+            Some(n) => n,
+            None => ctx.input(1).len_of(axis),
+        };
+        if z.len_of(axis) != n / 2 + 1 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "irfft: a signal of length {} has {} bins, got {}",
+                n,
+                n / 2 + 1,
+                z.len_of(axis)
+            )));
+        }
+        let last = Axis(z.ndim() - 1);
+        let (re, im) = (z.index_axis(last, 0), z.index_axis(last, 1));
+        let mut shape = re.shape().to_vec();
+        shape[axis.index()] = n;
+        let mut y = NdArray::<T>::zeros(shape);
+        let mut buf = Vec::with_capacity(n);
+        let lanes = re.lanes(axis).into_iter().zip(im.lanes(axis));
+        for ((re, im), mut dst) in lanes.zip(y.lanes_mut(axis)) {
+            buf.clear();
+            buf.extend(
+                re.iter().zip(&im).enumerate().map(|(k, (&re, &im))| {
+                    Complex::new(re, im) * rfft_scale::<T>(self.scaled, k, n)
+                }),
+            );
+            buf.resize(n, Complex::new(T::zero(), T::zero()));
+            transform(&mut buf, true);
+            for (dst, src) in dst.iter_mut().zip(&buf) {
+                *dst = src.re;
+            }
+        }
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let z = ctx.input(0);
+        let gz = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .set_shape(&shape(z))
+            .build(Rfft {
+                axis: self.axis,
+                scaled: self.scaled,
+            });
+        ctx.append_input_grad(Some(gz));
+        if self.n.is_none() {
+            ctx.append_input_grad(None);
+        }
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.n, self.scaled).hash(&mut state);
+    }
+}
@@ -1,5 +1,10 @@
+use crate::ndarray_ext::NdArray;
 use crate::op;
+use crate::tensor::{Dim, Tensor};
+use crate::tensor_ops::nth_tensor;
 use crate::Float;
+use std::rc::Rc;
+use std::hash::{Hash, Hasher};
 
 pub struct StopGradient;
 
@@ -14,3 +19,132 @@ impl<T: Float> op::Op<T> for StopGradient {
         ctx.append_input_grad(None);
     }
 }
+
+/// One-hot of the argmax of the input along `axis`, with the gradient of an identity.
+///
+/// This is the straight-through estimator of a hard sample: the first max of each lane is 1.
+pub struct StraightThroughOneHot {
+    pub axis: isize,
+}
+
+impl<T: Float> op::Op<T> for StraightThroughOneHot {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = ctx.input(0);
+        let ndim = x.ndim() as isize;
+        if self.axis >= ndim || self.axis < -ndim {
+            return Err(op::OpError::OutOfBounds(format!(
+                "straight_through_one_hot: axis {} is out of bounds for shape {:?}",
+                self.axis,
+                x.shape()
+            )));
+        }
+        let axis = ndarray::Axis(((self.axis + ndim) % ndim) as usize);
+        let mut y = NdArray::zeros(x.shape());
+        for (lane, mut out) in x.lanes(axis).into_iter().zip(y.lanes_mut(axis)) {
+            let mut best = 0;
+            for (i, &a) in lane.iter().enumerate() {
+                if a > lane[best] {
+                    best = i;
+                }
+            }
+            if !out.is_empty() {
+                out[best] = T::one();
+            }
+        }
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(gy));
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+}
+
+pub(crate) type Backward<T> = Rc<dyn Fn(&NdArray<T>) -> Vec<NdArray<T>>>;
+
+/// Passes the first input through, and gets the gradients of the others from `backward`.
+pub struct CustomGrad<T: Float> {
+    pub backward: Backward<T>,
+}
+
+/// Calls `backward` on the first input, checking that it returns one gradient
+/// of the right shape for each of the other inputs.
+pub struct CustomGradBackward<T: Float> {
+    pub backward: Backward<T>,
+}
+
+impl<T: Float> op::Op<T> for CustomGrad<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let ret = ctx.input(0);
+        ctx.append_output_view(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let num_inputs = ctx.num_inputs();
+        let mut builder = Tensor::builder(ctx.graph()).append_input(ctx.output_grad(), false);
+        for i in 1..num_inputs {
+            builder = builder.append_input(ctx.input(i), false);
+        }
+        let gxs = builder.build(CustomGradBackward {
+            backward: self.backward.clone(),
+        });
+        ctx.append_input_grad(None);
+        for i in 1..num_inputs {
+            ctx.append_input_grad(Some(nth_tensor(gxs, i - 1)));
+        }
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+}
+
+impl<T: Float> op::Op<T> for CustomGradBackward<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let gy = ctx.input(0).to_owned();
+        let gxs = (self.backward)(&gy);
+        let num_xs = ctx.num_inputs() - 1;
+        if gxs.len() != num_xs {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "custom_grad: backward returned {} gradients for {} inputs",
+                gxs.len(),
+                num_xs
+            )));
+        }
+        for (i, gx) in gxs.iter().enumerate() {
+            let x = ctx.input(i + 1);
+            if gx.shape() != x.shape() {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "custom_grad: gradient {} has shape {:?}, but the input has shape {:?}",
+                    i,
+                    gx.shape(),
+                    x.shape()
+                )));
+            }
+        }
+        for gx in gxs {
+            ctx.append_output(gx);
+        }
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        for _ in 0..ctx.num_inputs() {
+            ctx.append_input_grad(None);
+        }
+    }
+}
@@ -1,9 +1,17 @@
 use crate::op;
+use crate::tensor::Dim;
+use crate::tensor_ops::{scalar, shape, stop_gradient, training_switch, zeros};
 
 use crate::Float;
 
 pub(crate) struct ControlDependency;
 
+// The 1st input in training mode, the 2nd one otherwise
+pub(crate) struct TrainingSwitch;
+
+// The 1st input if it could be evaluated, the 2nd one otherwise
+pub(crate) struct Fallback;
+
 impl<F: Float> op::Op<F> for ControlDependency {
     // Reuse the 1st input
     fn compute(&self, ctx: &mut op::ComputeContext<F>) -> Result<(), op::OpError> {
@@ -13,8 +21,70 @@ impl<F: Float> op::Op<F> for ControlDependency {
     }
 
     fn grad(&self, ctx: &mut op::GradientContext<F>) {
-        for _ in 0..ctx.num_inputs() {
+        // the dependencies are only ordered, the 1st input passes through
+        ctx.append_input_grad(Some(ctx.output_grad()));
+        for _ in 1..ctx.num_inputs() {
             ctx.append_input_grad(None);
         }
     }
 }
+
+impl<F: Float> op::Op<F> for TrainingSwitch {
+    fn compute(&self, ctx: &mut op::ComputeContext<F>) -> Result<(), op::OpError> {
+        let i = if ctx.training() { 0 } else { 1 };
+        // a copy, as the result may be assigned to the variable it comes from
+        let ret = ctx.input(i).to_owned();
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<F>) {
+        let gy = ctx.output_grad();
+        let g = ctx.graph();
+        let zero = zeros(&shape(gy), g);
+        ctx.append_input_grad(Some(training_switch(gy, zero)));
+        ctx.append_input_grad(Some(training_switch(zero, gy)));
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[false, false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(match inputs {
+            [Some(a), Some(b)] if a == b => Some(a.clone()),
+            _ => None,
+        })
+    }
+}
+
+impl<F: Float> op::Op<F> for Fallback {
+    fn compute(&self, ctx: &mut op::ComputeContext<F>) -> Result<(), op::OpError> {
+        let i = match (ctx.input_error(0), ctx.input_error(1)) {
+            (None, _) => 0,
+            (Some(_), None) => 1,
+            (Some(_), Some(e)) => return Err(e.clone()),
+        };
+        let ret = ctx.input(i).to_owned();
+        ctx.append_output(ret);
+        // 1 if the primary was used, for `grad`
+        ctx.stash(ndarray::arr0(if i == 0 { F::one() } else { F::zero() }).into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<F>) {
+        let gy = ctx.output_grad();
+        let used_primary = stop_gradient(ctx.stashed(0));
+        let one = scalar(F::one(), ctx.graph());
+        ctx.append_input_grad(Some(gy * used_primary));
+        ctx.append_input_grad(Some(gy * (one - used_primary)));
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        &[false, false]
+    }
+
+    fn allows_failed_inputs(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,514 @@
+use crate::ndarray_ext::{NdArray, NdArrayView};
+use crate::op;
+use crate::tensor::Tensor;
+use crate::tensor_ops::*;
+use crate::Float;
+use ndarray::{s, Array2, Ix2};
+use std::hash::{Hash, Hasher};
+
+pub struct Trace;
+
+pub struct Diag;
+
+pub struct Cholesky;
+
+/// Inverse of a lower-triangular matrix (the upper triangle is ignored).
+pub struct LowerTriangularInverse;
+
+/// Computes both factors of the reduced QR decomposition at once (not differentiable).
+pub struct Qr;
+
+/// Computes `u`, `s` and `v` of the thin SVD at once (not differentiable).
+pub struct Svd;
+
+/// Selects one factor of `Qr` and differentiates it w.r.t. the decomposed matrix.
+///
+/// Inputs are `q, r` and the backprop input is the decomposed matrix.
+pub struct QrFactor {
+    pub index: usize,
+}
+
+/// Selects one factor of `Svd` and differentiates it w.r.t. the decomposed matrix.
+///
+/// Inputs are `u, s, v` and the backprop input is the decomposed matrix.
+pub struct SvdFactor {
+    pub index: usize,
+}
+
+/// Moore-Penrose pseudo-inverse, treating the singular values below
+/// `eps * max(m, n) * s_max` as zeros.
+pub struct Pinv;
+
+/// Keeps the lower triangle, optionally halving the diagonal.
+pub struct LowerTriangle {
+    pub half_diag: bool,
+}
+
+fn as_square_matrix<'v, T: Float>(
+    x: NdArrayView<'v, T>,
+    name: &str,
+) -> Result<ndarray::ArrayView2<'v, T>, op::OpError> {
+    let shape = x.shape().to_vec();
+    match x.into_dimensionality::<Ix2>() {
+        Ok(ret) if shape[0] == shape[1] => Ok(ret),
+        _ => Err(op::OpError::IncompatibleShape(format!(
+            "{}: input must be a square matrix, actual shape: {:?}",
+            name, shape
+        ))),
+    }
+}
+
+impl<T: Float> op::Op<T> for Trace {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = as_square_matrix(ctx.input(0), "trace")?;
+        let ret = x.diag().sum();
+        ctx.append_output(ndarray::arr0(ret).into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let x = ctx.input(0);
+        let eye = diag(ones(&shape(diag(x)), ctx.graph()));
+        ctx.append_input_grad(Some(ctx.output_grad() * eye));
+    }
+}
+
+impl<T: Float> op::Op<T> for Diag {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let ret: NdArray<T> = match x.ndim() {
+            1 => {
+                let n = x.len();
+                let mut ret = Array2::<T>::zeros((n, n));
+                ret.diag_mut().assign(&x);
+                ret.into_dyn()
+            }
+            2 => as_square_matrix(x, "diag")?.diag().to_owned().into_dyn(),
+            ndim => {
+                return Err(op::OpError::IncompatibleShape(format!(
+                    "diag: input must be 1-D or 2-D, actual ndim: {}",
+                    ndim
+                )))
+            }
+        };
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // vector <-> matrix, so the backward is `diag` itself
+        ctx.append_input_grad(Some(diag(ctx.output_grad())));
+    }
+}
+
+impl<T: Float> op::Op<T> for Cholesky {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let a = as_square_matrix(ctx.input(0), "cholesky")?;
+        let n = a.nrows();
+        let mut l = Array2::<T>::zeros((n, n));
+        for j in 0..n {
+            let mut d = a[[j, j]];
+            for k in 0..j {
+                d -= l[[j, k]] * l[[j, k]];
+            }
+            if d.is_nan() || d <= T::zero() {
+                return Err(op::OpError::NotPositiveDefinite(format!(
+                    "cholesky: input is not positive-definite (pivot {} is {})",
+                    j, d
+                )));
+            }
+            let d = d.sqrt();
+            l[[j, j]] = d;
+            for i in j + 1..n {
+                let mut s = a[[i, j]];
+                for k in 0..j {
+                    s -= l[[i, k]] * l[[j, k]];
+                }
+                l[[i, j]] = s / d;
+            }
+        }
+        ctx.append_output(l.into_dyn());
+        Ok(())
+    }
+
+    // Murray, "Differentiation of the Cholesky decomposition" (2016)
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let l = ctx.output();
+        let g = ctx.graph();
+        let l_inv = Tensor::builder(g)
+            .append_input(l, false)
+            .build(LowerTriangularInverse);
+        let middle = lower_triangle(matmul(transpose(l, &[1, 0]), ctx.output_grad()), true);
+        let s = matmul(matmul(transpose(l_inv, &[1, 0]), middle), l_inv);
+        let half = scalar(T::from(0.5).unwrap(), g);
+        ctx.append_input_grad(Some(half * (s + transpose(s, &[1, 0]))));
+    }
+}
+
+impl<T: Float> op::Op<T> for LowerTriangularInverse {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let l = as_square_matrix(ctx.input(0), "LowerTriangularInverse")?;
+        let n = l.nrows();
+        let mut ret = Array2::<T>::zeros((n, n));
+        // forward substitution, column by column
+        for j in 0..n {
+            ret[[j, j]] = T::one() / l[[j, j]];
+            for i in j + 1..n {
+                let mut s = T::zero();
+                for k in j..i {
+                    s -= l[[i, k]] * ret[[k, j]];
+                }
+                ret[[i, j]] = s / l[[i, i]];
+            }
+        }
+        ctx.append_output(ret.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let y = ctx.output();
+        let y_t = transpose(y, &[1, 0]);
+        let gx = neg(matmul(matmul(y_t, ctx.output_grad()), y_t));
+        ctx.append_input_grad(Some(lower_triangle(gx, false)));
+    }
+}
+
+impl<T: Float> op::Op<T> for LowerTriangle {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        let mut ret = x
+            .into_dimensionality::<Ix2>()
+            .map_err(|e| op::OpError::NdArrayError("LowerTriangle".to_string(), e))?
+            .to_owned();
+        let half = T::from(0.5).unwrap();
+        for ((i, j), a) in ret.indexed_iter_mut() {
+            if j > i {
+                *a = T::zero();
+            } else if j == i && self.half_diag {
+                *a *= half;
+            }
+        }
+        ctx.append_output(ret.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(Some(lower_triangle(ctx.output_grad(), self.half_diag)));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.half_diag.hash(&mut state);
+    }
+}
+
+// Householder Qr. Returns `(q, r)` with shapes `(m, k)` and `(k, n)` where `k = min(m, n)`,
+// and the diagonal of `r` made non-negative.
+fn householder_qr<T: Float>(a: &ndarray::ArrayView2<T>) -> (Array2<T>, Array2<T>) {
+    let (m, n) = a.dim();
+    let k = m.min(n);
+    let mut r = a.to_owned();
+    let mut q = Array2::<T>::eye(m);
+    for j in 0..k {
+        let norm = r
+            .slice(s![j.., j])
+            .iter()
+            .fold(T::zero(), |acc, &x| acc + x * x)
+            .sqrt();
+        if norm == T::zero() {
+            continue;
+        }
+        let alpha = if r[[j, j]] > T::zero() { -norm } else { norm };
+        let mut v = r.slice(s![j.., j]).to_owned();
+        v[0] -= alpha;
+        let v_norm2 = v.iter().fold(T::zero(), |acc, &x| acc + x * x);
+        if v_norm2 == T::zero() {
+            continue;
+        }
+        let two = T::from(2.).unwrap();
+        // r <- (I - 2vv^T / v^Tv) r
+        for col in 0..n {
+            let mut dot = T::zero();
+            for (i, &vi) in v.iter().enumerate() {
+                dot += vi * r[[j + i, col]];
+            }
+            let f = two * dot / v_norm2;
+            for (i, &vi) in v.iter().enumerate() {
+                r[[j + i, col]] -= f * vi;
+            }
+        }
+        // q <- q (I - 2vv^T / v^Tv)
+        for row in 0..m {
+            let mut dot = T::zero();
+            for (i, &vi) in v.iter().enumerate() {
+                dot += q[[row, j + i]] * vi;
+            }
+            let f = two * dot / v_norm2;
+            for (i, &vi) in v.iter().enumerate() {
+                q[[row, j + i]] -= f * vi;
+            }
+        }
+    }
+    let mut q = q.slice(s![.., ..k]).to_owned();
+    let mut r = r.slice(s![..k, ..]).to_owned();
+    for i in 0..k {
+        if r[[i, i]] < T::zero() {
+            r.row_mut(i).mapv_inplace(|x| -x);
+            q.column_mut(i).mapv_inplace(|x| -x);
+        }
+    }
+    for ((i, j), x) in r.indexed_iter_mut() {
+        if j < i {
+            *x = T::zero();
+        }
+    }
+    (q, r)
+}
+
+// One-sided Jacobi Svd of a matrix with `m >= n`.
+// Returns `(u, s, v)` with singular values in descending order.
+fn jacobi_svd_tall<T: Float>(
+    a: &ndarray::ArrayView2<T>,
+) -> (Array2<T>, ndarray::Array1<T>, Array2<T>) {
+    let (m, n) = a.dim();
+    let mut u = a.to_owned();
+    let mut v = Array2::<T>::eye(n);
+    let eps = T::epsilon();
+    const MAX_SWEEPS: usize = 60;
+    for _ in 0..MAX_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n {
+            for q in p + 1..n {
+                let (mut alpha, mut beta, mut gamma) = (T::zero(), T::zero(), T::zero());
+                for i in 0..m {
+                    alpha += u[[i, p]] * u[[i, p]];
+                    beta += u[[i, q]] * u[[i, q]];
+                    gamma += u[[i, p]] * u[[i, q]];
+                }
+                if gamma == T::zero() || gamma.abs() <= eps * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (T::from(2.).unwrap() * gamma);
+                let t = zeta.signum() / (zeta.abs() + (T::one() + zeta * zeta).sqrt());
+                let c = T::one() / (T::one() + t * t).sqrt();
+                let s = c * t;
+                for i in 0..m {
+                    let (up, uq) = (u[[i, p]], u[[i, q]]);
+                    u[[i, p]] = c * up - s * uq;
+                    u[[i, q]] = s * up + c * uq;
+                }
+                for i in 0..n {
+                    let (vp, vq) = (v[[i, p]], v[[i, q]]);
+                    v[[i, p]] = c * vp - s * vq;
+                    v[[i, q]] = s * vp + c * vq;
+                }
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+    let norms: Vec<T> = u
+        .columns()
+        .into_iter()
+        .map(|col| col.iter().fold(T::zero(), |acc, &x| acc + x * x).sqrt())
+        .collect();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&i, &j| {
+        norms[j]
+            .partial_cmp(&norms[i])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut u_ret = Array2::<T>::zeros((m, n));
+    let mut v_ret = Array2::<T>::zeros((n, n));
+    let mut s_ret = ndarray::Array1::<T>::zeros(n);
+    for (dst, &src) in order.iter().enumerate() {
+        let norm = norms[src];
+        s_ret[dst] = norm;
+        if norm > T::zero() {
+            u_ret
+                .column_mut(dst)
+                .assign(&u.column(src).mapv(|x| x / norm));
+        }
+        v_ret.column_mut(dst).assign(&v.column(src));
+    }
+    (u_ret, s_ret, v_ret)
+}
+
+impl<T: Float> op::Op<T> for Qr {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let a = ctx
+            .input(0)
+            .into_dimensionality::<Ix2>()
+            .map_err(|e| op::OpError::NdArrayError("qr: input must be 2-D".to_string(), e))?;
+        let (q, r) = householder_qr(&a);
+        ctx.append_output(q.into_dyn());
+        ctx.append_output(r.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for Svd {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let a = ctx
+            .input(0)
+            .into_dimensionality::<Ix2>()
+            .map_err(|e| op::OpError::NdArrayError("svd: input must be 2-D".to_string(), e))?;
+        let (u, s, v) = if a.nrows() >= a.ncols() {
+            jacobi_svd_tall(&a)
+        } else {
+            let (v, s, u) = jacobi_svd_tall(&a.t());
+            (u, s, v)
+        };
+        ctx.append_output(u.into_dyn());
+        ctx.append_output(s.into_dyn());
+        ctx.append_output(v.into_dyn());
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for Pinv {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let a = ctx
+            .input(0)
+            .into_dimensionality::<Ix2>()
+            .map_err(|e| op::OpError::NdArrayError("pinv: input must be 2-D".to_string(), e))?;
+        let (m, n) = a.dim();
+        let (u, s, v) = if m >= n {
+            jacobi_svd_tall(&a)
+        } else {
+            let (v, s, u) = jacobi_svd_tall(&a.t());
+            (u, s, v)
+        };
+        // same cutoff as `numpy.linalg.lstsq`
+        let s_max = s.iter().fold(T::zero(), |acc, &x| acc.max(x));
+        let cutoff = T::epsilon() * T::from(m.max(n)).unwrap() * s_max;
+        let s_inv = s.mapv(|x| if x > cutoff { T::one() / x } else { T::zero() });
+        let v_s_inv = &v * &s_inv.insert_axis(ndarray::Axis(0));
+        ctx.append_output(v_s_inv.dot(&u.t()).into_dyn());
+        Ok(())
+    }
+
+    // Golub and Pereyra, "The differentiation of pseudo-inverses and nonlinear least squares
+    // problems whose variables separate" (1973), for a constant rank.
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let gy = ctx.output_grad();
+        let a = ctx.input(0);
+        let y = ctx.output();
+        let y_t = transpose(y, &[1, 0]);
+        let gy_t = transpose(gy, &[1, 0]);
+        let col_proj = eye_like(a, g) - matmul(a, y);
+        let row_proj = eye_like(y, g) - matmul(y, a);
+        let ga = matmul(matmul(col_proj, gy_t), matmul(y, y_t))
+            + matmul(matmul(y_t, y), matmul(gy_t, row_proj))
+            - matmul(matmul(y_t, gy), y_t);
+        ctx.append_input_grad(Some(ga));
+    }
+}
+
+impl<T: Float> op::Op<T> for QrFactor {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let ret = ctx.input(self.index);
+        ctx.append_output_view(ret);
+        Ok(())
+    }
+
+    // Linear in (gq, gr), so each factor contributes its own part of the gradient.
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let gy = ctx.output_grad();
+        let q = ctx.input(0);
+        let r = ctx.input(1);
+        let q_t = transpose(q, &[1, 0]);
+        let r_inv_t = Tensor::builder(g)
+            .append_input(transpose(r, &[1, 0]), false)
+            .build(LowerTriangularInverse);
+        let ga = if self.index == 0 {
+            let qdq = matmul(q_t, gy);
+            let qdq_ = qdq - transpose(qdq, &[1, 0]);
+            matmul(matmul(q, lower_triangle(qdq_, false)), r_inv_t)
+                + matmul(gy - matmul(q, qdq), r_inv_t)
+        } else {
+            let rdr = matmul(r, transpose(gy, &[1, 0]));
+            let rdr_ = rdr - transpose(rdr, &[1, 0]);
+            matmul(q, gy + matmul(lower_triangle(rdr_, false), r_inv_t))
+        };
+        ctx.append_input_grad(Some(ga));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.index.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for SvdFactor {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let ret = ctx.input(self.index);
+        ctx.append_output_view(ret);
+        Ok(())
+    }
+
+    // Townsend, "Differentiating the Singular Value Decomposition" (2016).
+    // Linear in (gu, gs, gv), so each factor contributes its own part of the gradient.
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let g = ctx.graph();
+        let gy = ctx.output_grad();
+        let u = ctx.input(0);
+        let s = ctx.input(1);
+        let v = ctx.input(2);
+        let u_t = transpose(u, &[1, 0]);
+        let v_t = transpose(v, &[1, 0]);
+        let ga = match self.index {
+            1 => matmul(matmul(u, diag(gy)), v_t),
+            _ => {
+                // f[i, j] = 1 / (s[j]^2 - s[i]^2) for i != j, 0 otherwise
+                let eye = diag(ones(&shape(s), g));
+                let s2 = square(s);
+                let gaps = expand_dims(s2, &[0]) - expand_dims(s2, &[1]);
+                let f = (ones(&shape(eye), g) - eye) / (gaps + eye);
+                let s_inv = diag(inv(s));
+                let s_mat = diag(s);
+                if self.index == 0 {
+                    let utgu = matmul(u_t, gy);
+                    let j = f * (utgu - transpose(utgu, &[1, 0]));
+                    let proj = eye_like(u, g) - matmul(u, u_t);
+                    matmul(matmul(u, matmul(j, s_mat)), v_t)
+                        + matmul(matmul(matmul(proj, gy), s_inv), v_t)
+                } else {
+                    let vtgv = matmul(v_t, gy);
+                    let k = f * (vtgv - transpose(vtgv, &[1, 0]));
+                    let proj = eye_like(v, g) - matmul(v, v_t);
+                    matmul(matmul(u, matmul(s_mat, k)), v_t)
+                        + matmul(matmul(matmul(u, s_inv), transpose(gy, &[1, 0])), proj)
+                }
+            }
+        };
+        ctx.append_input_grad(Some(ga));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.index.hash(&mut state);
+    }
+}
+
+fn lower_triangle<T: Float>(x: Tensor<T>, half_diag: bool) -> Tensor<T> {
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(LowerTriangle { half_diag })
+}
+
+// Identity matrix whose size is the number of rows of `x`.
+fn eye_like<'g, T: Float>(x: Tensor<'g, T>, g: &'g crate::Graph<T>) -> Tensor<'g, T> {
+    let rows = slice(shape(x), &[0], &[1]);
+    diag(ones(&rows, g))
+}
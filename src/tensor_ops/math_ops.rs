@@ -2,13 +2,14 @@ use crate::ndarray_ext::{NdArray, NdArrayView};
 use crate::op;
 #[cfg(all(feature = "blas", feature = "intel-mkl"))]
 use crate::same_type;
-use crate::tensor::Tensor;
+use crate::tensor::{Dim, Tensor};
 #[cfg(all(feature = "blas", feature = "intel-mkl"))]
 use crate::tensor_ops::blas_ffi::*;
 use crate::tensor_ops::*;
 use crate::Float;
 use ndarray;
 use ndarray::Zip;
+use std::hash::{Hash, Hasher};
 
 pub struct Sin;
 pub struct Cos;
@@ -27,9 +28,17 @@ pub struct Exp2;
 pub struct Exp10;
 pub struct Sqrt;
 pub struct NegOp;
-pub struct Floor;
-pub struct Ceil;
+pub struct Floor {
+    pub grad: RoundingGrad,
+}
+pub struct Ceil {
+    pub grad: RoundingGrad,
+}
+pub struct Round {
+    pub grad: RoundingGrad,
+}
 pub struct Sign;
+pub struct LogicalNot;
 pub struct Inv;
 pub struct InvSqrt;
 pub struct Square;
@@ -50,6 +59,25 @@ pub struct Transpose {
 pub struct Lgamma;
 pub struct Digamma;
 
+/// The gradient of the rounding ops (`floor`, `ceil` and `round`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RoundingGrad {
+    /// No gradient, as the true derivative is 0 almost everywhere.
+    Zero,
+    /// The straight-through estimator: the gradient passes through unchanged,
+    /// as if the op were the identity (e.g. quantization-aware training).
+    StraightThrough,
+}
+
+impl RoundingGrad {
+    fn append_to<T: Float>(self, ctx: &mut op::GradientContext<T>) {
+        match self {
+            RoundingGrad::Zero => ctx.append_input_grad(None),
+            RoundingGrad::StraightThrough => ctx.append_input_grad(Some(ctx.output_grad())),
+        }
+    }
+}
+
 #[inline(always)]
 fn equal_fn<T: Float>(a: T, b: T) -> T {
     T::from((a == b) as i32).unwrap()
@@ -75,6 +103,18 @@ fn lesser_equal_fn<T: Float>(a: T, b: T) -> T {
     T::from((a <= b) as i32).unwrap()
 }
 #[inline(always)]
+fn logical_and_fn<T: Float>(a: T, b: T) -> T {
+    T::from((a != T::zero() && b != T::zero()) as i32).unwrap()
+}
+#[inline(always)]
+fn logical_or_fn<T: Float>(a: T, b: T) -> T {
+    T::from((a != T::zero() || b != T::zero()) as i32).unwrap()
+}
+#[inline(always)]
+fn logical_xor_fn<T: Float>(a: T, b: T) -> T {
+    T::from(((a != T::zero()) != (b != T::zero())) as i32).unwrap()
+}
+#[inline(always)]
 fn maximum_fn<T: Float>(a: T, b: T) -> T {
     a.max(b)
 }
@@ -111,50 +151,13 @@ macro_rules! impl_cmp_op {
                     x0.mapv(move |a| $assign(a, x1_elem))
                 } else {
                     // case that scalar is not involved
-                    // Check the input ranks.
-                    // op couldn't we catch here cause ndarray's panics.
-
-                    // rank check
-                    if shape0.len() != shape1.len() {
-                        panic!(
-                            "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
-                            shape0.len(),
-                            $name,
-                            shape1.len(),
-                            $name,
-                        )
-                    }
-
-                    let size0: usize = shape0.iter().product();
-                    let size1: usize = shape1.iter().product();
-
-                    // Whether broadcast of x0 and x1 is needed or not is depends on
-                    // their shapes.
-                    // FIXME: Is this cond branch ok?
-                    if size0 < size1 {
-                        let mut result = NdArray::zeros(shape1);
-                        Zip::from(&mut result)
-                            .and_broadcast(x0)
-                            .and(x1)
-                            .for_each(|r, a, b| *r = $assign(a.clone(), b.clone()));
-                        result
-                    } else if size0 > size1 {
-                        panic!(
-                            "Tensor ranks mismatch: {}({}'s lhs input) vs {}({}'s rhs input)",
-                            shape0.len(),
-                            $name,
-                            shape1.len(),
-                            $name
-                        );
-                    } else {
-                        // same
-                        let mut result = NdArray::zeros(shape0);
-                        Zip::from(&mut result)
-                            .and(x0)
-                            .and(x1)
-                            .for_each(|r, a, b| *r = $assign(a.clone(), b.clone()));
-                        result
-                    }
+                    let shape = crate::ndarray_ext::broadcast_shapes(shape0, shape1)?;
+                    let mut result = NdArray::zeros(shape);
+                    Zip::from(&mut result)
+                        .and_broadcast(&x0)
+                        .and_broadcast(x1)
+                        .for_each(|r, a, b| *r = $assign(a.clone(), b.clone()));
+                    result
                 };
 
                 ctx.append_output(ret);
@@ -180,6 +183,9 @@ impl_cmp_op!(Greater, "Greater", greater_fn, none_grad);
 impl_cmp_op!(Lesser, "Lesser", lesser_fn, none_grad);
 impl_cmp_op!(GreaterEqual, "GreaterEqual", greater_equal_fn, none_grad);
 impl_cmp_op!(LesserEqual, "LesserEqual", lesser_equal_fn, none_grad);
+impl_cmp_op!(LogicalAnd, "LogicalAnd", logical_and_fn, none_grad);
+impl_cmp_op!(LogicalOr, "LogicalOr", logical_or_fn, none_grad);
+impl_cmp_op!(LogicalXor, "LogicalXor", logical_xor_fn, none_grad);
 impl_cmp_op!(Maximum, "Maximum", maximum_fn, min_max_grad);
 impl_cmp_op!(Minimum, "Minimum", minimum_fn, min_max_grad);
 
@@ -192,6 +198,7 @@ fn none_grad<'g, T: Float>(
     ctx: &mut op::GradientContext<T>,
 ) {
     ctx.append_input_grad(None);
+    ctx.append_input_grad(None);
 }
 
 #[inline]
@@ -295,7 +302,8 @@ impl<T: Float> op::Op<T> for Abs {
 
 impl<T: Float> op::Op<T> for NegOp {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
-        let ret = ctx.input(0).map(|x| x.neg());
+        let x = ctx.input(0);
+        let ret = ctx.alloc_map(&x, |x| x.neg());
         ctx.append_output(ret);
         Ok(())
     }
@@ -314,7 +322,8 @@ impl<T: Float> op::Op<T> for Square {
 
         #[cfg(not(all(feature = "blas", feature = "intel-mkl")))]
         {
-            let ret = ctx.input(0).mapv(|a| a * a);
+            let x = ctx.input(0);
+            let ret = ctx.alloc_map(&x, |a| a * a);
             ctx.append_output(ret);
         }
         Ok(())
@@ -385,6 +394,20 @@ impl<T: Float> op::Op<T> for Sign {
     }
 }
 
+impl<T: Float> op::Op<T> for LogicalNot {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let ret = ctx
+            .input(0)
+            .mapv(|x| T::from((x == T::zero()) as i32).unwrap());
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+    }
+}
+
 impl<T: Float> op::Op<T> for Floor {
     fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
         #[cfg(all(feature = "blas", feature = "intel-mkl"))]
@@ -400,7 +423,11 @@ impl<T: Float> op::Op<T> for Floor {
     }
 
     fn grad(&self, ctx: &mut op::GradientContext<T>) {
-        ctx.append_input_grad(None);
+        self.grad.append_to(ctx);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.grad.hash(&mut state);
     }
 }
 
@@ -419,7 +446,27 @@ impl<T: Float> op::Op<T> for Ceil {
     }
 
     fn grad(&self, ctx: &mut op::GradientContext<T>) {
-        ctx.append_input_grad(None)
+        self.grad.append_to(ctx);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.grad.hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for Round {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let ret = ctx.input(0).map(|a| a.round());
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        self.grad.append_to(ctx);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.grad.hash(&mut state);
     }
 }
 
@@ -463,6 +510,10 @@ impl<T: Float> op::Op<T> for Transpose {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.invert_axes.hash(&mut state);
+    }
 }
 
 #[cfg(all(feature = "blas", feature = "intel-mkl"))]
@@ -554,10 +605,11 @@ pub fn logsumexp_forward<T: Float>(x: &NdArrayView<T>, axis: isize, keep_dims: b
 
     let max_fn = T::max;
     let min_val = T::min_value();
+    // keeps the reduced axis, so that it broadcasts along `axis` even if `keep_dims` is false
     let max = &x
         .fold_axis(ndarray::Axis(axis), min_val, move |&a, &b| max_fn(a, b))
-        .into_shape(ndarray::IxDyn(reduced_shape))
-        .unwrap();
+        .mapv(|m| if m.is_finite() { m } else { T::zero() })
+        .insert_axis(ndarray::Axis(axis));
 
     let exp = {
         // subtract `max` to prevent overflow of exp
@@ -573,23 +625,23 @@ pub fn logsumexp_forward<T: Float>(x: &NdArrayView<T>, axis: isize, keep_dims: b
         tmp
     };
 
-    // unwrap is safe
     let mut sum = exp
         .sum_axis(ndarray::Axis(axis))
-        .into_shape(ndarray::IxDyn(reduced_shape))
-        .unwrap();
+        .insert_axis(ndarray::Axis(axis));
 
     #[cfg(all(feature = "blas", feature = "intel-mkl"))]
-    {
+    let ret = {
         fast_inplace_ln_impl(&mut sum);
         inplace_add_impl(sum, max)
-    }
+    };
     #[cfg(not(all(feature = "blas", feature = "intel-mkl")))]
-    {
+    let ret = {
         sum.mapv_inplace(move |a| a.ln());
         sum += max;
         sum
-    }
+    };
+    // unwrap is safe
+    ret.into_shape(ndarray::IxDyn(reduced_shape)).unwrap()
 }
 
 impl<T: Float> op::Op<T> for LogSumExp {
@@ -600,12 +652,31 @@ impl<T: Float> op::Op<T> for LogSumExp {
     }
 
     fn grad(&self, ctx: &mut op::GradientContext<T>) {
-        // let ref sum = c.exp(output);
-        // let ref exp = c.exp(ctx.input(0));
-        // let gx = gy * exp / sum;
-        let gx = softmax(ctx.input(0), self.axis) * ctx.output_grad();
+        let g = ctx.graph();
+        let x = ctx.input(0);
+        // gy has the reduced shape
+        let gy = Tensor::builder(g)
+            .append_input(ctx.output_grad(), false)
+            .append_input(shape(x), false)
+            .append_input(scalar(T::from(self.axis).unwrap(), g), false)
+            .build(reduction_ops::ReduceGradCommon {
+                should_make_broadcast_dims: !self.keep_dims,
+                sparse_axes: false,
+            });
+        let gx = softmax(x, self.axis) * gy;
         ctx.append_input_grad(Some(gx))
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.keep_dims).hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([
+            ("axis", self.axis.into()),
+            ("keep_dims", self.keep_dims.into()),
+        ])
+    }
 }
 
 impl<T: Float> op::Op<T> for Pow<T> {
@@ -627,6 +698,10 @@ impl<T: Float> op::Op<T> for Pow<T> {
         let gx = ctx.output_grad() * scalar(self.a, ctx.graph()) * pow(x, self.a - T::one());
         ctx.append_input_grad(Some(gx))
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        op::float_bits(self.a).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for Sqrt {
@@ -718,7 +793,8 @@ impl<T: Float> op::Op<T> for Exp {
         }
         #[cfg(not(all(feature = "blas", feature = "intel-mkl")))]
         {
-            let ret = ctx.input(0).map(|a| a.exp());
+            let x = ctx.input(0);
+            let ret = ctx.alloc_map(&x, |a| a.exp());
             ctx.append_output(ret);
         }
         Ok(())
@@ -845,7 +921,8 @@ impl<T: Float> op::Op<T> for Tanh {
         }
         #[cfg(not(all(feature = "blas", feature = "intel-mkl")))]
         {
-            let ret = ctx.input(0).map(|a| a.tanh());
+            let x = ctx.input(0);
+            let ret = ctx.alloc_map(&x, |a| a.tanh());
             ctx.append_output(ret);
         }
         Ok(())
@@ -1013,8 +1090,9 @@ impl<T: Float> op::Op<T> for Tan {
     }
 
     fn grad(&self, ctx: &mut op::GradientContext<T>) {
-        let cos = cos(&ctx.input(0));
-        ctx.append_input_grad(Some(ctx.output_grad() / square(cos)));
+        // sec^2 = 1 + tan^2, reusing the output instead of dividing by a vanishing cos
+        let sec2 = 1. + square(ctx.output());
+        ctx.append_input_grad(Some(sec2 * ctx.output_grad()));
     }
 }
 
@@ -1057,3 +1135,101 @@ macro_rules! impl_gamma {
 
 impl_gamma!(f32, digamma_f32);
 impl_gamma!(f64, digamma_f64);
+
+#[cfg(feature = "f16")]
+pub struct HalfPrecision;
+
+#[cfg(feature = "f16")]
+impl<T: Float> op::Op<T> for HalfPrecision {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let ret = ctx.input(0).mapv(move |a| {
+            let half = half::f16::from_f32(a.to_f32().unwrap());
+            T::from(half.to_f32()).unwrap()
+        });
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // straight-through
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(gy))
+    }
+}
+
+/// Normalizes its input along the last axis, stashing the inverse standard deviation.
+pub struct LayerNorm<T: Float> {
+    pub eps: T,
+}
+
+/// Gradient of `LayerNorm`: inputs are `gy`, the output and the stashed inverse standard
+/// deviation of the `LayerNorm`.
+pub struct LayerNormGrad;
+
+// Mean along the last axis, keeping it
+fn mean_last_axis<T: Float>(x: &NdArrayView<T>) -> NdArray<T> {
+    let axis = ndarray::Axis(x.ndim() - 1);
+    let n = T::from(x.shape()[axis.0]).unwrap();
+    x.sum_axis(axis).insert_axis(axis).mapv(move |a| a / n)
+}
+
+impl<T: Float> op::Op<T> for LayerNorm<T> {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let x = ctx.input(0);
+        if x.ndim() == 0 || x.shape()[x.ndim() - 1] == 0 {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "layer_norm: input must have a non-empty last axis, got shape {:?}",
+                x.shape()
+            )));
+        }
+        let centered = &x - &mean_last_axis(&x);
+        let variance = mean_last_axis(&centered.mapv(|a| a * a).view());
+        let eps = self.eps;
+        let inv_std = variance.mapv(move |v| T::one() / (v + eps).sqrt());
+        ctx.append_output(centered * &inv_std);
+        ctx.stash(inv_std);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.output(), false)
+            .append_input(ctx.stashed(0), false)
+            .build(LayerNormGrad);
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn needs_input_for_grad(&self) -> &[bool] {
+        // the backward pass reads the output instead
+        &[false]
+    }
+
+    fn infer_shape(&self, inputs: &[Option<Vec<Dim>>]) -> Result<Option<Vec<Dim>>, op::OpError> {
+        Ok(inputs[0].clone())
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        op::float_bits(self.eps).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for LayerNormGrad {
+    fn compute(&self, ctx: &mut op::ComputeContext<T>) -> Result<(), op::OpError> {
+        let gy = ctx.input(0);
+        let y = ctx.input(1);
+        let inv_std = ctx.input(2);
+        // gx = (gy - mean(gy) - y mean(gy y)) / std
+        let gy_y = &gy * &y;
+        let gx = (&gy - &mean_last_axis(&gy) - &y * &mean_last_axis(&gy_y.view())) * &inv_std;
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut op::GradientContext<T>) {
+        // not differentiable twice
+        for _ in 0..3 {
+            ctx.append_input_grad(None);
+        }
+    }
+}
@@ -1,24 +1,56 @@
 //! A collection of functions for manipulating `autograd::Tensor` objects
+//!
+//! # Empty tensors
+//!
+//! Tensors with a zero-sized dimension, e.g. from an empty slice or an all-false
+//! [boolean_mask], go through the ops like the others, with the conventions of numpy:
+//!
+//! - Elementwise ops, reshapes, `concat` and `stack` give empty results; empty inputs of
+//!   `concat` contribute nothing.
+//! - Reductions over an empty axis give their identity: 0 for [reduce_sum], 1 for
+//!   [reduce_prod], `-inf` for [reduce_logsumexp]. [reduce_mean] and [reduce_variance] give
+//!   NaN, as 0 / 0.
+//! - [reduce_max], [reduce_min], [argmax] and [argmin] have no identity, and fail with
+//!   [OpError::IncompatibleShape](crate::op::OpError::IncompatibleShape) over an empty axis.
+//! - Matrix products with an empty inner dimension give zeros of the output shape.
+//! - [gather] with no indices gives a tensor empty along the gathered axis.
+//!
+//! # Parameter checks
+//!
+//! The ops check their parameters when they're built if their values are known by then, and
+//! panic with a message naming the bad one instead of failing at the evaluation: the `axes`
+//! given as arrays to the reductions and [transpose], the `axis` of [concat], [stack] and
+//! [softmax], the ranges of [slice] and the widths of [pad]. Whether an axis is in range is
+//! only known for an input of known rank, i.e. a placeholder or a constant; the others are
+//! checked at evaluation.
 use ndarray;
 
 use crate::ndarray_ext::{ArrayRng, NdArray};
 use crate::tensor::{AsTensor, Tensor};
+use crate::variable::RUNNING_STATS_NAMESPACE_ID;
 use crate::{Float, Graph};
 use rand::Rng;
 
+pub use array_ops::{MeshgridIndexing, PadMode};
+pub use conv_ops::grid_sample::GridSamplePadding;
+pub use math_ops::RoundingGrad;
+
 mod activation_ops;
-mod array_ops;
+pub(crate) mod array_ops;
 pub(crate) mod basic_source_ops;
 pub(crate) mod binary_ops;
 mod blas_ffi;
 pub(crate) mod const_gen_ops;
 mod conv_ops;
 pub(crate) mod dot_ops;
+mod einsum_ops;
+mod fft_ops;
 pub(crate) mod gradient_descent_ops;
 mod gradient_ops;
-mod graph_ops;
+pub(crate) mod graph_ops;
 pub(crate) mod higher_order_ops;
 pub(crate) mod hook_ops;
+mod linalg_ops;
 mod math_ops;
 mod random_ops;
 mod reduction_ops;
@@ -91,6 +123,27 @@ impl<'graph, F: Float> Tensor<'graph, F> {
 ///     assert_eq!(8., gx[0].as_ref().unwrap()[ndarray::IxDyn(&[])]);
 /// });
 /// ```
+///
+/// `xs` need not be leaves: the gradient with respect to an intermediate tensor has the
+/// shape of that tensor, and the backprop doesn't go below it unless other `xs` are there.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|ctx| {
+///     let x = ctx.placeholder("x", &[2, 3]);
+///     let hidden = T::relu(T::matmul(x, T::ones(&[3, 4], ctx)));
+///     let loss = T::reduce_mean(T::square(hidden), &[0, 1], false);
+///
+///     let g_hidden = T::grad(&[loss], &[hidden])[0];
+///     let x_val = ag::ndarray::arr2(&[[1., 2., 3.], [0., 0., 1.]]);
+///     let g_hidden = ctx.evaluator().push(g_hidden).feed(x, x_val.view()).run();
+///     // d(mean(h^2))/dh = 2h / 8
+///     assert_eq!(g_hidden[0].as_ref().unwrap()[[1, 0]], 0.25);
+///     assert_eq!(g_hidden[0].as_ref().unwrap().shape(), &[2, 4]);
+/// });
+/// ```
 pub fn grad<'graph, F: Float, A, B>(ys: &[A], xs: &[B]) -> Vec<Tensor<'graph, F>>
 where
     A: AsRef<Tensor<'graph, F>>,
@@ -207,7 +260,7 @@ where
         // jac is matrix
         let mut jac = Vec::with_capacity(objective_len);
         for vec in &vec_vec {
-            jac.push(expand_dims(flatten(&vec[i]), &[0]));
+            jac.push(expand_dims(flatten(vec[i], 0), &[0]));
         }
         // (y size, x size)
         ret.push(concat(&jac, 0));
@@ -215,6 +268,121 @@ where
     ret
 }
 
+/// Number of elements of `y` from which [jacobian] warns about its cost
+pub const JACOBIAN_SIZE_WARN: usize = 1024;
+
+/// Evaluates the dense Jacobian of `y` with `x`: the matrix of shape `(y size, x size)` whose
+/// row `i` is the gradient of the `i`-th element of `y` (in row-major order) with `x`.
+///
+/// Runs one vector-Jacobian product per element of `y`, i.e. [grad_with_default] seeded with
+/// each one-hot array in turn, and a full forward and backward evaluation for each. That's
+/// fine for small outputs, but the cost grows with the size of `y`: this warns on stderr from
+/// [JACOBIAN_SIZE_WARN] elements on. See [jacobians] for the Jacobians as tensors.
+///
+/// `feeder` feeds the placeholders `y` depends on, to each of these evaluations. The seed
+/// placeholder and the product are built once per `(y, x)` and reused by the next calls, e.g.
+/// with another batch fed. Fails as `y.eval(ctx)` does.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = g.placeholder("x", &[2]);
+///    let y = concat(&[square(x), x * 3.], 0);
+///    let x_value = array![1., 2.];
+///    let mut feeder = ag::Feeder::new();
+///    feeder.push(x, x_value.view());
+///    let j = jacobian(y, x, feeder, g).unwrap();
+///    assert_eq!(j, array![[2., 0.], [0., 4.], [3., 0.], [0., 3.]].into_dyn());
+/// });
+/// ```
+pub fn jacobian<'graph, A, B, F: Float>(
+    y: A,
+    x: B,
+    feeder: crate::Feeder<F>,
+    ctx: &'graph crate::Context<F>,
+) -> Result<NdArray<F>, crate::EvalError>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let (y, x) = (y.as_ref(), x.as_ref());
+    let mut evaluator = ctx.evaluator();
+    let mut values = evaluator.push(y).push(x).set_feeder(feeder.clone()).run().into_iter();
+    let y_shape = values.next().unwrap()?.shape().to_vec();
+    let y_len: usize = y_shape.iter().product();
+    let x_len = values.next().unwrap()?.len();
+    if y_len >= JACOBIAN_SIZE_WARN {
+        eprintln!(
+            "jacobian: {} elements in `y`, as many backward passes. \
+            Use a vector-Jacobian product if the dense matrix isn't needed",
+            y_len
+        );
+    }
+    let cached = ctx.graph.jacobian_vjps.borrow().get(&(y.id, x.id)).cloned();
+    let (seed, vjp) = match cached {
+        Some((seed, vjp)) => (ctx.graph.tensor(seed), ctx.graph.tensor(vjp)),
+        None => {
+            let seed = ctx.placeholder("jacobian_seed", &vec![-1; y_shape.len()]);
+            let vjp = grad_with_default(&[y], &[x], &[seed])[0];
+            ctx.graph.jacobian_vjps.borrow_mut().insert((y.id, x.id), (seed.id, vjp.id));
+            (seed, vjp)
+        }
+    };
+
+    let mut ret = Vec::with_capacity(y_len * x_len);
+    let mut one_hot = NdArray::zeros(y_shape);
+    for i in 0..y_len {
+        one_hot.as_slice_mut().unwrap()[i] = F::one();
+        let mut feeder = feeder.reborrow();
+        feeder.push(seed, one_hot.view());
+        let row = ctx.evaluator().push(vjp).set_feeder(feeder).run();
+        ret.extend(row.into_iter().next().unwrap()?.iter());
+        one_hot.as_slice_mut().unwrap()[i] = F::zero();
+    }
+    Ok(NdArray::from_shape_vec(ndarray::IxDyn(&[y_len, x_len]), ret).unwrap())
+}
+
+/// Evaluates the product of the Hessian of `loss` with `x` and the vector `v`, of the shape
+/// of `x`, without building the Hessian.
+///
+/// `loss` is summed up if it isn't a scalar. The product is the gradient with `x` of the dot
+/// product of `grad(loss, x)` and `v`: a double backward, whose cost is a few times the one
+/// of the gradient, whatever the size of `x`. This is what second-order optimizers
+/// (Newton-CG, trust regions) and curvature estimates need from the Hessian.
+/// Fails as `loss.eval(ctx)` does, e.g. if `loss` depends on a placeholder.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 2.], g);
+///    // the Hessian is diag(6 x)
+///    let loss = sum_all(pow(x, 3.));
+///    let v = convert_to_tensor(array![1., -1.], g);
+///    assert_eq!(hvp(loss, x, v, g), Ok(array![6., -12.].into_dyn()));
+/// });
+/// ```
+pub fn hvp<'graph, A, B, C, F: Float>(
+    loss: A,
+    x: B,
+    v: C,
+    ctx: &'graph crate::Context<F>,
+) -> Result<NdArray<F>, crate::EvalError>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let gx = grad(&[loss], &[x])[0];
+    let dot = sum_all(gx * v.as_ref());
+    grad(&[dot], &[x])[0].eval(ctx)
+}
+
 /// (Experimental) Computes hessian vector product
 pub fn _hessian_vector_product<'graph, A, B, C, F: Float>(
     ys: &[A],
@@ -251,6 +419,166 @@ where
         .build(gradient_ops::StopGradient)
 }
 
+/// Evaluates `x` now, and returns its value as a new constant.
+///
+/// Unlike [stop_gradient], the result isn't connected to `x` at all: it's a source of the graph,
+/// like the result of [convert_to_tensor], so evaluating it doesn't evaluate `x` again.
+/// This snapshots a value to start a new subgraph from.
+/// Fails as `x.eval(ctx)` does, e.g. if `x` depends on a placeholder.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 2.], g) * 3.;
+///    let y = detach(x, g).unwrap();
+///    assert!(y.is_source());
+///    assert_eq!(y.eval(g), Ok(array![3., 6.].into_dyn()));
+/// });
+/// ```
+pub fn detach<'graph, A, F: Float>(
+    x: A,
+    ctx: &'graph crate::Context<F>,
+) -> Result<Tensor<'graph, F>, crate::EvalError>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    Ok(convert_to_tensor(x.as_ref().eval(ctx)?, ctx))
+}
+
+/// Returns `x` as computed by the first evaluation that needs it, and that value ever after.
+///
+/// The value is stored in the context the first time the result is evaluated, from the
+/// variables and feeds of that evaluation. After that, the subgraph behind `x` is not
+/// computed again: neither [Context::set_variable](crate::Context::set_variable), nor an
+/// optimizer update, nor new feeds change the result, and the placeholders behind `x` need
+/// not be fed anymore. This fixes, e.g., normalization statistics computed from an initial
+/// batch. The frozen value is only dropped by [Context::clear](crate::Context::clear), and
+/// [Context::clear_pinned](crate::Context::clear_pinned) doesn't touch it.
+///
+/// The result is a constant: no gradient flows back to `x` through it.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::new();
+/// env.name("w").set(ag::ndarray::arr1(&[1., 2.]));
+/// env.run(|g| {
+///     let mean = T::freeze_after_first(T::reduce_mean(g.variable("w"), &[0], false));
+///     assert_eq!(mean.eval(g).unwrap()[[]], 1.5);
+///
+///     g.set_variable("w", ag::ndarray::arr1(&[5., 5.]).into_dyn());
+///     assert_eq!(mean.eval(g).unwrap()[[]], 1.5);
+/// });
+/// ```
+pub fn freeze_after_first<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let y = stop_gradient(x);
+    y.graph().frozen.borrow_mut().insert(y.id);
+    y
+}
+
+/// Returns `y` unchanged, with its gradients with respect to `xs` computed by `backward`.
+///
+/// `backward` gets the gradient of `y` and returns one gradient for each of `xs`, of the same
+/// shape as it. The gradients that would flow back through the subgraph between `xs` and `y`
+/// are replaced by these: use this for straight-through estimators or surrogate gradients
+/// without writing an `Op`. The gradients computed by `backward` are constants, so they
+/// can't be differentiated again. Evaluating them fails if `backward` returns the wrong number
+/// of arrays or a wrong shape.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 2.], g);
+///    // the gradient of `square` is replaced by the one of an identity
+///    let y = custom_grad(square(x), &[x], |gy| vec![gy.clone()]);
+///    let gx = grad(&[y], &[x])[0];
+///    assert_eq!(y.eval(g), Ok(array![1., 4.].into_dyn()));
+///    assert_eq!(gx.eval(g), Ok(array![1., 1.].into_dyn()));
+/// });
+/// ```
+pub fn custom_grad<'graph, A, B, F: Float, G>(y: A, xs: &[B], backward: G) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    G: Fn(&NdArray<F>) -> Vec<NdArray<F>> + 'static,
+{
+    let y = y.as_ref();
+    let mut builder = Tensor::builder(y.graph()).append_input(y, false);
+    for x in xs {
+        builder = builder.append_input(x.as_ref(), false);
+    }
+    builder.build(gradient_ops::CustomGrad {
+        backward: std::rc::Rc::new(backward),
+    })
+}
+
+/// Gradient reversal layer: returns `x` unchanged, and multiplies its gradient by `-lambda`.
+///
+/// The core of domain-adversarial training (https://arxiv.org/abs/1409.7495): placed between
+/// a feature extractor and a domain classifier, it makes the extractor maximize the loss the
+/// classifier minimizes. Built on [custom_grad], so the reversed gradient can't be
+/// differentiated again.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., -2.], g);
+///    let y = gradient_reversal(x, 0.5);
+///    assert_eq!(y.eval(g), Ok(array![1., -2.].into_dyn()));
+///    let gx = grad(&[square(y)], &[x])[0];
+///    assert_eq!(gx.eval(g), Ok(array![-1., 2.].into_dyn()));
+/// });
+/// ```
+pub fn gradient_reversal<'graph, A, F: Float>(x: A, lambda: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    custom_grad(x, &[x], move |gy| vec![gy.mapv(|a| a * -lambda)])
+}
+
+/// Prints a summary of `x` to stderr when it's evaluated, and passes it through unchanged.
+///
+/// The summary is `msg` followed by the shape, the min, max and mean, and the first few
+/// elements of `x`. Gradients go through as with an identity. Setting the environment variable
+/// `AUTOGRAD_NO_PRINT` when building the graph makes this return `x` itself, so that the
+/// prints cost nothing once debugging is done.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let x = T::ones(&[2, 3], g);
+///     let y = T::print_tensor(x * 2., "y:");
+///     assert_eq!(y.eval(g), Ok(ag::ndarray_ext::ones(&[2, 3]) * 2.));
+///     // y: shape=[2, 3] min=2 max=2 mean=2 values=[2.0, 2.0, 2.0, 2.0, 2.0], ...
+/// });
+/// ```
+pub fn print_tensor<'graph, A, F: Float>(x: A, msg: &'static str) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = *x.as_ref();
+    if std::env::var_os("AUTOGRAD_NO_PRINT").is_some() {
+        return x;
+    }
+    x.register_hook(crate::hooks::Summary(msg))
+}
+
 /// Returns a `Tensor` representation of the input tensor's shape
 ///
 /// ```
@@ -330,6 +658,8 @@ where
 }
 
 /// Elementwise sine
+///
+/// The gradient is `cos(x)`.
 pub fn sin<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -343,6 +673,8 @@ where
 }
 
 /// Elementwise cosine
+///
+/// The gradient is `-sin(x)`.
 pub fn cos<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -356,6 +688,22 @@ where
 }
 
 /// Elementwise tangent
+///
+/// The gradient is `sec^2(x)`, computed as `1 + tan^2(x)` from the output.
+/// Neither is clamped near the poles `pi/2 + k * pi`: no float is exactly a pole, so a finite `x`
+/// gives a finite but possibly huge output, and a gradient growing with its square.
+/// Clip `x` beforehand if that matters.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = scalar(std::f64::consts::FRAC_PI_2, g);
+///    let y = tan(x).eval(g).unwrap()[ndarray::IxDyn(&[])];
+///    assert!(y.is_finite() && y > 1e15);
+/// });
+/// ```
 pub fn tan<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -537,11 +885,16 @@ where
         .build(math_ops::Digamma)
 }
 
-#[doc(hidden)]
-/// Gets n th tensor in `x`.
+/// Gets the `n` th output of a multi-output op.
+///
+/// An op produces several arrays by calling
+/// [ComputeContext::append_output](crate::op::ComputeContext::append_output) several times.
+/// The tensor built with the op stands for its first output, and `nth_tensor` gives the
+/// others, which can feed different branches. In `Op::grad`, the gradient of each output
+/// is [GradientContext::output_grad_at](crate::op::GradientContext::output_grad_at).
 ///
 /// `x` must be a result of a multi-outputs op;
-/// otherwise index-out-of-bounds error may happen.
+/// otherwise its evaluation fails with an index-out-of-bounds error.
 pub fn nth_tensor<'graph, A, F: Float>(x: A, n: usize) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -554,6 +907,24 @@ where
 }
 
 /// Identity function without copy.
+///
+/// The output is a view of the array of `x`, so evaluating it allocates nothing
+/// (except when `x` is a variable: its array can be updated in place, and is copied),
+/// and the gradient passes straight through to `x`. A separate node for the same value
+/// is useful as a named checkpoint, or as a place to attach hooks such as [Tensor::show].
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let x = T::convert_to_tensor(array![1., 2.], g);
+///    let y = T::identity(x);
+///    assert_eq!(y.eval(g), x.eval(g));
+///    assert_eq!(T::grad(&[y], &[x])[0].eval(g), Ok(array![1., 1.].into_dyn()));
+/// });
+/// ```
 pub fn identity<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -582,6 +953,49 @@ where
         .build(array_ops::InferBinOpShape)
 }
 
+// Whether `axis` is an axis of a tensor of rank `rank`, counted from the end if negative
+fn is_axis(axis: isize, rank: usize) -> bool {
+    -(rank as isize) <= axis && axis < rank as isize
+}
+
+// The rank of `x`, if it's known when the graph is built
+fn known_rank<F: Float>(x: &Tensor<F>) -> Option<usize> {
+    x.known_dims().map(|dims| dims.len())
+}
+
+// Panics unless `axis` is an axis of `x`, if the rank of `x` is known
+fn check_axis<F: Float>(op: &str, axis: isize, x: &Tensor<F>) {
+    if let Some(rank) = known_rank(x) {
+        assert!(
+            is_axis(axis, rank),
+            "{}: invalid axis {} for a tensor of rank {}",
+            op,
+            axis,
+            rank
+        );
+    }
+}
+
+// Panics unless `axes` are axes of `x`, if both are known. The reductions of scalars
+// ignore their axes.
+fn check_axes<'graph, AT, F: Float>(op: &str, axes: &AT, x: &Tensor<'graph, F>)
+where
+    AT: AsTensor<'graph, F>,
+{
+    if let (Some(axes), Some(rank)) = (axes.known_values(), known_rank(x)) {
+        for &axis in &axes {
+            assert!(
+                rank == 0 || is_axis(axis, rank),
+                "{}: invalid axis {} in axes {:?} for a tensor of rank {}",
+                op,
+                axis,
+                axes,
+                rank
+            );
+        }
+    }
+}
+
 /// Elementwise addition.
 ///
 /// This can be replaced with `+` operation of Tensor.
@@ -636,6 +1050,9 @@ where
 /// Elementwise division.
 ///
 /// This can be replaced with `/` operation of Tensor.
+/// The gradients are `gy / b` for `a` and `-gy * a / b^2` for `b`, summed over the axes
+/// along which each was broadcast. Where `b` is zero, the result and both gradients are
+/// infinite or NaN as the float division gives them; see [safe_div] to avoid that.
 #[inline]
 pub fn div<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
@@ -650,6 +1067,38 @@ where
         .build(binary_ops::DivOp)
 }
 
+/// Elementwise division whose denominator is kept at least `eps` away from zero.
+///
+/// Computes `a / b'`, where `b'` is `b` with its magnitude clamped to `eps` or more and its sign
+/// kept (0 counts as positive). Where `b` was clamped, `b'` is a constant, so `b` gets no
+/// gradient there; `a` always gets `1 / b'`. Outputs and gradients stay finite for finite inputs.
+/// `eps` should be positive.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![1., 1., 1., 6.], g);
+///    let b = convert_to_tensor(array![0., -1e-9, 1e-9, 3.], g);
+///    let c = safe_div(a, b, 1e-3);
+///    assert_eq!(c.eval(g), Ok(array![1e3, -1e3, 1e3, 2.].into_dyn()));
+/// });
+/// ```
+pub fn safe_div<'graph, A, B, F: Float>(a: A, b: B, eps: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let b = b.as_ref();
+    let g = b.graph();
+    let zero = scalar(F::zero(), g);
+    // +1 or -1, with +1 for a zero
+    let sign = scalar(F::from(2.).unwrap(), g) * greater_equal(b, zero) - scalar(F::one(), g);
+    div(a, sign * maximum(abs(b), scalar(eps, g)))
+}
+
 /// Elementwise sqrt
 #[inline]
 pub fn sqrt<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
@@ -850,8 +1299,7 @@ where
 ///
 /// if `a[i] == b[i]` then `return-value[i]` will be 1 else 0
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 ///
 /// ```
 /// use ndarray::array;
@@ -882,8 +1330,7 @@ where
 ///
 /// if `a[i] != b[i]` then `return-value[i]` will be 1 else 0
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 ///
 /// ```
 /// use ndarray::array;
@@ -962,6 +1409,67 @@ where
     Tensor::builder(g).append_input(x.as_ref(), false).build(op)
 }
 
+/// Returns the indices that sort `x` along `axis`, in ascending order or `descending`.
+///
+/// The sort is stable: equal elements keep their original order in either direction,
+/// and NaNs come last. `axis` can be negative. As with [argmax], the indices are floats and
+/// have no gradient.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[3., 1., 2.], [5., 5., 4.]], g);
+///    let y = argsort(x, 1, false);
+///    assert_eq!(y.eval(g), Ok(array![[1., 2., 0.], [2., 0., 1.]].into_dyn()));
+///    let y = argsort(x, 1, true);
+///    assert_eq!(y.eval(g), Ok(array![[0., 2., 1.], [0., 1., 2.]].into_dyn()));
+/// });
+/// ```
+pub fn argsort<'graph, A, F: Float>(x: A, axis: isize, descending: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(array_ops::ArgSort { axis, descending })
+}
+
+/// Sorts `x` along `axis`, in ascending order or `descending`.
+///
+/// Elements are ordered as by [argsort]. The gradient of each element goes back to the
+/// position it was taken from.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[3., 1., 2.], [5., 5., 4.]], g);
+///    let y = sort(x, -1, true);
+///    assert_eq!(y.eval(g), Ok(array![[3., 2., 1.], [5., 5., 4.]].into_dyn()));
+/// });
+/// ```
+pub fn sort<'graph, A, F: Float>(x: A, axis: isize, descending: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .append_input(argsort(x, axis, descending), false)
+        .set_shape(&shape(x))
+        .build(array_ops::TakeAlongAxis {
+            axis,
+            inverse: false,
+        })
+}
+
 /// Expands the shape (inserts axes).
 ///
 /// Each axis can be negative.
@@ -1046,6 +1554,38 @@ where
     Tensor::builder(g).append_input(x.as_ref(), false).build(op)
 }
 
+/// Broadcasts `x` to `shape` explicitly.
+///
+/// `shape` must be reachable from the shape of `x` by numpy's broadcasting rules, i.e. by
+/// prepending axes and stretching axes of size 1; otherwise evaluation fails with
+/// [OpError::IncompatibleShape](crate::op::OpError::IncompatibleShape). The gradient
+/// sums over the broadcasted axes.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 2.]], g);
+///    let y = broadcast_to(x, &[3, 2]);
+///    assert_eq!(y.eval(g), Ok(array![[1., 2.], [1., 2.], [1., 2.]].into_dyn()));
+///    assert!(broadcast_to(x, &[3, 3]).eval(g).is_err());
+/// });
+/// ```
+pub fn broadcast_to<'graph, A, AT, F: Float>(x: A, shape: &AT) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .append_input(x, false)
+        .append_input(&shape.as_tensor(g), false)
+        .build(array_ops::BroadcastTo)
+}
+
 /// Limits all elements of `x` so as to be within `[min, max]`
 ///
 /// ```
@@ -1069,9 +1609,15 @@ where
     Tensor::builder(g).append_input(x.as_ref(), false).build(op)
 }
 
-/// Takes max along specified axes.
+/// Limits the elements of `x` to the `low`th and `high`th percentiles of all its elements.
 ///
-/// Each of element of `axes` can be negative.
+/// The percentiles, in `[0, 100]`, are computed over the flattened `x` by a partial sort,
+/// interpolating linearly between the closest ranks as numpy's `percentile` does; NaNs are
+/// ignored and left as they are. This clamps outliers, e.g. for a robust normalization.
+/// The bounds are constants: the gradient passes through the elements within them, and is
+/// zero for the clamped ones.
+///
+/// Panics unless `0 <= low <= high <= 100`.
 ///
 /// ```
 /// use ndarray::array;
@@ -1079,18 +1625,54 @@ where
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let x = convert_to_tensor(array![[2., 4.], [3., 1.]], g);
-///    let y = reduce_max(&x, &[0], false);
-///    assert_eq!(y.eval(g), Ok(array![3., 4.].into_dyn()));
+///    let x = convert_to_tensor(array![[1., 2., 3.], [4., 5., 100.]], g);
+///    let y = clip_by_percentile(x, 0., 80.);
+///    assert_eq!(y.eval(g), Ok(array![[1., 2., 3.], [4., 5., 5.]].into_dyn()));
+///    let gx = grad(&[y], &[x])[0];
+///    assert_eq!(gx.eval(g), Ok(array![[1., 1., 1.], [1., 1., 0.]].into_dyn()));
 /// });
 /// ```
-pub fn reduce_max<'graph, A, AT, F: Float>(x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
+pub fn clip_by_percentile<'graph, A, F: Float>(x: A, low: F, high: F) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    AT: AsTensor<'graph, F>,
 {
+    let hundred = F::from(100.).unwrap();
+    assert!(
+        F::zero() <= low && low <= high && high <= hundred,
+        "clip_by_percentile: the percentiles must satisfy 0 <= low <= high <= 100"
+    );
     let x = x.as_ref();
-    let g = x.graph();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(array_ops::ClipByPercentile { low, high })
+}
+
+/// Takes max along specified axes.
+///
+/// Each of element of `axes` can be negative.
+/// The gradient flows to every element equal to the maximum of its group, or only to the
+/// first one in [deterministic](crate::Context::set_deterministic) mode.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[2., 4.], [3., 1.]], g);
+///    let y = reduce_max(&x, &[0], false);
+///    assert_eq!(y.eval(g), Ok(array![3., 4.].into_dyn()));
+/// });
+/// ```
+pub fn reduce_max<'graph, A, AT, F: Float>(x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    check_axes("reduce_max", axes, x);
+    let g = x.graph();
     let op = reduction_ops::ReduceMax {
         keep_dims,
         sparse_axes: false,
@@ -1104,6 +1686,8 @@ where
 /// Takes min along specified axes.
 ///
 /// Each of element of `axes` can be negative.
+/// The gradient flows to every element equal to the minimum of its group, or only to the
+/// first one in [deterministic](crate::Context::set_deterministic) mode.
 ///
 /// ```
 /// use ndarray::array;
@@ -1122,6 +1706,7 @@ where
     AT: AsTensor<'graph, F>,
 {
     let x = x.as_ref();
+    check_axes("reduce_min", axes, x);
     let g = x.graph();
     let op = reduction_ops::ReduceMin {
         keep_dims,
@@ -1198,6 +1783,7 @@ where
     AT: AsTensor<'graph, F>,
 {
     let x = x.as_ref();
+    check_axes("reduce_sum", axes, x);
     let g = x.graph();
     let op = reduction_ops::ReduceSum {
         keep_dims,
@@ -1209,6 +1795,147 @@ where
         .build(op)
 }
 
+/// Reduces `x` along `axis` with a binary `op`, starting from `init`.
+///
+/// Each lane `[x_1, ..., x_n]` along `axis`, which can be negative, becomes
+/// `op(...op(op(init, x_1), x_2)..., x_n)`, and the axis is removed. `grad(a, b)` returns
+/// the partial derivatives of `op(a, b)` with respect to `a` and `b`, from which the gradient
+/// of `x` is accumulated by the chain rule. This gives the reductions not provided as ops
+/// without writing an `Op`; the gradient is a constant that can't be differentiated again.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[2., 4.], [3., 1.]], g);
+///    // a product
+///    let y = fold(&x, 1, 1., |a, b| a * b, |a, b| (b, a));
+///    assert_eq!(y.eval(g), Ok(array![8., 3.].into_dyn()));
+///    assert_eq!(grad(&[y], &[x])[0].eval(g), Ok(array![[4., 2.], [1., 3.]].into_dyn()));
+/// });
+/// ```
+pub fn fold<'graph, A, F: Float, O, G>(
+    x: A,
+    axis: isize,
+    init: F,
+    op: O,
+    grad: G,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    O: Fn(F, F) -> F + 'static,
+    G: Fn(F, F) -> (F, F) + 'static,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(reduction_ops::Fold {
+            axis,
+            init,
+            op: std::rc::Rc::new(op),
+            grad: std::rc::Rc::new(grad),
+        })
+}
+
+/// Running maximum of `x` along `axis`.
+///
+/// `y[i]` is the max of `x[..=i]` along `axis`, or of `x[i..]` if `reverse`. The gradient of
+/// `y[i]` goes to the element holding that max; on ties, to the first one met by the scan.
+/// `axis` can be negative.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 3., 2., 4.], g);
+///    assert_eq!(cummax(x, 0, false).eval(g), Ok(array![1., 3., 3., 4.].into_dyn()));
+///    assert_eq!(cummax(x, 0, true).eval(g), Ok(array![4., 4., 4., 4.].into_dyn()));
+/// });
+/// ```
+pub fn cummax<'graph, A, F: Float>(x: A, axis: isize, reverse: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(reduction_ops::CumExtreme {
+            axis,
+            reverse,
+            max: true,
+        })
+}
+
+/// Running minimum of `x` along `axis`.
+///
+/// Same as [cummax], with the min.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![3., 1., 2., 0.], g);
+///    assert_eq!(cummin(x, 0, false).eval(g), Ok(array![3., 1., 1., 0.].into_dyn()));
+///    assert_eq!(cummin(x, 0, true).eval(g), Ok(array![0., 0., 0., 0.].into_dyn()));
+/// });
+/// ```
+pub fn cummin<'graph, A, F: Float>(x: A, axis: isize, reverse: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(reduction_ops::CumExtreme {
+            axis,
+            reverse,
+            max: false,
+        })
+}
+
+/// Takes sumation along specified axes using compensated (Kahan) summation.
+///
+/// Slower than [reduce_sum], but the rounding error does not grow with the number of
+/// summed elements, which matters when summing many values of varying magnitude
+/// (e.g. a loss over a large batch).
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1e8f32, 1., 1., -1e8], g);
+///    let y = reduce_sum_kahan(&x, &[0], false);
+///    assert_eq!(y.eval(g), Ok(ndarray::arr0(2.).into_dyn()));
+/// });
+/// ```
+pub fn reduce_sum_kahan<'graph, A, AT, F: Float>(
+    x: A,
+    axes: &AT,
+    keep_dims: bool,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    check_axes("reduce_sum_kahan", axes, x);
+    let g = x.graph();
+    Tensor::builder(g)
+        .append_input(x, false)
+        .append_input(axes.as_tensor(g), false)
+        .build(reduction_ops::ReduceSumKahan { keep_dims })
+}
+
 /// Takes mean along specified axes.
 ///
 /// Elements of `axes` can be negative.
@@ -1230,6 +1957,7 @@ where
     AT: AsTensor<'graph, F>,
 {
     let x = x.as_ref();
+    check_axes("reduce_mean", axes, x);
     let g = x.graph();
     let op = reduction_ops::ReduceMean {
         keep_dims,
@@ -1241,9 +1969,54 @@ where
         .build(op)
 }
 
+/// Takes mean along specified axes, weighting each element of `x` by `weights`.
+///
+/// Computes `sum(x * weights) / sum(weights)` over `axes`, with `weights` broadcast to the
+/// shape of `x`, e.g. per-class weights `(num_classes,)` for a loss of `(batch_size,
+/// num_classes)`. Both `x` and `weights` get gradients. Elements of `axes` can be negative.
+///
+/// Where the weights sum to zero, the result is 0 rather than NaN, so that a batch without
+/// any weighted element doesn't poison the loss; `x` gets no gradient there, and `weights`
+/// get that of `sum(x * weights)`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 3.], [2., 4.]], g);
+///    let w = convert_to_tensor(array![[1., 3.], [0., 0.]], g);
+///    let y = weighted_mean(x, w, &[1], false);
+///    assert_eq!(y.eval(g), Ok(array![2.5, 0.].into_dyn()));
+/// });
+/// ```
+pub fn weighted_mean<'graph, A, B, AT, F: Float>(
+    x: A,
+    weights: B,
+    axes: &AT,
+    keep_dims: bool,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    let axes = axes.as_tensor(g);
+    let weights = broadcast_to(weights, &shape(x));
+    let total = reduce_sum(x * weights, &axes, keep_dims);
+    let total_weight = reduce_sum(weights, &axes, keep_dims);
+    // 1 where the weights sum to zero, which leaves the (zero) numerator as is
+    let is_zero = equal(total_weight, scalar(F::zero(), g));
+    total / (total_weight + is_zero)
+}
+
 /// Takes product along specified axes.
 ///
 /// Elements of `axes` can be negative.
+/// The gradient of each element is the product of the others, which stays exact where `x` has zeros.
 ///
 /// ```
 /// use ndarray::array;
@@ -1262,6 +2035,7 @@ where
     AT: AsTensor<'graph, F>,
 {
     let x = x.as_ref();
+    check_axes("reduce_prod", axes, x);
     let g = x.graph();
     let op = reduction_ops::ReduceProd {
         keep_dims,
@@ -1273,6 +2047,85 @@ where
         .build(op)
 }
 
+/// Counts the nonzero elements along specified axes.
+///
+/// Elements of `axes` can be negative. Reducing an axis of length 0 counts 0.
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[2., 0., -1.], [0., 0., 3.]], g);
+///    let y = count_nonzero(x, &[1], false);
+///    assert_eq!(y.eval(g), Ok(array![2., 1.].into_dyn()));
+/// });
+/// ```
+pub fn count_nonzero<'graph, A, AT, F: Float>(x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    reduce_sum(not_equal(x, scalar(F::zero(), g)), axes, keep_dims)
+}
+
+/// Logical or along specified axes: 1 where any element is nonzero, else 0.
+///
+/// Elements of `axes` can be negative. Reducing an axis of length 0 gives 0.
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 0.], [0., 0.]], g);
+///    assert_eq!(reduce_any(x, &[1], false).eval(g), Ok(array![1., 0.].into_dyn()));
+/// });
+/// ```
+pub fn reduce_any<'graph, A, AT, F: Float>(x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    check_axes("reduce_any", axes, x);
+    let g = x.graph();
+    greater(count_nonzero(x, axes, keep_dims), scalar(F::zero(), g))
+}
+
+/// Logical and along specified axes: 1 where all the elements are nonzero, else 0.
+///
+/// Elements of `axes` can be negative. Reducing an axis of length 0 gives 1.
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 2.], [0., 3.]], g);
+///    assert_eq!(reduce_all(x, &[1], false).eval(g), Ok(array![1., 0.].into_dyn()));
+/// });
+/// ```
+pub fn reduce_all<'graph, A, AT, F: Float>(x: A, axes: &AT, keep_dims: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    check_axes("reduce_all", axes, x);
+    let g = x.graph();
+    let zero = scalar(F::zero(), g);
+    equal(reduce_sum(equal(x, zero), axes, keep_dims), zero)
+}
+
 /// Compute population variance along specified axes.
 ///
 /// Elements of `axes` can be negative.
@@ -1298,6 +2151,7 @@ where
     AT: AsTensor<'graph, F>,
 {
     let x = x.as_ref();
+    check_axes("reduce_variance", axes, x);
     reduce_mean(square(x - reduce_mean(x, axes, true)), axes, keep_dims)
 }
 
@@ -1305,6 +2159,9 @@ where
 ///
 /// Only one element in `shape` can be `-1`.
 ///
+/// Panics if `shape` is given as an array with a negative size other than `-1`, or with
+/// more than one `-1`. A `shape` tensor is checked at evaluation instead.
+///
 /// ```
 /// use ndarray;
 /// use autograd as ag;
@@ -1321,6 +2178,22 @@ where
     A: AsRef<Tensor<'graph, F>> + Copy,
     AT: AsTensor<'graph, F>,
 {
+    if let Some(sizes) = shape.known_values() {
+        for (i, &n) in sizes.iter().enumerate() {
+            assert!(
+                n >= -1,
+                "reshape: invalid size {} at index {} of the new shape {:?}",
+                n,
+                i,
+                sizes
+            );
+        }
+        assert!(
+            sizes.iter().filter(|&&n| n == -1).count() <= 1,
+            "reshape: more than one -1 in the new shape {:?}",
+            sizes
+        );
+    }
     let x = x.as_ref();
     let g = x.graph();
     Tensor::builder(g)
@@ -1329,7 +2202,10 @@ where
         .build(array_ops::Reshape)
 }
 
-/// Flattens the input tensor into 1-ranked (vector) without copy.
+/// Flattens the dimensions of `x` from `start_axis` onwards into one, without copy if possible.
+///
+/// `start_axis = 0` gives a 1-ranked tensor (vector), and `start_axis = 1` turns a batch of
+/// feature maps into a batch of vectors. `start_axis` can be negative.
 ///
 /// ```
 /// use autograd as ag;
@@ -1337,11 +2213,11 @@ where
 ///
 /// ag::run(|g| {
 ///    let x: ag::Tensor<f32> = zeros(&[3, 2, 2], g);
-///    let z = flatten(x);
-///    assert_eq!(z.eval(g).unwrap().shape(), &[12]);
+///    assert_eq!(flatten(x, 0).eval(g).unwrap().shape(), &[12]);
+///    assert_eq!(flatten(x, 1).eval(g).unwrap().shape(), &[3, 4]);
 /// });
 /// ```
-pub fn flatten<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+pub fn flatten<'graph, A, F: Float>(x: A, start_axis: isize) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
@@ -1349,12 +2225,13 @@ where
     let g = x.graph();
     Tensor::builder(g)
         .append_input(x.as_ref(), false)
-        .append_input(&scalar(F::one().neg(), g), false)
-        .set_shape(&shape(x))
-        .build(array_ops::Reshape)
+        .build(array_ops::Flatten { start_axis })
 }
 
-/// Returns -1 if x < 0, 0 if x==0, 1 if x > 0, element-wise.
+/// Pads `x` with `paddings[axis] = (before, after)` elements on each axis.
+///
+/// See [PadMode] for how the padded region is filled. The gradient of the padded region
+/// flows back to the elements it was copied from (nowhere for `PadMode::Constant`).
 ///
 /// ```
 /// use ndarray::array;
@@ -1362,27 +2239,48 @@ where
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a = convert_to_tensor(array![-5., 4.5, 0.], g);
-///    let b = sign(a);
-///    assert_eq!(
-///        b.eval(g).unwrap().as_slice().unwrap(),
-///        &[-1., 1., 0.]
-///    );
+///    let x = convert_to_tensor(array![1., 2., 3.], g);
+///    let a = pad(x, &[(2, 1)], PadMode::Constant(0.));
+///    let b = pad(x, &[(2, 1)], PadMode::Reflect);
+///    let c = pad(x, &[(2, 1)], PadMode::Edge);
+///    assert_eq!(a.eval(g), Ok(array![0., 0., 1., 2., 3., 0.].into_dyn()));
+///    assert_eq!(b.eval(g), Ok(array![3., 2., 1., 2., 3., 2.].into_dyn()));
+///    assert_eq!(c.eval(g), Ok(array![1., 1., 1., 2., 3., 3.].into_dyn()));
 /// });
 /// ```
-pub fn sign<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+///
+/// Evaluation fails if `paddings.len()` doesn't match the rank of `x`.
+pub fn pad<'graph, A, F: Float>(
+    x: A,
+    paddings: &[(usize, usize)],
+    mode: PadMode<F>,
+) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
+    let x = x.as_ref();
+    if let Some(rank) = known_rank(x) {
+        assert_eq!(
+            paddings.len(),
+            rank,
+            "pad: {} paddings for a tensor of rank {}",
+            paddings.len(),
+            rank
+        );
+    }
+    let g = x.graph();
     Tensor::builder(g)
-        .set_shape(&shape(a))
-        .append_input(a.as_ref(), false)
-        .build(math_ops::Sign)
+        .append_input(x, false)
+        .build(array_ops::Pad {
+            paddings: paddings.to_vec(),
+            mode,
+        })
 }
 
-/// Returns the largest integer less than or equal to a number, element-wise.
+/// Circularly shifts the elements of `x` by `shifts[i]` along `axes[i]`.
+///
+/// Elements shifted beyond the last position re-appear at the first.
+/// Shifts and axes can be negative.
 ///
 /// ```
 /// use ndarray::array;
@@ -1390,27 +2288,31 @@ where
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a = convert_to_tensor(array![-0.2, 0., 0.2], g);
-///    let b = abs(a);
-///    assert_eq!(
-///        b.eval(g),
-///        Ok(ndarray::arr1(&[0.2, 0., 0.2]).into_dyn())
-///    );
+///    let x = convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+///    let y = roll(x, &[1], &[1]);
+///    assert_eq!(y.eval(g), Ok(array![[3., 1., 2.], [6., 4., 5.]].into_dyn()));
+///    let y = roll(x, &[1, -1], &[0, 1]);
+///    assert_eq!(y.eval(g), Ok(array![[5., 6., 4.], [2., 3., 1.]].into_dyn()));
 /// });
 /// ```
-pub fn abs<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+pub fn roll<'graph, A, F: Float>(x: A, shifts: &[isize], axes: &[isize]) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
+    let x = x.as_ref();
+    let g = x.graph();
     Tensor::builder(g)
-        .set_shape(&shape(a))
-        .append_input(a.as_ref(), false)
-        .build(math_ops::Abs)
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(array_ops::Roll {
+            shifts: shifts.to_vec(),
+            axes: axes.to_vec(),
+        })
 }
 
-/// Returns the largest integer less than or equal to a number, element-wise.
+/// Reverses the order of elements of `x` along each of `axes`, without copy.
+///
+/// Axes can be negative.
 ///
 /// ```
 /// use ndarray::array;
@@ -1418,26 +2320,269 @@ where
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a = convert_to_tensor(array![-1.7, -1.5, -0.2, 0.2, 1.5, 1.7, 2.0], g);
-///    let b = floor(a);
-///    assert_eq!(
-///        b.eval(g),
-///        Ok(array![-2., -2., -1.,  0.,  1.,  1.,  2.].into_dyn())
-///    );
+///    let x = convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+///    assert_eq!(flip(x, &[1]).eval(g), Ok(array![[3., 2., 1.], [6., 5., 4.]].into_dyn()));
+///    assert_eq!(flip(x, &[0, -1]).eval(g), Ok(array![[6., 5., 4.], [3., 2., 1.]].into_dyn()));
 /// });
 /// ```
-pub fn floor<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+pub fn flip<'graph, A, F: Float>(x: A, axes: &[isize]) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
+    let x = x.as_ref();
+    let g = x.graph();
     Tensor::builder(g)
-        .set_shape(&shape(a))
-        .append_input(a.as_ref(), false)
-        .build(math_ops::Floor)
-}
-
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(array_ops::Flip {
+            axes: axes.to_vec(),
+        })
+}
+
+/// Returns -1 if x < 0, 0 if x==0, 1 if x > 0, element-wise.
+///
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![-5., 4.5, 0.], g);
+///    let b = sign(a);
+///    assert_eq!(
+///        b.eval(g).unwrap().as_slice().unwrap(),
+///        &[-1., 1., 0.]
+///    );
+/// });
+/// ```
+pub fn sign<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(a))
+        .append_input(a.as_ref(), false)
+        .build(math_ops::Sign)
+}
+
+/// Elementwise absolute value.
+///
+/// The gradient is `sign(x)`, so the subgradient at 0 is 0.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![-0.2, 0., 0.2], g);
+///    let b = abs(a);
+///    assert_eq!(
+///        b.eval(g),
+///        Ok(ndarray::arr1(&[0.2, 0., 0.2]).into_dyn())
+///    );
+/// });
+/// ```
+pub fn abs<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(a))
+        .append_input(a.as_ref(), false)
+        .build(math_ops::Abs)
+}
+
+/// Returns the largest integer less than or equal to a number, element-wise.
+///
+/// Not differentiable; see [floor_with_grad] for a straight-through gradient.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![-1.7, -1.5, -0.2, 0.2, 1.5, 1.7, 2.0], g);
+///    let b = floor(a);
+///    assert_eq!(
+///        b.eval(g),
+///        Ok(array![-2., -2., -1.,  0.,  1.,  1.,  2.].into_dyn())
+///    );
+/// });
+/// ```
+pub fn floor<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    floor_with_grad(a, RoundingGrad::Zero)
+}
+
+/// Same as [floor], with the gradient chosen by `grad`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![-0.5, 1.5], g);
+///    let b = floor_with_grad(a, RoundingGrad::StraightThrough);
+///    assert_eq!(grad(&[b], &[a])[0].eval(g), Ok(array![1., 1.].into_dyn()));
+/// });
+/// ```
+pub fn floor_with_grad<'graph, A, F: Float>(a: A, grad: RoundingGrad) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(a))
+        .append_input(a.as_ref(), false)
+        .build(math_ops::Floor { grad })
+}
+
+/// Rounds the elements of `x` to the nearest `f16` values (requires the `f16` feature).
+///
+/// The result keeps the element type `F`. The gradient passes through unchanged, so
+/// this can wrap `f32` master weights to train a model with half-precision weights.
+/// See [half_storage](crate::half_storage).
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![1.0f32, 1.0001, 65504.], g);
+///    assert_eq!(half_precision(a).eval(g), Ok(array![1., 1., 65504.].into_dyn()));
+/// });
+/// ```
+#[cfg(feature = "f16")]
+pub fn half_precision<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(x))
+        .append_input(x, false)
+        .build(math_ops::HalfPrecision)
+}
+
+/// The ops computed in half precision inside [Context::autocast](crate::Context::autocast).
+#[cfg(feature = "f16")]
+pub const AUTOCAST_OPS: [&str; 6] = [
+    "matmul",
+    "batch_matmul",
+    "batch_matmul_t",
+    "conv2d",
+    "dilated_conv2d",
+    "conv2d_transpose",
+];
+
+// Rounds `x`, an input of `op`, to `f16` inside `Context::autocast` unless `op` is kept in
+// full precision. The rounded copy is stored as `f16`.
+#[cfg(feature = "f16")]
+fn autocast<'graph, F: Float>(op: &str, x: &Tensor<'graph, F>) -> Tensor<'graph, F> {
+    // already stored as `f16`, e.g. the output of another autocast op
+    if !is_autocast(op, x.graph()) || x.graph().half_outputs.borrow().contains(&x.id) {
+        return *x;
+    }
+    let ret = half_precision(x);
+    x.graph().half_outputs.borrow_mut().insert(ret.id);
+    ret
+}
+
+// Stores `y`, the output of `op`, as `f16` inside `Context::autocast` unless `op` is kept in
+// full precision.
+#[cfg(feature = "f16")]
+fn autocast_output<'graph, F: Float>(op: &str, y: Tensor<'graph, F>) -> Tensor<'graph, F> {
+    if is_autocast(op, y.graph()) {
+        y.graph().half_outputs.borrow_mut().insert(y.id);
+    }
+    y
+}
+
+#[cfg(feature = "f16")]
+fn is_autocast<F: Float>(op: &str, g: &Graph<F>) -> bool {
+    matches!(&*g.autocast.borrow(), Some(full_precision) if !full_precision.contains(&op))
+}
+
+#[cfg(not(feature = "f16"))]
+#[inline]
+fn autocast<'graph, F: Float>(_: &str, x: &Tensor<'graph, F>) -> Tensor<'graph, F> {
+    *x
+}
+
+#[cfg(not(feature = "f16"))]
+#[inline]
+fn autocast_output<'graph, F: Float>(_: &str, y: Tensor<'graph, F>) -> Tensor<'graph, F> {
+    y
+}
+
+/// Simulates the quantization of `x` to `bits`-bit unsigned integers, for quantization-aware
+/// training.
+///
+/// Computes `(clamp(round(x / scale) + zero_point, 0, 2^bits - 1) - zero_point) * scale`,
+/// so the outputs lie on the grid of the dequantized integers. `scale` and `zero_point` are
+/// broadcast to `x`: scalars quantize per tensor, and e.g. `[channel, 1]` shaped ones per
+/// channel of a `[channel, n]` weight. `zero_point` should hold integers.
+///
+/// The gradient is the straight-through estimator: it passes to `x` unchanged where the
+/// rounded value is within the range, and is 0 where it was clamped.
+/// `scale` and `zero_point` get no gradient.
+///
+/// Panics if `bits` is not in `1..=24`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![-1., 0.26, 0.9, 2.], g);
+///    // 2 bits: the integers 0..=3, i.e. `[-0.5, 0., 0.5, 1.]`
+///    let y = fake_quantize(x, scalar(0.5, g), scalar(1., g), 2);
+///    assert_eq!(y.eval(g), Ok(array![-0.5, 0.5, 1., 1.].into_dyn()));
+///    assert_eq!(grad(&[y], &[x])[0].eval(g), Ok(array![0., 1., 1., 0.].into_dyn()));
+/// });
+/// ```
+pub fn fake_quantize<'graph, A, B, C, F: Float>(
+    x: A,
+    scale: B,
+    zero_point: C,
+    bits: u32,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(
+        (1..=24).contains(&bits),
+        "fake_quantize: bits must be in 1..=24, got {}",
+        bits
+    );
+    let x = x.as_ref();
+    let g = x.graph();
+    let (scale, zero_point) = (stop_gradient(scale), stop_gradient(zero_point));
+    let q_max = F::from((1u32 << bits) - 1).unwrap();
+    let q = round(stop_gradient(x) / scale) + zero_point;
+    let in_range = greater_equal(q, scalar(F::zero(), g)) * lesser_equal(q, scalar(q_max, g));
+    let dequantized = (clip(q, F::zero(), q_max) - zero_point) * scale;
+    // the value of `dequantized`, the gradient of `x` masked by `in_range`
+    dequantized + in_range * (x - stop_gradient(x))
+}
+
 /// Performs the `-` operation.
 ///
 /// ```
@@ -1551,6 +2696,8 @@ where
 
 /// Returns the smallest integer greater than or equal to a number, element-wise.
 ///
+/// Not differentiable; see [ceil_with_grad] for a straight-through gradient.
+///
 /// ```
 /// use ndarray::array;
 /// use autograd as ag;
@@ -1567,6 +2714,48 @@ where
 /// });
 /// ```
 pub fn ceil<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    ceil_with_grad(a, RoundingGrad::Zero)
+}
+
+/// Same as [ceil], with the gradient chosen by `grad`.
+pub fn ceil_with_grad<'graph, A, F: Float>(a: A, grad: RoundingGrad) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(a))
+        .append_input(a.as_ref(), false)
+        .build(math_ops::Ceil { grad })
+}
+
+/// Rounds to the nearest integer element-wise, rounding half-way cases away from zero.
+///
+/// Not differentiable; see [round_with_grad] for a straight-through gradient.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![-1.5, -0.2, 0.5, 1.7], g);
+///    assert_eq!(round(a).eval(g), Ok(array![-2., -0., 1., 2.].into_dyn()));
+/// });
+/// ```
+pub fn round<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    round_with_grad(a, RoundingGrad::Zero)
+}
+
+/// Same as [round], with the gradient chosen by `grad`.
+pub fn round_with_grad<'graph, A, F: Float>(a: A, grad: RoundingGrad) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
@@ -1575,13 +2764,12 @@ where
     Tensor::builder(g)
         .set_shape(&shape(a))
         .append_input(a.as_ref(), false)
-        .build(math_ops::Ceil)
+        .build(math_ops::Round { grad })
 }
 
 /// Compares a couple of tensors and returns a binary tensor.
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 pub fn greater<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1597,8 +2785,7 @@ where
 
 /// Compares a couple of tensors and returns a binary tensor.
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 pub fn greater_equal<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1614,8 +2801,7 @@ where
 
 /// Compares a couple of tensors and returns a binary tensor.
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 pub fn lesser<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1631,8 +2817,7 @@ where
 
 /// Compares a couple of tensors and returns a binary tensor.
 ///
-/// # Panics
-/// When broadcast is impossible
+/// The evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
 pub fn lesser_equal<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -1646,60 +2831,167 @@ where
         .build(math_ops::LesserEqual)
 }
 
-/// Elementwise logistic sigmoid function.
-pub fn sigmoid<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+/// Same as [lesser], under the name used by numpy.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[1.], [2.]], g);
+///    let b = convert_to_tensor(array![1., 2., 3.], g);
+///    let c = less(a, b);
+///    assert_eq!(c.eval(g), Ok(array![[0., 1., 1.], [0., 0., 1.]].into_dyn()));
+/// });
+/// ```
+pub fn less<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = x.as_ref();
-    let g = x.graph();
-    Tensor::builder(g)
-        .set_shape(&shape(x))
-        .append_input(x.as_ref(), false)
-        .build(activation_ops::Sigmoid)
+    lesser(a, b)
 }
 
-/// Elementwise exponential linear unit.
-///
-/// See <https://arxiv.org/abs/1511.07289>
-pub fn elu<'graph, A, F: Float>(x: A, alpha: F) -> Tensor<'graph, F>
+/// Same as [lesser_equal], under the name used by numpy.
+pub fn less_equal<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = x.as_ref();
-    let g = x.graph();
-    Tensor::builder(g)
-        .set_shape(&shape(x))
-        .append_input(x.as_ref(), false)
-        .build(activation_ops::ELU { alpha })
+    lesser_equal(a, b)
 }
 
-/// Elementwise rectified linear unit.
-pub fn relu<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+/// Elementwise logical AND of a couple of tensors, returning a binary tensor.
+///
+/// Nonzero elements count as true, so that the 0/1 results of the comparison ops like
+/// [greater] can be combined into masks. The inputs are broadcast against each other, and the
+/// evaluation fails when broadcast is impossible (see [crate::ndarray_ext::broadcast_shapes]).
+/// No gradient flows through the logical ops.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![-2., 0.5, 3.], g);
+///    let in_range = logical_and(greater(x, scalar(0., g)), lesser(x, scalar(1., g)));
+///    assert_eq!(in_range.eval(g), Ok(array![0., 1., 0.].into_dyn()));
+/// });
+/// ```
+pub fn logical_and<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = x.as_ref();
-    let g = x.graph();
+    let a = a.as_ref();
+    let g = a.graph();
     Tensor::builder(g)
-        .set_shape(&shape(x))
-        .append_input(x.as_ref(), false)
-        .build(activation_ops::ReLU)
+        .append_input(a.as_ref(), false)
+        .append_input(b.as_ref(), false)
+        .build(math_ops::LogicalAnd)
 }
 
-/// Elementwise leaky relu.
-///
-/// In common, `alpha` is around 0.1 ~ 0.2.
+/// Elementwise logical OR of a couple of tensors, returning a binary tensor.
 ///
-/// https://ai.stanford.edu/~amaas/papers/relu_hybrid_icml2013_final.pdf
-pub fn leaky_relu<'graph, A, F: Float>(x: A, alpha: F) -> Tensor<'graph, F>
+/// Nonzero elements count as true. See [logical_and] for the broadcasting.
+pub fn logical_or<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = x.as_ref();
-    let g = x.graph();
-    maximum(x, scalar(alpha, g) * x.as_ref())
-}
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a.as_ref(), false)
+        .append_input(b.as_ref(), false)
+        .build(math_ops::LogicalOr)
+}
+
+/// Elementwise logical XOR of a couple of tensors, returning a binary tensor.
+///
+/// Nonzero elements count as true. See [logical_and] for the broadcasting.
+pub fn logical_xor<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a.as_ref(), false)
+        .append_input(b.as_ref(), false)
+        .build(math_ops::LogicalXor)
+}
+
+/// Elementwise logical NOT, returning 1 where `a` is 0 and 0 elsewhere.
+pub fn logical_not<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(a))
+        .append_input(a.as_ref(), false)
+        .build(math_ops::LogicalNot)
+}
+
+/// Elementwise logistic sigmoid function.
+pub fn sigmoid<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(x))
+        .append_input(x.as_ref(), false)
+        .build(activation_ops::Sigmoid)
+}
+
+/// Elementwise exponential linear unit.
+///
+/// See <https://arxiv.org/abs/1511.07289>
+pub fn elu<'graph, A, F: Float>(x: A, alpha: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(x))
+        .append_input(x.as_ref(), false)
+        .build(activation_ops::ELU { alpha })
+}
+
+/// Elementwise rectified linear unit.
+pub fn relu<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .set_shape(&shape(x))
+        .append_input(x.as_ref(), false)
+        .build(activation_ops::ReLU)
+}
+
+/// Elementwise leaky relu.
+///
+/// In common, `alpha` is around 0.1 ~ 0.2.
+///
+/// https://ai.stanford.edu/~amaas/papers/relu_hybrid_icml2013_final.pdf
+pub fn leaky_relu<'graph, A, F: Float>(x: A, alpha: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    maximum(x, scalar(alpha, g) * x.as_ref())
+}
 
 /// Elementwise softplus.
 pub fn softplus<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
@@ -1722,6 +3014,7 @@ where
     A: AsRef<Tensor<'graph, F>>,
 {
     let x = x.as_ref();
+    check_axis("reduce_logsumexp", axis, x);
     let g = x.graph();
     let op = math_ops::LogSumExp {
         axis,
@@ -1735,11 +3028,27 @@ where
 /// Computes `softmax(x)` along specified axis and
 /// takes logarithm of it.
 /// `axis` can be negative.
+///
+/// Computed as `x - logsumexp(x)` by a single op, which stays finite where `log(softmax(x))`
+/// gives `-inf` (the softmax underflowing to 0) and skips the division of its gradient.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let x = T::convert_to_tensor(array![[1000., 0.]], g);
+///    let y = T::log_softmax(x, 1).eval(g).unwrap();
+///    assert_eq!(y, array![[0., -1000.]].into_dyn());
+/// });
+/// ```
 pub fn log_softmax<'graph, A, F: Float>(x: A, axis: isize) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
     let x = x.as_ref();
+    check_axis("log_softmax", axis, x);
     let g = x.graph();
     Tensor::builder(g)
         .set_shape(&shape(x))
@@ -1755,11 +3064,43 @@ where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
     let x = x.as_ref();
+    check_axis("softmax", axis, x);
     let g = x.graph();
     let op = activation_ops::Softmax { axis };
     Tensor::builder(g).append_input(x.as_ref(), false).build(op)
 }
 
+/// Computes `softmax(logits / temperature)` along `axis`.
+///
+/// `temperature` is a tensor broadcast against `logits`, e.g. a scalar or one value per row,
+/// and can be a variable: the gradient flows to both `logits` and `temperature`, so the
+/// temperature can be learned, as in calibration or knowledge distillation.
+/// Higher temperatures flatten the distribution. `axis` can be negative.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let logits = convert_to_tensor(array![[1., 2.]], g);
+///    let t = scalar(1e3, g);
+///    let p = tempered_softmax(logits, t, 1).eval(g).unwrap();
+///    assert!(p.abs_diff_eq(&array![[0.5, 0.5]].into_dyn(), 1e-3));
+/// });
+/// ```
+pub fn tempered_softmax<'graph, A, B, F: Float>(
+    logits: A,
+    temperature: B,
+    axis: isize,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    softmax(div(logits, temperature), axis)
+}
+
 /// Computes `binary_cross_entropy(sigmoid(y), t)`.
 ///
 /// This function is better than that combination in that it can prevent
@@ -1839,191 +3180,1586 @@ where
         .build(op)
 }
 
-/// Computes mean squared error
+/// Binary focal loss, i.e. [sigmoid_cross_entropy] down-weighted on easy examples.
 ///
-/// Note that the mean axis is the last one.
-pub fn mean_squared_error<'graph, A, B, F: Float>(y: A, t: B) -> Tensor<'graph, F>
-where
-    A: AsRef<Tensor<'graph, F>> + Copy,
-    B: AsRef<Tensor<'graph, F>> + Copy,
-{
-    reduce_mean(square(y.as_ref() - t.as_ref()), &[-1], false)
-}
-
-/// Matrix multiplication.
+/// Computes `alpha_t (1 - p_t)^gamma ce` elementwise, where `ce` is
+/// `sigmoid_cross_entropy(y, t)`, `p_t` is the predicted probability of the target class
+/// (`sigmoid(y)` where `t` is 1, and `1 - sigmoid(y)` where it's 0), and `alpha_t` is `alpha`
+/// for positives and `1 - alpha` for negatives. A negative `alpha` disables that weighting, and
+/// `gamma = 0` with it gives `sigmoid_cross_entropy` back.
 ///
-/// Both `a` and `b` must be 2-ranked tensors.
+/// The backward is fused, and stays finite where `p_t` saturates. `t` gets no gradient.
+///
+/// # Arguments
+/// * `y` - Logits with arbitrary shape
+/// * `t` - 0/1 targets with the same shape as `y`
+///
+/// # Returns
+/// Loss tensor with the same shape as `y`
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[4, 2], g);
-///    let b: ag::Tensor<f32> = zeros(&[2, 3], g);
-///    let c = matmul(a, b);
-///    assert_eq!(c.eval(g).unwrap().shape(), &[4, 3]);
+///    let y = convert_to_tensor(array![3., 3.], g);
+///    let t = convert_to_tensor(array![1., 0.], g);
+///    let loss = focal_loss(y, t, 2., -1.).eval(g).unwrap();
+///    // the easy positive is down-weighted much more than the hard negative
+///    assert!(loss[0] < 1e-3 && loss[1] > 2.5);
 /// });
 /// ```
-///
-/// This function supports only f32 and f64.
-pub fn matmul<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+pub fn focal_loss<'graph, A, B, F: Float>(y: A, t: B, gamma: F, alpha: F) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
+    let y = y.as_ref();
+    let g = y.graph();
     Tensor::builder(g)
-        .append_input(a.as_ref(), false)
-        .append_input(b.as_ref(), false)
-        .build(dot_ops::MatMul {
-            transpose_a: false,
-            transpose_b: false,
-        })
+        .set_shape(&shape(y))
+        .append_input(y, false)
+        .append_input(t.as_ref(), false)
+        .build(xent_ops::FocalLoss { gamma, alpha })
 }
 
-/// Computes tensor-dot-product (tensor contraction) along specified axes.
+/// Multi-class focal loss, i.e. [softmax_cross_entropy] down-weighted on easy examples.
+///
+/// Computes `-sum(t (1 - p)^gamma log p)` over the classes with `p = softmax(y)`, which is
+/// `softmax_cross_entropy` for `gamma = 0`. Per-class weights can be applied to `t`.
+///
+/// The backward is fused. `t` gets no gradient.
 ///
 /// # Arguments
-/// * `a` - First input tensor
-/// * `b` - Second input tensor
-/// * `a_axes` - `a`'s Contraction axes
-/// * `b_axes` - `b`'s Contraction axes
+/// * `y` - Tensor with shape (batch_size, num_classes)
+/// * `t` - One-hot tensor with shape (batch_size, num_classes)
 ///
-/// NOTE:
+/// # Returns
+/// Loss tensor with shape (batch_size,)
+pub fn softmax_focal_loss<'graph, A, B, F: Float>(y: A, t: B, gamma: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let y = y.as_ref();
+    let g = y.graph();
+    Tensor::builder(g)
+        .append_input(y, false)
+        .append_input(t.as_ref(), false)
+        .build(xent_ops::SoftmaxFocalLoss { gamma })
+}
+
+/// Multiplies `loss` by `scale` before backprop, for mixed precision training.
 ///
-/// * length of `a_axes` and `b_axes` must match.
-/// * Each axis number can be negative.
-/// * Supports only f32 and f64.
+/// Gradients too small for f16 underflow to zero; scaling the loss scales all the gradients
+/// by the same factor, lifting them into the representable range. Divide them back with
+/// [unscale_grads] before the update, or let a [LossScaler](crate::training::LossScaler)
+/// do both and adapt the factor.
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[3, 4, 5], g);
-///    let b: ag::Tensor<f32> = zeros(&[4, 3, 2], g);
-///    let c = tensordot(a, b, &[1, 0], &[0, 1]);
-///    assert_eq!(c.eval(g).unwrap().shape(), &[5, 2]);
+///    let x = convert_to_tensor(array![1., 2.], g);
+///    let loss = sum_all(square(x));
+///    let gx = grad(&[loss_scale(loss, 1024.)], &[x]);
+///    assert_eq!(gx[0].eval(g), Ok(array![2048., 4096.].into_dyn()));
+///    assert_eq!(unscale_grads(&gx, 1024.)[0].eval(g), Ok(array![2., 4.].into_dyn()));
 /// });
 /// ```
-///
-/// For detailed description,
-/// see <https://docs.scipy.org/doc/numpy/reference/generated/numpy.tensordot.html>.
-pub fn tensordot<'graph, A, B, AT1, AT2, F: Float>(
-    a: A,
-    b: B,
-    a_axes: &AT1,
-    b_axes: &AT2,
-) -> Tensor<'graph, F>
+pub fn loss_scale<'graph, A, F: Float>(loss: A, scale: F) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    B: AsRef<Tensor<'graph, F>> + Copy,
-    AT1: AsTensor<'graph, F>,
-    AT2: AsTensor<'graph, F>,
 {
-    let a = a.as_ref();
-    let g = a.graph();
-    // Preprocess
-    let pre = &Tensor::builder(g)
-        .append_input(a.as_ref(), false)
-        .append_input(b.as_ref(), false)
-        .append_input(&a_axes.as_tensor(g), false)
-        .append_input(&b_axes.as_tensor(g), false)
-        .build(dot_ops::TensordotPreprocess);
-    let final_shape = nth_tensor(pre, 0);
-    let perm_a = nth_tensor(pre, 1);
-    let perm_b = nth_tensor(pre, 2);
-    let new_shape_a = nth_tensor(pre, 3);
-    let new_shape_b = nth_tensor(pre, 4);
+    let loss = loss.as_ref();
+    loss * scalar(scale, loss.graph())
+}
 
-    let a_reshaped = reshape(transpose(a, &perm_a), &new_shape_a);
-    let b_reshaped = reshape(transpose(b, &perm_b), &new_shape_b);
+/// Divides each of `grads` by `scale`, undoing [loss_scale] on the gradients.
+pub fn unscale_grads<'graph, A, F: Float>(grads: &[A], scale: F) -> Vec<Tensor<'graph, F>>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    grads
+        .iter()
+        .map(|g| {
+            let g = g.as_ref();
+            g / scalar(scale, g.graph())
+        })
+        .collect()
+}
 
-    // matmul
-    let mm = matmul(a_reshaped, b_reshaped);
-    reshape(mm, &final_shape)
+/// Computes mean squared error
+///
+/// Note that the mean axis is the last one.
+pub fn mean_squared_error<'graph, A, B, F: Float>(y: A, t: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    reduce_mean(square(y.as_ref() - t.as_ref()), &[-1], false)
 }
 
-/// Batched matrix multiplication with inputs's transposition.
+/// Triplet margin loss `max(d(anchor, positive) - d(anchor, negative) + margin, 0)`.
 ///
-/// The rank of `a` and `b` must be equals.
+/// `anchor`, `positive` and `negative` are `(batch_size, d)`, and `d` is the Euclidean
+/// distance between rows (clamped as in [pairwise_distance]). Triplets whose negative is
+/// already farther than the positive by `margin` have a loss and gradient of 0.
+///
+/// # Returns
+/// Loss tensor with shape (batch_size,)
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[2, 3, 2, 4], g);
-///    let b: ag::Tensor<f32> = zeros(&[2, 3, 2, 3], g);
-///    let c = batch_matmul_t(a, b, true, false);
-///    assert_eq!(c.eval(g).unwrap().shape(), &[2, 3, 4, 3]);
+///    let anchor = convert_to_tensor(array![[0., 0.], [0., 0.]], g);
+///    let positive = convert_to_tensor(array![[1., 0.], [3., 0.]], g);
+///    let negative = convert_to_tensor(array![[0., 4.], [0., 2.]], g);
+///    let loss = triplet_loss(anchor, positive, negative, 1.).eval(g).unwrap();
+///    assert!(loss.abs_diff_eq(&array![0., 2.].into_dyn(), 1e-6));
 /// });
 /// ```
-///
-/// This function supports only f32 and f64.
-/// For detailed description, see <https://www.tensorflow.org/api_docs/python/tf/matmul>.
-pub fn batch_matmul_t<'graph, A, B, F: Float>(
-    a: A,
-    b: B,
-    trans_a: bool,
-    trans_b: bool,
+pub fn triplet_loss<'graph, A, B, C, F: Float>(
+    anchor: A,
+    positive: B,
+    negative: C,
+    margin: F,
 ) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
-    let op = dot_ops::BatchMatMul {
-        transpose_a: trans_a,
-        transpose_b: trans_b,
-    };
-    Tensor::builder(g)
-        .append_input(a.as_ref(), false)
-        .append_input(b.as_ref(), false)
-        .build(op)
+    let anchor = anchor.as_ref();
+    let g = anchor.graph();
+    let d_pos = clamped_sqrt(reduce_sum(square(anchor - positive.as_ref()), &[1], false));
+    let d_neg = clamped_sqrt(reduce_sum(square(anchor - negative.as_ref()), &[1], false));
+    relu(d_pos - d_neg + scalar(margin, g))
 }
 
-/// Batched matrix multiplication.
+/// Contrastive loss `label * d^2 + (1 - label) * max(margin - d, 0)^2`.
 ///
-/// The rank of `a` and `b` must be equals.
+/// `a` and `b` are `(batch_size, d)`, and `d` is the Euclidean distance between their rows
+/// (clamped as in [pairwise_distance]). `label` is `(batch_size,)`: 1 for the pairs to pull
+/// together, 0 for the pairs to push at least `margin` apart. Dissimilar pairs already
+/// `margin` apart have a loss and gradient of 0.
+///
+/// # Returns
+/// Loss tensor with shape (batch_size,)
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = ones((&[2, 3, 4, 2]), g);
+///    let a = convert_to_tensor(array![[0., 0.], [0., 0.], [0., 0.]], g);
+///    let b = convert_to_tensor(array![[3., 0.], [0., 1.], [0., 3.]], g);
+///    let label = convert_to_tensor(array![1., 0., 0.], g);
+///    let loss = contrastive_loss(a, b, label, 2.).eval(g).unwrap();
+///    assert!(loss.abs_diff_eq(&array![9., 1., 0.].into_dyn(), 1e-6));
+/// });
+/// ```
+pub fn contrastive_loss<'graph, A, B, C, F: Float>(
+    a: A,
+    b: B,
+    label: C,
+    margin: F,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let label = label.as_ref();
+    let g = a.graph();
+    let squared = reduce_sum(square(a - b.as_ref()), &[1], false);
+    let hinge = relu(scalar(margin, g) - clamped_sqrt(squared));
+    label * squared + (scalar(F::one(), g) - label) * square(hinge)
+}
+
+/// Intersection over union of the boxes in `a` and those in `b`, row by row.
+///
+/// `a` and `b` are `(n, 4)` tensors of boxes `[x1, y1, x2, y2]` with `x1 <= x2` and `y1 <= y2`,
+/// and the result is `(n,)`. Differentiable with respect to both, e.g. for a `1 - iou` box
+/// regression loss. The division by the union goes through [safe_div], so that a pair of
+/// zero-area boxes gives 0 instead of NaN.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[0., 0., 2., 2.], [0., 0., 1., 1.]], g);
+///    let b = convert_to_tensor(array![[1., 1., 3., 3.], [2., 2., 3., 3.]], g);
+///    let y = iou(a, b).eval(g).unwrap();
+///    assert!(y.abs_diff_eq(&array![1. / 7., 0.].into_dyn(), 1e-6));
+/// });
+/// ```
+pub fn iou<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let (inter, union, _) = box_overlaps(a.as_ref(), b.as_ref());
+    safe_div(inter, union, F::epsilon())
+}
+
+/// Generalized intersection over union of the boxes in `a` and those in `b`, row by row.
+///
+/// Computes `iou - (c - union) / c` where `c` is the area of the smallest box enclosing both,
+/// which still has a gradient for disjoint boxes, unlike [iou]. The result is in `(-1, 1]`.
+/// Takes the same `(n, 4)` boxes as [iou], and guards its divisions the same way.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[0., 0., 2., 2.], [0., 0., 1., 1.]], g);
+///    let b = convert_to_tensor(array![[1., 1., 3., 3.], [2., 2., 3., 3.]], g);
+///    let y = giou(a, b).eval(g).unwrap();
+///    assert!(y.abs_diff_eq(&array![1. / 7. - 2. / 9., -7. / 9.].into_dyn(), 1e-6));
+/// });
+/// ```
+pub fn giou<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let (inter, union, enclosing) = box_overlaps(a.as_ref(), b.as_ref());
+    let eps = F::epsilon();
+    safe_div(inter, union, eps) - safe_div(enclosing - union, enclosing, eps)
+}
+
+// The areas of the intersection, union and enclosing box of the rows of `a` and `b`
+fn box_overlaps<'graph, F: Float>(
+    a: &Tensor<'graph, F>,
+    b: &Tensor<'graph, F>,
+) -> (Tensor<'graph, F>, Tensor<'graph, F>, Tensor<'graph, F>) {
+    let a = unstack(a, 4, 1);
+    let b = unstack(b, 4, 1);
+    let area = |c: &[Tensor<'graph, F>]| (c[2] - c[0]) * (c[3] - c[1]);
+    let inter_w = relu(minimum(a[2], b[2]) - maximum(a[0], b[0]));
+    let inter_h = relu(minimum(a[3], b[3]) - maximum(a[1], b[1]));
+    let inter = inter_w * inter_h;
+    let union = area(&a) + area(&b) - inter;
+    let enclosing_w = maximum(a[2], b[2]) - minimum(a[0], b[0]);
+    let enclosing_h = maximum(a[3], b[3]) - minimum(a[1], b[1]);
+    (inter, union, enclosing_w * enclosing_h)
+}
+
+// `sqrt(max(x, eps))`, whose gradient stays finite at 0
+fn clamped_sqrt<'graph, F: Float>(x: Tensor<'graph, F>) -> Tensor<'graph, F> {
+    sqrt(maximum(x, scalar(F::epsilon(), x.graph())))
+}
+
+/// Matrix multiplication.
+///
+/// Both `a` and `b` must be 2-ranked tensors.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[4, 2], g);
+///    let b: ag::Tensor<f32> = zeros(&[2, 3], g);
+///    let c = matmul(a, b);
+///    assert_eq!(c.eval(g).unwrap().shape(), &[4, 3]);
+/// });
+/// ```
+///
+/// This function supports only f32 and f64.
+pub fn matmul<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let y = Tensor::builder(g)
+        .append_input(autocast("matmul", a), false)
+        .append_input(autocast("matmul", b.as_ref()), false)
+        .build(dot_ops::MatMul {
+            transpose_a: false,
+            transpose_b: false,
+        });
+    autocast_output("matmul", y)
+}
+
+/// Matrix multiplication computed by tiles of `block` × `block`.
+///
+/// Same as [matmul], but the kernel multiplies one pair of tiles at a time and accumulates
+/// the products into the output, which bounds the kernel's scratch memory for very large inputs.
+/// The gradients are also tiled.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = ones(&[5, 3], g);
+///    let b: ag::Tensor<f32> = ones(&[3, 7], g);
+///    let c = matmul_tiled(a, b, 2);
+///    assert_eq!(c.eval(g), matmul(a, b).eval(g));
+/// });
+/// ```
+///
+/// Panics if `block` is 0. This function supports only f32 and f64.
+pub fn matmul_tiled<'graph, A, B, F: Float>(a: A, b: B, block: usize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(block > 0, "matmul_tiled: block must be positive");
+    let a = a.as_ref();
+    Tensor::builder(a.graph())
+        .append_input(a, false)
+        .append_input(b.as_ref(), false)
+        .build(dot_ops::TiledMatMul {
+            transpose_a: false,
+            transpose_b: false,
+            block,
+        })
+}
+
+/// Outer product of two vectors.
+///
+/// Both `a` and `b` must be 1-ranked tensors; the result has shape `[a.len, b.len]`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![1., 2.], g);
+///    let b = convert_to_tensor(array![3., 4., 5.], g);
+///    let c = outer(a, b);
+///    assert_eq!(c.eval(g), Ok(array![[3., 4., 5.], [6., 8., 10.]].into_dyn()));
+/// });
+/// ```
+pub fn outer<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a, false)
+        .append_input(b.as_ref(), false)
+        .build(dot_ops::Outer)
+}
+
+/// Inner product of two vectors.
+///
+/// Both `a` and `b` must be 1-ranked tensors of the same length; the result is a scalar.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![1., 2., 3.], g);
+///    let b = convert_to_tensor(array![4., 5., 6.], g);
+///    let c = dot(a, b);
+///    assert_eq!(c.eval(g), Ok(ndarray::arr0(32.).into_dyn()));
+/// });
+/// ```
+pub fn dot<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a, false)
+        .append_input(b.as_ref(), false)
+        .build(dot_ops::Dot)
+}
+
+/// Euclidean distances between the rows of `a` and the rows of `b`.
+///
+/// `a` is `(n, d)` and `b` is `(m, d)`; the result is `(n, m)`.
+/// Computed as `sqrt(|a|^2 + |b|^2 - 2ab^T)`, where the squared distances are clamped
+/// to at least `F::epsilon()` so that the gradient stays finite for identical rows.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[0., 0.], [3., 4.]], g);
+///    let b = convert_to_tensor(array![[3., 0.]], g);
+///    let d = pairwise_distance(a, b).eval(g).unwrap();
+///    assert!(d.abs_diff_eq(&array![[3.], [4.]].into_dyn(), 1e-6));
+/// });
+/// ```
+pub fn pairwise_distance<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let b = b.as_ref();
+    let g = a.graph();
+    let sq_a = reduce_sum(square(a), &[1], true);
+    let sq_b = transpose(reduce_sum(square(b), &[1], true), &[1, 0]);
+    let ab = matmul(a, transpose(b, &[1, 0]));
+    let squared = sq_a + sq_b - scalar(F::from(2.).unwrap(), g) * ab;
+    clamped_sqrt(squared)
+}
+
+/// Cosine similarity of `a` and `b` along `axis`: `sum(a * b) / (|a| * |b|)`.
+///
+/// `a` and `b` must have the same shape, and `axis` is removed from the result.
+/// Where either vector is zero the similarity is undefined; it's 0 there, and so is the
+/// gradient, instead of a NaN.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[1., 0.], [1., 1.], [0., 0.]], g);
+///    let b = convert_to_tensor(array![[2., 0.], [-1., -1.], [1., 2.]], g);
+///    let c = cosine_similarity(a, b, 1).eval(g).unwrap();
+///    assert!(c.abs_diff_eq(&array![1., -1., 0.].into_dyn(), 1e-12));
+/// });
+/// ```
+pub fn cosine_similarity<'graph, A, B, F: Float>(a: A, b: B, axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let b = b.as_ref();
+    let g = a.graph();
+    let sq_a = reduce_sum(square(a), &[axis], false);
+    let sq_b = reduce_sum(square(b), &[axis], false);
+    // the norms are clamped only to keep the masked-out quotients (and gradients) finite
+    let zero = scalar(F::zero(), g);
+    let tiny = scalar(F::min_positive_value(), g);
+    let nonzero = greater(sq_a, zero) * greater(sq_b, zero);
+    let norms = sqrt(maximum(sq_a, tiny)) * sqrt(maximum(sq_b, tiny));
+    nonzero * reduce_sum(a * b, &[axis], false) / norms
+}
+
+/// Sum of the diagonal elements of a square matrix.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///    assert_eq!(trace(a).eval(g), Ok(ndarray::arr0(5.).into_dyn()));
+/// });
+/// ```
+pub fn trace<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a, false)
+        .build(linalg_ops::Trace)
+}
+
+/// Extracts the diagonal of a square matrix, or builds a diagonal matrix from a vector.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///    assert_eq!(diag(a).eval(g), Ok(array![1., 4.].into_dyn()));
+///
+///    let v = convert_to_tensor(array![1., 2.], g);
+///    assert_eq!(diag(v).eval(g), Ok(array![[1., 0.], [0., 2.]].into_dyn()));
+/// });
+/// ```
+pub fn diag<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    Tensor::builder(g)
+        .append_input(x, false)
+        .build(linalg_ops::Diag)
+}
+
+/// Cholesky decomposition of a symmetric positive-definite matrix.
+///
+/// Returns the lower-triangular `L` such that `a = L L^T`. Only the lower triangle of `a`
+/// is read. Evaluation fails with `OpError::NotPositiveDefinite` if `a` is not positive-definite.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[4., 2.], [2., 5.]], g);
+///    assert_eq!(cholesky(a).eval(g), Ok(array![[2., 0.], [1., 2.]].into_dyn()));
+/// });
+/// ```
+///
+/// The gradient supports only f32 and f64.
+pub fn cholesky<'graph, A, F: Float>(a: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    Tensor::builder(g)
+        .append_input(a, false)
+        .set_shape(&shape(a))
+        .build(linalg_ops::Cholesky)
+}
+
+/// Reduced QR decomposition of a matrix.
+///
+/// Returns `(q, r)` such that `a = q r`, where `q` has orthonormal columns and `r` is
+/// upper-triangular with a non-negative diagonal.
+/// For an `m x n` matrix, `q` is `m x k` and `r` is `k x n` with `k = min(m, n)`.
+///
+/// The gradient is defined only for `m >= n` and full-rank `a` (`r` must be invertible).
+/// Higher-order gradients are not supported.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[3., 1.], [4., 2.], [0., 5.]], g);
+///    let (q, r) = qr(a);
+///    let a2 = matmul(q, r).eval(g).unwrap();
+///    assert!(a2.abs_diff_eq(&a.eval(g).unwrap(), 1e-10));
+/// });
+/// ```
+pub fn qr<'graph, A, F: Float>(a: A) -> (Tensor<'graph, F>, Tensor<'graph, F>)
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let qr = Tensor::builder(g)
+        .append_input(a, false)
+        .build(linalg_ops::Qr);
+    let (q, r) = (nth_tensor(qr, 0), nth_tensor(qr, 1));
+    let factor = |index| {
+        Tensor::builder(g)
+            .append_input(q, false)
+            .append_input(r, false)
+            .append_backprop_input(a)
+            .build(linalg_ops::QrFactor { index })
+    };
+    (factor(0), factor(1))
+}
+
+/// Thin singular value decomposition of a matrix.
+///
+/// Returns `(u, s, v)` such that `a = u diag(s) v^T`, with the singular values `s` in
+/// descending order. For an `m x n` matrix, `u` is `m x k`, `s` has length `k` and `v` is
+/// `n x k` where `k = min(m, n)`.
+///
+/// The gradient is undefined when singular values are repeated (or zero, for `u` and `v`),
+/// since the singular vectors aren't unique there. Note that the singular vectors are
+/// determined only up to sign. Higher-order gradients are not supported.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![[3., 0.], [0., -2.]], g);
+///    let (_u, s, _v) = svd(a);
+///    assert!(s.eval(g).unwrap().abs_diff_eq(&array![3., 2.].into_dyn(), 1e-10));
+/// });
+/// ```
+pub fn svd<'graph, A, F: Float>(a: A) -> (Tensor<'graph, F>, Tensor<'graph, F>, Tensor<'graph, F>)
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let svd = Tensor::builder(g)
+        .append_input(a, false)
+        .build(linalg_ops::Svd);
+    let (u, s, v) = (nth_tensor(svd, 0), nth_tensor(svd, 1), nth_tensor(svd, 2));
+    let factor = |index| {
+        Tensor::builder(g)
+            .append_input(u, false)
+            .append_input(s, false)
+            .append_input(v, false)
+            .append_backprop_input(a)
+            .build(linalg_ops::SvdFactor { index })
+    };
+    (factor(0), factor(1), factor(2))
+}
+
+/// Least-squares solution `x` minimizing `||matmul(a, x) - b||` for an `m x n` matrix `a`
+/// and an `m x k` matrix `b`.
+///
+/// `a` may be tall (an overdetermined system), square or wide. If `a` is rank-deficient,
+/// e.g. wide, this returns the solution of minimum norm, as `numpy.linalg.lstsq` does:
+/// the singular values of `a` below `eps * max(m, n)` times the largest one count as zeros.
+/// The result is `matmul(pinv(a), b)` with the pseudo-inverse computed by an SVD.
+///
+/// The gradients flow to both `a` and `b`. The one of `a` assumes that the rank of `a`
+/// doesn't change around it, and is unreliable when a singular value is close to the cutoff.
+/// Higher-order gradients are not supported.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    // fits `y = 1 + 2 t` through three points
+///    let a = convert_to_tensor(array![[1., 0.], [1., 1.], [1., 2.]], g);
+///    let b = convert_to_tensor(array![[1.], [3.], [5.]], g);
+///    let x = lstsq(a, b).eval(g).unwrap();
+///    assert!(x.abs_diff_eq(&array![[1.], [2.]].into_dyn(), 1e-10));
+/// });
+/// ```
+pub fn lstsq<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let pinv = Tensor::builder(g)
+        .append_input(a, false)
+        .build(linalg_ops::Pinv);
+    matmul(pinv, b)
+}
+
+/// Computes tensor-dot-product (tensor contraction) along specified axes.
+///
+/// # Arguments
+/// * `a` - First input tensor
+/// * `b` - Second input tensor
+/// * `a_axes` - `a`'s Contraction axes
+/// * `b_axes` - `b`'s Contraction axes
+///
+/// NOTE:
+///
+/// * length of `a_axes` and `b_axes` must match.
+/// * Each axis number can be negative.
+/// * Supports only f32 and f64.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[3, 4, 5], g);
+///    let b: ag::Tensor<f32> = zeros(&[4, 3, 2], g);
+///    let c = tensordot(a, b, &[1, 0], &[0, 1]);
+///    assert_eq!(c.eval(g).unwrap().shape(), &[5, 2]);
+/// });
+/// ```
+///
+/// For detailed description,
+/// see <https://docs.scipy.org/doc/numpy/reference/generated/numpy.tensordot.html>.
+pub fn tensordot<'graph, A, B, AT1, AT2, F: Float>(
+    a: A,
+    b: B,
+    a_axes: &AT1,
+    b_axes: &AT2,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    AT1: AsTensor<'graph, F>,
+    AT2: AsTensor<'graph, F>,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    // Preprocess
+    let pre = &Tensor::builder(g)
+        .append_input(a.as_ref(), false)
+        .append_input(b.as_ref(), false)
+        .append_input(&a_axes.as_tensor(g), false)
+        .append_input(&b_axes.as_tensor(g), false)
+        .build(dot_ops::TensordotPreprocess);
+    let final_shape = nth_tensor(pre, 0);
+    let perm_a = nth_tensor(pre, 1);
+    let perm_b = nth_tensor(pre, 2);
+    let new_shape_a = nth_tensor(pre, 3);
+    let new_shape_b = nth_tensor(pre, 4);
+
+    let a_reshaped = reshape(transpose(a, &perm_a), &new_shape_a);
+    let b_reshaped = reshape(transpose(b, &perm_b), &new_shape_b);
+
+    // matmul
+    let mm = matmul(a_reshaped, b_reshaped);
+    reshape(mm, &final_shape)
+}
+
+/// Einstein summation.
+///
+/// `equation` is a comma separated list of subscript labels (one per operand) optionally
+/// followed by `->` and the output labels, e.g. `"ij,jk->ik"` (matmul), `"bij,bjk->bik"`
+/// (batched matmul), `"ij->ji"` (transpose), `"ii->"` (trace) or `"i,j->ij"` (outer product).
+/// Labels that don't appear in the output are summed over.
+/// If `->` is omitted, the output labels are the ones appearing only once, sorted alphabetically.
+///
+/// Panics if `equation` is malformed. Evaluation fails if the sizes of the dimensions
+/// sharing a label don't match.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let a = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///    let b = T::convert_to_tensor(array![[1., 0.], [0., 1.]], g);
+///    let c = T::einsum("ij,jk->ik", &[a, b]);
+///    assert_eq!(c.eval(g), Ok(array![[1., 2.], [3., 4.]].into_dyn()));
+///
+///    let tr = T::einsum("ii", &[a]);
+///    assert_eq!(tr.eval(g), Ok(ndarray::arr0(5.).into_dyn()));
+/// });
+/// ```
+///
+/// NOTE: This is a straightforward loop over all the label indices; prefer `matmul` and
+/// friends for large inputs.
+pub fn einsum<'graph, A, F: Float>(equation: &str, operands: &[A]) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(!operands.is_empty(), "einsum: no operands given");
+    let (inputs, output) = match einsum_ops::parse_equation(equation, operands.len()) {
+        Ok(parsed) => parsed,
+        Err(e) => panic!("einsum: {}", e),
+    };
+    let g = operands[0].as_ref().graph();
+    let mut builder = Tensor::builder(g);
+    for x in operands {
+        builder = builder.append_input(x.as_ref(), false);
+    }
+    builder.build(einsum_ops::Einsum {
+        inputs,
+        output,
+        with_template: false,
+    })
+}
+
+/// Makes a complex tensor from its real and imaginary parts.
+///
+/// Complex tensors are plain float tensors whose last axis has size 2 and holds the
+/// real and imaginary parts, so `add`, `sub`, scalar multiplication and reductions
+/// over the other axes work on them as is. See also [real], [imag], [complex_mul] and [fft].
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let re = T::convert_to_tensor(array![1., 2.], g);
+///    let im = T::convert_to_tensor(array![3., 4.], g);
+///    let z = T::complex(re, im);
+///    assert_eq!(z.eval(g), Ok(array![[1., 3.], [2., 4.]].into_dyn()));
+///    assert_eq!(T::imag(z).eval(g), Ok(array![3., 4.].into_dyn()));
+/// });
+/// ```
+pub fn complex<'graph, A, B, F: Float>(re: A, im: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let re = re.as_ref();
+    Tensor::builder(re.graph())
+        .append_input(re, false)
+        .append_input(im.as_ref(), false)
+        .build(fft_ops::MakeComplex)
+}
+
+/// Real part of a complex tensor (see [complex]).
+pub fn real<'graph, A, F: Float>(z: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let z = z.as_ref();
+    Tensor::builder(z.graph())
+        .append_input(z, false)
+        .build(fft_ops::ComplexPart { index: 0 })
+}
+
+/// Imaginary part of a complex tensor (see [complex]).
+pub fn imag<'graph, A, F: Float>(z: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let z = z.as_ref();
+    Tensor::builder(z.graph())
+        .append_input(z, false)
+        .build(fft_ops::ComplexPart { index: 1 })
+}
+
+/// Elementwise product of complex tensors (see [complex]).
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let a = T::convert_to_tensor(array![[1., 2.]], g); // 1 + 2i
+///    let b = T::convert_to_tensor(array![[3., -1.]], g); // 3 - i
+///    assert_eq!(T::complex_mul(a, b).eval(g), Ok(array![[5., 5.]].into_dyn()));
+/// });
+/// ```
+pub fn complex_mul<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let (a_re, a_im) = (real(a), imag(a));
+    let (b_re, b_im) = (real(b), imag(b));
+    complex(a_re * b_re - a_im * b_im, a_re * b_im + a_im * b_re)
+}
+
+/// Discrete Fourier transform of a complex tensor along `axis`.
+///
+/// `x` is a complex tensor (see [complex]) and `axis` indexes its complex axes, i.e. the
+/// trailing axis of size 2 is not counted; it can be negative.
+/// The transform is unnormalized, and [ifft] is its inverse.
+/// Lengths that are powers of two use the radix-2 FFT, the others the O(n^2) DFT.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let x = T::complex(T::convert_to_tensor(array![1., 1., 1., 1.], g), T::zeros(&[4], g));
+///    let y = T::fft(x, 0);
+///    assert_eq!(T::real(y).eval(g), Ok(array![4., 0., 0., 0.].into_dyn()));
+/// });
+/// ```
+pub fn fft<'graph, A, F: Float>(x: A, axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(fft_ops::Fft {
+            axis,
+            inverse: false,
+            normalize: false,
+        })
+}
+
+/// Inverse of [fft] along `axis`, normalized by the length of the axis.
+pub fn ifft<'graph, A, F: Float>(x: A, axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(fft_ops::Fft {
+            axis,
+            inverse: true,
+            normalize: true,
+        })
+}
+
+/// Discrete Fourier transform of a real tensor along `axis`.
+///
+/// Returns the `n / 2 + 1` non-redundant bins as a complex tensor (see [complex]), where
+/// `n` is the length of `axis`; the other bins are their complex conjugates.
+/// Use [real] and [imag], or `sqrt(real^2 + imag^2)` for magnitudes, to get back to real tensors.
+/// [irfft] is the inverse.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let x = T::convert_to_tensor(array![1., 2., 3., 4.], g);
+///    let y = T::rfft(x, 0);
+///    assert_eq!(y.eval(g), Ok(array![[10., 0.], [-2., 2.], [-2., 0.]].into_dyn()));
+///    assert_eq!(T::irfft(y, 0, 4).eval(g), Ok(array![1., 2., 3., 4.].into_dyn()));
+/// });
+/// ```
+pub fn rfft<'graph, A, F: Float>(x: A, axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(fft_ops::Rfft {
+            axis,
+            scaled: false,
+        })
+}
+
+/// Inverse of [rfft]: real signal of length `n` along `axis` from its `n / 2 + 1` bins.
+///
+/// `axis` indexes the complex axes of `z` as in [fft]. The imaginary parts of the first bin
+/// (and of the last one when `n` is even) are ignored.
+pub fn irfft<'graph, A, F: Float>(z: A, axis: isize, n: usize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let z = z.as_ref();
+    Tensor::builder(z.graph())
+        .append_input(z, false)
+        .build(fft_ops::Irfft {
+            axis,
+            n: Some(n),
+            scaled: true,
+        })
+}
+
+/// Batched matrix multiplication with inputs's transposition.
+///
+/// The rank of `a` and `b` must be equals.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[2, 3, 2, 4], g);
+///    let b: ag::Tensor<f32> = zeros(&[2, 3, 2, 3], g);
+///    let c = batch_matmul_t(a, b, true, false);
+///    assert_eq!(c.eval(g).unwrap().shape(), &[2, 3, 4, 3]);
+/// });
+/// ```
+///
+/// This function supports only f32 and f64.
+/// For detailed description, see <https://www.tensorflow.org/api_docs/python/tf/matmul>.
+pub fn batch_matmul_t<'graph, A, B, F: Float>(
+    a: A,
+    b: B,
+    trans_a: bool,
+    trans_b: bool,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let op = dot_ops::BatchMatMul {
+        transpose_a: trans_a,
+        transpose_b: trans_b,
+    };
+    let y = Tensor::builder(g)
+        .append_input(autocast("batch_matmul_t", a), false)
+        .append_input(autocast("batch_matmul_t", b.as_ref()), false)
+        .build(op);
+    autocast_output("batch_matmul_t", y)
+}
+
+/// Batched matrix multiplication.
+///
+/// The rank of `a` and `b` must be equals.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = ones((&[2, 3, 4, 2]), g);
 ///    let b: ag::Tensor<f32> = ones((&[2, 3, 2, 3]), g);
 ///    let c = batch_matmul(a, b);
 ///    assert_eq!(c.eval(g).unwrap().shape(), &[2, 3, 4, 3]);
 /// });
 /// ```
-///
-/// This function supports only f32 and f64.
-/// For detailed description, see <https://www.tensorflow.org/api_docs/python/tf/matmul>.
-pub fn batch_matmul<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+///
+/// This function supports only f32 and f64.
+/// For detailed description, see <https://www.tensorflow.org/api_docs/python/tf/matmul>.
+pub fn batch_matmul<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let op = dot_ops::BatchMatMul {
+        transpose_a: false,
+        transpose_b: false,
+    };
+    let y = Tensor::builder(g)
+        .append_input(autocast("batch_matmul", a), false)
+        .append_input(autocast("batch_matmul", b.as_ref()), false)
+        .build(op);
+    autocast_output("batch_matmul", y)
+}
+
+/// Takes diff between two tensors.
+///
+/// Returns the sorted, unique values in `a` that are not in `b`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![4., 1., 5., 2., 3., 6.], g);
+///    let b = convert_to_tensor(array![[2., 3.], [1., 4.]], g);
+///    let c = setdiff1d(a, b);
+///    assert_eq!(
+///        c.eval(g),
+///        Ok(ndarray::arr1(&[5., 6.]).into_dyn())
+///    )
+/// });
+/// ```
+///
+pub fn setdiff1d<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let a = a.as_ref();
+    let g = a.graph();
+    let op = array_ops::SetDiff1D;
+    Tensor::builder(g)
+        .append_input(a.as_ref(), false)
+        .append_input(b.as_ref(), false)
+        .build(op)
+}
+
+/// Permutes dimensions without copy.
+///
+/// It's like TensorFlow or NumPy's.
+/// `x`'s rank (ndim) and `axes.len()` must match.
+///
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[1, 2, 3, 4, 5], g);
+///    let b = transpose(a, &[4, 2, 3, 0, 1]);
+///    assert_eq!(b.eval(g).unwrap().shape(), &[5, 3, 4, 1, 2]);
+/// });
+/// ```
+pub fn transpose<'graph, A, AT, F: Float>(x: A, axes: &AT) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
+{
+    let x = x.as_ref();
+    if let Some(perm) = axes.known_values() {
+        let mut seen = vec![false; perm.len()];
+        for &axis in &perm {
+            assert!(
+                0 <= axis
+                    && (axis as usize) < perm.len()
+                    && !std::mem::replace(&mut seen[axis as usize], true),
+                "transpose: axes {:?} isn't a permutation of 0..{}",
+                perm,
+                perm.len()
+            );
+        }
+        if let Some(rank) = known_rank(x) {
+            assert_eq!(
+                perm.len(),
+                rank,
+                "transpose: axes {:?} for a tensor of rank {}",
+                perm,
+                rank
+            );
+        }
+    }
+    let g = x.graph();
+    let op = math_ops::Transpose { invert_axes: false };
+    Tensor::builder(g)
+        .append_input(x.as_ref(), false)
+        .append_input(&axes.as_tensor(g), false)
+        .build(op)
+}
+
+/// Splits input tensors into parts.
+///
+/// Splits `x` into `sizes.len()` parts along `axis`.
+///
+/// The size of dimension of each part is `sizes[i]` on `axis`, but is
+/// `x.shape[i]` on other axis (similar to TensorFlow's `split`).
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[3, 7, 5], g);
+///    let b = split(a, &[2, 3, 2], 1);
+///
+///    let evaluated = g.evaluator().extend(&[&b[0], &b[1], &b[2]]).run();
+///    let e0 = &evaluated[0];
+///    let e1 = &evaluated[1];
+///    let e2 = &evaluated[2];
+///
+///    assert_eq!(e0.as_ref().unwrap().shape(), &[3, 2, 5]);
+///    assert_eq!(e1.as_ref().unwrap().shape(), &[3, 3, 5]);
+///    assert_eq!(e2.as_ref().unwrap().shape(), &[3, 2, 5]);
+/// });
+/// ```
+pub fn split<'graph, A, F: Float>(x: A, sizes: &[usize], axis: isize) -> Vec<Tensor<'graph, F>>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    let len = sizes.len();
+    let mut ret = Vec::with_capacity(len);
+    for i in 0..len {
+        let mut start_index = 0usize;
+        for &size in sizes[..i].iter() {
+            start_index += size;
+        }
+        let end_index = start_index + sizes[i];
+        ret.push(
+            Tensor::builder(g)
+                .append_input(x.as_ref(), false)
+                .build(array_ops::Split {
+                    start_index: start_index as isize,
+                    end_index: end_index as isize,
+                    axis,
+                }),
+        );
+    }
+    ret
+}
+
+/// Slices the input tensor.
+///
+/// # Arguments
+/// * `x` - Tensor with arbitrary shape.
+/// * `starts` - Inclusive start indices for the dimensions.
+/// * `ends` - End indices for the dimensions. **Each index is inclusive if it is negative and exclusive if it's not.**
+///
+/// NOTE: Negative values in `starts` and `ends` are counted from the back of the axis.
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[4, 4], g);
+///    let b = slice(a, &[0, 0], &[-1, 2]); // numpy equivalent is a[:, 0:2]
+///
+///    assert_eq!(b.eval(g).unwrap().shape(), &[4, 2]);
+/// });
+/// ```
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[4, 4], g);
+///    let b = slice(a, &[0, 0], &[-2, 2]); // numpy equivalent is a[:-1, :2]
+///
+///    assert_eq!(b.eval(g).unwrap().shape(), &[3, 2]);
+/// });
+/// ```
+pub fn slice<'graph, A, F: Float>(x: A, starts: &[isize], ends: &[isize]) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let g = x.graph();
+    // TODO: Make starts and ends ArrayLike
+    assert_eq!(starts.len(), ends.len());
+    let starts_ends = starts.iter().zip(ends.iter());
+
+    let indices = starts_ends
+        .map(|(s, &e)| {
+            let e = if e == -1 {
+                None
+            } else {
+                Some(if e < -1 { e + 1 } else { e })
+            };
+            let slice = ndarray::Slice::new(*s, e, 1);
+            ndarray::SliceInfoElem::from(slice)
+        })
+        .collect::<Vec<ndarray::SliceInfoElem>>();
+    if let Some(dims) = x.known_dims() {
+        assert_eq!(
+            indices.len(),
+            dims.len(),
+            "slice: {} ranges for a tensor of rank {}",
+            indices.len(),
+            dims.len()
+        );
+        for (axis, (&elem, dim)) in indices.iter().zip(dims).enumerate() {
+            if let crate::Dim::Known(len) = dim {
+                assert!(
+                    array_ops::slice_in_bounds(elem, len),
+                    "slice: range {}..{} is out of bounds for axis {} of length {}",
+                    starts[axis],
+                    ends[axis],
+                    axis,
+                    len
+                );
+            }
+        }
+    }
+
+    Tensor::builder(g)
+        .append_input(x.as_ref(), false)
+        .build(array_ops::Slice { indices })
+}
+
+/// Extracts overlapping windows of `size` elements along `axis`, taken every `stride` elements.
+///
+/// `axis` is replaced by the windows, and the elements of each window are placed in a new last axis,
+/// so an input of shape `[a, n, b]` with `axis = 1` gives `[a, (n - size) / stride + 1, b, size]`.
+/// In the gradient, the elements shared by several windows accumulate the gradients of all of them.
+/// `axis` can be negative.
+///
+/// Panics if `size` or `stride` is 0.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///    let x = T::convert_to_tensor(array![1., 2., 3., 4., 5.], g);
+///    let y = T::sliding_window(x, 3, 2, 0);
+///    assert_eq!(y.eval(g), Ok(array![[1., 2., 3.], [3., 4., 5.]].into_dyn()));
+/// });
+/// ```
+pub fn sliding_window<'graph, A, F: Float>(
+    x: A,
+    size: usize,
+    stride: usize,
+    axis: isize,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(size > 0, "sliding_window: size must be positive");
+    assert!(stride > 0, "sliding_window: stride must be positive");
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(array_ops::SlidingWindow { size, stride, axis })
+}
+
+/// Concatenates input tensors along specified axis.
+///
+/// `axis` can be negative.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a: ag::Tensor<f32> = zeros(&[3, 2], g);
+///    let b: ag::Tensor<f32> = zeros(&[3, 2], g);
+///    let c: ag::Tensor<f32> = zeros(&[3, 2], g);
+///    let d = concat(&[a, b, c], 0);
+///
+///    assert_eq!(d.eval(g).unwrap().shape(), &[9, 2]);
+/// });
+/// ```
+pub fn concat<'graph, A, F: Float>(tensors: &[A], axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert_ne!(tensors.len(), 0);
+    for t in tensors {
+        check_axis("concat", axis, t.as_ref());
+    }
+    let g = tensors[0].as_ref().graph();
+    let op = array_ops::Concat { axis };
+    let mut b = Tensor::builder(g);
+    for t in tensors {
+        b = b.append_input(t.as_ref(), false);
+    }
+    b.build(op)
+}
+
+/// Stacks input tensors along a new axis.
+///
+/// All the tensors must have the same shape; stacking `n` tensors of shape `[a, b]` along
+/// `axis = 1` gives `[a, n, b]`. `axis` can be negative, counting from the end of the
+/// output's axes. Each input gets its slice of the gradient. See [unstack] for the inverse.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = convert_to_tensor(array![1., 2.], g);
+///    let b = convert_to_tensor(array![3., 4.], g);
+///    assert_eq!(stack(&[a, b], 0).eval(g), Ok(array![[1., 2.], [3., 4.]].into_dyn()));
+///    assert_eq!(stack(&[a, b], -1).eval(g), Ok(array![[1., 3.], [2., 4.]].into_dyn()));
+/// });
+/// ```
+pub fn stack<'graph, A, F: Float>(tensors: &[A], axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert_ne!(tensors.len(), 0);
+    for rank in tensors.iter().filter_map(|t| known_rank(t.as_ref())) {
+        assert!(
+            is_axis(axis, rank + 1),
+            "stack: invalid axis {} for an output of rank {}",
+            axis,
+            rank + 1
+        );
+    }
+    let g = tensors[0].as_ref().graph();
+    let mut b = Tensor::builder(g);
+    for t in tensors {
+        b = b.append_input(t.as_ref(), false);
+    }
+    b.build(array_ops::Stack { axis })
+}
+
+/// Splits `x` into its `num` slices along `axis`, the inverse of [stack].
+///
+/// The slices have the rank of `x` minus one, and their gradients are stacked back into the
+/// gradient of `x`. `num` is the length of `axis`, as it must be known before evaluation;
+/// the evaluation fails if it doesn't match. `axis` can be negative.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 2.], [3., 4.], [5., 6.]], g);
+///    let cols = unstack(x, 2, 1);
+///    assert_eq!(cols[1].eval(g), Ok(array![2., 4., 6.].into_dyn()));
+///    assert!(unstack(x, 2, 0)[0].eval(g).is_err());
+/// });
+/// ```
+pub fn unstack<'graph, A, F: Float>(x: A, num: usize, axis: isize) -> Vec<Tensor<'graph, F>>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    let y = Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(array_ops::Unstack { axis, num });
+    (0..num).map(|i| nth_tensor(y, i)).collect()
+}
+
+/// Applies `build` to each slice of `xs` along its leading axis and stacks the results.
+///
+/// This batches a subgraph written for a single example, like `vmap` of JAX: `build` gets
+/// the `i`-th slice, with the rank of `xs` minus one, and its outputs must all have the same
+/// shape. The subgraph is built once per slice, so gradients flow through each copy. The
+/// length of the leading axis is read from [Context::infer_shape](crate::Context::infer_shape),
+/// hence `g`.
+///
+/// Panics if the length of the leading axis of `xs` isn't known, or is zero.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let xs = convert_to_tensor(array![[1., 2.], [3., 4.], [5., 6.]], g);
+///    // an outer product per example
+///    let ys = vmap(|x| matmul(expand_dims(x, &[1]), expand_dims(x, &[0])), xs, g);
+///    assert_eq!(ys.eval(g).unwrap().shape(), &[3, 2, 2]);
+///    assert_eq!(ys.eval(g).unwrap()[[2, 0, 1]], 30.);
+/// });
+/// ```
+pub fn vmap<'graph, A, F: Float, B>(
+    mut build: B,
+    xs: A,
+    g: &'graph crate::Context<F>,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: FnMut(&Tensor<'graph, F>) -> Tensor<'graph, F>,
+{
+    let num = g
+        .infer_shape(xs)
+        .ok()
+        .flatten()
+        .and_then(|shape| shape.first().and_then(|d| d.value()))
+        .expect("vmap: the length of the leading axis of `xs` must be known");
+    let ys: Vec<_> = unstack(xs, num, 0).iter().map(|x| build(x)).collect();
+    stack(&ys, 0)
+}
+
+/// Makes coordinate grids from the 1-D tensors `xs`.
+///
+/// With `MeshgridIndexing::Ij`, every output has shape `[n_0, n_1, ..., n_k]` where `n_i` is the
+/// length of `xs[i]`, and the `i`-th output repeats `xs[i]` along axis `i`.
+/// `MeshgridIndexing::Xy` swaps the first two axes, so that the 2-D grids are `[n_1, n_0]`.
+/// The gradient of each output flows back to its input vector.
+///
+/// Panics if `xs` is empty.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 2., 3.], g);
+///    let y = convert_to_tensor(array![4., 5.], g);
+///    let grids = meshgrid(&[x, y], MeshgridIndexing::Ij);
+///    assert_eq!(grids[0].eval(g), Ok(array![[1., 1.], [2., 2.], [3., 3.]].into_dyn()));
+///    assert_eq!(grids[1].eval(g), Ok(array![[4., 5.], [4., 5.], [4., 5.]].into_dyn()));
+///
+///    let grids = meshgrid(&[x, y], MeshgridIndexing::Xy);
+///    assert_eq!(grids[0].eval(g), Ok(array![[1., 2., 3.], [1., 2., 3.]].into_dyn()));
+///    assert_eq!(grids[1].eval(g), Ok(array![[4., 4., 4.], [5., 5., 5.]].into_dyn()));
+/// });
+/// ```
+pub fn meshgrid<'graph, A, F: Float>(xs: &[A], indexing: MeshgridIndexing) -> Vec<Tensor<'graph, F>>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert_ne!(xs.len(), 0);
+    let g = xs[0].as_ref().graph();
+    let ndim = xs.len();
+    // axis of the output along which `xs[i]` runs
+    let axis_of = |i: usize| match indexing {
+        MeshgridIndexing::Xy if ndim > 1 && i < 2 => 1 - i,
+        _ => i,
+    };
+    let mut sizes: Vec<_> = xs.iter().map(|x| shape(x.as_ref())).collect();
+    if axis_of(0) != 0 {
+        sizes.swap(0, 1);
+    }
+    let grid_shape = concat(&sizes, 0);
+    xs.iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let mut vec_shape = vec![F::one(); ndim];
+            vec_shape[axis_of(i)] = -F::one();
+            let vec_shape = convert_to_tensor(ndarray::Array1::from(vec_shape), g);
+            reshape(x.as_ref(), &vec_shape) + zeros(&grid_shape, g)
+        })
+        .collect()
+}
+
+/// Gathers subviews from the input tensor.
+///
+/// Same spec as <https://www.tensorflow.org/api_docs/python/tf/gather>.
+/// For example, this can be used for embedding vectors lookup etc.
+///
+/// Unlike `ag::gather`, `indices` can contain negative elements.
+///
+/// # Returns
+/// Tensor with shape `param.shape[..axis] + indices.shape + param.shape[axis+1..]`
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let param = zeros(&[5, 4, 8, 2], g);
+///    let indices = convert_to_tensor(array![[5., -1., 3.], [2., 1., -2.]], g);
+///    let y = gather_common(param, indices, 2);
+///
+///    assert_eq!(y.eval(g).unwrap().shape(), &[5, 4, 2, 3, 2])
+/// });
+/// ```
+pub fn gather_common<'graph, A, B, F: Float>(param: A, indices: B, axis: isize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let param = param.as_ref();
+    let g = param.graph();
+    let op = array_ops::Gather {
+        axis,
+        should_normalize_negative_indices: true,
+    };
+    Tensor::builder(g)
+        .append_input(indices.as_ref(), false)
+        .append_input(param.as_ref(), false)
+        .build(op)
+}
+
+/// Gathers subviews from the input tensor.
+///
+/// Same spec as <https://www.tensorflow.org/api_docs/python/tf/gather>.
+/// For example, this can be used for embedding vectors lookup etc.
+///
+/// # Returns
+/// Tensor with shape `param.shape[..axis] + indices.shape + param.shape[axis+1..]`
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let param = zeros(&[5, 4, 8, 2], g);
+///    let indices = convert_to_tensor(array![[5., 4., 3.], [2., 1., 0.]], g);  // shape: (2, 3)
+///    let y = gather(param, indices, 2);
+///
+///    assert_eq!(y.eval(g).unwrap().shape(), &[5, 4, 2, 3, 2])
+/// });
+/// ```
+pub fn gather<'graph, A, B, F: Float>(param: A, indices: B, axis: isize) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
-    let op = dot_ops::BatchMatMul {
-        transpose_a: false,
-        transpose_b: false,
+    let param = param.as_ref();
+    let g = param.graph();
+    let op = array_ops::Gather {
+        axis,
+        should_normalize_negative_indices: false,
     };
     Tensor::builder(g)
-        .append_input(a.as_ref(), false)
-        .append_input(b.as_ref(), false)
+        .append_input(indices.as_ref(), false)
+        .append_input(param, false)
         .build(op)
 }
 
-/// Takes diff between two tensors.
+/// Adds `updates` into a zero tensor of `target_shape` at `indices` along `axis`.
 ///
-/// Returns the sorted, unique values in `a` that are not in `b`.
+/// The inverse of [gather_common]: `updates` must have shape
+/// `target_shape[..axis] + indices.shape + target_shape[axis+1..]`, and the subviews at a
+/// repeated index accumulate. `indices` can contain negative elements.
+/// The gradient of `updates` gathers the output gradient at `indices`.
 ///
 /// ```
 /// use ndarray::array;
@@ -2031,341 +4767,416 @@ where
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a = convert_to_tensor(array![4., 1., 5., 2., 3., 6.], g);
-///    let b = convert_to_tensor(array![[2., 3.], [1., 4.]], g);
-///    let c = setdiff1d(a, b);
+///    let indices = convert_to_tensor(array![2., 0., 2.], g);
+///    let updates = convert_to_tensor(array![[1., 2.], [3., 4.], [5., 6.]], g);
+///    let y = scatter_add(&[4, 2], indices, updates, 0);
+///
 ///    assert_eq!(
-///        c.eval(g),
-///        Ok(ndarray::arr1(&[5., 6.]).into_dyn())
-///    )
+///        y.eval(g),
+///        Ok(array![[3., 4.], [0., 0.], [6., 8.], [0., 0.]].into_dyn())
+///    );
 /// });
 /// ```
-///
-pub fn setdiff1d<'graph, A, B, F: Float>(a: A, b: B) -> Tensor<'graph, F>
+pub fn scatter_add<'graph, AT, A, B, F: Float>(
+    target_shape: &AT,
+    indices: A,
+    updates: B,
+    axis: isize,
+) -> Tensor<'graph, F>
 where
+    AT: AsTensor<'graph, F>,
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let a = a.as_ref();
-    let g = a.graph();
-    let op = array_ops::SetDiff1D;
+    let updates = updates.as_ref();
+    let g = updates.graph();
     Tensor::builder(g)
-        .append_input(a.as_ref(), false)
-        .append_input(b.as_ref(), false)
-        .build(op)
+        .append_input(indices.as_ref(), false)
+        .append_input(updates, false)
+        .append_input(target_shape.as_tensor(g), false)
+        .build(array_ops::ScatterAdd { axis })
 }
 
-/// Permutes dimensions without copy.
-///
-/// It's like TensorFlow or NumPy's.
-/// `x`'s rank (ndim) and `axes.len()` must match.
+/// Selects the elements of `x` where `mask` is nonzero into a 1-D tensor.
 ///
+/// `mask` must have the same shape as `x`, and the elements are taken in row-major order.
+/// The length of the output depends on the values of `mask`, so its shape is unknown
+/// until evaluation. The gradient of `x` puts the output gradient back at the selected
+/// positions, with zeros elsewhere.
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[1, 2, 3, 4, 5], g);
-///    let b = transpose(a, &[4, 2, 3, 0, 1]);
-///    assert_eq!(b.eval(g).unwrap().shape(), &[5, 3, 4, 1, 2]);
+///    let x = convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+///    let mask = greater(x, scalar(2.5, g));
+///    let y = boolean_mask(x, mask);
+///
+///    assert_eq!(y.eval(g), Ok(array![3., 4., 5., 6.].into_dyn()));
 /// });
 /// ```
-pub fn transpose<'graph, A, AT, F: Float>(x: A, axes: &AT) -> Tensor<'graph, F>
+pub fn boolean_mask<'graph, A, B, F: Float>(x: A, mask: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    AT: AsTensor<'graph, F>,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
     let x = x.as_ref();
-    let g = x.graph();
-    let op = math_ops::Transpose { invert_axes: false };
-    Tensor::builder(g)
-        .append_input(x.as_ref(), false)
-        .append_input(&axes.as_tensor(g), false)
-        .build(op)
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .append_input(mask.as_ref(), false)
+        .build(array_ops::BooleanMask)
 }
 
-/// Splits input tensors into parts.
+/// Sets the elements of `x` where `mask` is nonzero to `value`.
 ///
-/// Splits `x` into `sizes.len()` parts along `axis`.
+/// `mask` broadcasts to the shape of `x`, e.g. a `[seq, seq]` causal mask over `[batch, seq, seq]`
+/// attention scores. `value` can be infinite, such as `-inf` before a softmax. The gradient of
+/// `x` is zero at the filled elements and passes through elsewhere.
 ///
-/// The size of dimension of each part is `sizes[i]` on `axis`, but is
-/// `x.shape[i]` on other axis (similar to TensorFlow's `split`).
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///    let mask = convert_to_tensor(array![[0., 1.]], g);
+///    let y = masked_fill(x, mask, f64::NEG_INFINITY);
+///
+///    let inf = f64::INFINITY;
+///    assert_eq!(y.eval(g), Ok(array![[1., -inf], [3., -inf]].into_dyn()));
+/// });
+/// ```
+pub fn masked_fill<'graph, A, B, F: Float>(x: A, mask: B, value: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .append_input(mask.as_ref(), false)
+        .set_shape(&shape(x))
+        .build(array_ops::MaskedFill { value })
+}
+
+/// Normalizes the input tensor with its mean and variance along specified axis.
 ///
 /// ```
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[3, 7, 5], g);
-///    let b = split(a, &[2, 3, 2], 1);
+///    let x: ag::Tensor<f32> = standard_normal(&[3, 4], g);
+///    let y1 = normalize(x, &[0]);
+///    let y2 = normalize(x, &[0]);
 ///
-///    let evaluated = g.evaluator().extend(&[&b[0], &b[1], &b[2]]).run();
+///    let evaluated = g.evaluator().extend(&[y1, y2]).run();
 ///    let e0 = &evaluated[0];
 ///    let e1 = &evaluated[1];
-///    let e2 = &evaluated[2];
-///
-///    assert_eq!(e0.as_ref().unwrap().shape(), &[3, 2, 5]);
-///    assert_eq!(e1.as_ref().unwrap().shape(), &[3, 3, 5]);
-///    assert_eq!(e2.as_ref().unwrap().shape(), &[3, 2, 5]);
+///    assert_eq!(e0.as_ref().unwrap().shape(), &[3, 4]);
+///    assert_eq!(e1.as_ref().unwrap().shape(), &[3, 4]);
 /// });
 /// ```
-pub fn split<'graph, A, F: Float>(x: A, sizes: &[usize], axis: isize) -> Vec<Tensor<'graph, F>>
+pub fn normalize<'graph, A, AT, F: Float>(_x: A, _axes: &AT) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    AT: AsTensor<'graph, F>,
 {
-    let x = x.as_ref();
+    let x = _x.as_ref();
     let g = x.graph();
-    let len = sizes.len();
-    let mut ret = Vec::with_capacity(len);
-    for i in 0..len {
-        let mut start_index = 0usize;
-        for &size in sizes[..i].iter() {
-            start_index += size;
-        }
-        let end_index = start_index + sizes[i];
-        ret.push(
-            Tensor::builder(g)
-                .append_input(x.as_ref(), false)
-                .build(array_ops::Split {
-                    start_index: start_index as isize,
-                    end_index: end_index as isize,
-                    axis,
-                }),
-        );
-    }
-    ret
+    let axes = _axes.as_tensor(g);
+    let mean = reduce_mean(x.as_ref(), &axes, true);
+    let centered = x - mean;
+    let variance = reduce_mean(square(centered), &axes, true);
+    let em5 = scalar(F::from(1e-5).unwrap(), g);
+    centered * inv_sqrt(variance + em5)
 }
 
-/// Slices the input tensor.
+/// Layer normalization: normalizes `x` to zero mean and unit variance along its last axis.
 ///
-/// # Arguments
-/// * `x` - Tensor with arbitrary shape.
-/// * `starts` - Inclusive start indices for the dimensions.
-/// * `ends` - End indices for the dimensions. **Each index is inclusive if it is negative and exclusive if it's not.**
+/// Computes `(x - mean) / sqrt(variance + eps)` as one op. The inverse standard deviation is
+/// kept from the forward pass for the gradient, which is computed by one fused op as well;
+/// that gradient isn't differentiable again. Multiply and add the output with variables for
+/// the learned scale and shift.
 ///
-/// NOTE: Negative values in `starts` and `ends` are counted from the back of the axis.
 /// ```
 /// use autograd as ag;
-/// use ag::tensor_ops::*;
+/// use ag::tensor_ops as T;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[4, 4], g);
-///    let b = slice(a, &[0, 0], &[-1, 2]); // numpy equivalent is a[:, 0:2]
-///
-///    assert_eq!(b.eval(g).unwrap().shape(), &[4, 2]);
+///    let x = T::convert_to_tensor(ag::ndarray::arr2(&[[1., 3.], [2., 2.]]), g);
+///    let y = T::layer_norm(x, 1e-5).eval(g).unwrap();
+///    let expected = ag::ndarray::arr2(&[[-1., 1.], [0., 0.]]).into_dyn();
+///    assert!(y.abs_diff_eq(&expected, 1e-4));
 /// });
 /// ```
+pub fn layer_norm<'graph, A, F: Float>(x: A, eps: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .set_shape(&shape(x))
+        .build(math_ops::LayerNorm { eps })
+}
+
+/// Divides `x` by its L2 norm along `axis`, so that each vector along it has unit length.
+///
+/// Norms smaller than `eps` are replaced with `eps`, so a zero vector stays zero and
+/// its gradient finite.
 ///
 /// ```
+/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
 ///
 /// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[4, 4], g);
-///    let b = slice(a, &[0, 0], &[-2, 2]); // numpy equivalent is a[:-1, :2]
-///
-///    assert_eq!(b.eval(g).unwrap().shape(), &[3, 2]);
+///    let x = convert_to_tensor(array![[3., 4.], [0., 0.]], g);
+///    let y = l2_normalize(x, 1, 1e-12).eval(g).unwrap();
+///    assert!(y.abs_diff_eq(&array![[0.6, 0.8], [0., 0.]].into_dyn(), 1e-12));
 /// });
 /// ```
-pub fn slice<'graph, A, F: Float>(x: A, starts: &[isize], ends: &[isize]) -> Tensor<'graph, F>
+pub fn l2_normalize<'graph, A, F: Float>(x: A, axis: isize, eps: F) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
 {
     let x = x.as_ref();
     let g = x.graph();
-    // TODO: Make starts and ends ArrayLike
-    assert_eq!(starts.len(), ends.len());
-    let starts_ends = starts.iter().zip(ends.iter());
-
-    let indices = starts_ends
-        .map(|(s, &e)| {
-            let e = if e == -1 {
-                None
-            } else {
-                Some(if e < -1 { e + 1 } else { e })
-            };
-            let slice = ndarray::Slice::new(*s, e, 1);
-            ndarray::SliceInfoElem::from(slice)
-        })
-        .collect::<Vec<ndarray::SliceInfoElem>>();
-
-    Tensor::builder(g)
-        .append_input(x.as_ref(), false)
-        .build(array_ops::Slice { indices })
+    let squared_norm = reduce_sum(square(x), &[axis], true);
+    // clamping the squared norm keeps the gradient of the square root finite at zero
+    x * inv_sqrt(maximum(squared_norm, scalar(eps * eps, g)))
 }
 
-/// Concatenates input tensors along specified axis.
+/// Applies batch normalization.
 ///
-/// `axis` can be negative.
+/// `scale` and `shift` should be shared variables.
+/// Since normalization is performed along 1st axis of `x`,
+/// both of them should have shape `(1, x.shape[1])`
 ///
 /// ```
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
+/// use ag::prelude::*;
 ///
-/// ag::run(|g| {
-///    let a: ag::Tensor<f32> = zeros(&[3, 2], g);
-///    let b: ag::Tensor<f32> = zeros(&[3, 2], g);
-///    let c: ag::Tensor<f32> = zeros(&[3, 2], g);
-///    let d = concat(&[a, b, c], 0);
+/// let mut env = ag::VariableEnvironment::new();
+/// let scale = env.set(ag::ndarray_ext::ones::<f32>(&[1, 4]));
+/// let shift = env.set(ag::ndarray_ext::zeros::<f32>(&[1, 4]));
 ///
-///    assert_eq!(d.eval(g).unwrap().shape(), &[9, 2]);
+/// env.run(|g| {
+///    let x = standard_normal(&[3, 4], g);
+///    let norm = batch_norm(x, g.variable(scale), g.variable(shift));
+///
+///    assert_eq!(norm.eval(g).unwrap().shape(), &[3, 4]);
 /// });
 /// ```
-pub fn concat<'graph, A, F: Float>(tensors: &[A], axis: isize) -> Tensor<'graph, F>
+pub fn batch_norm<'graph, A, B, C, F: Float>(x: A, scale: B, shift: C) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
 {
-    assert_ne!(tensors.len(), 0);
-    let g = tensors[0].as_ref().graph();
-    let op = array_ops::Concat { axis };
-    let mut b = Tensor::builder(g);
-    for t in tensors {
-        b = b.append_input(t.as_ref(), false);
-    }
-    b.build(op)
+    normalize(x, &[0]) * scale.as_ref() + shift.as_ref()
 }
 
-/// Gathers subviews from the input tensor.
+/// Applies batch normalization, keeping the running statistics registered under `name`.
 ///
-/// Same spec as <https://www.tensorflow.org/api_docs/python/tf/gather>.
-/// For example, this can be used for embedding vectors lookup etc.
-///
-/// Unlike `ag::gather`, `indices` can contain negative elements.
+/// The running mean and variance must be registered beforehand with
+/// [VariableEnvironment::add_running_stats](crate::VariableEnvironment::add_running_stats),
+/// with the same shape as `scale` and `shift`.
 ///
-/// # Returns
-/// Tensor with shape `param.shape[..axis] + indices.shape + param.shape[axis+1..]`
+/// In training mode `x` is normalized with its own statistics (like [batch_norm]), and each
+/// evaluation moves the running ones toward them:
+/// `running = momentum * running + (1 - momentum) * batch`, using the unbiased batch variance.
+/// Otherwise `x` is normalized with the running statistics, which are left untouched.
+/// See [Context::running_stats](crate::Context::running_stats) to read or overwrite them.
 ///
 /// ```
-/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
+/// use ag::prelude::*;
 ///
-/// ag::run(|g| {
-///    let param = zeros(&[5, 4, 8, 2], g);
-///    let indices = convert_to_tensor(array![[5., -1., 3.], [2., 1., -2.]], g);
-///    let y = gather_common(param, indices, 2);
+/// let mut env = ag::VariableEnvironment::new();
+/// let scale = env.set(ag::ndarray_ext::ones::<f32>(&[1, 4]));
+/// let shift = env.set(ag::ndarray_ext::zeros::<f32>(&[1, 4]));
+/// env.add_running_stats("bn1", &[1, 4]);
 ///
-///    assert_eq!(y.eval(g).unwrap().shape(), &[5, 4, 2, 3, 2])
+/// env.run(|g| {
+///    let x = standard_normal(&[3, 4], g);
+///    let norm = batch_norm_with_stats(x, g.variable(scale), g.variable(shift), "bn1", 0.9, true, g);
+///
+///    assert_eq!(norm.eval(g).unwrap().shape(), &[3, 4]);
+///    let (mean, var) = g.running_stats("bn1").unwrap();
+///    assert_eq!(mean.shape(), &[1, 4]);
+///    assert_eq!(var.shape(), &[1, 4]);
 /// });
 /// ```
-pub fn gather_common<'graph, A, B, F: Float>(param: A, indices: B, axis: isize) -> Tensor<'graph, F>
+pub fn batch_norm_with_stats<'graph, A, B, C, F: Float>(
+    x: A,
+    scale: B,
+    shift: C,
+    name: &str,
+    momentum: F,
+    training: bool,
+    g: &'graph crate::Context<F>,
+) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let param = param.as_ref();
-    let g = param.graph();
-    let op = array_ops::Gather {
-        axis,
-        should_normalize_negative_indices: true,
-    };
-    Tensor::builder(g)
-        .append_input(indices.as_ref(), false)
-        .append_input(param.as_ref(), false)
-        .build(op)
+    batch_norm_impl(x.as_ref(), scale.as_ref(), shift.as_ref(), name, momentum, Some(training), g)
 }
 
-/// Gathers subviews from the input tensor.
-///
-/// Same spec as <https://www.tensorflow.org/api_docs/python/tf/gather>.
-/// For example, this can be used for embedding vectors lookup etc.
+/// Same as [batch_norm_with_stats], in [training](crate::Context::training) mode or not
+/// depending on the context at each evaluation.
 ///
-/// # Returns
-/// Tensor with shape `param.shape[..axis] + indices.shape + param.shape[axis+1..]`
+/// Both modes are computed (without updating the running statistics in inference), and the
+/// output is picked with [training_switch].
 ///
 /// ```
-/// use ndarray::array;
 /// use autograd as ag;
 /// use ag::tensor_ops::*;
+/// use ag::prelude::*;
 ///
-/// ag::run(|g| {
-///    let param = zeros(&[5, 4, 8, 2], g);
-///    let indices = convert_to_tensor(array![[5., 4., 3.], [2., 1., 0.]], g);  // shape: (2, 3)
-///    let y = gather(param, indices, 2);
+/// let mut env = ag::VariableEnvironment::new();
+/// let scale = env.set(ag::ndarray_ext::ones::<f64>(&[1, 2]));
+/// let shift = env.set(ag::ndarray_ext::zeros::<f64>(&[1, 2]));
+/// env.add_running_stats("bn1", &[1, 2]);
 ///
-///    assert_eq!(y.eval(g).unwrap().shape(), &[5, 4, 2, 3, 2])
+/// env.run(|g| {
+///    let x = convert_to_tensor(ag::ndarray::arr2(&[[1., 2.], [3., 6.]]), g);
+///    let norm = batch_norm_by_mode(x, g.variable(scale), g.variable(shift), "bn1", 0.9, g);
+///
+///    g.training(false);
+///    // normalized with the initial running statistics, mean 0 and variance 1
+///    let y = norm.eval(g).unwrap();
+///    assert!((y[[1, 1]] - 6.).abs() < 1e-4);
+///    assert_eq!(g.running_stats("bn1").unwrap().0, ag::ndarray_ext::zeros::<f64>(&[1, 2]));
 /// });
 /// ```
-pub fn gather<'graph, A, B, F: Float>(param: A, indices: B, axis: isize) -> Tensor<'graph, F>
+pub fn batch_norm_by_mode<'graph, A, B, C, F: Float>(
+    x: A,
+    scale: B,
+    shift: C,
+    name: &str,
+    momentum: F,
+    g: &'graph crate::Context<F>,
+) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
+    C: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let param = param.as_ref();
-    let g = param.graph();
-    let op = array_ops::Gather {
-        axis,
-        should_normalize_negative_indices: false,
-    };
-    Tensor::builder(g)
-        .append_input(indices.as_ref(), false)
-        .append_input(param, false)
-        .build(op)
+    batch_norm_impl(x.as_ref(), scale.as_ref(), shift.as_ref(), name, momentum, None, g)
+}
+
+// `training` is `None` to follow `Context::training`
+fn batch_norm_impl<'graph, F: Float>(
+    x: &Tensor<'graph, F>,
+    scale: &Tensor<'graph, F>,
+    shift: &Tensor<'graph, F>,
+    name: &str,
+    momentum: F,
+    training: Option<bool>,
+    g: &'graph crate::Context<F>,
+) -> Tensor<'graph, F> {
+    let ns = g.env().namespace(RUNNING_STATS_NAMESPACE_ID);
+    let running_mean = g.variable_by_name(format!("{}/mean", name), &ns);
+    let running_var = g.variable_by_name(format!("{}/var", name), &ns);
+    let em5 = scalar(F::from(1e-5).unwrap(), g);
+    let inference = (x - running_mean) * inv_sqrt(running_var + em5) * scale + shift;
+    if training == Some(false) {
+        return inference;
+    }
+
+    let mean = reduce_mean(x, &[0], true);
+    let centered = x - mean;
+    let variance = reduce_mean(square(centered), &[0], true);
+    // Bessel's correction for the running estimate
+    let n = shape(x).access_elem(0);
+    let unbiased = variance * n / maximum(n - scalar(F::one(), g), scalar(F::one(), g));
+    let m = scalar(momentum, g);
+    let rest = scalar(F::one() - momentum, g);
+    let mut new_mean = running_mean * m + mean * rest;
+    let mut new_var = running_var * m + unbiased * rest;
+    if training.is_none() {
+        // the running statistics are assigned to themselves in inference
+        new_mean = training_switch(new_mean, running_mean);
+        new_var = training_switch(new_var, running_var);
+    }
+    let update_mean = assign(running_mean, new_mean);
+    let update_var = assign(running_var, new_var);
+    let y = centered * inv_sqrt(variance + em5) * scale + shift;
+    let y = y.depends_on(&[update_mean, update_var]);
+    if training.is_none() {
+        training_switch(y, inference)
+    } else {
+        y
+    }
 }
 
-/// Normalizes the input tensor with its mean and variance along specified axis.
+/// Returns `when_training` if the graph is evaluated in [training](crate::Context::training)
+/// mode, `otherwise` if not.
+///
+/// Both inputs are evaluated either way and must have the same shape; the gradient flows
+/// to the one picked.
 ///
 /// ```
 /// use autograd as ag;
-/// use ag::tensor_ops::*;
-///
-/// ag::run(|g| {
-///    let x: ag::Tensor<f32> = standard_normal(&[3, 4], g);
-///    let y1 = normalize(x, &[0]);
-///    let y2 = normalize(x, &[0]);
+/// use ag::tensor_ops as T;
 ///
-///    let evaluated = g.evaluator().extend(&[y1, y2]).run();
-///    let e0 = &evaluated[0];
-///    let e1 = &evaluated[1];
-///    assert_eq!(e0.as_ref().unwrap().shape(), &[3, 4]);
-///    assert_eq!(e1.as_ref().unwrap().shape(), &[3, 4]);
+/// ag::run(|g: &mut ag::Context<f32>| {
+///     let y = T::training_switch(T::ones(&[2], g), T::zeros(&[2], g));
+///     assert_eq!(y.eval(g).unwrap(), ag::ndarray::arr1(&[1., 1.]).into_dyn());
+///     g.training(false);
+///     assert_eq!(y.eval(g).unwrap(), ag::ndarray::arr1(&[0., 0.]).into_dyn());
 /// });
 /// ```
-pub fn normalize<'graph, A, AT, F: Float>(_x: A, _axes: &AT) -> Tensor<'graph, F>
+pub fn training_switch<'graph, A, B, F: Float>(when_training: A, otherwise: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    AT: AsTensor<'graph, F>,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = _x.as_ref();
-    let g = x.graph();
-    let axes = _axes.as_tensor(g);
-    let mean = reduce_mean(x.as_ref(), &axes, true);
-    let centered = x - mean;
-    let variance = reduce_mean(square(centered), &axes, true);
-    let em5 = scalar(F::from(1e-5).unwrap(), g);
-    centered * inv_sqrt(variance + em5)
+    let a = when_training.as_ref();
+    Tensor::builder(a.graph())
+        .append_input(a, false)
+        .append_input(otherwise.as_ref(), false)
+        .build(graph_ops::TrainingSwitch)
 }
 
-/// Applies batch normalization.
+/// Returns the value of `primary`, or that of `secondary` if `primary` fails to evaluate.
 ///
-/// `scale` and `shift` should be shared variables.
-/// Since normalization is performed along 1st axis of `x`,
-/// both of them should have shape `(1, x.shape[1])`
+/// Any [OpError](crate::op::OpError) in the subgraph of `primary` is caught here instead
+/// of failing the evaluation, e.g. a `cholesky` of a matrix that turned out not to be
+/// positive definite. Both inputs are evaluated either way, and the evaluation only fails
+/// if both do. The gradient flows to the one used; when `primary` failed, the gradients
+/// through it are likely to fail too.
 ///
 /// ```
 /// use autograd as ag;
-/// use ag::tensor_ops::*;
-/// use ag::prelude::*;
-///
-/// let mut env = ag::VariableEnvironment::new();
-/// let scale = env.set(ag::ndarray_ext::ones::<f32>(&[1, 4]));
-/// let shift = env.set(ag::ndarray_ext::zeros::<f32>(&[1, 4]));
-///
-/// env.run(|g| {
-///    let x = standard_normal(&[3, 4], g);
-///    let norm = batch_norm(x, g.variable(scale), g.variable(shift));
+/// use ag::tensor_ops as T;
 ///
-///    assert_eq!(norm.eval(g).unwrap().shape(), &[3, 4]);
+/// ag::run(|g: &mut ag::Context<f32>| {
+///     let x = T::ones(&[2], g);
+///     // out of bounds
+///     let y = T::fallback(T::slice(x, &[5], &[6]), T::zeros(&[1], g));
+///     assert_eq!(y.eval(g).unwrap(), ag::ndarray::arr1(&[0.]).into_dyn());
 /// });
 /// ```
-pub fn batch_norm<'graph, A, B, C, F: Float>(x: A, scale: B, shift: C) -> Tensor<'graph, F>
+pub fn fallback<'graph, A, B, F: Float>(primary: A, secondary: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
-    C: AsRef<Tensor<'graph, F>> + Copy,
 {
-    normalize(x, &[0]) * scale.as_ref() + shift.as_ref()
+    let a = primary.as_ref();
+    Tensor::builder(a.graph())
+        .append_input(a, false)
+        .append_input(secondary.as_ref(), false)
+        .build(graph_ops::Fallback)
 }
 
 use crate::graph::AsGraph;
@@ -2717,6 +5528,98 @@ where
         .build(const_gen_ops::Ones)
 }
 
+/// Returns a 1-D tensor of values from `start` up to (not including) `stop`, spaced by `step`.
+///
+/// `step` must be nonzero; the result is empty if `stop` is not reachable in the direction of
+/// `step`. Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = arange(0., 2., 0.5, g);
+///    assert_eq!(a.eval(g), Ok(array![0., 0.5, 1., 1.5].into_dyn()));
+/// });
+/// ```
+pub fn arange<F: Float>(start: F, stop: F, step: F, graph: &impl AsGraph<F>) -> Tensor<F> {
+    convert_to_tensor(ndarray::Array1::range(start, stop, step), graph)
+}
+
+/// Returns a 1-D tensor of `num` evenly spaced values from `start` to `stop`, both inclusive.
+///
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = linspace(0., 1., 5, g);
+///    assert_eq!(a.eval(g), Ok(array![0., 0.25, 0.5, 0.75, 1.].into_dyn()));
+/// });
+/// ```
+pub fn linspace<F: Float>(start: F, stop: F, num: usize, graph: &impl AsGraph<F>) -> Tensor<F> {
+    convert_to_tensor(ndarray::Array1::linspace(start, stop, num), graph)
+}
+
+/// Returns the `n x n` identity matrix.
+///
+/// Not differentiable.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let a = eye(2, g);
+///    assert_eq!(a.eval(g), Ok(array![[1., 0.], [0., 1.]].into_dyn()));
+/// });
+/// ```
+pub fn eye<F: Float>(n: usize, graph: &impl AsGraph<F>) -> Tensor<F> {
+    convert_to_tensor(ndarray::Array2::eye(n), graph)
+}
+
+/// 1D convolution, for sequences such as audio or time series.
+///
+/// * `x`: Tensor with shape `(batch, channel, length)`
+/// * `w`: Tensor with shape `(out_channel, channel, filter)`
+///
+/// Returns a tensor with shape `(batch, out_channel, out_length)`
+///
+/// where `out_length` = `(length + 2 * pad - filter) / stride + 1`, the padding being zeros.
+///
+/// Panics if `stride` is 0.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let x = T::convert_to_tensor(array![[[1., 2., 3., 4.]]], g);
+///     // differences of the neighbors
+///     let w = T::convert_to_tensor(array![[[-1., 0., 1.]]], g);
+///     let y = T::conv1d(x, w, 1, 1);
+///     assert_eq!(y.eval(g), Ok(array![[[2., 2., 2., -3.]]].into_dyn()));
+/// });
+/// ```
+pub fn conv1d<'graph, A, B, F: Float>(x: A, w: B, pad: usize, stride: usize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(stride > 0, "conv1d: stride must be positive");
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .append_input(w.as_ref(), false)
+        .build(conv_ops::conv1d::Conv1D { pad, stride })
+}
+
 /// 2D convolution.
 ///
 /// * `x`: Tensor with shape `(batch, channel, h, w)`
@@ -2729,160 +5632,449 @@ where
 ///   * `out_h` = `(h + 2 * pad - filter_h) / stride + 1`
 ///   * `out_w` = `(w + 2 * pad - filter_w) / stride + 1`
 ///
+/// Panics if `stride` is 0.
+///
 /// This function supports only f32 and f64.
 pub fn conv2d<'graph, A, B, F: Float>(x: A, w: B, pad: usize, stride: usize) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
     B: AsRef<Tensor<'graph, F>> + Copy,
 {
+    assert!(stride > 0, "conv2d: stride must be positive");
+    let x = x.as_ref();
+    let g = x.graph();
+    let y = Tensor::builder(g)
+        .append_input(autocast("conv2d", x), false)
+        .append_input(autocast("conv2d", w.as_ref()), false)
+        .build(conv_ops::conv2d::Conv2D {
+            pad,
+            stride,
+            dilation: 1,
+        });
+    autocast_output("conv2d", y)
+}
+
+/// 2D convolution with dilation.
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`
+/// * `w`: Tensor with shape `(out_channel, in_channel, filter_h, filter_w)`
+///
+/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+///
+/// where
+///
+///   * `out_h` = `(h + 2 * pad - (dilate * (filter - 1) + 1)) / stride + 1`
+///   * `out_w` = `(w + 2 * pad - (dilate * (filter - 1) + 1)) / stride + 1`
+///
+/// Panics if `stride` or `dilate` is 0.
+///
+/// This function supports only f32 and f64.
+pub fn dilated_conv2d<'graph, A, B, F: Float>(
+    x: A,
+    w: B,
+    pad: usize,
+    stride: usize,
+    dilate: usize,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(stride > 0, "dilated_conv2d: stride must be positive");
+    assert!(dilate > 0, "dilated_conv2d: dilate must be positive");
+    let x = x.as_ref();
+    let g = x.graph();
+    let y = Tensor::builder(g)
+        .append_input(autocast("dilated_conv2d", x), false)
+        .append_input(autocast("dilated_conv2d", w.as_ref()), false)
+        .build(conv_ops::conv2d::Conv2D {
+            pad,
+            stride,
+            dilation: dilate,
+        });
+    autocast_output("dilated_conv2d", y)
+}
+
+/// 2D transposed convolution.
+///
+/// * `x`: Tensor with shape `(batch, in_channel, h, w)`
+/// * `w`: Tensor with shape `(in_channel, out_channel, filter_h, filter_w)`
+///
+/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+///
+/// where
+///
+///   * `out_h` = `stride * (h - 1) - pad + filter_h`
+///   * `out_w` = `stride * (w - 1) - pad + filter_w`
+///
+/// Panics if `stride` is 0.
+///
+/// This function supports only f32 and f64.
+pub fn conv2d_transpose<'graph, A, B, F: Float>(
+    x: A,
+    w: B,
+    pad: usize,
+    stride: usize,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(stride > 0, "conv2d_transpose: stride must be positive");
+    let x = x.as_ref();
+    let g = x.graph();
+    let y = Tensor::builder(g)
+        .append_input(autocast("conv2d_transpose", x), false)
+        .append_input(autocast("conv2d_transpose", w.as_ref()), false)
+        .build(conv_ops::conv2d_transpose::Conv2DTranspose {
+            pad,
+            stride,
+            dilation: 1,
+        });
+    autocast_output("conv2d_transpose", y)
+}
+
+/// 2D transposed convolution with dilation.
+///
+/// * `x`: Tensor with shape `(batch, in_channel, h, w)`
+/// * `w`: Tensor with shape `(in_channel, out_channel, filter_h, filter_w)`
+///
+/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+///
+/// where
+///
+///   * `out_h` = `stride * (h - 1) - pad + (dilate * (filter_h - 1) + 1)`
+///   * `out_w` = `stride * (w - 1) - pad + (dilate * (filter_w - 1) + 1)`
+///
+/// Panics if `stride` or `dilate` is 0.
+///
+/// This function supports only f32 and f64.
+pub fn dilated_conv2d_transpose<'graph, A, B, F: Float>(
+    x: A,
+    w: B,
+    pad: usize,
+    stride: usize,
+    dilate: usize,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(stride > 0, "dilated_conv2d_transpose: stride must be positive");
+    assert!(dilate > 0, "dilated_conv2d_transpose: dilate must be positive");
     let x = x.as_ref();
     let g = x.graph();
     Tensor::builder(g)
         .append_input(x.as_ref(), false)
         .append_input(w.as_ref(), false)
-        .build(conv_ops::conv2d::Conv2D {
+        .build(conv_ops::conv2d_transpose::Conv2DTranspose {
             pad,
             stride,
-            dilation: 1,
+            dilation: dilate,
         })
 }
 
-/// 2D convolution with dilation.
+/// Global 2D average pooling.
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`
+///
+/// Returns a tensor with shape `(batch, channel)`, averaged over the spatial dims.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x: ag::Tensor<f32> = ones(&[2, 3, 4, 4], g);
+///    assert_eq!(global_avg_pool2d(x).eval(g).unwrap().shape(), &[2, 3]);
+/// });
+/// ```
+pub fn global_avg_pool2d<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    reduce_mean(x, &[2, 3], false)
+}
+
+/// Global 2D max pooling.
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`
+///
+/// Returns a tensor with shape `(batch, channel)`, the max over the spatial dims.
+/// The gradient is routed to the maximum element(s) of each feature map.
+pub fn global_max_pool2d<'graph, A, F: Float>(x: A) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    reduce_max(x, &[2, 3], false)
+}
+
+/// 2D max pooling.
 ///
 /// * `x`: Tensor with shape `(batch, channel, h, w)`
-/// * `w`: Tensor with shape `(out_channel, in_channel, filter_h, filter_w)`
 ///
-/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+/// Returns a tensor with shape `(batch, channel, out_h, out_w)`
 ///
 /// where
 ///
-///   * `out_h` = `(h + 2 * pad - (dilate * (filter - 1) + 1)) / stride + 1`
-///   * `out_w` = `(w + 2 * pad - (dilate * (filter - 1) + 1)) / stride + 1`
+///   * `out_h` = `(h + 2 * pad - pool_size) / stride + 1`
+///   * `out_w` = `(w + 2 * pad - pool_size) / stride + 1`
+///
+/// Panics if `pool_size` or `stride` is 0.
 ///
 /// This function supports only f32 and f64.
-pub fn dilated_conv2d<'graph, A, B, F: Float>(
+pub fn max_pool2d<'graph, A, F: Float>(
     x: A,
-    w: B,
+    pool_size: usize,
     pad: usize,
     stride: usize,
-    dilate: usize,
 ) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    B: AsRef<Tensor<'graph, F>> + Copy,
 {
+    assert!(pool_size > 0, "max_pool2d: pool_size must be positive");
+    assert!(stride > 0, "max_pool2d: stride must be positive");
     let x = x.as_ref();
     let g = x.graph();
     Tensor::builder(g)
         .append_input(x.as_ref(), false)
-        .append_input(w.as_ref(), false)
-        .build(conv_ops::conv2d::Conv2D {
+        .build(conv_ops::max_pool2d::MaxPool2D {
             pad,
             stride,
-            dilation: dilate,
+            size: pool_size,
         })
 }
 
-/// 2D transposed convolution.
-///
-/// * `x`: Tensor with shape `(batch, in_channel, h, w)`
-/// * `w`: Tensor with shape `(in_channel, out_channel, filter_h, filter_w)`
+/// 1D max pooling.
 ///
-/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+/// * `x`: Tensor with shape `(batch, channel, length)`
 ///
-/// where
+/// Returns a tensor with shape `(batch, channel, out_length)`
 ///
-///   * `out_h` = `stride * (h - 1) - pad + filter_h`
-///   * `out_w` = `stride * (w - 1) - pad + filter_w`
+/// where `out_length` = `(length + 2 * pad - pool_size) / stride + 1`. The padding is never
+/// the max of a window. The gradient is routed to the maximum element of each window.
 ///
-/// This function supports only f32 and f64.
-pub fn conv2d_transpose<'graph, A, B, F: Float>(
+/// Panics if `pool_size` or `stride` is 0, or if `pad` isn't less than `pool_size`.
+pub fn max_pool1d<'graph, A, F: Float>(
     x: A,
-    w: B,
+    pool_size: usize,
     pad: usize,
     stride: usize,
 ) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    B: AsRef<Tensor<'graph, F>> + Copy,
 {
+    assert!(pool_size > 0, "max_pool1d: pool_size must be positive");
+    assert!(stride > 0, "max_pool1d: stride must be positive");
+    assert!(pad < pool_size, "max_pool1d: pad must be less than pool_size");
     let x = x.as_ref();
-    let g = x.graph();
-    Tensor::builder(g)
-        .append_input(x.as_ref(), false)
-        .append_input(w.as_ref(), false)
-        .build(conv_ops::conv2d_transpose::Conv2DTranspose {
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(conv_ops::pool1d::MaxPool1D {
+            size: pool_size,
             pad,
             stride,
-            dilation: 1,
         })
 }
 
-/// 2D transposed convolution with dilation.
+/// 1D average pooling, i.e. a moving average over time.
 ///
-/// * `x`: Tensor with shape `(batch, in_channel, h, w)`
-/// * `w`: Tensor with shape `(in_channel, out_channel, filter_h, filter_w)`
+/// * `x`: Tensor with shape `(batch, channel, length)`
 ///
-/// Returns a tensor with shape `(batch, out_channel, out_h, out_w)`
+/// Returns a tensor with shape `(batch, channel, out_length)`
 ///
-/// where
+/// where `out_length` = `(length + 2 * pad - pool_size) / stride + 1`. Every window is
+/// divided by `pool_size`, the padding counting as zeros.
 ///
-///   * `out_h` = `stride * (h - 1) - pad + (dilate * (filter_h - 1) + 1)`
-///   * `out_w` = `stride * (w - 1) - pad + (dilate * (filter_w - 1) + 1)`
+/// Panics if `pool_size` or `stride` is 0.
 ///
-/// This function supports only f32 and f64.
-pub fn dilated_conv2d_transpose<'graph, A, B, F: Float>(
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let x = T::convert_to_tensor(array![[[1., 3., 5., 7.]]], g);
+///     let y = T::avg_pool1d(x, 2, 0, 1);
+///     assert_eq!(y.eval(g), Ok(array![[[2., 4., 6.]]].into_dyn()));
+/// });
+/// ```
+pub fn avg_pool1d<'graph, A, F: Float>(
     x: A,
-    w: B,
+    pool_size: usize,
     pad: usize,
     stride: usize,
-    dilate: usize,
 ) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
-    B: AsRef<Tensor<'graph, F>> + Copy,
 {
+    assert!(pool_size > 0, "avg_pool1d: pool_size must be positive");
+    assert!(stride > 0, "avg_pool1d: stride must be positive");
     let x = x.as_ref();
-    let g = x.graph();
-    Tensor::builder(g)
-        .append_input(x.as_ref(), false)
-        .append_input(w.as_ref(), false)
-        .build(conv_ops::conv2d_transpose::Conv2DTranspose {
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(conv_ops::pool1d::AvgPool1D {
+            size: pool_size,
             pad,
             stride,
-            dilation: dilate,
         })
 }
 
-/// 2D max pooling.
+/// Nearest neighbor upsampling.
 ///
 /// * `x`: Tensor with shape `(batch, channel, h, w)`
 ///
-/// Returns a tensor with shape `(batch, channel, out_h, out_w)`
+/// Returns a tensor with shape `(batch, channel, floor(h * scale), floor(w * scale))`
+/// whose pixel `(i, j)` is the pixel `(floor(i / scale), floor(j / scale))` of `x`.
+/// The gradient of a pixel of `x` is the sum of the gradients of its copies.
 ///
-/// where
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
 ///
-///   * `out_h` = `(h + 2 * pad - pool_size) / stride + 1`
-///   * `out_w` = `(w + 2 * pad - pool_size) / stride + 1`
+/// ag::run(|g| {
+///     let x = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///     let y = T::upsample_nearest2d(T::reshape(x, &[1, 1, 2, 2]), 2.);
+///     let expected = array![
+///         [1., 1., 2., 2.],
+///         [1., 1., 2., 2.],
+///         [3., 3., 4., 4.],
+///         [3., 3., 4., 4.]
+///     ];
+///     assert_eq!(y.eval(g), Ok(expected.into_shape((1, 1, 4, 4)).unwrap().into_dyn()));
+/// });
+/// ```
+pub fn upsample_nearest2d<'graph, A, F: Float>(x: A, scale: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    upsample2d(x.as_ref(), scale, false)
+}
+
+/// Bilinear upsampling.
 ///
-/// This function supports only f32 and f64.
-pub fn max_pool2d<'graph, A, F: Float>(
+/// * `x`: Tensor with shape `(batch, channel, h, w)`
+///
+/// Returns a tensor with shape `(batch, channel, floor(h * scale), floor(w * scale))`.
+/// Pixel centers are aligned as with `align_corners=False` in PyTorch:
+/// pixel `i` interpolates `x` at `(i + 0.5) / scale - 0.5`, clamped to the edges.
+/// `scale` can be any positive number, e.g. `1.5`.
+/// The gradient of a pixel of `x` sums the gradients of the pixels interpolating it,
+/// weighted by the interpolation weights.
+pub fn upsample_bilinear2d<'graph, A, F: Float>(x: A, scale: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    upsample2d(x.as_ref(), scale, true)
+}
+
+/// Samples `x` at the locations in `grid` with bilinear interpolation.
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`
+/// * `grid`: Tensor with shape `(batch, h_out, w_out, 2)` holding `(x, y)` coordinates
+///   normalized to `[-1, 1]`, where `(-1, -1)` is the top-left corner of `x`
+///
+/// Returns a tensor with shape `(batch, channel, h_out, w_out)`.
+/// As with `align_corners=False` in PyTorch, the extremes `-1` and `1` are the outer edges of the
+/// corner pixels. `padding` decides the values sampled outside of `x` (see [GridSamplePadding]).
+/// Differentiable w.r.t. both `x` and `grid` (first order only), e.g. for spatial transformers.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let x = T::reshape(T::convert_to_tensor(array![[1., 2.], [3., 4.]], g), &[1, 1, 2, 2]);
+///     // the center of the image, and a point right of it
+///     let grid = T::reshape(T::convert_to_tensor(array![0., 0., 2., 0.], g), &[1, 1, 2, 2]);
+///     let y = T::grid_sample(x, grid, T::GridSamplePadding::Border);
+///     assert_eq!(y.eval(g), Ok(array![2.5, 3.].into_shape((1, 1, 1, 2)).unwrap().into_dyn()));
+///     let y = T::grid_sample(x, grid, T::GridSamplePadding::Zeros);
+///     assert_eq!(y.eval(g), Ok(array![2.5, 0.].into_shape((1, 1, 1, 2)).unwrap().into_dyn()));
+/// });
+/// ```
+pub fn grid_sample<'graph, A, B, F: Float>(
     x: A,
-    pool_size: usize,
-    pad: usize,
-    stride: usize,
+    grid: B,
+    padding: GridSamplePadding,
 ) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
 {
-    let x = x.as_ref();
-    let g = x.graph();
-    Tensor::builder(g)
+    Tensor::builder(x.as_ref().graph())
         .append_input(x.as_ref(), false)
-        .build(conv_ops::max_pool2d::MaxPool2D {
-            pad,
-            stride,
-            size: pool_size,
+        .append_input(grid.as_ref(), false)
+        .build(conv_ops::grid_sample::GridSample { padding })
+}
+
+fn upsample2d<'graph, F: Float>(
+    x: &Tensor<'graph, F>,
+    scale: F,
+    bilinear: bool,
+) -> Tensor<'graph, F> {
+    let scale = scale.to_f64().unwrap();
+    assert!(
+        scale.is_finite() && scale > 0.,
+        "upsample2d: scale must be positive, got {}",
+        scale
+    );
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(conv_ops::upsample::Upsample2D { scale, bilinear })
+}
+
+/// Moves each `block x block` spatial block into channels.
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`, where `h` and `w` are divisible by `block`
+///
+/// Returns a tensor with shape `(batch, channel * block * block, h / block, w / block)`.
+/// As in TensorFlow, the pixel at offset `(i, j)` of its block goes to
+/// the channel `(i * block + j) * channel + c`. See also [depth_to_space], its inverse.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let x = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///     let y = T::space_to_depth(T::reshape(x, &[1, 1, 2, 2]), 2);
+///     let expected = array![1., 2., 3., 4.].into_shape((1, 4, 1, 1)).unwrap();
+///     assert_eq!(y.eval(g), Ok(expected.into_dyn()));
+/// });
+/// ```
+pub fn space_to_depth<'graph, A, F: Float>(x: A, block: usize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    Tensor::builder(x.as_ref().graph())
+        .append_input(x.as_ref(), false)
+        .build(array_ops::SpaceToDepth {
+            block,
+            inverse: false,
+        })
+}
+
+/// Moves channels into `block x block` spatial blocks, the inverse of [space_to_depth].
+///
+/// * `x`: Tensor with shape `(batch, channel, h, w)`, where `channel` is divisible by
+///   `block * block`
+///
+/// Returns a tensor with shape `(batch, channel / (block * block), h * block, w * block)`,
+/// e.g. to turn the output of a convolution into a higher resolution image
+/// (sub-pixel convolution).
+pub fn depth_to_space<'graph, A, F: Float>(x: A, block: usize) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    Tensor::builder(x.as_ref().graph())
+        .append_input(x.as_ref(), false)
+        .build(array_ops::SpaceToDepth {
+            block,
+            inverse: true,
         })
 }
 
@@ -2921,12 +6113,214 @@ where
     Tensor::builder(g)
         .append_input(x.as_ref(), false)
         .build(random_ops::Dropout {
-            train,
+            train: Some(train),
             arr_rng: ArrayRng::new(rng),
             dropout_ratio,
         })
 }
 
+/// Dropout, active when the graph is evaluated in [training](crate::Context::training) mode.
+///
+/// Same as [dropout] with `train` read from the context at each evaluation.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g: &mut ag::Context<f64>| {
+///     let y = T::dropout_by_mode(T::ones(&[1000], g), 0.5);
+///     // roughly half of the elements are dropped
+///     let kept = y.eval(g).unwrap().iter().filter(|&&y| y != 0.).count();
+///     assert!(kept > 400 && kept < 600);
+///
+///     g.training(false);
+///     assert!(y.eval(g).unwrap().iter().all(|&y| y == 0.5));
+/// });
+/// ```
+pub fn dropout_by_mode<'graph, A, F: Float>(x: A, dropout_ratio: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let x = x.as_ref();
+    Tensor::builder(x.graph())
+        .append_input(x, false)
+        .build(random_ops::Dropout {
+            train: None,
+            arr_rng: ArrayRng::new(crate::ndarray_ext::get_default_rng()),
+            dropout_ratio,
+        })
+}
+
+/// Averages `n` independent samples of a randomized computation, e.g. MC dropout.
+///
+/// `build` is called `n` times with an rng seeded from `seed` and the index of the copy,
+/// and must draw its randomness from it (e.g. with [dropout_rng], or [random_normal_rng]
+/// with the rng wrapped in an [ArrayRng]): the ops seeded by default would all draw the
+/// same numbers. Returns the mean of the `n` copies, through
+/// which the gradients flow to each of them.
+///
+/// Panics if `n` is 0.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g: &mut ag::Context<f64>| {
+///     let x = T::ones(&[4], g);
+///     // tends to the output of dropout in inference, `x * (1 - 0.25)`
+///     let y = T::monte_carlo(|_, rng| T::dropout_rng(x, 0.25, true, rng), 1000, 0, g);
+///     let y = y.eval(g).unwrap();
+///     assert!(y.iter().all(|&y| (y - 0.75).abs() < 0.1));
+/// });
+/// ```
+pub fn monte_carlo<'graph, F: Float, B>(
+    mut build: B,
+    n: usize,
+    seed: u64,
+    g: &'graph crate::Context<F>,
+) -> Tensor<'graph, F>
+where
+    B: FnMut(&'graph crate::Context<F>, rand::rngs::StdRng) -> Tensor<'graph, F>,
+{
+    use rand::SeedableRng;
+
+    assert!(n > 0, "monte_carlo: n must be positive");
+    let samples: Vec<_> = (0..n as u64)
+        .map(|i| {
+            // distinct for each copy; `seed_from_u64` scrambles it further
+            let derived = seed ^ i.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            build(g, rand::rngs::StdRng::seed_from_u64(derived))
+        })
+        .collect();
+    add_n(&samples) / scalar(F::from(n).unwrap(), g)
+}
+
+/// Multiplies `weight` elementwise by the 0/1 `mask`, for pruned (sparse) models.
+///
+/// The gradient of `weight` is multiplied by `mask` too, so the pruned weights get a zero
+/// gradient and a gradient descent step leaves them as they are: a pruned weight set to zero
+/// stays zero during training. `mask` is a constant, broadcast to the shape of `weight`;
+/// no gradient flows to it. Use it in place of the weight in the model, e.g.
+/// `matmul(x, prune_mask(w, mask))`, for lottery ticket or structured pruning experiments.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let w = convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+///    let mask = convert_to_tensor(array![[1., 0.], [0., 1.]], g);
+///    let y = prune_mask(w, mask);
+///    assert_eq!(y.eval(g), Ok(array![[1., 0.], [0., 4.]].into_dyn()));
+///    let gw = grad(&[y * 5.], &[w])[0];
+///    assert_eq!(gw.eval(g), Ok(array![[5., 0.], [0., 5.]].into_dyn()));
+/// });
+/// ```
+pub fn prune_mask<'graph, A, B, F: Float>(weight: A, mask: B) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    mul(weight, stop_gradient(mask))
+}
+
+/// Exponential moving average step: `decay * prev + (1 - decay) * x`, elementwise.
+///
+/// Unlike the moving averages kept outside the graph, e.g. the running statistics of
+/// `batch_norm`, this is differentiable: the gradient of `x` is `(1 - decay) * gy`, and the
+/// one of `prev` is `decay * gy`. So a smoothed signal or a target network can be built and
+/// trained inside the graph. `x` and `prev` broadcast like in [add].
+///
+/// Panics if `decay` isn't in `[0, 1]`.
+///
+/// ```
+/// use ndarray::array;
+/// use autograd as ag;
+/// use ag::tensor_ops::*;
+///
+/// ag::run(|g| {
+///    let x = convert_to_tensor(array![1., 2.], g);
+///    let prev = convert_to_tensor(array![3., 6.], g);
+///    let y = ema_op(x, prev, 0.75);
+///    assert_eq!(y.eval(g), Ok(array![2.5, 5.].into_dyn()));
+///    let gs = grad(&[y], &[x, prev]);
+///    assert_eq!(gs[0].eval(g), Ok(array![0.25, 0.25].into_dyn()));
+///    assert_eq!(gs[1].eval(g), Ok(array![0.75, 0.75].into_dyn()));
+/// });
+/// ```
+pub fn ema_op<'graph, A, B, F: Float>(x: A, prev: B, decay: F) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+    B: AsRef<Tensor<'graph, F>> + Copy,
+{
+    assert!(
+        F::zero() <= decay && decay <= F::one(),
+        "ema_op: `decay` must be in [0, 1]"
+    );
+    let (x, prev) = (*x.as_ref(), *prev.as_ref());
+    prev * decay + x * (F::one() - decay)
+}
+
+/// Draws a sample from the Gumbel-softmax (concrete) distribution over the last axis of `logits`.
+///
+/// Returns `softmax((logits + g) / tau)` where `g` is Gumbel noise, a differentiable relaxation
+/// of sampling a category with probabilities `softmax(logits)`. Lower `tau` makes the samples
+/// closer to one-hot. With `hard`, the output is the one-hot of the sample's argmax, while the
+/// gradient is that of the soft sample (straight-through estimator).
+///
+/// https://arxiv.org/abs/1611.01144
+///
+/// `XorShiftRng` is used internally.
+/// If you need to specify a seed value or use any other `Rng`, use `gumbel_softmax_rng` instead.
+pub fn gumbel_softmax<'graph, A, F: Float>(logits: A, tau: F, hard: bool) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    gumbel_softmax_rng(logits, tau, hard, crate::ndarray_ext::get_default_rng())
+}
+
+/// Same as [gumbel_softmax], drawing the noise from `rng`.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::rand::{rngs::StdRng, SeedableRng};
+/// use ag::tensor_ops as T;
+///
+/// ag::run(|g| {
+///     let logits = T::convert_to_tensor(ag::ndarray::arr2(&[[1., 2., 3.]]), g);
+///     let y = T::gumbel_softmax_rng(logits, 0.5, true, StdRng::seed_from_u64(42));
+///     let y = y.eval(g).unwrap();
+///     assert_eq!(y.sum(), 1.);
+///     assert!(y.iter().all(|&a| a == 0. || a == 1.));
+/// });
+/// ```
+pub fn gumbel_softmax_rng<'graph, A, F: Float, R: Rng + 'static>(
+    logits: A,
+    tau: F,
+    hard: bool,
+    rng: R,
+) -> Tensor<'graph, F>
+where
+    A: AsRef<Tensor<'graph, F>> + Copy,
+{
+    let logits = logits.as_ref();
+    let g = logits.graph();
+    let u = standard_uniform_rng(ArrayRng::new(rng), &shape(logits), g);
+    // keeps both logs finite
+    let u = clip(u, F::min_positive_value(), F::one() - F::epsilon());
+    let gumbel = neg(ln(neg(ln(u))));
+    let y = softmax((logits + gumbel) / scalar(tau, g), -1);
+    if hard {
+        Tensor::builder(g)
+            .append_input(y, false)
+            .set_shape(&shape(logits))
+            .build(gradient_ops::StraightThroughOneHot { axis: -1 })
+    } else {
+        y
+    }
+}
+
 /// Same as [crate::tensor::Tensor::map()]
 pub fn map<'graph, A, F: Float>(
     x: A,
@@ -2974,6 +6368,10 @@ where
 ///
 /// Internally uses ndarray::ArrayBase::assign as is.
 /// Note that `x` must be a variable tensor.
+///
+/// The returned tensor is empty: use it with [Tensor::depends_on] to run the assignment
+/// before other tensors, which can still be differentiated. [grad] panics if the gradient
+/// has to flow through the assignment itself.
 pub fn assign<'graph, A, B, F: Float>(x: A, y: B) -> Tensor<'graph, F>
 where
     A: AsRef<Tensor<'graph, F>> + Copy,
@@ -2997,8 +6395,8 @@ impl<'g, F: Float> Tensor<'g, F> {
     }
     /// Same as [tensor_ops::flatten](flatten)
     #[inline]
-    pub fn flatten(&self) -> Tensor<'g, F> {
-        flatten(self)
+    pub fn flatten(&self, start_axis: isize) -> Tensor<'g, F> {
+        flatten(self, start_axis)
     }
     /// Same as [tensor_ops::squeeze](squeeze)
     #[inline]
@@ -3037,6 +6435,15 @@ impl<'g, F: Float> Tensor<'g, F> {
     pub fn reduce_sum<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
         reduce_sum(self, axes, keep_dims)
     }
+    /// Same as [tensor_ops::reduce_sum_kahan](reduce_sum_kahan)
+    #[inline]
+    pub fn reduce_sum_kahan<AT: AsTensor<'g, F>>(
+        &self,
+        axes: &AT,
+        keep_dims: bool,
+    ) -> Tensor<'g, F> {
+        reduce_sum_kahan(self, axes, keep_dims)
+    }
     /// Same as [tensor_ops::reduce_mean](reduce_mean)
     #[inline]
     pub fn reduce_mean<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
@@ -3047,6 +6454,21 @@ impl<'g, F: Float> Tensor<'g, F> {
     pub fn reduce_prod<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
         reduce_prod(self, axes, keep_dims)
     }
+    /// Same as [tensor_ops::count_nonzero](count_nonzero)
+    #[inline]
+    pub fn count_nonzero<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
+        count_nonzero(self, axes, keep_dims)
+    }
+    /// Same as [tensor_ops::reduce_any](reduce_any)
+    #[inline]
+    pub fn reduce_any<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
+        reduce_any(self, axes, keep_dims)
+    }
+    /// Same as [tensor_ops::reduce_all](reduce_all)
+    #[inline]
+    pub fn reduce_all<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
+        reduce_all(self, axes, keep_dims)
+    }
     /// Same as [tensor_ops::reduce_min](reduce_min)
     #[inline]
     pub fn reduce_min<AT: AsTensor<'g, F>>(&self, axes: &AT, keep_dims: bool) -> Tensor<'g, F> {
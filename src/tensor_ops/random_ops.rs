@@ -2,6 +2,21 @@ use crate::ndarray_ext::{self, ArrayRng};
 use crate::op;
 use crate::Float;
 use rand::Rng;
+use std::any::Any;
+use std::hash::{Hash, Hasher};
+
+// `Op::state` and `Op::restore_state` of the ops drawing from `self.arr_rng`
+macro_rules! rng_state {
+    () => {
+        fn state(&self) -> Option<Box<dyn Any>> {
+            self.arr_rng.state()
+        }
+
+        fn restore_state(&self, state: &dyn Any) {
+            self.arr_rng.restore_state(state)
+        }
+    };
+}
 
 pub struct StandardNormal<T: Float, R: Rng> {
     pub arr_rng: ArrayRng<T, R>,
@@ -105,7 +120,7 @@ impl<'a, T: Float, R: Rng> Gamma<T, R> {
     }
 }
 
-impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
+impl<T: Float, R: Rng + 'static> op::Op<T> for RandomNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -118,9 +133,15 @@ impl<T: Float, R: Rng> op::Op<T> for RandomNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.mean.to_bits(), self.stddev.to_bits()).hash(&mut state);
+    }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for RandomUniform<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -133,9 +154,15 @@ impl<R: Rng, T: Float> op::Op<T> for RandomUniform<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.min.to_bits(), self.max.to_bits()).hash(&mut state);
+    }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for StandardNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.standard_normal(shape.as_slice()));
@@ -145,9 +172,11 @@ impl<R: Rng, T: Float> op::Op<T> for StandardNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for StandardUniform<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.standard_uniform(shape.as_slice()));
@@ -157,9 +186,11 @@ impl<R: Rng, T: Float> op::Op<T> for StandardUniform<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for Bernoulli<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.bernoulli(shape.as_slice(), self.p));
@@ -169,9 +200,15 @@ impl<R: Rng, T: Float> op::Op<T> for Bernoulli<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.p.to_bits().hash(&mut state);
+    }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for Exponential<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(self.arr_rng.exponential(shape.as_slice(), self.lambda));
@@ -181,9 +218,15 @@ impl<R: Rng, T: Float> op::Op<T> for Exponential<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.lambda.to_bits().hash(&mut state);
+    }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for LogNormal<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -196,9 +239,15 @@ impl<R: Rng, T: Float> op::Op<T> for LogNormal<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.mean.to_bits(), self.stddev.to_bits()).hash(&mut state);
+    }
+
+    rng_state!();
 }
 
-impl<R: Rng, T: Float> op::Op<T> for Gamma<T, R> {
+impl<R: Rng + 'static, T: Float> op::Op<T> for Gamma<T, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(0));
         ctx.append_output(
@@ -211,6 +260,12 @@ impl<R: Rng, T: Float> op::Op<T> for Gamma<T, R> {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.shape_param.to_bits(), self.scale.to_bits()).hash(&mut state);
+    }
+
+    rng_state!();
 }
 
 use crate::tensor_ops::*;
@@ -218,13 +273,14 @@ use crate::tensor_ops::*;
 pub struct Dropout<F: Float, R: Rng> {
     pub arr_rng: ArrayRng<F, R>,
     pub dropout_ratio: F,
-    pub train: bool,
+    // `None` to follow `Context::training`
+    pub train: Option<bool>,
 }
 
-impl<R: Rng, F: Float> op::Op<F> for Dropout<F, R> {
+impl<R: Rng + 'static, F: Float> op::Op<F> for Dropout<F, R> {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<F>) -> Result<(), crate::op::OpError> {
         let x = ctx.input(0);
-        if self.train {
+        if self.train.unwrap_or_else(|| ctx.training()) {
             let mask = self
                 .arr_rng
                 .bernoulli(x.shape(), (F::one() - self.dropout_ratio).to_f64().unwrap());
@@ -233,6 +289,10 @@ impl<R: Rng, F: Float> op::Op<F> for Dropout<F, R> {
         } else {
             let coef = F::one() - self.dropout_ratio;
             ctx.append_output(x.mapv(move |x| x * coef));
+            if self.train.is_none() {
+                // the gradient is built before the mode is known
+                ctx.append_output(ndarray_ext::NdArray::from_elem(x.shape(), coef));
+            }
         }
         Ok(())
     }
@@ -242,4 +302,10 @@ impl<R: Rng, F: Float> op::Op<F> for Dropout<F, R> {
         let mask = nth_tensor(ctx.output(), 1);
         ctx.append_input_grad(Some(gy * mask));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (op::float_bits(self.dropout_ratio), self.train).hash(&mut state);
+    }
+
+    rng_state!();
 }
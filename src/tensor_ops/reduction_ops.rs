@@ -8,6 +8,8 @@ use crate::Float;
 use ndarray;
 use std::f32;
 use std::mem;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 
 pub struct ReduceMin {
     pub keep_dims: bool,
@@ -31,6 +33,10 @@ pub struct ReduceSum {
     pub sparse_axes: bool,
 }
 
+pub struct ReduceSumKahan {
+    pub keep_dims: bool,
+}
+
 pub struct ReduceMean {
     pub keep_dims: bool,
     pub sparse_axes: bool,
@@ -46,11 +52,51 @@ pub struct ArgMin {
     pub keep_dim: bool,
 }
 
+pub(crate) type FoldFn<T> = Rc<dyn Fn(T, T) -> T>;
+pub(crate) type FoldGradFn<T> = Rc<dyn Fn(T, T) -> (T, T)>;
+
+/// Folds the lanes along `axis` with `op`, starting from `init`.
+pub struct Fold<T: Float> {
+    pub axis: isize,
+    pub init: T,
+    pub op: FoldFn<T>,
+    pub grad: FoldGradFn<T>,
+}
+
+/// Gradient of `Fold`: inputs are `gy` and `x`.
+pub struct FoldGrad<T: Float> {
+    pub axis: isize,
+    pub init: T,
+    pub op: FoldFn<T>,
+    pub grad: FoldGradFn<T>,
+}
+
+/// Running max (or min) along `axis`, scanned backwards if `reverse`.
+pub struct CumExtreme {
+    pub axis: isize,
+    pub reverse: bool,
+    pub max: bool,
+}
+
+/// Gradient of `CumExtreme`: inputs are `gy` and `x`.
+pub struct CumExtremeGrad {
+    pub axis: isize,
+    pub reverse: bool,
+    pub max: bool,
+}
+
 pub struct ReduceGradCommon {
     pub should_make_broadcast_dims: bool,
     pub sparse_axes: bool,
 }
 
+// Mask of the elements of `x` equal to the reduced `y` (broadcast back to the shape of `x`),
+// through which the gradient of `reduce_max` and `reduce_min` flows: all of them, or only
+// the first one of each reduced group in deterministic mode.
+pub struct MinMaxGradMask {
+    pub sparse_axes: bool,
+}
+
 macro_rules! impl_reduce_forward {
     ($forward_name:ident, $reduce_fn_name:ident, $reduce_default:ident) => {
         fn $forward_name<'v, T: Float>(
@@ -103,10 +149,73 @@ macro_rules! impl_reduce_forward {
 }
 
 impl_reduce_forward!(compute_reduce_sum, add, zero);
+
+// Sums `xs` by recursive halving, so the order of additions depends only on the length.
+fn pairwise_sum<T: Float>(xs: ndarray::ArrayView1<T>) -> T {
+    const BLOCK: usize = 32;
+    let n = xs.len();
+    if n <= BLOCK {
+        xs.iter().fold(T::zero(), |acc, &x| acc + x)
+    } else {
+        let (l, r) = xs.split_at(ndarray::Axis(0), n / 2);
+        pairwise_sum(l) + pairwise_sum(r)
+    }
+}
+
+// Neumaier's variant of Kahan summation: the rounding error of each addition is
+// accumulated separately, whichever of the two operands is larger.
+fn kahan_sum<T: Float>(xs: ndarray::ArrayView1<T>) -> T {
+    let mut sum = T::zero();
+    let mut compensation = T::zero();
+    for &x in xs {
+        let t = sum + x;
+        if sum.abs() >= x.abs() {
+            compensation += (sum - t) + x;
+        } else {
+            compensation += (x - t) + sum;
+        }
+        sum = t;
+    }
+    sum + compensation
+}
+
+// `compute_reduce_sum` summing each lane with `lane_sum`.
+fn compute_reduce_sum_by<T: Float>(
+    x: &NdArrayView<T>,
+    mut axes: Vec<usize>,
+    keep_dims: bool,
+    lane_sum: fn(ndarray::ArrayView1<T>) -> T,
+) -> crate::OpOutput<T> {
+    if ndarray_ext::is_scalar_shape(x.shape()) || axes.is_empty() {
+        return crate::OpOutput::View(x.raw_view());
+    }
+    axes.sort();
+    let mut folded = x.to_owned();
+    for axis in axes.into_iter().rev() {
+        let ret = folded.map_axis(ndarray::Axis(axis), lane_sum);
+        folded = if keep_dims {
+            ndarray_ext::expand_dims(ret, axis)
+        } else {
+            ret
+        };
+    }
+    crate::OpOutput::Owned(folded)
+}
 impl_reduce_forward!(compute_reduce_min, min, max_value);
 impl_reduce_forward!(compute_reduce_max, max, min_value);
 impl_reduce_forward!(compute_reduce_prod, mul, one);
 
+// Max, min and their indices have no identity to fill the lanes of an empty axis with.
+fn check_nonempty_axes(name: &str, shape: &[usize], axes: &[usize]) -> Result<(), op::OpError> {
+    match axes.iter().find(|&&axis| shape[axis] == 0) {
+        Some(axis) => Err(op::OpError::IncompatibleShape(format!(
+            "{}: can't reduce the empty axis {} of shape {:?}",
+            name, axis, shape
+        ))),
+        None => Ok(()),
+    }
+}
+
 #[inline]
 fn preprocess_axes<T: Float>(
     x: &NdArrayView<T>,
@@ -123,7 +232,14 @@ fn preprocess_axes<T: Float>(
 impl<T: Float> op::Op<T> for ReduceSumToScalar {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
-        ctx.append_output(ndarray::arr0(x.sum()).into_dyn());
+        let sum = if ctx.deterministic() {
+            // row-major order, whatever the layout of `x`
+            let flat: Vec<T> = x.iter().cloned().collect();
+            pairwise_sum(ndarray::ArrayView1::from(&flat))
+        } else {
+            x.sum()
+        };
+        ctx.append_output(ndarray::arr0(sum).into_dyn());
         Ok(())
     }
 
@@ -141,10 +257,9 @@ struct ReduceSumToScalarGrad;
 impl<T: Float> op::Op<T> for ReduceSumToScalarGrad {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let shape = ndarray_ext::as_shape(&ctx.input(1));
-        let ret = unsafe {
-            let x = *ctx.input(0).as_ptr();
-            ndarray::ArrayD::<T>::from_elem(ndarray::IxDyn(shape.as_slice()), x)
-        };
+        let x = unsafe { *ctx.input(0).as_ptr() };
+        let mut ret = ctx.alloc(&shape);
+        ret.fill(x);
         ctx.append_output(ret);
         Ok(())
     }
@@ -162,7 +277,12 @@ impl<T: Float> op::Op<T> for ReduceSum {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
         let axes = preprocess_axes(x, &ctx.input(1), self.sparse_axes);
-        match compute_reduce_sum(x, axes, self.keep_dims) {
+        let sum = if ctx.deterministic() {
+            compute_reduce_sum_by(x, axes, self.keep_dims, pairwise_sum)
+        } else {
+            compute_reduce_sum(x, axes, self.keep_dims)
+        };
+        match sum {
             crate::OpOutput::Owned(ret) => ctx.append_output(ret),
             crate::OpOutput::View(ret) => ctx.append_output_view_raw(ret),
         }
@@ -182,6 +302,40 @@ impl<T: Float> op::Op<T> for ReduceSum {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.keep_dims, self.sparse_axes).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for ReduceSumKahan {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = &ctx.input(0);
+        let axes = ndarray_ext::normalize_negative_axes(&ctx.input(1), x.ndim());
+        match compute_reduce_sum_by(x, axes, self.keep_dims, kahan_sum) {
+            crate::OpOutput::Owned(ret) => ctx.append_output(ret),
+            crate::OpOutput::View(ret) => ctx.append_output_view_raw(ret),
+        }
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let grad_op = ReduceGradCommon {
+            should_make_broadcast_dims: !self.keep_dims,
+            sparse_axes: false,
+        };
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(shape(ctx.input(0)), false)
+            .append_input(ctx.input(1), false)
+            .build(grad_op);
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.keep_dims.hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMean {
@@ -200,7 +354,11 @@ impl<T: Float> op::Op<T> for ReduceMean {
             reduction_len *= x_shape[axis as usize] as f32;
         }
         // Do summation
-        let sum = compute_reduce_sum(x, axes, self.keep_dims);
+        let sum = if ctx.deterministic() {
+            compute_reduce_sum_by(x, axes, self.keep_dims, pairwise_sum)
+        } else {
+            compute_reduce_sum(x, axes, self.keep_dims)
+        };
 
         // Do division
         match sum {
@@ -236,6 +394,10 @@ impl<T: Float> op::Op<T> for ReduceMean {
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.keep_dims, self.sparse_axes).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceProd {
@@ -254,29 +416,56 @@ impl<T: Float> op::Op<T> for ReduceProd {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
-        let grad_op = ReduceGradCommon {
-            should_make_broadcast_dims: !self.keep_dims,
-            sparse_axes: self.sparse_axes,
-        };
+        // The gradient of each element is the product of the others, i.e. `prod / x_i`
+        // unless `x_i` is 0: the product is taken with the zeros replaced with 1s, and it's
+        // kept only for the elements whose group has no zeros but possibly themselves.
+        let g = ctx.graph();
         let x0 = ctx.input(0);
         let x1 = ctx.input(1);
-        let gy = ctx.output_grad();
-        let output = ctx.output();
-        let tmp = Tensor::builder(ctx.graph())
-            .append_input(&(gy * output), false)
-            .append_input(&shape(x0), false)
-            .append_input(&x1, false)
-            .build(grad_op);
-        let gx = tmp / x0;
+        let x_shape = shape(x0);
+        let broadcast = |t| {
+            let t: Tensor<'_, T> = t;
+            Tensor::builder(g)
+                .append_input(t, false)
+                .append_input(x_shape, false)
+                .append_input(x1, false)
+                .build(ReduceGradCommon {
+                    should_make_broadcast_dims: !self.keep_dims,
+                    sparse_axes: self.sparse_axes,
+                })
+        };
+        let is_zero = equal(x0, scalar(T::zero(), g));
+        let x_safe = x0 + is_zero;
+        let prod_safe = Tensor::builder(g)
+            .append_input(x_safe, false)
+            .append_input(x1, false)
+            .build(ReduceProd {
+                keep_dims: self.keep_dims,
+                sparse_axes: self.sparse_axes,
+            });
+        let num_zeros = Tensor::builder(g)
+            .append_input(is_zero, false)
+            .append_input(x1, false)
+            .build(ReduceSum {
+                keep_dims: self.keep_dims,
+                sparse_axes: self.sparse_axes,
+            });
+        let others_nonzero = equal(broadcast(num_zeros) - is_zero, scalar(T::zero(), g));
+        let gx = broadcast(ctx.output_grad() * prod_safe) / x_safe * others_nonzero;
         ctx.append_input_grad(Some(gx));
         ctx.append_input_grad(None);
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.keep_dims, self.sparse_axes).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMin {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
         let axes = preprocess_axes(x, &ctx.input(1), self.sparse_axes);
+        check_nonempty_axes("ReduceMin", x.shape(), &axes)?;
         match compute_reduce_min(x, axes, self.keep_dims) {
             crate::OpOutput::Owned(ret) => {
                 ctx.append_output(ret);
@@ -299,12 +488,17 @@ impl<T: Float> op::Op<T> for ReduceMin {
             ctx,
         );
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.keep_dims, self.sparse_axes).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceMax {
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
         let axes = preprocess_axes(x, &ctx.input(1), self.sparse_axes);
+        check_nonempty_axes("ReduceMax", x.shape(), &axes)?;
         match compute_reduce_max(x, axes, self.keep_dims) {
             crate::OpOutput::Owned(ret) => {
                 ctx.append_output(ret);
@@ -327,6 +521,10 @@ impl<T: Float> op::Op<T> for ReduceMax {
             ctx,
         );
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.keep_dims, self.sparse_axes).hash(&mut state);
+    }
 }
 
 fn min_max_grad<'g, T: Float>(
@@ -357,11 +555,61 @@ fn min_max_grad<'g, T: Float>(
         .append_input(x_shape, false)
         .append_input(x2, false)
         .build(grad_op2);
-    let eq = equal(x1, y);
-    ctx.append_input_grad(Some(mul(eq, gy)));
+    let mask = Tensor::builder(ctx.graph())
+        .append_input(x1, false)
+        .append_input(y, false)
+        .append_input(x2, false)
+        .build(MinMaxGradMask { sparse_axes });
+    ctx.append_input_grad(Some(mul(mask, gy)));
     ctx.append_input_grad(None);
 }
 
+impl<T: Float> op::Op<T> for MinMaxGradMask {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = &ctx.input(0);
+        let y = &ctx.input(1);
+        if !ctx.deterministic() {
+            let mask = ndarray::Zip::from(x)
+                .and(y)
+                .map_collect(|&a, &b| T::from((a == b) as i32).unwrap());
+            ctx.append_output(mask);
+            return Ok(());
+        }
+        // the lowest flat index of each group of ties, whatever the layout of `x`
+        let axes = preprocess_axes(x, &ctx.input(2), self.sparse_axes);
+        let mut group_shape = x.shape().to_vec();
+        for &axis in &axes {
+            group_shape[axis] = 1;
+        }
+        let mut found = ndarray::Array::from_elem(group_shape, false);
+        let mut mask = NdArray::zeros(x.shape());
+        for ((mut i, &a), m) in x.indexed_iter().zip(mask.iter_mut()) {
+            if a != y[&i] {
+                continue;
+            }
+            for &axis in &axes {
+                i[axis] = 0;
+            }
+            if !found[&i] {
+                found[&i] = true;
+                *m = T::one();
+            }
+        }
+        ctx.append_output(mask);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.sparse_axes.hash(&mut state);
+    }
+}
+
 fn argx_helper<T: Float>(
     x: &NdArrayView<T>,
     comp_fn: fn(T, T) -> T,
@@ -432,6 +680,8 @@ impl<T: Float> op::Op<T> for ArgMin {
     // cf. https://github.com/tensorflow/compiler/tf2xla/kernels/index_ops.cc
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, x.ndim());
+        check_nonempty_axes("ArgMin", x.shape(), &[axis])?;
         let result = argx_helper(x, T::min, T::max_value(), self.keep_dim, self.axis);
         ctx.append_output(result);
         Ok(())
@@ -440,12 +690,18 @@ impl<T: Float> op::Op<T> for ArgMin {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None)
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.keep_dim).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ArgMax {
     // cf. https://github.com/tensorflow/compiler/tf2xla/kernels/index_ops.cc
     fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
         let x = &ctx.input(0);
+        let axis = ndarray_ext::normalize_negative_axis(self.axis, x.ndim());
+        check_nonempty_axes("ArgMax", x.shape(), &[axis])?;
         let result = argx_helper(x, T::max, T::min_value(), self.keep_dim, self.axis);
         ctx.append_output(result);
         Ok(())
@@ -454,6 +710,10 @@ impl<T: Float> op::Op<T> for ArgMax {
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
         ctx.append_input_grad(None)
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.keep_dim).hash(&mut state);
+    }
 }
 
 impl<T: Float> op::Op<T> for ReduceGradCommon {
@@ -487,17 +747,20 @@ impl<T: Float> op::Op<T> for ReduceGradCommon {
             }
             // do broadcast
             let a = gy.into_shape(gy_shape).unwrap();
-            ctx.append_output(a.broadcast(target_shape).unwrap().to_owned())
+            let ret = ctx.alloc_copy(&a.broadcast(target_shape).unwrap());
+            ctx.append_output(ret)
         } else {
             // do broadcast
-            ctx.append_output(gy.broadcast(target_shape).unwrap().to_owned())
+            let ret = ctx.alloc_copy(&gy.broadcast(target_shape).unwrap());
+            ctx.append_output(ret)
         }
         Ok(())
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // `gy` lacks the reduced axes exactly when they had to be reinserted
         let sum = tensor_ops::reduction_ops::ReduceSum {
-            keep_dims: self.should_make_broadcast_dims,
+            keep_dims: !self.should_make_broadcast_dims,
             sparse_axes: self.sparse_axes,
         };
         let axes = &ctx.input(2);
@@ -510,3 +773,172 @@ impl<T: Float> op::Op<T> for ReduceGradCommon {
         ctx.append_input_grad(None);
     }
 }
+
+fn lane_axis(name: &str, axis: isize, ndim: usize) -> Result<usize, op::OpError> {
+    let ret = if axis < 0 { axis + ndim as isize } else { axis };
+    if ret < 0 || ret >= ndim as isize {
+        return Err(op::OpError::OutOfBounds(format!(
+            "{}: axis {} is out of bounds for {} dims",
+            name, axis, ndim
+        )));
+    }
+    Ok(ret as usize)
+}
+
+impl<T: Float> op::Op<T> for Fold<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = ctx.input(0);
+        let axis = lane_axis("fold", self.axis, x.ndim())?;
+        let ret = x.map_axis(ndarray::Axis(axis), |lane| {
+            lane.iter().fold(self.init, |acc, &v| (self.op)(acc, v))
+        });
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.input(0), false)
+            .build(FoldGrad {
+                axis: self.axis,
+                init: self.init,
+                op: self.op.clone(),
+                grad: self.grad.clone(),
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.init.to_f64().unwrap().to_bits()).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for FoldGrad<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        let axis = lane_axis("fold", self.axis, x.ndim())?;
+        let mut gx = NdArray::zeros(x.shape());
+        let mut accs = Vec::new();
+        ndarray::Zip::from(gx.lanes_mut(ndarray::Axis(axis)))
+            .and(x.lanes(ndarray::Axis(axis)))
+            .and(&gy)
+            .for_each(|mut gx, x, &gy| {
+                // the partial results before each element
+                accs.clear();
+                let mut acc = self.init;
+                for &v in x {
+                    accs.push(acc);
+                    acc = (self.op)(acc, v);
+                }
+                // back from the last element, `g` is the gradient of the partial result
+                let mut g = gy;
+                for i in (0..x.len()).rev() {
+                    let (d_acc, d_v) = (self.grad)(accs[i], x[i]);
+                    gx[i] = g * d_v;
+                    g *= d_acc;
+                }
+            });
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+}
+
+// Position of the running extreme at each element of `lane`, in the order of the scan.
+// Ties keep the earlier position, and NaNs never take over.
+fn running_extreme<T: Float>(
+    lane: ndarray::ArrayView1<T>,
+    max: bool,
+    reverse: bool,
+    ret: &mut Vec<usize>,
+) {
+    ret.clear();
+    let n = lane.len();
+    let mut best = if reverse { n.wrapping_sub(1) } else { 0 };
+    for k in 0..n {
+        let i = if reverse { n - 1 - k } else { k };
+        let (v, cur) = (lane[i], lane[best]);
+        if (max && v > cur) || (!max && v < cur) || cur.is_nan() {
+            best = i;
+        }
+        ret.push(best);
+    }
+}
+
+impl<T: Float> op::Op<T> for CumExtreme {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = ctx.input(0);
+        let name = if self.max { "cummax" } else { "cummin" };
+        let axis = lane_axis(name, self.axis, x.ndim())?;
+        let mut y = NdArray::zeros(x.shape());
+        let mut at = Vec::new();
+        ndarray::Zip::from(y.lanes_mut(ndarray::Axis(axis)))
+            .and(x.lanes(ndarray::Axis(axis)))
+            .for_each(|mut y, x| {
+                running_extreme(x, self.max, self.reverse, &mut at);
+                let n = x.len();
+                for (k, &j) in at.iter().enumerate() {
+                    let i = if self.reverse { n - 1 - k } else { k };
+                    y[i] = x[j];
+                }
+            });
+        ctx.append_output(y);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(ctx.output_grad(), false)
+            .append_input(ctx.input(0), false)
+            .build(CumExtremeGrad {
+                axis: self.axis,
+                reverse: self.reverse,
+                max: self.max,
+            });
+        ctx.append_input_grad(Some(gx));
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.reverse, self.max).hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for CumExtremeGrad {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let gy = ctx.input(0);
+        let x = ctx.input(1);
+        let name = if self.max { "cummax" } else { "cummin" };
+        let axis = lane_axis(name, self.axis, x.ndim())?;
+        let mut gx = NdArray::zeros(x.shape());
+        let mut at = Vec::new();
+        ndarray::Zip::from(gx.lanes_mut(ndarray::Axis(axis)))
+            .and(x.lanes(ndarray::Axis(axis)))
+            .and(gy.lanes(ndarray::Axis(axis)))
+            .for_each(|mut gx, x, gy| {
+                // each output takes its gradient to the element it copied
+                running_extreme(x, self.max, self.reverse, &mut at);
+                let n = x.len();
+                for (k, &j) in at.iter().enumerate() {
+                    let i = if self.reverse { n - 1 - k } else { k };
+                    gx[j] += gy[i];
+                }
+            });
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (self.axis, self.reverse, self.max).hash(&mut state);
+    }
+}
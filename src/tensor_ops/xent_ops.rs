@@ -5,11 +5,30 @@ use crate::tensor_ops;
 use crate::tensor_ops::*;
 use crate::Float;
 use ndarray;
+use std::hash::{Hash, Hasher};
 
 pub struct SoftmaxCrossEntropy;
 pub struct SparseSoftmaxCrossEntropy;
 pub struct SparseSoftmaxCrossEntropyGrad;
 pub struct SigmoidCrossEntropy;
+/// Binary focal loss of logits against 0/1 targets.
+pub struct FocalLoss<T: Float> {
+    pub gamma: T,
+    pub alpha: T,
+}
+/// Gradient of `FocalLoss` for the logits: inputs are `x`, `t` and `gy`.
+pub struct FocalLossGrad<T: Float> {
+    pub gamma: T,
+    pub alpha: T,
+}
+/// Multi-class focal loss of logits against one-hot targets.
+pub struct SoftmaxFocalLoss<T: Float> {
+    pub gamma: T,
+}
+/// Gradient of `SoftmaxFocalLoss` for the logits: inputs are `log_softmax(x)`, `t` and `gy`.
+pub struct SoftmaxFocalLossGrad<T: Float> {
+    pub gamma: T,
+}
 pub struct LogSoftmax {
     pub axis: isize,
 }
@@ -22,12 +41,21 @@ impl<T: Float> op::Op<T> for LogSoftmax {
     }
 
     fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        // y = x - logsumexp(x), so gx = gy - softmax(x) * Σgy
         let gy = ctx.output_grad();
         let sm = exp(ctx.output());
-        let sum = reduce_sum(gy, &[1], true);
+        let sum = reduce_sum(gy, &[self.axis], true);
         let mul = sm * sum;
         ctx.append_input_grad(Some(gy - mul));
     }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.axis.hash(&mut state);
+    }
+
+    fn params(&self) -> op::OpParams {
+        op::params([("axis", self.axis.into())])
+    }
 }
 
 impl<T: Float> op::Op<T> for SigmoidCrossEntropy {
@@ -200,3 +228,173 @@ impl<T: Float> op::Op<T> for SoftmaxCrossEntropy {
         ctx.append_input_grad(Some(gx2));
     }
 }
+
+// `(alpha_t, p_t)` of a logit `x` and a 0/1 target `t`, where `alpha < 0` means no weighting.
+#[inline]
+fn focal_terms<T: Float>(x: T, t: T, alpha: T) -> (T, T) {
+    let p = T::one() / (T::one() + (-x).exp());
+    let p_t = p * t + (T::one() - p) * (T::one() - t);
+    let alpha_t = if alpha < T::zero() {
+        T::one()
+    } else {
+        alpha * t + (T::one() - alpha) * (T::one() - t)
+    };
+    (alpha_t, p_t)
+}
+
+// Stable `-t log(sigmoid(x)) - (1 - t) log(1 - sigmoid(x))`, as in `SigmoidCrossEntropy`.
+#[inline]
+fn sigmoid_xent<T: Float>(x: T, t: T) -> T {
+    ((-x.abs()).exp() + T::one()).ln() + T::zero().max(x) - t * x
+}
+
+impl<T: Float> op::Op<T> for FocalLoss<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = &ctx.input(0);
+        let t = &ctx.input(1);
+        if x.shape() != t.shape() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "focal_loss: shapes of logits and targets differ: {:?} vs {:?}",
+                x.shape(),
+                t.shape()
+            )));
+        }
+        let mut ret = NdArray::zeros(x.shape());
+        ndarray::Zip::from(&mut ret)
+            .and(x)
+            .and(t)
+            .for_each(|r, &x, &t| {
+                let (alpha_t, p_t) = focal_terms(x, t, self.alpha);
+                *r = alpha_t * (T::one() - p_t).powf(self.gamma) * sigmoid_xent(x, t);
+            });
+        ctx.append_output(ret);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(&ctx.input(0), false)
+            .append_input(&ctx.input(1), false)
+            .append_input(&ctx.output_grad(), false)
+            .build(FocalLossGrad {
+                gamma: self.gamma,
+                alpha: self.alpha,
+            });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        (
+            self.gamma.to_f64().unwrap().to_bits(),
+            self.alpha.to_f64().unwrap().to_bits(),
+        )
+            .hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for FocalLossGrad<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = &ctx.input(0);
+        let t = &ctx.input(1);
+        let gy = &ctx.input(2);
+        let mut gx = NdArray::zeros(x.shape());
+        ndarray::Zip::from(&mut gx)
+            .and(x)
+            .and(t)
+            .and(gy)
+            .for_each(|gx, &x, &t, &gy| {
+                let (alpha_t, p_t) = focal_terms(x, t, self.alpha);
+                let p = T::one() / (T::one() + (-x).exp());
+                // d(1 - p_t)/dx = -(2t - 1) p (1 - p), and p (1 - p) / (1 - p_t) = p_t for
+                // 0/1 targets, which keeps this finite where `1 - p_t` underflows to zero.
+                let sign = t + t - T::one();
+                let modulating = (T::one() - p_t).powf(self.gamma);
+                let d_xent = p - t;
+                let d_modulating = -self.gamma * sign * p_t * sigmoid_xent(x, t);
+                *gx = gy * alpha_t * modulating * (d_modulating + d_xent);
+            });
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+}
+
+impl<T: Float> op::Op<T> for SoftmaxFocalLoss<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let x = &ctx.input(0);
+        let t = &ctx.input(1);
+        if x.ndim() != 2 || x.shape() != t.shape() {
+            return Err(op::OpError::IncompatibleShape(format!(
+                "softmax_focal_loss: logits and targets must both be (batch_size, num_classes): {:?} vs {:?}",
+                x.shape(),
+                t.shape()
+            )));
+        }
+        let log_x: NdArray<T> = x - &tensor_ops::math_ops::logsumexp_forward(x, 1, true);
+        // - t (1 - p)^gamma log p, summed over the classes
+        let gamma = self.gamma;
+        let mut terms = log_x.mapv(move |lp| -(T::one() - lp.exp()).powf(gamma) * lp);
+        terms *= t;
+        ctx.append_output(terms.sum_axis(ndarray::Axis(1)));
+        ctx.append_output(log_x);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        let log_x = nth_tensor(ctx.output(), 1);
+        let gx = Tensor::builder(ctx.graph())
+            .append_input(&log_x, false)
+            .append_input(&ctx.input(1), false)
+            .append_input(&ctx.output_grad(), false)
+            .build(SoftmaxFocalLossGrad { gamma: self.gamma });
+        ctx.append_input_grad(Some(gx));
+        ctx.append_input_grad(None);
+    }
+
+    fn hash_params(&self, mut state: &mut dyn Hasher) {
+        self.gamma.to_f64().unwrap().to_bits().hash(&mut state);
+    }
+}
+
+impl<T: Float> op::Op<T> for SoftmaxFocalLossGrad<T> {
+    fn compute(&self, ctx: &mut crate::op::ComputeContext<T>) -> Result<(), crate::op::OpError> {
+        let log_x = &ctx.input(0);
+        let t = &ctx.input(1);
+        let gy = &ctx.input(2);
+        let gamma = self.gamma;
+        // With `a_c = t_c {(1 - p_c)^gamma - gamma (1 - p_c)^(gamma - 1) p_c log p_c}`, the
+        // derivative of `t_c (1 - p_c)^gamma log p_c` by `x_j` is `a_c (delta_cj - p_j)`, so
+        // the gradient of the loss is `p_j sum(a) - a_j`.
+        let mut a = log_x.mapv(move |lp| {
+            let p = lp.exp();
+            let q = T::one() - p;
+            if q <= T::zero() {
+                // the limit of both terms as `p -> 1`, for `gamma > 0`
+                return if gamma > T::zero() {
+                    T::zero()
+                } else {
+                    T::one()
+                };
+            }
+            q.powf(gamma) - gamma * q.powf(gamma - T::one()) * p * lp
+        });
+        a *= t;
+        let sum = a.sum_axis(ndarray::Axis(1)).insert_axis(ndarray::Axis(1));
+        let mut gx = log_x.mapv(|lp| lp.exp()) * &sum - &a;
+        gx *= &gy.view().insert_axis(ndarray::Axis(1));
+        ctx.append_output(gx);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut crate::op::GradientContext<T>) {
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+        ctx.append_input_grad(None);
+    }
+}
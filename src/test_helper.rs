@@ -1,141 +1,291 @@
 //! Provides helper functions for testing.
 use crate::evaluation::Feeder;
+use crate::ndarray::{self, Dimension};
+use crate::ndarray_ext::ArrayRng;
 use crate::tensor::Tensor;
 use crate::tensor_ops::*;
-use crate::{ndarray_ext, Context, Float};
+use crate::{Context, Float, NdArray};
 
-/// Checks the validity of `gradients` with finite difference trick.
-/// For this test only, `variables` must be *shared* variables.
-pub fn check_theoretical_grads<'g, 't, 'v, F: Float, A>(
+/// Default relative tolerance of [assert_tensors_close], as in numpy's `allclose`.
+pub const DEFAULT_RTOL: f64 = 1e-5;
+
+/// Default absolute tolerance of [assert_tensors_close], as in numpy's `allclose`.
+pub const DEFAULT_ATOL: f64 = 1e-8;
+
+/// Asserts that `a` and `b` have the same shape and that `|a - b| <= atol + rtol * |b|`
+/// holds elementwise.
+///
+/// Panics with the first mismatching index otherwise. NaNs never match.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+/// use ag::test_helper::{assert_tensors_close, DEFAULT_ATOL, DEFAULT_RTOL};
+///
+/// ag::run(|g| {
+///     let x: ag::Tensor<f64> = T::exp(T::ln(T::ones(&[3], g)));
+///     let x = x.eval(g).unwrap();
+///     assert_tensors_close(&x, &ag::ndarray_ext::ones(&[3]), DEFAULT_RTOL, DEFAULT_ATOL);
+/// });
+/// ```
+pub fn assert_tensors_close<F: Float>(a: &NdArray<F>, b: &NdArray<F>, rtol: f64, atol: f64) {
+    assert_eq!(a.shape(), b.shape(), "tensors have different shapes");
+    if let Some((i, x, y)) = first_mismatch(a, b, rtol, atol) {
+        panic!(
+            "tensors differ at index {:?}: {} vs {} (rtol = {}, atol = {})",
+            i, x, y, rtol, atol
+        );
+    }
+}
+
+// Index and values of the first element where `|a - b| <= atol + rtol * |b|` fails.
+fn first_mismatch<F: Float>(
+    a: &NdArray<F>,
+    b: &NdArray<F>,
+    rtol: f64,
+    atol: f64,
+) -> Option<(Vec<usize>, F, F)> {
+    let rtol = F::from(rtol).unwrap();
+    let atol = F::from(atol).unwrap();
+    a.indexed_iter()
+        .zip(b.iter())
+        // `x == y` for the infinities
+        .find(|&((_, &x), &y)| !(x == y || (x - y).abs() <= atol + rtol * y.abs()))
+        .map(|((i, &x), &y)| (i.slice().to_vec(), x, y))
+}
+
+/// Asserts that the graphs `a` and `b` compute the same outputs, e.g. before and after
+/// refactoring a model.
+///
+/// `feeds` lists the placeholders the graphs read, by name, with the shapes to feed: each is
+/// fed the same standard normal array (drawn with a fixed seed) in both graphs. Then the
+/// `i` th outputs of `a` and `b` must match as in [assert_tensors_close] with the default
+/// tolerances.
+///
+/// Panics with the first mismatching output and index otherwise, or if an evaluation fails.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::tensor_ops as T;
+/// use ag::test_helper::assert_graphs_equivalent;
+///
+/// ag::run(|g| {
+///     let x: ag::Tensor<f64> = g.placeholder("x", &[-1, 3]);
+///     let before = T::reduce_sum(T::square(x) * 2., &[1], false);
+///     // refactored
+///     let x = g.placeholder("x", &[-1, 3]);
+///     let after = T::reduce_sum(x * x + x * x, &[1], false);
+///     assert_graphs_equivalent(&[before], &[after], g, &[("x", &[4, 3])]);
+/// });
+/// ```
+pub fn assert_graphs_equivalent<'g, F: Float, A>(
+    a: &[A],
+    b: &[A],
+    g: &'g Context<F>,
+    feeds: &[(&'static str, &[usize])],
+) where
+    A: AsRef<Tensor<'g, F>> + Copy,
+{
+    assert_eq!(
+        a.len(),
+        b.len(),
+        "assert_graphs_equivalent: the graphs have different numbers of outputs"
+    );
+    let rng = ArrayRng::<F>::default();
+    let values: Vec<_> = feeds
+        .iter()
+        .map(|&(_, shape)| rng.standard_normal(shape))
+        .collect();
+    let mut evaluator = g.evaluator();
+    for (&(name, _), value) in feeds.iter().zip(&values) {
+        evaluator.feed(name, value.view());
+    }
+    let results = evaluator.extend(a).extend(b).run();
+    let (results_a, results_b) = results.split_at(a.len());
+
+    for (i, (x, y)) in results_a.iter().zip(results_b).enumerate() {
+        let x = x
+            .as_ref()
+            .unwrap_or_else(|e| panic!("output {} of a failed: {}", i, e));
+        let y = y
+            .as_ref()
+            .unwrap_or_else(|e| panic!("output {} of b failed: {}", i, e));
+        assert_eq!(
+            x.shape(),
+            y.shape(),
+            "output {} of the graphs has different shapes",
+            i
+        );
+        if let Some((index, x, y)) = first_mismatch(x, y, DEFAULT_RTOL, DEFAULT_ATOL) {
+            panic!(
+                "output {} of the graphs differs at index {:?}: {} vs {}",
+                i, index, x, y
+            );
+        }
+    }
+}
+
+/// Gradients of one variable given by [grad_numerical_vs_analytic].
+#[derive(Clone, Debug)]
+pub struct GradCheckReport<F: Float> {
+    /// The gradient computed by backprop.
+    pub analytic: NdArray<F>,
+    /// The gradient estimated with central differences.
+    pub numerical: NdArray<F>,
+    /// The largest `|analytic - numerical|`.
+    pub max_abs_error: F,
+    /// Index of the element with the largest absolute error.
+    pub worst_index: Vec<usize>,
+}
+
+impl<F: Float> GradCheckReport<F> {
+    /// Whether `|analytic - numerical| <= atol + rtol * |numerical|` for every element.
+    pub fn is_close(&self, rtol: f64, atol: f64) -> bool {
+        let rtol = F::from(rtol).unwrap();
+        let atol = F::from(atol).unwrap();
+        self.analytic
+            .iter()
+            .zip(&self.numerical)
+            .all(|(&a, &n)| (a - n).abs() <= atol + rtol * n.abs())
+    }
+}
+
+/// Compares the gradients of `objective` with respect to each of `variables`, given by
+/// backprop, to their estimates with central differences.
+///
+/// `variables` must be variable tensors, since their arrays are perturbed by `eps` in place
+/// one element at a time, and `objective` is summed to a scalar first.
+///
+/// Selecting the tolerances: the estimates are off by `O(eps^2)` (truncation)
+/// plus `O(epsilon / eps)` (rounding), where `epsilon` is the machine epsilon.
+/// With f64, `eps = 1e-3` and an absolute tolerance of `1e-3` suit most ops;
+/// with f32, try `eps = 1e-2` and `1e-2`. Keep the inputs of non-smooth ops
+/// (`relu`, `abs`, `max`...) away from their kinks, where the estimates are meaningless.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+///
+/// let mut env = ag::VariableEnvironment::new();
+/// let w = env.set(ag::ndarray::arr1(&[0.5, -1., 2.]));
+///
+/// env.run(|g| {
+///     let w = g.variable(w);
+///     let y = T::sin(w) * w;
+///     let reports =
+///         ag::test_helper::grad_numerical_vs_analytic(y, &[w], ag::Feeder::new(), 1e-3, g);
+///     assert!(reports[0].is_close(1e-4, 1e-6), "{:?}", reports[0]);
+/// });
+/// ```
+pub fn grad_numerical_vs_analytic<'g, 'v, F: Float, A>(
     objective: A,
-    gradients: &'t [A],
-    variables: &'t [A],
+    variables: &[A],
     feeder: Feeder<'v, F>,
     eps: F,
-    tol: F,
     g: &'g Context<F>,
-) where
+) -> Vec<GradCheckReport<F>>
+where
     A: AsRef<Tensor<'g, F>> + Copy,
 {
     let objective = sum_all(objective);
+    let gradients = grad(&[objective], variables);
+    compare_gradients(objective, &gradients, variables, feeder, eps, g)
+}
+
+fn compare_gradients<'g, 'v, F: Float, A, B>(
+    objective: Tensor<'g, F>,
+    gradients: &[A],
+    variables: &[B],
+    feeder: Feeder<'v, F>,
+    eps: F,
+    g: &'g Context<F>,
+) -> Vec<GradCheckReport<F>>
+where
+    A: AsRef<Tensor<'g, F>> + Copy,
+    B: AsRef<Tensor<'g, F>> + Copy,
+{
     // backprop
-    let theoretical_grads = g
+    let analytic = g
         .evaluator()
         .extend(gradients)
         .set_feeder(feeder.clone())
         .run();
+    let eval_objective = || {
+        g.evaluator()
+            .push(objective)
+            .set_feeder(feeder.clone())
+            .run()
+            .remove(0)
+            .unwrap()
+            .sum()
+    };
+    let two = F::one() + F::one();
 
-    // for each variable nodes
-    for (var_node, th_grad) in variables.iter().zip(theoretical_grads) {
-        // Copy gradient array if needed
-        let th_copied = if th_grad.as_ref().unwrap().is_standard_layout() {
-            None
-        } else {
-            Some(ndarray_ext::deep_copy(&th_grad.as_ref().unwrap().view()))
-        };
-        let th_ptr = if let Some(ref inner) = th_copied {
-            inner.as_ptr()
-        } else {
-            th_grad.as_ref().unwrap().as_ptr()
-        };
-
-        // for each values
-        let v_len = g
+    let mut ret = Vec::with_capacity(variables.len());
+    for (var, analytic) in variables.iter().zip(analytic) {
+        let vid = var
+            .as_ref()
+            .get_variable_id()
+            .expect("This is not a variable");
+        let arr = g
             .env()
-            .get_array_by_id(
-                var_node
-                    .as_ref()
-                    .get_variable_id()
-                    .expect("This is not a variable"),
-            )
-            .expect("variable array not found")
-            .borrow()
-            .len();
-
-        for i in 0..v_len as isize {
-            let evacuated;
-            // +
-            unsafe {
-                let mut guard_mut = g
-                    .env()
-                    .get_array_by_id(
-                        var_node
-                            .as_ref()
-                            .get_variable_id()
-                            .expect("This is not a variable"),
-                    )
-                    .expect("variable array not found")
-                    .borrow_mut();
-                let head = guard_mut.as_mut_ptr();
-                evacuated = *head.offset(i);
-                *head.offset(i) = evacuated + eps;
-            }
-
-            // eval
-            let obj_pos_orig = g
-                .evaluator()
-                .push(objective)
-                .set_feeder(feeder.clone())
-                .run()
-                .remove(0)
-                .unwrap();
-            let obj_pos = if obj_pos_orig.is_standard_layout() {
-                obj_pos_orig
-            } else {
-                ndarray_ext::deep_copy(&obj_pos_orig.view())
-            };
-
-            unsafe {
-                let mut guard_mut = g
-                    .env()
-                    .get_array_by_id(
-                        var_node
-                            .as_ref()
-                            .get_variable_id()
-                            .expect("This is not a variable"),
-                    )
-                    .expect("variable array not found")
-                    .borrow_mut();
-
-                let head = guard_mut.as_mut_ptr();
-                *head.offset(i) = evacuated - eps;
-            }
-
-            // eval
-            let obj_neg_orig = g
-                .evaluator()
-                .push(objective)
-                .set_feeder(feeder.clone())
-                .run()
-                .remove(0)
-                .unwrap();
-            let obj_neg = if obj_neg_orig.is_standard_layout() {
-                obj_neg_orig
-            } else {
-                ndarray_ext::deep_copy(&obj_neg_orig.view())
-            };
+            .get_array_by_id(vid)
+            .expect("variable array not found");
+        let analytic = analytic.unwrap();
+        let shape = arr.borrow().raw_dim();
+        let mut numerical = NdArray::<F>::zeros(shape.clone());
+        let mut max_abs_error = F::zero();
+        let mut worst_index = vec![0; shape.ndim()];
 
+        for i in ndarray::indices(shape) {
+            let i = i.slice();
+            let evacuated = arr.borrow()[i];
+            arr.borrow_mut()[i] = evacuated + eps;
+            let obj_pos = eval_objective();
+            arr.borrow_mut()[i] = evacuated - eps;
+            let obj_neg = eval_objective();
             // restore
-            unsafe {
-                let mut guard_mut = g
-                    .env()
-                    .get_array_by_id(
-                        var_node
-                            .as_ref()
-                            .get_variable_id()
-                            .expect("This is not a variable"),
-                    )
-                    .expect("variable array not found")
-                    .borrow_mut();
-                let head = guard_mut.as_mut_ptr();
-                *head.offset(i) = evacuated;
-            }
+            arr.borrow_mut()[i] = evacuated;
 
-            let two = F::one() + F::one();
-            let g_num = (obj_pos - obj_neg).sum() / (two * eps);
-            let g_th = unsafe { *th_ptr.offset(i) };
+            let g_num = (obj_pos - obj_neg) / (two * eps);
+            numerical[i] = g_num;
+            let error = (g_num - analytic[i]).abs();
+            if error > max_abs_error || error.is_nan() {
+                max_abs_error = error;
+                worst_index = i.to_vec();
+            }
+        }
+        ret.push(GradCheckReport {
+            analytic,
+            numerical,
+            max_abs_error,
+            worst_index,
+        });
+    }
+    ret
+}
 
-            // compare
+/// Checks the validity of `gradients` with finite difference trick.
+/// For this test only, `variables` must be *shared* variables.
+///
+/// Panics if an element of a gradient is off by more than `tol`.
+/// See [grad_numerical_vs_analytic] for a report instead.
+pub fn check_theoretical_grads<'g, 't, 'v, F: Float, A>(
+    objective: A,
+    gradients: &'t [A],
+    variables: &'t [A],
+    feeder: Feeder<'v, F>,
+    eps: F,
+    tol: F,
+    g: &'g Context<F>,
+) where
+    A: AsRef<Tensor<'g, F>> + Copy,
+{
+    let objective = sum_all(objective);
+    for report in compare_gradients(objective, gradients, variables, feeder, eps, g) {
+        // compare
+        for (&g_num, &g_th) in report.numerical.iter().zip(&report.analytic) {
             let diff = (g_num - g_th).abs();
             if diff > tol {
                 panic!(
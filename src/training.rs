@@ -0,0 +1,419 @@
+//! Helpers for training loops.
+use crate::evaluation::Feeder;
+use crate::ndarray_ext::NdArray;
+use crate::optimizers::Optimizer;
+use crate::tensor::Tensor;
+use crate::tensor_ops as T;
+use crate::variable::VariableEnvironment;
+use crate::{Context, EvalError, Float};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// Scales `base`, e.g. a learning rate, linearly up over the first `warmup_steps` steps.
+///
+/// Returns `base * (step + 1) / warmup_steps` until `step + 1` reaches `warmup_steps`, and
+/// `base` from then on, so that the very first step isn't wasted with a zero rate. `step` is
+/// usually [Context::global_step](crate::Context::global_step).
+///
+/// ```
+/// use autograd as ag;
+/// use ag::training::warmup;
+///
+/// assert_eq!(warmup(0.1, 0, 4), 0.025);
+/// assert_eq!(warmup(0.1, 3, 4), 0.1);
+/// assert_eq!(warmup(0.1, 100, 4), 0.1);
+/// ```
+pub fn warmup<F: Float>(base: F, step: usize, warmup_steps: usize) -> F {
+    if step + 1 >= warmup_steps {
+        return base;
+    }
+    base * F::from(step + 1).unwrap() / F::from(warmup_steps).unwrap()
+}
+
+/// Stops training once a monitored metric, e.g. a validation loss, stops decreasing.
+///
+/// [EarlyStopping::should_stop] takes the metric of each epoch, and returns true after
+/// `patience` epochs in a row without an improvement on the best value, where an improvement
+/// is a decrease by more than `min_delta`.
+///
+/// [EarlyStopping::should_stop_with] also copies the variables of an environment whenever
+/// the metric improves, so that [EarlyStopping::restore_best] can put the best weights back
+/// when stopping.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::training::EarlyStopping;
+///
+/// let mut early_stopping = EarlyStopping::new(2, 0.01);
+/// let losses = [1.0, 0.5, 0.495, 0.6, 0.4];
+/// let stopped_at = losses.iter().position(|&loss| early_stopping.should_stop(loss));
+/// assert_eq!(stopped_at, Some(3));
+/// assert_eq!(early_stopping.best(), Some(0.5));
+/// ```
+pub struct EarlyStopping<F: Float> {
+    patience: usize,
+    min_delta: F,
+    best: Option<F>,
+    epochs_since_best: usize,
+    // Variable arrays saved by `should_stop_with` at the best epoch, in the order of their ids
+    best_weights: Option<Vec<NdArray<F>>>,
+}
+
+impl<F: Float> EarlyStopping<F> {
+    /// Creates a helper stopping after `patience` epochs without an improvement larger than `min_delta`.
+    pub fn new(patience: usize, min_delta: F) -> Self {
+        EarlyStopping {
+            patience,
+            min_delta,
+            best: None,
+            epochs_since_best: 0,
+            best_weights: None,
+        }
+    }
+
+    /// Records the `metric` of an epoch, and returns true if training should stop.
+    pub fn should_stop(&mut self, metric: F) -> bool {
+        self.record(metric);
+        self.epochs_since_best >= self.patience
+    }
+
+    /// Same as [EarlyStopping::should_stop], also copying the variables of `env` if `metric`
+    /// is the best so far.
+    pub fn should_stop_with(&mut self, metric: F, env: &VariableEnvironment<F>) -> bool {
+        if self.record(metric) {
+            self.best_weights = Some(env.iter().map(|(_, arr)| arr.borrow().clone()).collect());
+        }
+        self.epochs_since_best >= self.patience
+    }
+
+    /// Writes the variables copied at the best epoch back to `env`.
+    ///
+    /// Returns false if nothing was copied, i.e. [EarlyStopping::should_stop_with]
+    /// has never been called. Panics if `env` isn't the environment given to it.
+    pub fn restore_best(&self, env: &VariableEnvironment<F>) -> bool {
+        let best_weights = match &self.best_weights {
+            Some(weights) => weights,
+            None => return false,
+        };
+        let arrays: Vec<_> = env.iter().collect();
+        assert_eq!(
+            arrays.len(),
+            best_weights.len(),
+            "restore_best: the environment has {} variables, but {} were saved",
+            arrays.len(),
+            best_weights.len()
+        );
+        for ((_, arr), best) in arrays.into_iter().zip(best_weights) {
+            arr.borrow_mut().assign(best);
+        }
+        true
+    }
+
+    /// Returns the best metric recorded so far.
+    pub fn best(&self) -> Option<F> {
+        self.best
+    }
+
+    /// Returns the number of epochs recorded since the best one.
+    pub fn epochs_since_best(&self) -> usize {
+        self.epochs_since_best
+    }
+
+    // Returns true if `metric` is the new best.
+    fn record(&mut self, metric: F) -> bool {
+        match self.best {
+            Some(best) if metric >= best - self.min_delta => {
+                self.epochs_since_best += 1;
+                false
+            }
+            _ => {
+                self.best = Some(metric);
+                self.epochs_since_best = 0;
+                true
+            }
+        }
+    }
+}
+
+/// Loss scaling for mixed precision training, with a factor adapted to the gradients.
+///
+/// [LossScaler::scale_loss] multiplies a loss by the current factor (see
+/// [loss_scale](crate::tensor_ops::loss_scale)). [LossScaler::step] evaluates the gradients
+/// of the scaled loss, divides them back, and hands them to an optimizer, unless one of them
+/// holds an Inf or a NaN: that update is skipped, and a dynamic scaler multiplies its factor by
+/// `backoff_factor`. After `growth_interval` steps in a row with finite gradients, a dynamic
+/// scaler multiplies it by `growth_factor`, to use as much of the range as the gradients allow.
+/// A fixed scaler only skips the steps.
+///
+/// The factor is read when the scaled loss is built, so build it anew for each step.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::SGD;
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+/// use ag::training::LossScaler;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// let w = env.set(ag::ndarray::arr1(&[1.]));
+/// let mut scaler = LossScaler::dynamic(1024., 2., 0.5, 100);
+/// let sgd = SGD::new(0.1);
+///
+/// env.run(|g| {
+///     let w = g.variable(w);
+///     let loss = scaler.scale_loss(T::sum_all(T::square(w)));
+///     let grads = T::grad(&[loss], &[w]);
+///     assert_eq!(scaler.step(&sgd, &[w], &grads, g, ag::Feeder::new()), Ok(true));
+///     assert_eq!(w.eval(g), Ok(ag::ndarray::arr1(&[0.8]).into_dyn()));
+/// });
+/// ```
+pub struct LossScaler<F: Float> {
+    scale: F,
+    growth_factor: F,
+    backoff_factor: F,
+    growth_interval: usize,
+    dynamic: bool,
+    // Steps in a row with finite gradients since the last change of `scale`
+    good_steps: usize,
+}
+
+impl<F: Float> LossScaler<F> {
+    /// Creates a scaler always using `scale`.
+    pub fn fixed(scale: F) -> Self {
+        LossScaler {
+            scale,
+            growth_factor: F::one(),
+            backoff_factor: F::one(),
+            growth_interval: usize::MAX,
+            dynamic: false,
+            good_steps: 0,
+        }
+    }
+
+    /// Creates a scaler starting from `init_scale`, backed off by `backoff_factor` on
+    /// non-finite gradients and grown by `growth_factor` every `growth_interval` good steps.
+    ///
+    /// Panics unless `growth_factor >= 1`, `0 < backoff_factor <= 1` and `growth_interval > 0`.
+    pub fn dynamic(
+        init_scale: F,
+        growth_factor: F,
+        backoff_factor: F,
+        growth_interval: usize,
+    ) -> Self {
+        assert!(
+            growth_factor >= F::one(),
+            "LossScaler: growth_factor must be >= 1"
+        );
+        assert!(
+            backoff_factor > F::zero() && backoff_factor <= F::one(),
+            "LossScaler: backoff_factor must be in (0, 1]"
+        );
+        assert!(
+            growth_interval > 0,
+            "LossScaler: growth_interval must be positive"
+        );
+        LossScaler {
+            scale: init_scale,
+            growth_factor,
+            backoff_factor,
+            growth_interval,
+            dynamic: true,
+            good_steps: 0,
+        }
+    }
+
+    /// Returns the current factor.
+    pub fn scale(&self) -> F {
+        self.scale
+    }
+
+    /// Multiplies `loss` by the current factor.
+    pub fn scale_loss<'g, A>(&self, loss: A) -> Tensor<'g, F>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        T::loss_scale(loss, self.scale)
+    }
+
+    /// Records the unscaled gradients of a step, and returns true if they are all finite.
+    ///
+    /// A dynamic scaler adjusts its factor here; [LossScaler::step] calls this already.
+    pub fn update(&mut self, grads: &[NdArray<F>]) -> bool {
+        let finite = grads.iter().all(|g| g.iter().all(|v| v.is_finite()));
+        if !self.dynamic {
+            return finite;
+        }
+        if finite {
+            self.good_steps += 1;
+            if self.good_steps >= self.growth_interval {
+                self.scale *= self.growth_factor;
+                self.good_steps = 0;
+            }
+        } else {
+            self.scale *= self.backoff_factor;
+            self.good_steps = 0;
+        }
+        finite
+    }
+
+    /// Evaluates `grads`, the gradients of a loss scaled by [LossScaler::scale_loss], unscales
+    /// them and updates `variables` with `optimizer` if they are all finite.
+    ///
+    /// Returns whether the update was applied, or the first error raised by the gradients.
+    pub fn step<'g, O, A, B>(
+        &mut self,
+        optimizer: &O,
+        variables: &[A],
+        grads: &[B],
+        g: &'g Context<F>,
+        feeder: Feeder<F>,
+    ) -> Result<bool, EvalError>
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+        B: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let scale = self.scale;
+        let mut evaluator = g.evaluator();
+        evaluator.set_feeder(feeder.clone()).extend(grads);
+        let mut unscaled = Vec::with_capacity(grads.len());
+        for gx in evaluator.run() {
+            unscaled.push(gx?.mapv(|v| v / scale));
+        }
+        if !self.update(&unscaled) {
+            return Ok(false);
+        }
+        let grads: Vec<_> = unscaled
+            .into_iter()
+            .map(|gx| T::convert_to_tensor(gx, g))
+            .collect();
+        optimizer.update(variables, &grads, g, feeder);
+        Ok(true)
+    }
+}
+
+/// Sums the gradients of variables over several graphs by variable name, for a single
+/// optimizer step.
+///
+/// A variable is a different tensor in each graph, e.g. an encoder shared by two models built
+/// in separate [VariableEnvironment::run]s, but it keeps its name: [GradAccumulator::accumulate]
+/// evaluates the gradients of a loss in one graph and adds them to the sums of the variables'
+/// names, and [GradAccumulator::step] updates the variables of another graph with the sums.
+/// The names include the namespaces, so `w` of `layer1` and `w` of `layer2` have their own
+/// sums. The anonymous variables have a random name, which only matches in their own
+/// environment.
+///
+/// ```
+/// use autograd as ag;
+/// use ag::optimizers::SGD;
+/// use ag::prelude::*;
+/// use ag::tensor_ops as T;
+/// use ag::training::GradAccumulator;
+///
+/// let mut env = ag::VariableEnvironment::<f64>::new();
+/// env.slot().name("encoder").set(ag::ndarray::arr1(&[1.]));
+/// let mut acc = GradAccumulator::new();
+///
+/// env.run(|g| {
+///     let loss = T::sum_all(g.variable("encoder") * 2.);
+///     acc.accumulate(loss, &[g.variable("encoder")], ag::Feeder::new(), g).unwrap();
+/// });
+/// env.run(|g| {
+///     let loss = T::sum_all(g.variable("encoder") * 3.);
+///     acc.accumulate(loss, &[g.variable("encoder")], ag::Feeder::new(), g).unwrap();
+///     assert_eq!(acc.get("", "encoder"), Some(&ag::ndarray::arr1(&[5.]).into_dyn()));
+///
+///     acc.step(&SGD::new(0.1), &[g.variable("encoder")], g);
+///     assert!((g.variable("encoder").eval(g).unwrap()[0] - 0.5).abs() < 1e-12);
+/// });
+/// ```
+pub struct GradAccumulator<F: Float> {
+    // by namespace and name
+    sums: HashMap<(String, String), NdArray<F>>,
+}
+
+impl<F: Float> Default for GradAccumulator<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: Float> GradAccumulator<F> {
+    /// Creates an accumulator without any gradient.
+    pub fn new() -> Self {
+        GradAccumulator {
+            sums: HashMap::new(),
+        }
+    }
+
+    /// Evaluates the gradients of `loss` with `variables` in the graph of `ctx`, and adds them
+    /// to the sums of the variables' names.
+    ///
+    /// Returns the first error raised by the gradients, in which case nothing is added.
+    /// Panics if one of `variables` is not a variable.
+    pub fn accumulate<'g, A, B>(
+        &mut self,
+        loss: A,
+        variables: &[B],
+        feeder: Feeder<F>,
+        ctx: &'g Context<F>,
+    ) -> Result<(), EvalError>
+    where
+        A: AsRef<Tensor<'g, F>> + Copy,
+        B: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let grads = crate::optimizers::grad_only(loss, variables, feeder, ctx)?;
+        for (v, gx) in variables.iter().zip(grads) {
+            match self.sums.entry(full_name(v.as_ref(), ctx)) {
+                Entry::Occupied(mut sum) => *sum.get_mut() += &gx,
+                Entry::Vacant(sum) => {
+                    sum.insert(gx);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sum of the gradients accumulated for the variable named `name` in
+    /// `namespace`; the default namespace is `""`.
+    pub fn get(&self, namespace: &str, name: &str) -> Option<&NdArray<F>> {
+        self.sums.get(&(namespace.to_string(), name.to_string()))
+    }
+
+    /// Drops the gradients accumulated so far.
+    pub fn clear(&mut self) {
+        self.sums.clear();
+    }
+
+    /// Updates `variables` with `optimizer` and the sums of their gradients, then clears the
+    /// sums.
+    ///
+    /// The variables without accumulated gradients are left as they are. Panics if one of
+    /// `variables` is not a variable.
+    pub fn step<'g, O, A>(&mut self, optimizer: &O, variables: &[A], ctx: &'g Context<F>)
+    where
+        O: Optimizer<F>,
+        A: AsRef<Tensor<'g, F>> + Copy,
+    {
+        let mut vars = Vec::with_capacity(variables.len());
+        let mut grads = Vec::with_capacity(variables.len());
+        for v in variables {
+            if let Some(sum) = self.sums.remove(&full_name(v.as_ref(), ctx)) {
+                vars.push(*v.as_ref());
+                grads.push(T::convert_to_tensor(sum, ctx));
+            }
+        }
+        self.sums.clear();
+        if !vars.is_empty() {
+            optimizer.update(&vars, &grads, ctx, Feeder::new());
+        }
+    }
+}
+
+// Namespace and name `v` was registered with
+fn full_name<'g, F: Float>(v: &Tensor<'g, F>, ctx: &'g Context<F>) -> (String, String) {
+    v.get_variable_id()
+        .and_then(|vid| ctx.env().variable_full_name(vid))
+        .map(|(namespace, name)| (namespace.to_string(), name.to_string()))
+        .expect("GradAccumulator: not a variable")
+}
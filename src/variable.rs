@@ -76,16 +76,17 @@
 //! // new_env.run(...
 //! ```
 use crate::graph::Context;
-use crate::{uuid::Uuid, Float, FxHashMap, Graph, NdArray, Tensor, NdArrayViewMut, NdArrayView};
+use crate::{uuid::Uuid, Float, FxHashMap, FxHashSet, Graph, NdArray, Tensor, NdArrayViewMut, NdArrayView};
 use serde::Deserialize;
 use serde_json;
 use smallvec::alloc::fmt::Formatter;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 use std::error::Error;
 use std::fs::File;
-use std::ops::Deref;
+use std::ops::{Deref, Range};
 use std::path::Path;
+use std::rc::Rc;
 
 #[derive(Copy, Clone, Hash, PartialEq, Eq, Debug, Serialize, Deserialize)]
 /// Variable array's ID that is unique in a `VariableEnvironment`.
@@ -113,6 +114,9 @@ impl std::fmt::Display for VariableID {
 
 const DEFAULT_NAMESPACE_ID: &'static str = "";
 
+/// Namespace of the arrays registered by [VariableEnvironment::add_running_stats].
+pub const RUNNING_STATS_NAMESPACE_ID: &str = "running_stats";
+
 pub type Variable<F> = RefCell<NdArray<F>>;
 
 /// Get or create a variable tensor.
@@ -152,10 +156,56 @@ impl<'g, 'e: 'g, F: Float> GetVariableTensor<'g, F, (&'static str, &'static str)
 /// Manages variable arrays
 ///
 /// See [variable](crate::variable).
-#[derive(Clone)]
 pub struct VariableEnvironment<F> {
-    pub(crate) array_list: Vec<Variable<F>>,
+    // Shared with other envs by `VariableEnvironment::share`
+    pub(crate) array_list: Vec<Rc<Variable<F>>>,
     pub(crate) name_to_id: FxHashMap<FullName, VariableID>,
+    pub(crate) slices: FxHashMap<VariableID, VariableSlice>,
+    // See `nn::tie_weights`
+    pub(crate) ties: FxHashMap<VariableID, VariableTie>,
+    // The variables stored as f16, see `half_storage`. Their entries in `array_list` are empty,
+    // or their f32 master weights once updated in place.
+    #[cfg(feature = "f16")]
+    pub(crate) half: FxHashMap<VariableID, RefCell<ndarray::ArrayD<half::f16>>>,
+    // Namespaces of the states of the optimizers, kept by `Context::gc`
+    pub(crate) state_namespaces: FxHashSet<String>,
+    // See `Context::global_step`
+    pub(crate) global_step: Cell<usize>,
+}
+
+// A variable whose array is the block `ranges` of the array of `parent`.
+//
+// Its own entry in `array_list` is an empty array: the data lives in the parent's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct VariableSlice {
+    pub(crate) parent: VariableID,
+    // (start, end) along each leading axis; the remaining axes are taken whole
+    pub(crate) ranges: Vec<(usize, usize)>,
+}
+
+impl VariableSlice {
+    // Range along `axis` of the parent's array of `shape`
+    fn range(&self, axis: usize, shape: &[usize]) -> (usize, usize) {
+        self.ranges.get(axis).cloned().unwrap_or((0, shape[axis]))
+    }
+
+    fn overlaps(&self, other: &VariableSlice, shape: &[usize]) -> bool {
+        (0..shape.len()).all(|axis| {
+            let (a0, a1) = self.range(axis, shape);
+            let (b0, b1) = other.range(axis, shape);
+            a0 < b1 && b0 < a1
+        })
+    }
+}
+
+// A variable made of the array of `to`, by `nn::tie_weights`.
+//
+// Its own entry in `array_list` is the one of `to` if they have the same shape, an empty array
+// if it's the transpose.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct VariableTie {
+    pub(crate) to: VariableID,
+    pub(crate) transposed: bool,
 }
 
 // Identifies variable array
@@ -255,7 +305,7 @@ pub trait NamespaceTrait<F: Float> {
         self.env()
             .name_to_id
             .get(name)
-            .map(|vid| &self.env().array_list[vid.0])
+            .map(|vid| &*self.env().array_list[vid.0])
     }
 
     /// Lists all the IDs of the variable arrays in this namespace.
@@ -357,10 +407,42 @@ fn register_variable<F: Float, D: ndarray::Dimension, S: Into<String>>(
     let vid = FullName::new(namespace_id, variable_name.into());
     let next_id = env.array_list.len().into();
     env.name_to_id.insert(vid, next_id);
-    env.array_list.push(RefCell::new(v.into_dyn()));
+    env.array_list.push(Rc::new(RefCell::new(v.into_dyn())));
     next_id
 }
 
+// Makes the variables tied with the same shape hold the array of the one they're tied to
+// again, after the arrays of `env` were copied.
+fn share_tied_arrays<T>(env: &mut VariableEnvironment<T>) {
+    for (vid, tie) in &env.ties {
+        if !tie.transposed {
+            env.array_list[vid.0] = env.array_list[tie.to.0].clone();
+        }
+    }
+}
+
+impl<F: Float> Clone for VariableEnvironment<F> {
+    /// Copies the variable arrays: the clone doesn't share them with `self`.
+    fn clone(&self) -> Self {
+        let mut ret = VariableEnvironment {
+            array_list: self
+                .array_list
+                .iter()
+                .map(|v| Rc::new(RefCell::new(v.borrow().clone())))
+                .collect(),
+            name_to_id: self.name_to_id.clone(),
+            slices: self.slices.clone(),
+            ties: self.ties.clone(),
+            #[cfg(feature = "f16")]
+            half: self.half.clone(),
+            state_namespaces: self.state_namespaces.clone(),
+            global_step: self.global_step.clone(),
+        };
+        share_tied_arrays(&mut ret);
+        ret
+    }
+}
+
 impl<'env, F: Float> NamespaceTrait<F> for VariableNamespace<'env, F> {
     #[inline]
     fn name(&self) -> &'static str {
@@ -476,15 +558,33 @@ fn test_namespace_iter() {
 }
 
 #[derive(Serialize)]
-struct SerializableVariableEnvironment<'a, F> {
-    array_list: &'a Vec<Variable<F>>,
+pub(crate) struct SerializableVariableEnvironment<'a, F> {
+    array_list: Vec<&'a Variable<F>>,
     name_to_id: FxHashMap<String, VariableID>,
+    slices: Vec<(VariableID, VariableSlice)>,
+    ties: Vec<(VariableID, VariableTie)>,
+    // the bits of the f16 elements
+    #[cfg(feature = "f16")]
+    half: Vec<(VariableID, ndarray::ArrayD<u16>)>,
+    state_namespaces: Vec<String>,
+    global_step: usize,
 }
 
 #[derive(Deserialize)]
-struct DeserializedVariableEnvironment<F> {
+pub(crate) struct DeserializedVariableEnvironment<F> {
     array_list: Vec<Variable<F>>,
     name_to_id: FxHashMap<String, VariableID>,
+    #[serde(default)]
+    slices: Vec<(VariableID, VariableSlice)>,
+    #[serde(default)]
+    ties: Vec<(VariableID, VariableTie)>,
+    #[cfg(feature = "f16")]
+    #[serde(default)]
+    half: Vec<(VariableID, ndarray::ArrayD<u16>)>,
+    #[serde(default)]
+    state_namespaces: Vec<String>,
+    #[serde(default)]
+    global_step: usize,
 }
 
 // f32 save and load
@@ -505,9 +605,23 @@ impl<'env> VariableEnvironment<f32> {
         let VariableEnvironment {
             array_list,
             name_to_id,
+            slices,
+            ties,
+            #[cfg(feature = "f16")]
+            half,
+            state_namespaces,
+            global_step,
         } = Self::load_internal(raw)?;
         self.array_list = array_list;
         self.name_to_id = name_to_id;
+        self.slices = slices;
+        self.ties = ties;
+        #[cfg(feature = "f16")]
+        {
+            self.half = half;
+        }
+        self.state_namespaces = state_namespaces;
+        self.global_step = global_step;
         Ok(())
     }
 }
@@ -530,9 +644,23 @@ impl<'env> VariableEnvironment<f64> {
         let VariableEnvironment {
             array_list,
             name_to_id,
+            slices,
+            ties,
+            #[cfg(feature = "f16")]
+            half,
+            state_namespaces,
+            global_step,
         } = Self::load_internal(raw)?;
         self.array_list = array_list;
         self.name_to_id = name_to_id;
+        self.slices = slices;
+        self.ties = ties;
+        #[cfg(feature = "f16")]
+        {
+            self.half = half;
+        }
+        self.state_namespaces = state_namespaces;
+        self.global_step = global_step;
         Ok(())
     }
 }
@@ -543,6 +671,12 @@ impl<'env, F: Float> VariableEnvironment<F> {
         Self {
             name_to_id: FxHashMap::default(),
             array_list: Vec::new(),
+            slices: FxHashMap::default(),
+            ties: FxHashMap::default(),
+            #[cfg(feature = "f16")]
+            half: FxHashMap::default(),
+            state_namespaces: FxHashSet::default(),
+            global_step: Cell::new(0),
         }
     }
 
@@ -552,7 +686,7 @@ impl<'env, F: Float> VariableEnvironment<F> {
         self.array_list
             .iter()
             .enumerate()
-            .map(|(i, v)| (VariableID::from(i), v))
+            .map(|(i, v)| (VariableID::from(i), &**v))
     }
 
     /// Saves the current VariableEnvironment to storage.
@@ -573,7 +707,7 @@ impl<'env, F: Float> VariableEnvironment<F> {
         Ok(ret)
     }
 
-    fn load_internal<T>(
+    pub(crate) fn load_internal<T>(
         env: DeserializedVariableEnvironment<T>,
     ) -> Result<VariableEnvironment<T>, Box<dyn Error>> {
         let name_to_id: FxHashMap<FullName, VariableID> = env
@@ -591,21 +725,53 @@ impl<'env, F: Float> VariableEnvironment<F> {
             })
             .collect();
 
-        Ok(VariableEnvironment {
-            array_list: env.array_list,
+        let mut ret = VariableEnvironment {
+            array_list: env.array_list.into_iter().map(Rc::new).collect(),
             name_to_id,
-        })
+            slices: env.slices.into_iter().collect(),
+            ties: env.ties.into_iter().collect(),
+            #[cfg(feature = "f16")]
+            half: env
+                .half
+                .into_iter()
+                .map(|(vid, bits)| (vid, RefCell::new(bits.mapv(half::f16::from_bits))))
+                .collect(),
+            state_namespaces: env.state_namespaces.into_iter().collect(),
+            global_step: Cell::new(env.global_step),
+        };
+        share_tied_arrays(&mut ret);
+        Ok(ret)
     }
 
-    fn prepare_for_serde(&self) -> SerializableVariableEnvironment<F> {
+    pub(crate) fn prepare_for_serde(&self) -> SerializableVariableEnvironment<F> {
         let name_to_id: FxHashMap<String, VariableID> = self
             .name_to_id
             .iter()
             .map(|(fullname, vid)| (fullname.to_string(), *vid))
             .collect();
+        let mut slices: Vec<_> = self.slices.iter().map(|(&k, v)| (k, v.clone())).collect();
+        slices.sort_by_key(|&(vid, _)| vid.0);
+        let mut ties: Vec<_> = self.ties.iter().map(|(&k, v)| (k, v.clone())).collect();
+        ties.sort_by_key(|&(vid, _)| vid.0);
+        #[cfg(feature = "f16")]
+        let mut half: Vec<_> = self
+            .half
+            .iter()
+            .map(|(&vid, arr)| (vid, arr.borrow().mapv(half::f16::to_bits)))
+            .collect();
+        #[cfg(feature = "f16")]
+        half.sort_by_key(|&(vid, _)| vid.0);
+        let mut state_namespaces: Vec<_> = self.state_namespaces.iter().cloned().collect();
+        state_namespaces.sort();
         SerializableVariableEnvironment {
-            array_list: &self.array_list,
+            array_list: self.array_list.iter().map(|v| &**v).collect(),
             name_to_id,
+            slices,
+            ties,
+            #[cfg(feature = "f16")]
+            half,
+            state_namespaces,
+            global_step: self.global_step.get(),
         }
     }
 
@@ -619,6 +785,294 @@ impl<'env, F: Float> VariableEnvironment<F> {
         register_variable(v, DEFAULT_NAMESPACE_ID, Uuid::new_v4().to_string(), self)
     }
 
+    /// Registers a variable that is the block `ranges` of the variable `parent`, with the
+    /// *default* namespace.
+    ///
+    /// `ranges` selects the block along the leading axes of `parent`; the remaining axes are
+    /// taken whole. The slice shares the memory of `parent`: updating it, e.g. with an
+    /// optimizer, updates that block of `parent` in place and leaves the rest untouched.
+    /// The slice has no array of its own, so [VariableEnvironment::get_array_by_id] returns
+    /// an empty array for it; read it by evaluating its variable tensor.
+    ///
+    /// Panics if `ranges` are out of the bounds of `parent`, if `parent` is a slice itself,
+    /// or if the block overlaps another slice of `parent`: ops updating their inputs in place
+    /// rely on the arrays they write to not aliasing each other. For the same reason, an
+    /// evaluation updating the slice in place can't use `parent`, and one updating `parent`
+    /// can't use the slice: it panics.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut env = ag::VariableEnvironment::<f64>::new();
+    /// let w = env.set(ag::ndarray_ext::zeros(&[4, 3]));
+    /// let head = env.slice(w, &[2..4]);
+    ///
+    /// env.run(|g| {
+    ///     let head = g.variable(head);
+    ///     T::assign(head, T::ones(&[2, 3], g)).eval(g).unwrap();
+    ///     let w = g.variable(w).eval(g).unwrap();
+    ///     assert_eq!(w.sum(), 6.);
+    ///     assert_eq!(w[[1, 0]], 0.);
+    /// });
+    /// ```
+    pub fn slice(&'env mut self, parent: VariableID, ranges: &[Range<usize>]) -> VariableID {
+        assert!(
+            !self.slices.contains_key(&parent),
+            "slice: variable {} is a slice itself",
+            parent
+        );
+        assert!(!self.is_half(parent), "slice: variable {} is stored as f16", parent);
+        let shape = self.array_list[parent.0].borrow().shape().to_vec();
+        let in_bounds = |(r, &n): (&Range<usize>, &usize)| r.start < r.end && r.end <= n;
+        assert!(
+            ranges.len() <= shape.len() && ranges.iter().zip(&shape).all(in_bounds),
+            "slice: {:?} is out of the bounds of variable {} of shape {:?}",
+            ranges,
+            parent,
+            shape
+        );
+        let slice = VariableSlice {
+            parent,
+            ranges: ranges.iter().map(|r| (r.start, r.end)).collect(),
+        };
+        for (vid, other) in &self.slices {
+            if other.parent == parent && slice.overlaps(other, &shape) {
+                panic!(
+                    "slice: {:?} overlaps the slice {} of variable {}",
+                    ranges, vid, parent
+                );
+            }
+        }
+        let empty = NdArray::zeros(ndarray::IxDyn(&[0]));
+        let name = Uuid::new_v4().to_string();
+        let vid = register_variable(empty, DEFAULT_NAMESPACE_ID, name, self);
+        self.slices.insert(vid, slice);
+        vid
+    }
+
+    /// Registers the variable `vid` of `other` with the *default* namespace, sharing its
+    /// array instead of copying it.
+    ///
+    /// Both envs then hold the same array, e.g. for a teacher and a student model: an update
+    /// through a context of either, such as an optimizer step, is seen by the other.
+    /// Two things follow from the aliasing:
+    /// - Each context keeps its own caches, so outputs cached by
+    ///   [Context::set_incremental](crate::Context::set_incremental) or
+    ///   [Tensor::pin](crate::Tensor::pin) in one context go stale when the other updates
+    ///   the array.
+    /// - Ops updating their inputs in place write to the shared array directly, so don't run
+    ///   them in one context while an evaluation of the other is reading it.
+    ///
+    /// Panics if `vid` is a [slice](VariableEnvironment::slice), or if its array is already
+    /// in this env.
+    ///
+    /// ```
+    /// use autograd as ag;
+    /// use ag::prelude::*;
+    /// use ag::tensor_ops as T;
+    ///
+    /// let mut main = ag::VariableEnvironment::<f64>::new();
+    /// let w = main.set(ag::ndarray_ext::zeros(&[2]));
+    /// let mut other = ag::VariableEnvironment::new();
+    /// let w2 = other.share(&main, w);
+    ///
+    /// main.run(|g| T::assign(g.variable(w), T::ones(&[2], g)).eval(g).unwrap());
+    /// other.run(|g| assert_eq!(g.variable(w2).eval(g).unwrap().sum(), 2.));
+    /// ```
+    pub fn share(&mut self, other: &VariableEnvironment<F>, vid: VariableID) -> VariableID {
+        self.share_as(other, vid, DEFAULT_NAMESPACE_ID, Uuid::new_v4().to_string())
+    }
+
+    /// Same as [VariableEnvironment::share] for all the variables of the namespace
+    /// `namespace_id` of `other`, which keep their names.
+    ///
+    /// Returns the ids of the shared variables in this env, ordered by their ids in `other`.
+    pub fn share_namespace(
+        &mut self,
+        other: &VariableEnvironment<F>,
+        namespace_id: &'static str,
+    ) -> Vec<VariableID> {
+        let mut vars: Vec<_> = other
+            .name_to_id
+            .iter()
+            .filter(|(name, _)| name.namespace_id == namespace_id)
+            .map(|(name, &vid)| (vid, name.variable_name.clone()))
+            .collect();
+        vars.sort_by_key(|&(vid, _)| vid.0);
+        vars.into_iter()
+            .map(|(vid, name)| self.share_as(other, vid, namespace_id, name))
+            .collect()
+    }
+
+    fn share_as(
+        &mut self,
+        other: &VariableEnvironment<F>,
+        vid: VariableID,
+        namespace_id: &'static str,
+        variable_name: String,
+    ) -> VariableID {
+        assert!(
+            !other.slices.contains_key(&vid),
+            "share: variable {} is a slice",
+            vid
+        );
+        assert!(!other.is_half(vid), "share: variable {} is stored as f16", vid);
+        let array = &other.array_list[vid.0];
+        assert!(
+            !self.array_list.iter().any(|a| Rc::ptr_eq(a, array)),
+            "share: the array of variable {} is already in this env",
+            vid
+        );
+        let next_id = self.array_list.len().into();
+        let name = FullName::new(namespace_id, variable_name);
+        self.name_to_id.insert(name, next_id);
+        self.array_list.push(array.clone());
+        next_id
+    }
+
+    // The variables sharing memory with `vid`: its parent if it's a slice, its slices otherwise.
+    pub(crate) fn aliases(&self, vid: VariableID) -> Vec<VariableID> {
+        if let Some(slice) = self.slices.get(&vid) {
+            vec![slice.parent]
+        } else {
+            let mut ret: Vec<_> = self
+                .slices
+                .iter()
+                .filter(|(_, s)| s.parent == vid)
+                .map(|(&k, _)| k)
+                .collect();
+            ret.sort_by_key(|k| k.0);
+            ret
+        }
+    }
+
+    // Name of the variable `vid` in its namespace, if it was registered with one
+    pub(crate) fn variable_name(&self, vid: VariableID) -> Option<&str> {
+        self.name_to_id
+            .iter()
+            .find(|&(_, &id)| id == vid)
+            .map(|(fullname, _)| fullname.variable_name.deref())
+    }
+
+    // Namespace and name of the variable `vid`, if it was registered with a name
+    pub(crate) fn variable_full_name(&self, vid: VariableID) -> Option<(&str, &str)> {
+        self.name_to_id
+            .iter()
+            .find(|&(_, &id)| id == vid)
+            .map(|(fullname, _)| (fullname.namespace_id.deref(), fullname.variable_name.deref()))
+    }
+
+    // Shape of the variable `vid`
+    pub(crate) fn variable_shape(&self, vid: VariableID) -> Vec<usize> {
+        assert!(vid.0 < self.array_list.len(), "variable array not found");
+        #[cfg(feature = "f16")]
+        if let Some(half) = self.half.get(&vid) {
+            return half.borrow().shape().to_vec();
+        }
+        self.as_view(vid).shape().to_vec()
+    }
+
+    // The variable `vid` upcast from f16, if it's stored as f16 (see `half_storage`)
+    #[cfg(feature = "f16")]
+    pub(crate) fn upcast_half(&self, vid: VariableID) -> Option<NdArray<F>> {
+        let half = self.half.get(&vid)?;
+        let ret = half.borrow().mapv(|x| F::from(x.to_f32()).unwrap());
+        Some(ret)
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    pub(crate) fn upcast_half(&self, _: VariableID) -> Option<NdArray<F>> {
+        None
+    }
+
+    // Overwrites the variable `vid` with `value` rounded to f16, if it's stored as f16.
+    // Returns false otherwise.
+    #[cfg(feature = "f16")]
+    pub(crate) fn assign_half(&self, vid: VariableID, value: &NdArrayView<F>) -> bool {
+        match self.half.get(&vid) {
+            Some(half) => {
+                *half.borrow_mut() = value.mapv(|x| half::f16::from_f32(x.to_f32().unwrap()));
+                if self.has_master_weights(vid) {
+                    self.as_view_mut(vid).assign(value);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    pub(crate) fn assign_half(&self, _: VariableID, _: &NdArrayView<F>) -> bool {
+        false
+    }
+
+    #[cfg(feature = "f16")]
+    #[inline]
+    pub(crate) fn is_half(&self, vid: VariableID) -> bool {
+        self.half.contains_key(&vid)
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    pub(crate) fn is_half(&self, _: VariableID) -> bool {
+        false
+    }
+
+    // Whether the variable `vid` stored as f16 has its f32 master weights in `array_list`
+    #[cfg(feature = "f16")]
+    pub(crate) fn has_master_weights(&self, vid: VariableID) -> bool {
+        match self.half.get(&vid) {
+            Some(half) => self.array_list[vid.0].borrow().shape() == half.borrow().shape(),
+            None => false,
+        }
+    }
+
+    // Copies the variable `vid` stored as f16 to its f32 master weights, the array updated in
+    // place by the optimizers, unless it has them already.
+    #[cfg(feature = "f16")]
+    pub(crate) fn init_master_weights(&self, vid: VariableID) {
+        if !self.has_master_weights(vid) {
+            *self.array_list[vid.0].borrow_mut() = self.upcast_half(vid).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    pub(crate) fn init_master_weights(&self, _: VariableID) {}
+
+    // Rounds the master weights of the variable `vid` to its f16 array.
+    #[cfg(feature = "f16")]
+    pub(crate) fn round_master_weights(&self, vid: VariableID) {
+        let master = self.array_list[vid.0].borrow();
+        *self.half[&vid].borrow_mut() = master.mapv(|x| half::f16::from_f32(x.to_f32().unwrap()));
+    }
+
+    #[cfg(not(feature = "f16"))]
+    #[inline]
+    pub(crate) fn round_master_weights(&self, _: VariableID) {}
+
+    // Marks `namespace_id` as holding the state of an optimizer, which `Context::gc` keeps
+    pub(crate) fn add_state_namespace(&mut self, namespace_id: &str) {
+        self.state_namespaces.insert(namespace_id.to_owned());
+    }
+
+    /// Registers the running mean (zeros) and variance (ones) of `shape` used by
+    /// [batch_norm_with_stats](crate::tensor_ops::batch_norm_with_stats) under `name`.
+    ///
+    /// They're ordinary variables named `"{name}/mean"` and `"{name}/var"` in
+    /// [RUNNING_STATS_NAMESPACE_ID], so `save` and `load` keep them along with the parameters.
+    pub fn add_running_stats(&mut self, name: &str, shape: &[usize]) {
+        let ns = RUNNING_STATS_NAMESPACE_ID;
+        let mean = crate::ndarray_ext::zeros(shape);
+        register_variable(mean, ns, format!("{}/mean", name), self);
+        let var = crate::ndarray_ext::ones(shape);
+        register_variable(var, ns, format!("{}/var", name), self);
+    }
+
     /// Prepares a slot for the *default* namespace to register a variable array
     pub fn name<S: Into<String>>(
         &'env mut self,
@@ -676,7 +1130,7 @@ impl<'env, F: Float> VariableEnvironment<F> {
     /// `VariableID` is returned by the `*Slot::set`.
     #[inline]
     pub fn get_array_by_id(&self, vid: VariableID) -> Option<&RefCell<NdArray<F>>> {
-        self.array_list.get(vid.0)
+        self.array_list.get(vid.0).map(|v| &**v)
     }
 
     /// Creates a computation graph associated with this `VariableEnvironment`.
@@ -686,24 +1140,40 @@ impl<'env, F: Float> VariableEnvironment<F> {
     where
         FN: FnOnce(&mut Context<'env, F>) -> R,
     {
-        let g = Graph {
-            node_set: RefCell::new(Vec::with_capacity(256)),
-            variable2node: RefCell::new(FxHashMap::default()),
-        };
+        let mut graph = Graph::new(256);
+        graph.ties = self.ties.clone();
         let mut c = Context {
             var_env_ref: self,
-            graph: g,
+            graph,
         };
         f(&mut c)
     }
 
     pub(crate) fn as_view(&self, vid: VariableID) -> NdArrayView<F> {
+        if let Some(slice) = self.slices.get(&vid) {
+            let mut ret = self.as_view(slice.parent);
+            let shape = ret.shape().to_vec();
+            ret.slice_each_axis_inplace(|ax| {
+                let (start, end) = slice.range(ax.axis.index(), &shape);
+                ndarray::Slice::from(start..end)
+            });
+            return ret;
+        }
         unsafe {
             self.array_list[vid.0].borrow().raw_view().clone().deref_into_view()
         }
     }
 
     pub(crate) fn as_view_mut(&self, vid: VariableID) -> NdArrayViewMut<F> {
+        if let Some(slice) = self.slices.get(&vid) {
+            let mut ret = self.as_view_mut(slice.parent);
+            let shape = ret.shape().to_vec();
+            ret.slice_each_axis_inplace(|ax| {
+                let (start, end) = slice.range(ax.axis.index(), &shape);
+                ndarray::Slice::from(start..end)
+            });
+            return ret;
+        }
         unsafe {
             self.array_list[vid.0].borrow_mut().raw_view_mut().clone().deref_into_view_mut()
         }
@@ -721,6 +1191,15 @@ impl<'t, 'g, F: Float> Graph<F> {
         if let Some(tid) = tid {
             // use existing tensor
             self.tensor(tid)
+        } else if let Some(tie) = self.ties.get(&vid) {
+            // the variable (or its transpose) it's tied to
+            let to = self.variable_by_id(tie.to);
+            if !tie.transposed {
+                return to;
+            }
+            let transposed = crate::tensor_ops::transpose(to, &[1, 0]);
+            self.variable2node.borrow_mut().insert(vid, transposed.id);
+            transposed
         } else {
             // allocate a new tensor
             let allocated = Tensor::builder(self)
@@ -757,16 +1236,23 @@ impl<'t, 'g, F: Float> Graph<F> {
 
     /// Get tensors with their variable ids.
     ///
+    /// The variables tied to another one by [nn::tie_weights](crate::nn::tie_weights) are left
+    /// out.
     /// See `VariableEnvironment` for the usages.
     pub fn var_tensors_by_id<'e: 'g>(
         &'g self,
         env: &'e VariableEnvironment<F>,
     ) -> impl Iterator<Item = (VariableID, Tensor<'g, F>)> {
-        (0..env.array_list.len()).map(move |vid| (vid.into(), self.variable_by_id(vid.into())))
+        (0..env.array_list.len())
+            .map(VariableID::from)
+            .filter(move |&vid| !env.ties.contains_key(&vid))
+            .map(move |vid| (vid, self.variable_by_id(vid)))
     }
 
     /// Get tensors and their variable names in the specified namespace.
     ///
+    /// The variables tied to another one by [nn::tie_weights](crate::nn::tie_weights) are left
+    /// out.
     /// See `VariableEnvironment` for the usages.
     pub fn var_tensors_by_name<'ns, 'e: 'g>(
         &'g self,
@@ -774,7 +1260,8 @@ impl<'t, 'g, F: Float> Graph<F> {
     ) -> impl Iterator<Item = (&'ns str, Tensor<'g, F>)> {
         ns.env().name_to_id.iter().filter_map(move |ent| {
             // filter out other namespaces
-            if &ent.0.namespace_id == ns.name() {
+            let env = ns.env();
+            if &ent.0.namespace_id == ns.name() && !env.ties.contains_key(ent.1) {
                 Some((ent.0.variable_name.deref(), self.variable_by_id(*ent.1)))
             } else {
                 None
@@ -823,6 +1310,7 @@ fn save_and_load() {
     let mut env = VariableEnvironment::new();
     env.slot().name("a").set(rng.standard_normal(&[2, 3]));
     env.slot().name("b").set(rng.standard_normal(&[2, 3]));
+    env.global_step.set(7);
 
     // save
     env.save(&path).unwrap();
@@ -839,6 +1327,7 @@ fn save_and_load() {
         }
 
         assert_eq!(env.name_to_id, loaded_env.name_to_id);
+        assert_eq!(loaded_env.global_step.get(), 7);
     }
 }
 
@@ -2,6 +2,7 @@ mod test_array_gen;
 mod test_binary_ops_eval;
 mod test_binary_ops_grad;
 mod test_core;
+mod test_half;
 mod test_optimizers;
 mod test_tensor_ops_eval;
 mod test_tensor_ops_grad;
@@ -55,3 +55,65 @@ fn slice_negative() {
         assert_eq!(b.eval(g).unwrap().shape(), &[2, 2]);
     });
 }
+
+#[test]
+fn slice_out_of_bounds() {
+    ag::run(|g| {
+        let ref a: ag::Tensor<f32> = T::zeros(&[4, 4], g);
+        let ref b = T::exp(T::slice(a, &[0, 0], &[-1, 5])); // the second axis has only 4 elements
+        let ref c = T::reduce_sum(b, &[0], false);
+        let err = c.eval(g).unwrap_err();
+        match &err {
+            ag::EvalError::OpError {
+                error: ag::op::OpError::OutOfBounds(_),
+                trace,
+            } => assert_eq!(trace, &["Slice", "Exp", "ReduceSum"]),
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert_eq!(
+            err.to_string(),
+            "slice: Slice { start: 0, end: Some(5), step: 1 } is out of bounds for shape [4, 4]\n    \
+             at Slice\n    at Exp\n    at ReduceSum"
+        );
+    });
+}
+
+#[test]
+fn broadcast_shapes() {
+    use ag::ndarray_ext::broadcast_shapes;
+    assert_eq!(broadcast_shapes(&[3, 1], &[1, 4]), Ok(vec![3, 4]));
+    assert_eq!(broadcast_shapes(&[2, 3, 4], &[4]), Ok(vec![2, 3, 4]));
+    assert_eq!(broadcast_shapes(&[], &[2, 2]), Ok(vec![2, 2]));
+    assert_eq!(broadcast_shapes(&[5, 1, 1], &[1, 6]), Ok(vec![5, 1, 6]));
+    assert_eq!(broadcast_shapes(&[0, 2], &[1, 2]), Ok(vec![0, 2]));
+
+    for (a, b) in [(&[3, 1][..], &[2, 4][..]), (&[3], &[4]), (&[2, 3], &[3, 2])] {
+        assert_eq!(
+            broadcast_shapes(a, b),
+            Err(ag::op::OpError::IncompatibleShape(format!(
+                "cannot broadcast {:?} and {:?}",
+                a, b
+            )))
+        );
+    }
+}
+
+#[test]
+fn broadcast_error() {
+    ag::run(|g| {
+        let a: ag::Tensor<f32> = T::zeros(&[3, 1], g);
+        let b = T::ones(&[2, 4], g);
+        for y in [a + b, a - b, a * b, a / b, T::maximum(a, b)] {
+            match y.eval(g) {
+                Err(ag::EvalError::OpError {
+                    error: ag::op::OpError::IncompatibleShape(msg),
+                    ..
+                }) => assert_eq!(msg, "cannot broadcast [3, 1] and [2, 4]"),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        // broadcasts both sides
+        let c = T::maximum(T::ones(&[1, 4], g), a);
+        assert_eq!(c.eval(g).unwrap().shape(), &[3, 4]);
+    });
+}
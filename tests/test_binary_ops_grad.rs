@@ -478,3 +478,43 @@ fn scalar_tensor_div_g3() {
         );
     });
 }
+
+#[test]
+fn safe_div_near_zero() {
+    let mut env = ag::VariableEnvironment::new();
+    let a = env.slot().set(array![1., 1., 1., -2., 6.]);
+    let b = env.slot().set(array![0., -1e-12, 1e-12, 1e-300, 3.]);
+    env.run(|graph| {
+        let (a, b) = (graph.variable(a), graph.variable(b));
+        let y = T::safe_div(a, b, 1e-3);
+        let grads = T::grad(&[y], &[a, b]);
+        let y = y.eval(graph).unwrap();
+        assert_eq!(y, array![1e3, -1e3, 1e3, -2e3, 2.].into_dyn());
+        // the clamped denominators are constants; the last one is a plain division
+        let (ga, gb) = (grads[0].eval(graph).unwrap(), grads[1].eval(graph).unwrap());
+        assert_eq!(ga, array![1e3, -1e3, 1e3, 1e3, 1. / 3.].into_dyn());
+        assert_eq!(gb, array![0., 0., 0., 0., -6. / 9.].into_dyn());
+        assert!(y.iter().chain(&ga).chain(&gb).all(|v: &f64| v.is_finite()));
+    });
+}
+
+#[test]
+fn safe_div() {
+    let mut env = ag::VariableEnvironment::new();
+    let a = env.slot().set(array![0.5, -1., 2.]);
+    let b = env.slot().set(array![-0.3, 0.7, 1.5]);
+    env.run(|graph| {
+        let (a, b) = (graph.variable(a), graph.variable(b));
+        let y = T::safe_div(a, b, 1e-2);
+        let g = T::grad(&[y], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            y,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
@@ -1,7 +1,9 @@
 extern crate autograd as ag;
 extern crate ndarray;
 
+use ag::prelude::*;
 use ag::tensor_ops as T;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 struct MultiOutputOp;
 
@@ -35,6 +37,86 @@ fn test_nth_tensor() {
     });
 }
 
+// (2x, x^2)
+struct DoubleAndSquare;
+
+impl<F: ag::Float> ag::op::Op<F> for DoubleAndSquare {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<F>) -> Result<(), ag::op::OpError> {
+        let x = ctx.input(0);
+        let two = F::from(2.).unwrap();
+        ctx.append_output(x.mapv(|a| a * two));
+        ctx.append_output(x.mapv(|a| a * a));
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut ag::op::GradientContext<F>) {
+        let x = ctx.input(0);
+        let two = T::scalar(F::from(2.).unwrap(), ctx.graph());
+        let gx = (ctx.output_grad_at(0) + ctx.output_grad_at(1) * x) * two;
+        ctx.append_input_grad(Some(gx));
+    }
+}
+
+// Square with a wrong gradient (3x instead of 2x)
+struct BadSquare;
+
+impl ag::op::Op<f64> for BadSquare {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<f64>) -> Result<(), ag::op::OpError> {
+        let x = ctx.input(0);
+        ctx.append_output(x.mapv(|a| a * a));
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut ag::op::GradientContext<f64>) {
+        let gx = ctx.output_grad() * ctx.input(0) * 3.;
+        ctx.append_input_grad(Some(gx));
+    }
+}
+
+#[test]
+fn test_multi_output_grad() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ag::ndarray::arr1(&[1., -2., 0.5]));
+    env.run(|g| {
+        let x = g.variable(v);
+        let a = ag::Tensor::builder(g)
+            .append_input(x, false)
+            .build(DoubleAndSquare);
+        let b = T::nth_tensor(a, 1);
+        assert_eq!(a.eval(g), Ok(ag::ndarray::arr1(&[2., -4., 1.]).into_dyn()));
+        assert_eq!(b.eval(g), Ok(ag::ndarray::arr1(&[1., 4., 0.25]).into_dyn()));
+        assert!(T::nth_tensor(a, 2).eval(g).is_err());
+
+        // each output feeds its own branch
+        let z = T::reduce_sum(a * 3. + T::sin(b), &[0], false);
+        let gx = T::grad(&[z], &[x]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            gx.as_slice(),
+            &[x],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            g,
+        );
+        let only_b = T::grad(&[T::reduce_sum(b, &[0], false)], &[x])[0];
+        assert_eq!(
+            only_b.eval(g),
+            Ok(ag::ndarray::arr1(&[2., -4., 1.]).into_dyn())
+        );
+        let only_a = T::grad(&[T::reduce_sum(a, &[0], false)], &[x])[0];
+        assert_eq!(
+            only_a.eval(g),
+            Ok(ag::ndarray::arr1(&[2., 2., 2.]).into_dyn())
+        );
+
+        // the gradients of an intermediate tensor are summed only once
+        let y = T::reduce_sum(b * 2. + b * 3., &[0], false);
+        let gb = T::grad(&[y], &[b])[0];
+        assert_eq!(gb.eval(g), Ok(ag::ndarray::arr1(&[5., 5., 5.]).into_dyn()));
+    });
+}
+
 #[test]
 fn test_hook() {
     let ctx = ag::VariableEnvironment::new();
@@ -73,10 +155,2793 @@ fn test_hook() {
 fn test_too_many_nodes() {
     let ctx = ag::VariableEnvironment::new();
     ctx.run(|g| {
-        for _ in 0..10000 {
+        for _ in 0..20000 {
             let x = g.placeholder("x", &[3]);
             let z = 2.0 * x / 2.0 / 2.0;
             T::grad(&[z], &[x])[0];
         }
     });
 }
+
+#[test]
+fn test_max_depth() {
+    ag::run(|g| {
+        let mut x: ag::Tensor<f32> = T::zeros(&[2], g);
+        for _ in 0..20_000 {
+            x = T::neg(x);
+        }
+        let shallow = T::exp(T::zeros(&[2], g));
+
+        g.set_max_depth(10_000);
+        let ret = g.evaluator().extend(&[x, shallow]).run();
+        assert_eq!(
+            ret[0],
+            Err(ag::EvalError::TooDeep {
+                depth: 20_001,
+                max_depth: 10_000
+            })
+        );
+        assert_eq!(ret[1], Ok(ag::ndarray::arr1(&[1., 1.]).into_dyn()));
+
+        g.set_max_depth(usize::MAX);
+        assert!(x.eval(g).is_ok());
+    });
+}
+
+#[test]
+fn test_eval_keep() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[2]);
+        let h = T::square(x);
+        let y = T::reduce_sum(h, &[0], false);
+
+        let ret = g
+            .evaluator()
+            .push(y)
+            .feed(x, ag::ndarray::arr1(&[1., 2.]).view())
+            .run_keep();
+        assert_eq!(ret[0], Ok(ag::ndarray::arr0(5.).into_dyn()));
+        assert_eq!(
+            *g.peek(&h).unwrap(),
+            ag::ndarray::arr1(&[1., 4.]).into_dyn()
+        );
+        assert_eq!(*g.peek(&y).unwrap(), ag::ndarray::arr0(5.).into_dyn());
+        assert!(g.peek(&x).is_none());
+        assert_eq!(g.memory_report().kept_bytes(), (2 + 1 + 1) * 8); // h, y and the reduction axes
+
+        // plain evaluations leave the cache as is
+        let z = T::neg(h);
+        g.evaluator()
+            .push(z)
+            .feed(x, ag::ndarray::arr1(&[1., 2.]).view())
+            .run();
+        assert!(g.peek(&z).is_none());
+        assert!(g.peek(&h).is_some());
+
+        g.clear_kept();
+        assert!(g.peek(&h).is_none());
+    });
+}
+
+#[test]
+fn test_snapshot() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[2]);
+        let h = T::sigmoid(x);
+        let y = T::reduce_sum(h, &[0], false);
+        let x_val = ag::ndarray::arr1(&[-1., 2.]);
+
+        assert!(g.snapshot(&h).is_none());
+        let ret = g
+            .evaluator()
+            .push(y)
+            .push(h)
+            .feed(x, x_val.view())
+            .run_keep();
+        let saved = g.snapshot(&h).unwrap();
+        // the cache isn't borrowed: it can be cleared while `saved` lives
+        g.clear_kept();
+        assert!(g.snapshot(&h).is_none());
+        assert_eq!(ret[1].as_ref().unwrap(), &saved);
+        assert!(g.snapshot(&x).is_none());
+    });
+}
+
+#[test]
+fn test_eval_named() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(ag::ndarray::arr1(&[1., 2.]), g);
+        let h = T::square(x);
+        let y = T::reduce_sum(h, &[0], false);
+
+        let ret = g
+            .eval_named(&[("loss", &y), ("h", &h), ("squares", &h)])
+            .unwrap();
+        let mut keys: Vec<_> = ret.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["h", "loss", "squares"]);
+        assert_eq!(ret["loss"], ag::ndarray::arr0(5.).into_dyn());
+        assert_eq!(ret["h"], ag::ndarray::arr1(&[1., 4.]).into_dyn());
+        assert_eq!(ret["squares"], ret["h"]);
+
+        // out of bounds, which the shape of `h` tells only at evaluation
+        let bad = T::slice(h, &[5], &[6]);
+        assert!(g.eval_named(&[("y", &y), ("bad", &bad)]).is_err());
+    });
+}
+
+#[test]
+fn test_eval_views() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(ag::ndarray::arr1(&[1., 2.]), g);
+        let h = T::square(x);
+        let y = T::reduce_sum(h, &[0], false);
+
+        let views = g.eval_views(&[&h, &y, &h]).unwrap();
+        assert_eq!(views.len(), 3);
+        assert_eq!(views.get(0), ag::ndarray::arr1(&[1., 4.]).into_dyn());
+        assert_eq!(views.get(1), ag::ndarray::arr0(5.).into_dyn());
+        // both requests of `h` view the one array computed
+        assert_eq!(views.get(0).as_ptr(), views.get(2).as_ptr());
+        assert_eq!(
+            g.op_call_counts()
+                .iter()
+                .find(|c| c.0 == "Square")
+                .unwrap()
+                .1,
+            1
+        );
+        let shapes: Vec<_> = views.iter().map(|v| v.shape().to_vec()).collect();
+        assert_eq!(shapes, vec![vec![2], vec![], vec![2]]);
+
+        // out of bounds, which the shape of `h` tells only at evaluation
+        let bad = T::slice(h, &[5], &[6]);
+        assert!(g.eval_views(&[&y, &bad]).is_err());
+    });
+}
+
+#[test]
+fn test_run_stream() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let w = g.placeholder("w", &[2, 3]);
+        let y = T::sigmoid(T::matmul(x, w));
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let w_value = rng.standard_normal(&[2, 3]);
+        let batches = [rng.standard_normal(&[4, 2]), rng.standard_normal(&[3, 2])];
+
+        let streamed: Vec<_> = g
+            .evaluator()
+            .push(y)
+            .feed(w, w_value.view())
+            .run_stream(batches.iter().map(|batch| {
+                let mut feeder = ag::Feeder::new();
+                feeder.push(x, batch.view());
+                feeder
+            }))
+            .collect();
+
+        assert_eq!(streamed.len(), 2);
+        for (batch, ret) in batches.iter().zip(streamed) {
+            let expected = g
+                .evaluator()
+                .push(y)
+                .feed(x, batch.view())
+                .feed(w, w_value.view())
+                .run();
+            assert_eq!(ret, expected);
+        }
+    });
+}
+
+#[test]
+fn test_run_stream_recycles_outputs() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let y = T::reduce_sum(T::tanh(T::exp(x) * 2.) + 1., &[1], false);
+        let batches = vec![ag::ndarray::Array2::<f64>::zeros((4, 3)); 3];
+
+        let mut allocations = Vec::new();
+        g.evaluator()
+            .push(y)
+            .run_stream(batches.iter().map(|batch| {
+                let mut feeder = ag::Feeder::new();
+                feeder.push(x, batch.view());
+                feeder
+            }))
+            .for_each(|ret| {
+                assert!(ret[0].is_ok());
+                allocations.push(g.allocation_count());
+                g.reset_counters();
+            });
+
+        // the next batches reuse the arrays of the first one
+        assert!(allocations[1] < allocations[0], "{:?}", allocations);
+        assert_eq!(allocations[1], allocations[2]);
+        // the pool of the context stays off
+        assert_eq!(g.buffer_pool_stats(), ag::PoolStats::default());
+    });
+}
+
+#[test]
+fn test_deterministic_reductions() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+        let x_value = rng.standard_normal(&[300, 700]);
+        let x = g.placeholder("x", &[300, 700]);
+        let xt = T::transpose(x, &[1, 0]);
+        let targets = [
+            T::sum_all(x),
+            T::sum_all(T::transpose(xt, &[1, 0])),
+            T::reduce_sum(x, &[0], false),
+            T::reduce_sum(xt, &[1], false),
+            T::reduce_mean(x, &[0, 1], false),
+        ];
+        let run = || {
+            g.evaluator()
+                .extend(&targets)
+                .feed(x, x_value.view())
+                .run()
+                .into_iter()
+                .map(|ret| ret.unwrap())
+                .collect::<Vec<_>>()
+        };
+        let to_bits = |arr: &ag::NdArray<f32>| arr.iter().map(|a| a.to_bits()).collect::<Vec<_>>();
+
+        let fast = run();
+        g.set_deterministic(true);
+        let first = run();
+        let second = run();
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(to_bits(a), to_bits(b));
+        }
+        assert_eq!(to_bits(&first[0]), to_bits(&first[1]));
+        assert_eq!(to_bits(&first[2]), to_bits(&first[3]));
+        for (a, b) in first.iter().zip(&fast) {
+            assert!(a
+                .iter()
+                .zip(b)
+                .all(|(a, b)| (a - b).abs() <= 1e-4 * (1. + b.abs())));
+        }
+    });
+}
+
+#[test]
+fn test_reference_mode() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        // small integers, so that every product and partial sum is exact
+        let a = ag::ndarray::Array::from_shape_fn((4, 5, 6), |(b, i, k)| {
+            ((b * 7 + i * 3 + k * 5) % 11) as f64 - 5.
+        });
+        let b = ag::ndarray::Array::from_shape_fn((4, 6, 3), |(b, k, j)| {
+            ((b * 2 + k * 7 + j * 3) % 9) as f64 - 4.
+        });
+        let mut expected = ag::ndarray::Array3::<f64>::zeros((4, 5, 3));
+        for ((b_, i, j), e) in expected.indexed_iter_mut() {
+            *e = (0..6).map(|k| a[[b_, i, k]] * b[[b_, k, j]]).sum();
+        }
+
+        assert!(!g.is_reference_mode());
+        g.set_reference_mode(true);
+        assert!(g.is_reference_mode());
+        let x = T::convert_to_tensor(a.clone(), g);
+        let y = T::convert_to_tensor(b.clone(), g);
+        let z = T::batch_matmul(x, y);
+        assert_eq!(z.eval(g).unwrap(), expected.clone().into_dyn());
+
+        let x0 = T::convert_to_tensor(a.index_axis(ag::ndarray::Axis(0), 1).to_owned(), g);
+        let y0 = T::convert_to_tensor(b.index_axis(ag::ndarray::Axis(0), 1).to_owned(), g);
+        let expected0 = expected
+            .index_axis(ag::ndarray::Axis(0), 1)
+            .to_owned()
+            .into_dyn();
+        assert_eq!(T::matmul(x0, y0).eval(g).unwrap(), expected0);
+        assert_eq!(T::matmul_tiled(x0, y0, 2).eval(g).unwrap(), expected0);
+    });
+}
+
+static NUM_COMPUTED: [AtomicUsize; 6] = [
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+    AtomicUsize::new(0),
+];
+
+fn num_computed(i: usize) -> usize {
+    NUM_COMPUTED[i].load(Ordering::SeqCst)
+}
+
+// Identity op counting its computations in `NUM_COMPUTED[self.0]`
+struct Counted(usize);
+
+impl ag::op::Op<f64> for Counted {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<f64>) -> Result<(), ag::op::OpError> {
+        NUM_COMPUTED[self.0].fetch_add(1, Ordering::SeqCst);
+        let x = ctx.input(0).to_owned();
+        ctx.append_output(x);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut ag::op::GradientContext<f64>) {
+        let gy = ctx.output_grad();
+        ctx.append_input_grad(Some(gy));
+    }
+}
+
+#[test]
+fn test_pin() {
+    ag::run(|g| {
+        let c = T::convert_to_tensor(ag::ndarray::arr1(&[1., 2.]), g);
+        let counted = ag::Tensor::builder(g)
+            .append_input(c, false)
+            .build(Counted(0));
+        let pinned = T::square(counted).pin();
+        let x = g.placeholder("x", &[2]);
+        let y = pinned * x;
+
+        let eval = |x_value: ag::NdArray<f64>| {
+            g.evaluator()
+                .extend(&[y, pinned])
+                .feed(x, x_value.view())
+                .run()
+        };
+        let first = eval(ag::ndarray::arr1(&[1., 1.]).into_dyn());
+        let second = eval(ag::ndarray::arr1(&[2., 3.]).into_dyn());
+        assert_eq!(num_computed(0), 1);
+        assert_eq!(first[0], Ok(ag::ndarray::arr1(&[1., 4.]).into_dyn()));
+        assert_eq!(second[0], Ok(ag::ndarray::arr1(&[2., 12.]).into_dyn()));
+        assert_eq!(second[1], Ok(ag::ndarray::arr1(&[1., 4.]).into_dyn()));
+
+        g.clear_pinned();
+        eval(ag::ndarray::arr1(&[1., 1.]).into_dyn());
+        assert_eq!(num_computed(0), 2);
+    });
+}
+
+#[test]
+fn test_evict() {
+    ag::run(|g| {
+        let c = T::convert_to_tensor(ag::ndarray::arr1(&[1., 2.]), g);
+        let a = ag::Tensor::builder(g)
+            .append_input(c, false)
+            .build(Counted(4))
+            .pin();
+        let b = ag::Tensor::builder(g)
+            .append_input(c, false)
+            .build(Counted(5))
+            .pin();
+        let y = a + b;
+        y.eval(g).unwrap();
+        assert_eq!(g.cache_len(), 2);
+
+        assert!(g.evict(&a));
+        assert!(!g.evict(&a));
+        assert!(!g.evict(&y));
+        assert_eq!(g.cache_len(), 1);
+        assert_eq!(y.eval(g), Ok(ag::ndarray::arr1(&[2., 4.]).into_dyn()));
+        assert_eq!((num_computed(4), num_computed(5)), (2, 1));
+        assert_eq!(g.cache_len(), 2);
+
+        g.clear_cache();
+        assert_eq!(g.cache_len(), 0);
+        y.eval(g).unwrap();
+        assert_eq!((num_computed(4), num_computed(5)), (3, 2));
+    });
+}
+
+#[test]
+#[should_panic]
+fn test_pin_placeholder_dependent() {
+    ag::run::<f32, _, _>(|g| {
+        let x = g.placeholder("x", &[2]);
+        T::square(x).pin();
+    });
+}
+
+#[test]
+fn test_eval_prunes_unrequested_outputs() {
+    ag::run(|g| {
+        let x = T::ones(&[3, 3], g);
+        let cheap = x * 2.;
+        let counted = ag::Tensor::builder(g)
+            .append_input(x, false)
+            .build(Counted(3));
+        let expensive = T::sum_all(T::matmul(counted, counted));
+
+        assert_eq!(cheap.eval(g).unwrap().sum(), 18.);
+        assert_eq!(num_computed(3), 0);
+        g.evaluator().extend(&[cheap, expensive]).run();
+        assert_eq!(num_computed(3), 1);
+    });
+}
+
+#[test]
+fn test_incremental_eval() {
+    let mut env = ag::VariableEnvironment::new();
+    env.name("a").set(ag::ndarray::arr1(&[1., 2.]));
+    env.name("b").set(ag::ndarray::arr1(&[3., 4.]));
+
+    env.run(|g| {
+        g.set_incremental(true);
+        let a = g.variable("a");
+        let b = g.variable("b");
+        let branch_a = ag::Tensor::builder(g)
+            .append_input(a, false)
+            .build(Counted(1));
+        let branch_b = ag::Tensor::builder(g)
+            .append_input(b, false)
+            .build(Counted(2));
+        let y = T::sum_all(branch_a * branch_b);
+
+        assert_eq!(y.eval(g), Ok(ag::ndarray::arr0(11.).into_dyn()));
+        assert_eq!(y.eval(g), Ok(ag::ndarray::arr0(11.).into_dyn()));
+        assert_eq!((num_computed(1), num_computed(2)), (1, 1));
+
+        g.set_variable("a", ag::ndarray::arr1(&[2., 0.]).into_dyn());
+        assert_eq!(y.eval(g), Ok(ag::ndarray::arr0(6.).into_dyn()));
+        assert_eq!((num_computed(1), num_computed(2)), (2, 1));
+
+        g.set_incremental(false);
+        y.eval(g).unwrap();
+        assert_eq!((num_computed(1), num_computed(2)), (3, 2));
+    });
+}
+
+#[test]
+fn test_on_gradient() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    env.name("w1").set(rng.glorot_uniform(&[3, 4]));
+    env.name("w2").set(rng.glorot_uniform(&[4, 2]));
+    env.name("b").set(ag::ndarray_ext::zeros(&[1, 2]));
+
+    env.run(|g| {
+        let calls = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let calls_ = calls.clone();
+        g.on_gradient(move |var, gx| {
+            calls_
+                .borrow_mut()
+                .push((var.get_variable_id().unwrap(), gx.shape().to_vec()));
+        });
+
+        let x = T::ones(&[5, 3], g);
+        let (w1, w2, b) = (g.variable("w1"), g.variable("w2"), g.variable("b"));
+        let y = T::matmul(T::relu(T::matmul(x, w1)), w2) + b;
+        let ns = g.default_namespace();
+        let (vars, grads) = ag::optimizers::grad_helper(&[y], &ns);
+        let ret = g.evaluator().extend(&grads).run();
+        assert!(ret.iter().all(|r| r.is_ok()));
+
+        // once per variable
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 3);
+        for v in &vars {
+            let vid = v.get_variable_id().unwrap();
+            let shape = g
+                .env()
+                .get_array_by_id(vid)
+                .unwrap()
+                .borrow()
+                .shape()
+                .to_vec();
+            assert_eq!(calls.iter().filter(|c| c.0 == vid).count(), 1);
+            assert!(calls.contains(&(vid, shape)));
+        }
+
+        let norms = ag::GradientNorms::new();
+        g.on_gradient(norms.hook());
+        let i = vars.iter().position(|v| v.id() == b.id()).unwrap();
+        let gb = grads[i].eval(g).unwrap();
+        let vid = b.get_variable_id().unwrap();
+        let expected = gb.iter().map(|a| a * a).sum::<f32>().sqrt();
+        assert_eq!(norms.norms().len(), 1);
+        assert!((norms.get(vid).unwrap() - expected).abs() < 1e-6);
+    });
+}
+
+#[test]
+fn test_add_chain_frees_inputs() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let a = env.name("a").set(ag::ndarray_ext::ones(&[1, 10]));
+    let b = env.name("b").set(ag::ndarray_ext::ones(&[10, 1000]));
+    let w = env.name("w").set(ag::ndarray_ext::ones(&[1, 1000]));
+    env.run(|g| {
+        let a = g.variable(a);
+        let x = g.variable(w);
+        let h0 = T::matmul(a, g.variable(b));
+        let mut h = h0;
+        for _ in 0..20 {
+            h = h + x;
+        }
+        let y = T::reduce_sum(h, &[0, 1], false);
+        let ga = T::grad(&[y], &[a])[0];
+        let ret = g.evaluator().extend(&[ga, y]).run();
+        assert_eq!(
+            ret[0].as_ref().unwrap(),
+            &ag::ndarray::arr2(&[[1000.; 10]]).into_dyn()
+        );
+        assert_eq!(ret[1].as_ref().unwrap()[[]], 30000.);
+
+        // The sums are dropped as soon as the next one is computed, and `h0` too
+        // since the backward of add only needs its shape.
+        let report = g.memory_report();
+        assert!(report.outputs.iter().all(|m| m.id != h0.id()));
+        assert!(report.output_bytes() < 3 * 1000 * 8);
+    });
+}
+
+#[test]
+fn test_identity_adds_no_allocation() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[-1]);
+        let h = T::exp(x);
+        let y = T::identity(h);
+        let x_value = ag::ndarray::arr1(&[0., 1., 2.]).into_dyn();
+
+        g.evaluator()
+            .push(T::sum_all(h))
+            .feed(x, x_value.view())
+            .run();
+        let without = g.memory_report().output_bytes();
+        g.evaluator()
+            .push(T::sum_all(y))
+            .feed(x, x_value.view())
+            .run();
+        let report = g.memory_report();
+        assert!(report.outputs.iter().all(|m| m.id != y.id()));
+        assert_eq!(report.output_bytes(), without);
+
+        let gx = T::grad(&[y], &[x])[0];
+        let gx_direct = T::grad(&[h], &[x])[0];
+        let ret = g
+            .evaluator()
+            .extend(&[gx, gx_direct])
+            .feed(x, x_value.view())
+            .run();
+        assert_eq!(ret[0].as_ref().unwrap(), ret[1].as_ref().unwrap());
+    });
+}
+
+#[test]
+fn test_assert_tensors_close() {
+    use ag::test_helper::{assert_tensors_close, DEFAULT_ATOL, DEFAULT_RTOL};
+
+    let a = ag::ndarray::arr2(&[[1., 2.], [3., 4.]]).into_dyn();
+    let mut b = a.clone();
+    b[[1, 0]] += 1e-7;
+    assert_tensors_close(&a, &b, DEFAULT_RTOL, DEFAULT_ATOL);
+
+    b[[1, 0]] += 1e-3;
+    let err = std::panic::catch_unwind(|| assert_tensors_close(&a, &b, DEFAULT_RTOL, DEFAULT_ATOL))
+        .unwrap_err();
+    let msg = err.downcast_ref::<String>().unwrap();
+    assert!(msg.starts_with("tensors differ at index [1, 0]"), "{}", msg);
+    assert_tensors_close(&a, &b, 1e-3, 0.);
+}
+
+#[test]
+fn test_grad_with_inplace_update() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr1(&[1., 2.]));
+    let v = env.set(ag::ndarray_ext::zeros(&[2]));
+    env.run(|g| {
+        let (w, v) = (g.variable(w), g.variable(v));
+        // `v` is updated before `y` is computed, and the gradient flows to `w` regardless
+        let update = T::assign(v, w * 3.);
+        let y = T::reduce_sum(T::square(w).depends_on(&[update]), &[0], false);
+        let gw = T::grad(&[y], &[w])[0];
+        assert_eq!(gw.eval(g).unwrap(), ndarray::arr1(&[2., 4.]).into_dyn());
+        assert_eq!(v.eval(g).unwrap(), ndarray::arr1(&[3., 6.]).into_dyn());
+    });
+}
+
+#[test]
+#[should_panic(expected = "Assign updates its input in place")]
+fn test_grad_through_inplace_op() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr1(&[1., 2.]));
+    let v = env.set(ag::ndarray_ext::zeros(&[2]));
+    env.run(|g| {
+        let (w, v) = (g.variable(w), g.variable(v));
+        let y = T::sum_all(T::assign(v, w * 3.));
+        T::grad(&[y], &[w]);
+    });
+}
+
+#[test]
+fn test_grad_numerical_vs_analytic() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let v = env.set(ag::ndarray::arr1(&[1., -2., 0.]));
+    let w = env.set(ag::ndarray::arr2(&[[0.5, 1.], [2., -1.]]));
+    env.run(|g| {
+        let (v, w) = (g.variable(v), g.variable(w));
+        let y = T::reduce_sum(T::sin(v), &[0], false) * T::matmul(w, w);
+        let reports =
+            ag::test_helper::grad_numerical_vs_analytic(y, &[v, w], ag::Feeder::new(), 1e-4, g);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].numerical.shape(), &[2, 2]);
+        for report in &reports {
+            assert!(report.is_close(1e-5, 1e-6), "{:?}", report);
+        }
+
+        let y = ag::Tensor::builder(g)
+            .append_input(v, false)
+            .build(BadSquare);
+        let report =
+            &ag::test_helper::grad_numerical_vs_analytic(y, &[v], ag::Feeder::new(), 1e-4, g)[0];
+        assert!(!report.is_close(1e-3, 1e-3));
+        // off by x everywhere, the most at -2
+        assert_eq!(report.worst_index, vec![1]);
+        assert!((report.max_abs_error - 2.).abs() < 1e-6);
+        assert_eq!(
+            report.analytic,
+            ag::ndarray::arr1(&[3., -6., 0.]).into_dyn()
+        );
+    });
+}
+
+#[test]
+fn test_infer_shape_symbolic() {
+    use ag::Dim;
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    env.name("w").set(ag::ndarray_ext::ones(&[64, 10]));
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 64]);
+        let y = T::matmul(x, g.variable("w"));
+        assert_eq!(
+            g.infer_shape(y),
+            Ok(Some(vec![Dim::Unknown, Dim::Known(10)]))
+        );
+
+        // symbols survive the ops and are checked against each other
+        let b = Dim::Symbol("batch");
+        let x = g.symbolic_placeholder("xs", &[b, Dim::Known(64)]);
+        let h = T::relu(T::matmul(x, g.variable("w")));
+        let mask = g.symbolic_placeholder("mask", &[b, Dim::Known(1)]);
+        assert_eq!(g.infer_shape(h * mask), Ok(Some(vec![b, Dim::Known(10)])));
+        assert!(g.infer_shape(T::matmul(h, g.variable("w"))).is_err());
+
+        // ops without shape inference make the shape unknown
+        assert_eq!(g.infer_shape(T::reduce_sum(h, &[0], false)), Ok(None));
+
+        let out = g
+            .evaluator()
+            .push(h * mask)
+            .feed("xs", ag::ndarray_ext::ones(&[3, 64]).view())
+            .feed("mask", ag::ndarray_ext::ones(&[3, 1]).view())
+            .run();
+        assert_eq!(out[0].as_ref().unwrap().shape(), &[3, 10]);
+    });
+}
+
+#[test]
+#[should_panic(expected = "dim `batch`")]
+fn test_symbolic_dim_mismatch() {
+    use ag::Dim;
+    ag::run::<f64, _, _>(|g| {
+        let b = Dim::Symbol("batch");
+        let x = g.symbolic_placeholder("x", &[b, Dim::Known(2)]);
+        let y = g.symbolic_placeholder("y", &[b, Dim::Known(2)]);
+        g.evaluator()
+            .push(x + y)
+            .feed("x", ag::ndarray_ext::ones(&[3, 2]).view())
+            .feed("y", ag::ndarray_ext::ones(&[4, 2]).view())
+            .run();
+    });
+}
+
+#[test]
+fn test_grad_of_shared_variable() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr1(&[1., 2., 3.]));
+    env.run(|g| {
+        let w = g.variable(w);
+        let a = T::convert_to_tensor(ndarray::arr1(&[1., -1., 2.]).into_dyn(), g);
+        let b = T::convert_to_tensor(ndarray::arr1(&[3., 0.5, -4.]).into_dyn(), g);
+        // `w` reaches `y` through two branches
+        let y = a * w + b * w;
+        let gy = T::convert_to_tensor(ndarray::arr1(&[1., 2., 3.]).into_dyn(), g);
+        let gw = T::grad_with_default(&[y], &[w], &[gy])[0];
+        let expected = (a + b) * gy;
+        assert_eq!(gw.eval(g), expected.eval(g));
+    });
+}
+
+#[test]
+fn test_update_variable_slice() {
+    use ag::optimizers::{Optimizer, SGD};
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let init = ndarray::Array::range(1., 13., 1.)
+        .into_shape((4, 3))
+        .unwrap();
+    let w = env.set(init.clone());
+    let head = env.slice(w, &[1..3]);
+    env.run(|g| {
+        let h = g.variable(head);
+        assert_eq!(
+            h.eval(g).unwrap(),
+            init.slice(ndarray::s![1..3, ..]).into_dyn()
+        );
+        let loss = T::sum_all(T::square(h));
+        let grads = T::grad(&[loss], &[h]);
+        // -0.5 * 2h brings the slice to 0
+        SGD::new(0.5).update(&[h], &grads, g, ag::Feeder::new());
+    });
+    let w = env.get_array_by_id(w).unwrap().borrow();
+    assert!(w.slice(ndarray::s![1..3, ..]).iter().all(|&a| a == 0.));
+    assert_eq!(w.slice(ndarray::s![0, ..]), init.slice(ndarray::s![0, ..]));
+    assert_eq!(w.slice(ndarray::s![3, ..]), init.slice(ndarray::s![3, ..]));
+}
+
+#[test]
+fn test_share_variables_between_envs() {
+    use ag::optimizers::{Optimizer, SGD};
+    let mut teacher = ag::VariableEnvironment::<f64>::new();
+    let w_id = teacher
+        .namespace_mut("net")
+        .slot()
+        .name("w")
+        .set(ndarray::arr1(&[1., 2.]));
+    let copied = teacher.clone();
+    let mut student = ag::VariableEnvironment::new();
+    let ids = student.share_namespace(&teacher, "net");
+    assert_eq!(ids.len(), 1);
+
+    teacher.run(|g| {
+        let w = g.variable(("net", "w"));
+        let grads = T::grad(&[T::sum_all(w)], &[w]);
+        SGD::new(1.).update(&[w], &grads, g, ag::Feeder::new());
+    });
+    student.run(|g| {
+        let w = g.variable(("net", "w"));
+        assert_eq!(w.eval(g).unwrap(), ndarray::arr1(&[0., 1.]).into_dyn());
+    });
+    // `clone` copies the arrays
+    let w = copied.get_array_by_id(w_id).unwrap().borrow();
+    assert_eq!(*w, ndarray::arr1(&[1., 2.]).into_dyn());
+}
+
+#[test]
+#[should_panic(expected = "overlaps the slice")]
+fn test_overlapping_variable_slices() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ag::ndarray_ext::zeros(&[4, 3]));
+    env.slice(w, &[0..2]);
+    // disjoint blocks are fine
+    env.slice(w, &[2..4, 0..1]);
+    env.slice(w, &[1..3, 2..3]);
+}
+
+#[test]
+#[should_panic(expected = "is updated in place while its alias")]
+fn test_variable_slice_aliasing_its_parent() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ag::ndarray_ext::ones(&[4, 3]));
+    let head = env.slice(w, &[0..2]);
+    env.run(|g| {
+        let (w, head) = (g.variable(w), g.variable(head));
+        // reading the parent and updating the slice in separate evaluations is fine
+        let tail = T::slice(w, &[2, 0], &[4, 3]).eval(g).unwrap();
+        T::assign(head, T::convert_to_tensor(tail, g))
+            .eval(g)
+            .unwrap();
+        // but not in the same one
+        T::assign(head, T::slice(w, &[2, 0], &[4, 3]))
+            .eval(g)
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_sequential_registers_variables() {
+    let mlp = ag::nn::Sequential::new("mlp", 4).dense(8).relu().dense(3);
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let ids = mlp.register(&mut env);
+    assert_eq!(ids.len(), 4);
+
+    let ns = env.namespace("mlp");
+    let mut names = ns.current_var_names();
+    names.sort();
+    assert_eq!(names, ["dense0/b", "dense0/w", "dense1/b", "dense1/w"]);
+    let shapes: Vec<_> = ids
+        .iter()
+        .map(|&id| env.get_array_by_id(id).unwrap().borrow().shape().to_vec())
+        .collect();
+    assert_eq!(shapes, [vec![4, 8], vec![1, 8], vec![8, 3], vec![1, 3]]);
+
+    env.run(|g| {
+        let x = T::ones(&[2, 4], g);
+        let y = mlp.build(x, g, false);
+        assert_eq!(y.eval(g).unwrap().shape(), &[2, 3]);
+
+        let ns = g.namespace("mlp");
+        let (vars, _) = ag::optimizers::grad_helper(&[y], &ns);
+        assert_eq!(vars.len(), 4);
+    });
+}
+
+#[test]
+fn test_dense_layer() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let ids = ag::nn::Dense::new(4, 3).register(&mut env, "net", "fc");
+    ag::nn::Dense::new(4, 3)
+        .bias(false)
+        .initializer(ag::nn::Initializer::Zeros)
+        .register(&mut env, "net", "no_bias");
+    assert_eq!(ids.len(), 2);
+
+    env.run(|g| {
+        let x = T::ones(&[5, 4], g);
+        let y = ag::nn::dense(x, g, "net", "fc");
+        assert_eq!(y.eval(g).unwrap().shape(), &[5, 3]);
+        let z = ag::nn::dense(x, g, "net", "no_bias");
+        assert_eq!(
+            z.eval(g).unwrap(),
+            ndarray::Array2::<f64>::zeros((5, 3)).into_dyn()
+        );
+
+        let (w, b) = (g.variable(ids[0]), g.variable(ids[1]));
+        let grads = T::grad(&[T::sum_all(y)], &[w, b]);
+        // every weight sees all 5 rows of ones
+        assert_eq!(
+            grads[0].eval(g).unwrap(),
+            ndarray::Array2::from_elem((4, 3), 5.).into_dyn()
+        );
+        assert_eq!(
+            grads[1].eval(g).unwrap(),
+            ndarray::Array2::from_elem((1, 3), 5.).into_dyn()
+        );
+    });
+}
+
+#[test]
+fn test_tie_weights() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let e_val = ndarray::arr2(&[[1., 2., 3.], [4., 5., 6.]]).into_dyn();
+    let embedding = env.name("embedding").set(e_val.clone());
+    let projection = env.name("projection").set(ag::ndarray_ext::zeros(&[3, 2]));
+    let bias = env.name("bias").set(ag::ndarray_ext::zeros(&[2, 3]));
+    ag::nn::tie_weights(&mut env, embedding, projection);
+    // same-shaped variables share the array
+    ag::nn::tie_weights(&mut env, embedding, bias);
+    assert_eq!(*env.get_array_by_id(bias).unwrap().borrow(), e_val);
+    assert_eq!(
+        env.get_array_by_id(projection).unwrap().borrow().shape(),
+        &[0]
+    );
+
+    env.run(|g| {
+        let (e, p) = (g.variable("embedding"), g.variable("projection"));
+        assert_eq!(p.eval(g).unwrap(), e_val.t());
+        assert_eq!(g.variable(projection).eval(g).unwrap(), e_val.t());
+        assert_eq!(g.variable("bias").eval(g).unwrap(), e_val);
+
+        // the gradients of both usages go to the embedding, the only variable left
+        let c = T::convert_to_tensor(ndarray::arr2(&[[1., 0.], [0., 2.], [3., 0.]]), g);
+        let loss = T::sum_all(e) + T::sum_all(p * c);
+        let ns = g.default_namespace();
+        let (vars, grads) = ag::optimizers::grad_helper(&[loss], &ns);
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars[0].get_variable_id(), e.get_variable_id());
+        let expected = ndarray::arr2(&[[2., 1., 4.], [1., 3., 1.]]).into_dyn();
+        assert_eq!(grads[0].eval(g).unwrap(), expected);
+
+        // an update of the embedding shows through the projection
+        T::assign(e, e * 2.).eval(g).unwrap();
+        assert_eq!(p.eval(g).unwrap(), e_val.t().mapv(|x| 2. * x));
+    });
+    // in the environment and in the next runs as well
+    assert_eq!(
+        *env.get_array_by_id(bias).unwrap().borrow(),
+        e_val.mapv(|x| 2. * x)
+    );
+
+    // the ties are saved with the variables
+    let path = std::env::temp_dir().join(format!("autograd_test_ties_{}.json", std::process::id()));
+    env.save(&path).unwrap();
+    let loaded = ag::VariableEnvironment::<f64>::load(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    loaded.run(|g| {
+        let e = g.variable(embedding);
+        T::assign(e, T::zeros(&[2, 3], g)).eval(g).unwrap();
+        assert_eq!(
+            g.variable(projection).eval(g).unwrap(),
+            ag::ndarray_ext::zeros::<f64>(&[3, 2])
+        );
+        assert_eq!(
+            g.variable(bias).eval(g).unwrap(),
+            ag::ndarray_ext::zeros::<f64>(&[2, 3])
+        );
+    });
+}
+
+#[test]
+#[should_panic(expected = "can't tie")]
+fn test_tie_weights_shape_mismatch() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let a = env.set(ag::ndarray_ext::zeros(&[2, 3]));
+    let b = env.set(ag::ndarray_ext::zeros(&[3, 3]));
+    ag::nn::tie_weights(&mut env, a, b);
+}
+
+#[test]
+fn test_sinusoidal_positional_encoding() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        // an odd `dim` ends with a lone sine column
+        for &(seq_len, dim) in &[(6, 8), (5, 3)] {
+            let pe = ag::nn::sinusoidal_positional_encoding(seq_len, dim, g);
+            let expected = ndarray::Array2::from_shape_fn((seq_len, dim), |(pos, j)| {
+                let angle = pos as f64 / 10000f64.powf((j - j % 2) as f64 / dim as f64);
+                if j % 2 == 0 {
+                    angle.sin()
+                } else {
+                    angle.cos()
+                }
+            });
+            ag::test_helper::assert_tensors_close(
+                &pe.eval(g).unwrap(),
+                &expected.into_dyn(),
+                1e-12,
+                1e-12,
+            );
+        }
+    });
+}
+
+#[test]
+fn test_early_stopping_patience() {
+    use ag::training::EarlyStopping;
+
+    let mut early_stopping = EarlyStopping::new(3, 0.1);
+    // 0.95 and 0.92 are within min_delta of the best, so they count as bad epochs
+    for (metric, epochs_since_best) in [(1., 0), (0.95, 1), (0.92, 2), (0.5, 0), (0.6, 1)] {
+        assert!(!early_stopping.should_stop(metric));
+        assert_eq!(early_stopping.epochs_since_best(), epochs_since_best);
+    }
+    assert_eq!(early_stopping.best(), Some(0.5));
+    assert!(!early_stopping.should_stop(0.45));
+    assert!(early_stopping.should_stop(0.5));
+    assert_eq!(early_stopping.best(), Some(0.5));
+    assert_eq!(early_stopping.epochs_since_best(), 3);
+
+    let mut zero_patience = EarlyStopping::new(0, 0.);
+    assert!(zero_patience.should_stop(1.));
+}
+
+#[test]
+fn test_early_stopping_restores_best_weights() {
+    use ag::training::EarlyStopping;
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr1(&[0., 0.]));
+    let mut early_stopping = EarlyStopping::new(1, 0.);
+    assert!(!early_stopping.restore_best(&env));
+
+    let mut stopped = false;
+    for (step, loss) in [3., 1., 2.].iter().enumerate() {
+        env.run(|g| {
+            let filled = T::ones(&[2], g) * step as f64;
+            T::assign(g.variable(w), filled).eval(g).unwrap();
+        });
+        stopped = early_stopping.should_stop_with(*loss, &env);
+    }
+    assert!(stopped);
+    assert!(early_stopping.restore_best(&env));
+    assert_eq!(
+        *env.get_array_by_id(w).unwrap().borrow(),
+        ndarray::arr1(&[1., 1.]).into_dyn()
+    );
+}
+
+#[test]
+fn test_rewrite_mul_by_one() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let one = T::convert_to_tensor(ndarray::arr2(&[[1., 1.]]).into_dyn(), g);
+        let y = T::exp(x * one) * one + x;
+        let is_one = |t: ag::Tensor<f32>| {
+            t.is_source()
+                && !t.is_placeholder()
+                && t.eval(g).map_or(false, |a| a.iter().all(|&v| v == 1.))
+        };
+
+        let rewritten = g.rewrite(&[y], |t| {
+            (t.op_name() == "MulOp" && is_one(t.get_input(1))).then(|| t.get_input(0))
+        });
+        let z = rewritten[0];
+        assert_ne!(z.id(), y.id());
+        assert_eq!(z.get_input(1).id(), x.id());
+
+        let mut ids = Vec::new();
+        g.visit(&[z], &mut |t| ids.push(t.id()));
+        assert_eq!(ids, vec![x.id(), z.get_input(0).id(), z.id()]);
+
+        let x_value = ag::ndarray::arr2(&[[0., 1.], [2., 3.]]).into_dyn();
+        let results = g.evaluator().extend(&[y, z]).feed(x, x_value.view()).run();
+        assert_eq!(results[0].as_ref().unwrap(), results[1].as_ref().unwrap());
+    });
+}
+
+#[test]
+fn test_no_grad() {
+    let mut env = ag::VariableEnvironment::new();
+    let w = env
+        .name("w")
+        .set(ag::ndarray::arr2(&[[1., -2.], [3., 0.5]]));
+    env.run(|g| {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let calls_ = calls.clone();
+        g.on_gradient(move |_, _| calls_.set(calls_.get() + 1));
+
+        let w = g.variable(w);
+        let x = T::convert_to_tensor(ag::ndarray::arr2(&[[2., 1.]]), g);
+        let y = T::tanh(T::matmul(x, w));
+        let gw = T::grad(&[y], &[w])[0];
+        let expected = y.eval(g).unwrap();
+        let expected_gw = gw.eval(g).unwrap();
+        assert_eq!(calls.get(), 1);
+
+        let (y2, gw2, gw3) = g.no_grad(|g| {
+            assert!(g.is_no_grad());
+            let y = y.eval(g).unwrap();
+            let gw = gw.eval(g).unwrap();
+            // gradients made in the scope work, but aren't reported to the hook
+            let gw3 = T::grad(&[T::tanh(T::matmul(x, w))], &[w])[0];
+            (y, gw, gw3)
+        });
+        assert!(!g.is_no_grad());
+        assert_eq!(y2, expected);
+        assert_eq!(gw2, expected_gw);
+        assert_eq!(calls.get(), 1);
+        assert_eq!(gw3.eval(g).unwrap(), expected_gw);
+        assert_eq!(calls.get(), 1);
+        gw.eval(g).unwrap();
+        assert_eq!(calls.get(), 2);
+    });
+}
+
+#[test]
+fn test_detach() {
+    let mut env = ag::VariableEnvironment::new();
+    let w = env.name("w").set(ag::ndarray::arr1(&[1., -2., 3.]));
+    env.run(|g| {
+        let w = g.variable(w);
+        let x = T::square(w);
+        let y = T::detach(x, g).unwrap();
+        assert!(y.is_source());
+        assert_eq!(y.num_inputs(), 0);
+        assert_eq!(y.eval(g).unwrap(), x.eval(g).unwrap());
+
+        // `y` is a constant: only the `w` factor of `y * w` contributes
+        let gw = T::grad(&[y * w], &[w])[0];
+        assert_eq!(gw.eval(g).unwrap(), x.eval(g).unwrap());
+    });
+}
+
+#[test]
+fn test_tensor_display() {
+    let mut env = ag::VariableEnvironment::new();
+    env.name("w").set(ag::ndarray_ext::zeros(&[2]));
+    env.run(|g| {
+        let x: ag::Tensor<f32> = g.placeholder("x", &[2]);
+        let w = g.variable("w");
+        let y = T::exp(x + w);
+        let y_ = y;
+        assert_eq!(y_.id(), y.id());
+        assert_eq!(
+            format!("{}", x),
+            format!("tensor {} (placeholder \"x\")", x.id())
+        );
+        assert_eq!(format!("{}", w), format!("tensor {} (variable)", w.id()));
+        assert_eq!(format!("{}", y), format!("tensor {} (Exp)", y.id()));
+        assert!(format!("{:?}", y).starts_with(&format!("Tensor {{ id: {}, op: ", y.id())));
+    });
+}
+
+#[test]
+fn test_fingerprint() {
+    fn build<'g>(g: &'g ag::Context<f64>, axes: &[isize; 1], stride: usize) -> ag::Tensor<'g, f64> {
+        let x = g.placeholder("x", &[-1, 1, 4, 4]);
+        let w = g.variable("w");
+        let h = T::relu(T::conv2d(x, w, 1, stride));
+        T::reduce_mean(h, axes, false)
+    }
+    let fingerprint_in = |env: &ag::VariableEnvironment<f64>, axes: &[isize; 1], stride| {
+        env.run(|g| {
+            // shifts the tensor ids
+            let _ = T::ones(&[3], g) * 2.;
+            g.fingerprint(&[build(g, axes, stride)])
+        })
+    };
+    let mut env = ag::VariableEnvironment::new();
+    env.name("w").set(ag::ndarray_ext::ones(&[2, 1, 3, 3]));
+    let mut other_env = ag::VariableEnvironment::new();
+    other_env
+        .name("w")
+        .set(ag::ndarray_ext::zeros(&[2, 1, 3, 3]));
+
+    let base = env.run(|g| g.fingerprint(&[build(g, &[0], 1)]));
+    // the same structure in other runs, and with other variable values
+    assert_eq!(fingerprint_in(&env, &[0], 1), base);
+    assert_eq!(fingerprint_in(&other_env, &[0], 1), base);
+    // an op parameter, or a constant
+    assert_ne!(fingerprint_in(&env, &[0], 2), base);
+    assert_ne!(fingerprint_in(&env, &[1], 1), base);
+
+    env.run(|g| {
+        let y = build(g, &[0], 1);
+        let z = T::exp(y);
+        assert_ne!(g.fingerprint(&[y, z]), g.fingerprint(&[z, y]));
+        assert_ne!(g.fingerprint(&[y]), g.fingerprint(&[z]));
+    });
+}
+
+#[test]
+fn test_visit_diamond() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[2]);
+        let left = T::exp(x);
+        let right = T::sin(x);
+        let y = left + right;
+
+        let mut ids = Vec::new();
+        g.visit(&[y, left], &mut |t| ids.push(t.id()));
+        assert_eq!(ids, vec![x.id(), left.id(), right.id(), y.id()]);
+
+        // `x` is reached through both branches but visited once
+        let mut num_visits = std::collections::HashMap::new();
+        g.visit(&[y, y], &mut |t| {
+            *num_visits.entry(t.id()).or_insert(0) += 1
+        });
+        assert_eq!(num_visits.len(), 4);
+        assert!(num_visits.values().all(|&n| n == 1));
+    });
+}
+
+#[test]
+fn test_op_call_count_diamond() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[2]);
+        let shared = T::exp(x);
+        let y = T::square(shared) + T::sigmoid(shared);
+        let count = |name| {
+            let counts = g.op_call_counts();
+            counts.iter().find(|c| c.0 == name).map_or(0, |c| c.1)
+        };
+
+        let x_value = ag::ndarray::arr1(&[0., 1.]).into_dyn();
+        g.evaluator().push(y).feed(x, x_value.view()).run();
+        assert_eq!(count("Exp"), 1);
+        assert_eq!(g.op_call_count(), 4);
+
+        g.reset_counters();
+        assert_eq!(g.op_call_count(), 0);
+        g.evaluator().push(shared).feed(x, x_value.view()).run();
+        assert_eq!((count("Exp"), count("Square")), (1, 0));
+    });
+}
+
+#[test]
+fn test_stashed_intermediates() {
+    ag::run(|g| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let y = T::layer_norm(x, 1e-5);
+        let loss = T::sum_all(T::square(y) * x);
+        let gx = T::grad(&[loss], &[x])[0];
+        let count = |name| {
+            let counts = g.op_call_counts();
+            counts.iter().find(|c| c.0 == name).map_or(0, |c| c.1)
+        };
+
+        let x_value = ag::ndarray::arr2(&[[0., 1., 5.], [2., -2., 1.]]).into_dyn();
+        let ret = g.evaluator().push(y).push(gx).feed(x, x_value.view()).run();
+        // the backward pass reads the statistics of the forward one instead of recomputing them
+        assert_eq!((count("LayerNorm"), count("LayerNormGrad")), (1, 1));
+        assert_eq!(count("ReduceMean") + count("ReduceSum"), 0);
+        // and they don't show as an output
+        assert_eq!(ret[0].as_ref().unwrap().shape(), &[2, 3]);
+    });
+}
+
+// Identity taking at least 5ms
+struct Sleepy;
+
+impl ag::op::Op<f64> for Sleepy {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<f64>) -> Result<(), ag::op::OpError> {
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        let x = ctx.input(0);
+        ctx.append_output_view(x);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut ag::op::GradientContext<f64>) {
+        ctx.append_input_grad(Some(ctx.output_grad()));
+    }
+}
+
+#[test]
+fn test_profiler() {
+    ag::run(|g| {
+        let profiler = ag::Profiler::new();
+        g.set_profiler(&profiler);
+        let x = T::ones(&[4], g);
+        let slow = ag::Tensor::builder(g).append_input(x, false).build(Sleepy);
+        let y = T::exp(slow) + T::square(x);
+        y.eval(g).unwrap();
+        y.eval(g).unwrap();
+
+        let stats = profiler.stats();
+        assert_eq!(stats[0].name, "Sleepy");
+        assert_eq!(stats[0].count, 2);
+        assert!(stats[0].total >= std::time::Duration::from_millis(10));
+        assert!(stats[0].p50 <= stats[0].p95);
+        let exp = stats.iter().find(|t| t.name == "Exp").unwrap();
+        assert_eq!(exp.count, 2);
+
+        let report = profiler.report();
+        let lines: Vec<_> = report.lines().collect();
+        assert!(lines[0].starts_with("op"));
+        assert!(lines[1].starts_with("Sleepy"));
+        assert_eq!(lines.len(), 1 + stats.len());
+
+        g.clear_profiler();
+        y.eval(g).unwrap();
+        assert_eq!(profiler.stats()[0].count, 2);
+        profiler.clear();
+        assert!(profiler.stats().is_empty());
+    });
+}
+
+// Counts the calls it gets and computes with the CPU backend
+#[derive(Default)]
+struct CountingBackend {
+    calls: std::rc::Rc<std::cell::RefCell<Vec<&'static str>>>,
+}
+
+impl ag::backend::Backend<f64> for CountingBackend {
+    fn name(&self) -> &'static str {
+        "counting"
+    }
+
+    fn zeros(&self, shape: &[usize]) -> ag::NdArray<f64> {
+        self.calls.borrow_mut().push("zeros");
+        ag::backend::CpuBackend.zeros(shape)
+    }
+
+    fn matmul(
+        &self,
+        a: &ndarray::ArrayView2<f64>,
+        b: &ndarray::ArrayView2<f64>,
+    ) -> ndarray::Array2<f64> {
+        self.calls.borrow_mut().push("matmul");
+        ag::backend::CpuBackend.matmul(a, b)
+    }
+
+    fn binary(
+        &self,
+        op: ag::backend::BinaryOp,
+        a: &ag::NdArrayView<f64>,
+        b: &ag::NdArrayView<f64>,
+    ) -> ag::NdArray<f64> {
+        self.calls.borrow_mut().push(match op {
+            ag::backend::BinaryOp::Add => "add",
+            ag::backend::BinaryOp::Sub => "sub",
+            ag::backend::BinaryOp::Mul => "mul",
+            ag::backend::BinaryOp::Div => "div",
+        });
+        ag::backend::CpuBackend.binary(op, a, b)
+    }
+}
+
+#[test]
+fn test_backend() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        assert_eq!(g.backend_name(), "cpu");
+        let a = T::convert_to_tensor(ndarray::arr2(&[[1., 2.], [3., 4.]]), g);
+        let y = T::matmul(a, T::transpose(a, &[1, 0])) + T::zeros(&[2, 2], g) * a - a / a;
+        let expected = y.eval(g).unwrap();
+
+        let backend = CountingBackend::default();
+        let calls = backend.calls.clone();
+        g.set_backend(backend);
+        assert_eq!(g.backend_name(), "counting");
+        assert_eq!(y.eval(g).unwrap(), expected);
+        let mut calls = calls.borrow().clone();
+        calls.sort();
+        assert_eq!(calls, ["add", "div", "matmul", "mul", "sub", "zeros"]);
+    });
+}
+
+#[test]
+fn test_inputs_of() {
+    let mut env = ag::VariableEnvironment::new();
+    env.name("w").set(ag::ndarray_ext::ones(&[2, 2]));
+    env.name("unused").set(ag::ndarray_ext::ones(&[2]));
+    env.run(|g| {
+        let x: ag::Tensor<f32> = g.placeholder("x", &[-1, 2]);
+        let t = g.placeholder("t", &[-1, 2]);
+        let w = g.variable("w");
+        let _ = g.variable("unused");
+        // two branches sharing `x`
+        let a = T::matmul(x, w);
+        let b = T::sigmoid(x) * t;
+        let y = T::reduce_sum(a + b, &[0, 1], false);
+
+        let ids = |ts: Vec<ag::Tensor<f32>>| ts.iter().map(|t| t.id()).collect::<Vec<_>>();
+        assert_eq!(ids(g.inputs_of(&[y])), vec![x.id(), t.id(), w.id()]);
+        assert_eq!(ids(g.inputs_of(&[a])), vec![x.id(), w.id()]);
+        assert_eq!(ids(g.inputs_of(&[a, b])), vec![x.id(), t.id(), w.id()]);
+        assert!(g.inputs_of(&[T::ones(&[2], g)]).is_empty());
+    });
+}
+
+#[test]
+fn test_gc() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let w = env.name("w").set(ag::ndarray_ext::ones(&[2, 2]));
+    let unused = env.name("unused").set(ag::ndarray_ext::ones(&[3]));
+    let table = env.name("table").set(ag::ndarray_ext::ones(&[4, 2]));
+    let rows = env.slice(table, &[0..2]);
+    let mut shared_env = ag::VariableEnvironment::new();
+    let shared = shared_env.share(&env, unused);
+    let dropped = env.name("dropped").set(ag::ndarray_ext::ones(&[5]));
+    let kept = env
+        .namespace_mut("kept")
+        .slot()
+        .name("a")
+        .set(ag::ndarray_ext::ones(&[1]));
+    env.add_running_stats("bn", &[2]);
+
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let y = T::matmul(x, g.variable(w)) + g.variable(rows);
+        let _ = g.variable(dropped) * 2.;
+        // `unused` is shared with another env, and `table` is viewed by the live `rows`
+        // and the running stats are kept
+        assert_eq!(g.gc_keeping(&[&y], &["kept"]), vec![dropped]);
+        assert_eq!(g.gc(&[&y]), vec![kept]);
+        assert_eq!(g.gc(&[&y]), vec![]);
+
+        let array = |vid| {
+            g.env()
+                .get_array_by_id(vid)
+                .unwrap()
+                .borrow()
+                .shape()
+                .to_vec()
+        };
+        assert_eq!(array(dropped), vec![0]);
+        assert_eq!(array(w), vec![2, 2]);
+        assert_eq!(array(table), vec![4, 2]);
+        assert_eq!(array(unused), vec![3]);
+        assert_eq!(g.variable(w).eval(g).unwrap().shape(), &[2, 2]);
+    });
+    shared_env.run(|g| assert_eq!(g.variable(shared).eval(g).unwrap().len(), 3));
+}
+
+#[test]
+fn test_gc_keeps_optimizer_state() {
+    use ag::optimizers::{Adam, Optimizer};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.name("w").set(ndarray::arr1(&[1., -2.]));
+    let old_head = env.name("old_head").set(ndarray::arr1(&[3.]));
+    let adam = Adam::default("gc_adam", [w], &mut env);
+    let step = |env: &ag::VariableEnvironment<f64>, expected: Vec<ag::variable::VariableID>| {
+        env.run(|g| {
+            let w = g.variable(w);
+            let loss = T::sum_all(T::square(w));
+            let gw = T::grad(&[loss], &[w])[0];
+            adam.update(&[w], &[gw], g, ag::Feeder::new());
+            // the moments of `w` aren't inputs of `loss`
+            assert_eq!(g.gc(&[&loss]), expected);
+        });
+    };
+    step(&env, vec![old_head]);
+    step(&env, vec![]);
+    let w_val = env.get_array_by_id(w).unwrap().borrow().clone();
+    assert!(w_val[0] < 1. && w_val[1] > -2.);
+
+    // and so are they after a save and a load
+    let path = std::env::temp_dir().join(format!("autograd_test_gc_{}.json", std::process::id()));
+    env.save(&path).unwrap();
+    let loaded = ag::VariableEnvironment::<f64>::load(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    step(&loaded, vec![]);
+    step(&loaded, vec![]);
+}
+
+#[test]
+fn test_fallback() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1]);
+        // out of bounds unless `x` has 5 elements or more
+        let y = T::fallback(T::slice(x, &[4], &[5]) * 2., T::reduce_sum(x, &[0], true));
+        let gx = T::grad(&[y], &[x])[0];
+        let eval = |value: &[f64]| {
+            let value = ag::ndarray::arr1(value).into_dyn();
+            g.evaluator().push(y).push(gx).feed(x, value.view()).run()
+        };
+        let ys = eval(&[1., 2., 3., 4., 5.]);
+        assert_eq!(ys[0], Ok(ag::ndarray::arr1(&[10.]).into_dyn()));
+        assert_eq!(
+            ys[1],
+            Ok(ag::ndarray::arr1(&[0., 0., 0., 0., 2.]).into_dyn())
+        );
+        let ys = eval(&[1., 2.]);
+        assert_eq!(ys[0], Ok(ag::ndarray::arr1(&[3.]).into_dyn()));
+        // the backward pass of the failed slice fails as well
+        assert!(ys[1].is_err());
+
+        // fails if both do
+        let z = T::fallback(T::slice(x, &[4], &[5]), T::slice(x, &[3], &[4]));
+        let result = g
+            .evaluator()
+            .push(z)
+            .feed(x, ag::ndarray::arr1(&[1., 2.]).into_dyn().view())
+            .run();
+        assert!(result[0].is_err());
+    });
+}
+
+#[test]
+fn test_eval_ref() {
+    let mut env = ag::VariableEnvironment::new();
+    let vid = env.name("w").set(ag::ndarray_ext::ones(&[3, 2]));
+    env.run(|g| {
+        let w: ag::Tensor<f32> = g.variable(vid);
+        let original = g.env().get_array_by_id(vid).unwrap().borrow().as_ptr();
+        // the variable's own array, not a copy
+        assert_eq!(w.eval_ref(g).unwrap().as_ptr(), original);
+        assert_ne!(w.eval(g).unwrap().as_ptr(), original);
+
+        let y = T::reduce_sum(w, &[0], false);
+        assert_eq!(
+            *y.eval_ref(g).unwrap(),
+            ag::ndarray::arr1(&[3., 3.]).into_dyn()
+        );
+        assert!(g.peek(&y).is_some());
+    });
+}
+
+#[test]
+fn test_num_parameters() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    env.name("w1").set(rng.glorot_uniform(&[4, 8]));
+    env.name("b1").set(ag::ndarray_ext::zeros(&[1, 8]));
+    env.name("w2").set(rng.glorot_uniform(&[8, 3]));
+    env.name("b2").set(ag::ndarray_ext::zeros(&[1, 3]));
+    env.name("scale").set(ag::ndarray_ext::ones(&[1, 8]));
+    env.name("shift").set(ag::ndarray_ext::zeros(&[1, 8]));
+    env.add_running_stats("bn", &[1, 8]);
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 4]);
+        let h = T::matmul(x, g.variable("w1")) + g.variable("b1");
+        assert_eq!(g.num_parameters(&[h]), 4 * 8 + 8);
+
+        let (scale, shift) = (g.variable("scale"), g.variable("shift"));
+        let h = T::relu(T::batch_norm_with_stats(
+            h, scale, shift, "bn", 0.9, false, g,
+        ));
+        let y = T::matmul(h, g.variable("w2")) + g.variable("b2");
+        // the running mean and variance are left out
+        assert_eq!(g.num_parameters(&[y]), 4 * 8 + 8 + 8 + 8 + 8 * 3 + 3);
+        assert_eq!(g.num_parameters(&[y, h]), g.num_parameters(&[y]));
+        assert_eq!(g.num_parameters(&[x]), 0);
+    });
+}
+
+#[test]
+fn test_running_stats() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let scale = env.set(ag::ndarray_ext::ones(&[1, 2]));
+    let shift = env.set(ag::ndarray_ext::zeros(&[1, 2]));
+    env.add_running_stats("bn", &[1, 2]);
+    env.run(|g| {
+        assert!(g.running_stats("nope").is_none());
+        let (mean, var) = g.running_stats("bn").unwrap();
+        assert_eq!(mean, ag::ndarray::arr2(&[[0., 0.]]).into_dyn());
+        assert_eq!(var, ag::ndarray::arr2(&[[1., 1.]]).into_dyn());
+
+        // mean 3 and variance 4
+        let x = T::standard_normal(&[64, 2], g) * 2. + 3.;
+        let (scale, shift) = (g.variable(scale), g.variable(shift));
+        let y = T::batch_norm_with_stats(x, scale, shift, "bn", 0.9, true, g);
+        for _ in 0..200 {
+            y.eval(g).unwrap();
+        }
+        let (mean, var) = g.running_stats("bn").unwrap();
+        for (&m, &v) in mean.iter().zip(var.iter()) {
+            assert!((m - 3.).abs() < 0.3, "running mean {}", m);
+            assert!((v - 4.).abs() < 0.8, "running variance {}", v);
+        }
+
+        // inference uses and leaves the stats as they are
+        g.set_running_stats(
+            "bn",
+            ag::ndarray::arr2(&[[1., 2.]]).into_dyn(),
+            ag::ndarray::arr2(&[[4., 1.]]).into_dyn(),
+        );
+        let x = T::convert_to_tensor(ag::ndarray::arr2(&[[3., 2.], [-1., 4.]]), g);
+        let y = T::batch_norm_with_stats(x, scale, shift, "bn", 0.9, false, g);
+        let y = y.eval(g).unwrap();
+        let expected = ag::ndarray::arr2(&[[1., 0.], [-1., 2.]]).into_dyn();
+        assert!(y.abs_diff_eq(&expected, 1e-4));
+        let (mean, _) = g.running_stats("bn").unwrap();
+        assert_eq!(mean, ag::ndarray::arr2(&[[1., 2.]]).into_dyn());
+    });
+}
+
+#[test]
+fn test_training_mode() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let scale = env.set(ag::ndarray_ext::ones(&[1, 2]));
+    let shift = env.set(ag::ndarray_ext::zeros(&[1, 2]));
+    env.add_running_stats("bn", &[1, 2]);
+    env.run(|g| {
+        assert!(g.is_training());
+        let x = T::convert_to_tensor(ag::ndarray::arr2(&[[1., 2.], [3., 6.]]), g);
+        let (scale, shift) = (g.variable(scale), g.variable(shift));
+        let norm = T::batch_norm_by_mode(x, scale, shift, "bn", 0.5, g);
+        let dropped = T::dropout_by_mode(T::ones(&[100], g), 0.5);
+
+        // training: batch statistics, and the running ones move
+        let y = norm.eval(g).unwrap();
+        let expected = ag::ndarray::arr2(&[[-1., -1.], [1., 1.]]).into_dyn();
+        assert!(y.abs_diff_eq(&expected, 1e-4));
+        let (mean, var) = g.running_stats("bn").unwrap();
+        assert_eq!(mean, ag::ndarray::arr2(&[[1., 2.]]).into_dyn());
+        assert_eq!(var, ag::ndarray::arr2(&[[1.5, 4.5]]).into_dyn());
+        assert!(dropped.eval(g).unwrap().iter().any(|&y| y == 0.));
+
+        // inference, with the same tensors: running statistics, left as they are
+        g.training(false);
+        let y = norm.eval(g).unwrap();
+        let expected = ag::ndarray::arr2(&[[0., 0.], [2. / 1.5f64.sqrt(), 4. / 4.5f64.sqrt()]]);
+        assert!(y.abs_diff_eq(&expected.into_dyn(), 1e-4));
+        let (mean, _) = g.running_stats("bn").unwrap();
+        assert_eq!(mean, ag::ndarray::arr2(&[[1., 2.]]).into_dyn());
+        assert_eq!(
+            dropped.eval(g).unwrap(),
+            ag::ndarray::Array1::from_elem(100, 0.5).into_dyn()
+        );
+
+        // the gradient follows the mode too
+        let gx = T::grad(&[T::reduce_sum(norm * x, &[0, 1], false)], &[x])[0];
+        let gx_inference = gx.eval(g).unwrap();
+        g.training(true);
+        assert_ne!(gx.eval(g).unwrap(), gx_inference);
+    });
+}
+
+#[test]
+fn test_histogram_and_bincount() {
+    use ag::metrics::{bincount, histogram};
+
+    let x = ndarray::arr2(&[[-1., 0., 0.25], [0.3, 0.75, 1.]]).into_dyn();
+    // -1 is out of range and dropped, 1 falls in the last bin
+    assert_eq!(histogram(&x.view(), 4, (0., 1.)), vec![1, 2, 0, 2]);
+    let nan = ndarray::arr1(&[f64::NAN, 0.5]).into_dyn();
+    assert_eq!(histogram(&nan.view(), 2, (0., 1.)), vec![0, 1]);
+
+    let labels = ndarray::arr1(&[3., 0., -1., 3., 1.]).into_dyn();
+    assert_eq!(bincount(&labels.view(), 0), vec![1, 1, 0, 2]);
+    assert_eq!(bincount(&labels.view(), 6), vec![1, 1, 0, 2, 0, 0]);
+}
+
+#[test]
+fn test_global_step_and_warmup() {
+    use ag::optimizers::{Optimizer, TrainStep, SGD};
+    use ag::training::warmup;
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr1(&[0.]));
+    let mut sgd = SGD::new(0.);
+    let mut rates = Vec::new();
+    for _ in 0..4 {
+        env.run(|g| {
+            sgd.alpha = warmup(0.1, g.global_step(), 2);
+            rates.push(sgd.alpha);
+            let w = g.variable(w);
+            let grads = T::grad(&[T::sum_all(w)], &[w]);
+            sgd.update(&[w], &grads, g, ag::Feeder::new());
+        });
+    }
+    assert_eq!(rates, vec![0.05, 0.1, 0.1, 0.1]);
+    assert_eq!(
+        *env.get_array_by_id(w).unwrap().borrow(),
+        ndarray::arr1(&[-0.35]).into_dyn()
+    );
+
+    env.run(|g| {
+        assert_eq!(g.global_step(), 4);
+        let w = g.variable(w);
+        let mut train = TrainStep::new(&sgd, T::sum_all(w), &[w], g);
+        train.step(ag::Feeder::new()).unwrap();
+        assert_eq!(g.global_step(), 5);
+        // an update op evaluated by hand isn't counted
+        let grads = T::grad(&[T::sum_all(w)], &[w]);
+        sgd.get_update_op(&[w], &grads, g).eval(g).unwrap();
+        assert_eq!(g.global_step(), 5);
+        assert_eq!(g.increment_global_step(), 6);
+        g.set_global_step(0);
+    });
+    env.run(|g| assert_eq!(g.global_step(), 0));
+}
+
+#[test]
+fn test_eval_with_timeout() {
+    use std::time::Duration;
+
+    fn slow(x: ag::NdArrayView<f64>) -> ag::NdArray<f64> {
+        std::thread::sleep(Duration::from_millis(20));
+        x.to_owned()
+    }
+
+    ag::run(|g| {
+        let x = T::ones(&[2], g);
+        let mut y = x;
+        for _ in 0..20 {
+            y = y.map(slow);
+        }
+        let z = y * 2.;
+        let ret = g.eval_with_timeout(&[&x, &z], Duration::from_millis(50));
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[1., 1.]).into_dyn()));
+        assert_eq!(
+            ret[1],
+            Err(ag::EvalError::Timeout {
+                limit: Duration::from_millis(50)
+            })
+        );
+        // stopped a few ops past the deadline, well before the end of the chain
+        assert!(g.op_call_count() < 10);
+
+        // the deadline doesn't outlive the call
+        assert_eq!(z.eval(g), Ok(ndarray::arr1(&[2., 2.]).into_dyn()));
+        let ret = g.eval_with_timeout(&[&z], Duration::from_secs(60));
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[2., 2.]).into_dyn()));
+    });
+}
+
+#[test]
+#[should_panic(expected = "reshape: invalid size -2 at index 1")]
+fn test_reshape_rejects_negative_size_on_construction() {
+    ag::run(|g| {
+        let x: ag::Tensor<f32> = T::zeros(&[3, 4], g);
+        // panics here, before anything is evaluated
+        let _ = T::reshape(x, &[3, -2]);
+    });
+}
+
+#[test]
+fn test_construction_time_validation() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    ag::run(|g| {
+        let x: ag::Tensor<f32> = T::zeros(&[1, 1, 4, 4], g);
+        let w: ag::Tensor<f32> = T::zeros(&[1, 1, 2, 2], g);
+        let fails = |f: &dyn Fn()| catch_unwind(AssertUnwindSafe(f)).is_err();
+        assert!(fails(&|| {
+            T::reshape(x, &[-1, -1]);
+        }));
+        assert!(fails(&|| {
+            T::conv2d(x, w, 0, 0);
+        }));
+        assert!(fails(&|| {
+            T::max_pool2d(x, 0, 0, 1);
+        }));
+        assert_eq!(T::reshape(x, &[2, -1]).eval(g).unwrap().shape(), &[2, 8]);
+    });
+}
+
+#[test]
+#[should_panic(expected = "reduce_sum: invalid axis 2 in axes [2] for a tensor of rank 2")]
+fn test_reduce_sum_rejects_axis_out_of_range_on_construction() {
+    ag::run(|g: &mut ag::Context<f32>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let _ = T::reduce_sum(x, &[2], false);
+    });
+}
+
+#[test]
+#[should_panic(expected = "transpose: axes [1, 1] isn't a permutation of 0..2")]
+fn test_transpose_rejects_repeated_axis_on_construction() {
+    ag::run(|g: &mut ag::Context<f32>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let _ = T::transpose(x, &[1, 1]);
+    });
+}
+
+#[test]
+fn test_construction_time_axis_validation() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    ag::run(|g: &mut ag::Context<f32>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let fails = |f: &dyn Fn()| catch_unwind(AssertUnwindSafe(f)).is_err();
+        assert!(fails(&|| {
+            T::reduce_max(x, &[0, -3], false);
+        }));
+        assert!(fails(&|| {
+            T::softmax(x, 2);
+        }));
+        assert!(fails(&|| {
+            T::concat(&[x, x], -3);
+        }));
+        assert!(fails(&|| {
+            T::stack(&[x, x], 3);
+        }));
+        assert!(fails(&|| {
+            T::transpose(x, &[0, 2]);
+        }));
+        assert!(fails(&|| {
+            T::transpose(x, &[2, 0, 1]);
+        }));
+        assert!(fails(&|| {
+            T::slice(x, &[0], &[2]);
+        }));
+        assert!(fails(&|| {
+            T::slice(x, &[0, 1], &[2, 5]);
+        }));
+        assert!(fails(&|| {
+            T::pad(x, &[(1, 1)], T::PadMode::Constant(0.));
+        }));
+
+        // negative axes count from the end
+        T::reduce_mean(x, &[-2], true);
+        T::softmax(x, -1);
+        T::stack(&[x, x], 2);
+        T::slice(x, &[0, 1], &[-1, 3]);
+        T::transpose(x, &[1, 0]);
+        // the rank of an op output isn't known until the evaluation
+        T::reduce_sum(T::exp(x), &[2], false);
+    });
+}
+
+#[test]
+fn test_graph_depth() {
+    ag::run(|g| {
+        let x: ag::Tensor<f64> = g.placeholder("x", &[2]);
+        let short = T::sin(x);
+        let long = T::exp(T::tanh(T::cos(x)));
+        let y = short + long;
+        assert_eq!(g.graph_depth(&[short]), 1);
+        assert_eq!(g.graph_depth(&[long]), 3);
+        // the longer branch decides
+        assert_eq!(g.graph_depth(&[y]), 4);
+        assert_eq!(g.graph_depth(&[short, long]), 3);
+        assert_eq!(g.graph_depth::<ag::Tensor<f64>>(&[]), 0);
+
+        g.set_max_depth(g.graph_depth(&[y]));
+        let feed = ndarray::arr1(&[0., 1.]);
+        assert!(g.evaluator().push(y).feed(x, feed.view()).run()[0].is_ok());
+    });
+}
+
+#[test]
+fn test_loss_scaling() {
+    use ag::optimizers::SGD;
+    use ag::training::LossScaler;
+
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let w = env.set(ndarray::arr1(&[0.5, -3.]));
+    env.run(|g| {
+        let w = g.variable(w);
+        let loss = T::sum_all(T::square(w) * 0.25);
+        let gw = T::grad(&[loss], &[w])[0].eval(g).unwrap();
+        // a power of two scales the gradients exactly, without rounding
+        let scaled = T::grad(&[T::loss_scale(loss, 65536.)], &[w])[0];
+        assert_eq!(scaled.eval(g).unwrap(), &gw * 65536.);
+        let unscaled = T::unscale_grads(&[scaled], 65536.)[0];
+        assert_eq!(unscaled.eval(g).unwrap(), gw);
+    });
+
+    let mut scaler = LossScaler::dynamic(8., 2., 0.5, 2);
+    let sgd = SGD::new(1.);
+    let mut applied = Vec::new();
+    for &x in &[1., f32::INFINITY, 1., 1., 1.] {
+        env.run(|g| {
+            let w = g.variable(w);
+            let loss = scaler.scale_loss(T::sum_all(w * x));
+            let grads = T::grad(&[loss], &[w]);
+            applied.push(
+                scaler
+                    .step(&sgd, &[w], &grads, g, ag::Feeder::new())
+                    .unwrap(),
+            );
+        });
+    }
+    // backed off on the Inf, grown after two good steps in a row
+    assert_eq!(applied, vec![true, false, true, true, true]);
+    assert_eq!(scaler.scale(), 8.);
+    // the skipped step left the weights alone
+    assert_eq!(
+        *env.get_array_by_id(w).unwrap().borrow(),
+        ndarray::arr1(&[-3.5, -7.]).into_dyn()
+    );
+
+    let mut fixed = LossScaler::fixed(4.);
+    assert!(!fixed.update(&[ndarray::arr1(&[f32::NAN]).into_dyn()]));
+    assert_eq!(fixed.scale(), 4.);
+}
+
+#[test]
+fn test_save_and_load_array() {
+    use ag::ndarray_ext::{load_array, save_array};
+
+    let dir = "/tmp/rust-autograd/test/save_and_load_array";
+    std::fs::create_dir_all(dir).unwrap();
+    let path = format!("{}/x.bin", dir);
+
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = rng.standard_normal(&[2, 3, 4]);
+    save_array(&path, &x).unwrap();
+    assert_eq!(load_array::<f64, _>(&path).unwrap(), x);
+    // the element type is recorded
+    assert!(load_array::<f32, _>(&path).is_err());
+
+    // a transposed array is written in logical order
+    let t = x.reversed_axes();
+    save_array(&path, &t).unwrap();
+    assert_eq!(load_array::<f64, _>(&path).unwrap(), t);
+
+    let scalar = ndarray::arr0(1.5f32).into_dyn();
+    save_array(&path, &scalar).unwrap();
+    assert_eq!(load_array::<f32, _>(&path).unwrap(), scalar);
+
+    std::fs::write(&path, b"not an array").unwrap();
+    assert!(load_array::<f32, _>(&path).is_err());
+}
+
+#[test]
+fn test_monte_carlo() {
+    use ag::ndarray_ext::ArrayRng;
+    use ag::rand::Rng;
+
+    ag::run(|g: &mut ag::Context<f64>| {
+        // the exact mean of `x * u` with `u ~ U(0, 2)` is `x`
+        let x = T::convert_to_tensor(ndarray::arr1(&[1., -2., 3.]), g);
+        let estimate = |n, seed| {
+            T::monte_carlo(
+                |_, rng| x * T::random_uniform_rng(ArrayRng::new(rng), &[3], 0., 2., g),
+                n,
+                seed,
+                g,
+            )
+            .eval(g)
+            .unwrap()
+        };
+        let error = |y: ag::NdArray<f64>| (y - &x.eval(g).unwrap()).mapv(f64::abs).sum();
+        let (few, many) = (error(estimate(10, 0)), error(estimate(2000, 0)));
+        assert!(many < 0.15);
+        assert!(many < few);
+        // the same seed gives the same samples
+        assert_eq!(estimate(5, 1), estimate(5, 1));
+        assert_ne!(estimate(5, 1), estimate(5, 2));
+
+        // each copy gets its own rng
+        let mut firsts = Vec::new();
+        T::monte_carlo(
+            |_, mut rng| {
+                firsts.push(rng.gen::<u64>());
+                x
+            },
+            3,
+            7,
+            g,
+        );
+        firsts.dedup();
+        assert_eq!(firsts.len(), 3);
+
+        // the gradient of the average is the average of the gradients
+        let y = T::monte_carlo(|_, _| T::square(x), 4, 0, g);
+        let gx = T::grad(&[T::sum_all(y)], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(ndarray::arr1(&[2., -4., 6.]).into_dyn()));
+    });
+}
+
+#[test]
+fn test_lr_multipliers() {
+    use ag::optimizers::{Adam, Optimizer, SGD};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let init = ndarray::arr1(&[1., -2.]);
+    for &name in &["backbone", "head", "backbone_adam", "head_adam"] {
+        env.slot().name(name).set(init.clone());
+    }
+    let mut sgd = SGD::new(0.1);
+    sgd.lr_multipliers.insert("backbone".to_string(), 0.1);
+    sgd.lr_multipliers.insert("head".to_string(), 2.);
+    let mut adam = Adam::default(
+        "lr_multipliers_adam",
+        env.default_namespace().current_var_ids(),
+        &mut env,
+    );
+    adam.lr_multipliers.insert("backbone_adam".to_string(), 0.5);
+
+    env.run(|g| {
+        let moved = |name: &'static str| {
+            let v = g.variable(name).eval(g).unwrap();
+            (v - &init).mapv(f64::abs).sum()
+        };
+        let loss_and_grads = |a: &'static str, b: &'static str| {
+            let vars = [g.variable(a), g.variable(b)];
+            let loss = T::sum_all(T::square(vars[0]) + T::square(vars[1]));
+            (vars, T::grad(&[loss], &vars))
+        };
+        let (vars, grads) = loss_and_grads("backbone", "head");
+        sgd.update(&vars, &grads, g, ag::Feeder::new());
+        assert!((moved("head") / moved("backbone") - 20.).abs() < 1e-9);
+
+        // Adam's first step moves every element by about its learning rate
+        let (vars, grads) = loss_and_grads("backbone_adam", "head_adam");
+        adam.update(&vars, &grads, g, ag::Feeder::new());
+        assert!((moved("head_adam") / moved("backbone_adam") - 2.).abs() < 1e-6);
+    });
+}
+
+#[test]
+fn test_assert_graphs_equivalent() {
+    use ag::test_helper::assert_graphs_equivalent;
+
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let a = T::sigmoid(x) * 2. - 1.;
+        let b = T::tanh(x * 0.5); // refactored
+        let c = T::tanh(x * 0.51); // a "refactor" that changed the behavior
+        let feeds = [("x", &[5usize, 2][..])];
+        assert_graphs_equivalent(&[a, T::sum_all(a)], &[b, T::sum_all(b)], g, &feeds);
+
+        let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            assert_graphs_equivalent(&[a, T::sum_all(a)], &[a, T::sum_all(c)], g, &feeds)
+        }))
+        .unwrap_err();
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.starts_with("output 1 of the graphs differs"), "{}", msg);
+    });
+}
+
+// Identity whose gradient must not be asked for
+struct NoBackprop;
+
+impl ag::op::Op<f64> for NoBackprop {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<f64>) -> Result<(), ag::op::OpError> {
+        let x = ctx.input(0).to_owned();
+        ctx.append_output(x);
+        Ok(())
+    }
+
+    fn grad(&self, _: &mut ag::op::GradientContext<f64>) {
+        panic!("the backprop went below the requested tensor");
+    }
+}
+
+#[test]
+fn test_grad_of_intermediate() {
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr2(&[[1., -1., 0.5], [2., 0., -1.]]));
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let pre = T::tanh(T::matmul(x, g.variable(w)));
+        let hidden = ag::Tensor::builder(g)
+            .append_input(pre, false)
+            .build(NoBackprop);
+        let loss = T::reduce_sum(T::square(hidden), &[0, 1], false);
+
+        let g_hidden = T::grad(&[loss], &[hidden])[0];
+        let x_val = ndarray::arr2(&[[0.5, 0.1], [-0.2, 0.3], [0., 1.]]);
+        let results = g
+            .evaluator()
+            .extend(&[hidden, g_hidden])
+            .feed("x", x_val.view())
+            .run();
+        let (h, gh) = (results[0].as_ref().unwrap(), results[1].as_ref().unwrap());
+        assert_eq!(gh.shape(), &[3, 3]);
+        assert_eq!(gh, &(h * 2.));
+
+        // with a tensor below the intermediate too, the backprop goes on to it
+        let loss = T::reduce_sum(T::square(pre), &[0, 1], false);
+        let gs = T::grad(&[loss], &[pre, g.variable(w)]);
+        let gw = T::matmul(T::transpose(x, &[1, 0]), gs[0] * (1. - T::square(pre)));
+        let results = g
+            .evaluator()
+            .extend(&[gs[0], gs[1], gw])
+            .feed("x", x_val.view())
+            .run();
+        assert_eq!(results[0].as_ref().unwrap(), gh);
+        ag::test_helper::assert_tensors_close(
+            results[1].as_ref().unwrap(),
+            results[2].as_ref().unwrap(),
+            1e-12,
+            0.,
+        );
+    });
+}
+
+#[test]
+fn test_freeze_after_first() {
+    use ag::optimizers::{Optimizer, SGD};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    env.name("w").set(ndarray::arr1(&[1., 3.]));
+    env.run(|g| {
+        let w = g.variable("w");
+        let frozen = T::freeze_after_first(T::reduce_mean(w, &[0], false));
+        let y = w - frozen;
+        assert_eq!(y.eval(g), Ok(ndarray::arr1(&[-1., 1.]).into_dyn()));
+
+        // the consumers see the new values, the frozen tensor doesn't
+        g.set_variable("w", ndarray::arr1(&[4., 8.]).into_dyn());
+        assert_eq!(y.eval(g), Ok(ndarray::arr1(&[2., 6.]).into_dyn()));
+        let gw = T::grad(&[T::sum_all(w * 3.)], &[w])[0];
+        SGD::new(0.1).update(&[w], &[gw], g, ag::Feeder::new());
+        assert_eq!(frozen.eval(g), Ok(ndarray::arr0(2.).into_dyn()));
+        g.clear_pinned();
+        assert_eq!(frozen.eval(g), Ok(ndarray::arr0(2.).into_dyn()));
+        // constant
+        let gw = T::grad(&[T::sum_all(y)], &[w])[0];
+        assert_eq!(gw.eval(g), Ok(ndarray::arr1(&[1., 1.]).into_dyn()));
+
+        // statistics of the first batch fed
+        let x = g.placeholder("x", &[-1]);
+        let scale = T::freeze_after_first(T::reduce_max(T::abs(x), &[0], false));
+        let normalized = x / scale;
+        let batch = ndarray::arr1(&[-4., 2.]);
+        let out = g.evaluator().push(normalized).feed(x, batch.view()).run();
+        assert_eq!(out[0], Ok(ndarray::arr1(&[-1., 0.5]).into_dyn()));
+        let batch = ndarray::arr1(&[8., 1.]);
+        let out = g.evaluator().push(normalized).feed(x, batch.view()).run();
+        assert_eq!(out[0], Ok(ndarray::arr1(&[2., 0.25]).into_dyn()));
+        assert_eq!(scale.eval(g), Ok(ndarray::arr0(4.).into_dyn()));
+    });
+}
+
+#[test]
+fn test_prune_mask() {
+    use ag::optimizers::{Adam, Optimizer};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr2(&[[0.5, 0.], [0., -1.]]));
+    let adam = Adam::default(
+        "prune_mask_adam",
+        env.default_namespace().current_var_ids(),
+        &mut env,
+    );
+    env.run(|g| {
+        let w = g.variable(w);
+        let mask = T::convert_to_tensor(ndarray::arr2(&[[1., 0.], [0., 1.]]), g);
+        let x = T::convert_to_tensor(ndarray::arr2(&[[1., 2.], [-3., 4.]]), g);
+        let loss = T::sum_all(T::square(T::matmul(x, T::prune_mask(w, mask)) - 1.));
+        let gw = T::grad(&[loss], &[w])[0];
+
+        let gw_val = gw.eval(g).unwrap();
+        assert_eq!((gw_val[[0, 1]], gw_val[[1, 0]]), (0., 0.));
+        assert!(gw_val[[0, 0]] != 0. && gw_val[[1, 1]] != 0.);
+
+        for _ in 0..3 {
+            adam.update(&[w], &[gw], g, ag::Feeder::new());
+        }
+        let w_val = w.eval(g).unwrap();
+        assert_eq!((w_val[[0, 1]], w_val[[1, 0]]), (0., 0.));
+        assert!(w_val[[0, 0]] != 0.5 && w_val[[1, 1]] != -1.);
+    });
+}
+
+// Records the thread it's computed on, the size of the rayon pool it runs in, and the threads
+// its parallel loop ran on
+struct ThreadsOp {
+    callers: std::sync::Arc<std::sync::Mutex<Vec<std::thread::ThreadId>>>,
+    pool_sizes: std::sync::Arc<std::sync::Mutex<Vec<usize>>>,
+    threads: std::sync::Arc<std::sync::Mutex<std::collections::HashSet<std::thread::ThreadId>>>,
+}
+
+impl ag::op::Op<f32> for ThreadsOp {
+    fn compute(&self, ctx: &mut ag::op::ComputeContext<f32>) -> Result<(), ag::op::OpError> {
+        use rayon::prelude::*;
+        self.callers
+            .lock()
+            .unwrap()
+            .push(std::thread::current().id());
+        ctx.install(|| {
+            self.pool_sizes
+                .lock()
+                .unwrap()
+                .push(rayon::current_num_threads());
+            (0..256).into_par_iter().for_each(|_| {
+                std::thread::sleep(std::time::Duration::from_micros(100));
+                let id = std::thread::current().id();
+                self.threads.lock().unwrap().insert(id);
+            });
+        });
+        let x = ctx.input(0).to_owned();
+        ctx.append_output(x);
+        Ok(())
+    }
+
+    fn grad(&self, ctx: &mut ag::op::GradientContext<f32>) {
+        ctx.append_input_grad(None);
+    }
+}
+
+#[test]
+fn test_eval_with_threads() {
+    let callers = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let pool_sizes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let threads = std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashSet::new()));
+    ag::run(|g| {
+        let x = T::ones(&[3], g);
+        let y = ag::Tensor::builder(g)
+            .append_input(x, false)
+            .build(ThreadsOp {
+                callers: callers.clone(),
+                pool_sizes: pool_sizes.clone(),
+                threads: threads.clone(),
+            });
+        let ret = g.eval_with_threads(&[&y], 1);
+        assert_eq!(ret[0], Ok(ndarray::arr1(&[1., 1., 1.]).into_dyn()));
+        assert_eq!(*pool_sizes.lock().unwrap(), vec![1]);
+        // serial
+        assert_eq!(threads.lock().unwrap().len(), 1);
+
+        g.eval_with_threads(&[&y], 3);
+        assert_eq!(pool_sizes.lock().unwrap()[1], 3);
+        // the ops are computed on the calling thread, only their parallel loops are in the pool
+        let caller = std::thread::current().id();
+        assert_eq!(*callers.lock().unwrap(), vec![caller, caller]);
+    });
+}
+
+#[test]
+fn test_tensor_ids_are_deterministic() {
+    fn build_ids() -> Vec<(usize, &'static str)> {
+        let mut env = ag::VariableEnvironment::<f64>::new();
+        let w = env.set(ndarray::arr2(&[[1., 2.], [3., 4.]]));
+        let b = env.set(ndarray::arr1(&[0.5, -0.5]));
+        env.run(|g| {
+            let x = g.placeholder("x", &[-1, 2]);
+            let (w, b) = (g.variable(w), g.variable(b));
+            let y = T::sigmoid(T::matmul(x, w) + b);
+            let loss = T::reduce_mean(T::square(y), &[0, 1], false);
+            let grads = T::grad(&[loss], &[w, b]);
+            let mut tensors = vec![x, w, b, y, loss];
+            tensors.extend(grads);
+            tensors.iter().map(|t| (t.id(), t.op_name())).collect()
+        })
+    }
+    let first = build_ids();
+    assert_eq!(first[0].0, 0);
+    for _ in 0..3 {
+        assert_eq!(build_ids(), first);
+    }
+}
+
+#[test]
+fn test_multi_task() {
+    use ag::optimizers::{MultiTask, SGD};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let trunk = env.set(ndarray::arr2(&[[0.5, -1.], [1., 0.25]]));
+    let head_a = env.set(ndarray::arr2(&[[1.], [2.]]));
+    let head_b = env.set(ndarray::arr2(&[[-1.], [0.5]]));
+    let (fast, slow) = (SGD::new(0.1), SGD::new(0.01));
+    env.run(|g| {
+        let x = T::convert_to_tensor(ndarray::arr2(&[[1., 2.], [-1., 0.5]]), g);
+        let (trunk, head_a, head_b) = (g.variable(trunk), g.variable(head_a), g.variable(head_b));
+        let h = T::tanh(T::matmul(x, trunk));
+        let loss_a = T::mean_all(T::square(T::matmul(h, head_a) - 1.));
+        let loss_b = T::mean_all(T::square(T::matmul(h, head_b) + 2.));
+
+        // each loss reaches its own head only, both reach the trunk
+        let ga = T::grad(&[loss_a], &[head_a, head_b, trunk]);
+        let gb = T::grad(&[loss_b], &[head_a, head_b, trunk]);
+        let before: Vec<_> = [
+            trunk,
+            head_a,
+            head_b,
+            ga[0],
+            ga[1],
+            gb[0],
+            gb[1],
+            ga[2] + gb[2],
+        ]
+        .iter()
+        .map(|t| t.eval(g).unwrap())
+        .collect();
+        assert_eq!(before[4], ndarray::arr2(&[[0.], [0.]]).into_dyn());
+        assert_eq!(before[5], ndarray::arr2(&[[0.], [0.]]).into_dyn());
+        let losses_before = (loss_a.eval(g).unwrap(), loss_b.eval(g).unwrap());
+
+        let mut task = MultiTask::new(&slow, &[trunk], g);
+        task.add_head(&fast, loss_a, &[head_a])
+            .add_head(&slow, loss_b, &[head_b]);
+        let losses = task.step(ag::Feeder::new()).unwrap();
+        assert_eq!((losses[0].clone(), losses[1].clone()), losses_before);
+        assert_eq!(g.global_step(), 1);
+
+        let close = |a: &ag::NdArray<f64>, b: &ag::NdArray<f64>| {
+            ag::test_helper::assert_tensors_close(a, b, 1e-12, 1e-12)
+        };
+        close(
+            &head_a.eval(g).unwrap(),
+            &(&before[1] - &(&before[3] * 0.1)),
+        );
+        close(
+            &head_b.eval(g).unwrap(),
+            &(&before[2] - &(&before[6] * 0.01)),
+        );
+        close(
+            &trunk.eval(g).unwrap(),
+            &(&before[0] - &(&before[7] * 0.01)),
+        );
+    });
+}
+
+#[test]
+fn test_compile_adds_in_place() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let h = T::exp(x);
+        let s = T::sin(x);
+        let y = s + h;
+        // `h` is read twice
+        let z = h + T::square(h);
+        let compiled = g.compile(&[y, z]);
+        assert_eq!(compiled[0].op_name(), "AddInplaceOp");
+        assert_eq!(compiled[1].op_name(), "AddOp");
+
+        let x_value = ndarray::arr2(&[[0.5, -1., 2.], [0., 1., 3.]]);
+        let eval = |ys: &[ag::Tensor<f64>]| {
+            g.reset_counters();
+            let ret = g
+                .evaluator()
+                .extend(ys)
+                .feed(x, x_value.view())
+                .run()
+                .into_iter()
+                .map(|y| y.unwrap())
+                .collect::<Vec<_>>();
+            (ret, g.allocation_count())
+        };
+        let (expected, allocations) = eval(&[y]);
+        let (got, compiled_allocations) = eval(&compiled[..1]);
+        assert_eq!(got, expected);
+        assert_eq!(compiled_allocations, allocations - 1);
+
+        // `s` can't be overwritten when it's evaluated too
+        let (got, compiled_allocations) = eval(&[compiled[0], s]);
+        assert_eq!(got[0], expected[0]);
+        assert_eq!(got[1], x_value.mapv(f64::sin).into_dyn());
+        assert_eq!(compiled_allocations, allocations);
+    });
+}
+
+#[test]
+fn test_checkpoint_restore() {
+    let mut env = ag::VariableEnvironment::new();
+    env.name("w").set(ndarray::arr1(&[1., 2., 3.]));
+    env.run(|g| {
+        let w = g.variable("w");
+        let noise = T::random_normal(&[3], 0., 1., g);
+        let dropped = T::dropout_by_mode(w, 0.5);
+        g.set_global_step(7);
+        // the generators are saved as they are, not as they started
+        noise.eval(g).unwrap();
+        let snapshot = g.checkpoint();
+        let eval = || (noise.eval(g).unwrap(), dropped.eval(g).unwrap());
+        let expected = eval();
+
+        g.set_variable("w", ndarray::arr1(&[0., 0., 0.]).into_dyn());
+        g.increment_global_step();
+        assert_ne!(eval().0, expected.0);
+
+        g.restore(&snapshot);
+        assert_eq!(w.eval(g), Ok(ndarray::arr1(&[1., 2., 3.]).into_dyn()));
+        assert_eq!(g.global_step(), 7);
+        assert_eq!(eval(), expected);
+        // a snapshot can be restored more than once
+        g.restore(&snapshot);
+        assert_eq!(eval(), expected);
+    });
+}
+
+#[test]
+fn test_checkpoint_restore_any_generator() {
+    use ag::ndarray_ext::ArrayRng;
+    use ag::rand::rngs::mock::StepRng;
+    use ag::rand::{Error, RngCore};
+
+    // a generator that isn't `Clone`
+    struct Uncloneable(StepRng);
+
+    impl RngCore for Uncloneable {
+        fn next_u32(&mut self) -> u32 {
+            self.0.next_u32()
+        }
+        fn next_u64(&mut self) -> u64 {
+            self.0.next_u64()
+        }
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.0.fill_bytes(dest)
+        }
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+            self.0.try_fill_bytes(dest)
+        }
+    }
+
+    ag::run(|g: &mut ag::Context<f64>| {
+        let rng = ArrayRng::cloneable(StepRng::new(1, 1 << 60));
+        let noise = T::random_uniform_rng(rng, &[3], 0., 1., g);
+        let rng = ArrayRng::new(Uncloneable(StepRng::new(1, 1 << 60)));
+        let uncaptured = T::random_uniform_rng(rng, &[3], 0., 1., g);
+        let snapshot = g.checkpoint();
+        let expected = noise.eval(g).unwrap();
+        let first = uncaptured.eval(g).unwrap();
+        assert_ne!(noise.eval(g).unwrap(), expected);
+        g.restore(&snapshot);
+        assert_eq!(noise.eval(g).unwrap(), expected);
+        // not replayed
+        assert_ne!(uncaptured.eval(g).unwrap(), first);
+    });
+}
+
+#[test]
+fn test_checkpoint_restore_training_state() {
+    use ag::optimizers::{Adam, Optimizer};
+
+    let mut env = ag::VariableEnvironment::<f64>::new();
+    let w = env.set(ndarray::arr2(&[[1., -2.], [0.5, 3.]]));
+    let scale = env.set(ag::ndarray_ext::ones(&[1, 2]));
+    let shift = env.set(ag::ndarray_ext::zeros(&[1, 2]));
+    env.add_running_stats("bn", &[1, 2]);
+    let adam = Adam::default("checkpoint_adam", [w, scale, shift], &mut env);
+
+    env.run(|g| {
+        let state = |g: &ag::Context<f64>| {
+            let moments: Vec<_> = g
+                .env()
+                .namespace("checkpoint_adam")
+                .iter()
+                .map(|(name, arr)| (name.to_string(), arr.borrow().clone()))
+                .collect();
+            (moments, g.running_stats("bn").unwrap(), g.global_step())
+        };
+        let x = T::convert_to_tensor(ndarray::arr2(&[[1., 2.], [3., -1.], [0., 1.]]), g);
+        let (w, scale, shift) = (g.variable(w), g.variable(scale), g.variable(shift));
+        let h = T::matmul(x, w);
+        let y = T::batch_norm_with_stats(h, scale, shift, "bn", 0.9, true, g);
+        let loss = T::reduce_mean(T::square(y - 1.), &[0, 1], false);
+        let params = [w, scale, shift];
+        let grads = T::grad(&[loss], &params);
+        g.set_global_step(3);
+        let snapshot = g.checkpoint();
+        let expected = state(g);
+
+        adam.update(&params, &grads, g, ag::Feeder::new());
+        let trained = state(g);
+        assert_ne!(trained.0, expected.0);
+        assert_ne!(trained.1, expected.1);
+        assert_ne!(trained.2, expected.2);
+
+        g.restore(&snapshot);
+        assert_eq!(state(g), expected);
+    });
+}
+
+#[test]
+fn test_grad_only() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let w = env.set(rng.standard_normal(&[3, 2]));
+    let b = env.set(rng.standard_normal(&[1, 2]));
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let (w, b) = (g.variable(w), g.variable(b));
+        let loss = T::reduce_mean(T::sigmoid(T::matmul(x, w) + b), &[0, 1], false);
+        let x_value = rng.standard_normal(&[4, 3]);
+
+        let grads = T::grad(&[loss], &[w, b]);
+        let full = g
+            .evaluator()
+            .push(loss)
+            .extend(&grads)
+            .feed(x, x_value.view())
+            .run();
+        let mut feeder = ag::Feeder::new();
+        feeder.push(x, x_value.view());
+        let got = ag::optimizers::grad_only(loss, &[w, b], feeder, g).unwrap();
+        assert_eq!(got.len(), 2);
+        assert_eq!(Ok(got[0].clone()), full[1]);
+        assert_eq!(Ok(got[1].clone()), full[2]);
+    });
+}
+
+#[test]
+fn test_deterministic_max_tie_break() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        g.set_deterministic(true);
+        // transposed, so that the memory order isn't the row-major one:
+        // [[2, 5], [5, 1], [5, 5]]
+        let x = T::convert_to_tensor(ndarray::arr2(&[[2., 5., 5.], [5., 1., 5.]]), g);
+        let x = T::transpose(x, &[1, 0]);
+        let gx_columns = T::grad(&[T::reduce_max(x, &[0], false)], &[x])[0];
+        let gx_all = T::grad(&[T::reduce_max(x, &[0, 1], false)], &[x])[0];
+        let gx_rows = T::grad(&[T::reduce_min(x, &[1], true)], &[x])[0];
+        let expected = [
+            ndarray::arr2(&[[0., 1.], [1., 0.], [0., 0.]]).into_dyn(),
+            ndarray::arr2(&[[0., 1.], [0., 0.], [0., 0.]]).into_dyn(),
+            ndarray::arr2(&[[1., 0.], [0., 1.], [1., 0.]]).into_dyn(),
+        ];
+        for num_threads in [1, 2, 4] {
+            for _ in 0..3 {
+                let got = g.eval_with_threads(&[&gx_columns, &gx_all, &gx_rows], num_threads);
+                for (got, expected) in got.into_iter().zip(&expected) {
+                    assert_eq!(&got.unwrap(), expected);
+                }
+            }
+        }
+
+        // every tie gets the gradient otherwise
+        g.set_deterministic(false);
+        assert_eq!(
+            gx_all.eval(g),
+            Ok(ndarray::arr2(&[[0., 1.], [1., 0.], [1., 1.]]).into_dyn())
+        );
+    });
+}
+
+#[test]
+fn test_op_params() {
+    use ag::op::OpParam;
+
+    ag::run::<f32, _, _>(|g| {
+        let x = T::zeros(&[1, 2, 5, 5], g);
+        let w = T::zeros(&[3, 2, 3, 3], g);
+        let y = T::conv2d(x, w, 1, 2);
+        assert_eq!(y.op_name(), "Conv2D");
+        let params = y.op_params();
+        assert_eq!(params["stride"], OpParam::Int(2));
+        assert_eq!(params["pad"], OpParam::Int(1));
+        assert_eq!(params["dilation"], OpParam::Int(1));
+
+        // ops without parameters have none
+        assert!((x + w).op_params().is_empty());
+    });
+}
+
+#[test]
+fn test_from_spec_mlp() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    env.slot().name("w1").set(rng.glorot_uniform(&[4, 3]));
+    env.slot().name("b1").set(ag::ndarray_ext::zeros(&[1, 3]));
+    env.slot().name("w2").set(rng.glorot_uniform(&[3, 2]));
+
+    env.run(|g| {
+        let nodes = ag::spec::from_spec(
+            "
+            # two-layer perceptron
+            x = placeholder(shape=[-1, 4])
+            t = placeholder(shape=[-1, 1])
+            w1 = variable()
+            b1 = variable()
+            second = variable(name=\"w2\")
+            a = matmul(x, w1)
+            z = add(a, b1)
+            h = relu(z)
+            logits = matmul(h, second)
+            losses = sparse_softmax_cross_entropy(logits, t)
+            loss = mean_all(losses)
+            ",
+            g,
+        )
+        .unwrap();
+
+        let x = g.placeholder("x", &[-1, 4]);
+        let t = g.placeholder("t", &[-1, 1]);
+        let h = T::relu(T::matmul(x, g.variable("w1")) + g.variable("b1"));
+        let logits = T::matmul(h, g.variable("w2"));
+        let loss = T::mean_all(T::sparse_softmax_cross_entropy(logits, t));
+        assert_eq!(g.fingerprint(&[nodes["loss"]]), g.fingerprint(&[loss]));
+
+        let x_value = rng.standard_normal(&[5, 4]);
+        let t_value = ndarray::arr2(&[[0.], [1.], [1.], [0.], [1.]]).into_dyn();
+        let results = g
+            .evaluator()
+            .extend(&[nodes["loss"], loss])
+            .feed("x", x_value.view())
+            .feed("t", t_value.view())
+            .run();
+        assert_eq!(results[0], results[1]);
+    });
+}
+
+#[test]
+fn test_from_spec_errors() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    env.slot().name("w").set(ag::ndarray_ext::zeros(&[2, 2]));
+    env.run(|g| {
+        let error = |spec: &str| {
+            let e = ag::spec::from_spec(spec, g).err().unwrap();
+            (e.line, e.message)
+        };
+        let (line, message) = error("x = placeholder(shape=[2])\ny = foo(x)");
+        assert_eq!((line, message.as_str()), (2, "unknown op `foo`"));
+        let (line, message) = error("y = relu(x)");
+        assert_eq!((line, message.as_str()), (1, "unknown node `x`"));
+        let (_, message) = error("v = variable(name=\"missing\")");
+        assert_eq!(message, "variable `missing` not found");
+        let (_, message) = error("w = variable()\ny = softmax(w, axes=1)");
+        assert_eq!(message, "unknown parameter `axes` of softmax");
+        let (_, message) = error("w = variable()\ny = relu(relu(w))");
+        assert!(message.starts_with("expected `,` or `)`"), "{}", message);
+        let (_, message) = error("w = variable()\nw = relu(w)");
+        assert_eq!(message, "`w` is already defined");
+    });
+}
+
+#[test]
+fn test_eval_with_trace() {
+    let mut env = ag::VariableEnvironment::new();
+    let w = env.set(ag::ndarray_ext::ones::<f32>(&[3, 4]));
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let y = T::matmul(x, g.variable(w));
+        let z = T::reduce_sum(y, &[1], false);
+        let t = T::reshape(z, &[1, -1]);
+
+        let x_value = ag::ndarray_ext::ones(&[2, 3]);
+        let mut feeder = ag::Feeder::new();
+        feeder.push(x, x_value.view());
+        let (ret, trace) = g.eval_with_trace(&[&t], feeder);
+        assert_eq!(ret[0], Ok(ndarray::arr2(&[[12., 12.]]).into_dyn()));
+
+        let shape_of = |tensor: ag::Tensor<f32>| {
+            trace
+                .iter()
+                .find(|(u, _)| u.id() == tensor.id())
+                .map(|(_, shape)| shape.clone())
+        };
+        assert_eq!(shape_of(y), Some(vec![2, 4]));
+        assert_eq!(shape_of(z), Some(vec![2]));
+        assert_eq!(shape_of(t), Some(vec![1, 2]));
+        // the inputs aren't computed
+        assert_eq!(shape_of(x), None);
+        assert_eq!(shape_of(g.variable(w)), None);
+    });
+}
+
+#[test]
+fn test_column_major_outputs() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let a = T::convert_to_tensor(rng.standard_normal(&[3, 4]), g);
+        let b = T::convert_to_tensor(rng.standard_normal(&[4, 5]), g);
+        let y = T::matmul(a, b);
+        let row_major = y.eval(g).unwrap();
+        assert_eq!(row_major.strides(), &[5, 1]);
+        assert!(!g.is_column_major());
+
+        g.set_column_major(true);
+        let column_major = y.eval(g).unwrap();
+        assert_eq!(column_major.strides(), &[1, 3]);
+        assert_eq!(column_major, row_major);
+        // the whole buffer in column-major order
+        let buffer = column_major.as_slice_memory_order().unwrap();
+        assert_eq!(buffer, row_major.t().iter().cloned().collect::<Vec<_>>());
+
+        g.set_column_major(false);
+        assert_eq!(y.eval(g).unwrap().strides(), &[5, 1]);
+    });
+}
+
+#[test]
+fn test_buffer_pool() {
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let mut env = ag::VariableEnvironment::new();
+    let w1 = env.set(rng.glorot_uniform(&[4, 8]));
+    let w2 = env.set(rng.glorot_uniform(&[8, 1]));
+    let x_value = rng.standard_normal(&[6, 4]);
+    env.run(|g| {
+        let x = T::convert_to_tensor(x_value.clone(), g);
+        let h = T::tanh(T::matmul(x, g.variable(w1))) + 1.;
+        let loss = T::mean_all(T::square(T::matmul(h, g.variable(w2))));
+        let grads = T::grad(&[loss], &[g.variable(w1), g.variable(w2)]);
+        let run = || {
+            g.reset_counters();
+            let mut results = g.evaluator().extend(&grads).push(loss).run();
+            let results: Vec<_> = results.drain(..).map(|r| r.unwrap()).collect();
+            (results, g.allocation_count())
+        };
+        let (expected, allocations) = run();
+        assert_eq!(g.buffer_pool_stats(), ag::PoolStats::default());
+
+        g.set_buffer_pool(true);
+        run();
+        let (pooled, pooled_allocations) = run();
+        assert_eq!(pooled, expected);
+        let stats = g.buffer_pool_stats();
+        assert!(stats.hits > 0 && stats.recycled > 0);
+        assert!(pooled_allocations < allocations);
+
+        g.set_buffer_pool(false);
+        assert_eq!(g.buffer_pool_stats(), ag::PoolStats::default());
+    });
+}
+
+#[test]
+fn test_eval_compiled_by_feed_shape() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let y = T::exp(x) + x * T::ones(&T::shape(x), g);
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let eval = |batch_size: usize| {
+            let value = rng.standard_normal(&[batch_size, 3]);
+            let mut feeder = ag::Feeder::new();
+            feeder.push(x, value.view());
+            g.reset_counters();
+            let compiled = g.eval_compiled(&[y], feeder.clone()).remove(0).unwrap();
+            // the shape of `x` was folded into a constant
+            assert!(g.op_call_counts().iter().all(|&(name, _)| name != "Shape"));
+            assert_eq!(
+                compiled,
+                g.evaluator()
+                    .push(y)
+                    .set_feeder(feeder)
+                    .run()
+                    .remove(0)
+                    .unwrap()
+            );
+        };
+
+        eval(4);
+        assert_eq!(g.compilation_count(), 1);
+        eval(4);
+        assert_eq!(g.compilation_count(), 1);
+        eval(2);
+        assert_eq!(g.compilation_count(), 2);
+        eval(4);
+        assert_eq!(g.compilation_count(), 2);
+    });
+}
+
+#[test]
+fn test_eval_compiled_ignores_feed_order() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let w = g.placeholder("w", &[3, 2]);
+        let y = T::matmul(x, w);
+        let x_value = ag::ndarray::Array2::<f64>::ones((4, 3));
+        let w_value = ag::ndarray::Array2::<f64>::ones((3, 2));
+
+        let mut feeder = ag::Feeder::new();
+        feeder.push(x, x_value.view()).push(w, w_value.view());
+        let first = g.eval_compiled(&[y], feeder);
+        let mut feeder = ag::Feeder::new();
+        feeder.push(w, w_value.view()).push(x, x_value.view());
+        let second = g.eval_compiled(&[y], feeder);
+
+        assert_eq!(first, second);
+        assert_eq!(g.compilation_count(), 1);
+    });
+}
+
+#[test]
+fn test_repro_replays_loss_and_gradients() {
+    // the placeholder `x`, the loss and its gradients
+    type Step<'g> = (
+        ag::Tensor<'g, f64>,
+        ag::Tensor<'g, f64>,
+        Vec<ag::Tensor<'g, f64>>,
+    );
+    fn step<'g>(g: &'g ag::Context<f64>) -> Step<'g> {
+        let x = g.placeholder("x", &[-1, 3]);
+        let y = g.placeholder("y", &[-1]);
+        let h = T::tanh(T::matmul(x, g.variable("w")) + g.variable("b"));
+        let loss = T::mean_all(T::square(T::reshape(h, &[-1]) - y));
+        let grads = T::grad(&[loss], &[g.variable("w"), g.variable("b")]);
+        (x, loss, grads)
+    }
+    fn run<'g>(
+        g: &'g ag::Context<f64>,
+        step: &Step<'g>,
+        feeder: ag::Feeder<f64>,
+    ) -> Vec<ag::NdArray<f64>> {
+        let mut ret = g
+            .evaluator()
+            .push(step.1)
+            .extend(&step.2)
+            .set_feeder(feeder)
+            .run();
+        ret.drain(..).map(|r| r.unwrap()).collect()
+    }
+
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let mut env = ag::VariableEnvironment::new();
+    env.slot().name("w").set(rng.standard_normal(&[3, 1]));
+    env.slot().name("b").set(rng.standard_normal(&[1, 1]));
+    let x = rng.standard_normal(&[5, 3]);
+    let y = rng.standard_normal(&[5]);
+    let path =
+        std::env::temp_dir().join(format!("autograd_test_repro_{}.json", std::process::id()));
+
+    let expected = env.run(|g| {
+        let step = step(g);
+        let mut feeder = ag::Feeder::new();
+        // fed by tensor and by name
+        feeder.push(step.0, x.view()).push("y", y.view());
+        ag::debug::dump_repro(&step.1, g, &feeder, &path).unwrap();
+        run(g, &step, feeder)
+    });
+
+    let repro = ag::debug::load_repro::<f64, _>(&path).unwrap();
+    repro.env.run(|g| {
+        let step = step(g);
+        assert!(repro.matches(&step.1, g));
+        assert!(!repro.matches(&T::exp(step.1), g));
+        assert_eq!(run(g, &step, repro.feeder()), expected);
+    });
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_repro_replays_random_ops_after_steps() {
+    use ag::optimizers::{Optimizer, SGD};
+    fn loss<'g>(g: &'g ag::Context<f64>) -> ag::Tensor<'g, f64> {
+        let x = g.placeholder("x", &[-1, 3]);
+        let h = T::dropout(T::matmul(x, g.variable("w")), 0.5, true);
+        T::mean_all(T::square(h))
+    }
+    // the loss scaled by a schedule of the global step
+    fn eval<'g>(g: &'g ag::Context<f64>, loss: ag::Tensor<'g, f64>, x: ag::Feeder<f64>) -> f64 {
+        let loss = g
+            .evaluator()
+            .push(loss)
+            .set_feeder(x)
+            .run()
+            .remove(0)
+            .unwrap();
+        loss[ndarray::IxDyn(&[])] * ag::training::warmup(1., g.global_step(), 10)
+    }
+
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let mut env = ag::VariableEnvironment::new();
+    env.slot().name("w").set(rng.standard_normal(&[3, 4]));
+    let x = rng.standard_normal(&[5, 3]);
+    let path = std::env::temp_dir().join(format!(
+        "autograd_test_repro_steps_{}.json",
+        std::process::id()
+    ));
+    let sgd = SGD::new(0.1);
+
+    // the graph is built once, so its dropout draws new masks at each step
+    let expected = env.run(|g| {
+        let loss = loss(g);
+        let w = g.variable("w");
+        let gw = T::grad(&[loss], &[w]);
+        for _ in 0..3 {
+            let mut feeder = ag::Feeder::new();
+            feeder.push("x", x.view());
+            eval(g, loss, feeder.clone());
+            sgd.update(&[w], &gw, g, feeder);
+        }
+        let mut feeder = ag::Feeder::new();
+        feeder.push("x", x.view());
+        ag::debug::dump_repro(&loss, g, &feeder, &path).unwrap();
+        eval(g, loss, feeder)
+    });
+
+    let repro = ag::debug::load_repro::<f64, _>(&path).unwrap();
+    repro.env.run(|g| {
+        let loss = loss(g);
+        assert!(repro.matches(&loss, g));
+        assert_eq!(g.global_step(), 3);
+        // a fresh dropout draws the masks of the first step
+        assert_ne!(eval(g, loss, repro.feeder()), expected);
+        repro.restore(g);
+        assert_eq!(eval(g, loss, repro.feeder()), expected);
+    });
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,283 @@
+#![cfg(feature = "f16")]
+
+extern crate autograd as ag;
+extern crate ndarray;
+
+use ag::half_storage::HalfArray;
+use ag::optimizers::{Optimizer, SGD};
+use ag::prelude::*;
+use ag::tensor_ops as T;
+
+#[test]
+fn half_array_round_trip() {
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    let x = rng.standard_normal(&[64, 32]);
+    let half = HalfArray::from_f32(&x.view());
+    assert_eq!(half.shape(), &[64, 32]);
+    assert_eq!(half.bytes() * 2, x.len() * std::mem::size_of::<f32>());
+
+    // f16 has an 11-bit significand
+    let y = half.to_f32();
+    for (a, b) in x.iter().zip(y.iter()) {
+        assert!((a - b).abs() <= a.abs() * 2f32.powi(-11), "{} {}", a, b);
+    }
+    // already representable values survive another round trip
+    assert_eq!(HalfArray::from_f32(&y.view()).to_f32(), y);
+}
+
+#[test]
+fn set_and_get_half_variable() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let vid = env.set(ndarray::arr1(&[0.1f32, 2., 3.]));
+    let half = env.get_half(vid);
+    env.set_half(vid, &half);
+    assert_eq!(*env.get_array_by_id(vid).unwrap().borrow(), half.to_f32());
+}
+
+#[test]
+fn store_variable_as_half() {
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    let table_value = rng.standard_normal(&[100, 16]);
+    let rounded = HalfArray::from_f32(&table_value.view()).to_f32();
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let table = env.name("table").set(table_value.clone());
+    env.store_as_half(table);
+
+    // the f32 array is freed
+    assert_eq!(env.get_array_by_id(table).unwrap().borrow().len(), 0);
+    let half = env.get_half(table);
+    assert_eq!(
+        half.bytes() * 2,
+        table_value.len() * std::mem::size_of::<f32>()
+    );
+    assert_eq!(half.to_f32(), rounded);
+
+    env.run(|g| {
+        let ids = T::convert_to_tensor(ndarray::arr1(&[3f32, 7.]), g);
+        let rows = T::gather(g.variable("table"), ids, 0).eval(g).unwrap();
+        assert_eq!(
+            rows,
+            ndarray::stack![
+                ndarray::Axis(0),
+                rounded.slice(ndarray::s![3, ..]),
+                rounded.slice(ndarray::s![7, ..])
+            ]
+            .into_dyn()
+        );
+        assert_eq!(g.variable(table).eval(g).unwrap(), rounded);
+        assert_eq!(g.memory_report().variable_bytes(), 100 * 16 * 2);
+        assert_eq!(g.var_tensors_by_id(g.env()).count(), 1);
+
+        // overwritten in f16
+        g.set_variable(table, &rounded * 2.);
+        assert_eq!(g.variable(table).eval(g).unwrap(), &rounded * 2.);
+    });
+    assert_eq!(env.get_array_by_id(table).unwrap().borrow().len(), 0);
+    env.set_half(table, &HalfArray::from_f32(&rounded.view()));
+    assert_eq!(env.get_half(table).to_f32(), rounded);
+
+    // saved and loaded as f16
+    let path = std::env::temp_dir().join(format!("autograd_test_half_{}.json", std::process::id()));
+    env.save(&path).unwrap();
+    let loaded = ag::VariableEnvironment::<f32>::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.get_array_by_id(table).unwrap().borrow().len(), 0);
+    assert_eq!(loaded.get_half(table), env.get_half(table));
+}
+
+#[test]
+fn half_variable_keeps_master_weights() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let vid = env.set(ag::ndarray_ext::ones(&[2]));
+    env.store_as_half(vid);
+    let opt = SGD::new(1e-4);
+    for _ in 0..20 {
+        env.run(|g| {
+            // each step is smaller than the spacing of f16 around 1
+            let grads = [T::ones(&[2], g)];
+            opt.update(&[g.variable(vid)], &grads, g, ag::Feeder::new());
+        });
+    }
+    let master = env.get_array_by_id(vid).unwrap().borrow().clone();
+    ag::test_helper::assert_tensors_close(
+        &master,
+        &ndarray::arr1(&[0.998f32; 2]).into_dyn(),
+        0.,
+        1e-6,
+    );
+    assert_eq!(env.get_half(vid), HalfArray::from_f32(&master.view()));
+    assert!(env.get_half(vid).to_f32()[0] < 1.);
+    env.run(|g| {
+        assert_eq!(g.variable(vid).eval(g).unwrap(), env.get_half(vid).to_f32());
+        assert_eq!(g.memory_report().variable_bytes(), 2 * 2 + 2 * 4);
+    });
+}
+
+#[test]
+fn restore_half_variable() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let table = env.name("table").set(ndarray::arr1(&[1f32, 0.5]));
+    env.store_as_half(table);
+    let value = env.get_half(table);
+    env.run(|g| {
+        let snapshot = g.checkpoint();
+        g.set_variable(table, ndarray::arr1(&[3f32, 4.]).into_dyn());
+        g.restore(&snapshot);
+        assert_eq!(g.env().get_half(table), value);
+        assert_eq!(g.variable(table).eval(g).unwrap(), value.to_f32());
+
+        let y = T::convert_to_tensor(ndarray::arr1(&[0f32]), g);
+        assert_eq!(g.gc(&[&y]), vec![table]);
+        assert_eq!(g.env().get_half(table).bytes(), 0);
+        g.restore(&snapshot);
+        assert_eq!(g.env().get_half(table), value);
+        assert_eq!(g.variable(table).eval(g).unwrap(), value.to_f32());
+    });
+}
+
+#[test]
+fn train_with_half_embedding_table() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    let table = env.name("table").set(rng.standard_normal(&[10, 3]));
+    env.name("w").set(rng.glorot_uniform(&[3, 1]));
+    env.store_as_half(table);
+    let table_value = env.get_half(table);
+    let opt = SGD::new(0.1);
+
+    let ids_value = ndarray::Array::from_iter((0..64).map(|i| (i % 10) as f32));
+    let x_value = table_value
+        .to_f32()
+        .into_dimensionality::<ndarray::Ix2>()
+        .unwrap()
+        .select(
+            ndarray::Axis(0),
+            &(0..64).map(|i| i % 10).collect::<Vec<_>>(),
+        );
+    let y_value = x_value.dot(&ndarray::arr2(&[[1.5f32], [-2.], [0.5]]));
+
+    let mut losses = Vec::new();
+    for _ in 0..200 {
+        env.run(|g| {
+            let ids = T::convert_to_tensor(ids_value.clone(), g);
+            let y = T::convert_to_tensor(y_value.clone(), g);
+            let x = T::gather(g.variable("table"), ids, 0);
+            let loss = T::reduce_mean(T::square(T::matmul(x, g.variable("w")) - y), &[0, 1], false);
+            let ns = g.default_namespace();
+            // the table stored as f16 is trained along with `w`
+            let (vars, grads) = ag::optimizers::grad_helper(&[loss], &ns);
+            assert_eq!(vars.len(), 2);
+            opt.update(&vars, &grads, g, ag::Feeder::new());
+            losses.push(loss.eval(g).unwrap()[[]]);
+        });
+    }
+    assert!(losses[199] < 1e-3 * losses[0], "{:?}", &losses[190..]);
+    assert_ne!(env.get_half(table), table_value);
+    let master = env.get_array_by_id(table).unwrap().borrow().clone();
+    assert_eq!(env.get_half(table), HalfArray::from_f32(&master.view()));
+}
+
+#[test]
+fn train_with_half_precision_weights() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    env.name("w").set(rng.glorot_uniform(&[3, 1]));
+    let opt = SGD::new(0.1);
+
+    let x_value = rng
+        .standard_normal(&[64, 3])
+        .into_dimensionality::<ndarray::Ix2>()
+        .unwrap();
+    let y_value = x_value.dot(&ndarray::arr2(&[[1.5f32], [-2.], [0.5]]));
+
+    let mut losses = Vec::new();
+    for _ in 0..200 {
+        env.run(|g| {
+            let x = T::convert_to_tensor(x_value.clone(), g);
+            let y = T::convert_to_tensor(y_value.clone(), g);
+            let w = T::half_precision(g.variable("w"));
+            let loss = T::reduce_mean(T::square(T::matmul(x, w) - y), &[0, 1], false);
+            let ns = g.default_namespace();
+            let (vars, grads) = ag::optimizers::grad_helper(&[loss], &ns);
+            opt.update(&vars, &grads, g, ag::Feeder::new());
+            losses.push(loss.eval(g).unwrap()[[]]);
+        });
+    }
+    assert!(losses[199] < 1e-3 * losses[0], "{:?}", &losses[190..]);
+}
+
+#[test]
+fn autocast_reduces_memory_within_tolerance() {
+    let mut env = ag::VariableEnvironment::<f32>::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f32>::default();
+    env.name("x").set(rng.standard_normal(&[64, 32]));
+    env.name("w1").set(rng.glorot_uniform(&[32, 64]));
+    env.name("w2").set(rng.glorot_uniform(&[64, 32]));
+    env.run(|g| {
+        fn loss_of<'g>(g: &'g ag::Context<f32>) -> ag::Tensor<'g, f32> {
+            let h = T::matmul(
+                T::matmul(g.variable("x"), g.variable("w1")),
+                g.variable("w2"),
+            );
+            T::reduce_mean(T::square(T::tanh(h) - 0.5), &[0, 1], false)
+        }
+        let full = loss_of(g);
+        let half = g.autocast(|g| loss_of(g));
+        let full_value = full.eval(g).unwrap()[[]];
+        let full_report = g.memory_report();
+        let half_value = half.eval(g).unwrap()[[]];
+        let half_report = g.memory_report();
+
+        assert_ne!(full_value, half_value);
+        assert!(
+            (full_value - half_value).abs() <= 1e-3 * full_value.abs(),
+            "{} {}",
+            full_value,
+            half_value
+        );
+        // the matmul outputs are stored as f16 at the peak
+        assert!(
+            half_report.output_bytes() < full_report.output_bytes(),
+            "{:?} {:?}",
+            half_report.outputs,
+            full_report.outputs
+        );
+        let matmuls: Vec<_> = half_report
+            .outputs
+            .iter()
+            .filter(|m| m.name.contains("MatMul"))
+            .collect();
+        assert!(!matmuls.is_empty());
+        for m in matmuls {
+            assert_eq!(m.bytes, m.shape.iter().product::<usize>() * 2);
+        }
+
+        // the gradients are those of a straight-through rounding
+        let w = g.variable("w1");
+        let gw = g.autocast(|g| T::grad(&[loss_of(g)], &[w])[0]);
+        let gw_full = T::grad(&[loss_of(g)], &[w])[0];
+        ag::test_helper::assert_tensors_close(
+            &gw.eval(g).unwrap(),
+            &gw_full.eval(g).unwrap(),
+            1e-3,
+            1e-2,
+        );
+    });
+}
+
+#[test]
+fn autocast_full_precision_allowlist() {
+    ag::run(|g: &mut ag::Context<f32>| {
+        let a = T::convert_to_tensor(ndarray::arr2(&[[1.0001f32, 2.], [3., 4.0001]]), g);
+        let full = T::matmul(a, a).eval(g).unwrap();
+        let kept = g.autocast_with_full_precision(&["matmul"], |_| T::matmul(a, a));
+        assert_eq!(kept.eval(g).unwrap(), full);
+        let cast = g.autocast(|_| T::matmul(a, a));
+        assert_ne!(cast.eval(g).unwrap(), full);
+
+        // ops out of `AUTOCAST_OPS` aren't affected
+        let sum = g.autocast(|_| T::reduce_sum(a, &[0, 1], false));
+        assert_eq!(sum.eval(g).unwrap()[[]], 10.0002);
+        assert!(!g.is_autocast());
+    });
+}
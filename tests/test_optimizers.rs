@@ -62,3 +62,133 @@ fn run<O: Optimizer<f64>>(opt: O, env: VariableEnvironment<f64>) {
         opt.update(&vars, &grads, g, ag::Feeder::new());
     });
 }
+
+fn mean_loss<'g>(x: ag::Tensor<'g, f64>, g: &'g ag::Context<f64>) -> ag::Tensor<'g, f64> {
+    let y = T::convert_to_tensor(array![1., 0.], g);
+    let z = T::matmul(x, g.variable("w")) + g.variable("b");
+    T::reduce_mean(T::sparse_softmax_cross_entropy(z, &y), &[0], false)
+}
+
+#[test]
+fn test_train_step_matches_rebuilding() {
+    let batches = [array![[0.1, 0.2], [0.2, 0.1]], array![[1., -1.], [0.5, 2.]]];
+    let mut env = make_env();
+    let opt = optimizers::Adam::default(
+        "train_step_adam",
+        env.default_namespace().current_var_ids(),
+        &mut env,
+    );
+    let rebuilt = env.clone();
+
+    let mut expected = Vec::new();
+    for batch in batches.iter().cycle().take(3) {
+        rebuilt.run(|g| {
+            let x = g.placeholder("x", &[-1, 2]);
+            let loss = mean_loss(x, g);
+            let ns = g.default_namespace();
+            let (vars, grads) = optimizers::grad_helper(&[loss], &ns);
+            let update = opt.get_update_op(&vars, &grads, g);
+            let mut ret = g
+                .evaluator()
+                .push(update)
+                .push(loss)
+                .feed(x, batch.view())
+                .run();
+            expected.push(ret.pop().unwrap().unwrap());
+        });
+    }
+
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 2]);
+        let loss = mean_loss(x, g);
+        let ns = g.default_namespace();
+        let vars: Vec<_> = g.var_tensors_by_name(&ns).map(|(_, t)| t).collect();
+        let mut step = optimizers::TrainStep::new(&opt, loss, &vars, g);
+        assert_eq!(step.loss().id(), loss.id());
+
+        for (batch, expected) in batches.iter().cycle().zip(&expected) {
+            let mut feeder = ag::Feeder::new();
+            feeder.push(x, batch.view());
+            assert_eq!(&step.step(feeder).unwrap(), expected);
+        }
+    });
+    for name in ["w", "b"] {
+        let ns = env.default_namespace();
+        let other = rebuilt.default_namespace();
+        assert_eq!(
+            *ns.get_array_by_name(name).unwrap().borrow(),
+            *other.get_array_by_name(name).unwrap().borrow()
+        );
+    }
+}
+
+#[test]
+fn test_grad_accumulator_across_graphs() {
+    use ag::training::GradAccumulator;
+
+    let mut env = VariableEnvironment::<f64>::new();
+    env.slot().name("encoder").set(array![1., -2.]);
+    env.slot().name("head").set(array![3., 4.]);
+    let mut acc = GradAccumulator::new();
+
+    // two graphs sharing the encoder
+    env.run(|g| {
+        let (encoder, head) = (g.variable("encoder"), g.variable("head"));
+        let loss = T::sum_all(encoder * head);
+        acc.accumulate(loss, &[encoder, head], ag::Feeder::new(), g)
+            .unwrap();
+    });
+    env.run(|g| {
+        let encoder = g.variable("encoder");
+        let loss = T::sum_all(T::square(encoder));
+        acc.accumulate(loss, &[encoder], ag::Feeder::new(), g)
+            .unwrap();
+    });
+
+    // the gradients of the sum of both losses, in a single graph
+    let expected = env.run(|g| {
+        let (encoder, head) = (g.variable("encoder"), g.variable("head"));
+        let loss = T::sum_all(encoder * head) + T::sum_all(T::square(encoder));
+        optimizers::grad_only(loss, &[encoder, head], ag::Feeder::new(), g).unwrap()
+    });
+    assert_eq!(acc.get("", "encoder"), Some(&expected[0]));
+    assert_eq!(acc.get("", "head"), Some(&expected[1]));
+    assert_eq!(expected[0], array![5., 0.].into_dyn());
+
+    env.run(|g| {
+        let (encoder, head) = (g.variable("encoder"), g.variable("head"));
+        acc.step(&optimizers::SGD::new(0.1), &[encoder, head], g);
+        assert_eq!(acc.get("", "encoder"), None);
+        assert_eq!(encoder.eval(g), Ok(array![0.5, -2.].into_dyn()));
+        assert_eq!(head.eval(g), Ok(array![2.9, 4.2].into_dyn()));
+    });
+}
+
+#[test]
+fn test_grad_accumulator_keeps_namespaces_apart() {
+    use ag::training::GradAccumulator;
+
+    let mut env = VariableEnvironment::<f64>::new();
+    env.namespace_mut("layer1").slot().name("w").set(array![1., 2.]);
+    env.namespace_mut("layer2").slot().name("w").set(array![[3.]]);
+    let mut acc = GradAccumulator::new();
+
+    for _ in 0..2 {
+        env.run(|g| {
+            let (w1, w2) = (g.variable(("layer1", "w")), g.variable(("layer2", "w")));
+            let loss = T::sum_all(w1) + T::sum_all(w2 * 2.);
+            acc.accumulate(loss, &[w1, w2], ag::Feeder::new(), g)
+                .unwrap();
+        });
+    }
+    assert_eq!(acc.get("layer1", "w"), Some(&array![2., 2.].into_dyn()));
+    assert_eq!(acc.get("layer2", "w"), Some(&array![[4.]].into_dyn()));
+    assert_eq!(acc.get("", "w"), None);
+
+    env.run(|g| {
+        let (w1, w2) = (g.variable(("layer1", "w")), g.variable(("layer2", "w")));
+        acc.step(&optimizers::SGD::new(0.5), &[w1, w2], g);
+        assert_eq!(w1.eval(g), Ok(array![0., 1.].into_dyn()));
+        assert_eq!(w2.eval(g), Ok(array![[1.]].into_dyn()));
+    });
+}
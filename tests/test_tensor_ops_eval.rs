@@ -17,6 +17,118 @@ fn reduce_prod() {
     });
 }
 
+#[test]
+fn reduce_prod_gradient_with_zeros() {
+    let mut env = VariableEnvironment::new();
+    let x = env
+        .slot()
+        .set(array![[2., 0., 3.], [1., 2., 3.], [0., 5., 0.]]);
+    env.run(|g| {
+        let x = g.variable(x);
+        let y = T::reduce_prod(x, &[1], false);
+        assert_eq!(y.eval(g), Ok(array![0., 6., 0.].into_dyn()));
+        let gx = T::grad(&[y], &[x])[0];
+        // only the zero of the first row sees a nonzero product of the others
+        assert_eq!(
+            gx.eval(g),
+            Ok(array![[0., 6., 0.], [6., 3., 2.], [0., 0., 0.]].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn count_nonzero_any_all() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[0., 2., 0.], [-1., 0.5, 3.], [0., 0., 0.]], g);
+        assert_eq!(
+            T::count_nonzero(x, &[1], false).eval(g),
+            Ok(array![1., 3., 0.].into_dyn())
+        );
+        assert_eq!(
+            x.count_nonzero(&[0, 1], false).eval(g),
+            Ok(ndarray::arr0(4.).into_dyn())
+        );
+        assert_eq!(
+            T::reduce_any(x, &[-1], true).eval(g),
+            Ok(array![[1.], [1.], [0.]].into_dyn())
+        );
+        assert_eq!(
+            T::reduce_all(x, &[1], false).eval(g),
+            Ok(array![0., 1., 0.].into_dyn())
+        );
+        assert_eq!(
+            T::reduce_all(x, &[0], false).eval(g),
+            Ok(array![0., 0., 0.].into_dyn())
+        );
+
+        // reductions of an empty axis give the identity elements
+        let empty = T::zeros(&[2, 0], g);
+        assert_eq!(
+            T::count_nonzero(empty, &[1], false).eval(g),
+            Ok(array![0., 0.].into_dyn())
+        );
+        assert_eq!(
+            T::reduce_any(empty, &[1], false).eval(g),
+            Ok(array![0., 0.].into_dyn())
+        );
+        assert_eq!(
+            T::reduce_all(empty, &[1], false).eval(g),
+            Ok(array![1., 1.].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn arange_linspace_eye() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        assert_eq!(
+            T::arange(1., 4., 1., g).eval(g),
+            Ok(array![1., 2., 3.].into_dyn())
+        );
+        assert_eq!(
+            T::arange(3., 0., -1.5, g).eval(g),
+            Ok(array![3., 1.5].into_dyn())
+        );
+        assert_eq!(T::arange(0., 0., 1., g).eval(g).unwrap().shape(), &[0]);
+        assert_eq!(
+            T::linspace(-1., 1., 3, g).eval(g),
+            Ok(array![-1., 0., 1.].into_dyn())
+        );
+        assert_eq!(T::linspace(0., 1., 0, g).eval(g).unwrap().shape(), &[0]);
+        assert_eq!(
+            T::eye(3, g).eval(g),
+            Ok(array![[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn meshgrid_shapes() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = T::arange(0., 3., 1., g);
+        let y = T::arange(0., 4., 1., g);
+        let z = T::arange(0., 5., 1., g);
+
+        for grid in T::meshgrid(&[x, y], T::MeshgridIndexing::Ij) {
+            assert_eq!(grid.eval(g).unwrap().shape(), &[3, 4]);
+        }
+        for grid in T::meshgrid(&[x, y], T::MeshgridIndexing::Xy) {
+            assert_eq!(grid.eval(g).unwrap().shape(), &[4, 3]);
+        }
+        for grid in T::meshgrid(&[x, y, z], T::MeshgridIndexing::Ij) {
+            assert_eq!(grid.eval(g).unwrap().shape(), &[3, 4, 5]);
+        }
+        let grids = T::meshgrid(&[x, y, z], T::MeshgridIndexing::Xy);
+        for grid in &grids {
+            assert_eq!(grid.eval(g).unwrap().shape(), &[4, 3, 5]);
+        }
+        let c = grids[2].eval(g).unwrap();
+        assert_eq!(c[[1, 2, 3]], 3.);
+        let a = grids[0].eval(g).unwrap();
+        assert_eq!(a[[1, 2, 3]], 2.);
+    });
+}
+
 #[test]
 fn argmax() {
     ag::run(|g| {
@@ -183,3 +295,1157 @@ fn test_batch_matmul_with_copy() {
         assert_eq!(ret.shape(), &[2, 2, 2]);
     });
 }
+
+#[test]
+fn einsum() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+        let b = T::convert_to_tensor(array![[1., 2.], [3., 4.], [5., 6.]], g);
+        let v = T::convert_to_tensor(array![1., 2.], g);
+
+        let mm = T::einsum("ij,jk->ik", &[a, b]);
+        assert_eq!(mm.eval(g), T::matmul(a, b).eval(g));
+        let bmm = T::einsum(
+            "bij,bjk->bik",
+            &[T::expand_dims(a, &[0]), T::expand_dims(b, &[0])],
+        );
+        assert_eq!(bmm.eval(g), Ok(array![[[22., 28.], [49., 64.]]].into_dyn()));
+        let t = T::einsum("ij->ji", &[a]);
+        assert_eq!(
+            t.eval(g),
+            Ok(array![[1., 4.], [2., 5.], [3., 6.]].into_dyn())
+        );
+        let tr = T::einsum("ii->", &[mm]);
+        assert_eq!(tr.eval(g), Ok(ndarray::arr0(86.).into_dyn()));
+        let outer = T::einsum("i,j->ij", &[v, v]);
+        assert_eq!(outer.eval(g), Ok(array![[1., 2.], [2., 4.]].into_dyn()));
+    });
+}
+
+#[test]
+fn einsum_label_mismatch() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+        let mm = T::einsum("ij,jk->ik", &[a, a]);
+        assert!(mm.eval(g).is_err());
+    });
+}
+
+#[test]
+fn outer_and_dot_reject_non_vectors() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+        let v = T::convert_to_tensor(array![1., 2.], g);
+        assert!(T::outer(a, v).eval(g).is_err());
+        assert!(T::dot(v, a).eval(g).is_err());
+        assert!(T::dot(v, T::convert_to_tensor(array![1., 2., 3.], g))
+            .eval(g)
+            .is_err());
+    });
+}
+
+#[test]
+fn trace_and_diag_reject_non_square() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+        assert!(T::trace(a).eval(g).is_err());
+        assert!(T::diag(a).eval(g).is_err());
+        assert!(T::diag(T::expand_dims(a, &[0])).eval(g).is_err());
+    });
+}
+
+#[test]
+fn cholesky_not_positive_definite() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2.], [2., 1.]], g);
+        match T::cholesky(a).eval(g) {
+            Err(ag::EvalError::OpError {
+                error: ag::op::OpError::NotPositiveDefinite(_),
+                ..
+            }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn svd_reconstruction() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+        let (u, s, v) = T::svd(a);
+        let a2 = T::matmul(T::matmul(u, T::diag(s)), T::transpose(v, &[1, 0]));
+        let utu = T::matmul(T::transpose(u, &[1, 0]), u);
+        assert!(a2.eval(g).unwrap().abs_diff_eq(&a.eval(g).unwrap(), 1e-10));
+        assert!(utu
+            .eval(g)
+            .unwrap()
+            .abs_diff_eq(&ndarray::Array2::eye(2).into_dyn(), 1e-10));
+        assert_eq!(v.eval(g).unwrap().shape(), &[3, 2]);
+    });
+}
+
+#[test]
+fn lstsq_overdetermined_and_rank_deficient() {
+    ag::run(|g| {
+        // the normal equations of the 4 x 2 system give `x = [[3.5], [1.4]]`
+        let a = T::convert_to_tensor(array![[1., 1.], [1., 2.], [1., 3.], [1., 4.]], g);
+        let b = T::convert_to_tensor(array![[6.], [5.], [7.], [10.]], g);
+        let x = T::lstsq(a, b).eval(g).unwrap();
+        assert!(x.abs_diff_eq(&array![[3.5], [1.4]].into_dyn(), 1e-10));
+
+        // both columns are the same: the minimum norm solution splits the weight evenly
+        let a = T::convert_to_tensor(array![[1., 1.], [2., 2.], [3., 3.]], g);
+        let b = T::convert_to_tensor(array![[2.], [4.], [6.]], g);
+        let x = T::lstsq(a, b).eval(g).unwrap();
+        assert!(x.abs_diff_eq(&array![[1.], [1.]].into_dyn(), 1e-10));
+
+        // underdetermined
+        let a = T::convert_to_tensor(array![[1., 0., 1.]], g);
+        let b = T::convert_to_tensor(array![[2.]], g);
+        let x = T::lstsq(a, b).eval(g).unwrap();
+        assert!(x.abs_diff_eq(&array![[1.], [0.], [1.]].into_dyn(), 1e-10));
+    });
+}
+
+#[test]
+fn pairwise_distance_of_identical_rows() {
+    let mut env = VariableEnvironment::new();
+    let v = env.slot().set(array![[1., 2.], [3., 4.]]);
+    env.run(|g| {
+        let v = g.variable(v);
+        let d = T::pairwise_distance(v, v);
+        let grad = T::grad(&[d], &[v])[0];
+        let d = d.eval(g).unwrap();
+        assert!(d[ndarray::IxDyn(&[0, 0])] < 1e-6 && d[ndarray::IxDyn(&[1, 1])] < 1e-6);
+        assert!(grad.eval(g).unwrap().iter().all(|x: &f64| x.is_finite()));
+    });
+}
+
+#[test]
+fn cosine_similarity_of_zero_vectors() {
+    let mut env = VariableEnvironment::new();
+    let a = env.slot().set(array![[0., 0., 0.], [1., 2., 3.]]);
+    let b = env.slot().set(array![[1., -1., 2.], [0., 0., 0.]]);
+    env.run(|g| {
+        let (a, b) = (g.variable(a), g.variable(b));
+        let c = T::cosine_similarity(a, b, 1);
+        let grads = T::grad(&[c], &[a, b]);
+        assert_eq!(c.eval(g), Ok(array![0., 0.].into_dyn()));
+        for grad in grads {
+            assert!(grad.eval(g).unwrap().iter().all(|&x: &f64| x == 0.));
+        }
+    });
+}
+
+#[test]
+fn l2_normalize_unit_norm() {
+    let mut env = VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.slot().set(rng.standard_normal(&[3, 4]));
+    let zero = env.slot().set(array![[0., 0.], [1., 1.]]);
+    env.run(|g| {
+        let y = T::l2_normalize(g.variable(x), 0, 1e-12).eval(g).unwrap();
+        let norms = y.mapv(|a| a * a).sum_axis(ndarray::Axis(0));
+        assert!(norms.iter().all(|&n| (n - 1.).abs() < 1e-12));
+
+        let zero = g.variable(zero);
+        let y = T::l2_normalize(zero, 1, 1e-6);
+        let half = 0.5f64.sqrt();
+        assert!(y
+            .eval(g)
+            .unwrap()
+            .abs_diff_eq(&array![[0., 0.], [half, half]].into_dyn(), 1e-12));
+        let grad = T::grad(&[y], &[zero])[0].eval(g).unwrap();
+        assert!(grad.iter().all(|x| x.is_finite()));
+    });
+}
+
+#[test]
+fn triplet_and_contrastive_loss_hinges() {
+    let mut env = VariableEnvironment::new();
+    let anchor = env.slot().set(array![[0., 0.], [0., 0.]]);
+    env.run(|g| {
+        let anchor = g.variable(anchor);
+        // the first triplet satisfies the margin, the second doesn't
+        let positive = T::convert_to_tensor(array![[1., 0.], [3., 0.]], g);
+        let negative = T::convert_to_tensor(array![[0., 4.], [0., 2.]], g);
+        let loss = T::triplet_loss(anchor, positive, negative, 1.);
+        let grad = T::grad(&[loss], &[anchor])[0].eval(g).unwrap();
+        assert!(loss
+            .eval(g)
+            .unwrap()
+            .abs_diff_eq(&array![0., 2.].into_dyn(), 1e-6));
+        assert_eq!(grad.slice(ndarray::s![0, ..]), array![0., 0.]);
+        // d(d_pos - d_neg)/d(anchor) = -(p - a)/|p - a| + (n - a)/|n - a|
+        assert!(grad
+            .slice(ndarray::s![1, ..])
+            .abs_diff_eq(&array![-1., 1.], 1e-6));
+
+        // a dissimilar pair outside the margin, and one inside it
+        let b = T::convert_to_tensor(array![[0., 3.], [0., 1.]], g);
+        let label = T::convert_to_tensor(array![0., 0.], g);
+        let loss = T::contrastive_loss(anchor, b, label, 2.);
+        let grad = T::grad(&[loss], &[anchor])[0].eval(g).unwrap();
+        assert!(loss
+            .eval(g)
+            .unwrap()
+            .abs_diff_eq(&array![0., 1.].into_dyn(), 1e-6));
+        assert_eq!(grad.slice(ndarray::s![0, ..]), array![0., 0.]);
+        // d((2 - d)^2)/d(anchor) = 2 (2 - d) (b - a)/|b - a|
+        assert!(grad
+            .slice(ndarray::s![1, ..])
+            .abs_diff_eq(&array![0., 2.], 1e-6));
+    });
+}
+
+#[test]
+fn conv_to_dense_transition() {
+    ag::run(|g| {
+        let x: ag::Tensor<f64> = T::ones(&[2, 3, 5, 5], g);
+        let w: ag::Tensor<f64> = T::ones(&[4, 3, 3, 3], g);
+        let h = T::conv2d(x, w, 0, 1); // (2, 4, 3, 3)
+        let dense_w: ag::Tensor<f64> = T::ones(&[4 * 3 * 3, 10], g);
+        let y = T::matmul(T::flatten(h, 1), dense_w);
+        assert_eq!(y.eval(g).unwrap().shape(), &[2, 10]);
+        let y = T::matmul(T::global_avg_pool2d(h), T::ones(&[4, 10], g));
+        assert_eq!(y.eval(g).unwrap().shape(), &[2, 10]);
+        assert_eq!(T::global_max_pool2d(h).eval(g).unwrap().shape(), &[2, 4]);
+    });
+}
+
+#[test]
+fn pad_2d() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+        let y = T::pad(x, &[(1, 0), (0, 1)], T::PadMode::Edge);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[1., 2., 2.], [1., 2., 2.], [3., 4., 4.]].into_dyn())
+        );
+        // wraps around more than once
+        let y = T::pad(x, &[(0, 0), (3, 0)], T::PadMode::Reflect);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[2., 1., 2., 1., 2.], [4., 3., 4., 3., 4.]].into_dyn())
+        );
+        // the rank of an op output is checked at evaluation
+        assert!(T::pad(x * 2., &[(1, 1)], T::PadMode::Constant(0.))
+            .eval(g)
+            .is_err());
+    });
+}
+
+#[test]
+fn roll_wraps_around() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![1., 2., 3., 4.], g);
+        assert_eq!(
+            T::roll(x, &[1], &[0]).eval(g),
+            Ok(array![4., 1., 2., 3.].into_dyn())
+        );
+        assert_eq!(
+            T::roll(x, &[-1], &[0]).eval(g),
+            Ok(array![2., 3., 4., 1.].into_dyn())
+        );
+        // more than the length
+        assert_eq!(
+            T::roll(x, &[6], &[-1]).eval(g),
+            Ok(array![3., 4., 1., 2.].into_dyn())
+        );
+        assert!(T::roll(x, &[1], &[1]).eval(g).is_err());
+    });
+}
+
+#[test]
+fn flip_invalid_axes() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+        assert!(T::flip(x, &[2]).eval(g).is_err());
+        assert!(T::flip(x, &[1, -1]).eval(g).is_err());
+        assert_eq!(
+            T::flip(x, &[]).eval(g),
+            Ok(array![[1., 2.], [3., 4.]].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn reduce_sum_kahan_error() {
+    ag::run(|g| {
+        // a large value followed by many small ones, each below half an ulp of the running sum
+        let mut values = vec![1e4f32];
+        values.extend(std::iter::repeat(4e-4f32).take(100_000));
+        let exact: f64 = values.iter().map(|&a| a as f64).sum();
+
+        let x = T::convert_to_tensor(ndarray::Array1::from(values).into_dyn(), g);
+        let naive = T::reduce_sum(x, &[0], false).eval(g).unwrap()[[]];
+        let kahan = T::reduce_sum_kahan(x, &[0], false).eval(g).unwrap()[[]];
+        let naive_err = (naive as f64 - exact).abs();
+        let kahan_err = (kahan as f64 - exact).abs();
+        assert!(naive_err > 10., "{}", naive_err);
+        assert!(kahan_err < 0.1, "{}", kahan_err);
+    });
+}
+
+#[test]
+fn broadcast_to() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., 2., 3., 4.]], g);
+        let y = T::broadcast_to(x, &[3, 4]);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[1., 2., 3., 4.], [1., 2., 3., 4.], [1., 2., 3., 4.]].into_dyn())
+        );
+        let gx = T::grad(&[y], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(array![[3., 3., 3., 3.]].into_dyn()));
+        assert!(T::broadcast_to(x, &[3, 2]).eval(g).is_err());
+        assert!(T::broadcast_to(x, &[4]).eval(g).is_err());
+    });
+}
+
+#[test]
+fn weighted_mean_zero_weights() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., 2.], [3., 4.]], g);
+        let w = T::convert_to_tensor(array![[0., 0.], [1., 1.]], g);
+        let y = T::weighted_mean(x, w, &[1], false);
+        assert_eq!(y.eval(g), Ok(array![0., 3.5].into_dyn()));
+        let gs = T::grad(&[y], &[x, w]);
+        // no gradient to the unweighted row of `x`, and that of the sum to its weights
+        assert_eq!(gs[0].eval(g), Ok(array![[0., 0.], [0.5, 0.5]].into_dyn()));
+        assert_eq!(
+            gs[1].eval(g),
+            Ok(array![[1., 2.], [-0.25, 0.25]].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn focal_loss_without_focusing_is_cross_entropy() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let y = T::convert_to_tensor(rng.standard_normal(&[2, 3]), g);
+        let binary = T::convert_to_tensor(array![[1., 0., 0.], [0., 1., 1.]], g);
+        // a single row, as the gradient of `softmax_cross_entropy` takes (batch_size, 1)
+        let y_row = T::slice(y, &[0, 0], &[1, -1]);
+        let one_hot = T::convert_to_tensor(array![[0., 1., 0.]], g);
+        let pairs = [
+            (
+                y,
+                T::focal_loss(y, binary, 0., -1.),
+                T::sigmoid_cross_entropy(y, binary),
+            ),
+            (
+                y_row,
+                T::softmax_focal_loss(y_row, one_hot, 0.),
+                T::softmax_cross_entropy(y_row, one_hot),
+            ),
+        ];
+        for (y, focal, xent) in pairs.iter() {
+            let (g_focal, g_xent) = (T::grad(&[focal], &[y])[0], T::grad(&[xent], &[y])[0]);
+            let ys = g
+                .evaluator()
+                .extend(&[*focal, *xent, g_focal, g_xent])
+                .run();
+            let ys: Vec<_> = ys.into_iter().map(|y| y.unwrap()).collect();
+            assert!(ys[0].abs_diff_eq(&ys[1], 1e-10));
+            assert!(ys[2].abs_diff_eq(&ys[3], 1e-10));
+        }
+    });
+}
+
+#[test]
+fn iou_and_giou() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(
+            array![
+                [0., 0., 4., 2.],
+                [0., 0., 2., 2.],
+                [1., 1., 1., 1.],
+                [0., 0., 1., 1.]
+            ],
+            g,
+        );
+        let b = T::convert_to_tensor(
+            array![
+                [2., 0., 6., 2.],
+                [0., 0., 2., 2.],
+                [1., 1., 1., 1.],
+                [1., 0., 2., 1.]
+            ],
+            g,
+        );
+        // half overlapping, identical, zero-area and touching boxes
+        let iou = T::iou(a, b).eval(g).unwrap();
+        assert!(iou.abs_diff_eq(&array![4. / 12., 1., 0., 0.].into_dyn(), 1e-6));
+        let giou = T::giou(a, b).eval(g).unwrap();
+        assert!(giou.abs_diff_eq(&array![4. / 12., 1., 0., 0.].into_dyn(), 1e-6));
+    });
+}
+
+#[test]
+fn comparisons_broadcast() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![[1.], [2.], [3.]], g);
+        let b = T::convert_to_tensor(array![2., f64::NAN], g);
+        let cases = [
+            (T::equal(a, b), array![[0., 0.], [1., 0.], [0., 0.]]),
+            (T::not_equal(a, b), array![[1., 1.], [0., 1.], [1., 1.]]),
+            (T::greater(a, b), array![[0., 0.], [0., 0.], [1., 0.]]),
+            (T::greater_equal(a, b), array![[0., 0.], [1., 0.], [1., 0.]]),
+            (T::less(a, b), array![[1., 0.], [0., 0.], [0., 0.]]),
+            (T::less_equal(a, b), array![[1., 0.], [1., 0.], [0., 0.]]),
+        ];
+        for (y, expected) in cases.iter() {
+            assert_eq!(y.eval(g), Ok(expected.clone().into_dyn()));
+        }
+        // against a scalar, and with no gradient
+        let y = T::less_equal(a, T::scalar(2., g));
+        assert_eq!(y.eval(g), Ok(array![[1.], [1.], [0.]].into_dyn()));
+        assert_eq!(
+            T::grad(&[y], &[a])[0].eval(g),
+            Ok(array![[0.], [0.], [0.]].into_dyn())
+        );
+        let c = T::convert_to_tensor(array![[1.], [2.]], g);
+        assert!(T::less(a, c).eval(g).is_err());
+    });
+}
+
+#[test]
+fn logical_ops() {
+    ag::run(|g| {
+        // every pair of truth values, with a nonzero other than 1
+        let a = T::convert_to_tensor(array![0., 0., 2., 1.], g);
+        let b = T::convert_to_tensor(array![0., 1., 0., -1.], g);
+        assert_eq!(
+            T::logical_and(a, b).eval(g),
+            Ok(array![0., 0., 0., 1.].into_dyn())
+        );
+        assert_eq!(
+            T::logical_or(a, b).eval(g),
+            Ok(array![0., 1., 1., 1.].into_dyn())
+        );
+        assert_eq!(
+            T::logical_xor(a, b).eval(g),
+            Ok(array![0., 1., 1., 0.].into_dyn())
+        );
+        assert_eq!(
+            T::logical_not(a).eval(g),
+            Ok(array![1., 1., 0., 0.].into_dyn())
+        );
+
+        let rows = T::convert_to_tensor(array![[1.], [0.]], g);
+        let cols = T::convert_to_tensor(array![1., 0., 1.], g);
+        assert_eq!(
+            T::logical_and(rows, cols).eval(g),
+            Ok(array![[1., 0., 1.], [0., 0., 0.]].into_dyn())
+        );
+        assert_eq!(
+            T::logical_or(rows, T::scalar(0., g)).eval(g),
+            Ok(array![[1.], [0.]].into_dyn())
+        );
+        let other_rows = T::convert_to_tensor(array![[1.], [0.], [1.]], g);
+        assert!(T::logical_xor(rows, other_rows).eval(g).is_err());
+    });
+}
+
+#[test]
+fn empty_batch_through_graph() {
+    let mut env = VariableEnvironment::new();
+    let w = env.set(ag::ndarray_ext::ones::<f64>(&[3, 2]));
+    env.run(|g| {
+        let x = g.placeholder("x", &[-1, 3]);
+        let w = g.variable(w);
+        let logits = T::matmul(x, w);
+        let probs = T::softmax(logits, 1);
+        let picked = T::gather(probs, T::convert_to_tensor(array![0.], g), 1);
+        let loss = T::reduce_sum(T::concat(&[T::square(logits), picked], 1), &[0, 1], false);
+        let gw = T::grad(&[loss], &[w])[0];
+
+        let empty = NdArray::<f64>::zeros(ndarray::IxDyn(&[0, 3]));
+        let ys = g
+            .evaluator()
+            .extend(&[logits, probs, loss, gw])
+            .feed(x, empty.view())
+            .run();
+        assert_eq!(ys[0].as_ref().unwrap().shape(), &[0, 2]);
+        assert_eq!(ys[1].as_ref().unwrap().shape(), &[0, 2]);
+        assert_eq!(ys[2], Ok(ndarray::arr0(0.).into_dyn()));
+        assert_eq!(ys[3], Ok(ag::ndarray_ext::zeros(&[3, 2])));
+    });
+}
+
+#[test]
+fn empty_tensors() {
+    ag::run(|g| {
+        let e = T::zeros(&[0, 3], g);
+        assert_eq!(
+            T::reduce_sum(e, &[0], false).eval(g),
+            Ok(array![0., 0., 0.].into_dyn())
+        );
+        assert_eq!(
+            T::reduce_prod(e, &[0], false).eval(g),
+            Ok(array![1., 1., 1.].into_dyn())
+        );
+        let mean: NdArray<f64> = T::reduce_mean(e, &[0], false).eval(g).unwrap();
+        assert!(mean.iter().all(|m| m.is_nan()));
+        assert!(T::reduce_max(e, &[0], false).eval(g).is_err());
+        assert!(T::argmin(e, 0, false).eval(g).is_err());
+        // reductions over the other axes are empty
+        assert_eq!(T::reduce_max(e, &[1], false).eval(g).unwrap().shape(), &[0]);
+        assert_eq!(T::argmax(e, 1, false).eval(g).unwrap().shape(), &[0]);
+
+        let a = T::zeros(&[2, 0], g);
+        let b = T::zeros(&[0, 3], g);
+        assert_eq!(T::matmul(a, b).eval(g), Ok(ag::ndarray_ext::zeros(&[2, 3])));
+        let a = T::zeros(&[4, 2, 0], g);
+        let b = T::zeros(&[4, 0, 3], g);
+        assert_eq!(
+            T::batch_matmul(a, b).eval(g),
+            Ok(ag::ndarray_ext::zeros(&[4, 2, 3]))
+        );
+        let a = T::zeros(&[0, 2, 3], g);
+        let b = T::zeros(&[0, 3, 1], g);
+        assert_eq!(T::batch_matmul(a, b).eval(g).unwrap().shape(), &[0, 2, 1]);
+
+        let x = T::ones(&[2, 3], g);
+        assert_eq!(
+            T::concat(&[e, x, e], 0).eval(g),
+            Ok(ag::ndarray_ext::ones(&[2, 3]))
+        );
+        let no_indices = T::convert_to_tensor(ag::ndarray_ext::zeros(&[0]), g);
+        assert_eq!(
+            T::gather(x, no_indices, 1).eval(g).unwrap().shape(),
+            &[2, 0]
+        );
+        let masked = T::boolean_mask(x, T::zeros(&[2, 3], g));
+        assert_eq!(T::sum_all(masked).eval(g), Ok(ndarray::arr0(0.).into_dyn()));
+    });
+}
+
+#[test]
+fn cummax_and_cummin_route_gradients() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![1., 3., 2., 5., 4.], g);
+        let w = T::convert_to_tensor(array![1., 2., 3., 4., 5.], g);
+
+        let y = T::cummax(x, 0, false);
+        assert_eq!(y.eval(g), Ok(array![1., 3., 3., 5., 5.].into_dyn()));
+        // the max changes hands at 3 and 5, which collect the gradients of the outputs they hold
+        let gx = T::grad(&[y * w], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(array![1., 5., 0., 9., 0.].into_dyn()));
+
+        let y = T::cummax(x, 0, true);
+        assert_eq!(y.eval(g), Ok(array![5., 5., 5., 5., 4.].into_dyn()));
+        let gx = T::grad(&[y * w], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(array![0., 0., 0., 10., 5.].into_dyn()));
+
+        let x = T::convert_to_tensor(array![[2., 2., 1.], [0., 4., -1.]], g);
+        let y = T::cummin(x, -1, false);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[2., 2., 1.], [0., 0., -1.]].into_dyn())
+        );
+        // ties go to the first element
+        let gx = T::grad(&[y], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(array![[2., 0., 1.], [2., 0., 1.]].into_dyn())
+        );
+        assert!(T::cummin(x, 2, false).eval(g).is_err());
+    });
+}
+
+#[test]
+fn vmap_matches_a_loop() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let xs = env.set(rng.standard_normal(&[4, 3]));
+    env.run(|g| {
+        let xs = g.variable(xs);
+        let ys = T::vmap(|x| T::reduce_sum(T::square(x), &[0], false) * x, xs, g);
+        let looped: Vec<_> = (0..4)
+            .map(|i| {
+                let x = T::reshape(T::slice(xs, &[i, 0], &[i + 1, 3]), &[3]);
+                T::reduce_sum(T::square(x), &[0], false) * x
+            })
+            .collect();
+        let looped = T::stack(&looped, 0);
+        assert_eq!(ys.eval(g).unwrap().shape(), &[4, 3]);
+        assert_eq!(ys.eval(g), looped.eval(g));
+        assert_eq!(
+            T::grad(&[ys], &[xs])[0].eval(g),
+            T::grad(&[looped], &[xs])[0].eval(g)
+        );
+    });
+}
+
+#[test]
+#[should_panic(expected = "vmap: the length of the leading axis of `xs` must be known")]
+fn vmap_needs_the_leading_axis() {
+    ag::run(|g: &mut ag::Context<f32>| {
+        let x = g.placeholder("x", &[-1, 3]);
+        T::vmap(|x| *x, x, g);
+    });
+}
+
+#[test]
+fn div_gradients_near_zero_denominator() {
+    ag::run(|g| {
+        let a = T::convert_to_tensor(array![2f64, -3., 0.], g);
+        let b = T::convert_to_tensor(array![1e-3, 1e-3, 0.], g);
+        let y = a / b;
+        let gs = T::grad(&[y], &[a, b]);
+        // large but finite near zero
+        assert_eq!(y.eval(g).unwrap()[0], 2e3);
+        assert_eq!(gs[0].eval(g).unwrap()[1], 1e3);
+        assert!((gs[1].eval(g).unwrap()[1] - 3e6).abs() < 1e-3);
+        // 0 / 0: NaN everywhere
+        assert!(y.eval(g).unwrap()[2].is_nan());
+        assert_eq!(gs[0].eval(g).unwrap()[2], f64::INFINITY);
+        assert!(gs[1].eval(g).unwrap()[2].is_nan());
+    });
+}
+
+#[test]
+fn clip_by_percentile_bounds() {
+    ag::run(|g| {
+        // 0, 1, ..., 100 out of order: the `p`th percentile is `p`
+        let values: Vec<f64> = (0..101).map(|i| ((i * 37) % 101) as f64).collect();
+        let x = T::convert_to_tensor(
+            ndarray::Array::from_shape_vec(vec![101], values).unwrap(),
+            g,
+        );
+        let y = T::clip_by_percentile(x, 5., 90.).eval(g).unwrap();
+        assert_eq!(y.fold(f64::INFINITY, |a, &b| a.min(b)), 5.);
+        assert_eq!(y.fold(f64::NEG_INFINITY, |a, &b| a.max(b)), 90.);
+
+        // interpolated as numpy does: np.percentile(range(1, 11), [25, 62.5])
+        let x = T::reshape(T::arange(1., 11., 1., g), &[2, 5]);
+        let y = T::clip_by_percentile(x, 25., 62.5).eval(g).unwrap();
+        assert_eq!(
+            y,
+            array![[3.25, 3.25, 3.25, 4., 5.], [6., 6.625, 6.625, 6.625, 6.625]].into_dyn()
+        );
+
+        // NaNs are ignored
+        let x = T::convert_to_tensor(array![f64::NAN, 1., 2., 3.], g);
+        let y = T::clip_by_percentile(x, 50., 100.).eval(g).unwrap();
+        assert!(y[0].is_nan());
+        assert_eq!(y.slice(ndarray::s![1..]), array![2., 2., 3.]);
+    });
+}
+
+#[test]
+fn ema_op_formula() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., -2.], [0., 4.]], g);
+        let prev = T::convert_to_tensor(array![[3.], [8.]], g);
+        let y = T::ema_op(x, prev, 0.9).eval(g).unwrap();
+        let expected = array![[0.9 * 3. + 0.1, 0.9 * 3. - 0.2], [0.9 * 8., 0.9 * 8. + 0.4]];
+        ag::test_helper::assert_tensors_close(&y, &expected.into_dyn(), 1e-12, 1e-12);
+        // the ends of the range
+        assert_eq!(T::ema_op(x, prev, 0.).eval(g), x.eval(g));
+        assert_eq!(
+            T::ema_op(x, prev, 1.).eval(g),
+            Ok(array![[3., 3.], [8., 8.]].into_dyn())
+        );
+    });
+}
+
+#[test]
+#[should_panic(expected = "ema_op")]
+fn ema_op_decay_out_of_range() {
+    ag::run(|g| {
+        let x = T::ones(&[2], g);
+        T::ema_op(x, x, 1.5);
+    });
+}
+
+#[test]
+fn log_softmax_large_magnitude() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1e4f64, -1e4, 0.], [-3e4, -3e4, -3e4]], g);
+        let y = T::log_softmax(x, 1).eval(g).unwrap();
+        assert!(y.iter().all(|a| a.is_finite()));
+        assert_eq!(y.slice(ndarray::s![0, ..]), array![0., -2e4, -1e4]);
+        let third = -(3f64.ln());
+        assert!(y
+            .slice(ndarray::s![1, ..])
+            .iter()
+            .all(|&a| (a - third).abs() < 1e-12));
+
+        // the gradient is finite too
+        let gx = T::grad(&[T::log_softmax(x, 0)], &[x])[0].eval(g).unwrap();
+        assert!(gx.iter().all(|a: &f64| a.is_finite()));
+    });
+}
+
+#[test]
+fn log_softmax_matches_log_of_softmax() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = T::convert_to_tensor(rng.standard_normal(&[4, 5]) * 3., g);
+        for &axis in &[0, 1, -1] {
+            let a = T::log_softmax(x, axis);
+            let b = T::ln(T::softmax(x, axis));
+            let (a, b) = (a.eval(g).unwrap(), b.eval(g).unwrap());
+            ag::test_helper::assert_tensors_close(&a, &b, 1e-10, 1e-10);
+        }
+    });
+}
+
+#[test]
+fn jacobian_of_affine_map() {
+    let mut env = VariableEnvironment::new();
+    let x = env.set(array![[0.5], [-1.], [2.]]);
+    env.run(|g| {
+        let a = array![[1., 2., 0.], [-3., 0.5, 4.], [0., 0., -1.]];
+        let x = g.variable(x);
+        let y = T::matmul(T::convert_to_tensor(a.clone(), g), x) + 7.;
+        let j = T::jacobian(y, x, ag::Feeder::new(), g).unwrap();
+        assert_eq!(j, a.into_dyn());
+    });
+}
+
+#[test]
+fn jacobian_with_placeholder() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = g.placeholder("x", &[-1]);
+        let y = T::square(x) * 2.;
+        let jacobian_of = |x_value: ndarray::Array1<f64>| {
+            let mut feeder = ag::Feeder::new();
+            feeder.push(x, x_value.view());
+            T::jacobian(y, x, feeder, g).unwrap()
+        };
+        assert_eq!(
+            jacobian_of(array![1., 2.]),
+            array![[4., 0.], [0., 8.]].into_dyn()
+        );
+        // the seed and the product are built once: the probes are the only new tensors
+        let probe = g.placeholder("probe", &[-1]).id();
+        assert_eq!(jacobian_of(array![3.]), array![[12.]].into_dyn());
+        assert_eq!(g.placeholder("probe", &[-1]).id(), probe + 1);
+    });
+}
+
+#[test]
+fn hvp_of_quadratic() {
+    let mut env = VariableEnvironment::new();
+    let x = env.set(array![0.5, -1., 2.]);
+    env.run(|g| {
+        // loss = x^T A x / 2 + b^T x, whose Hessian is (A + A^T) / 2
+        let a = array![[2., 1., 0.], [1., 3., -1.], [4., 0., 1.]];
+        let x = g.variable(x);
+        let xa = T::matmul(T::expand_dims(x, &[0]), T::convert_to_tensor(a.clone(), g));
+        let loss = T::sum_all(T::flatten(xa, 0) * x) * 0.5
+            + T::sum_all(x * T::convert_to_tensor(array![1., 2., 3.], g));
+        let v = array![1., -2., 0.5];
+        let hv = T::hvp(loss, x, T::convert_to_tensor(v.clone(), g), g).unwrap();
+        let hessian = (&a + &a.t()) * 0.5;
+        ag::test_helper::assert_tensors_close(&hv, &hessian.dot(&v).into_dyn(), 1e-12, 1e-12);
+    });
+}
+
+#[test]
+fn max_pool1d_ignores_padding() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = T::convert_to_tensor(ndarray::array![[[-1., -2., -3.]]], g);
+        let y = T::max_pool1d(x, 2, 1, 1);
+        assert_eq!(
+            y.eval(g),
+            Ok(ndarray::array![[[-1., -1., -2., -3.]]].into_dyn())
+        );
+    });
+}
+
+#[test]
+fn fold_matches_reduce_sum() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let x = T::convert_to_tensor(rng.standard_normal(&[3, 4, 2]), g);
+        for &axis in &[0, 1, -1] {
+            let y = T::fold(x, axis, 0., |a, b| a + b, |_, _| (1., 1.));
+            let z = T::reduce_sum(x, &[axis], false);
+            let ys = g.evaluator().push(y).push(z).run();
+            let (y, z) = (ys[0].as_ref().unwrap(), ys[1].as_ref().unwrap());
+            assert!(y.abs_diff_eq(z, 1e-12), "{:?} {:?}", y, z);
+            let gx = T::grad(&[T::fold(x, axis, 0., |a, b| a + b, |_, _| (1., 1.))], &[x])[0];
+            assert_eq!(
+                gx.eval(g).unwrap(),
+                ag::ndarray_ext::ones::<f64>(&[3, 4, 2])
+            );
+        }
+        assert!(T::fold(x, 3, 0., |a, b| a + b, |_, _| (1., 1.))
+            .eval(g)
+            .is_err());
+    });
+}
+
+#[test]
+fn fft_round_trip() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        // a power of two and a length taking the plain DFT
+        for &n in &[8, 6] {
+            let x = T::convert_to_tensor(rng.standard_normal(&[3, n, 2]), g);
+            let y = T::fft(x, 1);
+            let z = T::ifft(y, -1);
+            let ret = g.evaluator().extend(&[x, y, z]).run();
+            let mut ret = ret.into_iter().map(|a| a.unwrap());
+            let (x, y, z) = (
+                ret.next().unwrap(),
+                ret.next().unwrap(),
+                ret.next().unwrap(),
+            );
+            assert!(z.abs_diff_eq(&x, 1e-9));
+
+            // y_0 is the sum of the inputs
+            let sum = x.sum_axis(ndarray::Axis(1));
+            assert!(y.index_axis(ndarray::Axis(1), 0).abs_diff_eq(&sum, 1e-9));
+        }
+    });
+}
+
+#[test]
+fn rfft_round_trip() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        for &n in &[8, 7] {
+            let x = T::convert_to_tensor(rng.standard_normal(&[n, 3]), g);
+            let y = T::rfft(x, 0);
+            assert_eq!(y.eval(g).unwrap().shape(), &[n / 2 + 1, 3, 2]);
+            let z = T::irfft(y, 0, n);
+            let ret = g.evaluator().extend(&[x, z]).run();
+            let mut ret = ret.into_iter().map(|a| a.unwrap());
+            let (x, z) = (ret.next().unwrap(), ret.next().unwrap());
+            assert!(z.abs_diff_eq(&x, 1e-9));
+        }
+    });
+}
+
+#[test]
+fn sliding_window() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[0., 1., 2., 3.], [4., 5., 6., 7.]], g);
+        let y = T::sliding_window(x, 2, 1, -1);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![
+                [[0., 1.], [1., 2.], [2., 3.]],
+                [[4., 5.], [5., 6.], [6., 7.]]
+            ]
+            .into_dyn())
+        );
+        // windows along the first axis end up in the last one
+        let y = T::sliding_window(x, 2, 1, 0);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[[0., 4.], [1., 5.], [2., 6.], [3., 7.]]].into_dyn())
+        );
+        assert!(T::sliding_window(x, 5, 1, 1).eval(g).is_err());
+    });
+}
+
+#[test]
+fn scatter_add() {
+    ag::run(|g| {
+        // -3 is column 0, so both columns get two updates per row
+        let indices = T::convert_to_tensor(array![[1., 0.], [-3., 1.]], g);
+        let updates = T::convert_to_tensor(array![[[1., 2.], [3., 4.]], [[5., 6.], [7., 8.]]], g);
+        let y = T::scatter_add(&[2, 3], indices, updates, 1);
+        assert_eq!(
+            y.eval(g),
+            Ok(array![[5., 5., 0.], [13., 13., 0.]].into_dyn())
+        );
+        // each gathered element comes back once per occurrence of its index
+        let x = T::convert_to_tensor(array![[1., 2., 3.], [4., 5., 6.]], g);
+        let z = T::scatter_add(&[2, 3], indices, T::gather_common(x, indices, 1), 1);
+        assert_eq!(
+            z.eval(g),
+            Ok(array![[2., 4., 0.], [8., 10., 0.]].into_dyn())
+        );
+        let bad = T::convert_to_tensor(array![[3., 0.], [0., 1.]], g);
+        assert!(T::scatter_add(&[2, 3], bad, updates, 1).eval(g).is_err());
+        assert!(T::scatter_add(&[2, 4], indices, x, 1).eval(g).is_err());
+    });
+}
+
+#[test]
+fn boolean_mask() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(array![[1., -2., 3.], [-4., 5., -6.]], g);
+        let mask = T::greater(x, T::scalar(0., g));
+        let y = T::boolean_mask(x, mask);
+        assert_eq!(y.eval(g), Ok(array![1., 3., 5.].into_dyn()));
+
+        // the gradient lands on the selected positions only
+        let w = T::convert_to_tensor(array![10., 20., 30.], g);
+        let gx = T::grad(&[T::reduce_sum(y * w, &[0], false)], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(array![[10., 0., 20.], [0., 30., 0.]].into_dyn())
+        );
+
+        let none = T::boolean_mask(x, T::zeros(&[2, 3], g));
+        assert_eq!(none.eval(g).unwrap().shape(), &[0]);
+        assert!(T::boolean_mask(x, T::ones(&[3, 2], g)).eval(g).is_err());
+    });
+}
+
+#[test]
+fn upsample2d() {
+    // (h, w) -> (1, 1, h, w)
+    let nchw = |a: ndarray::Array2<f64>| {
+        a.insert_axis(ndarray::Axis(0))
+            .insert_axis(ndarray::Axis(0))
+            .into_dyn()
+    };
+    ag::run(|g| {
+        let x = T::convert_to_tensor(nchw(array![[1., 2.], [3., 4.]]), g);
+        let y = T::upsample_bilinear2d(x, 2.);
+        let expected = array![
+            [1., 1.25, 1.75, 2.],
+            [1.5, 1.75, 2.25, 2.5],
+            [2.5, 2.75, 3.25, 3.5],
+            [3., 3.25, 3.75, 4.]
+        ];
+        assert_eq!(y.eval(g), Ok(nchw(expected)));
+        let y = T::upsample_bilinear2d(x, 1.5);
+        let expected = array![[1., 1.5, 2.], [2., 2.5, 3.], [3., 3.5, 4.]];
+        assert_eq!(y.eval(g), Ok(nchw(expected)));
+
+        let x: ag::Tensor<f64> = T::ones(&[2, 3, 5, 4], g);
+        let y = T::upsample_nearest2d(x, 1.5).eval(g).unwrap();
+        assert_eq!(y.shape(), &[2, 3, 7, 6]);
+        let y = T::upsample_bilinear2d(x, 0.5).eval(g).unwrap();
+        assert_eq!(y.shape(), &[2, 3, 2, 2]);
+        assert!(T::upsample_nearest2d(T::ones(&[2, 3], g), 2.)
+            .eval(g)
+            .is_err());
+    });
+}
+
+#[test]
+fn upsample2d_grad_accumulation() {
+    let nchw = |a: ndarray::Array2<f64>| {
+        a.insert_axis(ndarray::Axis(0))
+            .insert_axis(ndarray::Axis(0))
+            .into_dyn()
+    };
+    ag::run(|g| {
+        let x = T::convert_to_tensor(nchw(array![[1., 2.], [3., 4.]]), g);
+        // each source pixel is copied 4 times
+        let gx = T::grad(&[T::upsample_nearest2d(x, 2.)], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(nchw(array![[4., 4.], [4., 4.]])));
+        // rows and columns 0 are copied twice, 1 once
+        let gx = T::grad(&[T::upsample_nearest2d(x, 1.5)], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(nchw(array![[4., 2.], [2., 1.]])));
+        // interpolation weights 1 + 0.5 along each axis
+        let gx = T::grad(&[T::upsample_bilinear2d(x, 1.5)], &[x])[0];
+        let expected = nchw(array![[2.25, 2.25], [2.25, 2.25]]);
+        ag::test_helper::assert_tensors_close(&gx.eval(g).unwrap(), &expected, 1e-12, 0.);
+    });
+}
+
+#[test]
+fn grid_sample_identity() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let (h, w) = (3, 4);
+        let x = T::convert_to_tensor(
+            ag::ndarray_ext::ArrayRng::<f64>::default().standard_normal(&[2, 3, h, w]),
+            g,
+        );
+        // the pixel centers, in normalized coordinates
+        let xs = T::linspace(-1. + 1. / w as f64, 1. - 1. / w as f64, w, g);
+        let ys = T::linspace(-1. + 1. / h as f64, 1. - 1. / h as f64, h, g);
+        let grids = T::meshgrid(&[xs, ys], T::MeshgridIndexing::Xy);
+        let grid = T::concat(
+            &[
+                T::expand_dims(grids[0], &[2]),
+                T::expand_dims(grids[1], &[2]),
+            ],
+            2,
+        );
+        let grid = T::concat(&[T::expand_dims(grid, &[0]); 2], 0);
+        for &padding in &[T::GridSamplePadding::Zeros, T::GridSamplePadding::Border] {
+            let y = T::grid_sample(x, grid, padding);
+            ag::test_helper::assert_tensors_close(
+                &y.eval(g).unwrap(),
+                &x.eval(g).unwrap(),
+                1e-12,
+                1e-12,
+            );
+        }
+
+        // on a horizontal ramp, moving a point by `du` moves the sampled value by `du * w / 2`
+        let ramp = T::convert_to_tensor(ndarray::Array::linspace(0., 3., 4), g);
+        let ramp = T::reshape(ramp, &[1, 1, 1, 4]) + T::zeros(&[2, 3, h, w], g);
+        let y = T::grid_sample(ramp, grid, T::GridSamplePadding::Zeros);
+        ag::test_helper::assert_tensors_close(
+            &y.eval(g).unwrap(),
+            &ramp.eval(g).unwrap(),
+            1e-12,
+            1e-12,
+        );
+        let ggrid = T::grad(&[y], &[grid])[0].eval(g).unwrap();
+        assert_eq!(ggrid.shape(), &[2, h, w, 2]);
+        for (idx, &v) in ggrid.indexed_iter() {
+            // summed over the 3 channels; the edge pixels also see the zero padding
+            if idx[1] == 0 || idx[1] == h - 1 || idx[2] == 0 || idx[2] == w - 1 {
+                continue;
+            }
+            let expected = if idx[3] == 0 { 3. * w as f64 / 2. } else { 0. };
+            assert!((v - expected).abs() < 1e-9, "{:?}: {}", idx, v);
+        }
+    });
+}
+
+#[test]
+fn space_to_depth() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(ndarray::Array::linspace(0., 47., 48), g);
+        let x = T::reshape(x, &[2, 3, 2, 4]);
+        let y = T::space_to_depth(x, 2);
+        let y_ = y.eval(g).unwrap();
+        assert_eq!(y_.shape(), &[2, 12, 1, 2]);
+        // the top left pixels of the blocks of channel 1 of batch 0
+        assert_eq!(y_.slice(ndarray::s![0, 1, .., ..]), array![[8., 10.]]);
+        // the bottom right ones
+        assert_eq!(y_.slice(ndarray::s![0, 10, .., ..]), array![[13., 15.]]);
+        assert_eq!(T::depth_to_space(y, 2).eval(g), x.eval(g));
+        assert_eq!(T::depth_to_space(x, 1).eval(g), x.eval(g));
+
+        assert!(T::space_to_depth(x, 3).eval(g).is_err());
+        assert!(T::depth_to_space(x, 2).eval(g).is_err());
+        assert!(T::space_to_depth(T::ones(&[2, 4], g), 2).eval(g).is_err());
+    });
+}
+
+#[test]
+fn print_tensor() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = T::convert_to_tensor(array![[1., -2., 3.], [0.5, 4., -6.]].into_dyn(), g);
+        let y = T::print_tensor(x, "print_tensor test:");
+        assert_eq!(y.eval(g), x.eval(g));
+        // the gradient goes through unchanged too
+        let gx = T::grad(&[T::sum_all(y * x)], &[x])[0];
+        assert_eq!(gx.eval(g), (x * 2.).eval(g));
+    });
+}
+
+#[test]
+fn argsort_and_sort() {
+    ag::run(|g: &mut ag::Context<f64>| {
+        let x = T::convert_to_tensor(array![0.5, -2., 3., 0.5, 1.].into_dyn(), g);
+        for &descending in &[false, true] {
+            let indices = T::argsort(x, 0, descending);
+            let sorted = T::sort(x, 0, descending);
+            assert_eq!(T::gather(x, indices, 0).eval(g), sorted.eval(g));
+        }
+        // ties keep their original order in both directions
+        let indices = T::argsort(x, 0, false).eval(g).unwrap();
+        assert_eq!(indices, array![1., 0., 3., 4., 2.].into_dyn());
+        let indices = T::argsort(x, 0, true).eval(g).unwrap();
+        assert_eq!(indices, array![2., 4., 0., 3., 1.].into_dyn());
+
+        let y = T::convert_to_tensor(array![[2., f64::NAN, 1.], [0., 1., -1.]], g);
+        let sorted = T::sort(y, 1, true).eval(g).unwrap();
+        assert_eq!(sorted.slice(ndarray::s![1, ..]), array![1., 0., -1.]);
+        assert_eq!(sorted.slice(ndarray::s![0, ..2]), array![2., 1.]);
+        assert!(sorted[[0, 2]].is_nan());
+        assert!(T::argsort(y, 2, false).eval(g).is_err());
+    });
+}
+
+#[test]
+fn softplus_and_logsumexp_at_large_magnitudes() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(ndarray::arr1(&[-1000., 0., 1000.]), g);
+        let y = T::softplus(x);
+        let expected = ndarray::arr1(&[0., 2f64.ln(), 1000.]);
+        assert!(y.eval(g).unwrap().abs_diff_eq(&expected.into_dyn(), 1e-12));
+        let gx = T::grad(&[y], &[x])[0];
+        assert_eq!(
+            gx.eval(g).unwrap(),
+            ndarray::arr1(&[0., 0.5, 1.]).into_dyn()
+        );
+
+        let x = T::convert_to_tensor(ndarray::arr2(&[[1000., 1000.], [-1000., -1000.]]), g);
+        let y = T::reduce_logsumexp(x, 1, false);
+        let expected = ndarray::arr1(&[1000. + 2f64.ln(), -1000. + 2f64.ln()]);
+        assert!(y.eval(g).unwrap().abs_diff_eq(&expected.into_dyn(), 1e-9));
+        let gx = T::grad(&[y], &[x])[0];
+        assert_eq!(
+            gx.eval(g).unwrap(),
+            ndarray::Array2::from_elem((2, 2), 0.5).into_dyn()
+        );
+    });
+}
+
+#[test]
+fn gumbel_softmax_straight_through() {
+    use ag::rand::{rngs::StdRng, SeedableRng};
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let logits = T::convert_to_tensor(rng.standard_normal(&[4, 5]), g);
+        let w = T::convert_to_tensor(rng.standard_normal(&[4, 5]), g);
+        let sample = |hard| T::gumbel_softmax_rng(logits, 0.5, hard, StdRng::seed_from_u64(7));
+        let (soft, hard) = (sample(false), sample(true));
+
+        let soft_value = soft.eval(g).unwrap();
+        assert_eq!(soft_value, sample(false).eval(g).unwrap());
+        let hard_value = hard.eval(g).unwrap();
+        for (s, h) in soft_value.outer_iter().zip(hard_value.outer_iter()) {
+            assert!((s.sum() - 1.).abs() < 1e-12);
+            assert!(h.iter().all(|&a| a == 0. || a == 1.));
+            assert_eq!(h.sum(), 1.);
+            let argmax = (0..5).max_by(|&i, &j| s[i].partial_cmp(&s[j]).unwrap());
+            assert_eq!(h[argmax.unwrap()], 1.);
+        }
+
+        // the hard sample's gradient is the soft one's
+        let g_soft = T::grad(&[T::sum_all(soft * w)], &[logits])[0];
+        let g_hard = T::grad(&[T::sum_all(hard * w)], &[logits])[0];
+        let g_soft = g_soft.eval(g).unwrap();
+        assert_eq!(g_hard.eval(g).unwrap(), g_soft);
+        assert!(g_soft.iter().any(|&a| a != 0.));
+    });
+}
+
+#[test]
+fn masked_fill_gradient() {
+    ag::run(|g| {
+        let x = T::convert_to_tensor(ndarray::arr2(&[[1., 2.], [3., 4.]]), g);
+        let mask = T::convert_to_tensor(ndarray::arr2(&[[0., 1.], [1., 0.]]), g);
+        let w = T::convert_to_tensor(ndarray::arr2(&[[5., 6.], [7., 8.]]), g);
+        let y = T::masked_fill(x, mask, f64::NEG_INFINITY);
+        let y_value = y.eval(g).unwrap();
+        assert_eq!(y_value[[0, 0]], 1.);
+        assert_eq!(y_value[[0, 1]], f64::NEG_INFINITY);
+
+        let gx = T::grad(&[T::sum_all(T::masked_fill(x, mask, 0.) * w)], &[x])[0];
+        assert_eq!(
+            gx.eval(g).unwrap(),
+            ndarray::arr2(&[[5., 0.], [0., 8.]]).into_dyn()
+        );
+        // no NaN from the infinite fill value
+        let gx = T::grad(&[T::sum_all(T::softmax(y, 1))], &[x])[0];
+        assert!(gx.eval(g).unwrap().iter().all(|a| a.is_finite()));
+    });
+}
+
+#[test]
+fn matmul_tiled() {
+    ag::run(|g| {
+        let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+        let a = T::convert_to_tensor(rng.standard_normal(&[37, 23]), g);
+        let b = T::convert_to_tensor(rng.standard_normal(&[23, 41]), g);
+        let direct = T::matmul(a, b).eval(g).unwrap();
+        // blocks dividing none, some and all of the dims
+        for &block in &[1, 8, 23, 64] {
+            let tiled = T::matmul_tiled(a, b, block).eval(g).unwrap();
+            assert!(tiled.abs_diff_eq(&direct, 1e-10), "block {}", block);
+        }
+    });
+}
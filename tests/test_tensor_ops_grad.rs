@@ -326,6 +326,27 @@ fn tan() {
     });
 }
 
+#[test]
+fn trig_away_from_poles() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ndarray::arr1(&[-3., -1.2, -0.4, 0.7, 1.4, 2.5, 4.]).into_dyn());
+    env.run(|graph| {
+        let v = graph.variable(v);
+        for &z in &[T::sin(v), T::cos(v), T::tan(v)] {
+            let g = T::grad(&[z], &[v]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[v],
+                ag::Feeder::new(),
+                1e-4,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
 #[test]
 fn pow() {
     let mut env = ag::VariableEnvironment::new();
@@ -432,6 +453,27 @@ fn expand_dims() {
     });
 }
 
+#[test]
+fn broadcast_to() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::broadcast_to(v, &[2, 3, 4]);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
 #[test]
 fn squeeze() {
     let mut env = ag::VariableEnvironment::new();
@@ -475,6 +517,80 @@ fn matmul() {
     });
 }
 
+#[test]
+fn matmul_both_operands() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.slot().set(rng.standard_normal(&[4, 2]));
+    let b = env.slot().set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        let z = T::matmul(a, b);
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            5e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn matmul_second_order() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.slot().set(rng.standard_normal(&[4, 2]));
+    let b = env.slot().set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        // the gradients of `matmul` are `matmul`s of transposed operands
+        let ga = T::grad(&[T::square(T::matmul(a, b))], &[a])[0];
+        let g = T::grad(&[ga], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            ga,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            5e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn batch_matmul_t() {
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    for &(trans_a, trans_b) in &[(false, false), (false, true), (true, false), (true, true)] {
+        let mut env = ag::VariableEnvironment::new();
+        let a_shape = if trans_a { [2, 2, 4] } else { [2, 4, 2] };
+        let b_shape = if trans_b { [2, 3, 2] } else { [2, 2, 3] };
+        let a = env.slot().set(rng.standard_normal(&a_shape));
+        let b = env.slot().set(rng.standard_normal(&b_shape));
+        env.run(|graph| {
+            let a = graph.variable(a);
+            let b = graph.variable(b);
+            let z = T::batch_matmul_t(a, b, trans_a, trans_b);
+            let g = T::grad(&[z], &[a, b]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[a, b],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        });
+    }
+}
+
 #[test]
 fn batch_matmul() {
     let mut env = ag::VariableEnvironment::new();
@@ -498,19 +614,20 @@ fn batch_matmul() {
 }
 
 #[test]
-fn implicit_broadcast() {
+fn outer() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let b = env.set(rng.standard_normal(&[1, 3]));
+    let v1 = env.slot().set(rng.standard_normal(&[3]));
+    let v2 = env.slot().set(rng.standard_normal(&[4]));
     env.run(|graph| {
-        let x = T::convert_to_tensor(rng.standard_normal(&[4, 3]), graph);
-        let b = graph.variable(b);
-        let z = x + b;
-        let g = T::grad(&[z], &[b]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::outer(v1, v2);
+        let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[b],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -520,21 +637,20 @@ fn implicit_broadcast() {
 }
 
 #[test]
-fn wx_plus_b() {
+fn dot() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let w = env.set(rng.standard_normal(&[2, 3]));
-    let b = env.set(rng.standard_normal(&[1, 3]));
+    let v1 = env.slot().set(rng.standard_normal(&[3]));
+    let v2 = env.slot().set(rng.standard_normal(&[3]));
     env.run(|graph| {
-        let x = T::convert_to_tensor(rng.standard_normal(&[4, 2]), graph);
-        let w = graph.variable(w);
-        let b = graph.variable(b);
-        let z = T::matmul(x, w) + b;
-        let g = T::grad(&[z], &[b]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::dot(v1, v2);
+        let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[b],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -544,17 +660,20 @@ fn wx_plus_b() {
 }
 
 #[test]
-fn reduce_min() {
+fn pairwise_distance() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v1 = env.slot().set(rng.standard_normal(&[3, 2]));
+    let v2 = env.slot().set(rng.standard_normal(&[4, 2]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::reduce_min(v, &[1], false); // keep_dims=false
-        let g = T::grad(&[z], &[v]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::pairwise_distance(v1, v2);
+        let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -564,17 +683,20 @@ fn reduce_min() {
 }
 
 #[test]
-fn reduce_min_keep() {
+fn cosine_similarity() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v1 = env.slot().set(rng.standard_normal(&[3, 4]));
+    let v2 = env.slot().set(rng.standard_normal(&[3, 4]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::reduce_min(v, &[1], true); // keep_dims=true
-        let g = T::grad(&[z], &[v]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::cosine_similarity(v1, v2, 0);
+        let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -584,12 +706,15 @@ fn reduce_min_keep() {
 }
 
 #[test]
-fn reduce_max() {
+fn layer_norm() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.slot().set(rng.standard_normal(&[2, 3, 4]));
+    let c = rng.standard_normal(&[2, 3, 4]);
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::reduce_max(v, &[1], false); // keep_dims=false
+        // weighted, since the sum of a normalized vector doesn't depend on it
+        let z = T::layer_norm(v, 1e-5) * T::convert_to_tensor(c.clone(), graph);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -604,12 +729,14 @@ fn reduce_max() {
 }
 
 #[test]
-fn reduce_max_keep() {
+fn l2_normalize() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.slot().set(rng.standard_normal(&[3, 4]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::reduce_max(v, &[1], true); // keep_dims=true
+        let z = T::l2_normalize(v, 1, 1e-12)
+            * T::convert_to_tensor(rng.standard_normal(&[3, 4]), graph);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -624,18 +751,20 @@ fn reduce_max_keep() {
 }
 
 #[test]
-fn reduce_mean() {
+fn triplet_loss() {
     let mut env = ag::VariableEnvironment::new();
-    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    // every triplet violates the margin, away from the kink of the hinge
+    let a = env.slot().set(ndarray::arr2(&[[0., 0.], [1., 1.]]));
+    let p = env.slot().set(ndarray::arr2(&[[1., 2.], [-1., 1.5]]));
+    let n = env.slot().set(ndarray::arr2(&[[0.5, -1.], [1., 2.5]]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::reduce_mean(v, &[1], false); // keep_dims=false
-        let g = T::grad(&[z], &[v]);
+        let (a, p, n) = (graph.variable(a), graph.variable(p), graph.variable(n));
+        let z = T::triplet_loss(a, p, n, 1.);
+        let g = T::grad(&[z], &[a, p, n]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[a, p, n],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -645,13 +774,40 @@ fn reduce_mean() {
 }
 
 #[test]
-fn reduce_mean_keep() {
+fn contrastive_loss() {
+    let mut env = ag::VariableEnvironment::new();
+    let a = env
+        .slot()
+        .set(ndarray::arr2(&[[0., 0.], [1., 1.], [2., -1.]]));
+    let b = env
+        .slot()
+        .set(ndarray::arr2(&[[1., 2.], [1.5, 0.5], [3., 3.]]));
+    env.run(|graph| {
+        let (a, b) = (graph.variable(a), graph.variable(b));
+        // a similar pair, a dissimilar pair inside the margin and one outside it
+        let label = T::convert_to_tensor(ndarray::arr1(&[1., 0., 0.]), graph);
+        let z = T::contrastive_loss(a, b, label, 2.);
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn trace() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    let v = env.set(rng.standard_normal(&[3, 3]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::reduce_mean(v, &[1], true); // keep_dims=true
+        let z = T::trace(T::matmul(v, v));
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -666,18 +822,21 @@ fn reduce_mean_keep() {
 }
 
 #[test]
-fn reduce_sum() {
+fn diag() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    let m = env.slot().set(rng.standard_normal(&[3, 3]));
+    let v = env.slot().set(rng.standard_normal(&[3]));
     env.run(|graph| {
+        let m = graph.variable(m);
         let v = graph.variable(v);
-        let z = T::reduce_sum(v, &[1], false); // keep_dims=false
-        let g = T::grad(&[z], &[v]);
+        // matrix -> vector and vector -> matrix
+        let z = T::diag(m) * T::diag(T::matmul(T::diag(v), m));
+        let g = T::grad(&[z], &[m, v]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[m, v],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -687,13 +846,16 @@ fn reduce_sum() {
 }
 
 #[test]
-fn reduce_sum_keep() {
+fn cholesky() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    let v = env.set(rng.standard_normal(&[3, 3]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::reduce_sum(v, &[1], true); // keep_dims=true
+        // symmetric positive-definite
+        let eye = T::convert_to_tensor(ndarray::Array2::<f64>::eye(3).into_dyn(), graph);
+        let a = T::matmul(v, T::transpose(v, &[1, 0])) + eye;
+        let z = T::cholesky(a);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -708,13 +870,14 @@ fn reduce_sum_keep() {
 }
 
 #[test]
-fn reduce_prod() {
+fn qr() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    let v = env.set(rng.standard_normal(&[4, 3]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::reduce_prod(v, &[1], false); // keep_dims=false
+        let (q, r) = T::qr(v);
+        let z = T::reduce_sum(q, &[0], false) * T::reduce_sum(T::square(r), &[0], false);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -729,19 +892,21 @@ fn reduce_prod() {
 }
 
 #[test]
-fn maximum() {
+fn svd() {
     let mut env = ag::VariableEnvironment::new();
-    let v1 = env.set(ndarray::arr1(&[1., 2., 3.]));
-    let v2 = env.set(ndarray::arr1(&[4., 5., 6.]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[4, 3]));
     env.run(|graph| {
-        let v1 = graph.variable(v1);
-        let v2 = graph.variable(v2);
-        let z = T::maximum(v1, v2);
-        let g = T::grad(&[z], &[v1, v2]);
+        let v = graph.variable(v);
+        let (u, s, vt) = T::svd(v);
+        // squared to be invariant to the signs of singular vectors
+        let z = T::reduce_sum(T::square(u), &[1], false)
+            + T::sum_all(s * T::reduce_sum(T::square(vt) * T::square(vt), &[0], false));
+        let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v1, v2],
+            &[v],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -751,14 +916,43 @@ fn maximum() {
 }
 
 #[test]
-fn minimum() {
+fn lstsq() {
     let mut env = ag::VariableEnvironment::new();
-    let v1 = env.set(ndarray::arr1(&[1., 2., 3.]));
-    let v2 = env.set(ndarray::arr1(&[4., 5., 6.]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let tall = env.slot().set(rng.standard_normal(&[5, 3]));
+    let wide = env.slot().set(rng.standard_normal(&[2, 4]));
+    let b_tall = env.slot().set(rng.standard_normal(&[5, 2]));
+    let b_wide = env.slot().set(rng.standard_normal(&[2, 2]));
+    env.run(|graph| {
+        for &(a, b) in &[(tall, b_tall), (wide, b_wide)] {
+            let a = graph.variable(a);
+            let b = graph.variable(b);
+            let z = T::lstsq(a, b);
+            let g = T::grad(&[z], &[a, b]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[a, b],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn einsum() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v1 = env.slot().set(rng.standard_normal(&[2, 4, 3]));
+    let v2 = env.slot().set(rng.standard_normal(&[2, 3, 3]));
     env.run(|graph| {
         let v1 = graph.variable(v1);
         let v2 = graph.variable(v2);
-        let z = T::minimum(v1, v2);
+        // batched matmul followed by a sum over `j` of `v2`'s diagonal
+        let z = T::einsum("bij,bkk,bjl->bil", &[v1, v2, v2]);
         let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -773,12 +967,13 @@ fn minimum() {
 }
 
 #[test]
-fn abs() {
+fn einsum_reduce() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ndarray::arr1(&[1., 2., 3.]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 4]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::abs(v);
+        let z = T::einsum("ij->i", &[v]);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -793,18 +988,19 @@ fn abs() {
 }
 
 #[test]
-fn neg() {
+fn implicit_broadcast() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 3]));
+    let b = env.set(rng.standard_normal(&[1, 3]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::neg(v);
-        let g = T::grad(&[z], &[v]);
+        let x = T::convert_to_tensor(rng.standard_normal(&[4, 3]), graph);
+        let b = graph.variable(b);
+        let z = x + b;
+        let g = T::grad(&[z], &[b]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[b],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -814,18 +1010,21 @@ fn neg() {
 }
 
 #[test]
-fn square() {
+fn wx_plus_b() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 3]));
+    let w = env.set(rng.standard_normal(&[2, 3]));
+    let b = env.set(rng.standard_normal(&[1, 3]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::square(v);
-        let g = T::grad(&[z], &[v]);
+        let x = T::convert_to_tensor(rng.standard_normal(&[4, 2]), graph);
+        let w = graph.variable(w);
+        let b = graph.variable(b);
+        let z = T::matmul(x, w) + b;
+        let g = T::grad(&[z], &[b]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[b],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -835,13 +1034,12 @@ fn square() {
 }
 
 #[test]
-fn reciprocal() {
+fn reduce_min() {
     let mut env = ag::VariableEnvironment::new();
-    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::inv(v);
+        let z = T::reduce_min(v, &[1], false); // keep_dims=false
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -856,13 +1054,12 @@ fn reciprocal() {
 }
 
 #[test]
-fn lgamma() {
+fn reduce_min_keep() {
     let mut env = ag::VariableEnvironment::new();
-    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::lgamma_f64(v);
+        let z = T::reduce_min(v, &[1], true); // keep_dims=true
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -877,13 +1074,12 @@ fn lgamma() {
 }
 
 #[test]
-fn dropout() {
+fn reduce_max() {
     let mut env = ag::VariableEnvironment::new();
-    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::dropout(v, 0.01, true);
+        let z = T::reduce_max(v, &[1], false); // keep_dims=false
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -898,13 +1094,12 @@ fn dropout() {
 }
 
 #[test]
-fn transpose() {
+fn reduce_max_keep() {
     let mut env = ag::VariableEnvironment::new();
-    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[1, 2, 3, 4]));
+    let v = env.set(ndarray::arr2(&[[0., 1.], [3., 2.]]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::transpose(v, &[2, 3, 0, 1]);
+        let z = T::reduce_max(v, &[1], true); // keep_dims=true
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -919,14 +1114,13 @@ fn transpose() {
 }
 
 #[test]
-fn reshape_after_transpose() {
+fn reduce_mean() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 3, 4]));
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::transpose(v, &[2, 1, 0]);
-        let z = T::reshape(z, &[4, 6]);
+        let z = T::reduce_mean(v, &[1], false); // keep_dims=false
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -941,17 +1135,13 @@ fn reshape_after_transpose() {
 }
 
 #[test]
-fn transpose_then_reshape_then_mm() {
+fn reduce_mean_keep() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[1, 2, 3, 4, 5]));
-    let v2 = env.set(rng.standard_normal(&[8, 2]));
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let v2 = graph.variable(v2);
-        let z = T::transpose(v, &[4, 2, 3, 0, 1]);
-        let z = T::reshape(z, &[15, 8]);
-        let z = T::matmul(z, v2);
+        let z = T::reduce_mean(v, &[1], true); // keep_dims=true
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -966,20 +1156,1116 @@ fn transpose_then_reshape_then_mm() {
 }
 
 #[test]
-fn add() {
+fn weighted_mean() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[3, 4]));
+    // kept away from a zero total
+    let w = env.set(rng.standard_uniform(&[1, 4]) + 0.5);
+    env.run(|graph| {
+        let x = graph.variable(x);
+        let w = graph.variable(w);
+        let z = T::weighted_mean(x, w, &[1], false);
+        let g = T::grad(&[z], &[x, w]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[x, w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reduce_sum() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_sum(v, &[1], false); // keep_dims=false
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn fold() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        // `2 x_1 x_2 x_3` along the first axis
+        let z = T::fold(v, 0, 2., |a, b| a * b, |a, b| (b, a));
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn cummax_cummin() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::cummax(v, 1, false) + T::cummin(v, 0, true);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn vmap() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 4]));
+    let w = env.set(rng.standard_normal(&[4, 2]));
+    env.run(|graph| {
+        let (v, w) = (graph.variable(v), graph.variable(w));
+        let z = T::vmap(
+            |x| T::softmax(T::reshape(T::matmul(T::reshape(x, &[1, 4]), w), &[2]), 0),
+            v,
+            graph,
+        );
+        let g = T::grad(&[z * z], &[v, w]);
+        ag::test_helper::check_theoretical_grads(
+            z * z,
+            g.as_slice(),
+            &[v, w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reduce_sum_keep() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_sum(v, &[1], true); // keep_dims=true
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reduce_sum_middle_axis() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        for &keep_dims in &[false, true] {
+            // squared so that the second-order gradient goes through the broadcast's grad
+            let z = T::square(T::reduce_sum(v, &[1], keep_dims));
+            let g = T::grad(&[z], &[v])[0];
+            assert_eq!(g.eval(graph).unwrap().shape(), &[2, 3, 4]);
+            let gg = T::grad(&[g], &[v])[0];
+            assert_eq!(gg.eval(graph).unwrap().shape(), &[2, 3, 4]);
+            ag::test_helper::check_theoretical_grads(
+                g,
+                &[gg],
+                &[v],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn reduce_sum_kahan() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_sum_kahan(v, &[0, -1], false);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn fft() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[4, 3, 2]));
+    let w = env.set(rng.standard_normal(&[4, 3, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let w = graph.variable(w);
+        // real loss through the complex ops
+        let y = T::complex_mul(T::fft(v, 0), T::ifft(w, -1));
+        let z = T::reduce_sum(T::real(y) * T::imag(y), &[0, 1], false);
+        let g = T::grad(&[z], &[v, w]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v, w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn rfft() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 6]));
+    let w = env.set(rng.standard_normal(&[2, 3, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let w = graph.variable(w);
+        // power spectrum of `v`, and a signal of odd length from `w`
+        let y = T::rfft(v, -1);
+        let power = T::square(T::real(y)) + T::square(T::imag(y));
+        let z = T::irfft(w, 1, 5);
+        let loss = T::reduce_sum(power, &[0, 1], false) + T::reduce_sum(z * z * z, &[0, 1], false);
+        let g = T::grad(&[loss], &[v, w]);
+        ag::test_helper::check_theoretical_grads(
+            loss,
+            g.as_slice(),
+            &[v, w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn sliding_window() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 7, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let y = T::sliding_window(v, 3, 2, 1);
+        let z = T::reduce_sum(y * y, &[0, 1, 2, 3], false);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn sliding_window_overlap() {
+    ag::run(|g| {
+        let x: ag::Tensor<f64> = T::zeros(&[6], g);
+        // each element is counted once per window containing it
+        let y = T::sliding_window(x, 3, 1, 0);
+        let gx = T::grad(&[T::reduce_sum(y, &[0, 1], false)], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(ndarray::arr1(&[1., 2., 3., 3., 2., 1.]).into_dyn())
+        );
+
+        // the last element isn't covered by any window
+        let y = T::sliding_window(x, 3, 2, 0);
+        let gx = T::grad(&[T::reduce_sum(y, &[0, 1], false)], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(ndarray::arr1(&[1., 1., 2., 1., 1., 0.]).into_dyn())
+        );
+    });
+}
+
+#[test]
+fn meshgrid() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.set(rng.standard_normal(&[3]));
+    let b = env.set(rng.standard_normal(&[4]));
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        let grids = T::meshgrid(&[a, b], T::MeshgridIndexing::Xy);
+        let z = T::reduce_sum(grids[0] * grids[1] * grids[1], &[0, 1], false);
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn matmul_tiled() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.set(rng.standard_normal(&[5, 3]));
+    let b = env.set(rng.standard_normal(&[3, 4]));
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        let y = T::matmul_tiled(a, b, 2);
+        // squared to differentiate the gradients' tiled matmuls again
+        let z = T::reduce_sum(y * y, &[0, 1], false);
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a, b],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+        let gg = T::grad(&[T::reduce_sum(g[0], &[0, 1], false)], &[b]);
+        ag::test_helper::check_theoretical_grads(
+            T::reduce_sum(g[0], &[0, 1], false),
+            gg.as_slice(),
+            &[b],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reduce_prod() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_prod(v, &[1], false); // keep_dims=false
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reduce_prod_with_zeros() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ndarray::arr2(&[[0., 2.], [-1., 3.], [4., 0.]]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_prod(v, &[0], true);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn maximum() {
+    let mut env = ag::VariableEnvironment::new();
+    let v1 = env.set(ndarray::arr1(&[1., 2., 3.]));
+    let v2 = env.set(ndarray::arr1(&[4., 5., 6.]));
+    env.run(|graph| {
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::maximum(v1, v2);
+        let g = T::grad(&[z], &[v1, v2]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v1, v2],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn minimum() {
+    let mut env = ag::VariableEnvironment::new();
+    let v1 = env.set(ndarray::arr1(&[1., 2., 3.]));
+    let v2 = env.set(ndarray::arr1(&[4., 5., 6.]));
+    env.run(|graph| {
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::minimum(v1, v2);
+        let g = T::grad(&[z], &[v1, v2]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v1, v2],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn abs() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ndarray::arr1(&[1., 2., 3.]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::abs(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn abs_subgradient() {
+    ag::run(|g| {
+        let x: ag::Tensor<f64> = T::convert_to_tensor(ndarray::arr1(&[-2., -0.5, 0., 0.5, 3.]), g);
+        let gx = T::grad(&[T::abs(x)], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(ndarray::arr1(&[-1., -1., 0., 1., 1.]).into_dyn())
+        );
+        // `sign` is a constant for the gradient
+        let gx = T::grad(&[x * T::sign(x)], &[x])[0];
+        assert_eq!(
+            gx.eval(g),
+            Ok(ndarray::arr1(&[-1., -1., 0., 1., 1.]).into_dyn())
+        );
+    });
+}
+
+#[test]
+fn rounding_straight_through() {
+    ag::run(|g| {
+        let x: ag::Tensor<f64> = T::convert_to_tensor(ndarray::arr1(&[-1.5, -0.2, 0.5, 1.7]), g);
+        let w = T::convert_to_tensor(ndarray::arr1(&[1., 2., 3., 4.]), g);
+        let rounded = |mode| {
+            [
+                T::floor_with_grad(x, mode),
+                T::ceil_with_grad(x, mode),
+                T::round_with_grad(x, mode),
+            ]
+        };
+        let expected = [[-2., -1., 0., 1.], [-1., -0., 1., 2.], [-2., -0., 1., 2.]];
+        let zero = rounded(T::RoundingGrad::Zero);
+        let ste = rounded(T::RoundingGrad::StraightThrough);
+        for ((&y, &y_ste), expected) in zero.iter().zip(&ste).zip(&expected) {
+            assert_eq!(y.eval(g), Ok(ndarray::arr1(expected).into_dyn()));
+            assert_eq!(y_ste.eval(g), Ok(ndarray::arr1(expected).into_dyn()));
+            let gx = T::grad(&[y * w], &[x])[0];
+            assert_eq!(gx.eval(g), Ok(ndarray::arr1(&[0., 0., 0., 0.]).into_dyn()));
+            let gx = T::grad(&[y_ste * w], &[x])[0];
+            assert_eq!(gx.eval(g), w.eval(g));
+        }
+        // the plain versions have no gradient
+        let gx = T::grad(&[T::floor(x) + T::ceil(x) + T::round(x)], &[x])[0];
+        assert_eq!(gx.eval(g), Ok(ndarray::arr1(&[0., 0., 0., 0.]).into_dyn()));
+    });
+}
+
+#[test]
+fn fake_quantize_per_channel() {
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x_arr = rng.random_uniform(&[2, 50], -3., 3.);
+    ag::run(|g| {
+        let x = T::convert_to_tensor(x_arr.clone(), g);
+        let scale = T::convert_to_tensor(ndarray::arr2(&[[0.1], [0.03]]), g);
+        let zero_point = T::convert_to_tensor(ndarray::arr2(&[[128.], [10.]]), g);
+        let y = T::fake_quantize(x, scale, zero_point, 8);
+        let gx = T::grad(&[y], &[x])[0].eval(g).unwrap();
+        let y = y.eval(g).unwrap();
+
+        let params = [(0.1, 128.), (0.03, 10.)];
+        for ((idx, &y), &x) in y.indexed_iter().zip(&x_arr) {
+            let (scale, zero_point) = params[idx[0]];
+            // on the grid of the integers 0..=255
+            let q = y / scale + zero_point;
+            assert!((q - q.round()).abs() < 1e-9, "{:?}: {}", idx, q);
+            assert!(q.round() >= 0. && q.round() <= 255.);
+            // within half a step of `x`, unless clamped, and only then without gradient
+            let q_x = (x / scale).round() + zero_point;
+            if (0. ..=255.).contains(&q_x) {
+                assert!((y - x).abs() <= scale / 2. + 1e-12);
+                assert_eq!(gx[&idx], 1.);
+            } else {
+                assert_eq!(gx[&idx], 0.);
+            }
+        }
+        // channel 1 only covers `[-0.3, 7.35]`, so some values are clamped
+        assert!(gx.iter().any(|&g| g == 0.));
+    });
+}
+
+#[test]
+fn neg() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::neg(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn square() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::square(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reciprocal() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::inv(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn lgamma() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::lgamma_f64(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn dropout() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.random_uniform(&[2, 3], 1., 1.01));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::dropout(v, 0.01, true);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn transpose() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 2, 3, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::transpose(v, &[2, 3, 0, 1]);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn reshape_after_transpose() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::transpose(v, &[2, 1, 0]);
+        let z = T::reshape(z, &[4, 6]);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn transpose_then_reshape_then_mm() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 2, 3, 4, 5]));
+    let v2 = env.set(rng.standard_normal(&[8, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let v2 = graph.variable(v2);
+        let z = T::transpose(v, &[4, 2, 3, 0, 1]);
+        let z = T::reshape(z, &[15, 8]);
+        let z = T::matmul(z, v2);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn add() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.set(rng.standard_normal(&[2, 2]));
+    let b = env.set(rng.standard_normal(&[2, 2]));
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        let z = a + b;
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn mul() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
     let a = env.set(rng.standard_normal(&[2, 2]));
     let b = env.set(rng.standard_normal(&[2, 2]));
     env.run(|graph| {
-        let a = graph.variable(a);
-        let b = graph.variable(b);
-        let z = a + b;
-        let g = T::grad(&[z], &[a, b]);
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        let z = a * b;
+        let g = T::grad(&[z], &[a, b]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn div_broadcast() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let a = env.set(rng.standard_normal(&[2, 3]));
+    // away from zero, with both signs
+    let b = env.set(rng.random_uniform(&[1, 3], 0.5, 2.) * ndarray::arr2(&[[1., -1., 1.]]));
+    let c = env.set(rng.standard_normal(&[1, 3]));
+    let d = env.set(rng.random_uniform(&[2, 1], 0.5, 2.));
+    env.run(|graph| {
+        let (a, b) = (graph.variable(a), graph.variable(b));
+        let (c, d) = (graph.variable(c), graph.variable(d));
+        // the denominator broadcast, then the numerator
+        let z = a / b + c / d;
+        let g = T::grad(&[z], &[a, b, c, d]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[a, b, c, d],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn ema_op() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 3]));
+    let prev = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let (x, prev) = (graph.variable(x), graph.variable(prev));
+        let y = T::ema_op(x, prev, 0.7);
+        let g = T::grad(&[y], &[x, prev]);
+        ag::test_helper::check_theoretical_grads(
+            y,
+            g.as_slice(),
+            &[x, prev],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn log_softmax_axis0() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::log_softmax(v, 0);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn sigmoid() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::sigmoid(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn elu() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::elu(v, 1.);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn relu() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ag::ndarray::arr1(&[0.2, 0.5]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::relu(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn softplus() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 2]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::softplus(v);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn logsumexp() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_logsumexp(v, 1, true);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn logsumexp_without_keep_dim() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::reduce_logsumexp(v, 0, false);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn log_softmax() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::log_softmax(v, 1);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn tempered_softmax() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.slot().set(rng.standard_normal(&[2, 3]));
+    let t = env.slot().set(ndarray::arr0(1.5));
+    let t_rows = env.slot().set(ndarray::arr2(&[[0.7], [2.]]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        for &t in &[t, t_rows] {
+            let t = graph.variable(t);
+            // squared, since the sum of a softmax doesn't depend on its inputs
+            let z = T::square(T::tempered_softmax(v, t, 1));
+            let g = T::grad(&[z], &[v, t]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[v, t],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn softmax_cross_entropy() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 3]));
+    env.run(|graph| {
+        let t = T::convert_to_tensor(ndarray::arr2(&[[1., 0., 0.]]), graph);
+        let v = graph.variable(v);
+        let z = T::softmax_cross_entropy(v, t);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn sigmoid_cross_entropy() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[1, 3]));
+    env.run(|graph| {
+        let t = T::convert_to_tensor(rng.standard_normal(&[1, 3]), graph);
+        let v = graph.variable(v);
+        let z = T::sigmoid_cross_entropy(v, t);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn focal_loss() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let t = T::convert_to_tensor(ndarray::arr2(&[[1., 0., 1.], [0., 0., 1.]]), graph);
+        let v = graph.variable(v);
+        let z = T::focal_loss(v, t, 2., 0.25);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn softmax_focal_loss() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let t = T::convert_to_tensor(ndarray::arr2(&[[1., 0., 0.], [0., 0., 1.]]), graph);
+        let v = graph.variable(v);
+        let z = T::softmax_focal_loss(v, t, 1.5);
+        let g = T::grad(&[z], &[v]);
+        ag::test_helper::check_theoretical_grads(
+            z,
+            g.as_slice(),
+            &[v],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn iou_and_giou() {
+    let mut env = ag::VariableEnvironment::new();
+    // partial overlaps without ties between the coordinates
+    let a = env.set(ndarray::arr2(&[[0., 0., 2., 3.], [1., 0.5, 4., 2.]]).into_dyn());
+    let b = env.set(ndarray::arr2(&[[1., 1., 3.5, 2.5], [-1., 1., 2., 3.]]).into_dyn());
+    env.run(|graph| {
+        let a = graph.variable(a);
+        let b = graph.variable(b);
+        for z in [T::iou(a, b), T::giou(a, b)] {
+            let g = T::grad(&[z], &[a, b]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[a, b],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn sparse_softmax_cross_entropy() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let t = T::convert_to_tensor(ndarray::arr1(&[1., 0.]), graph);
+        let v = graph.variable(v);
+        let z = T::sparse_softmax_cross_entropy(v, t);
+        let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[a],
+            &[v],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -989,20 +2275,19 @@ fn add() {
 }
 
 #[test]
-fn mul() {
+fn gather() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let a = env.set(rng.standard_normal(&[2, 2]));
-    let b = env.set(rng.standard_normal(&[2, 2]));
+    let v = env.set(rng.standard_normal(&[5, 4, 8, 2]));
     env.run(|graph| {
-        let a = graph.variable(a);
-        let b = graph.variable(b);
-        let z = a * b;
-        let g = T::grad(&[z], &[a, b]);
+        let v = graph.variable(v);
+        let x = T::convert_to_tensor(ndarray::arr2(&[[5., 4., 3.], [2., 1., 0.]]), graph);
+        let z = T::gather(v, x, 2);
+        let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[a],
+            &[v],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -1012,13 +2297,15 @@ fn mul() {
 }
 
 #[test]
-fn sigmoid() {
+fn scatter_add() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 2]));
+    let v = env.set(rng.standard_normal(&[3, 2, 2, 4]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::sigmoid(v);
+        // duplicate indices
+        let x = T::convert_to_tensor(ndarray::arr2(&[[4., 1.], [1., -1.]]), graph);
+        let z = T::scatter_add(&[3, 5, 4], x, v, 1);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1033,13 +2320,15 @@ fn sigmoid() {
 }
 
 #[test]
-fn elu() {
+fn boolean_mask() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 2]));
+    let v = env.set(rng.standard_normal(&[3, 4]));
+    let m = rng.bernoulli(&[3, 4], 0.5);
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::elu(v, 1.);
+        let mask = T::convert_to_tensor(m, graph);
+        let z = T::boolean_mask(v, mask);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1054,12 +2343,97 @@ fn elu() {
 }
 
 #[test]
-fn relu() {
+fn upsample2d() {
     let mut env = ag::VariableEnvironment::new();
-    let v = env.set(ag::ndarray::arr1(&[0.2, 0.5]));
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 2, 3, 4]));
+    env.run(|graph| {
+        let x = graph.variable(x);
+        for &scale in &[2., 1.5, 0.7] {
+            let up = [
+                T::upsample_nearest2d(x, scale),
+                T::upsample_bilinear2d(x, scale),
+            ];
+            for &y in &up {
+                let (h, w) = ((3. * scale) as usize, (4. * scale) as usize);
+                let y = y * T::convert_to_tensor(rng.standard_normal(&[2, 2, h, w]), graph);
+                let g = T::grad(&[y], &[x]);
+                ag::test_helper::check_theoretical_grads(
+                    y,
+                    &g,
+                    &[x],
+                    ag::Feeder::new(),
+                    1e-3,
+                    1e-3,
+                    graph,
+                );
+            }
+        }
+    });
+}
+
+#[test]
+fn grid_sample() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 2, 3, 4]));
+    // sampling points in (-1, len) pixels, away from the kinks at integer pixel coordinates
+    let mut grid = rng.random_uniform(&[2, 2, 5, 2], 0., 1.);
+    for (idx, u) in grid.indexed_iter_mut() {
+        let len = if idx[3] == 0 { 4. } else { 3. };
+        let p = -1. + (len + 1.) * *u;
+        let p = p.floor() + 0.2 + 0.6 * (p - p.floor());
+        *u = (2. * p + 1.) / len - 1.;
+    }
+    let grid = env.set(grid);
+    env.run(|graph| {
+        let x = graph.variable(x);
+        let grid = graph.variable(grid);
+        for &padding in &[T::GridSamplePadding::Zeros, T::GridSamplePadding::Border] {
+            let y = T::grid_sample(x, grid, padding);
+            let y = y * T::convert_to_tensor(rng.standard_normal(&[2, 2, 2, 5]), graph);
+            let g = T::grad(&[y], &[x, grid]);
+            ag::test_helper::check_theoretical_grads(
+                y,
+                &g,
+                &[x, grid],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn space_to_depth() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 2, 4, 6]));
+    env.run(|graph| {
+        let x = graph.variable(x);
+        let y = T::space_to_depth(x, 2);
+        let y = y * T::convert_to_tensor(rng.standard_normal(&[2, 8, 2, 3]), graph);
+        let g = T::grad(&[y], &[x]);
+        ag::test_helper::check_theoretical_grads(y, &g, &[x], ag::Feeder::new(), 1e-3, 1e-3, graph);
+
+        // the gradient passes through a round trip unchanged
+        let z = T::depth_to_space(T::space_to_depth(x, 2), 2);
+        let gz = T::grad(&[z], &[x])[0];
+        assert_eq!(gz.eval(graph), Ok(ag::ndarray_ext::ones(&[2, 2, 4, 6])));
+    });
+}
+
+#[test]
+fn sort() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ndarray::arr2(&[[0.3, -1.2, 2.5], [1.1, 0.7, -0.4]]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::relu(v);
+        let w = T::convert_to_tensor(ndarray::arr2(&[[1., 2., 3.]]), graph);
+        // weighted so that every rank gets a different gradient
+        let z = T::sort(v, 1, false) * w;
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1074,13 +2448,14 @@ fn relu() {
 }
 
 #[test]
-fn softplus() {
+fn masked_fill() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 2]));
+    let v = env.set(rng.standard_normal(&[2, 3]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::softplus(v);
+        let mask = T::convert_to_tensor(ndarray::arr2(&[[1., 0., 1.]]), graph);
+        let z = T::masked_fill(v, mask, 5.);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1095,18 +2470,28 @@ fn softplus() {
 }
 
 #[test]
-fn logsumexp() {
+fn gradient_penalty() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 3]));
-    env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::reduce_logsumexp(v, 1, true);
-        let g = T::grad(&[z], &[v]);
+    let w1 = env.slot().set(rng.standard_normal(&[3, 4]));
+    let w2 = env.slot().set(rng.standard_normal(&[4, 1]));
+    env.run(|graph| {
+        let (w1, w2) = (graph.variable(w1), graph.variable(w2));
+        let x = T::convert_to_tensor(rng.standard_normal(&[5, 3]), graph);
+        let critic = T::matmul(T::tanh(T::matmul(x, w1)), w2);
+        // (||d critic / dx|| - 1)^2 for each sample
+        let gx = T::grad(&[critic], &[x])[0];
+        let norms = T::sqrt(T::reduce_sum(T::square(gx), &[1], false));
+        let penalty = T::reduce_mean(T::square(norms - 1.), &[0], false);
+
+        let grads = T::grad(&[penalty], &[w1, w2]);
+        for grad in &grads {
+            assert!(grad.eval(graph).unwrap().iter().all(|x| x.is_finite()));
+        }
         ag::test_helper::check_theoretical_grads(
-            z,
-            g.as_slice(),
-            &[v],
+            penalty,
+            grads.as_slice(),
+            &[w1, w2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -1116,18 +2501,20 @@ fn logsumexp() {
 }
 
 #[test]
-fn log_softmax() {
+fn concat() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[1, 3]));
+    let v1 = env.set(rng.standard_normal(&[1, 2]));
+    let v2 = env.set(rng.standard_normal(&[1, 2]));
     env.run(|graph| {
-        let v = graph.variable(v);
-        let z = T::log_softmax(v, 1);
-        let g = T::grad(&[z], &[v]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        let z = T::concat(&[v1, v2], 1);
+        let g = T::grad(&[z], &[v1]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -1137,19 +2524,22 @@ fn log_softmax() {
 }
 
 #[test]
-fn softmax_cross_entropy() {
+fn concat_unequal_parts() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[1, 3]));
+    let v1 = env.set(rng.standard_normal(&[2, 1]));
+    let v2 = env.set(rng.standard_normal(&[2, 3]));
     env.run(|graph| {
-        let t = T::convert_to_tensor(ndarray::arr2(&[[1., 0., 0.]]), graph);
-        let v = graph.variable(v);
-        let z = T::softmax_cross_entropy(v, t);
-        let g = T::grad(&[z], &[v]);
+        let v1 = graph.variable(v1);
+        let v2 = graph.variable(v2);
+        // weights telling the elements of the concat apart
+        let w = T::convert_to_tensor(rng.standard_normal(&[2, 4]), graph);
+        let z = T::sum_all(T::concat(&[v1, v2], 1) * w);
+        let g = T::grad(&[z], &[v1, v2]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v],
+            &[v1, v2],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -1159,14 +2549,145 @@ fn softmax_cross_entropy() {
 }
 
 #[test]
-fn sigmoid_cross_entropy() {
+fn concat_with_constant() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ag::ndarray_ext::ArrayRng::<f64>::default().standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let c = T::ones(&[4, 3], graph);
+        let v = graph.variable(v);
+        let z = T::concat(&[c, v], 0);
+        let gv = T::grad(&[z], &[v])[0];
+        assert_eq!(
+            T::shape(gv).eval(graph).unwrap(),
+            ndarray::arr1(&[2., 3.]).into_dyn()
+        );
+        assert_eq!(
+            gv.eval(graph).unwrap(),
+            ndarray::Array2::<f64>::ones((2, 3)).into_dyn()
+        );
+    });
+}
+
+#[test]
+fn stack() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[1, 3]));
+    let vs: Vec<_> = (0..3).map(|_| env.set(rng.standard_normal(&[4]))).collect();
+    env.run(|graph| {
+        let vs: Vec<_> = vs.iter().map(|&v| graph.variable(v)).collect();
+        for &axis in &[0, 1, -1] {
+            let z = T::stack(&vs, axis);
+            let expected: &[usize] = if axis == 0 { &[3, 4] } else { &[4, 3] };
+            assert_eq!(z.eval(graph).unwrap().shape(), expected);
+            // weight the elements so that each input gets a different gradient
+            let z = z * T::convert_to_tensor(rng.standard_normal(expected), graph);
+            let g = T::grad(&[z], &vs);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &vs,
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn stack_shape_mismatch() {
+    ag::run(|g| {
+        let a: ag::Tensor<f64> = T::zeros(&[4], g);
+        let b = T::zeros(&[5], g);
+        assert!(T::stack(&[a, b], 0).eval(g).is_err());
+        assert!(T::stack(&[a, a], 2).eval(g).is_err());
+    });
+}
+
+#[test]
+fn unstack() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3, 4]));
     env.run(|graph| {
-        let t = T::convert_to_tensor(rng.standard_normal(&[1, 3]), graph);
         let v = graph.variable(v);
-        let z = T::sigmoid_cross_entropy(v, t);
+        for &(num, axis) in &[(2, 0), (3, 1), (4, -1)] {
+            let parts = T::unstack(v, num, axis);
+            assert_eq!(parts.len(), num);
+            let restacked = T::stack(&parts, axis);
+            assert_eq!(restacked.eval(graph), v.eval(graph));
+
+            // only some of the parts are used
+            let z = parts[num - 1] * parts[0] * 3.;
+            let g = T::grad(&[z], &[v]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[v],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn custom_grad() {
+    let mut env = ag::VariableEnvironment::new();
+    let v = env.set(ag::ndarray::arr1(&[-1., 2., 3.]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        // the gradient of `square` is overridden by `3 * gy` instead of `2 * v * gy`
+        let y = T::custom_grad(T::square(v), &[v], |gy| vec![gy * 3.]);
+        let z = T::reduce_sum(y * 2., &[0], false);
+        let g = T::grad(&[z], &[v])[0];
+        assert_eq!(y.eval(graph), T::square(v).eval(graph));
+        assert_eq!(
+            g.eval(graph),
+            Ok(ag::ndarray::arr1(&[6., 6., 6.]).into_dyn())
+        );
+
+        // `backward` must return one gradient of the input's shape per input
+        let y = T::custom_grad(T::square(v), &[v], |gy| vec![gy.clone(), gy.clone()]);
+        assert!(T::grad(&[y], &[v])[0].eval(graph).is_err());
+        let y = T::custom_grad(T::square(v), &[v], |_| vec![ag::ndarray_ext::zeros(&[2])]);
+        assert!(T::grad(&[y], &[v])[0].eval(graph).is_err());
+    });
+}
+
+#[test]
+fn gradient_reversal() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[2, 3]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let y = T::gradient_reversal(v, 0.3);
+        assert_eq!(y.eval(graph), v.eval(graph));
+
+        let z = T::sum_all(T::sin(y));
+        let reversed = T::grad(&[z], &[v])[0];
+        let plain = T::grad(&[T::sum_all(T::sin(v))], &[v])[0];
+        ag::test_helper::assert_tensors_close(
+            &reversed.eval(graph).unwrap(),
+            &(plain.eval(graph).unwrap() * -0.3),
+            1e-12,
+            1e-12,
+        );
+    });
+}
+
+#[test]
+fn slice() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[4, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let z = T::slice(v, &[0, 0], &[-1, 2]); // numpy equivalent is v[:, 0:2]
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1181,17 +2702,16 @@ fn sigmoid_cross_entropy() {
 }
 
 #[test]
-fn sparse_softmax_cross_entropy() {
+fn split() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[2, 3]));
+    let v = env.set(rng.standard_normal(&[3, 7, 5]));
     env.run(|graph| {
-        let t = T::convert_to_tensor(ndarray::arr1(&[1., 0.]), graph);
         let v = graph.variable(v);
-        let z = T::sparse_softmax_cross_entropy(v, t);
-        let g = T::grad(&[z], &[v]);
+        let z = T::split(v, &[2, 3, 2], 1);
+        let g = T::grad(&[&z[1]], &[v]);
         ag::test_helper::check_theoretical_grads(
-            z,
+            z[1],
             g.as_slice(),
             &[v],
             ag::Feeder::new(),
@@ -1203,14 +2723,13 @@ fn sparse_softmax_cross_entropy() {
 }
 
 #[test]
-fn gather() {
+fn flatten() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[5, 4, 8, 2]));
+    let v = env.set(rng.standard_normal(&[4, 4]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let x = T::convert_to_tensor(ndarray::arr2(&[[5., 4., 3.], [2., 1., 0.]]), graph);
-        let z = T::gather(v, x, 2);
+        let z = T::flatten(v, 0);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1225,20 +2744,18 @@ fn gather() {
 }
 
 #[test]
-fn concat() {
+fn flatten_from_axis() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v1 = env.set(rng.standard_normal(&[1, 2]));
-    let v2 = env.set(rng.standard_normal(&[1, 2]));
+    let v = env.set(rng.standard_normal(&[2, 3, 2]));
     env.run(|graph| {
-        let v1 = graph.variable(v1);
-        let v2 = graph.variable(v2);
-        let z = T::concat(&[v1, v2], 1);
-        let g = T::grad(&[z], &[v1]);
+        let v = graph.variable(v);
+        let z = T::flatten(T::transpose(v, &[0, 2, 1]), 1);
+        let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
             g.as_slice(),
-            &[v1, v2],
+            &[v],
             ag::Feeder::new(),
             1e-3,
             1e-3,
@@ -1248,13 +2765,13 @@ fn concat() {
 }
 
 #[test]
-fn slice() {
+fn global_pool2d() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[4, 4]));
+    let v = env.set(rng.standard_normal(&[2, 3, 2, 2]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::slice(v, &[0, 0], &[-1, 2]); // numpy equivalent is v[:, 0:2]
+        let z = T::global_avg_pool2d(v) * T::global_max_pool2d(v);
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1269,16 +2786,47 @@ fn slice() {
 }
 
 #[test]
-fn split() {
+fn pad() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[3, 7, 5]));
+    let v = env.set(rng.standard_normal(&[3, 2]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::split(v, &[2, 3, 2], 1);
-        let g = T::grad(&[&z[1]], &[v]);
+        let paddings = &[(2, 1), (1, 1)];
+        let w = T::convert_to_tensor(rng.standard_normal(&[6, 4]), graph);
+        for &mode in &[
+            T::PadMode::Constant(1.),
+            T::PadMode::Reflect,
+            T::PadMode::Edge,
+        ] {
+            // weighted to tell each padded element apart
+            let z = T::pad(v, paddings, mode) * w;
+            let g = T::grad(&[z], &[v]);
+            ag::test_helper::check_theoretical_grads(
+                z,
+                g.as_slice(),
+                &[v],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
+#[test]
+fn roll() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let v = env.set(rng.standard_normal(&[3, 4]));
+    env.run(|graph| {
+        let v = graph.variable(v);
+        let w = T::convert_to_tensor(rng.standard_normal(&[3, 4]), graph);
+        let z = T::roll(v, &[2, -5], &[0, 1]) * w;
+        let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
-            z[1],
+            z,
             g.as_slice(),
             &[v],
             ag::Feeder::new(),
@@ -1290,13 +2838,14 @@ fn split() {
 }
 
 #[test]
-fn flatten() {
+fn flip() {
     let mut env = ag::VariableEnvironment::new();
     let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
-    let v = env.set(rng.standard_normal(&[4, 4]));
+    let v = env.set(rng.standard_normal(&[3, 4]));
     env.run(|graph| {
         let v = graph.variable(v);
-        let z = T::flatten(v);
+        let w = T::convert_to_tensor(rng.standard_normal(&[3, 4]), graph);
+        let z = T::flip(v, &[0, 1]) * w;
         let g = T::grad(&[z], &[v]);
         ag::test_helper::check_theoretical_grads(
             z,
@@ -1394,6 +2943,83 @@ fn conv2d_transpose_filter_grad() {
     });
 }
 
+#[test]
+fn conv1d() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 3, 7]));
+    let w = env.set(rng.standard_normal(&[4, 3, 3]));
+    env.run(|graph| {
+        let x = graph.variable(x);
+        let w = graph.variable(w);
+        // out_length = (7 + 2 - 3) / 2 + 1
+        let y =
+            T::conv1d(x, w, 1, 2) * T::convert_to_tensor(rng.standard_normal(&[2, 4, 4]), graph);
+        let g = T::grad(&[y], &[x, w]);
+        ag::test_helper::check_theoretical_grads(
+            y,
+            &g,
+            &[x, w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+
+        // second order, through the gradient ops
+        let gx =
+            T::grad(&[y], &[x])[0] * T::convert_to_tensor(rng.standard_normal(&[2, 3, 7]), graph);
+        let gg = T::grad(&[gx], &[w]);
+        ag::test_helper::check_theoretical_grads(
+            gx,
+            &gg,
+            &[w],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+        let gw =
+            T::grad(&[y], &[w])[0] * T::convert_to_tensor(rng.standard_normal(&[4, 3, 3]), graph);
+        let gg = T::grad(&[gw], &[x]);
+        ag::test_helper::check_theoretical_grads(
+            gw,
+            &gg,
+            &[x],
+            ag::Feeder::new(),
+            1e-3,
+            1e-3,
+            graph,
+        );
+    });
+}
+
+#[test]
+fn pool1d() {
+    let mut env = ag::VariableEnvironment::new();
+    let rng = ag::ndarray_ext::ArrayRng::<f64>::default();
+    let x = env.set(rng.standard_normal(&[2, 3, 7]));
+    env.run(|graph| {
+        let x = graph.variable(x);
+        // out_length = (7 + 2 - 3) / 2 + 1
+        let pooled = [T::max_pool1d(x, 3, 1, 2), T::avg_pool1d(x, 3, 1, 2)];
+        for &y in &pooled {
+            assert_eq!(y.eval(graph).unwrap().shape(), &[2, 3, 4]);
+            let y = y * T::convert_to_tensor(rng.standard_normal(&[2, 3, 4]), graph);
+            let g = T::grad(&[y], &[x]);
+            ag::test_helper::check_theoretical_grads(
+                y,
+                &g,
+                &[x],
+                ag::Feeder::new(),
+                1e-3,
+                1e-3,
+                graph,
+            );
+        }
+    });
+}
+
 #[test]
 // zero grad
 fn conv2d_filter_grad() {